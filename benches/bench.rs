@@ -112,6 +112,7 @@ fn write_b_bench(b: &mut Bencher, tx_size: TxSize, qindex: usize) {
                 qindex as u8,
                 ac,
                 IntraParam::None,
+                &Default::default(),
                 RDOType::PixelDistRealRate,
                 true,
               );
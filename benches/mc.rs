@@ -13,6 +13,7 @@ use rav1e::bench::encoder::{motion_compensate, FrameInvariants};
 use rav1e::bench::frame::{AsRegion, PlaneOffset, PlaneSlice};
 use rav1e::bench::mc::*;
 use rav1e::bench::partition::RefType;
+use rav1e::bench::predict::{CompoundParams, MotionMode};
 use rav1e::bench::util::Aligned;
 use rav1e::context::{BlockOffset, TileBlockOffset};
 use rav1e::prelude::*;
@@ -540,6 +541,9 @@ fn bench_motion_compensate(c: &mut Criterion) {
           PredictionMode::NEAREST_NEARESTMV,
           [RefType::LAST_FRAME, RefType::ALTREF_FRAME],
           [MotionVector { row: 0, col: 0 }, MotionVector { row: 0, col: 0 }],
+          MotionMode::SIMPLE_TRANSLATION,
+          CompoundParams::Average,
+          [FilterMode::REGULAR; 2],
           BlockSize::BLOCK_32X16,
           TileBlockOffset(BlockOffset { x: 152, y: 88 }),
           false,
@@ -575,6 +579,9 @@ fn bench_motion_compensate_hbd(c: &mut Criterion) {
           PredictionMode::NEAREST_NEARESTMV,
           [RefType::LAST_FRAME, RefType::ALTREF_FRAME],
           [MotionVector { row: 0, col: 0 }, MotionVector { row: 0, col: 0 }],
+          MotionMode::SIMPLE_TRANSLATION,
+          CompoundParams::Average,
+          [FilterMode::REGULAR; 2],
           BlockSize::BLOCK_32X16,
           TileBlockOffset(BlockOffset { x: 152, y: 88 }),
           false,
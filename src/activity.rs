@@ -54,6 +54,13 @@ impl ActivityMask {
     ActivityMask { variances: variances.into_boxed_slice() }
   }
 
+  /// The mean variance of the 8×8 blocks.
+  pub fn mean_variance(&self) -> f64 {
+    let sum = self.variances.iter().map(|&v| v as u64).sum::<u64>();
+    // The block variances are not normalized by the number of pixels.
+    sum as f64 / (64 * self.variances.len().max(1)) as f64
+  }
+
   #[profiling::function]
   pub fn fill_scales(
     &self, bit_depth: usize, activity_scales: &mut Box<[DistortionScale]>,
@@ -7,7 +7,6 @@
 // Media Patent License 1.0 was not distributed with this source code in the
 // PATENTS file, you can obtain it at www.aomedia.org/license/patent.
 
-use crate::api::color::*;
 use crate::api::config::EncoderConfig;
 use crate::api::context::RcData;
 use crate::api::util::*;
@@ -15,11 +14,9 @@ use crate::encoder::*;
 use crate::frame::*;
 use crate::util::Pixel;
 
-use bitstream_io::{BigEndian, BitWrite2, BitWriter};
 use crossbeam::channel::{Receiver, Sender};
 use thiserror::Error;
 
-use std::io;
 use std::sync::Arc;
 
 /// An error returned from the `send` methods.
@@ -334,9 +331,9 @@ impl<T: Pixel> PacketReceiver<T> {
 impl<T: Pixel> PacketReceiver<T> {
   /// Produces a sequence header matching the current encoding context.
   ///
-  /// Its format is compatible with the AV1 Matroska and ISOBMFF specification.
-  /// Note that the returned header does not include any config OBUs which are
-  /// required for some uses. See [the specification].
+  /// Its format is compatible with the AV1 Matroska and ISOBMFF specification:
+  /// an `AV1CodecConfigurationRecord` whose config OBUs hold the sequence
+  /// header OBU. See [the specification].
   ///
   /// [the specification]:
   /// https://aomediacodec.github.io/av1-isobmff/#av1codecconfigurationbox-section
@@ -347,34 +344,7 @@ impl<T: Pixel> PacketReceiver<T> {
   /// and usually indicates the system is out of memory.
   #[inline]
   pub fn container_sequence_header(&self) -> Vec<u8> {
-    fn sequence_header_inner(seq: &Sequence) -> io::Result<Vec<u8>> {
-      let mut buf = Vec::new();
-
-      {
-        let mut bw = BitWriter::endian(&mut buf, BigEndian);
-        bw.write_bit(true)?; // marker
-        bw.write(7, 1)?; // version
-        bw.write(3, seq.profile)?;
-        bw.write(5, 31)?; // level
-        bw.write_bit(false)?; // tier
-        bw.write_bit(seq.bit_depth > 8)?; // high_bitdepth
-        bw.write_bit(seq.bit_depth == 12)?; // twelve_bit
-        bw.write_bit(seq.chroma_sampling == ChromaSampling::Cs400)?; // monochrome
-        bw.write_bit(seq.chroma_sampling != ChromaSampling::Cs444)?; // chroma_subsampling_x
-        bw.write_bit(seq.chroma_sampling == ChromaSampling::Cs420)?; // chroma_subsampling_y
-        bw.write(2, 0)?; // chroma_sample_position
-        bw.write(3, 0)?; // reserved
-        bw.write_bit(false)?; // initial_presentation_delay_present
-
-        bw.write(4, 0)?; // reserved
-      }
-
-      Ok(buf)
-    }
-
-    let seq = Sequence::new(&self.config);
-
-    sequence_header_inner(&seq).unwrap()
+    container_sequence_header::<T>(&self.config).unwrap()
   }
 }
 
@@ -146,6 +146,14 @@ impl Config {
   /// The last buffer in the `PassDataReceiver` is the summary of the whole
   /// encoding process.
   ///
+  /// The pass data emitted describes this pass, so it can configure a further
+  /// multipass or second pass channel, for as many passes as needed.
+  /// Its summary counts the passes so far, see
+  /// [`RateControlSummary::passes`], and the statistics of each frame can be
+  /// read with [`RateControlFrameStats::from_slice`].
+  /// The further passes allocate their quantizers from them, and start new
+  /// scenes at the same frames, see [`RateControlFrameStats`].
+  ///
   /// # Errors
   ///
   /// - Returns `InvalidConfig` if the configuration is invalid.
@@ -234,6 +242,11 @@ impl RcSecondPass for Receiver<RcData> {
       }
     }
 
+    if inner.rc_state.places_keyframes() {
+      inner.place_keyframes_from_pass_data();
+      inner.compute_frame_invariants();
+    }
+
     Ok(())
   }
 }
@@ -307,6 +320,8 @@ impl Config {
     let run = move || {
       for f in receive_frame.iter() {
         // info!("frame in {}", inner.frame_count);
+        // The keyframe placement may wait for the pass data of the lookahead.
+        receive_rc_pass2.feed_pass_data(&mut inner).unwrap();
         while !inner.needs_more_fi_lookahead() {
           receive_rc_pass2.feed_pass_data(&mut inner).unwrap();
           // needs_more_fi_lookahead() should guard for missing output_frameno
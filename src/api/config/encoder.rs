@@ -16,6 +16,7 @@ use crate::encoder::Tune;
 use crate::serialize::{Deserialize, Serialize};
 
 use std::fmt;
+use std::str::FromStr;
 
 // We add 1 to rdo_lookahead_frames in a bunch of places.
 pub(crate) const MAX_RDO_LOOKAHEAD_FRAMES: usize = usize::MAX - 1;
@@ -51,12 +52,31 @@ pub struct EncoderConfig {
   /// HDR content light parameters.
   pub content_light: Option<ContentLight>,
 
-  /// AV1 level index to target (0-31).
-  /// If None, allow the encoder to decide.
-  /// Currently, rav1e is unable to guarantee that the output bitstream
-  /// meets the rate limitations of the specified level.
+  /// AV1 level index to target (0-31), in the main tier.
+  ///
+  /// The rate control enforces the maximum bitrate and compressed frame
+  /// size of the level, with a decoder buffer model of one second at the
+  /// maximum bitrate of the level unless [`max_bitrate`] is set. The other
+  /// limits, such as the picture size, the decode and header rates, and the
+  /// number and size of the tiles, are checked when validating the
+  /// configuration. Lossless encoding cannot meet any level but 31, which is
+  /// unconstrained.
+  ///
+  /// If None, level 31 is signaled, unless [`auto_level`] is set.
+  ///
+  /// [`max_bitrate`]: #structfield.max_bitrate
+  /// [`auto_level`]: #structfield.auto_level
   pub level_idx: Option<u8>,
 
+  /// Signal the lowest level the stream conforms to when no level is set.
+  ///
+  /// Only a target or maximum bitrate bounds the level, so without either
+  /// level 31 is signaled. Unlike a level set with [`level_idx`], the
+  /// selected level does not constrain the rate control.
+  ///
+  /// [`level_idx`]: #structfield.level_idx
+  pub auto_level: bool,
+
   /// Enable signaling timing info in the bitstream.
   pub enable_timing_info: bool,
 
@@ -69,6 +89,17 @@ pub struct EncoderConfig {
   /// Interval between switch frames (0 to disable)
   pub switch_frame_interval: u64,
 
+  /// Write the OBUs without their sizes, each one preceded by its length as
+  /// in the length-delimited bitstream format of Annex B.
+  ///
+  /// Packets then hold the OBUs of a temporal unit, which [`ObuWriter`]
+  /// splits into one frame unit per frame, hidden frames included.
+  /// Containers other than Annex B streams require the OBUs to carry their
+  /// sizes.
+  ///
+  /// [`ObuWriter`]: crate::api::ObuWriter
+  pub annex_b: bool,
+
   // encoder configuration
   /// The *minimum* interval between two keyframes
   pub min_key_frame_interval: u64,
@@ -81,12 +112,84 @@ pub struct EncoderConfig {
   ///
   /// In this mode the frame reordering is disabled.
   pub low_latency: bool,
+  /// Number of temporal layers (1 to disable temporal scalability).
+  ///
+  /// Each layer above the base one doubles the frame rate of the operating
+  /// point that includes it. Only supported in low latency mode.
+  pub temporal_layers: u8,
   /// The base quantizer to use.
   pub quantizer: usize,
   /// The minimum allowed base quantizer to use in bitrate mode.
   pub min_quantizer: u8,
   /// The target bitrate for the bitrate mode.
   pub bitrate: i32,
+  /// Drop frames that would underflow the rate control reservoir.
+  ///
+  /// A dropped inter frame produces a [`Packet`] with the `dropped` flag set
+  /// and no data, so that the container timestamps can be adjusted. Requires
+  /// a target [`bitrate`] and [`low_latency`].
+  ///
+  /// [`Packet`]: crate::api::Packet
+  /// [`bitrate`]: #structfield.bitrate
+  /// [`low_latency`]: #structfield.low_latency
+  pub drop_frames: bool,
+  /// The quality target for the constant quality mode (0-255), on the same
+  /// scale as [`quantizer`].
+  ///
+  /// In this mode the base quantizer of each frame follows this target,
+  /// raised for frames more complex than the recent average and lowered for
+  /// simpler ones. It cannot be combined with a target [`bitrate`], but may
+  /// be capped with [`max_bitrate`]. [`quantizer`] is ignored in this mode,
+  /// while [`min_quantizer`] still bounds the quantizer from below.
+  ///
+  /// [`quantizer`]: #structfield.quantizer
+  /// [`bitrate`]: #structfield.bitrate
+  /// [`max_bitrate`]: #structfield.max_bitrate
+  /// [`min_quantizer`]: #structfield.min_quantizer
+  pub crf: Option<u8>,
+  /// The quality target for the quality-targeted mode.
+  ///
+  /// In this mode the encoder selects a base quantizer for each group of
+  /// pictures started by a key frame: it codes a few frames of the group at
+  /// several quantizers, the first one as a key frame and the others as
+  /// frames predicted from the previous one, and keeps the coarsest quantizer
+  /// whose mean quality meets the target. [`quantizer`] bounds the search. It cannot
+  /// be combined with a target [`bitrate`] or the constant quality mode.
+  ///
+  /// [`quantizer`]: #structfield.quantizer
+  /// [`bitrate`]: #structfield.bitrate
+  pub quality_target: Option<QualityTarget>,
+  /// The peak bitrate of the decoder buffer model, in bits per second
+  /// (0 to disable the model).
+  ///
+  /// When set, every temporal unit is coded so that a decoder buffer of
+  /// [`buffer_size`] bits, filled at this rate, never underflows.
+  ///
+  /// [`buffer_size`]: #structfield.buffer_size
+  pub max_bitrate: i32,
+  /// The size of the decoder buffer model in bits.
+  ///
+  /// If 0, one second of [`max_bitrate`] is used.
+  ///
+  /// [`max_bitrate`]: #structfield.max_bitrate
+  pub buffer_size: i32,
+  /// Enable signaling the decoder buffer model in the bitstream.
+  ///
+  /// The frame headers then carry their removal time from the decoder
+  /// buffer, each temporal unit being removed at its timestamp relative to
+  /// the last key frame, in units of the time base.
+  ///
+  /// Requires [`max_bitrate`] and [`enable_timing_info`].
+  ///
+  /// [`max_bitrate`]: #structfield.max_bitrate
+  /// [`enable_timing_info`]: #structfield.enable_timing_info
+  pub enable_decoder_model_info: bool,
+  /// Flag to enable mathematically lossless coding.
+  ///
+  /// In this mode the quantizer settings are ignored and every frame is
+  /// coded with a base quantizer of 0, the Walsh-Hadamard transform and the
+  /// loop filters disabled.
+  pub lossless: bool,
   /// Metric to tune the quality for.
   pub tune: Tune,
   /// Parameters for grain synthesis.
@@ -111,9 +214,24 @@ pub struct EncoderConfig {
   /// [`tile_cols`]: #structfield.tile_cols
   /// [`tile_rows`]: #structfield.tile_rows
   pub tiles: usize,
+  /// Horizontal super-resolution mode.
+  ///
+  /// Only key frames can be coded at a reduced width, as inter frames would
+  /// need motion compensation from scaled references, so super-resolution
+  /// requires a [`max_key_frame_interval`] of 1 or a still picture.
+  ///
+  /// [`max_key_frame_interval`]: #structfield.max_key_frame_interval
+  pub superres_mode: SuperresMode,
+  /// Whether to use the screen content coding tools, such as palette
+  /// prediction and intra block copy.
+  pub screen_content: ScreenContentMode,
 
   /// Settings which affect the encoding speed vs. quality trade-off.
   pub speed_settings: SpeedSettings,
+  /// Settings that apply to ranges of input frames, in increasing order.
+  ///
+  /// A key frame is inserted at the start of each zone.
+  pub zones: Vec<Zone>,
 }
 
 /// Default preset for `EncoderConfig`: it is a balance between quality and
@@ -150,6 +268,7 @@ impl EncoderConfig {
       content_light: None,
 
       level_idx: None,
+      auto_level: false,
 
       enable_timing_info: false,
 
@@ -157,19 +276,31 @@ impl EncoderConfig {
 
       error_resilient: false,
       switch_frame_interval: 0,
+      annex_b: false,
 
       min_key_frame_interval: 12,
       max_key_frame_interval: 240,
       min_quantizer: 0,
       reservoir_frame_delay: None,
       low_latency: false,
+      temporal_layers: 1,
       quantizer: 100,
       bitrate: 0,
+      crf: None,
+      quality_target: None,
+      drop_frames: false,
+      max_bitrate: 0,
+      buffer_size: 0,
+      enable_decoder_model_info: false,
+      lossless: false,
       tune: Tune::default(),
       film_grain_params: None,
       tile_cols: 0,
       tile_rows: 0,
       tiles: 0,
+      superres_mode: SuperresMode::default(),
+      screen_content: ScreenContentMode::default(),
+      zones: Vec::new(),
       speed_settings: SpeedSettings::from_preset(speed),
     }
   }
@@ -238,6 +369,18 @@ impl EncoderConfig {
       .unwrap_or(false)
   }
 
+  /// Returns the size of the decoder buffer model in bits, or 0 if the
+  /// model is disabled.
+  pub(crate) fn decoder_buffer_size(&self) -> i64 {
+    if self.max_bitrate <= 0 {
+      0
+    } else if self.buffer_size > 0 {
+      self.buffer_size as i64
+    } else {
+      self.max_bitrate as i64
+    }
+  }
+
   pub(crate) fn get_film_grain_at(
     &self, timestamp: u64,
   ) -> Option<&GrainTableSegment> {
@@ -259,6 +402,194 @@ impl EncoderConfig {
   }
 }
 
+/// Horizontal super-resolution mode.
+///
+/// Frames coded with super-resolution are encoded at a reduced width and
+/// upscaled back to the full width before loop restoration. Only key frames
+/// are coded at a reduced width, since inter prediction from references of a
+/// different size is not supported.
+#[derive(
+  Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Default,
+)]
+pub enum SuperresMode {
+  /// Always code frames at full width.
+  #[default]
+  None,
+  /// Code key frames with the given denominator, in the range 9–16.
+  /// The coded width is the full width scaled by 8 / denominator.
+  Fixed(u8),
+  /// Pick a denominator for each key frame depending on the quantizer it
+  /// is coded with, so that key frames coded at low bitrates are coded at
+  /// a reduced width.
+  Auto,
+}
+
+impl fmt::Display for SuperresMode {
+  fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+    match self {
+      SuperresMode::None => write!(f, "none"),
+      SuperresMode::Fixed(denom) => write!(f, "{}", denom),
+      SuperresMode::Auto => write!(f, "auto"),
+    }
+  }
+}
+
+impl FromStr for SuperresMode {
+  type Err = &'static str;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "none" => Ok(SuperresMode::None),
+      "auto" => Ok(SuperresMode::Auto),
+      _ => s
+        .parse()
+        .map(SuperresMode::Fixed)
+        .map_err(|_| "expected none, auto or a denominator from 9 to 16"),
+    }
+  }
+}
+
+/// Screen content coding mode.
+///
+/// Screen content, such as text, slides and desktop captures, is made of
+/// few colours, sharp edges and repeated patterns, which the screen content
+/// coding tools are designed for.
+#[derive(
+  Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Default,
+)]
+pub enum ScreenContentMode {
+  /// Detect screen content in the lookahead, and only enable the tools on
+  /// the frames it is found in.
+  #[default]
+  Auto,
+  /// Enable the tools on every frame.
+  On,
+  /// Never enable the tools.
+  Off,
+}
+
+impl fmt::Display for ScreenContentMode {
+  fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+    match self {
+      ScreenContentMode::Auto => write!(f, "auto"),
+      ScreenContentMode::On => write!(f, "on"),
+      ScreenContentMode::Off => write!(f, "off"),
+    }
+  }
+}
+
+impl FromStr for ScreenContentMode {
+  type Err = &'static str;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "auto" => Ok(ScreenContentMode::Auto),
+      "on" => Ok(ScreenContentMode::On),
+      "off" => Ok(ScreenContentMode::Off),
+      _ => Err("expected auto, on or off"),
+    }
+  }
+}
+
+/// Quality to reach in the quality-targeted mode, measured on the luma
+/// plane.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum QualityTarget {
+  /// Mean PSNR in dB.
+  Psnr(f64),
+  /// Mean SSIM, between 0 and 1.
+  Ssim(f64),
+}
+
+impl QualityTarget {
+  /// Returns whether the target is in the range of its metric.
+  pub(crate) fn is_valid(self) -> bool {
+    match self {
+      QualityTarget::Psnr(psnr) => psnr > 0. && psnr <= 100.,
+      QualityTarget::Ssim(ssim) => ssim > 0. && ssim < 1.,
+    }
+  }
+}
+
+impl FromStr for QualityTarget {
+  type Err = &'static str;
+
+  /// Parses a target from `psnr:<dB>` or `ssim:<value>`.
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    const EXPECTED: &str = "expected psnr:<dB> or ssim:<value>";
+    let (metric, value) = s.split_once(':').ok_or(EXPECTED)?;
+    let value = value.trim().parse().map_err(|_| EXPECTED)?;
+    match metric.trim() {
+      "psnr" => Ok(QualityTarget::Psnr(value)),
+      "ssim" => Ok(QualityTarget::Ssim(value)),
+      _ => Err(EXPECTED),
+    }
+  }
+}
+
+impl fmt::Display for QualityTarget {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      QualityTarget::Psnr(psnr) => write!(f, "psnr:{}", psnr),
+      QualityTarget::Ssim(ssim) => write!(f, "ssim:{}", ssim),
+    }
+  }
+}
+
+/// Encoder settings that apply to a range of input frames.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, Default)]
+pub struct Zone {
+  /// First input frame of the zone.
+  pub start_frame: u64,
+  /// Last input frame of the zone, included in the zone.
+  pub end_frame: u64,
+  /// Base quantizer index (1-255) of the frames of the zone, instead of the
+  /// one selected by the rate control.
+  pub quantizer: Option<u8>,
+  /// Speed preset (0-10) of the frames of the zone.
+  ///
+  /// Only the settings that can change from frame to frame follow the
+  /// preset of the zone. The coding tools enabled for the whole sequence,
+  /// the lookahead and the scene detection keep the settings of the encoder.
+  pub speed: Option<u8>,
+  /// Multiplier of the number of bits spent on the frames of the zone.
+  ///
+  /// Only used in the bitrate and constant quality modes. In the bitrate
+  /// mode, the frames outside of the zone make up for the difference.
+  pub bitrate_factor: Option<f64>,
+}
+
+impl FromStr for Zone {
+  type Err = &'static str;
+
+  /// Parses a zone from `start-end:key=value,...`, where the keys are `q`,
+  /// `speed` and `bitrate_factor`.
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    const EXPECTED: &str =
+      "expected start-end:q=..,speed=..,bitrate_factor=..";
+    let (range, settings) = s.split_once(':').ok_or(EXPECTED)?;
+    let (start, end) = range.split_once('-').ok_or(EXPECTED)?;
+    let mut zone = Zone {
+      start_frame: start.trim().parse().map_err(|_| EXPECTED)?,
+      end_frame: end.trim().parse().map_err(|_| EXPECTED)?,
+      ..Default::default()
+    };
+    for setting in settings.split(',') {
+      let (key, value) = setting.split_once('=').ok_or(EXPECTED)?;
+      let value = value.trim();
+      match key.trim() {
+        "q" => zone.quantizer = Some(value.parse().map_err(|_| EXPECTED)?),
+        "speed" => zone.speed = Some(value.parse().map_err(|_| EXPECTED)?),
+        "bitrate_factor" => {
+          zone.bitrate_factor = Some(value.parse().map_err(|_| EXPECTED)?)
+        }
+        _ => return Err(EXPECTED),
+      }
+    }
+    Ok(zone)
+  }
+}
+
 impl fmt::Display for EncoderConfig {
   fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
     let pairs = [
@@ -266,8 +597,20 @@ impl fmt::Display for EncoderConfig {
       ("keyint_max", self.max_key_frame_interval.to_string()),
       ("quantizer", self.quantizer.to_string()),
       ("bitrate", self.bitrate.to_string()),
+      ("crf", self.crf.map_or("none".to_string(), |crf| crf.to_string())),
+      (
+        "quality_target",
+        self
+          .quality_target
+          .map_or("none".to_string(), |target| target.to_string()),
+      ),
+      ("drop_frames", self.drop_frames.to_string()),
+      ("max_bitrate", self.max_bitrate.to_string()),
+      ("buffer_size", self.buffer_size.to_string()),
       ("min_quantizer", self.min_quantizer.to_string()),
+      ("lossless", self.lossless.to_string()),
       ("low_latency", self.low_latency.to_string()),
+      ("temporal_layers", self.temporal_layers.to_string()),
       ("tune", self.tune.to_string()),
       (
         "rdo_lookahead_frames",
@@ -285,6 +628,13 @@ impl fmt::Display for EncoderConfig {
       ("cdef", self.speed_settings.cdef.to_string()),
       ("lrf", self.speed_settings.lrf.to_string()),
       ("enable_timing_info", self.enable_timing_info.to_string()),
+      ("annex_b", self.annex_b.to_string()),
+      (
+        "enable_decoder_model_info",
+        self.enable_decoder_model_info.to_string(),
+      ),
+      ("superres", self.superres_mode.to_string()),
+      ("screen_content", self.screen_content.to_string()),
       (
         "min_block_size",
         self.speed_settings.partition.partition_range.min.to_string(),
@@ -337,6 +687,21 @@ impl fmt::Display for EncoderConfig {
         "use_satd_subpel",
         self.speed_settings.motion.use_satd_subpel.to_string(),
       ),
+      ("global_motion", self.speed_settings.motion.global_motion.to_string()),
+      ("motion_modes", self.speed_settings.motion.motion_modes.to_string()),
+      (
+        "masked_compound",
+        self.speed_settings.motion.masked_compound.to_string(),
+      ),
+      ("interintra", self.speed_settings.motion.interintra.to_string()),
+      (
+        "distance_weighted_compound",
+        self.speed_settings.motion.distance_weighted_compound.to_string(),
+      ),
+      (
+        "interp_filter_search",
+        self.speed_settings.motion.interp_filter_search.to_string(),
+      ),
     ];
     write!(
       f,
@@ -24,7 +24,7 @@ use crate::levels::*;
 
 mod rate;
 pub use rate::Error as RateControlError;
-pub use rate::{RateControlConfig, RateControlSummary};
+pub use rate::{RateControlConfig, RateControlFrameStats, RateControlSummary};
 
 mod speedsettings;
 pub use speedsettings::*;
@@ -103,11 +103,66 @@ pub enum InvalidConfig {
     "invalid switch frame interval {0} (must only be used with low latency mode)"
   )]
   InvalidSwitchFrameInterval(u64),
+  /// The number of temporal layers is invalid.
+  #[error(
+    "invalid temporal layers {0} (expected >= 1, <= 3, layers > 1 must only be used with low latency mode)"
+  )]
+  InvalidTemporalLayers(u8),
+  /// The peak bitrate of the decoder buffer model is invalid.
+  #[error("invalid max bitrate {actual} (expected >= {min})")]
+  InvalidMaxBitrate {
+    /// The actual value.
+    actual: i32,
+    /// The minimal supported value.
+    min: i32,
+  },
+  /// The size of the decoder buffer model is invalid.
+  #[error(
+    "invalid buffer size {0} (expected >= 0, must only be used with max bitrate)"
+  )]
+  InvalidBufferSize(i32),
+  /// The decoder model info cannot be signaled.
+  #[error(
+    "decoder model info requires max bitrate and timing info to be enabled"
+  )]
+  InvalidDecoderModelInfo,
+  /// Frames cannot be dropped.
+  #[error("frame dropping requires a target bitrate and low latency mode")]
+  InvalidDropFrames,
+
+  /// The super-resolution denominator is invalid.
+  #[error("invalid superres denominator {0} (expected >= 9, <= 16)")]
+  InvalidSuperresDenom(u8),
+  /// Super-resolution was enabled along with inter frames.
+  #[error("superres requires a maximum key frame interval of 1")]
+  InvalidSuperresWithInterFrames,
 
   /// An option unsupported in still picture mode was enabled along with it.
   #[error("invalid option {0} specified with still picture mode")]
   InvalidOptionWithStillPicture(&'static str),
 
+  /// An option unsupported in lossless mode was enabled along with it.
+  #[error("invalid option {0} specified with lossless mode")]
+  InvalidOptionWithLossless(&'static str),
+
+  /// The constant quality mode was combined with a target bitrate.
+  #[error("The constant quality mode cannot be used with a target bitrate")]
+  CrfWithTargetBitrate,
+
+  /// The quality-targeted mode was combined with a target bitrate, a
+  /// decoder buffer model or the constant quality mode.
+  #[error("The quality-targeted mode cannot be used with rate control")]
+  QualityTargetWithRateControl,
+
+  /// The quality target is out of the range of its metric.
+  #[error("invalid quality target")]
+  InvalidQualityTarget,
+
+  /// A zone is invalid: out of order, overlapping the previous one, or
+  /// with invalid settings.
+  #[error("invalid zone {0}")]
+  InvalidZone(usize),
+
   /// The rate control needs a target bitrate in order to produce results
   #[error("The rate control requires a target bitrate")]
   TargetBitrateNeeded,
@@ -385,10 +440,105 @@ impl Config {
       return Err(InvalidSwitchFrameInterval(config.switch_frame_interval));
     }
 
+    if !(1..=3).contains(&config.temporal_layers)
+      || (config.temporal_layers > 1 && !config.low_latency)
+    {
+      return Err(InvalidTemporalLayers(config.temporal_layers));
+    }
+
+    if config.crf.is_some() && config.bitrate > 0 {
+      return Err(CrfWithTargetBitrate);
+    }
+
+    if let Some(target) = config.quality_target {
+      if config.bitrate > 0 || config.max_bitrate > 0 || config.crf.is_some() {
+        return Err(QualityTargetWithRateControl);
+      }
+      if !target.is_valid() {
+        return Err(InvalidQualityTarget);
+      }
+    }
+
+    let mut prev_end_frame = None;
+    for (i, zone) in config.zones.iter().enumerate() {
+      if zone.start_frame > zone.end_frame
+        || prev_end_frame.is_some_and(|end| zone.start_frame <= end)
+        || zone.quantizer == Some(0)
+        || zone.speed.is_some_and(|speed| speed > 10)
+        || zone.bitrate_factor.is_some_and(|f| !(f.is_finite() && f > 0.))
+      {
+        return Err(InvalidZone(i));
+      }
+      prev_end_frame = Some(zone.end_frame);
+    }
+
+    if config.drop_frames && (config.bitrate <= 0 || !config.low_latency) {
+      return Err(InvalidDropFrames);
+    }
+
+    if config.max_bitrate != 0 {
+      if config.bitrate <= 0 && config.crf.is_none() {
+        return Err(TargetBitrateNeeded);
+      }
+      if config.max_bitrate < config.bitrate.max(1) {
+        return Err(InvalidMaxBitrate {
+          actual: config.max_bitrate,
+          min: config.bitrate.max(1),
+        });
+      }
+    }
+
+    if config.buffer_size < 0
+      || (config.buffer_size > 0 && config.max_bitrate == 0)
+    {
+      return Err(InvalidBufferSize(config.buffer_size));
+    }
+
+    if config.enable_decoder_model_info
+      && (config.max_bitrate == 0 || !config.enable_timing_info)
+    {
+      return Err(InvalidDecoderModelInfo);
+    }
+
+    if let SuperresMode::Fixed(denom) = config.superres_mode {
+      if !(9..=16).contains(&denom) {
+        return Err(InvalidSuperresDenom(denom));
+      }
+    }
+
     if config.enable_timing_info && config.still_picture {
       return Err(InvalidOptionWithStillPicture("enable_timing_info"));
     }
 
+    if config.temporal_layers > 1 && config.still_picture {
+      return Err(InvalidOptionWithStillPicture("temporal_layers"));
+    }
+
+    if config.lossless {
+      if config.bitrate > 0 {
+        return Err(InvalidOptionWithLossless("bitrate"));
+      }
+      if config.crf.is_some() {
+        return Err(InvalidOptionWithLossless("crf"));
+      }
+      if config.quality_target.is_some() {
+        return Err(InvalidOptionWithLossless("quality_target"));
+      }
+      if config.superres_mode != SuperresMode::None {
+        return Err(InvalidOptionWithLossless("superres"));
+      }
+      if config.film_grain_params.is_some() {
+        return Err(InvalidOptionWithLossless("film_grain_params"));
+      }
+    }
+
+    if config.superres_mode != SuperresMode::None
+      && config.max_key_frame_interval > 1
+      && !config.still_picture
+    {
+      return Err(InvalidSuperresWithInterFrames);
+    }
+
     // <https://aomediacodec.github.io/av1-spec/#color-config-syntax>
     if let Some(color_description) = config.color_description {
       if config.chroma_sampling != ChromaSampling::Cs400
@@ -411,20 +561,8 @@ impl Config {
         if !AV1_LEVEL_DEFINED[level_idx as usize] {
           return Err(LevelUndefined);
         }
-        if config.width * config.height
-          > AV1_LEVEL_MAX_PIC_SIZE[level_idx as usize]
-        {
-          return Err(LevelConstraintsExceeded);
-        }
-        if config.width > AV1_LEVEL_MAX_H_SIZE[level_idx as usize] {
-          return Err(LevelConstraintsExceeded);
-        }
-        if config.height > AV1_LEVEL_MAX_V_SIZE[level_idx as usize] {
-          return Err(LevelConstraintsExceeded);
-        }
-        if ((config.width * config.height) as u64 * config.time_base.num)
-          .div_ceil(config.time_base.den)
-          > AV1_LEVEL_MAX_DISPLAY_RATE[level_idx as usize] as u64
+        let seq = crate::encoder::Sequence::new(config);
+        if !level_constraints_met(config, level_idx, &seq.tiling, seq.profile)
         {
           return Err(LevelConstraintsExceeded);
         }
@@ -20,6 +20,9 @@ pub enum Error {
   /// The summary provided is possibly corrupted
   #[error("The summary content is invalid: {0}")]
   CorruptedSummary(String),
+  /// The frame data provided is possibly corrupted
+  #[error("The frame data is invalid: {0}")]
+  CorruptedFrameData(String),
 }
 
 /// Rate control configuration
@@ -29,16 +32,63 @@ pub struct RateControlConfig {
   pub(crate) summary: Option<RateControlSummary>,
 }
 
+pub use crate::rate::RCFrameStats as RateControlFrameStats;
 pub use crate::rate::RCSummary as RateControlSummary;
 
 impl RateControlSummary {
   /// Deserializes a byte slice into a `RateControlSummary`
   pub(crate) fn from_slice(bytes: &[u8]) -> Result<Self, Error> {
     let mut de = RCDeserialize::default();
-    let _ = de.buffer_fill(bytes, 0, TWOPASS_HEADER_SZ);
+    // The magic value and version number tell the size of the summary.
+    let consumed = de.buffer_fill(bytes, 0, 8);
+    let size = de.summary_size().map_err(Error::CorruptedSummary)?;
+    let _ = de.buffer_fill(bytes, consumed, size);
 
     de.parse_summary().map_err(Error::CorruptedSummary)
   }
+
+  /// Deserializes the statistics of a frame from a frame packet of the
+  /// encoding pass this summary describes
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if the serialized data is invalid.
+  pub fn frame_stats(
+    &self, bytes: &[u8],
+  ) -> Result<RateControlFrameStats, Error> {
+    parse_frame_stats(bytes, self.fields())
+  }
+}
+
+impl RateControlFrameStats {
+  /// Deserializes the statistics of a frame from a frame packet emitted by
+  /// this version of the encoder
+  ///
+  /// Use [`RateControlSummary::frame_stats`] to read stored pass data, which
+  /// may come from another version.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if the serialized data is invalid.
+  pub fn from_slice(bytes: &[u8]) -> Result<Self, Error> {
+    parse_frame_stats(bytes, &TWOPASS_FIELDS)
+  }
+}
+
+// Deserializes a frame packet laid out as described by fields.
+fn parse_frame_stats(
+  bytes: &[u8], fields: &[(u8, u8)],
+) -> Result<RateControlFrameStats, Error> {
+  let size = fields.iter().map(|&(_, size)| size as usize).sum();
+  if bytes.len() != size {
+    return Err(Error::CorruptedFrameData(
+      "Incorrect buffer size".to_string(),
+    ));
+  }
+  let mut de = RCDeserialize::default();
+  let _ = de.buffer_fill(bytes, 0, size);
+
+  de.parse_frame_stats(fields).map_err(Error::CorruptedFrameData)
 }
 
 impl RateControlConfig {
@@ -105,12 +105,44 @@ impl Default for SpeedSettings {
         include_near_mvs: true,
         use_satd_subpel: true,
         me_allow_full_search: true,
+        global_motion: true,
+        motion_modes: true,
+        masked_compound: true,
+        interintra: true,
+        distance_weighted_compound: true,
+        interp_filter_search: InterpFilterSearch::Full,
       },
     }
   }
 }
 
 impl SpeedSettings {
+  /// Returns the settings of a speed preset for some of the frames of a
+  /// sequence encoded with these settings.
+  ///
+  /// The settings that must stay the same for the whole sequence, such as
+  /// the coding tools signaled in the sequence header, are kept.
+  pub(crate) fn with_frame_preset(&self, speed: u8) -> Self {
+    let mut settings = Self::from_preset(speed);
+    settings.multiref = self.multiref;
+    settings.rdo_lookahead_frames = self.rdo_lookahead_frames;
+    settings.scene_detection_mode = self.scene_detection_mode;
+    settings.cdef = self.cdef;
+    settings.lrf = self.lrf;
+    // Inter frames reuse the segmentation data of their references.
+    settings.segmentation = self.segmentation;
+    // Temporal RDO depends on it, and runs in the lookahead.
+    settings.transform.tx_domain_distortion =
+      self.transform.tx_domain_distortion;
+    settings.motion.motion_modes = self.motion.motion_modes;
+    settings.motion.masked_compound = self.motion.masked_compound;
+    settings.motion.interintra = self.motion.interintra;
+    settings.motion.distance_weighted_compound =
+      self.motion.distance_weighted_compound;
+    settings.motion.interp_filter_search = self.motion.interp_filter_search;
+    settings
+  }
+
   /// Set the speed setting according to a numeric speed preset.
   pub fn from_preset(speed: u8) -> Self {
     // The default settings are equivalent to speed 0
@@ -125,6 +157,8 @@ impl SpeedSettings {
       settings.partition.non_square_partition_max_threshold =
         BlockSize::BLOCK_8X8;
 
+      settings.motion.interp_filter_search = InterpFilterSearch::Separable;
+
       settings.prediction.prediction_modes =
         PredictionModesSetting::ComplexKeyframes;
     }
@@ -132,6 +166,10 @@ impl SpeedSettings {
     if speed >= 3 {
       settings.rdo_lookahead_frames = 30;
 
+      settings.motion.masked_compound = false;
+      settings.motion.interintra = false;
+      settings.motion.interp_filter_search = InterpFilterSearch::Joint;
+
       settings.partition.partition_range =
         PartitionRange::new(BlockSize::BLOCK_8X8, BlockSize::BLOCK_64X64);
     }
@@ -143,6 +181,7 @@ impl SpeedSettings {
     if speed >= 5 {
       settings.sgr_complexity = SGRComplexityLevel::Reduced;
       settings.motion.include_near_mvs = false;
+      settings.motion.distance_weighted_compound = false;
     }
 
     if speed >= 6 {
@@ -152,6 +191,7 @@ impl SpeedSettings {
       settings.transform.reduced_tx_set = true;
 
       settings.motion.me_allow_full_search = false;
+      settings.motion.interp_filter_search = InterpFilterSearch::Off;
     }
 
     if speed >= 7 {
@@ -163,6 +203,8 @@ impl SpeedSettings {
       // backwards references.
       settings.multiref = false;
       settings.fast_deblock = true;
+      settings.motion.global_motion = false;
+      settings.motion.motion_modes = false;
     }
 
     if speed >= 8 {
@@ -258,6 +300,40 @@ pub struct MotionSpeedSettings {
   /// Enable full search in some parts of motion estimation. Allowing full
   /// search is slower.
   pub me_allow_full_search: bool,
+
+  /// Enables global motion estimation and warped global motion prediction.
+  ///
+  /// Enabled is slower.
+  pub global_motion: bool,
+
+  /// Enables overlapped block motion compensation and local warped motion,
+  /// chosen per block during RDO.
+  ///
+  /// Enabled is slower.
+  pub motion_modes: bool,
+
+  /// Enables wedge and difference-weighted masks blending the two
+  /// predictions of compound blocks.
+  ///
+  /// Enabled is slower.
+  pub masked_compound: bool,
+
+  /// Enables blending an intra prediction into single reference inter
+  /// blocks.
+  ///
+  /// Enabled is slower.
+  pub interintra: bool,
+
+  /// Enables weighting the two predictions of compound blocks by the
+  /// distance of their references.
+  ///
+  /// Enabled is slower.
+  pub distance_weighted_compound: bool,
+
+  /// Interpolation filters to search for each inter block.
+  ///
+  /// Larger searches are slower.
+  pub interp_filter_search: InterpFilterSearch,
 }
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
@@ -449,3 +525,43 @@ impl fmt::Display for SegmentationLevel {
     )
   }
 }
+
+/// Interpolation filters to search for each inter block
+#[derive(
+  Clone,
+  Copy,
+  Debug,
+  PartialOrd,
+  PartialEq,
+  Eq,
+  FromPrimitive,
+  Serialize,
+  Deserialize,
+)]
+#[cfg_attr(test, derive(Default))]
+pub enum InterpFilterSearch {
+  /// Use the regular filter for every block.
+  #[cfg_attr(test, default)]
+  Off,
+  /// Search the same filter in both directions.
+  Joint,
+  /// Search the horizontal filter, then the vertical filter.
+  Separable,
+  /// Search all pairs of horizontal and vertical filters.
+  Full,
+}
+
+impl fmt::Display for InterpFilterSearch {
+  fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+    write!(
+      f,
+      "{}",
+      match self {
+        InterpFilterSearch::Off => "Off",
+        InterpFilterSearch::Joint => "Joint",
+        InterpFilterSearch::Separable => "Separable",
+        InterpFilterSearch::Full => "Full",
+      }
+    )
+  }
+}
@@ -9,12 +9,8 @@
 #![deny(missing_docs)]
 
 use std::fmt;
-use std::io;
 use std::sync::Arc;
 
-use bitstream_io::{BigEndian, BitWrite2, BitWriter};
-
-use crate::api::color::*;
 use crate::api::config::*;
 use crate::api::internal::*;
 use crate::api::util::*;
@@ -73,6 +69,10 @@ impl<T: Pixel> Context<T> {
   /// or the encoder internal limit is hit (`std::i32::MAX` frames) the
   /// [`EncoderStatus::EnoughData`] error is returned.
   ///
+  /// If the [`importance_map`] of the frame does not match its dimensions
+  /// or has entries that are not positive, or its [`quantizer`] is 0, the
+  /// [`EncoderStatus::Failure`] error is returned.
+  ///
   /// # Examples
   ///
   /// ```
@@ -104,6 +104,9 @@ impl<T: Pixel> Context<T> {
   ///
   /// [`flush`]: #method.flush
   /// [`EncoderStatus::EnoughData`]: enum.EncoderStatus.html#variant.EnoughData
+  /// [`importance_map`]: struct.FrameParameters.html#structfield.importance_map
+  /// [`quantizer`]: struct.FrameParameters.html#structfield.quantizer
+  /// [`EncoderStatus::Failure`]: enum.EncoderStatus.html#variant.Failure
   #[inline]
   pub fn send_frame<F>(&mut self, frame: F) -> Result<(), EncoderStatus>
   where
@@ -326,9 +329,15 @@ impl<T: Pixel> Context<T> {
 
   /// Produces a sequence header matching the current encoding context.
   ///
-  /// Its format is compatible with the AV1 Matroska and ISOBMFF specification.
-  /// Note that the returned header does not include any config OBUs which are
-  /// required for some uses. See [the specification].
+  /// Its format is compatible with the AV1 Matroska and ISOBMFF specification:
+  /// an `AV1CodecConfigurationRecord` whose config OBUs hold the sequence
+  /// header OBU. See [the specification].
+  ///
+  /// Earlier versions returned the 4-byte record alone, with the level set
+  /// to 31 and the main tier whatever the configuration. The record now
+  /// carries the level, tier and chroma sample position of the sequence,
+  /// and is followed by its sequence header OBU, so callers that expected
+  /// exactly 4 bytes must take its length into account.
   ///
   /// [the specification]:
   /// https://aomediacodec.github.io/av1-isobmff/#av1codecconfigurationbox-section
@@ -339,34 +348,7 @@ impl<T: Pixel> Context<T> {
   /// and usually indicates the system is out of memory.
   #[inline]
   pub fn container_sequence_header(&self) -> Vec<u8> {
-    fn sequence_header_inner(seq: &Sequence) -> io::Result<Vec<u8>> {
-      let mut buf = Vec::new();
-
-      {
-        let mut bw = BitWriter::endian(&mut buf, BigEndian);
-        bw.write_bit(true)?; // marker
-        bw.write(7, 1)?; // version
-        bw.write(3, seq.profile)?;
-        bw.write(5, 31)?; // level
-        bw.write_bit(false)?; // tier
-        bw.write_bit(seq.bit_depth > 8)?; // high_bitdepth
-        bw.write_bit(seq.bit_depth == 12)?; // twelve_bit
-        bw.write_bit(seq.chroma_sampling == ChromaSampling::Cs400)?; // monochrome
-        bw.write_bit(seq.chroma_sampling != ChromaSampling::Cs444)?; // chroma_subsampling_x
-        bw.write_bit(seq.chroma_sampling == ChromaSampling::Cs420)?; // chroma_subsampling_y
-        bw.write(2, 0)?; // chroma_sample_position
-        bw.write(3, 0)?; // reserved
-        bw.write_bit(false)?; // initial_presentation_delay_present
-
-        bw.write(4, 0)?; // reserved
-      }
-
-      Ok(buf)
-    }
-
-    let seq = Sequence::new(&self.config);
-
-    sequence_header_inner(&seq).unwrap()
+    container_sequence_header::<T>(&self.config).unwrap()
   }
 }
 
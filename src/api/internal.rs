@@ -11,22 +11,29 @@
 use crate::activity::ActivityMask;
 use crate::api::lookahead::*;
 use crate::api::{
-  EncoderConfig, EncoderStatus, FrameType, Opaque, Packet, T35,
+  EncoderConfig, EncoderStatus, FrameType, Opaque, Packet, QualitySearchStats,
+  QualityTarget, T35,
 };
 use crate::color::ChromaSampling::Cs400;
 use crate::cpu_features::CpuFeatureLevel;
-use crate::dist::get_satd;
+use crate::dist::{frame_psnr, frame_ssim, get_satd};
 use crate::encoder::*;
 use crate::frame::*;
+use crate::global_motion::estimate_global_motion;
+use crate::levels::{level_max_bitrate, level_max_frame_bits};
+use crate::me::{FrameMEStats, RefMEStats};
 use crate::partition::*;
 use crate::rate::{
-  RCState, FRAME_NSUBTYPES, FRAME_SUBTYPE_I, FRAME_SUBTYPE_P,
-  FRAME_SUBTYPE_SEF,
+  RCFrameStats, RCState, FRAME_NSUBTYPES, FRAME_SUBTYPE_I, FRAME_SUBTYPE_P,
+  FRAME_SUBTYPE_SEF, VBV_MAX_REENCODES,
+};
+use crate::rdo::DistortionScale;
+use crate::scenechange::{
+  SceneChangeDetector, ScreenContentDetector, ScreenContentStats,
 };
-use crate::scenechange::SceneChangeDetector;
 use crate::stats::EncoderStats;
 use crate::tiling::Area;
-use crate::util::Pixel;
+use crate::util::{blog64, q57, Fixed, Pixel};
 use arrayvec::ArrayVec;
 use std::cmp;
 use std::collections::{BTreeMap, BTreeSet};
@@ -56,6 +63,9 @@ pub struct InterConfig {
   /// Keyframes reset this interval.
   /// This MUST be a multiple of `group_input_len`.
   pub(crate) switch_frame_interval: u64,
+  /// Number of temporal layers.
+  /// Only values larger than 1 when re-ordering is disabled.
+  pub(crate) temporal_layers: u64,
 }
 
 impl InterConfig {
@@ -81,6 +91,8 @@ impl InterConfig {
     let group_output_len = group_input_len + pyramid_depth;
     let switch_frame_interval = enc_config.switch_frame_interval;
     assert!(switch_frame_interval % group_input_len == 0);
+    let temporal_layers = enc_config.temporal_layers as u64;
+    assert!(temporal_layers == 1 || !reorder);
     InterConfig {
       reorder,
       multiref: reorder || enc_config.speed_settings.multiref,
@@ -88,6 +100,7 @@ impl InterConfig {
       group_input_len,
       group_output_len,
       switch_frame_interval,
+      temporal_layers,
     }
   }
 
@@ -155,6 +168,32 @@ impl InterConfig {
     }
   }
 
+  /// Get the temporal layer of a frame given its order hint.
+  /// The layers follow a dyadic pattern, e.g. 0, 2, 1, 2 for three layers.
+  pub(crate) const fn get_temporal_id(&self, order_hint: u32) -> u8 {
+    if self.temporal_layers <= 1 {
+      return 0;
+    }
+    let pos = order_hint & ((1 << (self.temporal_layers - 1)) - 1);
+    if pos == 0 {
+      0
+    } else {
+      (self.temporal_layers as u32 - 1 - pos.trailing_zeros()) as u8
+    }
+  }
+
+  pub(crate) const fn get_temporal_slot_idx(
+    &self, temporal_id: u8, order_hint: u32,
+  ) -> u32 {
+    // Base layer frames are stored in slots 0..4, and the middle layer of a
+    //  three layer structure in slot 4. The top layer is never referenced.
+    if temporal_id == 0 {
+      (order_hint >> (self.temporal_layers - 1)) & 3
+    } else {
+      4
+    }
+  }
+
   pub(crate) const fn get_show_frame(&self, idx_in_group_output: u64) -> bool {
     idx_in_group_output >= self.pyramid_depth
   }
@@ -219,6 +258,13 @@ impl<T: Pixel> FrameData<T> {
   }
 }
 
+/// Maximum number of frames of a group of pictures coded at each quantizer
+///  tried by the quality-targeted mode.
+const QUALITY_SEARCH_SAMPLES: usize = 4;
+/// Number of quantizers tried by the quality-targeted mode for each group of
+///  pictures.
+const QUALITY_SEARCH_STEPS: usize = 5;
+
 type FrameQueue<T> = BTreeMap<u64, Option<Arc<Frame<T>>>>;
 type FrameDataQueue<T> = BTreeMap<u64, Option<FrameData<T>>>;
 
@@ -247,10 +293,19 @@ pub(crate) struct ContextInner<T: Pixel> {
   /// Maps `output_frameno` to `gop_input_frameno_start`.
   pub(crate) gop_input_frameno_start: BTreeMap<u64, u64>,
   keyframe_detector: SceneChangeDetector<T>,
+  screen_content_detector: ScreenContentDetector,
   pub(crate) config: Arc<EncoderConfig>,
+  /// Configuration of the frames of each zone
+  zone_configs: Vec<Arc<EncoderConfig>>,
   seq: Arc<Sequence>,
   pub(crate) rc_state: RCState,
   maybe_prev_log_base_q: Option<i64>,
+  /// Timestamp of the last key frame, from which the removal times of the
+  ///  decoder model are counted
+  keyframe_pts: u64,
+  /// Motion estimation buffer used to estimate the inter costs of the frames
+  ///  in the pass data
+  inter_cost_me_stats: Option<RefMEStats>,
   /// The next `input_frameno` to be processed by lookahead.
   next_lookahead_frame: u64,
   /// The next `output_frameno` to be computed by lookahead.
@@ -259,22 +314,78 @@ pub(crate) struct ContextInner<T: Pixel> {
   opaque_q: BTreeMap<u64, Opaque>,
   /// Optional T35 metadata per frame
   t35_q: BTreeMap<u64, Box<[T35]>>,
+  /// Presentation timestamp and duration per frame, in `time_base` units
+  frame_time_q: BTreeMap<u64, (u64, u64)>,
+  /// Base quantizer index override and offset per frame
+  quantizer_q: BTreeMap<u64, (Option<u8>, i16)>,
+  /// Frames that must not be used as references
+  no_reference_frames: BTreeSet<u64>,
+  /// Optional importance map per frame
+  importance_map_q: BTreeMap<u64, Box<[f32]>>,
+  /// Timestamp and duration of the last frame sent, and whether that
+  ///  duration was provided by the user
+  last_frame_time: Option<(u64, u64, bool)>,
+  /// Quantizer selected for the current group of pictures in the
+  ///  quality-targeted mode
+  quality_search: Option<QualitySearchStats>,
 }
 
 impl<T: Pixel> ContextInner<T> {
   pub fn new(enc: &EncoderConfig) -> Self {
     // initialize with temporal delimiter
-    let packet_data = TEMPORAL_DELIMITER.to_vec();
+    let mut packet_data = Vec::new();
+    write_temporal_delimiter(&mut packet_data, enc.annex_b).unwrap();
     let mut keyframes = BTreeSet::new();
     keyframes.insert(0);
 
-    let maybe_ac_qi_max =
-      if enc.quantizer < 255 { Some(enc.quantizer as u8) } else { None };
+    // The quality target of the constant quality mode takes the place of the
+    //  quantizer, which must not cap it.
+    let maybe_ac_qi_max = if enc.crf.is_none() && enc.quantizer < 255 {
+      Some(enc.quantizer as u8)
+    } else {
+      None
+    };
 
     let seq = Arc::new(Sequence::new(enc));
+    // A level set by the caller bounds the bitrate through a decoder buffer
+    //  model of its own, unless a stricter one is configured, and the size
+    //  of every frame. A level selected by the encoder is only signaled.
+    let level_idx = enc.level_idx.unwrap_or(31);
+    let (max_bitrate, vbv_buffer_size, max_frame_bits) = if level_idx < 31 {
+      let max_frame_bits = level_max_frame_bits(
+        level_idx,
+        seq.profile,
+        enc.width,
+        enc.height,
+        enc.still_picture,
+      );
+      if enc.max_bitrate > 0 {
+        (enc.max_bitrate, enc.decoder_buffer_size(), max_frame_bits)
+      } else {
+        let max_bitrate = level_max_bitrate(level_idx, seq.profile);
+        (max_bitrate as i32, max_bitrate, max_frame_bits)
+      }
+    } else {
+      (enc.max_bitrate, enc.decoder_buffer_size(), 0)
+    };
     let inter_cfg = InterConfig::new(enc);
     let lookahead_distance = inter_cfg.keyframe_lookahead_distance() as usize;
 
+    let config = Arc::new(enc.clone());
+    let zone_configs = enc
+      .zones
+      .iter()
+      .map(|zone| match zone.speed {
+        Some(speed) => Arc::new(EncoderConfig {
+          speed_settings: enc.speed_settings.with_frame_preset(speed),
+          ..enc.clone()
+        }),
+        None => Arc::clone(&config),
+      })
+      .collect();
+    let keyframes_forced =
+      enc.zones.iter().map(|zone| zone.start_frame).collect();
+
     ContextInner {
       frame_count: 0,
       limit: None,
@@ -284,7 +395,7 @@ impl<T: Pixel> ContextInner<T> {
       frame_q: BTreeMap::new(),
       frame_data: BTreeMap::new(),
       keyframes,
-      keyframes_forced: BTreeSet::new(),
+      keyframes_forced,
       packet_data,
       gop_output_frameno_start: BTreeMap::new(),
       gop_input_frameno_start: BTreeMap::new(),
@@ -294,7 +405,12 @@ impl<T: Pixel> ContextInner<T> {
         lookahead_distance,
         seq.clone(),
       ),
-      config: Arc::new(enc.clone()),
+      screen_content_detector: ScreenContentDetector::new(
+        enc.screen_content,
+        enc.bit_depth,
+      ),
+      config,
+      zone_configs,
       seq,
       rc_state: RCState::new(
         enc.width as i32,
@@ -306,12 +422,25 @@ impl<T: Pixel> ContextInner<T> {
         enc.min_quantizer,
         enc.max_key_frame_interval as i32,
         enc.reservoir_frame_delay,
+        max_bitrate,
+        vbv_buffer_size,
+        max_frame_bits,
+        enc.crf,
+        enc.drop_frames,
       ),
       maybe_prev_log_base_q: None,
+      keyframe_pts: 0,
+      inter_cost_me_stats: None,
       next_lookahead_frame: 1,
       next_lookahead_output_frameno: 0,
       opaque_q: BTreeMap::new(),
       t35_q: BTreeMap::new(),
+      frame_time_q: BTreeMap::new(),
+      quantizer_q: BTreeMap::new(),
+      no_reference_frames: BTreeSet::new(),
+      importance_map_q: BTreeMap::new(),
+      last_frame_time: None,
+      quality_search: None,
     }
   }
 
@@ -320,6 +449,23 @@ impl<T: Pixel> ContextInner<T> {
     &mut self, mut frame: Option<Arc<Frame<T>>>,
     params: Option<FrameParameters>,
   ) -> Result<(), EncoderStatus> {
+    // Lossless coding cannot be selected per frame.
+    if params.as_ref().is_some_and(|params| params.quantizer == Some(0)) {
+      return Err(EncoderStatus::Failure);
+    }
+    if let Some(map) =
+      params.as_ref().and_then(|params| params.importance_map.as_ref())
+    {
+      let EncoderConfig { width, height, .. } = *self.config;
+      let imp_blocks = width.align_power_of_two_and_shift(3)
+        * height.align_power_of_two_and_shift(3);
+      if map.len() != imp_blocks
+        || !map.iter().all(|&weight| weight.is_finite() && weight > 0.)
+      {
+        return Err(EncoderStatus::Failure);
+      }
+    }
+
     if let Some(ref mut frame) = frame {
       use crate::api::color::ChromaSampling;
       let EncoderConfig { width, height, chroma_sampling, .. } = *self.config;
@@ -342,11 +488,19 @@ impl<T: Pixel> ContextInner<T> {
 
     let input_frameno = self.frame_count;
     let is_flushing = frame.is_none();
-    if !is_flushing {
+    if let Some(ref frame) = frame {
       self.frame_count += 1;
+      self.screen_content_detector.analyze_frame(frame, input_frameno);
     }
     self.frame_q.insert(input_frameno, frame);
 
+    if !is_flushing {
+      let (pts, duration) = params
+        .as_ref()
+        .map_or((None, None), |params| (params.pts, params.duration));
+      self.set_frame_time(input_frameno, pts, duration);
+    }
+
     if let Some(params) = params {
       if params.frame_type_override == FrameTypeOverride::Key {
         self.keyframes_forced.insert(input_frameno);
@@ -355,9 +509,22 @@ impl<T: Pixel> ContextInner<T> {
         self.opaque_q.insert(input_frameno, op);
       }
       self.t35_q.insert(input_frameno, params.t35_metadata);
+      if params.quantizer.is_some() || params.quantizer_offset != 0 {
+        self
+          .quantizer_q
+          .insert(input_frameno, (params.quantizer, params.quantizer_offset));
+      }
+      if params.no_reference {
+        self.no_reference_frames.insert(input_frameno);
+      }
+      if let Some(map) = params.importance_map {
+        self.importance_map_q.insert(input_frameno, map);
+      }
     }
 
-    if !self.needs_more_frame_q_lookahead(self.next_lookahead_frame) {
+    if self.rc_state.places_keyframes() {
+      self.place_keyframes_from_pass_data();
+    } else if !self.needs_more_frame_q_lookahead(self.next_lookahead_frame) {
       let lookahead_frames = self
         .frame_q
         .range(self.next_lookahead_frame - 1..)
@@ -434,6 +601,47 @@ impl<T: Pixel> ContextInner<T> {
       .take(self.config.speed_settings.rdo_lookahead_frames + 1)
   }
 
+  /// Records the timestamp and duration of a new input frame, filling in the
+  ///  missing values from the previous frames.
+  fn set_frame_time(
+    &mut self, input_frameno: u64, pts: Option<u64>, duration: Option<u64>,
+  ) {
+    let mut last = self.last_frame_time;
+    if let (Some(pts), Some((last_pts, last_duration, false))) =
+      (pts, last.as_mut())
+    {
+      if pts > *last_pts {
+        // Now that we know when the previous frame ended, fix up its
+        //  duration, along with the frame invariants already built for it.
+        *last_duration = pts - *last_pts;
+        if let Some(time) = self.frame_time_q.get_mut(&(input_frameno - 1)) {
+          time.1 = *last_duration;
+        }
+        for fi in self.frame_data.values_mut().flatten().map(|fd| &mut fd.fi) {
+          if fi.input_frameno == input_frameno - 1 {
+            fi.duration = *last_duration;
+          }
+        }
+      }
+    }
+    let is_set = duration.is_some();
+    let pts = pts.unwrap_or_else(|| last.map_or(0, |(pts, d, _)| pts + d));
+    let duration =
+      duration.unwrap_or_else(|| last.map_or(1, |(_, d, _)| d)).max(1);
+    self.frame_time_q.insert(input_frameno, (pts, duration));
+    self.last_frame_time = Some((pts, duration, is_set));
+  }
+
+  /// Returns the timestamp and duration of an input frame, falling back to a
+  ///  constant frame rate if the frame is unknown.
+  fn get_frame_time(&self, input_frameno: u64) -> (u64, u64) {
+    self
+      .frame_time_q
+      .get(&input_frameno)
+      .copied()
+      .unwrap_or((input_frameno, 1))
+  }
+
   fn next_keyframe_input_frameno(
     &self, gop_input_frameno_start: u64, ignore_limit: bool,
   ) -> u64 {
@@ -518,7 +726,14 @@ impl<T: Pixel> ContextInner<T> {
     if self.needs_more_frame_q_lookahead(input_frameno) {
       return Err(EncoderStatus::NeedMoreData);
     }
+    // The keyframes placed from the pass data may wait for more of it.
+    if self.rc_state.places_keyframes()
+      && input_frameno >= self.next_lookahead_frame
+    {
+      return Err(EncoderStatus::NeedMoreData);
+    }
 
+    let (pts, duration) = self.get_frame_time(input_frameno);
     let t35_metadata = if let Some(t35) = self.t35_q.remove(&input_frameno) {
       t35
     } else {
@@ -560,6 +775,8 @@ impl<T: Pixel> ContextInner<T> {
             output_frameno_in_gop,
             next_keyframe_input_frameno,
             self.config.error_resilient,
+            pts,
+            duration,
             t35_metadata,
           );
           assert!(fi.is_none());
@@ -590,13 +807,19 @@ impl<T: Pixel> ContextInner<T> {
 
     let output_frameno_in_gop =
       output_frameno - self.gop_output_frameno_start[&output_frameno];
+    let screen_content = self.screen_content_detector.get(input_frameno);
+    let (pts, duration) = self.get_frame_time(input_frameno);
     if output_frameno_in_gop == 0 {
-      let fi = FrameInvariants::new_key_frame(
+      let mut fi = FrameInvariants::new_key_frame(
         self.config.clone(),
         self.seq.clone(),
         self.gop_input_frameno_start[&output_frameno],
+        pts,
+        duration,
         t35_metadata,
       );
+      fi.set_screen_content(screen_content);
+      self.apply_frame_overrides(&mut fi);
       Ok(Some(fi))
     } else {
       let next_keyframe_input_frameno = self.next_keyframe_input_frameno(
@@ -610,13 +833,209 @@ impl<T: Pixel> ContextInner<T> {
         output_frameno_in_gop,
         next_keyframe_input_frameno,
         self.config.error_resilient,
+        pts,
+        duration,
         t35_metadata,
-      );
+      )
+      .map(|mut fi| {
+        fi.set_screen_content(screen_content);
+        self.apply_frame_overrides(&mut fi);
+        fi
+      });
       assert!(fi.is_some());
       Ok(fi)
     }
   }
 
+  /// Selects the base quantizer of the group of pictures starting at the key
+  ///  frame `gop_input_frameno` in the quality-targeted mode.
+  ///
+  /// A few frames of the group found in the lookahead are coded for each
+  ///  quantizer tried by a bisection search for the coarsest one that meets
+  ///  the quality target: the first one as a key frame, and each of the
+  ///  others as a predicted frame referencing the reconstruction of the
+  ///  previous one.
+  fn search_gop_quantizer(
+    &mut self, gop_input_frameno: u64, output_frameno: u64,
+  ) {
+    let Some(target) = self.config.quality_target else {
+      return;
+    };
+    let next_keyframe = self
+      .keyframes
+      .range(gop_input_frameno + 1..)
+      .next()
+      .copied()
+      .unwrap_or(u64::MAX);
+    let frames: Vec<_> = self
+      .frame_q
+      .range(gop_input_frameno..next_keyframe)
+      .filter_map(|(_, frame)| frame.clone())
+      .collect();
+    let nsamples = QUALITY_SEARCH_SAMPLES.min(frames.len());
+    let samples: Vec<_> = (0..nsamples)
+      .map(|i| frames[i * frames.len() / nsamples].clone())
+      .collect();
+
+    // The samples are coded in a row, each one predicted from the previous.
+    let mut config = (*self.config).clone();
+    config.low_latency = true;
+    config.speed_settings.multiref = false;
+    let inter_cfg = InterConfig::new(&config);
+    let config = Arc::new(config);
+    let (w, h) = (self.config.width, self.config.height);
+    let bit_depth = self.config.bit_depth;
+    let planes = if self.config.chroma_sampling == Cs400 { 1 } else { 3 };
+    let measure = |qi: u8| -> f64 {
+      let mut quality_sum = 0.;
+      let mut prev_fi: Option<FrameInvariants<T>> = None;
+      for (i, frame) in samples.iter().enumerate() {
+        let (mut fi, fti) = match &prev_fi {
+          None => {
+            let mut fi = FrameInvariants::new_key_frame(
+              config.clone(),
+              self.seq.clone(),
+              0,
+              0,
+              1,
+              Box::new([]),
+            );
+            fi.coded_frame_data.as_mut().unwrap().compute_temporal_scores();
+            (fi, FRAME_SUBTYPE_I)
+          }
+          Some(prev_fi) => {
+            let fi = FrameInvariants::new_inter_frame(
+              prev_fi,
+              &inter_cfg,
+              0,
+              i as u64,
+              nsamples as u64,
+              false,
+              i as u64,
+              1,
+              Box::new([]),
+            )
+            .unwrap();
+            (fi, FRAME_SUBTYPE_P)
+          }
+        };
+        let qps = self.rc_state.select_qi(
+          self,
+          output_frameno,
+          fti,
+          true,
+          None,
+          0,
+          Some(qi),
+          0,
+          0,
+        );
+        fi.set_quantizers(&qps);
+        let mut fs = FrameState::new_with_frame(&fi, frame.clone());
+        if fti == FRAME_SUBTYPE_P {
+          compute_motion_vectors(&mut fi, &mut fs, &inter_cfg);
+        }
+        encode_frame(&fi, &mut fs, &inter_cfg);
+        let (src, rec) = (&frame.planes[0], &fs.rec.planes[0]);
+        quality_sum += match target {
+          QualityTarget::Psnr(_) => frame_psnr(src, rec, w, h, bit_depth),
+          QualityTarget::Ssim(_) => frame_ssim(src, rec, w, h, bit_depth),
+        };
+
+        Arc::get_mut(&mut fs.rec).unwrap().pad(fi.width, fi.height, planes);
+        update_rec_buffer(i as u64, &mut fi, &fs);
+        prev_fi = Some(fi);
+      }
+      quality_sum / samples.len() as f64
+    };
+    let meets_target = |quality: f64| match target {
+      QualityTarget::Psnr(value) | QualityTarget::Ssim(value) => {
+        quality >= value
+      }
+    };
+
+    // Quality falls as the quantizer grows.
+    let qi_min = self.config.min_quantizer.max(1);
+    let qi_max = (self.config.quantizer.min(255) as u8).max(qi_min);
+    let (mut lo, mut hi) = (qi_min, qi_max);
+    let mut best = None;
+    let mut finest = None;
+    let mut trial_quantizers = 0;
+    for _ in 0..QUALITY_SEARCH_STEPS {
+      if lo > hi {
+        break;
+      }
+      let qi = lo + (hi - lo) / 2;
+      let quality = measure(qi);
+      trial_quantizers += 1;
+      if meets_target(quality) {
+        best = Some((qi, quality));
+        if qi == hi {
+          break;
+        }
+        lo = qi + 1;
+      } else {
+        if qi == qi_min {
+          finest = Some((qi, quality));
+        }
+        if qi == lo {
+          break;
+        }
+        hi = qi - 1;
+      }
+    }
+    let (quantizer, quality) = best.or(finest).unwrap_or_else(|| {
+      // The target is out of reach, fall back to the finest quantizer.
+      trial_quantizers += 1;
+      (qi_min, measure(qi_min))
+    });
+
+    self.quality_search = Some(QualitySearchStats {
+      input_frameno: gop_input_frameno,
+      quantizer,
+      quality,
+      target_met: meets_target(quality),
+      sample_frames: nsamples,
+      trial_quantizers,
+    });
+  }
+
+  /// Applies the overrides given in the `FrameParameters` of the input frame.
+  fn apply_frame_overrides(&self, fi: &mut FrameInvariants<T>) {
+    (fi.quantizer_override, fi.quantizer_offset) =
+      self.quantizer_q.get(&fi.input_frameno).copied().unwrap_or_default();
+    fi.log_bitrate_factor = 0;
+    let zone_idx = self.config.zones.iter().position(|zone| {
+      fi.input_frameno >= zone.start_frame
+        && fi.input_frameno <= zone.end_frame
+    });
+    let config = match zone_idx {
+      Some(idx) => {
+        let zone = &self.config.zones[idx];
+        fi.quantizer_override = fi.quantizer_override.or(zone.quantizer);
+        if let Some(bitrate_factor) = zone.bitrate_factor {
+          // The factor is validated to be positive, keep it in Q16.
+          let bitrate_factor_q16 = (bitrate_factor * 65536.).round().max(1.);
+          fi.log_bitrate_factor = blog64(bitrate_factor_q16 as i64) - q57(16);
+        }
+        &self.zone_configs[idx]
+      }
+      None => &self.config,
+    };
+    if !Arc::ptr_eq(&fi.config, config) {
+      fi.set_config(Arc::clone(config));
+    }
+    // Key frames and switch frames always refresh every slot, and frames
+    //  coded ahead of their display order are shown later from their slot.
+    if fi.frame_type == FrameType::INTER
+      && fi.show_frame
+      && !fi.is_show_existing_frame()
+      && self.no_reference_frames.contains(&fi.input_frameno)
+    {
+      fi.refresh_frame_flags = 0;
+    }
+  }
+
   fn get_previous_fi(&self, output_frameno: u64) -> &FrameInvariants<T> {
     let res = self
       .frame_data
@@ -663,13 +1082,17 @@ impl<T: Pixel> ContextInner<T> {
     }
 
     let qps = {
-      let fti = frame_data.as_ref().unwrap().fi.get_frame_subtype();
+      let fi = &frame_data.as_ref().unwrap().fi;
       self.rc_state.select_qi(
         self,
         output_frameno,
-        fti,
+        fi.get_frame_subtype(),
+        fi.show_frame,
         self.maybe_prev_log_base_q,
         0,
+        fi.quantizer_override,
+        fi.quantizer_offset,
+        fi.log_bitrate_factor,
       )
     };
 
@@ -723,6 +1146,7 @@ impl<T: Pixel> ContextInner<T> {
         frame_me_stats: fs.frame_me_stats.clone(),
         output_frameno,
         segmentation: fs.segmentation,
+        gm_params: fi.gm_params,
       });
       for i in 0..REF_FRAMES {
         if (fi.refresh_frame_flags & (1 << i)) != 0 {
@@ -805,6 +1229,7 @@ impl<T: Pixel> ContextInner<T> {
       frame_me_stats: fs.frame_me_stats.clone(),
       output_frameno,
       segmentation: fs.segmentation,
+      gm_params: fi.gm_params,
     });
     for i in 0..REF_FRAMES {
       if (fi.refresh_frame_flags & (1 << i)) != 0 {
@@ -860,6 +1285,139 @@ impl<T: Pixel> ContextInner<T> {
       });
   }
 
+  /// Estimates the complexity of a frame for the constant quality mode, as
+  ///  the mean SATD cost of its importance blocks.
+  /// Inter frames use the cost of predicting them from a previous frame, if
+  ///  that is lower than the intra cost. It is the cost against the previous
+  ///  input frame computed by the scene change detection if available,
+  ///  otherwise the cost against the first reference of the frame with the
+  ///  motion vectors the lookahead found, so that no motion search is run.
+  fn estimate_frame_complexity(
+    &self, fi: &FrameInvariants<T>, fs: &FrameState<T>, fti: usize,
+  ) -> f64 {
+    let intra_cost = self.estimate_intra_cost(fi);
+    if fti == FRAME_SUBTYPE_I {
+      return intra_cost;
+    }
+    let inter_cost = self
+      .keyframe_detector
+      .inter_costs
+      .get(&fi.input_frameno)
+      .copied()
+      .or_else(|| {
+        let reference =
+          fi.rec_buffer.frames[fi.ref_frames[0] as usize].as_ref()?;
+        let frame = self.frame_q[&fi.input_frameno].as_ref().unwrap();
+        Some(estimate_mv_inter_costs(
+          frame,
+          &reference.frame,
+          &fs.frame_me_stats.read().expect("poisoned lock")[0],
+          self.config.bit_depth,
+          fi.cpu_feature_level,
+        ))
+      });
+    inter_cost.map_or(intra_cost, |inter_cost| intra_cost.min(inter_cost))
+  }
+
+  /// Estimates the mean intra SATD cost of the importance blocks of a frame.
+  fn estimate_intra_cost(&self, fi: &FrameInvariants<T>) -> f64 {
+    let mean_cost = |costs: &[u32]| {
+      costs.iter().map(|&cost| cost as u64).sum::<u64>() as f64
+        / costs.len().max(1) as f64
+    };
+    let lookahead_intra_costs =
+      &fi.coded_frame_data.as_ref().unwrap().lookahead_intra_costs;
+    if !lookahead_intra_costs.is_empty() {
+      mean_cost(lookahead_intra_costs)
+    } else if let Some(intra_costs) =
+      self.keyframe_detector.intra_costs.get(&fi.input_frameno)
+    {
+      mean_cost(intra_costs)
+    } else {
+      let frame = self.frame_q[&fi.input_frameno].as_ref().unwrap();
+      let mut temp_plane = frame.planes[0].clone();
+      mean_cost(&estimate_intra_costs(
+        &mut temp_plane,
+        frame,
+        self.config.bit_depth,
+        fi.cpu_feature_level,
+      ))
+    }
+  }
+
+  /// Estimates the mean SATD cost of predicting the importance blocks of a
+  ///  frame from the previous input frame.
+  /// Returns `None` if there is no previous input frame.
+  /// The cost from the scene change detection is reused if available.
+  fn estimate_inter_cost(&mut self, fi: &FrameInvariants<T>) -> Option<f64> {
+    if let Some(&inter_cost) =
+      self.keyframe_detector.inter_costs.get(&fi.input_frameno)
+    {
+      return Some(inter_cost);
+    }
+    let frame = Arc::clone(self.frame_q[&fi.input_frameno].as_ref().unwrap());
+    let Some(Some(prev_frame)) = fi
+      .input_frameno
+      .checked_sub(1)
+      .and_then(|input_frameno| self.frame_q.get(&input_frameno))
+    else {
+      return None;
+    };
+    let buffer =
+      Arc::clone(self.inter_cost_me_stats.get_or_insert_with(|| {
+        let cols = 2 * self.config.width.align_power_of_two_and_shift(3);
+        let rows = 2 * self.config.height.align_power_of_two_and_shift(3);
+        FrameMEStats::new_arc_array(cols, rows)
+      }));
+    Some(estimate_inter_costs(
+      frame,
+      Arc::clone(prev_frame),
+      self.config.bit_depth,
+      (*self.config).clone(),
+      Arc::clone(&self.seq),
+      buffer,
+    ))
+  }
+
+  /// Analyzes a coded frame for the pass data of the rate control.
+  /// The motion is measured against its first reference frame.
+  fn analyze_frame_stats(
+    &mut self, fi: &FrameInvariants<T>, fs: &FrameState<T>,
+  ) -> RCFrameStats {
+    let intra_cost = self.estimate_intra_cost(fi);
+    let inter_cost = self.estimate_inter_cost(fi);
+    let coded_data = fi.coded_frame_data.as_ref().unwrap();
+    let motion = if fi.frame_type.has_inter() {
+      estimate_motion_magnitude(
+        &fs.frame_me_stats.read().expect("poisoned lock")[0],
+        coded_data.w_in_imp_b,
+        coded_data.h_in_imp_b,
+      )
+    } else {
+      0.
+    };
+    // The psychovisual tuning already computed the activity mask.
+    let spatial_complexity = if self.config.tune == Tune::Psychovisual {
+      coded_data.activity_mask.mean_variance()
+    } else {
+      let frame = self.frame_q[&fi.input_frameno].as_ref().unwrap();
+      ActivityMask::from_plane(&frame.planes[0]).mean_variance()
+    };
+    RCFrameStats {
+      input_frameno: fi.input_frameno,
+      intra_cost: intra_cost as f32,
+      inter_cost: inter_cost.map(|cost| cost as f32),
+      motion: motion as f32,
+      scene_score: self
+        .keyframe_detector
+        .scores
+        .get(&fi.input_frameno)
+        .map_or(0., |&score| score as f32),
+      spatial_complexity: spatial_complexity as f32,
+      ..Default::default()
+    }
+  }
+
   #[profiling::function]
   pub fn compute_keyframe_placement(
     lookahead_frames: &[&Arc<Frame<T>>], keyframes_forced: &BTreeSet<u64>,
@@ -879,6 +1437,43 @@ impl<T: Pixel> ContextInner<T> {
     *next_lookahead_frame += 1;
   }
 
+  /// Places the keyframes of the queued frames from the statistics of the
+  ///  previous pass, as far as its data has been read.
+  ///
+  /// A frame starting a scene in the previous pass, i.e. coded as a keyframe
+  ///  with a scene score of at least 1, starts one again, within the keyframe
+  ///  intervals of this pass.
+  pub(crate) fn place_keyframes_from_pass_data(&mut self) {
+    while let Some(Some(_)) = self.frame_q.get(&self.next_lookahead_frame) {
+      let input_frameno = self.next_lookahead_frame;
+      let Some(stats) = self.rc_state.take_frame_stats(input_frameno) else {
+        // Wait for the data of the queued frames.
+        let end = self.frame_q.keys().last().copied().unwrap_or(input_frameno);
+        self.rc_state.wait_for_frame_stats(input_frameno..end + 1);
+        return;
+      };
+      if let Some(stats) = stats {
+        self
+          .keyframe_detector
+          .scores
+          .insert(input_frameno, stats.scene_score as f64);
+      }
+      let distance = input_frameno - *self.keyframes.iter().last().unwrap();
+      let scene_change =
+        stats.is_some_and(|stats| stats.keyframe && stats.scene_score >= 1.);
+      if self.keyframes_forced.contains(&input_frameno)
+        || self
+          .keyframe_detector
+          .handle_min_max_intervals(distance)
+          .unwrap_or(scene_change)
+      {
+        self.keyframes.insert(input_frameno);
+      }
+      self.next_lookahead_frame += 1;
+    }
+    self.rc_state.wait_for_frame_stats(0..0);
+  }
+
   #[profiling::function]
   pub fn compute_frame_invariants(&mut self) {
     while self.set_frame_properties(self.next_lookahead_output_frameno).is_ok()
@@ -1296,10 +1891,17 @@ impl<T: Pixel> ContextInner<T> {
       bits,
       FRAME_SUBTYPE_SEF,
       frame_data.fi.show_frame,
+      frame_data.fi.duration,
       0,
       false,
       false,
     );
+    if self.rc_state.emits_pass_data() {
+      self.rc_state.set_frame_stats(RCFrameStats {
+        input_frameno: frame_data.fi.input_frameno,
+        ..Default::default()
+      });
+    }
     let (rec, source) = if frame_data.fi.show_frame {
       (Some(frame_data.fs.rec.clone()), Some(frame_data.fs.input.clone()))
     } else {
@@ -1309,10 +1911,48 @@ impl<T: Pixel> ContextInner<T> {
     self.output_frameno += 1;
 
     let input_frameno = frame_data.fi.input_frameno;
+    let (pts, duration) = (frame_data.fi.pts, frame_data.fi.duration);
     let frame_type = frame_data.fi.frame_type;
+    let temporal_id = frame_data.fi.temporal_id;
     let qp = frame_data.fi.base_q_idx;
     let enc_stats = frame_data.fs.enc_stats.clone();
-    self.finalize_packet(rec, source, input_frameno, frame_type, qp, enc_stats)
+    let screen_content = frame_data.fi.screen_content;
+    self.finalize_packet(
+      rec,
+      source,
+      input_frameno,
+      pts,
+      duration,
+      frame_type,
+      temporal_id,
+      qp,
+      enc_stats,
+      screen_content,
+      false,
+    )
+  }
+
+  /// Returns the removal time of a frame from the decoder buffer, which is
+  ///  the timestamp of its temporal unit counted from the last key frame,
+  ///  the decoding ticks being units of the time base.
+  fn buffer_removal_time(
+    &mut self, fi: &FrameInvariants<T>, output_frameno: u64,
+  ) -> u32 {
+    // A hidden frame is removed along with the next frame shown.
+    let tu_pts = if fi.show_frame {
+      fi.pts
+    } else {
+      self
+        .frame_data
+        .range(output_frameno + 1..)
+        .filter_map(|(_, frame_data)| frame_data.as_ref())
+        .find(|frame_data| frame_data.fi.show_frame)
+        .map_or(fi.pts, |frame_data| frame_data.fi.pts)
+    };
+    if fi.frame_type == FrameType::KEY {
+      self.keyframe_pts = tu_pts;
+    }
+    tu_pts.saturating_sub(self.keyframe_pts) as u32
   }
 
   #[profiling::function]
@@ -1325,6 +1965,21 @@ impl<T: Pixel> ContextInner<T> {
     let mut log_isqrt_mean_scale = 0i64;
 
     if let Some(coded_data) = frame_data.fi.coded_frame_data.as_mut() {
+      if let Some(map) =
+        self.importance_map_q.remove(&frame_data.fi.input_frameno)
+      {
+        // Weight the distortion by the importance of the blocks before the
+        //  scales are normalized, so only the relative importance matters.
+        for (scale, &weight) in
+          coded_data.distortion_scales.iter_mut().zip(map.iter())
+        {
+          *scale *= DistortionScale::from(weight as f64);
+        }
+        coded_data.has_importance_map = true;
+        // The segments carry the quantizers of the more or less important
+        // blocks, unless the frame is lossless.
+        frame_data.fi.enable_segmentation = !self.config.lossless;
+      }
       if self.config.tune == Tune::Psychovisual {
         let frame =
           self.frame_q[&frame_data.fi.input_frameno].as_ref().unwrap();
@@ -1362,39 +2017,117 @@ impl<T: Pixel> ContextInner<T> {
       }
     }
 
+    // The lookahead motion vectors are overwritten by the trial encode, so
+    // global motion has to be estimated first.
+    if frame_data.fi.config.speed_settings.motion.global_motion {
+      estimate_global_motion(
+        &mut frame_data.fi,
+        &frame_data.fs,
+        &self.inter_cfg,
+      );
+    }
+
+    if frame_data.fi.sequence.decoder_model_info_present_flag {
+      frame_data.fi.buffer_removal_time =
+        self.buffer_removal_time(&frame_data.fi, cur_output_frameno);
+    }
+
+    if self.config.quality_target.is_some() {
+      if frame_data.fi.frame_type == FrameType::KEY {
+        self.search_gop_quantizer(
+          frame_data.fi.input_frameno,
+          cur_output_frameno,
+        );
+      }
+      if let Some(stats) = self.quality_search {
+        frame_data.fi.quantizer_override =
+          frame_data.fi.quantizer_override.or(Some(stats.quantizer));
+      }
+    }
+
     let fti = frame_data.fi.get_frame_subtype();
-    let qps = self.rc_state.select_qi(
+    if self.config.crf.is_some() {
+      let cplx =
+        self.estimate_frame_complexity(&frame_data.fi, &frame_data.fs, fti);
+      self.rc_state.update_crf_complexity(fti, cplx);
+    }
+    let mut qps = self.rc_state.select_qi(
       self,
       cur_output_frameno,
       fti,
+      frame_data.fi.show_frame,
       self.maybe_prev_log_base_q,
       log_isqrt_mean_scale,
+      frame_data.fi.quantizer_override,
+      frame_data.fi.quantizer_offset,
+      frame_data.fi.log_bitrate_factor,
     );
     frame_data.fi.set_quantizers(&qps);
 
-    if self.rc_state.needs_trial_encode(fti) {
+    // A quantizer chosen by the caller is used as is, unless the frame does
+    //  not fit the level.
+    let quantizer_forced = frame_data.fi.quantizer_override.is_some();
+    let level_constrained =
+      frame_data.fi.config.level_idx.is_some_and(|level_idx| level_idx < 31);
+    if self.rc_state.needs_trial_encode(fti) && !quantizer_forced {
       let mut trial_fs = frame_data.fs.clone();
       let data = encode_frame(&frame_data.fi, &mut trial_fs, &self.inter_cfg);
       self.rc_state.update_state(
         (data.len() * 8) as i64,
         fti,
         frame_data.fi.show_frame,
+        frame_data.fi.duration,
         qps.log_target_q,
         true,
         false,
       );
-      let qps = self.rc_state.select_qi(
+      qps = self.rc_state.select_qi(
         self,
         cur_output_frameno,
         fti,
+        frame_data.fi.show_frame,
         self.maybe_prev_log_base_q,
         log_isqrt_mean_scale,
+        frame_data.fi.quantizer_override,
+        frame_data.fi.quantizer_offset,
+        frame_data.fi.log_bitrate_factor,
       );
       frame_data.fi.set_quantizers(&qps);
     }
 
-    let data =
+    let vbv_fs = self
+      .rc_state
+      .vbv_frame_limit(true)
+      .filter(|_| !quantizer_forced || level_constrained)
+      .map(|_| frame_data.fs.clone());
+    let mut data =
       encode_frame(&frame_data.fi, &mut frame_data.fs, &self.inter_cfg);
+    if let Some(vbv_fs) = vbv_fs {
+      // Encode the frame again at a coarser quantizer if it would underflow
+      //  the decoder buffer model.
+      let mut prev = None;
+      for _ in 0..VBV_MAX_REENCODES {
+        let bits = (data.len() * 8) as i64;
+        let Some(vbv_qps) = self.rc_state.select_vbv_qi(
+          bits,
+          &qps,
+          prev,
+          fti,
+          frame_data.fi.show_frame,
+          self.config.bit_depth,
+          self.config.chroma_sampling,
+          log_isqrt_mean_scale,
+        ) else {
+          break;
+        };
+        prev = Some((bits, qps.log_target_q));
+        qps = vbv_qps;
+        frame_data.fi.set_quantizers(&qps);
+        frame_data.fs = vbv_fs.clone();
+        data =
+          encode_frame(&frame_data.fi, &mut frame_data.fs, &self.inter_cfg);
+      }
+    }
     #[cfg(feature = "dump_lookahead_data")]
     {
       let input_frameno = frame_data.fi.input_frameno;
@@ -1403,16 +2136,28 @@ impl<T: Pixel> ContextInner<T> {
     }
     let enc_stats = frame_data.fs.enc_stats.clone();
     self.maybe_prev_log_base_q = Some(qps.log_base_q);
-    // TODO: Add support for dropping frames.
-    self.rc_state.update_state(
+    // Only shown inter frames may be dropped: the decoder keeps its
+    //  reference slots as they were, and the following frames predict from
+    //  the same references the encoder kept.
+    let droppable = self.config.drop_frames
+      && frame_data.fi.frame_type == FrameType::INTER
+      && frame_data.fi.show_frame;
+    let dropped = self.rc_state.update_state(
       (data.len() * 8) as i64,
       fti,
       frame_data.fi.show_frame,
+      frame_data.fi.duration,
       qps.log_target_q,
       false,
-      false,
-    );
-    self.packet_data.extend(data);
+      droppable,
+    ) && droppable;
+    if self.rc_state.emits_pass_data() {
+      let stats = self.analyze_frame_stats(&frame_data.fi, &frame_data.fs);
+      self.rc_state.set_frame_stats(stats);
+    }
+    if !dropped {
+      self.packet_data.extend(data);
+    }
 
     let planes =
       if frame_data.fi.sequence.chroma_sampling == Cs400 { 1 } else { 3 };
@@ -1423,13 +2168,19 @@ impl<T: Pixel> ContextInner<T> {
       planes,
     );
 
-    let (rec, source) = if frame_data.fi.show_frame {
+    let (rec, source) = if frame_data.fi.show_frame && !dropped {
       (Some(frame_data.fs.rec.clone()), Some(frame_data.fs.input.clone()))
     } else {
       (None, None)
     };
 
-    update_rec_buffer(cur_output_frameno, &mut frame_data.fi, &frame_data.fs);
+    if !dropped {
+      update_rec_buffer(
+        cur_output_frameno,
+        &mut frame_data.fi,
+        &frame_data.fs,
+      );
+    }
 
     // Copy persistent fields into subsequent FrameInvariants.
     let rec_buffer = frame_data.fi.rec_buffer.clone();
@@ -1463,15 +2214,23 @@ impl<T: Pixel> ContextInner<T> {
 
     if fi.show_frame {
       let input_frameno = fi.input_frameno;
+      let (pts, duration) = (fi.pts, fi.duration);
       let frame_type = fi.frame_type;
+      let temporal_id = fi.temporal_id;
       let qp = fi.base_q_idx;
+      let screen_content = fi.screen_content;
       self.finalize_packet(
         rec,
         source,
         input_frameno,
+        pts,
+        duration,
         frame_type,
+        temporal_id,
         qp,
         enc_stats,
+        screen_content,
+        dropped,
       )
     } else {
       Err(EncoderStatus::Encoded)
@@ -1484,6 +2243,12 @@ impl<T: Pixel> ContextInner<T> {
       return Err(EncoderStatus::LimitReached);
     }
 
+    if self.rc_state.places_keyframes() {
+      // Use the pass data read since the last frame was sent.
+      self.place_keyframes_from_pass_data();
+      self.compute_frame_invariants();
+    }
+
     if self.needs_more_fi_lookahead() {
       return Err(EncoderStatus::NeedMoreData);
     }
@@ -1522,14 +2287,23 @@ impl<T: Pixel> ContextInner<T> {
 
   fn finalize_packet(
     &mut self, rec: Option<Arc<Frame<T>>>, source: Option<Arc<Frame<T>>>,
-    input_frameno: u64, frame_type: FrameType, qp: u8,
-    enc_stats: EncoderStats,
+    input_frameno: u64, pts: u64, duration: u64, frame_type: FrameType,
+    temporal_id: u8, qp: u8, enc_stats: EncoderStats,
+    screen_content: ScreenContentStats, dropped: bool,
   ) -> Result<Packet<T>, EncoderStatus> {
-    let data = self.packet_data.clone();
-    self.packet_data.clear();
-    if write_temporal_delimiter(&mut self.packet_data).is_err() {
-      return Err(EncoderStatus::Failure);
-    }
+    // The temporal delimiter of a dropped frame opens the next packet.
+    let data = if dropped {
+      Vec::new()
+    } else {
+      let data = self.packet_data.clone();
+      self.packet_data.clear();
+      if write_temporal_delimiter(&mut self.packet_data, self.config.annex_b)
+        .is_err()
+      {
+        return Err(EncoderStatus::Failure);
+      }
+      data
+    };
 
     self.frames_processed += 1;
     Ok(Packet {
@@ -1537,9 +2311,16 @@ impl<T: Pixel> ContextInner<T> {
       rec,
       source,
       input_frameno,
+      pts,
+      duration,
       frame_type,
+      temporal_id,
+      spatial_id: 0,
       qp,
+      dropped,
       enc_stats,
+      screen_content,
+      quality_search: self.quality_search,
       opaque: None,
     })
   }
@@ -1552,7 +2333,18 @@ impl<T: Pixel> ContextInner<T> {
     let frame_q_start = self.frame_q.keys().next().cloned().unwrap_or(0);
     for i in frame_q_start..cur_input_frameno {
       self.frame_q.remove(&i);
+      self.frame_time_q.remove(&i);
     }
+    self.quantizer_q = self.quantizer_q.split_off(&cur_input_frameno);
+    self.no_reference_frames =
+      self.no_reference_frames.split_off(&cur_input_frameno);
+    self.importance_map_q =
+      self.importance_map_q.split_off(&cur_input_frameno);
+    self.screen_content_detector.discard_before(cur_input_frameno);
+    self.keyframe_detector.scores =
+      self.keyframe_detector.scores.split_off(&cur_input_frameno);
+    self.keyframe_detector.inter_costs =
+      self.keyframe_detector.inter_costs.split_off(&cur_input_frameno);
 
     if self.output_frameno < 2 {
       return;
@@ -1653,11 +2445,10 @@ impl<T: Pixel> ContextInner<T> {
       }
       let idx_in_group_output =
         self.inter_cfg.get_idx_in_group_output(output_frameno_in_gop);
-      let input_frameno = prev_keyframe_input_frameno
-        + self
-          .inter_cfg
-          .get_order_hint(output_frameno_in_gop, idx_in_group_output)
-          as u64;
+      let order_hint = self
+        .inter_cfg
+        .get_order_hint(output_frameno_in_gop, idx_in_group_output);
+      let input_frameno = prev_keyframe_input_frameno + order_hint as u64;
       // For rate control purposes, ignore any limit on frame count that has
       //  been set.
       // We pretend that we will keep encoding frames forever to prevent the
@@ -1694,7 +2485,8 @@ impl<T: Pixel> ContextInner<T> {
       } else {
         // TODO: Implement golden P-frames.
         let fti = FRAME_SUBTYPE_P
-          + (self.inter_cfg.get_level(idx_in_group_output) as usize);
+          + (self.inter_cfg.get_level(idx_in_group_output) as usize)
+          + (self.inter_cfg.get_temporal_id(order_hint) as usize);
         acc[fti] += 1;
         nframes_total += 1;
       }
@@ -7,7 +7,7 @@ use crate::encoder::{
   FrameInvariants, FrameState, Sequence, IMPORTANCE_BLOCK_SIZE,
 };
 use crate::frame::{AsRegion, PlaneOffset};
-use crate::me::{estimate_tile_motion, RefMEStats};
+use crate::me::{estimate_tile_motion, FrameMEStats, RefMEStats};
 use crate::partition::{get_intra_edges, BlockSize};
 use crate::predict::{IntraParam, PredictionMode};
 use crate::tiling::{Area, PlaneRegion, TileRect};
@@ -190,6 +190,8 @@ pub(crate) fn estimate_inter_costs<T: Pixel>(
     Arc::new(config),
     sequence,
     0,
+    0,
+    1,
     Box::new([]),
   );
   let mut fi = FrameInvariants::new_inter_frame(
@@ -199,6 +201,8 @@ pub(crate) fn estimate_inter_costs<T: Pixel>(
     1,
     2,
     false,
+    1,
+    1,
     Box::new([]),
   )
   .unwrap();
@@ -220,11 +224,26 @@ pub(crate) fn estimate_inter_costs<T: Pixel>(
   compute_motion_vectors(&mut fi, &mut fs, &inter_cfg);
 
   // Estimate inter costs
+  let stats = &fs.frame_me_stats.read().expect("poisoned lock")[0];
+  estimate_mv_inter_costs(
+    &frame,
+    &ref_frame,
+    stats,
+    bit_depth,
+    fi.cpu_feature_level,
+  )
+}
+
+/// Computes the mean SATD cost of predicting the importance blocks of `frame`
+/// from `ref_frame` with the motion vectors in `stats`, without searching them.
+pub(crate) fn estimate_mv_inter_costs<T: Pixel>(
+  frame: &Frame<T>, ref_frame: &Frame<T>, stats: &FrameMEStats,
+  bit_depth: usize, cpu_feature_level: CpuFeatureLevel,
+) -> f64 {
   let plane_org = &frame.planes[0];
   let plane_ref = &ref_frame.planes[0];
   let h_in_imp_b = plane_org.cfg.height / IMPORTANCE_BLOCK_SIZE;
   let w_in_imp_b = plane_org.cfg.width / IMPORTANCE_BLOCK_SIZE;
-  let stats = &fs.frame_me_stats.read().expect("poisoned lock")[0];
   let bsize = BlockSize::from_width_and_height(
     IMPORTANCE_BLOCK_SIZE,
     IMPORTANCE_BLOCK_SIZE,
@@ -260,13 +279,28 @@ pub(crate) fn estimate_inter_costs<T: Pixel>(
         bsize.width(),
         bsize.height(),
         bit_depth,
-        fi.cpu_feature_level,
+        cpu_feature_level,
       ) as u64;
     });
   });
   inter_costs as f64 / (w_in_imp_b * h_in_imp_b) as f64
 }
 
+/// Computes the mean magnitude of the motion vectors of the importance blocks
+/// in `me_stats`, in MV units.
+pub(crate) fn estimate_motion_magnitude(
+  me_stats: &FrameMEStats, w_in_imp_b: usize, h_in_imp_b: usize,
+) -> f64 {
+  let mut magnitudes = 0.;
+  (0..h_in_imp_b).for_each(|y| {
+    (0..w_in_imp_b).for_each(|x| {
+      let mv = me_stats[y * 2][x * 2].mv;
+      magnitudes += (mv.col as f64).hypot(mv.row as f64);
+    });
+  });
+  magnitudes / (w_in_imp_b * h_in_imp_b).max(1) as f64
+}
+
 #[profiling::function]
 pub(crate) fn compute_motion_vectors<T: Pixel>(
   fi: &mut FrameInvariants<T>, fs: &mut FrameState<T>, inter_cfg: &InterConfig,
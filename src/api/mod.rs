@@ -21,6 +21,10 @@ pub mod context;
 pub(crate) mod internal;
 /// Lookahead-specific methods
 pub(crate) mod lookahead;
+/// ISOBMFF (MP4) muxing
+pub mod mp4;
+/// AV1 elementary stream writing
+pub mod obu;
 
 mod util;
 
@@ -33,4 +37,6 @@ pub use color::*;
 pub use config::*;
 pub use context::*;
 pub(crate) use internal::*;
+pub use mp4::*;
+pub use obu::*;
 pub use util::*;
@@ -0,0 +1,651 @@
+// Copyright (c) 2018-2023, The rav1e contributors. All rights reserved
+//
+// This source code is subject to the terms of the BSD 2 Clause License and
+// the Alliance for Open Media Patent License 1.0. If the BSD 2 Clause License
+// was not distributed with this source code in the LICENSE file, you can
+// obtain it at www.aomedia.org/license/software. If the Alliance for Open
+// Media Patent License 1.0 was not distributed with this source code in the
+// PATENTS file, you can obtain it at www.aomedia.org/license/patent.
+#![deny(missing_docs)]
+
+use std::io;
+use std::io::{Seek, SeekFrom, Write};
+
+use crate::api::color::*;
+use crate::api::config::EncoderConfig;
+use crate::api::obu::strip_temporal_delimiter;
+use crate::api::util::*;
+use crate::util::Pixel;
+
+// Sample flags of the movie fragments, as in ISO/IEC 14496-12 8.8.3.1:
+// sample_depends_on 2 for sync samples, and 1 with sample_is_non_sync_sample
+// for the others.
+const SYNC_SAMPLE_FLAGS: u32 = 0x0200_0000;
+const NON_SYNC_SAMPLE_FLAGS: u32 = 0x0101_0000;
+
+const TRACK_ID: u32 = 1;
+
+fn write_box(buf: &mut Vec<u8>, kind: &[u8; 4], content: &[u8]) {
+  buf.extend_from_slice(&(8 + content.len() as u32).to_be_bytes());
+  buf.extend_from_slice(kind);
+  buf.extend_from_slice(content);
+}
+
+fn write_full_box(
+  buf: &mut Vec<u8>, kind: &[u8; 4], version: u8, flags: u32, content: &[u8],
+) {
+  let mut full = Vec::with_capacity(4 + content.len());
+  full.extend_from_slice(&(((version as u32) << 24) | flags).to_be_bytes());
+  full.extend_from_slice(content);
+  write_box(buf, kind, &full);
+}
+
+fn write_ftyp(buf: &mut Vec<u8>, major: &[u8; 4], compatible: &[&[u8; 4]]) {
+  let mut ftyp = Vec::new();
+  ftyp.extend_from_slice(major);
+  ftyp.extend_from_slice(&0u32.to_be_bytes()); // minor_version
+  for brand in compatible {
+    ftyp.extend_from_slice(*brand);
+  }
+  write_box(buf, b"ftyp", &ftyp);
+}
+
+// The unity matrix of the movie and track headers.
+fn write_matrix(buf: &mut Vec<u8>) {
+  for v in [0x0001_0000u32, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000] {
+    buf.extend_from_slice(&v.to_be_bytes());
+  }
+}
+
+fn write_mvhd(buf: &mut Vec<u8>, timescale: u32, duration: u64) {
+  let mut mvhd = Vec::new();
+  mvhd.extend_from_slice(&0u64.to_be_bytes()); // creation_time
+  mvhd.extend_from_slice(&0u64.to_be_bytes()); // modification_time
+  mvhd.extend_from_slice(&timescale.to_be_bytes());
+  mvhd.extend_from_slice(&duration.to_be_bytes());
+  mvhd.extend_from_slice(&0x0001_0000u32.to_be_bytes()); // rate
+  mvhd.extend_from_slice(&0x0100u16.to_be_bytes()); // volume
+  mvhd.extend_from_slice(&[0; 10]); // reserved
+  write_matrix(&mut mvhd);
+  mvhd.extend_from_slice(&[0; 24]); // pre_defined
+  mvhd.extend_from_slice(&(TRACK_ID + 1).to_be_bytes()); // next_track_ID
+  write_full_box(buf, b"mvhd", 1, 0, &mvhd);
+}
+
+// Writes the visual sample entry of the track, with the AV1 configuration and
+// the color boxes of the AV1 ISOBMFF binding.
+fn write_av01(buf: &mut Vec<u8>, config: &EncoderConfig, av1c: &[u8]) {
+  let mut av01 = Vec::new();
+  av01.extend_from_slice(&[0; 6]); // reserved
+  av01.extend_from_slice(&1u16.to_be_bytes()); // data_reference_index
+  av01.extend_from_slice(&[0; 16]); // pre_defined and reserved
+  av01.extend_from_slice(&(config.width as u16).to_be_bytes());
+  av01.extend_from_slice(&(config.height as u16).to_be_bytes());
+  av01.extend_from_slice(&0x0048_0000u32.to_be_bytes()); // horizresolution
+  av01.extend_from_slice(&0x0048_0000u32.to_be_bytes()); // vertresolution
+  av01.extend_from_slice(&[0; 4]); // reserved
+  av01.extend_from_slice(&1u16.to_be_bytes()); // frame_count
+  let mut compressor_name = [0; 32];
+  let name = b"AOM Coding";
+  compressor_name[0] = name.len() as u8;
+  compressor_name[1..=name.len()].copy_from_slice(name);
+  av01.extend_from_slice(&compressor_name);
+  av01.extend_from_slice(&0x0018u16.to_be_bytes()); // depth
+  av01.extend_from_slice(&(-1i16).to_be_bytes()); // pre_defined
+
+  write_box(&mut av01, b"av1C", av1c);
+
+  if config.color_description.is_some()
+    || config.pixel_range == PixelRange::Full
+  {
+    let desc = config.color_description.unwrap_or(ColorDescription {
+      color_primaries: ColorPrimaries::Unspecified,
+      transfer_characteristics: TransferCharacteristics::Unspecified,
+      matrix_coefficients: MatrixCoefficients::Unspecified,
+    });
+    let mut colr = Vec::new();
+    colr.extend_from_slice(b"nclx");
+    colr.extend_from_slice(&(desc.color_primaries as u16).to_be_bytes());
+    colr.extend_from_slice(
+      &(desc.transfer_characteristics as u16).to_be_bytes(),
+    );
+    colr.extend_from_slice(&(desc.matrix_coefficients as u16).to_be_bytes());
+    colr.push(((config.pixel_range == PixelRange::Full) as u8) << 7);
+    write_box(&mut av01, b"colr", &colr);
+  }
+
+  let sar = config.sample_aspect_ratio;
+  if sar.num != sar.den {
+    let mut pasp = Vec::new();
+    pasp.extend_from_slice(&(sar.num as u32).to_be_bytes());
+    pasp.extend_from_slice(&(sar.den as u32).to_be_bytes());
+    write_box(&mut av01, b"pasp", &pasp);
+  }
+
+  if let Some(mastering) = config.mastering_display {
+    // The chromaticities are in increments of 0.00002 and in green, blue,
+    // red order, the luminances in increments of 0.0001 cd/m^2.
+    let to_mdcv = |v: u16| ((v as u32 * 50_000 + (1 << 15)) >> 16) as u16;
+    let mut mdcv = Vec::new();
+    for i in [1, 2, 0] {
+      let point = mastering.primaries[i];
+      mdcv.extend_from_slice(&to_mdcv(point.x).to_be_bytes());
+      mdcv.extend_from_slice(&to_mdcv(point.y).to_be_bytes());
+    }
+    mdcv.extend_from_slice(&to_mdcv(mastering.white_point.x).to_be_bytes());
+    mdcv.extend_from_slice(&to_mdcv(mastering.white_point.y).to_be_bytes());
+    let max_luminance = (mastering.max_luminance as u64 * 10_000) >> 8;
+    let min_luminance = (mastering.min_luminance as u64 * 10_000) >> 14;
+    mdcv.extend_from_slice(&(max_luminance as u32).to_be_bytes());
+    mdcv.extend_from_slice(&(min_luminance as u32).to_be_bytes());
+    write_box(&mut av01, b"mdcv", &mdcv);
+  }
+
+  if let Some(light) = config.content_light {
+    let mut clli = Vec::new();
+    clli.extend_from_slice(&light.max_content_light_level.to_be_bytes());
+    clli.extend_from_slice(&light.max_frame_average_light_level.to_be_bytes());
+    write_box(&mut av01, b"clli", &clli);
+  }
+
+  write_box(buf, b"av01", &av01);
+}
+
+// Writes the track of the movie box, with `tables` following the sample
+// description in the sample table.
+fn write_trak(
+  buf: &mut Vec<u8>, config: &EncoderConfig, av1c: &[u8], timescale: u32,
+  duration: u64, tables: &[u8],
+) {
+  let (render_width, render_height) = config.render_size();
+  let mut tkhd = Vec::new();
+  tkhd.extend_from_slice(&0u64.to_be_bytes()); // creation_time
+  tkhd.extend_from_slice(&0u64.to_be_bytes()); // modification_time
+  tkhd.extend_from_slice(&TRACK_ID.to_be_bytes());
+  tkhd.extend_from_slice(&[0; 4]); // reserved
+  tkhd.extend_from_slice(&duration.to_be_bytes());
+  tkhd.extend_from_slice(&[0; 8]); // reserved
+  tkhd.extend_from_slice(&[0; 8]); // layer, alternate_group and volume
+  write_matrix(&mut tkhd);
+  tkhd.extend_from_slice(&((render_width as u32) << 16).to_be_bytes());
+  tkhd.extend_from_slice(&((render_height as u32) << 16).to_be_bytes());
+
+  let mut mdhd = Vec::new();
+  mdhd.extend_from_slice(&0u64.to_be_bytes()); // creation_time
+  mdhd.extend_from_slice(&0u64.to_be_bytes()); // modification_time
+  mdhd.extend_from_slice(&timescale.to_be_bytes());
+  mdhd.extend_from_slice(&duration.to_be_bytes());
+  mdhd.extend_from_slice(&0x55C4u16.to_be_bytes()); // language "und"
+  mdhd.extend_from_slice(&[0; 2]); // pre_defined
+
+  let mut hdlr = Vec::new();
+  hdlr.extend_from_slice(&[0; 4]); // pre_defined
+  hdlr.extend_from_slice(b"vide");
+  hdlr.extend_from_slice(&[0; 12]); // reserved
+  hdlr.extend_from_slice(b"VideoHandler\0");
+
+  let mut dref = Vec::new();
+  dref.extend_from_slice(&1u32.to_be_bytes()); // entry_count
+  write_full_box(&mut dref, b"url ", 0, 1, &[]);
+  let mut dinf = Vec::new();
+  write_full_box(&mut dinf, b"dref", 0, 0, &dref);
+
+  let mut stsd = Vec::new();
+  stsd.extend_from_slice(&1u32.to_be_bytes()); // entry_count
+  write_av01(&mut stsd, config, av1c);
+  let mut stbl = Vec::new();
+  write_full_box(&mut stbl, b"stsd", 0, 0, &stsd);
+  stbl.extend_from_slice(tables);
+
+  let mut minf = Vec::new();
+  write_full_box(&mut minf, b"vmhd", 0, 1, &[0; 8]);
+  write_box(&mut minf, b"dinf", &dinf);
+  write_box(&mut minf, b"stbl", &stbl);
+
+  let mut mdia = Vec::new();
+  write_full_box(&mut mdia, b"mdhd", 1, 0, &mdhd);
+  write_full_box(&mut mdia, b"hdlr", 0, 0, &hdlr);
+  write_box(&mut mdia, b"minf", &minf);
+
+  let mut trak = Vec::new();
+  // track_enabled and track_in_movie
+  write_full_box(&mut trak, b"tkhd", 1, 3, &tkhd);
+  write_box(&mut trak, b"mdia", &mdia);
+  write_box(buf, b"trak", &trak);
+}
+
+// The sample durations in the time base of the config: up to the next
+// sample, so that the gaps of dropped frames are covered, and the duration
+// of the last sample otherwise.
+fn sample_durations(pts: &[u64], last_duration: u64) -> Vec<u32> {
+  pts
+    .windows(2)
+    .map(|w| (w[1] - w[0]) as u32)
+    .chain(pts.last().map(|_| last_duration as u32))
+    .collect()
+}
+
+/// A sample waiting to be written.
+#[derive(Clone, Copy, Debug)]
+struct Sample {
+  pts: u64,
+  duration: u64,
+  size: u32,
+  keyframe: bool,
+}
+
+/// Writes the encoded packets as the AV1 track of an ISOBMFF (MP4) file.
+///
+/// The frames are written to an `mdat` box as they come, and the tables
+/// indexing them to the `moov` box following it by [`finish`]. The output
+/// needs to be seekable to complete the size of the `mdat` box.
+///
+/// # Examples
+///
+/// ```
+/// use rav1e::prelude::*;
+/// use std::io::Cursor;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let enc = EncoderConfig { width: 64, height: 64, ..Default::default() };
+/// let cfg = Config::new().with_encoder_config(enc.clone());
+/// let mut ctx: Context<u8> = cfg.new_context()?;
+/// let mut mp4 = Mp4Muxer::new(
+///   Cursor::new(Vec::new()),
+///   &enc,
+///   &ctx.container_sequence_header(),
+/// )?;
+///
+/// ctx.send_frame(ctx.new_frame())?;
+/// ctx.flush();
+/// loop {
+///   match ctx.receive_packet() {
+///     Ok(packet) => mp4.write_packet(&packet)?,
+///     Err(EncoderStatus::Encoded) => {}
+///     Err(EncoderStatus::LimitReached) => break,
+///     Err(err) => return Err(err.into()),
+///   }
+/// }
+/// let file = mp4.finish()?.into_inner();
+/// assert_eq!(&file[4..8], b"ftyp");
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`finish`]: #method.finish
+pub struct Mp4Muxer<W: Write + Seek> {
+  output: W,
+  config: EncoderConfig,
+  av1c: Vec<u8>,
+  // File offsets of the mdat box and of its data.
+  mdat_pos: u64,
+  mdat_data_pos: u64,
+  samples: Vec<Sample>,
+}
+
+impl<W: Write + Seek> Mp4Muxer<W> {
+  /// Creates a muxer for a stream encoded with `config`, and writes the start
+  /// of the file.
+  ///
+  /// `sequence_header` is the AV1 configuration record returned by
+  /// [`Context::container_sequence_header`].
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if the stream is in the format of Annex B, as samples
+  /// hold OBUs with their sizes, or the error of the output.
+  ///
+  /// [`Context::container_sequence_header`]:
+  /// struct.Context.html#method.container_sequence_header
+  pub fn new(
+    mut output: W, config: &EncoderConfig, sequence_header: &[u8],
+  ) -> io::Result<Self> {
+    check_obu_format(config)?;
+    let mut buf = Vec::new();
+    write_ftyp(&mut buf, b"isom", &[b"isom", b"iso6", b"av01", b"mp41"]);
+    output.write_all(&buf)?;
+
+    // A 64-bit size, completed by finish().
+    let mdat_pos = output.stream_position()?;
+    output.write_all(&1u32.to_be_bytes())?;
+    output.write_all(b"mdat")?;
+    output.write_all(&0u64.to_be_bytes())?;
+    let mdat_data_pos = output.stream_position()?;
+
+    Ok(Mp4Muxer {
+      output,
+      config: config.clone(),
+      av1c: sequence_header.to_vec(),
+      mdat_pos,
+      mdat_data_pos,
+      samples: Vec::new(),
+    })
+  }
+
+  /// Writes the temporal unit of a frame shown at `pts` for `duration`, both
+  /// in units of the time base of the encoder config.
+  ///
+  /// # Errors
+  ///
+  /// Returns the error of the output, if any.
+  pub fn write_frame(
+    &mut self, pts: u64, duration: u64, data: &[u8], keyframe: bool,
+  ) -> io::Result<()> {
+    let data = strip_temporal_delimiter(data);
+    self.output.write_all(data)?;
+    self.samples.push(Sample {
+      pts,
+      duration,
+      size: data.len() as u32,
+      keyframe,
+    });
+    Ok(())
+  }
+
+  /// Writes the data of a packet. Dropped frames are skipped, the previous
+  /// frame being shown in their place.
+  ///
+  /// # Errors
+  ///
+  /// Returns the error of the output, if any.
+  pub fn write_packet<T: Pixel>(
+    &mut self, packet: &Packet<T>,
+  ) -> io::Result<()> {
+    if packet.dropped {
+      return Ok(());
+    }
+    self.write_frame(
+      packet.pts,
+      packet.duration,
+      &packet.data,
+      packet.frame_type == FrameType::KEY,
+    )
+  }
+
+  /// Completes the file with the `moov` box and returns the output.
+  ///
+  /// # Errors
+  ///
+  /// Returns the error of the output, if any.
+  pub fn finish(mut self) -> io::Result<W> {
+    let end = self.output.stream_position()?;
+    self.output.seek(SeekFrom::Start(self.mdat_pos + 8))?;
+    self.output.write_all(&(end - self.mdat_pos).to_be_bytes())?;
+    self.output.seek(SeekFrom::Start(end))?;
+
+    let time_base = self.config.time_base;
+    let timescale = time_base.den as u32;
+    let unit = time_base.num as u32;
+    let pts: Vec<u64> = self.samples.iter().map(|s| s.pts).collect();
+    let last_duration = self.samples.last().map_or(0, |s| s.duration);
+    let durations = sample_durations(&pts, last_duration);
+    let duration =
+      durations.iter().map(|&d| d as u64).sum::<u64>() * unit as u64;
+
+    let mut stts = Vec::new();
+    let mut runs: Vec<(u32, u32)> = Vec::new();
+    for &d in &durations {
+      match runs.last_mut() {
+        Some((count, delta)) if *delta == d * unit => *count += 1,
+        _ => runs.push((1, d * unit)),
+      }
+    }
+    stts.extend_from_slice(&(runs.len() as u32).to_be_bytes());
+    for (count, delta) in runs {
+      stts.extend_from_slice(&count.to_be_bytes());
+      stts.extend_from_slice(&delta.to_be_bytes());
+    }
+
+    let mut stss = Vec::new();
+    let keyframes: Vec<u32> = (1..)
+      .zip(&self.samples)
+      .filter(|(_, s)| s.keyframe)
+      .map(|(i, _)| i)
+      .collect();
+    stss.extend_from_slice(&(keyframes.len() as u32).to_be_bytes());
+    for i in keyframes {
+      stss.extend_from_slice(&i.to_be_bytes());
+    }
+
+    // All the samples are in a single chunk, at the start of the mdat data.
+    let nsamples = self.samples.len() as u32;
+    let mut stsc = Vec::new();
+    if nsamples > 0 {
+      stsc.extend_from_slice(&1u32.to_be_bytes()); // entry_count
+      stsc.extend_from_slice(&1u32.to_be_bytes()); // first_chunk
+      stsc.extend_from_slice(&nsamples.to_be_bytes());
+      stsc.extend_from_slice(&1u32.to_be_bytes()); // sample_description_index
+    } else {
+      stsc.extend_from_slice(&0u32.to_be_bytes());
+    }
+
+    let mut stsz = Vec::new();
+    stsz.extend_from_slice(&0u32.to_be_bytes()); // sample_size
+    stsz.extend_from_slice(&nsamples.to_be_bytes());
+    for sample in &self.samples {
+      stsz.extend_from_slice(&sample.size.to_be_bytes());
+    }
+
+    let mut stco = Vec::new();
+    stco.extend_from_slice(&((nsamples > 0) as u32).to_be_bytes());
+    if nsamples > 0 {
+      stco.extend_from_slice(&(self.mdat_data_pos as u32).to_be_bytes());
+    }
+
+    let mut tables = Vec::new();
+    write_full_box(&mut tables, b"stts", 0, 0, &stts);
+    write_full_box(&mut tables, b"stss", 0, 0, &stss);
+    write_full_box(&mut tables, b"stsc", 0, 0, &stsc);
+    write_full_box(&mut tables, b"stsz", 0, 0, &stsz);
+    write_full_box(&mut tables, b"stco", 0, 0, &stco);
+
+    let mut moov = Vec::new();
+    write_mvhd(&mut moov, timescale, duration);
+    write_trak(
+      &mut moov,
+      &self.config,
+      &self.av1c,
+      timescale,
+      duration,
+      &tables,
+    );
+    let mut buf = Vec::new();
+    write_box(&mut buf, b"moov", &moov);
+    self.output.write_all(&buf)?;
+    self.output.flush()?;
+    Ok(self.output)
+  }
+}
+
+/// Writes the encoded packets as a fragmented ISOBMFF (MP4) file, suitable
+/// for CMAF packaging in DASH or HLS.
+///
+/// The initialization segment is written first, then one movie fragment for
+/// each group of pictures, as soon as its next key frame or [`finish`] comes.
+/// The output does not need to be seekable.
+///
+/// [`finish`]: #method.finish
+pub struct FragmentedMp4Muxer<W: Write> {
+  output: W,
+  time_base: Rational,
+  sequence_number: u32,
+  samples: Vec<Sample>,
+  data: Vec<u8>,
+}
+
+impl<W: Write> FragmentedMp4Muxer<W> {
+  /// Creates a muxer for a stream encoded with `config`, and writes the
+  /// initialization segment.
+  ///
+  /// `sequence_header` is the AV1 configuration record returned by
+  /// [`Context::container_sequence_header`].
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if the stream is in the format of Annex B, as samples
+  /// hold OBUs with their sizes, or the error of the output.
+  ///
+  /// [`Context::container_sequence_header`]:
+  /// struct.Context.html#method.container_sequence_header
+  pub fn new(
+    mut output: W, config: &EncoderConfig, sequence_header: &[u8],
+  ) -> io::Result<Self> {
+    check_obu_format(config)?;
+    let mut buf = Vec::new();
+    write_ftyp(&mut buf, b"cmfc", &[b"cmfc", b"iso6", b"av01", b"dash"]);
+
+    let mut tables = Vec::new();
+    for kind in [b"stts", b"stsc", b"stco"] {
+      write_full_box(&mut tables, kind, 0, 0, &0u32.to_be_bytes());
+    }
+    write_full_box(&mut tables, b"stsz", 0, 0, &0u64.to_be_bytes());
+
+    let timescale = config.time_base.den as u32;
+    let mut moov = Vec::new();
+    write_mvhd(&mut moov, timescale, 0);
+    write_trak(&mut moov, config, sequence_header, timescale, 0, &tables);
+    let mut trex = Vec::new();
+    trex.extend_from_slice(&TRACK_ID.to_be_bytes());
+    trex.extend_from_slice(&1u32.to_be_bytes()); // sample_description_index
+    trex.extend_from_slice(&[0; 12]); // duration, size and flags
+    let mut mvex = Vec::new();
+    write_full_box(&mut mvex, b"trex", 0, 0, &trex);
+    write_box(&mut moov, b"mvex", &mvex);
+    write_box(&mut buf, b"moov", &moov);
+    output.write_all(&buf)?;
+
+    Ok(FragmentedMp4Muxer {
+      output,
+      time_base: config.time_base,
+      sequence_number: 0,
+      samples: Vec::new(),
+      data: Vec::new(),
+    })
+  }
+
+  // Writes the samples of the group of pictures as a movie fragment, the
+  // last one lasting until `next_pts` when it is known.
+  fn write_fragment(&mut self, next_pts: Option<u64>) -> io::Result<()> {
+    let Some(first) = self.samples.first() else {
+      return Ok(());
+    };
+    self.sequence_number += 1;
+    let unit = self.time_base.num as u32;
+    let mut pts: Vec<u64> = self.samples.iter().map(|s| s.pts).collect();
+    let last_duration = self.samples.last().unwrap().duration;
+    pts.extend(next_pts);
+    let mut durations = sample_durations(&pts, last_duration);
+    durations.truncate(self.samples.len());
+
+    let mut mfhd = Vec::new();
+    mfhd.extend_from_slice(&self.sequence_number.to_be_bytes());
+
+    // default-base-is-moof
+    let mut tfhd = Vec::new();
+    tfhd.extend_from_slice(&TRACK_ID.to_be_bytes());
+
+    let mut tfdt = Vec::new();
+    tfdt.extend_from_slice(&(first.pts * unit as u64).to_be_bytes());
+
+    // data_offset, then the duration, size and flags of each sample
+    const TRUN_FLAGS: u32 = 0x00_0701;
+    let trun_sz = 12 + 4 + 4 + 12 * self.samples.len();
+    let mut trun = Vec::new();
+    trun.extend_from_slice(&(self.samples.len() as u32).to_be_bytes());
+    // The sizes of the boxes of the moof box, up to the data of the mdat.
+    let moof_sz = 8 + (8 + 8) + 8 + (12 + 4) + (12 + 8) + trun_sz;
+    trun.extend_from_slice(&(moof_sz as u32 + 8).to_be_bytes());
+    for (sample, &d) in self.samples.iter().zip(&durations) {
+      trun.extend_from_slice(&(d * unit).to_be_bytes());
+      trun.extend_from_slice(&sample.size.to_be_bytes());
+      let flags = if sample.keyframe {
+        SYNC_SAMPLE_FLAGS
+      } else {
+        NON_SYNC_SAMPLE_FLAGS
+      };
+      trun.extend_from_slice(&flags.to_be_bytes());
+    }
+
+    let mut traf = Vec::new();
+    write_full_box(&mut traf, b"tfhd", 0, 0x02_0000, &tfhd);
+    write_full_box(&mut traf, b"tfdt", 1, 0, &tfdt);
+    write_full_box(&mut traf, b"trun", 0, TRUN_FLAGS, &trun);
+    let mut moof = Vec::new();
+    write_full_box(&mut moof, b"mfhd", 0, 0, &mfhd);
+    write_box(&mut moof, b"traf", &traf);
+    let mut buf = Vec::new();
+    write_box(&mut buf, b"moof", &moof);
+    debug_assert_eq!(buf.len(), moof_sz);
+    write_box(&mut buf, b"mdat", &self.data);
+    self.output.write_all(&buf)?;
+
+    self.samples.clear();
+    self.data.clear();
+    Ok(())
+  }
+
+  /// Adds the temporal unit of a frame shown at `pts` for `duration`, both
+  /// in units of the time base of the encoder config. A key frame writes the
+  /// fragment of the previous group of pictures.
+  ///
+  /// # Errors
+  ///
+  /// Returns the error of the output, if any.
+  pub fn write_frame(
+    &mut self, pts: u64, duration: u64, data: &[u8], keyframe: bool,
+  ) -> io::Result<()> {
+    if keyframe {
+      self.write_fragment(Some(pts))?;
+    }
+    let data = strip_temporal_delimiter(data);
+    self.data.extend_from_slice(data);
+    self.samples.push(Sample {
+      pts,
+      duration,
+      size: data.len() as u32,
+      keyframe,
+    });
+    Ok(())
+  }
+
+  /// Adds the data of a packet. Dropped frames are skipped, the previous
+  /// frame being shown in their place.
+  ///
+  /// # Errors
+  ///
+  /// Returns the error of the output, if any.
+  pub fn write_packet<T: Pixel>(
+    &mut self, packet: &Packet<T>,
+  ) -> io::Result<()> {
+    if packet.dropped {
+      return Ok(());
+    }
+    self.write_frame(
+      packet.pts,
+      packet.duration,
+      &packet.data,
+      packet.frame_type == FrameType::KEY,
+    )
+  }
+
+  /// Writes the last fragment and returns the output.
+  ///
+  /// # Errors
+  ///
+  /// Returns the error of the output, if any.
+  pub fn finish(mut self) -> io::Result<W> {
+    self.write_fragment(None)?;
+    self.output.flush()?;
+    Ok(self.output)
+  }
+}
+
+fn check_obu_format(config: &EncoderConfig) -> io::Result<()> {
+  if config.annex_b {
+    return Err(io::Error::new(
+      io::ErrorKind::InvalidInput,
+      "ISOBMFF does not support the Annex B bitstream format",
+    ));
+  }
+  Ok(())
+}
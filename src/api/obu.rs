@@ -0,0 +1,203 @@
+// Copyright (c) 2018-2023, The rav1e contributors. All rights reserved
+//
+// This source code is subject to the terms of the BSD 2 Clause License and
+// the Alliance for Open Media Patent License 1.0. If the BSD 2 Clause License
+// was not distributed with this source code in the LICENSE file, you can
+// obtain it at www.aomedia.org/license/software. If the Alliance for Open
+// Media Patent License 1.0 was not distributed with this source code in the
+// PATENTS file, you can obtain it at www.aomedia.org/license/patent.
+#![deny(missing_docs)]
+
+use std::io;
+use std::io::Write;
+
+use crate::api::config::EncoderConfig;
+use crate::api::util::Packet;
+use crate::header::ObuType;
+use crate::util::Pixel;
+
+fn leb128(mut value: u64) -> Vec<u8> {
+  let mut buf = Vec::new();
+  loop {
+    let byte = (value & 0x7f) as u8;
+    value >>= 7;
+    if value == 0 {
+      buf.push(byte);
+      return buf;
+    }
+    buf.push(byte | 0x80);
+  }
+}
+
+fn read_leb128(data: &mut &[u8]) -> io::Result<u64> {
+  let mut value = 0;
+  for i in 0..8 {
+    let (&byte, rest) =
+      data.split_first().ok_or(io::ErrorKind::InvalidData)?;
+    *data = rest;
+    value |= u64::from(byte & 0x7f) << (7 * i);
+    if byte & 0x80 == 0 {
+      return Ok(value);
+    }
+  }
+  Err(io::ErrorKind::InvalidData.into())
+}
+
+/// Splits the length-delimited OBUs of a temporal unit into frame units, each
+/// holding a frame header or frame OBU and its tile groups, along with the
+/// OBUs preceding it.
+fn frame_units(data: &[u8]) -> io::Result<Vec<&[u8]>> {
+  let mut units = Vec::new();
+  let mut start = 0;
+  let mut has_frame = false;
+  let mut rest = data;
+  while !rest.is_empty() {
+    let offset = data.len() - rest.len();
+    let obu_length = read_leb128(&mut rest)? as usize;
+    if obu_length == 0 || obu_length > rest.len() {
+      return Err(io::ErrorKind::InvalidData.into());
+    }
+    let obu_type = (rest[0] >> 3) & 0xf;
+    rest = &rest[obu_length..];
+
+    let is_frame = obu_type == ObuType::OBU_FRAME_HEADER as u8
+      || obu_type == ObuType::OBU_FRAME as u8;
+    let in_frame = obu_type == ObuType::OBU_TILE_GROUP as u8
+      || obu_type == ObuType::OBU_REDUNDANT_FRAME_HEADER as u8
+      || obu_type == ObuType::OBU_PADDING as u8;
+    if has_frame && !in_frame {
+      units.push(&data[start..offset]);
+      start = offset;
+      has_frame = false;
+    }
+    has_frame |= is_frame;
+  }
+  units.push(&data[start..]);
+  Ok(units)
+}
+
+/// Skips the temporal delimiter OBU which starts each packet, as the
+/// containers other than IVF and the Low Overhead Bitstream Format mark the
+/// temporal units themselves.
+///
+/// The packets of rav1e start with a temporal delimiter OBU carrying its size
+/// field, any other data being returned as is.
+pub fn strip_temporal_delimiter(data: &[u8]) -> &[u8] {
+  // obu_type 2 with obu_has_size_field set, followed by a zero size.
+  match data {
+    [0x12, 0x00, rest @ ..] => rest,
+    _ => data,
+  }
+}
+
+/// Writes the encoded packets as an AV1 elementary stream.
+///
+/// The stream is in the low overhead bitstream format of Section 5 of the
+/// specification, with the OBUs carrying their sizes, or in the
+/// length-delimited format of Annex B when [`annex_b`] is set in the encoder
+/// config. In the latter, each packet is written as a temporal unit holding a
+/// frame unit per frame, hidden frames included, each one after its
+/// `frame_unit_size`.
+///
+/// # Examples
+///
+/// ```
+/// use rav1e::prelude::*;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let enc = EncoderConfig {
+///   width: 64,
+///   height: 64,
+///   annex_b: true,
+///   ..Default::default()
+/// };
+/// let cfg = Config::new().with_encoder_config(enc.clone());
+/// let mut ctx: Context<u8> = cfg.new_context()?;
+/// let mut obu = ObuWriter::new(Vec::new(), &enc);
+///
+/// ctx.send_frame(ctx.new_frame())?;
+/// ctx.flush();
+/// loop {
+///   match ctx.receive_packet() {
+///     Ok(packet) => obu.write_packet(&packet)?,
+///     Err(EncoderStatus::Encoded) => {}
+///     Err(EncoderStatus::LimitReached) => break,
+///     Err(err) => return Err(err.into()),
+///   }
+/// }
+/// let stream = obu.into_inner();
+/// // temporal_unit_size, frame_unit_size, then the temporal delimiter.
+/// assert_eq!(stream[1] as usize, stream.len() - 2);
+/// assert_eq!(stream[2..4], [1, 0x10]);
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`annex_b`]: struct.EncoderConfig.html#structfield.annex_b
+pub struct ObuWriter<W: Write> {
+  output: W,
+  annex_b: bool,
+}
+
+impl<W: Write> ObuWriter<W> {
+  /// Creates a writer for a stream encoded with `config`.
+  pub fn new(output: W, config: &EncoderConfig) -> Self {
+    ObuWriter { output, annex_b: config.annex_b }
+  }
+
+  /// Writes the data of a temporal unit.
+  ///
+  /// # Errors
+  ///
+  /// Returns the error of the output, if any, or `InvalidData` if the OBUs of
+  /// an Annex B temporal unit cannot be delimited.
+  pub fn write_frame(&mut self, data: &[u8]) -> io::Result<()> {
+    if !self.annex_b {
+      return self.output.write_all(data);
+    }
+    let units = frame_units(data)?;
+    let sizes: Vec<_> =
+      units.iter().map(|unit| leb128(unit.len() as u64)).collect();
+    let temporal_unit_size = leb128(
+      units
+        .iter()
+        .zip(&sizes)
+        .map(|(unit, size)| unit.len() + size.len())
+        .sum::<usize>() as u64,
+    );
+    self.output.write_all(&temporal_unit_size)?;
+    for (unit, size) in units.iter().zip(&sizes) {
+      self.output.write_all(size)?;
+      self.output.write_all(unit)?;
+    }
+    Ok(())
+  }
+
+  /// Writes the data of a packet. Dropped frames are skipped.
+  ///
+  /// # Errors
+  ///
+  /// Returns the error of the output, if any.
+  pub fn write_packet<T: Pixel>(
+    &mut self, packet: &Packet<T>,
+  ) -> io::Result<()> {
+    if packet.dropped {
+      return Ok(());
+    }
+    self.write_frame(&packet.data)
+  }
+
+  /// Flushes the output.
+  ///
+  /// # Errors
+  ///
+  /// Returns the error of the output, if any.
+  pub fn flush(&mut self) -> io::Result<()> {
+    self.output.flush()
+  }
+
+  /// Returns the output.
+  pub fn into_inner(self) -> W {
+    self.output
+  }
+}
@@ -7,8 +7,12 @@
 // Media Patent License 1.0 was not distributed with this source code in the
 // PATENTS file, you can obtain it at www.aomedia.org/license/patent.
 
-use crate::encoder::FrameInvariants;
+use crate::encoder::{FrameInvariants, Sequence};
+use crate::header::ALL_REF_FRAMES_MASK;
+use crate::levels::level_constraints_met;
+use crate::partition::REF_FRAMES;
 use crate::prelude::*;
+use crate::tiling::TilingInfo;
 
 use std::sync::Arc;
 
@@ -94,6 +98,24 @@ fn fill_frame_const<T: Pixel>(frame: &mut Frame<T>, value: T) {
   }
 }
 
+/// Advances the pseudo-random sequence of `seed` and returns its next value.
+fn next_noise(seed: &mut u32) -> u32 {
+  *seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+  *seed
+}
+
+/// Fills the planes of `frame` with `base` plus pseudo-random noise of
+/// `amplitude_bits` bits.
+fn fill_noise(
+  frame: &mut Frame<u8>, seed: &mut u32, base: u8, amplitude_bits: u32,
+) {
+  for plane in frame.planes.iter_mut() {
+    for pixel in plane.data.iter_mut() {
+      *pixel = base + (next_noise(seed) >> (32 - amplitude_bits)) as u8;
+    }
+  }
+}
+
 #[cfg(feature = "channel-api")]
 mod channel {
   use super::*;
@@ -1316,11 +1338,234 @@ fn send_frame_kf<T: Pixel>(ctx: &mut Context<T>, keyframe: bool) {
     frame_type_override,
     opaque,
     t35_metadata: Box::new([]),
+    ..Default::default()
   };
 
   let _ = ctx.send_frame((input, fp));
 }
 
+fn frame_override_qps(quantizer: usize, fps: Vec<FrameParameters>) -> Vec<u8> {
+  let mut ctx = setup_encoder::<u8>(
+    64,
+    80,
+    10,
+    quantizer,
+    8,
+    ChromaSampling::Cs420,
+    0,
+    30,
+    0,
+    true,
+    0,
+    true,
+    10,
+    None,
+  );
+  let limit = fps.len();
+  for fp in fps {
+    let mut input = ctx.new_frame();
+    fill_frame_const(&mut input, 128);
+    ctx.send_frame((input, fp)).unwrap();
+  }
+  ctx.flush();
+
+  let mut qps = vec![0; limit];
+  loop {
+    match ctx.receive_packet() {
+      Ok(pkt) => qps[pkt.input_frameno as usize] = pkt.qp,
+      Err(EncoderStatus::Encoded) => {}
+      Err(EncoderStatus::LimitReached) => break,
+      Err(e) => panic!("Unexpected status {:?}", e),
+    }
+  }
+  qps
+}
+
+#[test]
+fn frame_quantizer_override() {
+  let limit = 6;
+  let default_fps = || (0..limit).map(|_| FrameParameters::default());
+  let qps_50 = frame_override_qps(50, default_fps().collect());
+  let qps_100 = frame_override_qps(100, default_fps().collect());
+
+  let fps = default_fps()
+    .enumerate()
+    .map(|(i, fp)| match i {
+      2 => FrameParameters { quantizer: Some(50), ..fp },
+      4 => FrameParameters { quantizer_offset: 30, ..fp },
+      _ => fp,
+    })
+    .collect();
+  let qps = frame_override_qps(100, fps);
+  assert_eq!(qps[1], qps_100[1]);
+  // The frame header carries the requested base_q_idx, with no adjustment
+  //  for the type of the frame unlike the quantizer of the config.
+  assert_eq!(qps[2], 50);
+  assert_ne!(qps_50[2], 50);
+  assert_eq!(qps[3], qps_100[3]);
+  assert_eq!(qps[4], qps_100[4] + 30);
+
+  let fps = default_fps()
+    .map(|fp| FrameParameters { quantizer: Some(80), ..fp })
+    .collect();
+  assert!(frame_override_qps(100, fps).iter().all(|&qp| qp == 80));
+}
+
+#[test]
+fn frame_quantizer_override_lossless() {
+  let mut ctx: Context<u8> = Config::default().new_context().unwrap();
+  let fp = FrameParameters { quantizer: Some(0), ..Default::default() };
+  assert_eq!(
+    ctx.send_frame((ctx.new_frame(), fp)),
+    Err(EncoderStatus::Failure)
+  );
+}
+
+#[interpolate_test(low_latency, true)]
+#[interpolate_test(reorder, false)]
+fn frame_no_reference(low_latency: bool) {
+  let mut ctx = setup_encoder::<u8>(
+    64,
+    80,
+    10,
+    100,
+    8,
+    ChromaSampling::Cs420,
+    0,
+    30,
+    0,
+    low_latency,
+    0,
+    true,
+    10,
+    None,
+  );
+  let limit = 10;
+  for i in 0..limit {
+    let input = ctx.new_frame();
+    let fp =
+      FrameParameters { no_reference: i % 2 == 1, ..Default::default() };
+    ctx.send_frame((input, fp)).unwrap();
+  }
+  ctx.flush();
+
+  // Key frames and hidden frames keep refreshing their slots.
+  for fi in get_frame_invariants(ctx).flatten() {
+    if fi.is_show_existing_frame() {
+      continue;
+    }
+    if fi.frame_type == FrameType::KEY {
+      assert_eq!(fi.refresh_frame_flags, ALL_REF_FRAMES_MASK);
+    } else if fi.show_frame && fi.input_frameno % 2 == 1 {
+      assert_eq!(fi.refresh_frame_flags, 0);
+    } else {
+      assert_ne!(fi.refresh_frame_flags, 0);
+    }
+  }
+}
+
+fn importance_map_sse(
+  importance_map: Option<Box<[f32]>>, segmentation: SegmentationLevel,
+) -> [u64; 2] {
+  let mut cfg = setup_config(
+    64,
+    64,
+    10,
+    100,
+    8,
+    ChromaSampling::Cs420,
+    0,
+    30,
+    0,
+    true,
+    0,
+    true,
+    10,
+    None,
+  );
+  cfg.enc.speed_settings.segmentation = segmentation;
+  let mut ctx: Context<u8> = cfg.new_context().unwrap();
+  let mut input = ctx.new_frame();
+  let mut seed = 1u32;
+  fill_noise(&mut input, &mut seed, 64, 7);
+  let source = input.clone();
+  let fp = FrameParameters { importance_map, ..Default::default() };
+  ctx.send_frame((input, fp)).unwrap();
+  ctx.flush();
+
+  let rec = loop {
+    match ctx.receive_packet() {
+      Ok(pkt) => break pkt.rec.unwrap(),
+      Err(EncoderStatus::Encoded) => {}
+      Err(e) => panic!("Unexpected status {:?}", e),
+    }
+  };
+  // Squared error of the left and right halves of the luma plane.
+  let mut sse = [0; 2];
+  for (rec, source) in
+    rec.planes[0].rows_iter().zip(source.planes[0].rows_iter())
+  {
+    for (x, (&a, &b)) in rec[..64].iter().zip(&source[..64]).enumerate() {
+      sse[x / 32] += (a as i64 - b as i64).pow(2) as u64;
+    }
+  }
+  sse
+}
+
+#[test]
+fn importance_map() {
+  let [left, right] = importance_map_sse(None, SegmentationLevel::Simple);
+  let ratio = left as f64 / right as f64;
+  assert!((0.8..1.25).contains(&ratio), "{} {}", left, right);
+
+  // The left half is more important than the right half.
+  let map: Box<[f32]> =
+    (0..64).map(|i| if i % 8 < 4 { 16. } else { 1. }).collect();
+  let [left, right] =
+    importance_map_sse(Some(map.clone()), SegmentationLevel::Simple);
+  assert!(left * 2 < right, "{} {}", left, right);
+
+  // The map enables the segmentation disabled by the speed settings.
+  let [left, right] =
+    importance_map_sse(Some(map), SegmentationLevel::Disabled);
+  assert!(left * 2 < right, "{} {}", left, right);
+}
+
+#[test]
+fn importance_map_invalid() {
+  let mut ctx = setup_encoder::<u8>(
+    64,
+    64,
+    10,
+    100,
+    8,
+    ChromaSampling::Cs420,
+    0,
+    30,
+    0,
+    true,
+    0,
+    true,
+    10,
+    None,
+  );
+  for map in [vec![1.; 63], vec![0.; 64], vec![f32::NAN; 64]] {
+    let fp = FrameParameters {
+      importance_map: Some(map.into_boxed_slice()),
+      ..Default::default()
+    };
+    assert_eq!(
+      ctx.send_frame((ctx.new_frame(), fp)),
+      Err(EncoderStatus::Failure)
+    );
+  }
+  let fp = FrameParameters {
+    importance_map: Some(vec![1.; 64].into_boxed_slice()),
+    ..Default::default()
+  };
+  assert_eq!(ctx.send_frame((ctx.new_frame(), fp)), Ok(()));
+}
+
 #[test]
 fn test_opaque_delivery() {
   let mut ctx = setup_encoder::<u8>(
@@ -1370,7 +1615,12 @@ fn send_frame_t35<T: Pixel>(ctx: &mut Context<T>) {
     data: Box::new(*b"AYAYA"),
   }]);
 
-  let fp = FrameParameters { frame_type_override, opaque, t35_metadata };
+  let fp = FrameParameters {
+    frame_type_override,
+    opaque,
+    t35_metadata,
+    ..Default::default()
+  };
 
   let _ = ctx.send_frame((input, fp));
 }
@@ -1403,15 +1653,9 @@ fn test_t35_parameter() {
   while ctx.receive_packet().is_ok() {}
 }
 
-#[interpolate_test(0, 0)]
-#[interpolate_test(1, 1)]
-#[interpolate_test(2, 2)]
-#[interpolate_test(3, 3)]
-#[interpolate_test(4, 4)]
-fn output_frameno_incremental_reorder_keyframe_at(kf_at: u64) {
-  // Test output_frameno configurations when there's a forced keyframe at the
-  // <kf_at>th frame, computing the lookahead data incrementally.
-
+#[interpolate_test(low_latency, true)]
+#[interpolate_test(reorder, false)]
+fn frame_timestamps(low_latency: bool) {
   let mut ctx = setup_encoder::<u8>(
     64,
     80,
@@ -1422,112 +1666,46 @@ fn output_frameno_incremental_reorder_keyframe_at(kf_at: u64) {
     0,
     5,
     0,
-    false,
+    low_latency,
     0,
-    false,
+    true,
     10,
     None,
   );
 
-  // TODO: when we support more pyramid depths, this test will need tweaks.
-  assert_eq!(ctx.inner.inter_cfg.pyramid_depth, 2);
-
-  let limit = 5;
-  for i in 0..limit {
-    send_frame_kf(&mut ctx, kf_at == i);
+  // (pts, duration) sent with each frame, and the ones expected back.
+  let sent = [
+    (Some(0), None),
+    (Some(3), None),
+    (Some(5), Some(4)),
+    (None, None),
+    (Some(12), None),
+    (None, Some(2)),
+    (None, None),
+  ];
+  let expected = [(0, 3), (3, 2), (5, 4), (9, 3), (12, 3), (15, 2), (17, 2)];
+
+  for &(pts, duration) in sent.iter() {
+    let fp = FrameParameters { pts, duration, ..Default::default() };
+    ctx.send_frame((ctx.new_frame(), fp)).unwrap();
   }
   ctx.flush();
 
-  // data[output_frameno] = (input_frameno, !invalid)
-  let data = get_frame_invariants(ctx)
-    .map(|fi| fi.map(|fi| fi.input_frameno))
-    .collect::<Vec<_>>();
-
-  assert_eq!(
-    &data[..],
-    match kf_at {
-      0 => {
-        &[
-          Some(0), // I-frame
-          Some(4),
-          Some(2),
-          Some(1),
-          Some(2),
-          Some(3),
-          Some(4),
-        ][..]
-      }
-      1 => {
-        &[
-          Some(0), // I-frame
-          Some(1), // I-frame
-          None,
-          Some(3),
-          Some(2),
-          Some(3),
-          Some(4),
-          None,
-        ][..]
-      }
-      2 => {
-        &[
-          Some(0), // I-frame
-          None,
-          None,
-          Some(1),
-          None,
-          None,
-          None,
-          Some(2), // I-frame
-          None,
-          Some(4),
-          Some(3),
-          Some(4),
-          None,
-          None,
-        ][..]
-      }
-      3 => {
-        &[
-          Some(0), // I-frame
-          None,
-          Some(2),
-          Some(1),
-          Some(2),
-          None,
-          None,
-          Some(3), // I-frame
-          None,
-          None,
-          Some(4),
-          None,
-          None,
-          None,
-        ][..]
-      }
-      4 => {
-        &[
-          Some(0), // I-frame
-          None,
-          Some(2),
-          Some(1),
-          Some(2),
-          Some(3),
-          None,
-          Some(4), // I-frame
-        ][..]
-      }
-      _ => unreachable!(),
+  let mut times = Vec::new();
+  loop {
+    match ctx.receive_packet() {
+      Ok(pkt) => times.push((pkt.pts, pkt.duration)),
+      Err(EncoderStatus::Encoded) => {}
+      Err(EncoderStatus::LimitReached) => break,
+      Err(e) => panic!("Unexpected status {:?}", e),
     }
-  );
+  }
+  assert_eq!(&times[..], &expected[..]);
 }
 
-#[interpolate_test(1, 1)]
-#[interpolate_test(2, 2)]
-#[interpolate_test(3, 3)]
-fn output_frameno_no_scene_change_at_short_flash(flash_at: u64) {
-  // Test output_frameno configurations when there's a single-frame flash at the
-  // <flash_at>th frame.
+#[interpolate_test(low_latency, true)]
+#[interpolate_test(reorder, false)]
+fn frame_timestamps_interleaved(low_latency: bool) {
   let mut ctx = setup_encoder::<u8>(
     64,
     80,
@@ -1538,38 +1716,1535 @@ fn output_frameno_no_scene_change_at_short_flash(flash_at: u64) {
     0,
     5,
     0,
-    false,
+    low_latency,
     0,
-    false,
-    10,
+    true,
+    1,
     None,
   );
 
-  // TODO: when we support more pyramid depths, this test will need tweaks.
-  assert_eq!(ctx.inner.inter_cfg.pyramid_depth, 2);
-
-  let limit = 5;
-  for i in 0..limit {
-    if i == flash_at {
-      send_test_frame(&mut ctx, u8::MIN);
-    } else {
-      send_test_frame(&mut ctx, u8::MAX);
+  // Only the timestamps are given, the durations follow from the next ones.
+  let pts = [0, 3, 4, 10, 12, 13, 20, 21, 25, 31, 32, 40];
+  let mut times = Vec::new();
+  let mut receive = |ctx: &mut Context<u8>| loop {
+    match ctx.receive_packet() {
+      Ok(pkt) => times.push((pkt.pts, pkt.duration)),
+      Err(EncoderStatus::Encoded) => {}
+      Err(EncoderStatus::NeedMoreData) => break false,
+      Err(EncoderStatus::LimitReached) => break true,
+      Err(e) => panic!("Unexpected status {:?}", e),
     }
+  };
+  // Packets are received as soon as possible, so frames are coded with as
+  //  few of the following timestamps known as the lookahead allows.
+  for &pts in pts.iter() {
+    let fp = FrameParameters { pts: Some(pts), ..Default::default() };
+    ctx.send_frame((ctx.new_frame(), fp)).unwrap();
+    receive(&mut ctx);
   }
   ctx.flush();
+  while !receive(&mut ctx) {}
 
-  // data[output_frameno] = (input_frameno, !invalid)
-  let data = get_frame_invariants(ctx)
-    .map(|fi| fi.map(|fi| fi.input_frameno))
-    .collect::<Vec<_>>();
+  let expected: Vec<_> =
+    pts.windows(2).map(|w| (w[0], w[1] - w[0])).chain([(40, 8)]).collect();
+  assert_eq!(times, expected);
+}
 
-  assert_eq!(
-    &data[..],
-    &[
-      Some(0), // I-frame
-      Some(4),
-      Some(2),
-      Some(1),
+fn vfr_encoded_bits(duration: u64) -> usize {
+  let mut ctx = setup_encoder::<u8>(
+    64,
+    80,
+    10,
+    255,
+    8,
+    ChromaSampling::Cs420,
+    0,
+    30,
+    40_000,
+    true,
+    0,
+    true,
+    10,
+    None,
+  );
+
+  let limit = 30;
+  for i in 0..limit {
+    let mut input = ctx.new_frame();
+    for plane in input.planes.iter_mut() {
+      let stride = plane.cfg.stride;
+      for (y, row) in plane.data.chunks_mut(stride).enumerate() {
+        for (x, pixel) in row.iter_mut().enumerate() {
+          let u = (x + 3 * i) as f64;
+          let v = (y + 2 * i) as f64;
+          *pixel = (128. + 60. * (u * 0.3).sin() * (v * 0.2).cos()) as u8;
+        }
+      }
+    }
+    let fp = FrameParameters {
+      pts: Some(i as u64 * duration),
+      duration: Some(duration),
+      ..Default::default()
+    };
+    ctx.send_frame((input, fp)).unwrap();
+  }
+  ctx.flush();
+
+  let mut bits = 0;
+  loop {
+    match ctx.receive_packet() {
+      Ok(pkt) => bits += pkt.data.len() * 8,
+      Err(EncoderStatus::Encoded) => {}
+      Err(EncoderStatus::LimitReached) => break,
+      Err(e) => panic!("Unexpected status {:?}", e),
+    }
+  }
+  bits
+}
+
+#[test]
+fn vfr_rate_control() {
+  // Frames lasting twice as long get about twice as many bits.
+  let bits_cfr = vfr_encoded_bits(1);
+  let bits_vfr = vfr_encoded_bits(2);
+  assert!(
+    bits_vfr > bits_cfr * 3 / 2,
+    "{} bits at half the frame rate, {} bits at the full one",
+    bits_vfr,
+    bits_cfr
+  );
+}
+
+// Encodes a clip that turns from flat to noisy halfway through and returns
+// the lowest fullness a decoder buffer filled at `max_bitrate` reaches.
+fn decoder_buffer_min_fullness(
+  maybe_crf: Option<u8>, max_bitrate: i32, buffer_size: i32,
+  enable_model: bool,
+) -> i64 {
+  let mut cfg = setup_config(
+    64,
+    80,
+    10,
+    255,
+    8,
+    ChromaSampling::Cs420,
+    0,
+    30,
+    if maybe_crf.is_some() { 0 } else { 20_000 },
+    false,
+    0,
+    true,
+    10,
+    None,
+  );
+  cfg.enc.crf = maybe_crf;
+  if enable_model {
+    cfg.enc.max_bitrate = max_bitrate;
+    cfg.enc.buffer_size = buffer_size;
+  }
+  let mut ctx: Context<u8> = cfg.new_context().unwrap();
+
+  let limit = 30;
+  let mut seed = 1u32;
+  for i in 0..limit {
+    let mut input = ctx.new_frame();
+    if i < limit / 2 {
+      fill_frame_const(&mut input, 128);
+    } else {
+      fill_noise(&mut input, &mut seed, 112, 5);
+    }
+    ctx.send_frame(input).unwrap();
+  }
+  ctx.flush();
+
+  let buffer_size = buffer_size as i64;
+  let mut fullness = buffer_size * 230 / 256;
+  let mut min_fullness = fullness;
+  loop {
+    match ctx.receive_packet() {
+      Ok(pkt) => {
+        fullness -= (pkt.data.len() * 8) as i64;
+        min_fullness = min_fullness.min(fullness);
+        fullness = (fullness + max_bitrate as i64 / 30).min(buffer_size);
+      }
+      Err(EncoderStatus::Encoded) => {}
+      Err(EncoderStatus::LimitReached) => break,
+      Err(e) => panic!("Unexpected status {:?}", e),
+    }
+  }
+  min_fullness
+}
+
+#[test]
+fn decoder_buffer_model() {
+  let (max_bitrate, buffer_size) = (60_000, 30_000);
+  assert!(
+    decoder_buffer_min_fullness(None, max_bitrate, buffer_size, false) < 0
+  );
+  let min_fullness =
+    decoder_buffer_min_fullness(None, max_bitrate, buffer_size, true);
+  assert!(min_fullness >= 0, "decoder buffer underflow: {}", min_fullness);
+}
+
+#[test]
+fn decoder_buffer_model_invalid_options() {
+  let enc = EncoderConfig { max_bitrate: 1000, ..Default::default() };
+  let config = Config::new().with_encoder_config(enc);
+  assert_eq!(config.validate(), Err(InvalidConfig::TargetBitrateNeeded));
+
+  let enc =
+    EncoderConfig { bitrate: 2000, max_bitrate: 1000, ..Default::default() };
+  let config = Config::new().with_encoder_config(enc);
+  assert_eq!(
+    config.validate(),
+    Err(InvalidConfig::InvalidMaxBitrate { actual: 1000, min: 2000 })
+  );
+
+  let enc = EncoderConfig { buffer_size: 1000, ..Default::default() };
+  let config = Config::new().with_encoder_config(enc);
+  assert_eq!(config.validate(), Err(InvalidConfig::InvalidBufferSize(1000)));
+
+  let enc = EncoderConfig {
+    bitrate: 1000,
+    max_bitrate: 2000,
+    enable_decoder_model_info: true,
+    ..Default::default()
+  };
+  let config = Config::new().with_encoder_config(enc);
+  assert_eq!(config.validate(), Err(InvalidConfig::InvalidDecoderModelInfo));
+}
+
+#[test]
+fn decoder_model_info() {
+  let mut cfg = setup_config(
+    64,
+    80,
+    10,
+    100,
+    8,
+    ChromaSampling::Cs420,
+    0,
+    30,
+    20_000,
+    false,
+    0,
+    true,
+    10,
+    None,
+  );
+  cfg.enc.max_bitrate = 40_000;
+  cfg.enc.enable_timing_info = true;
+  cfg.enc.enable_decoder_model_info = true;
+  cfg.validate().unwrap();
+  let seq = Sequence::new(&cfg.enc);
+  assert!(seq.decoder_model_info_present_flag);
+  // One second of buffer, 90% of which is filled before decoding starts.
+  assert_eq!(seq.decoder_buffer_delay, 80_858);
+  assert_eq!(seq.encoder_buffer_delay, 90_000 - 80_858);
+
+  let mut ctx: Context<u8> = cfg.new_context().unwrap();
+  send_frames(&mut ctx, 4, 0);
+  ctx.flush();
+  let mut count = 0;
+  loop {
+    match ctx.receive_packet() {
+      Ok(_) => count += 1,
+      Err(EncoderStatus::Encoded) => {}
+      Err(EncoderStatus::LimitReached) => break,
+      Err(e) => panic!("Unexpected status {:?}", e),
+    }
+  }
+  assert_eq!(count, 4);
+}
+
+// Encodes a clip that turns from flat to noisy halfway through, at a bitrate
+// too low for the noise, and returns the packets as
+// (input_frameno, frame_type, dropped, size).
+fn drop_frames_encode(
+  drop_frames: bool,
+) -> Vec<(u64, FrameType, bool, usize)> {
+  let mut cfg = setup_config(
+    64,
+    80,
+    10,
+    255,
+    8,
+    ChromaSampling::Cs420,
+    0,
+    60,
+    10_000,
+    true,
+    0,
+    true,
+    10,
+    None,
+  );
+  cfg.enc.drop_frames = drop_frames;
+  let mut ctx: Context<u8> = cfg.new_context().unwrap();
+
+  let limit = 30;
+  let mut seed = 1u32;
+  for i in 0..limit {
+    let mut input = ctx.new_frame();
+    if i < limit / 2 {
+      fill_frame_const(&mut input, 128);
+    } else {
+      fill_noise(&mut input, &mut seed, 96, 6);
+    }
+    ctx.send_frame(input).unwrap();
+  }
+  ctx.flush();
+
+  let mut packets = Vec::new();
+  loop {
+    match ctx.receive_packet() {
+      Ok(pkt) => packets.push((
+        pkt.input_frameno,
+        pkt.frame_type,
+        pkt.dropped,
+        pkt.data.len(),
+      )),
+      Err(EncoderStatus::Encoded) => {}
+      Err(EncoderStatus::LimitReached) => break,
+      Err(e) => panic!("Unexpected status {:?}", e),
+    }
+  }
+  packets
+}
+
+#[test]
+fn drop_frames() {
+  let packets = drop_frames_encode(false);
+  assert!(packets.iter().all(|&(_, _, dropped, _)| !dropped));
+  let bytes: usize = packets.iter().map(|&(.., size)| size).sum();
+
+  let packets_dropped = drop_frames_encode(true);
+  // Every input frame still produces a packet, in order.
+  assert_eq!(
+    packets_dropped.iter().map(|&(frameno, ..)| frameno).collect::<Vec<_>>(),
+    (0..30).collect::<Vec<_>>()
+  );
+  let mut ndropped = 0;
+  for &(_, frame_type, dropped, size) in &packets_dropped {
+    if dropped {
+      assert_eq!(frame_type, FrameType::INTER);
+      assert_eq!(size, 0);
+      ndropped += 1;
+    }
+  }
+  assert!(ndropped > 0);
+  let bytes_dropped: usize =
+    packets_dropped.iter().map(|&(.., size)| size).sum();
+  assert!(
+    bytes_dropped < bytes,
+    "{} bytes with frame dropping, {} bytes without",
+    bytes_dropped,
+    bytes
+  );
+}
+
+#[test]
+fn container_sequence_header() {
+  let mut cfg = setup_config(
+    64,
+    80,
+    10,
+    100,
+    10,
+    ChromaSampling::Cs422,
+    0,
+    30,
+    0,
+    false,
+    0,
+    true,
+    10,
+    None,
+  );
+  cfg.enc.level_idx = Some(8);
+  let ctx: Context<u16> = cfg.new_context().unwrap();
+  let header = ctx.container_sequence_header();
+  // marker and version, then profile 2 and level 8.
+  assert_eq!(header[..2], [0x81, 2 << 5 | 8]);
+  // high_bitdepth and chroma_subsampling_x.
+  assert_eq!(header[2], 0x48);
+  // The sequence header OBU follows.
+  assert_eq!(header[4] >> 3 & 0xf, 1);
+  assert_eq!(header[5] as usize, header.len() - 6);
+}
+
+// Returns the type and the content of the boxes at the top of `data`.
+fn mp4_boxes(mut data: &[u8]) -> Vec<([u8; 4], &[u8])> {
+  let mut boxes = Vec::new();
+  while !data.is_empty() {
+    let mut size = u32::from_be_bytes(data[..4].try_into().unwrap()) as usize;
+    let kind = data[4..8].try_into().unwrap();
+    let mut header = 8;
+    if size == 1 {
+      size = u64::from_be_bytes(data[8..16].try_into().unwrap()) as usize;
+      header = 16;
+    }
+    boxes.push((kind, &data[header..size]));
+    data = &data[size..];
+  }
+  boxes
+}
+
+// Returns the file, and the AV1 configuration record of the stream.
+fn encode_mp4(fragmented: bool) -> (Vec<u8>, Vec<u8>) {
+  let mut ctx: Context<u8> = setup_encoder(
+    64,
+    80,
+    10,
+    100,
+    8,
+    ChromaSampling::Cs420,
+    0,
+    5,
+    0,
+    false,
+    0,
+    true,
+    10,
+    None,
+  );
+  let header = ctx.container_sequence_header();
+  let enc = ctx.config.clone();
+  let output = std::io::Cursor::new(Vec::new());
+  let mut progressive = None;
+  let mut fmp4 = None;
+  if fragmented {
+    fmp4 = Some(FragmentedMp4Muxer::new(output, &enc, &header).unwrap());
+  } else {
+    progressive = Some(Mp4Muxer::new(output, &enc, &header).unwrap());
+  }
+
+  send_frames(&mut ctx, 12, 0);
+  ctx.flush();
+  loop {
+    match ctx.receive_packet() {
+      Ok(packet) => {
+        if let Some(mp4) = progressive.as_mut() {
+          mp4.write_packet(&packet).unwrap();
+        }
+        if let Some(mp4) = fmp4.as_mut() {
+          mp4.write_packet(&packet).unwrap();
+        }
+      }
+      Err(EncoderStatus::LimitReached) => break,
+      Err(EncoderStatus::Encoded) => {}
+      Err(err) => panic!("{:?}", err),
+    }
+  }
+
+  let data = match (progressive, fmp4) {
+    (Some(mp4), _) => mp4.finish().unwrap().into_inner(),
+    (_, Some(mp4)) => mp4.finish().unwrap().into_inner(),
+    _ => unreachable!(),
+  };
+  (data, header)
+}
+
+#[test]
+fn mp4_muxer() {
+  let (data, header) = encode_mp4(false);
+  let boxes = mp4_boxes(&data);
+  let kinds: Vec<_> = boxes.iter().map(|(kind, _)| kind).collect();
+  assert_eq!(kinds, [b"ftyp", b"mdat", b"moov"]);
+  assert_eq!(&boxes[0].1[..4], b"isom");
+  // Temporal delimiters are not stored in the samples.
+  assert_ne!(boxes[1].1[0], 0x12);
+
+  let moov = boxes[2].1;
+  let contains =
+    |needle: &[u8]| moov.windows(needle.len()).any(|w| w == needle);
+  assert!(contains(b"av01"));
+  assert!(contains(&[b"av1C".as_slice(), &header].concat()));
+  // Keyframes are sync samples, starting with the first one.
+  let stss = moov.windows(4).position(|w| w == b"stss").unwrap();
+  let count =
+    u32::from_be_bytes(moov[stss + 8..stss + 12].try_into().unwrap());
+  assert!(count >= 2);
+  assert_eq!(moov[stss + 12..stss + 16], 1u32.to_be_bytes());
+}
+
+#[test]
+fn fragmented_mp4_muxer() {
+  let (data, _) = encode_mp4(true);
+  let boxes = mp4_boxes(&data);
+  assert_eq!(&boxes[0].0, b"ftyp");
+  assert_eq!(&boxes[0].1[..4], b"cmfc");
+  assert_eq!(&boxes[1].0, b"moov");
+  assert!(boxes[1].1.windows(4).any(|w| w == b"mvex"));
+
+  // One fragment per GOP, each a moof followed by an mdat.
+  let fragments = &boxes[2..];
+  assert!(fragments.len() >= 4);
+  assert_eq!(fragments.len() % 2, 0);
+  for fragment in fragments.chunks(2) {
+    assert_eq!(&fragment[0].0, b"moof");
+    assert_eq!(&fragment[1].0, b"mdat");
+    let moof = fragment[0].1;
+    let trun = moof.windows(4).position(|w| w == b"trun").unwrap();
+    let count =
+      u32::from_be_bytes(moof[trun + 8..trun + 12].try_into().unwrap());
+    // data_offset, then the duration, size and flags of the first sample.
+    let size =
+      u32::from_be_bytes(moof[trun + 20..trun + 24].try_into().unwrap());
+    let flags =
+      u32::from_be_bytes(moof[trun + 24..trun + 28].try_into().unwrap());
+    assert!(count > 0);
+    assert!(size as usize <= fragment[1].1.len());
+    // Each fragment starts with a sync sample.
+    assert_eq!(flags & 0x0001_0000, 0);
+  }
+}
+
+fn encode_obu_stream(annex_b: bool) -> Vec<u8> {
+  let mut cfg = setup_config(
+    64,
+    80,
+    10,
+    100,
+    8,
+    ChromaSampling::Cs420,
+    0,
+    5,
+    0,
+    false,
+    0,
+    true,
+    10,
+    None,
+  );
+  cfg.enc.annex_b = annex_b;
+  let mut ctx: Context<u8> = cfg.new_context().unwrap();
+  let mut obu = ObuWriter::new(Vec::new(), &cfg.enc);
+
+  send_frames(&mut ctx, 8, 0);
+  ctx.flush();
+  loop {
+    match ctx.receive_packet() {
+      Ok(packet) => obu.write_packet(&packet).unwrap(),
+      Err(EncoderStatus::LimitReached) => break,
+      Err(EncoderStatus::Encoded) => {}
+      Err(err) => panic!("{:?}", err),
+    }
+  }
+  obu.into_inner()
+}
+
+fn read_leb128(data: &mut &[u8]) -> usize {
+  let mut value = 0;
+  for i in 0.. {
+    let byte = data[i];
+    value |= ((byte & 0x7f) as usize) << (7 * i);
+    if byte & 0x80 == 0 {
+      *data = &data[i + 1..];
+      break;
+    }
+  }
+  value
+}
+
+#[test]
+fn annex_b_stream() {
+  let section5 = encode_obu_stream(false);
+  let annex_b = encode_obu_stream(true);
+
+  // Rewrite the OBUs of the temporal units with their sizes.
+  let mut rewritten = Vec::new();
+  let mut temporal_units = 0;
+  let mut frame_units = 0;
+  let mut data = &annex_b[..];
+  while !data.is_empty() {
+    let temporal_unit_size = read_leb128(&mut data);
+    let (mut temporal_unit, rest) = data.split_at(temporal_unit_size);
+    data = rest;
+    temporal_units += 1;
+
+    let mut first = true;
+    while !temporal_unit.is_empty() {
+      let frame_unit_size = read_leb128(&mut temporal_unit);
+      let (mut frame_unit, rest) = temporal_unit.split_at(frame_unit_size);
+      temporal_unit = rest;
+      frame_units += 1;
+      // Each temporal unit starts with a temporal delimiter.
+      if first {
+        assert_eq!(frame_unit[..2], [1, 0x10]);
+        first = false;
+      }
+
+      let mut frame_headers = 0;
+      while !frame_unit.is_empty() {
+        let obu_length = read_leb128(&mut frame_unit);
+        let (obu, rest) = frame_unit.split_at(obu_length);
+        frame_unit = rest;
+        // obu_has_size_field is not set.
+        assert_eq!(obu[0] & 0x02, 0);
+        let obu_type = (obu[0] >> 3) & 0xf;
+        // Frame header and frame OBUs.
+        if obu_type == 3 || obu_type == 6 {
+          frame_headers += 1;
+        }
+        let header_size = if obu[0] & 0x04 != 0 { 2 } else { 1 };
+        rewritten.push(obu[0] | 0x02);
+        rewritten.extend_from_slice(&obu[1..header_size]);
+        let mut obu_size = obu_length - header_size;
+        while obu_size >= 0x80 {
+          rewritten.push((obu_size & 0x7f) as u8 | 0x80);
+          obu_size >>= 7;
+        }
+        rewritten.push(obu_size as u8);
+        rewritten.extend_from_slice(&obu[header_size..]);
+      }
+      assert_eq!(frame_headers, 1);
+    }
+  }
+  assert_eq!(temporal_units, 8);
+  // The hidden frames have frame units of their own.
+  assert!(frame_units > temporal_units);
+  assert_eq!(rewritten, section5);
+}
+
+#[test]
+fn mp4_muxer_annex_b() {
+  let enc = EncoderConfig { annex_b: true, ..Default::default() };
+  let output = std::io::Cursor::new(Vec::new());
+  assert!(Mp4Muxer::new(output, &enc, &[]).is_err());
+}
+
+#[test]
+fn drop_frames_invalid_options() {
+  let enc = EncoderConfig { drop_frames: true, ..Default::default() };
+  let config = Config::new().with_encoder_config(enc);
+  assert_eq!(config.validate(), Err(InvalidConfig::InvalidDropFrames));
+
+  let enc =
+    EncoderConfig { drop_frames: true, bitrate: 10_000, ..Default::default() };
+  let config = Config::new().with_encoder_config(enc);
+  assert_eq!(config.validate(), Err(InvalidConfig::InvalidDropFrames));
+
+  let enc = EncoderConfig {
+    drop_frames: true,
+    bitrate: 10_000,
+    low_latency: true,
+    ..Default::default()
+  };
+  let config = Config::new().with_encoder_config(enc);
+  assert!(config.validate().is_ok());
+}
+
+#[test]
+fn constant_quality() {
+  let mut cfg = setup_config(
+    64,
+    80,
+    10,
+    255,
+    8,
+    ChromaSampling::Cs420,
+    0,
+    30,
+    0,
+    true,
+    0,
+    true,
+    10,
+    None,
+  );
+  cfg.enc.crf = Some(100);
+  let mut ctx: Context<u8> = cfg.new_context().unwrap();
+
+  let limit = 20;
+  let mut seed = 1u32;
+  for i in 0..limit {
+    let mut input = ctx.new_frame();
+    if i < limit / 2 {
+      fill_frame_const(&mut input, 128);
+    } else {
+      fill_noise(&mut input, &mut seed, 112, 5);
+    }
+    ctx.send_frame(input).unwrap();
+  }
+  ctx.flush();
+
+  let mut qps = vec![0; limit];
+  loop {
+    match ctx.receive_packet() {
+      Ok(pkt) => qps[pkt.input_frameno as usize] = pkt.qp,
+      Err(EncoderStatus::Encoded) => {}
+      Err(EncoderStatus::LimitReached) => break,
+      Err(e) => panic!("Unexpected status {:?}", e),
+    }
+  }
+  // The flat frames stay at the quality target, the first complex frame is
+  //  quantized more coarsely, and the following ones less so as the average
+  //  complexity catches up.
+  assert_eq!(qps[1], qps[limit / 2 - 1]);
+  assert!(qps[limit / 2] > qps[limit / 2 - 1], "{:?}", qps);
+  assert!(qps[limit - 1] < qps[limit / 2], "{:?}", qps);
+}
+
+#[test]
+fn constant_quality_above_quantizer() {
+  let mut cfg = setup_config(
+    64,
+    80,
+    10,
+    EncoderConfig::default().quantizer,
+    8,
+    ChromaSampling::Cs420,
+    0,
+    30,
+    0,
+    true,
+    0,
+    true,
+    10,
+    None,
+  );
+  cfg.enc.crf = Some(200);
+  let mut ctx: Context<u8> = cfg.new_context().unwrap();
+
+  let limit = 5;
+  send_frames(&mut ctx, limit, 0);
+  ctx.flush();
+
+  let mut packets = 0;
+  loop {
+    match ctx.receive_packet() {
+      Ok(pkt) => {
+        // The quantizer does not cap the quality target.
+        assert!(pkt.qp > 100, "frame {}: qp {}", pkt.input_frameno, pkt.qp);
+        packets += 1;
+      }
+      Err(EncoderStatus::Encoded) => {}
+      Err(EncoderStatus::LimitReached) => break,
+      Err(e) => panic!("Unexpected status {:?}", e),
+    }
+  }
+  assert_eq!(packets, limit);
+}
+
+#[test]
+fn constant_quality_decoder_buffer_model() {
+  let (max_bitrate, buffer_size) = (60_000, 30_000);
+  assert!(
+    decoder_buffer_min_fullness(Some(40), max_bitrate, buffer_size, false) < 0
+  );
+  let min_fullness =
+    decoder_buffer_min_fullness(Some(40), max_bitrate, buffer_size, true);
+  assert!(min_fullness >= 0, "decoder buffer underflow: {}", min_fullness);
+}
+
+#[test]
+fn constant_quality_invalid_options() {
+  let enc =
+    EncoderConfig { crf: Some(100), bitrate: 1000, ..Default::default() };
+  let config = Config::new().with_encoder_config(enc);
+  assert_eq!(config.validate(), Err(InvalidConfig::CrfWithTargetBitrate));
+
+  let enc = EncoderConfig {
+    crf: Some(100),
+    quantizer: 0,
+    lossless: true,
+    ..Default::default()
+  };
+  let config = Config::new().with_encoder_config(enc);
+  assert_eq!(
+    config.validate(),
+    Err(InvalidConfig::InvalidOptionWithLossless("crf"))
+  );
+
+  let enc =
+    EncoderConfig { crf: Some(100), max_bitrate: 1000, ..Default::default() };
+  let config = Config::new().with_encoder_config(enc);
+  assert!(config.validate().is_ok());
+}
+
+#[test]
+fn zones_parse() {
+  assert_eq!(
+    "10-20:q=50,speed=3,bitrate_factor=1.5".parse::<Zone>(),
+    Ok(Zone {
+      start_frame: 10,
+      end_frame: 20,
+      quantizer: Some(50),
+      speed: Some(3),
+      bitrate_factor: Some(1.5),
+    })
+  );
+  assert_eq!(
+    "0-5:speed=10".parse::<Zone>(),
+    Ok(Zone {
+      start_frame: 0,
+      end_frame: 5,
+      speed: Some(10),
+      ..Default::default()
+    })
+  );
+  assert!("10-20".parse::<Zone>().is_err());
+  assert!("10:q=50".parse::<Zone>().is_err());
+  assert!("10-20:qp=50".parse::<Zone>().is_err());
+  assert!("10-20:q=256".parse::<Zone>().is_err());
+}
+
+#[test]
+fn zones_invalid() {
+  let validate = |zones: Vec<Zone>| {
+    let enc = EncoderConfig { zones, ..Default::default() };
+    Config::new().with_encoder_config(enc).validate()
+  };
+  let zone = |start_frame, end_frame| Zone {
+    start_frame,
+    end_frame,
+    ..Default::default()
+  };
+
+  assert!(validate(vec![zone(0, 9), zone(10, 19)]).is_ok());
+  assert_eq!(validate(vec![zone(10, 9)]), Err(InvalidConfig::InvalidZone(0)));
+  assert_eq!(
+    validate(vec![zone(0, 10), zone(10, 19)]),
+    Err(InvalidConfig::InvalidZone(1))
+  );
+  assert_eq!(
+    validate(vec![zone(10, 19), zone(0, 9)]),
+    Err(InvalidConfig::InvalidZone(1))
+  );
+  assert_eq!(
+    validate(vec![Zone { quantizer: Some(0), ..zone(0, 9) }]),
+    Err(InvalidConfig::InvalidZone(0))
+  );
+  assert_eq!(
+    validate(vec![Zone { speed: Some(11), ..zone(0, 9) }]),
+    Err(InvalidConfig::InvalidZone(0))
+  );
+  assert_eq!(
+    validate(vec![Zone { bitrate_factor: Some(0.), ..zone(0, 9) }]),
+    Err(InvalidConfig::InvalidZone(0))
+  );
+}
+
+fn zones_encode(
+  zones: Vec<Zone>, bitrate: i32, limit: u64,
+) -> Vec<(FrameType, u8, usize)> {
+  let enc = EncoderConfig {
+    width: 64,
+    height: 80,
+    quantizer: if bitrate > 0 { 255 } else { 100 },
+    bitrate,
+    low_latency: true,
+    max_key_frame_interval: 30,
+    speed_settings: SpeedSettings::from_preset(10),
+    zones,
+    ..Default::default()
+  };
+  let mut ctx: Context<u8> = Config::new()
+    .with_encoder_config(enc)
+    .with_threads(1)
+    .new_context()
+    .unwrap();
+  for i in 0..limit {
+    let mut input = ctx.new_frame();
+    fill_frame_const(&mut input, (i * 8 % 256) as u8);
+    ctx.send_frame(input).unwrap();
+  }
+  ctx.flush();
+
+  let mut frames = vec![(FrameType::INTER, 0, 0); limit as usize];
+  loop {
+    match ctx.receive_packet() {
+      Ok(pkt) => {
+        frames[pkt.input_frameno as usize] =
+          (pkt.frame_type, pkt.qp, pkt.data.len())
+      }
+      Err(EncoderStatus::Encoded) => {}
+      Err(EncoderStatus::LimitReached) => break,
+      Err(e) => panic!("Unexpected status {:?}", e),
+    }
+  }
+  frames
+}
+
+#[test]
+fn zones_quantizer_and_speed() {
+  let zone = Zone {
+    start_frame: 4,
+    end_frame: 7,
+    quantizer: Some(50),
+    speed: Some(6),
+    ..Default::default()
+  };
+  let frames = zones_encode(vec![zone], 0, 10);
+  let reference = zones_encode(Vec::new(), 0, 10);
+
+  // A key frame starts the zone.
+  assert_eq!(frames[4].0, FrameType::KEY);
+  assert_eq!(reference[4].0, FrameType::INTER);
+  for i in 1..10 {
+    if (4..=7).contains(&i) {
+      // Inter frames included, the frames of the zone have its base_q_idx.
+      assert_eq!(frames[i].1, 50, "{:?}", frames);
+    } else {
+      assert_eq!(frames[i].1, reference[i].1, "{:?} {:?}", frames, reference);
+    }
+  }
+}
+
+#[test]
+fn zones_bitrate_factor() {
+  let zone = Zone {
+    start_frame: 0,
+    end_frame: 9,
+    bitrate_factor: Some(4.),
+    ..Default::default()
+  };
+  let frames = zones_encode(vec![zone], 5_000, 10);
+  let reference = zones_encode(Vec::new(), 5_000, 10);
+
+  // The zone gets more bits, so a lower quantizer.
+  let qp_sum = |frames: &[(FrameType, u8, usize)]| -> u32 {
+    frames.iter().map(|f| f.1 as u32).sum()
+  };
+  assert!(
+    qp_sum(&frames) < qp_sum(&reference),
+    "{:?} {:?}",
+    frames,
+    reference
+  );
+}
+
+#[test]
+fn level_auto_selection() {
+  let level = |width, height, fps, bitrate| {
+    let enc = EncoderConfig {
+      width,
+      height,
+      time_base: Rational::new(1, fps),
+      bitrate,
+      auto_level: true,
+      ..Default::default()
+    };
+    assert!(Config::new().with_encoder_config(enc.clone()).validate().is_ok());
+    Sequence::new(&enc).level_idx[0]
+  };
+
+  // Without a bound on the bitrate, no level can be met.
+  assert_eq!(level(64, 80, 30, 0), 31);
+  assert_eq!(level(64, 80, 30, 1_000_000), 0);
+  assert_eq!(level(64, 80, 30, 2_000_000), 1);
+  assert_eq!(level(1920, 1080, 30, 5_000_000), 8);
+  assert_eq!(level(1920, 1080, 60, 5_000_000), 9);
+  assert_eq!(level(1920, 1080, 60, 25_000_000), 12);
+  assert_eq!(level(64, 80, 30, 200_000_000), 31);
+}
+
+#[test]
+fn level_constraints_exceeded() {
+  let validate =
+    |enc: EncoderConfig| Config::new().with_encoder_config(enc).validate();
+  let enc = EncoderConfig {
+    width: 1280,
+    height: 720,
+    level_idx: Some(5),
+    ..Default::default()
+  };
+  assert!(validate(enc.clone()).is_ok());
+
+  let invalid = [
+    EncoderConfig { width: 1920, height: 1080, ..enc.clone() },
+    EncoderConfig { time_base: Rational::new(1, 60), ..enc.clone() },
+    EncoderConfig { tile_cols: 8, tile_rows: 4, ..enc.clone() },
+    EncoderConfig { bitrate: 12_000_000, ..enc.clone() },
+    EncoderConfig {
+      bitrate: 5_000_000,
+      max_bitrate: 12_000_000,
+      ..enc.clone()
+    },
+    EncoderConfig {
+      bitrate: 5_000_000,
+      max_bitrate: 10_000_000,
+      buffer_size: 20_000_000,
+      ..enc.clone()
+    },
+    EncoderConfig { quantizer: 0, lossless: true, ..enc.clone() },
+  ];
+  for enc in invalid {
+    assert_eq!(
+      validate(enc.clone()),
+      Err(InvalidConfig::LevelConstraintsExceeded),
+      "{:?}",
+      enc
+    );
+  }
+}
+
+#[test]
+fn level_tile_size() {
+  let enc = EncoderConfig {
+    width: 2560,
+    height: 4096,
+    level_idx: Some(16),
+    ..Default::default()
+  };
+  assert!(Config::new().with_encoder_config(enc.clone()).validate().is_ok());
+
+  // A single tile would exceed the maximum tile area, 4096x2304.
+  let seq = Sequence::new(&enc);
+  let tiling = seq.tiling;
+  assert!(tiling.rows * tiling.cols > 1);
+  let tile_area = (tiling.tile_width_sb << tiling.sb_size_log2).min(2560)
+    * (tiling.tile_height_sb << tiling.sb_size_log2).min(4096);
+  assert!(tile_area <= 4096 * 2304);
+  let single_tile = TilingInfo {
+    cols: 1,
+    rows: 1,
+    tile_width_sb: 40,
+    tile_height_sb: 64,
+    ..tiling
+  };
+  assert!(!level_constraints_met(&enc, 16, &single_tile, seq.profile));
+
+  // A single tile would exceed the maximum tile width, 4096.
+  let enc = EncoderConfig { width: 8192, height: 1024, ..enc };
+  let seq = Sequence::new(&enc);
+  assert!(seq.tiling.cols > 1);
+  assert!(level_constraints_met(&enc, 16, &seq.tiling, seq.profile));
+  let single_tile = TilingInfo {
+    cols: 1,
+    rows: 1,
+    tile_width_sb: 128,
+    tile_height_sb: 16,
+    ..seq.tiling
+  };
+  assert!(!level_constraints_met(&enc, 16, &single_tile, seq.profile));
+}
+
+fn level_max_frame_size(level_idx: Option<u8>) -> usize {
+  let enc = EncoderConfig {
+    width: 64,
+    height: 80,
+    quantizer: 10,
+    min_key_frame_interval: 0,
+    max_key_frame_interval: 1,
+    level_idx,
+    speed_settings: SpeedSettings::from_preset(10),
+    ..Default::default()
+  };
+  let mut ctx: Context<u8> = Config::new()
+    .with_encoder_config(enc)
+    .with_threads(1)
+    .new_context()
+    .unwrap();
+  let mut seed = 1u32;
+  for _ in 0..3 {
+    let mut input = ctx.new_frame();
+    fill_noise(&mut input, &mut seed, 0, 8);
+    ctx.send_frame(input).unwrap();
+  }
+  ctx.flush();
+
+  let mut max_size = 0;
+  loop {
+    match ctx.receive_packet() {
+      Ok(pkt) => max_size = max_size.max(pkt.data.len()),
+      Err(EncoderStatus::Encoded) => {}
+      Err(EncoderStatus::LimitReached) => break,
+      Err(e) => panic!("Unexpected status {:?}", e),
+    }
+  }
+  max_size
+}
+
+#[test]
+fn level_frame_size() {
+  // Level 2.0 allows frames of 64x80 pixels to use 3840 bytes.
+  assert!(level_max_frame_size(None) > 3840);
+  assert!(level_max_frame_size(Some(0)) <= 3840);
+}
+
+#[test]
+fn quality_target_invalid_options() {
+  let validate =
+    |enc: EncoderConfig| Config::new().with_encoder_config(enc).validate();
+  let target = Some(QualityTarget::Psnr(40.));
+  assert!(validate(EncoderConfig {
+    quality_target: target,
+    ..Default::default()
+  })
+  .is_ok());
+  assert_eq!(
+    validate(EncoderConfig {
+      quality_target: target,
+      bitrate: 1000,
+      ..Default::default()
+    }),
+    Err(InvalidConfig::QualityTargetWithRateControl)
+  );
+  assert_eq!(
+    validate(EncoderConfig {
+      quality_target: target,
+      crf: Some(100),
+      ..Default::default()
+    }),
+    Err(InvalidConfig::QualityTargetWithRateControl)
+  );
+  assert_eq!(
+    validate(EncoderConfig {
+      quality_target: Some(QualityTarget::Ssim(1.5)),
+      ..Default::default()
+    }),
+    Err(InvalidConfig::InvalidQualityTarget)
+  );
+
+  assert_eq!("ssim:0.95".parse(), Ok(QualityTarget::Ssim(0.95)));
+  assert!("vmaf:95".parse::<QualityTarget>().is_err());
+}
+
+// Encodes two scenes of noise at different levels and returns the quantizer
+// search results and the PSNR of every frame.
+fn quality_target_encode(
+  target: QualityTarget,
+) -> (Vec<QualitySearchStats>, Vec<f64>) {
+  let enc = EncoderConfig {
+    width: 64,
+    height: 80,
+    quantizer: 255,
+    quality_target: Some(target),
+    max_key_frame_interval: 5,
+    low_latency: true,
+    speed_settings: SpeedSettings::from_preset(10),
+    ..Default::default()
+  };
+  let mut ctx: Context<u8> = Config::new()
+    .with_encoder_config(enc)
+    .with_threads(1)
+    .new_context()
+    .unwrap();
+  let limit = 10;
+  let mut seed = 1u32;
+  for i in 0..limit {
+    let mut input = ctx.new_frame();
+    let amplitude_bits = if i < 5 { 3 } else { 6 };
+    fill_noise(&mut input, &mut seed, 100, amplitude_bits);
+    ctx.send_frame(input).unwrap();
+  }
+  ctx.flush();
+
+  let mut searches = Vec::new();
+  let mut psnr = Vec::new();
+  loop {
+    match ctx.receive_packet() {
+      Ok(pkt) => {
+        let search = pkt.quality_search.unwrap();
+        if search.input_frameno == pkt.input_frameno {
+          searches.push(search);
+        }
+        psnr.push(crate::dist::frame_psnr(
+          &pkt.source.unwrap().planes[0],
+          &pkt.rec.unwrap().planes[0],
+          64,
+          80,
+          8,
+        ));
+      }
+      Err(EncoderStatus::Encoded) => {}
+      Err(EncoderStatus::LimitReached) => break,
+      Err(e) => panic!("Unexpected status {:?}", e),
+    }
+  }
+  (searches, psnr)
+}
+
+#[test]
+fn quality_target() {
+  let (searches, psnr) = quality_target_encode(QualityTarget::Psnr(38.));
+  assert_eq!(searches.len(), 2, "{:?}", searches);
+  for (i, search) in searches.iter().enumerate() {
+    assert_eq!(search.input_frameno, i as u64 * 5);
+    assert!(search.target_met, "{:?}", search);
+    assert!(search.quality >= 38.);
+    assert_eq!(search.sample_frames, 4);
+  }
+  // The noisier scene needs a finer quantizer.
+  assert!(searches[1].quantizer < searches[0].quantizer, "{:?}", searches);
+  // The key frames were sampled at the quantizer they are coded with.
+  assert!(psnr[0] >= 37. && psnr[5] >= 37., "{:?}", psnr);
+
+  let (finer_searches, _) = quality_target_encode(QualityTarget::Psnr(44.));
+  assert!(finer_searches[0].quantizer < searches[0].quantizer);
+}
+
+fn multipass_config() -> EncoderConfig {
+  EncoderConfig {
+    width: 64,
+    height: 64,
+    bitrate: 50_000,
+    min_key_frame_interval: 1,
+    low_latency: true,
+    speed_settings: SpeedSettings::from_preset(10),
+    ..Default::default()
+  }
+}
+
+// Runs an encoding pass over a panning texture with a scene change at frame
+//  6, returning the pass data emitted, if requested, and the number of
+//  packets.
+#[allow(clippy::type_complexity)]
+fn multipass_encode(
+  enc: &EncoderConfig, summary: Option<&[u8]>, frame_data: &[Box<[u8]>],
+  emit: bool,
+) -> (Option<Box<[u8]>>, Vec<Box<[u8]>>, usize) {
+  let mut rc = RateControlConfig::new().with_emit_data(emit);
+  if let Some(summary) = summary {
+    rc = rc.with_summary(RateControlSummary::from_slice(summary).unwrap());
+  }
+  let mut ctx: Context<u8> = Config::new()
+    .with_encoder_config(enc.clone())
+    .with_rate_control(rc)
+    .with_threads(1)
+    .new_context()
+    .unwrap();
+  let limit = 10;
+  // Random 8x8 cells, which motion search can follow.
+  let texture = |seed: u32| {
+    let mut seed = seed;
+    let cells: Vec<_> =
+      (0..16 * 8).map(|_| (next_noise(&mut seed) >> 24) as u8).collect();
+    (0..128 * 64).map(|i| cells[(i / 128 / 8) * 16 + i % 128 / 8]).collect()
+  };
+  let (first_scene, second_scene): (Vec<u8>, Vec<u8>) =
+    (texture(1), texture(2));
+  for i in 0..limit {
+    let mut input = ctx.new_frame();
+    let (scene, x_offset) =
+      if i < 6 { (&first_scene, i * 2 + 3) } else { (&second_scene, i + 3) };
+    for plane in input.planes.iter_mut() {
+      let stride = plane.cfg.stride;
+      for (y, row) in plane.data_origin_mut().chunks_mut(stride).enumerate() {
+        for (x, pixel) in row.iter_mut().enumerate() {
+          *pixel = scene[(y % 64) * 128 + (x + x_offset) % 128];
+        }
+      }
+    }
+    ctx.send_frame(input).unwrap();
+  }
+  ctx.flush();
+
+  let mut frame_data = frame_data.iter();
+  let mut pass_data = Vec::new();
+  let mut npackets = 0;
+  loop {
+    while summary.is_some() && ctx.rc_second_pass_data_required() > 0 {
+      ctx.rc_send_pass_data(frame_data.next().unwrap()).unwrap();
+    }
+    let done = match ctx.receive_packet() {
+      Ok(_) => {
+        npackets += 1;
+        false
+      }
+      Err(EncoderStatus::Encoded) => false,
+      Err(EncoderStatus::LimitReached) => true,
+      Err(e) => panic!("Unexpected status {:?}", e),
+    };
+    if emit {
+      match ctx.rc_receive_pass_data() {
+        Some(RcData::Frame(data)) => pass_data.push(data),
+        Some(RcData::Summary(data)) => {
+          return (Some(data), pass_data, npackets);
+        }
+        None => {}
+      }
+    }
+    if done {
+      return (None, pass_data, npackets);
+    }
+  }
+}
+
+#[test]
+fn multipass_frame_stats() {
+  let enc = multipass_config();
+  let (summary, frame_data, npackets) =
+    multipass_encode(&enc, None, &[], true);
+  let summary = summary.unwrap();
+  assert_eq!(npackets, 10);
+  assert_eq!(frame_data.len(), 10);
+  let rc_summary = RateControlSummary::from_slice(&summary).unwrap();
+  assert_eq!(rc_summary.passes(), 1);
+
+  let stats: Vec<_> = frame_data
+    .iter()
+    .map(|data| rc_summary.frame_stats(data).unwrap())
+    .collect();
+  for (i, s) in stats.iter().enumerate() {
+    assert_eq!(*s, RateControlFrameStats::from_slice(&frame_data[i]).unwrap());
+    assert_eq!(s.input_frameno, i as u64);
+    assert!(s.show_frame && !s.show_existing_frame);
+    assert!(s.intra_cost > 0. && s.spatial_complexity > 0., "{:?}", s);
+  }
+  assert!(stats[0].keyframe && stats[0].inter_cost.is_none());
+  // The pan is predicted well, the scene change is not.
+  assert!(stats[3].inter_cost.unwrap() < stats[3].intra_cost, "{:?}", stats);
+  assert!(stats[3].motion > stats[8].motion, "{:?}", stats);
+  assert!(
+    stats[6].inter_cost.unwrap() > stats[7].inter_cost.unwrap(),
+    "{:?}",
+    stats
+  );
+  assert!(stats[6].keyframe && stats[6].scene_score >= 1., "{:?}", stats);
+
+  // Each pass can feed the next one.
+  let (summary, frame_data, npackets) =
+    multipass_encode(&enc, Some(&summary), &frame_data, true);
+  assert_eq!(npackets, 10);
+  let summary = summary.unwrap();
+  let rc_summary = RateControlSummary::from_slice(&summary).unwrap();
+  assert_eq!(rc_summary.passes(), 2);
+  let (_, _, npackets) =
+    multipass_encode(&enc, Some(&summary), &frame_data, false);
+  assert_eq!(npackets, 10);
+}
+
+#[test]
+fn multipass_keyframes_from_pass_data() {
+  let mut enc = multipass_config();
+  let (summary, frame_data, _) = multipass_encode(&enc, None, &[], true);
+  // The next pass starts the new scene at the same frame without detecting
+  //  it again.
+  enc.speed_settings.scene_detection_mode = SceneDetectionSpeed::None;
+  let (summary, frame_data, npackets) =
+    multipass_encode(&enc, Some(&summary.unwrap()), &frame_data, true);
+  assert_eq!(npackets, 10);
+  let rc_summary = RateControlSummary::from_slice(&summary.unwrap()).unwrap();
+  let keyframes: Vec<_> = frame_data
+    .iter()
+    .map(|data| rc_summary.frame_stats(data).unwrap())
+    .filter(|stats| stats.keyframe)
+    .map(|stats| stats.input_frameno)
+    .collect();
+  assert_eq!(keyframes, [0, 6]);
+}
+
+#[test]
+fn multipass_version_1_data() {
+  let enc = multipass_config();
+  let (summary, frame_data, _) = multipass_encode(&enc, None, &[], true);
+  // Strip the summary and frame packets down to their version 1 layout.
+  let mut summary = summary.unwrap()[..68].to_vec();
+  summary[4..8].copy_from_slice(&1u32.to_le_bytes());
+  let frame_data: Vec<Box<[u8]>> =
+    frame_data.iter().map(|data| data[..8].into()).collect();
+
+  let rc_summary = RateControlSummary::from_slice(&summary).unwrap();
+  assert_eq!(rc_summary.passes(), 1);
+  let stats = rc_summary.frame_stats(&frame_data[1]).unwrap();
+  assert!(!stats.keyframe && stats.show_frame);
+  assert!(stats.inter_cost.is_none());
+  assert!(rc_summary.frame_stats(&frame_data[0][..4]).is_err());
+
+  let (_, _, npackets) =
+    multipass_encode(&enc, Some(&summary), &frame_data, false);
+  assert_eq!(npackets, 10);
+
+  summary[4..8].copy_from_slice(&3u32.to_le_bytes());
+  assert!(RateControlSummary::from_slice(&summary).is_err());
+}
+
+#[test]
+fn multipass_scene_detection_inter_costs() {
+  // The inter costs of the standard scene detection are reused, those of the
+  //  other frames are estimated as it would.
+  let inter_costs = |scene_detection_mode| {
+    let mut enc = multipass_config();
+    enc.low_latency = false;
+    enc.speed_settings.scene_detection_mode = scene_detection_mode;
+    let (summary, frame_data, _) = multipass_encode(&enc, None, &[], true);
+    let rc_summary =
+      RateControlSummary::from_slice(&summary.unwrap()).unwrap();
+    let mut inter_costs: Vec<_> = frame_data
+      .iter()
+      .map(|data| rc_summary.frame_stats(data).unwrap())
+      .filter(|stats| !stats.show_existing_frame)
+      .map(|stats| (stats.input_frameno, stats.inter_cost))
+      .collect();
+    inter_costs.sort_by_key(|&(input_frameno, _)| input_frameno);
+    inter_costs
+  };
+  let reused = inter_costs(SceneDetectionSpeed::Standard);
+  assert_eq!(reused.len(), 10);
+  assert!(reused[1..].iter().all(|(_, cost)| cost.is_some()));
+  assert_eq!(reused, inter_costs(SceneDetectionSpeed::None));
+}
+
+#[interpolate_test(0, 0)]
+#[interpolate_test(1, 1)]
+#[interpolate_test(2, 2)]
+#[interpolate_test(3, 3)]
+#[interpolate_test(4, 4)]
+fn output_frameno_incremental_reorder_keyframe_at(kf_at: u64) {
+  // Test output_frameno configurations when there's a forced keyframe at the
+  // <kf_at>th frame, computing the lookahead data incrementally.
+
+  let mut ctx = setup_encoder::<u8>(
+    64,
+    80,
+    10,
+    100,
+    8,
+    ChromaSampling::Cs420,
+    0,
+    5,
+    0,
+    false,
+    0,
+    false,
+    10,
+    None,
+  );
+
+  // TODO: when we support more pyramid depths, this test will need tweaks.
+  assert_eq!(ctx.inner.inter_cfg.pyramid_depth, 2);
+
+  let limit = 5;
+  for i in 0..limit {
+    send_frame_kf(&mut ctx, kf_at == i);
+  }
+  ctx.flush();
+
+  // data[output_frameno] = (input_frameno, !invalid)
+  let data = get_frame_invariants(ctx)
+    .map(|fi| fi.map(|fi| fi.input_frameno))
+    .collect::<Vec<_>>();
+
+  assert_eq!(
+    &data[..],
+    match kf_at {
+      0 => {
+        &[
+          Some(0), // I-frame
+          Some(4),
+          Some(2),
+          Some(1),
+          Some(2),
+          Some(3),
+          Some(4),
+        ][..]
+      }
+      1 => {
+        &[
+          Some(0), // I-frame
+          Some(1), // I-frame
+          None,
+          Some(3),
+          Some(2),
+          Some(3),
+          Some(4),
+          None,
+        ][..]
+      }
+      2 => {
+        &[
+          Some(0), // I-frame
+          None,
+          None,
+          Some(1),
+          None,
+          None,
+          None,
+          Some(2), // I-frame
+          None,
+          Some(4),
+          Some(3),
+          Some(4),
+          None,
+          None,
+        ][..]
+      }
+      3 => {
+        &[
+          Some(0), // I-frame
+          None,
+          Some(2),
+          Some(1),
+          Some(2),
+          None,
+          None,
+          Some(3), // I-frame
+          None,
+          None,
+          Some(4),
+          None,
+          None,
+          None,
+        ][..]
+      }
+      4 => {
+        &[
+          Some(0), // I-frame
+          None,
+          Some(2),
+          Some(1),
+          Some(2),
+          Some(3),
+          None,
+          Some(4), // I-frame
+        ][..]
+      }
+      _ => unreachable!(),
+    }
+  );
+}
+
+#[interpolate_test(1, 1)]
+#[interpolate_test(2, 2)]
+#[interpolate_test(3, 3)]
+fn output_frameno_no_scene_change_at_short_flash(flash_at: u64) {
+  // Test output_frameno configurations when there's a single-frame flash at the
+  // <flash_at>th frame.
+  let mut ctx = setup_encoder::<u8>(
+    64,
+    80,
+    10,
+    100,
+    8,
+    ChromaSampling::Cs420,
+    0,
+    5,
+    0,
+    false,
+    0,
+    false,
+    10,
+    None,
+  );
+
+  // TODO: when we support more pyramid depths, this test will need tweaks.
+  assert_eq!(ctx.inner.inter_cfg.pyramid_depth, 2);
+
+  let limit = 5;
+  for i in 0..limit {
+    if i == flash_at {
+      send_test_frame(&mut ctx, u8::MIN);
+    } else {
+      send_test_frame(&mut ctx, u8::MAX);
+    }
+  }
+  ctx.flush();
+
+  // data[output_frameno] = (input_frameno, !invalid)
+  let data = get_frame_invariants(ctx)
+    .map(|fi| fi.map(|fi| fi.input_frameno))
+    .collect::<Vec<_>>();
+
+  assert_eq!(
+    &data[..],
+    &[
+      Some(0), // I-frame
+      Some(4),
+      Some(2),
+      Some(1),
       Some(2),
       Some(3),
       Some(4),
@@ -2113,23 +3788,36 @@ fn log_q_exp_overflow() {
     mastering_display: None,
     content_light: None,
     level_idx: Some(31),
+    auto_level: false,
     enable_timing_info: false,
     still_picture: false,
     error_resilient: false,
     switch_frame_interval: 0,
+    annex_b: false,
     time_base: Rational { num: 1, den: 25 },
     min_key_frame_interval: 12,
     max_key_frame_interval: 240,
     reservoir_frame_delay: None,
     low_latency: false,
+    temporal_layers: 1,
     quantizer: 100,
     min_quantizer: 64,
     bitrate: 1,
+    crf: None,
+    quality_target: None,
+    drop_frames: false,
+    max_bitrate: 0,
+    buffer_size: 0,
+    enable_decoder_model_info: false,
+    lossless: false,
     tune: Tune::Psychovisual,
     film_grain_params: None,
     tile_cols: 0,
     tile_rows: 0,
     tiles: 0,
+    superres_mode: SuperresMode::None,
+    screen_content: ScreenContentMode::Off,
+    zones: Vec::new(),
     speed_settings: SpeedSettings {
       multiref: false,
       fast_deblock: true,
@@ -2190,23 +3878,36 @@ fn guess_frame_subtypes_assert() {
     mastering_display: None,
     content_light: None,
     level_idx: Some(31),
+    auto_level: false,
     enable_timing_info: false,
     still_picture: false,
     error_resilient: false,
     switch_frame_interval: 0,
+    annex_b: false,
     time_base: Rational { num: 1, den: 25 },
     min_key_frame_interval: 0,
     max_key_frame_interval: 1,
     reservoir_frame_delay: None,
     low_latency: false,
+    temporal_layers: 1,
     quantizer: 100,
     min_quantizer: 0,
     bitrate: 16384,
+    crf: None,
+    quality_target: None,
+    drop_frames: false,
+    max_bitrate: 0,
+    buffer_size: 0,
+    enable_decoder_model_info: false,
+    lossless: false,
     tune: Tune::Psychovisual,
     film_grain_params: None,
     tile_cols: 0,
     tile_rows: 0,
     tiles: 0,
+    superres_mode: SuperresMode::None,
+    screen_content: ScreenContentMode::Off,
+    zones: Vec::new(),
     speed_settings: SpeedSettings {
       multiref: false,
       fast_deblock: true,
@@ -2379,3 +4080,475 @@ fn max_quantizer_bounds_correctly() {
     }
   }
 }
+
+#[interpolate_test(fixed_8, SuperresMode::Fixed(16), 8)]
+#[interpolate_test(fixed_10, SuperresMode::Fixed(12), 10)]
+#[interpolate_test(auto_8, SuperresMode::Auto, 8)]
+fn superres_encode(mode: SuperresMode, bit_depth: usize) {
+  let mut config = setup_config(
+    130,
+    72,
+    6, // Include loop restoration
+    200,
+    bit_depth,
+    ChromaSampling::Cs420,
+    1,
+    1,
+    0,
+    true,
+    0,
+    true,
+    1,
+    None,
+  );
+  config.enc.superres_mode = mode;
+  let mut ctx: Context<u16> = config.new_context().unwrap();
+
+  let limit = 4;
+  for _ in 0..limit {
+    let mut input = ctx.new_frame();
+    for plane in input.planes.iter_mut() {
+      let stride = plane.cfg.stride;
+      for (y, row) in plane.data.chunks_mut(stride).enumerate() {
+        for (x, pixel) in row.iter_mut().enumerate() {
+          *pixel = ((x * 3 + y * 5) % (1 << bit_depth)) as u16;
+        }
+      }
+    }
+    ctx.send_frame(input).unwrap();
+  }
+  ctx.flush();
+
+  let mut count = 0;
+  loop {
+    match ctx.receive_packet() {
+      Ok(pkt) => {
+        let rec = pkt.rec.unwrap();
+        assert_eq!(rec.planes[0].cfg.width, 136);
+        count += 1;
+      }
+      Err(EncoderStatus::Encoded) => {}
+      Err(EncoderStatus::LimitReached) => break,
+      Err(e) => panic!("Unexpected status {:?}", e),
+    }
+  }
+  assert_eq!(count, limit);
+}
+
+#[test]
+fn screen_content_encode() {
+  let mut config = setup_config(
+    192,
+    160,
+    6,
+    100,
+    8,
+    ChromaSampling::Cs420,
+    2,
+    2,
+    0,
+    true,
+    0,
+    true,
+    1,
+    None,
+  );
+  config.enc.screen_content = ScreenContentMode::On;
+  let mut ctx: Context<u8> = config.new_context().unwrap();
+
+  // Repeat a glyph-like pattern so that blocks can be copied
+  let limit = 4;
+  for i in 0..limit {
+    let mut input = ctx.new_frame();
+    for plane in input.planes.iter_mut() {
+      let stride = plane.cfg.stride;
+      for (y, row) in plane.data.chunks_mut(stride).enumerate() {
+        for (x, pixel) in row.iter_mut().enumerate() {
+          let glyph = ((x + i) % 24) * 7 + (y % 20) * 13;
+          *pixel = if glyph % 97 > 60 { 220 } else { 30 };
+        }
+      }
+    }
+    ctx.send_frame(input).unwrap();
+  }
+  ctx.flush();
+
+  for i in 0..limit as u64 {
+    ctx.inner.encode_packet(i).unwrap();
+    let frame_data = ctx.inner.frame_data.get(&i).unwrap().as_ref().unwrap();
+    let fi = &frame_data.fi;
+    assert_eq!(fi.allow_intrabc, fi.frame_type == FrameType::KEY);
+  }
+}
+
+#[test]
+fn screen_content_detection() {
+  let config = setup_config(
+    192,
+    160,
+    10,
+    100,
+    8,
+    ChromaSampling::Cs420,
+    30,
+    30,
+    0,
+    true,
+    0,
+    true,
+    1,
+    None,
+  );
+  let mut ctx: Context<u8> = config.new_context().unwrap();
+
+  // Natural-looking noise in the first frames, text-like glyphs after
+  let limit = 4;
+  for i in 0..limit {
+    let mut input = ctx.new_frame();
+    for plane in input.planes.iter_mut() {
+      let stride = plane.cfg.stride;
+      for (y, row) in plane.data.chunks_mut(stride).enumerate() {
+        for (x, pixel) in row.iter_mut().enumerate() {
+          *pixel = if i < 2 {
+            ((x * 37 + y * 91 + i * 13) % 251) as u8
+          } else {
+            let glyph = ((x + i) % 24) * 7 + (y % 20) * 13;
+            if glyph % 97 > 60 {
+              220
+            } else {
+              30
+            }
+          };
+        }
+      }
+    }
+    ctx.send_frame(input).unwrap();
+  }
+  ctx.flush();
+
+  let mut count = 0;
+  loop {
+    match ctx.receive_packet() {
+      Ok(pkt) => {
+        let screen_content = pkt.screen_content;
+        let is_text = pkt.input_frameno >= 2;
+        assert_eq!(screen_content.tools, is_text);
+        assert_eq!(screen_content.integer_mv, is_text);
+        count += 1;
+      }
+      Err(EncoderStatus::Encoded) => {}
+      Err(EncoderStatus::LimitReached) => break,
+      Err(e) => panic!("Unexpected status {:?}", e),
+    }
+  }
+  assert_eq!(count, limit);
+}
+
+fn lossless_encode<T: Pixel>(
+  bit_depth: usize, chroma_sampling: ChromaSampling,
+) {
+  let mut config = setup_config(
+    64,
+    48,
+    6,
+    100,
+    bit_depth,
+    chroma_sampling,
+    30,
+    30,
+    0,
+    false,
+    0,
+    true,
+    1,
+    None,
+  );
+  config.enc.lossless = true;
+  let mut ctx: Context<T> = config.new_context().unwrap();
+
+  let limit = 3;
+  let mut inputs = Vec::new();
+  for i in 0..limit {
+    let mut input = ctx.new_frame();
+    for plane in input.planes.iter_mut() {
+      let stride = plane.cfg.stride;
+      for (y, row) in plane.data.chunks_mut(stride).enumerate() {
+        for (x, pixel) in row.iter_mut().enumerate() {
+          let v = (x * 37 + y * 91 + i * 13) % 251;
+          *pixel = T::cast_from(v << (bit_depth - 8));
+        }
+      }
+    }
+    inputs.push(input.clone());
+    ctx.send_frame(input).unwrap();
+  }
+  ctx.flush();
+
+  let mut count = 0;
+  loop {
+    match ctx.receive_packet() {
+      Ok(pkt) => {
+        let rec = pkt.rec.unwrap();
+        let input = &inputs[pkt.input_frameno as usize];
+        for (rec, input) in rec.planes.iter().zip(input.planes.iter()) {
+          let width = rec.cfg.width;
+          for (rec, input) in rec.rows_iter().zip(input.rows_iter()) {
+            assert_eq!(rec[..width], input[..width]);
+          }
+        }
+        count += 1;
+      }
+      Err(EncoderStatus::Encoded) => {}
+      Err(EncoderStatus::LimitReached) => break,
+      Err(e) => panic!("Unexpected status {:?}", e),
+    }
+  }
+  assert_eq!(count, limit);
+}
+
+#[test]
+fn lossless_encode_8bit() {
+  lossless_encode::<u8>(8, ChromaSampling::Cs420);
+}
+
+#[test]
+fn lossless_encode_10bit() {
+  lossless_encode::<u16>(10, ChromaSampling::Cs420);
+}
+
+#[test]
+fn lossless_encode_444() {
+  lossless_encode::<u8>(8, ChromaSampling::Cs444);
+}
+
+#[test]
+fn lossless_invalid_options() {
+  let enc =
+    EncoderConfig { lossless: true, bitrate: 1000, ..Default::default() };
+  let config = Config::new().with_encoder_config(enc);
+  assert_eq!(
+    config.validate(),
+    Err(InvalidConfig::InvalidOptionWithLossless("bitrate"))
+  );
+
+  let enc = EncoderConfig {
+    lossless: true,
+    superres_mode: SuperresMode::Fixed(16),
+    ..Default::default()
+  };
+  let config = Config::new().with_encoder_config(enc);
+  assert_eq!(
+    config.validate(),
+    Err(InvalidConfig::InvalidOptionWithLossless("superres"))
+  );
+}
+
+#[test]
+fn superres_invalid_options() {
+  let enc = EncoderConfig {
+    superres_mode: SuperresMode::Auto,
+    max_key_frame_interval: 2,
+    ..Default::default()
+  };
+  let config = Config::new().with_encoder_config(enc.clone());
+  assert_eq!(
+    config.validate(),
+    Err(InvalidConfig::InvalidSuperresWithInterFrames)
+  );
+
+  let enc = EncoderConfig { still_picture: true, ..enc };
+  let config = Config::new().with_encoder_config(enc);
+  assert!(config.validate().is_ok());
+}
+
+#[test]
+fn global_motion_encode() {
+  let config = setup_config(
+    128,
+    96,
+    6,
+    100,
+    8,
+    ChromaSampling::Cs420,
+    30,
+    30,
+    0,
+    true,
+    0,
+    true,
+    10,
+    None,
+  );
+  assert!(config.enc.speed_settings.motion.global_motion);
+  let mut ctx: Context<u8> = config.new_context().unwrap();
+
+  // Zoom in on a smooth pattern while panning across it
+  let limit = 8;
+  for i in 0..limit {
+    let mut input = ctx.new_frame();
+    for plane in input.planes.iter_mut() {
+      let stride = plane.cfg.stride;
+      for (y, row) in plane.data.chunks_mut(stride).enumerate() {
+        for (x, pixel) in row.iter_mut().enumerate() {
+          let u = (x * 64 / (64 + i) + 2 * i) as f64;
+          let v = (y * 64 / (64 + i) + i) as f64;
+          *pixel = (128. + 60. * (u * 0.15).sin() * (v * 0.1).cos()) as u8;
+        }
+      }
+    }
+    ctx.send_frame(input).unwrap();
+  }
+  ctx.flush();
+
+  let mut count = 0;
+  loop {
+    match ctx.receive_packet() {
+      Ok(_) => count += 1,
+      Err(EncoderStatus::Encoded) => {}
+      Err(EncoderStatus::LimitReached) => break,
+      Err(e) => panic!("Unexpected status {:?}", e),
+    }
+  }
+  assert_eq!(count, limit);
+}
+
+#[test]
+fn motion_modes_encode() {
+  let config = setup_config(
+    128,
+    96,
+    6,
+    100,
+    8,
+    ChromaSampling::Cs420,
+    30,
+    30,
+    0,
+    true,
+    0,
+    true,
+    10,
+    None,
+  );
+  assert!(config.enc.speed_settings.motion.motion_modes);
+  let mut ctx: Context<u8> = config.new_context().unwrap();
+
+  // Pan across a smooth pattern with some local distortion
+  let limit = 8;
+  for i in 0..limit {
+    let mut input = ctx.new_frame();
+    for plane in input.planes.iter_mut() {
+      let stride = plane.cfg.stride;
+      for (y, row) in plane.data.chunks_mut(stride).enumerate() {
+        for (x, pixel) in row.iter_mut().enumerate() {
+          let d = (i as f64) * ((x + y) as f64 * 0.05).sin();
+          let u = (x + 2 * i) as f64 + d;
+          let v = (y + i) as f64 - d;
+          *pixel = (128. + 60. * (u * 0.15).sin() * (v * 0.1).cos()) as u8;
+        }
+      }
+    }
+    ctx.send_frame(input).unwrap();
+  }
+  ctx.flush();
+
+  let mut count = 0;
+  loop {
+    match ctx.receive_packet() {
+      Ok(_) => count += 1,
+      Err(EncoderStatus::Encoded) => {}
+      Err(EncoderStatus::LimitReached) => break,
+      Err(e) => panic!("Unexpected status {:?}", e),
+    }
+  }
+  assert_eq!(count, limit);
+}
+
+#[interpolate_test(2, 2)]
+#[interpolate_test(3, 3)]
+fn temporal_layers(layers: u8) {
+  let mut config = setup_config(
+    64,
+    80,
+    10,
+    100,
+    8,
+    ChromaSampling::Cs420,
+    30,
+    30,
+    0,
+    true,
+    0,
+    true,
+    10,
+    None,
+  );
+  config.enc.temporal_layers = layers;
+  let expected: &[u8] = if layers == 2 { &[0, 1] } else { &[0, 2, 1, 2] };
+
+  let mut ctx: Context<u8> = config.new_context().unwrap();
+  let limit = 9;
+  send_frames(&mut ctx, limit, 0);
+  ctx.flush();
+
+  // Each frame only references frames of the same or lower temporal layers
+  //  and the top layer is never referenced.
+  let mut slot_temporal_id = [0u8; REF_FRAMES];
+  for (i, fi) in get_frame_invariants(ctx).enumerate() {
+    let fi = fi.unwrap();
+    assert_eq!(fi.temporal_id, expected[i % expected.len()]);
+    if fi.frame_type != FrameType::KEY {
+      for &slot in fi.ref_frames.iter() {
+        assert!(slot_temporal_id[slot as usize] <= fi.temporal_id);
+      }
+    }
+    if fi.temporal_id == layers - 1 {
+      assert_eq!(fi.refresh_frame_flags, 0);
+    }
+    for (slot, tid) in slot_temporal_id.iter_mut().enumerate() {
+      if fi.refresh_frame_flags & (1 << slot) != 0 {
+        *tid = fi.temporal_id;
+      }
+    }
+  }
+
+  let mut ctx: Context<u8> = config.new_context().unwrap();
+  send_frames(&mut ctx, limit, 0);
+  ctx.flush();
+
+  let mut count = 0;
+  loop {
+    match ctx.receive_packet() {
+      Ok(pkt) => {
+        let temporal_id = expected[count % expected.len()];
+        assert_eq!(pkt.temporal_id, temporal_id);
+        assert_eq!(pkt.spatial_id, 0);
+        if pkt.frame_type != FrameType::KEY {
+          // The frame OBU follows the temporal delimiter and carries the
+          //  extension header with the layer ids.
+          assert_eq!(&pkt.data[..4], &[0x12, 0x00, 0x36, temporal_id << 5]);
+        }
+        count += 1;
+      }
+      Err(EncoderStatus::Encoded) => {}
+      Err(EncoderStatus::LimitReached) => break,
+      Err(e) => panic!("Unexpected status {:?}", e),
+    }
+  }
+  assert_eq!(count, limit as usize);
+}
+
+#[test]
+fn temporal_layers_invalid_options() {
+  let enc = EncoderConfig { temporal_layers: 2, ..Default::default() };
+  let config = Config::new().with_encoder_config(enc);
+  assert_eq!(config.validate(), Err(InvalidConfig::InvalidTemporalLayers(2)));
+
+  let enc = EncoderConfig {
+    temporal_layers: 4,
+    low_latency: true,
+    ..Default::default()
+  };
+  let config = Config::new().with_encoder_config(enc);
+  assert_eq!(config.validate(), Err(InvalidConfig::InvalidTemporalLayers(4)));
+}
@@ -9,6 +9,7 @@
 #![deny(missing_docs)]
 
 use crate::frame::*;
+use crate::scenechange::ScreenContentStats;
 use crate::serialize::{Deserialize, Serialize};
 use crate::stats::EncoderStats;
 use crate::util::Pixel;
@@ -193,6 +194,26 @@ pub enum EncoderStatus {
   NotReady,
 }
 
+/// Outcome of the quantizer search of the quality-targeted mode for a group
+/// of pictures.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct QualitySearchStats {
+  /// Number of the input frame starting the group of pictures.
+  pub input_frameno: u64,
+  /// Base quantizer selected for the group of pictures.
+  pub quantizer: u8,
+  /// Mean quality of the sample frames coded at that quantizer, in the
+  /// metric of the target.
+  pub quality: f64,
+  /// Whether the quality meets the target. It does not when the target is
+  /// out of reach of the quantizers allowed.
+  pub target_met: bool,
+  /// Number of frames sampled from the group of pictures.
+  pub sample_frames: usize,
+  /// Number of quantizers tried.
+  pub trial_quantizers: usize,
+}
+
 /// Represents a packet.
 ///
 /// A packet contains one shown frame together with zero or more additional
@@ -210,15 +231,30 @@ pub struct Packet<T: Pixel> {
   /// The number of the input frame corresponding to the one shown frame in the
   /// TU stored in this packet. Since AV1 does not explicitly reorder frames,
   /// these will increase sequentially.
-  // TODO: When we want to add VFR support, we will need a more explicit time
-  // stamp here.
   pub input_frameno: u64,
+  /// Presentation timestamp of the shown frame, in units of the `time_base`.
+  pub pts: u64,
+  /// Duration of the shown frame, in units of the `time_base`.
+  pub duration: u64,
   /// Type of the shown frame.
   pub frame_type: FrameType,
+  /// Temporal layer of the shown frame, 0 without temporal scalability.
+  pub temporal_id: u8,
+  /// Spatial layer of the shown frame. Only a single spatial layer is
+  /// currently supported, so this is always 0.
+  pub spatial_id: u8,
   /// QP selected for the frame.
   pub qp: u8,
+  /// Whether the frame was dropped by the rate control, in which case the
+  /// packet carries no data.
+  pub dropped: bool,
   /// Block-level encoding stats for the frame
   pub enc_stats: EncoderStats,
+  /// Screen content analysis of the frame and the tools it enabled
+  pub screen_content: ScreenContentStats,
+  /// Quantizer search of the group of pictures of the frame, in the
+  /// quality-targeted mode
+  pub quality_search: Option<QualitySearchStats>,
   /// Optional user-provided opaque data
   #[cfg_attr(feature = "serialize", serde(skip))]
   pub opaque: Option<Opaque>,
@@ -237,10 +273,11 @@ impl<T: Pixel> fmt::Display for Packet<T> {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     write!(
       f,
-      "Frame {} - {} - {} bytes",
+      "Frame {} - {} - {} bytes{}",
       self.input_frameno,
       self.frame_type,
-      self.data.len()
+      self.data.len(),
+      if self.dropped { " (dropped)" } else { "" }
     )
   }
 }
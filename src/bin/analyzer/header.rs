@@ -0,0 +1,897 @@
+// Copyright (c) 2017-2023, The rav1e contributors. All rights reserved
+//
+// This source code is subject to the terms of the BSD 2 Clause License and
+// the Alliance for Open Media Patent License 1.0. If the BSD 2 Clause License
+// was not distributed with this source code in the LICENSE file, you can
+// obtain it at www.aomedia.org/license/software. If the Alliance for Open
+// Media Patent License 1.0 was not distributed with this source code in the
+// PATENTS file, you can obtain it at www.aomedia.org/license/patent.
+
+use bitstream_io::{BigEndian, BitRead2, BitReader};
+use rav1e::prelude::*;
+
+use std::io;
+
+pub const REF_FRAMES: usize = 8;
+const REF_FRAMES_LOG2: u32 = 3;
+const INTER_REFS_PER_FRAME: usize = 7;
+pub const ALL_REF_FRAMES_MASK: u32 = (1 << REF_FRAMES) - 1;
+
+// seq_force_screen_content_tools and seq_force_integer_mv value letting
+// each frame choose.
+const SELECT: u32 = 2;
+
+const SUPERRES_NUM: u32 = 8;
+const SUPERRES_DENOM_MIN: u32 = 9;
+const SUPERRES_DENOM_BITS: u32 = 3;
+
+const MAX_TILE_WIDTH: u32 = 4096;
+const MAX_TILE_AREA: u32 = 4096 * 2304;
+const MAX_TILE_ROWS: u32 = 64;
+const MAX_TILE_COLS: u32 = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[allow(non_camel_case_types)]
+pub enum ObuType {
+  OBU_SEQUENCE_HEADER = 1,
+  OBU_TEMPORAL_DELIMITER = 2,
+  OBU_FRAME_HEADER = 3,
+  OBU_TILE_GROUP = 4,
+  OBU_METADATA = 5,
+  OBU_FRAME = 6,
+  OBU_REDUNDANT_FRAME_HEADER = 7,
+  OBU_TILE_LIST = 8,
+  OBU_PADDING = 15,
+}
+
+impl ObuType {
+  const fn from_u32(obu_type: u32) -> Option<Self> {
+    Some(match obu_type {
+      1 => ObuType::OBU_SEQUENCE_HEADER,
+      2 => ObuType::OBU_TEMPORAL_DELIMITER,
+      3 => ObuType::OBU_FRAME_HEADER,
+      4 => ObuType::OBU_TILE_GROUP,
+      5 => ObuType::OBU_METADATA,
+      6 => ObuType::OBU_FRAME,
+      7 => ObuType::OBU_REDUNDANT_FRAME_HEADER,
+      8 => ObuType::OBU_TILE_LIST,
+      15 => ObuType::OBU_PADDING,
+      _ => return None,
+    })
+  }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ObuHeader {
+  /// `None` for the reserved types, which decoders ignore.
+  pub obu_type: Option<ObuType>,
+  pub temporal_id: u32,
+  pub spatial_id: u32,
+  pub has_size_field: bool,
+}
+
+/// The fields of the sequence header needed to read the frame headers, and
+/// those reported by the analysis.
+#[derive(Clone, Debug, Default)]
+pub struct SequenceHeader {
+  pub profile: u32,
+  pub still_picture: bool,
+  pub reduced_still_picture_hdr: bool,
+  pub equal_picture_interval: bool,
+  pub decoder_model_info_present_flag: bool,
+  pub buffer_removal_time_length: u32,
+  pub frame_presentation_time_length: u32,
+  pub operating_point_idc: Vec<u32>,
+  pub level_idx: Vec<u32>,
+  pub tier: Vec<u32>,
+  pub decoder_model_present_for_this_op: Vec<bool>,
+  pub frame_width_bits: u32,
+  pub frame_height_bits: u32,
+  pub max_frame_width: u32,
+  pub max_frame_height: u32,
+  pub frame_id_numbers_present_flag: bool,
+  pub delta_frame_id_length: u32,
+  pub frame_id_length: u32,
+  pub use_128x128_superblock: bool,
+  pub enable_order_hint: bool,
+  pub enable_ref_frame_mvs: bool,
+  pub force_screen_content_tools: u32,
+  pub force_integer_mv: u32,
+  pub order_hint_bits: u32,
+  pub enable_superres: bool,
+  pub bit_depth: u32,
+  pub chroma_sampling: ChromaSampling,
+  pub color_primaries: u8,
+  pub transfer_characteristics: u8,
+  pub matrix_coefficients: u8,
+  pub full_range: bool,
+  pub film_grain_params_present: bool,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TileInfo {
+  pub cols: u32,
+  pub rows: u32,
+  pub cols_log2: u32,
+  pub rows_log2: u32,
+}
+
+/// The fields of the uncompressed header up to the quantizer.
+#[derive(Clone, Copy, Debug)]
+pub struct FrameHeader {
+  pub show_existing_frame: bool,
+  pub frame_type: FrameType,
+  pub show_frame: bool,
+  pub showable_frame: bool,
+  pub error_resilient_mode: bool,
+  pub frame_size_override_flag: bool,
+  /// `None` when the sequence does not code the order hints.
+  pub order_hint: Option<u32>,
+  pub refresh_frame_flags: u32,
+  /// Removal time from the decoder buffer of the first operating point
+  /// with a decoder model, when signaled.
+  pub buffer_removal_time: Option<u32>,
+  pub upscaled_width: u32,
+  pub frame_width: u32,
+  pub frame_height: u32,
+  pub render_width: u32,
+  pub render_height: u32,
+  /// `None` for the frames shown again with `show_existing_frame`.
+  pub tile_info: Option<TileInfo>,
+  /// `None` for the frames shown again with `show_existing_frame`.
+  pub base_q_idx: Option<u8>,
+}
+
+impl FrameHeader {
+  /// Returns the state kept in the slots the frame refreshes.
+  pub const fn ref_frame(&self) -> RefFrame {
+    RefFrame {
+      frame_type: self.frame_type,
+      upscaled_width: self.upscaled_width,
+      frame_height: self.frame_height,
+      render_width: self.render_width,
+      render_height: self.render_height,
+    }
+  }
+}
+
+/// The state of a reference frame slot which the frame headers refer to.
+#[derive(Clone, Copy, Debug)]
+pub struct RefFrame {
+  pub frame_type: FrameType,
+  pub upscaled_width: u32,
+  pub frame_height: u32,
+  pub render_width: u32,
+  pub render_height: u32,
+}
+
+pub type RefFrames = [Option<RefFrame>; REF_FRAMES];
+
+#[derive(Clone, Debug)]
+pub enum Metadata {
+  ContentLight(ContentLight),
+  MasteringDisplay(MasteringDisplay),
+  T35(T35),
+  /// The type of a metadata OBU whose payload is not read.
+  Other(u64),
+}
+
+pub fn invalid_data(msg: &str) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, msg.to_owned())
+}
+
+fn unsupported(msg: &str) -> io::Error {
+  io::Error::new(io::ErrorKind::Unsupported, msg.to_owned())
+}
+
+// <https://aomediacodec.github.io/av1-spec/#tile-info-semantics>
+const fn tile_log2(blk_size: u32, target: u32) -> u32 {
+  let mut k = 0;
+  while (blk_size << k) < target {
+    k += 1;
+  }
+  k
+}
+
+pub trait ULEB128Reader {
+  fn read_uleb128(&mut self) -> io::Result<u64>;
+}
+
+impl<R: io::Read> ULEB128Reader for BitReader<R, BigEndian> {
+  fn read_uleb128(&mut self) -> io::Result<u64> {
+    let mut value = 0;
+    for i in 0..8 {
+      let byte: u8 = self.read(8)?;
+      value |= u64::from(byte & 0x7f) << (i * 7);
+      if byte & 0x80 == 0 {
+        return Ok(value);
+      }
+    }
+    Err(invalid_data("leb128 value longer than 8 bytes"))
+  }
+}
+
+pub trait UncompressedHeaderReader {
+  fn read_obu_header(&mut self) -> io::Result<ObuHeader>;
+  fn read_sequence_header_obu(&mut self) -> io::Result<SequenceHeader>;
+  fn read_metadata_obu(&mut self) -> io::Result<Metadata>;
+  fn read_frame_header_obu(
+    &mut self, seq: &SequenceHeader, refs: &RefFrames, obu: &ObuHeader,
+  ) -> io::Result<FrameHeader>;
+  fn read_tile_start_and_end(
+    &mut self, tile_info: &TileInfo,
+  ) -> io::Result<(u32, u32)>;
+
+  fn read_sequence_header(
+    &mut self, seq: &mut SequenceHeader,
+  ) -> io::Result<()>;
+  fn read_color_config(&mut self, seq: &mut SequenceHeader) -> io::Result<()>;
+  fn read_temporal_point_info(
+    &mut self, seq: &SequenceHeader,
+  ) -> io::Result<()>;
+  fn read_frame_size(
+    &mut self, seq: &SequenceHeader, fh: &mut FrameHeader,
+  ) -> io::Result<()>;
+  fn read_superres_params(
+    &mut self, seq: &SequenceHeader, fh: &mut FrameHeader,
+  ) -> io::Result<()>;
+  fn read_render_size(&mut self, fh: &mut FrameHeader) -> io::Result<()>;
+  fn read_frame_size_with_refs(
+    &mut self, seq: &SequenceHeader, refs: &RefFrames,
+    ref_frame_idx: &[usize; INTER_REFS_PER_FRAME], fh: &mut FrameHeader,
+  ) -> io::Result<()>;
+  fn read_tile_info(
+    &mut self, seq: &SequenceHeader, fh: &FrameHeader,
+  ) -> io::Result<TileInfo>;
+  fn read_quniform(&mut self, n: u32) -> io::Result<u32>;
+  fn read_uvlc(&mut self) -> io::Result<u32>;
+}
+
+impl<R: io::Read> UncompressedHeaderReader for BitReader<R, BigEndian> {
+  // Start of OBU Headers
+  // Read OBU Header syntax
+  fn read_obu_header(&mut self) -> io::Result<ObuHeader> {
+    if self.read_bit()? {
+      return Err(invalid_data("forbidden bit set in OBU header"));
+    }
+    let obu_type = ObuType::from_u32(self.read(4)?);
+    let has_extension = self.read_bit()?;
+    let has_size_field = self.read_bit()?; // obu_has_payload_length_field
+    self.skip(1)?; // reserved
+
+    let (mut temporal_id, mut spatial_id) = (0, 0);
+    if has_extension {
+      temporal_id = self.read(3)?;
+      spatial_id = self.read(2)?;
+      self.skip(3)?; // extension_header_reserved_3bits
+    }
+
+    Ok(ObuHeader { obu_type, temporal_id, spatial_id, has_size_field })
+  }
+
+  fn read_sequence_header_obu(&mut self) -> io::Result<SequenceHeader> {
+    let profile = self.read(3)?; // profile
+    if profile > 2 {
+      return Err(invalid_data("reserved sequence profile"));
+    }
+    let mut seq = SequenceHeader {
+      profile,
+      still_picture: self.read_bit()?, // still_picture
+      reduced_still_picture_hdr: self.read_bit()?, // reduced_still_picture_header
+      ..Default::default()
+    };
+
+    if seq.reduced_still_picture_hdr {
+      seq.operating_point_idc.push(0);
+      seq.level_idx.push(self.read(5)?); // level
+      seq.tier.push(0);
+      seq.decoder_model_present_for_this_op.push(false);
+    } else {
+      let mut buffer_delay_length = 0;
+      if self.read_bit()? {
+        // timing info present
+        self.skip(32)?; // num_units_in_display_tick
+        self.skip(32)?; // time_scale
+
+        seq.equal_picture_interval = self.read_bit()?;
+        if seq.equal_picture_interval {
+          self.read_uvlc()?; // num_ticks_per_picture_minus_1
+        }
+        seq.decoder_model_info_present_flag = self.read_bit()?;
+        if seq.decoder_model_info_present_flag {
+          buffer_delay_length = self.read::<u32>(5)? + 1;
+          self.skip(32)?; // num_units_in_decoding_tick
+          seq.buffer_removal_time_length = self.read::<u32>(5)? + 1;
+          seq.frame_presentation_time_length = self.read::<u32>(5)? + 1;
+        }
+      }
+
+      let initial_display_delay_present = self.read_bit()?;
+      let operating_points_cnt = self.read::<u32>(5)? + 1;
+      for _ in 0..operating_points_cnt {
+        seq.operating_point_idc.push(self.read(12)?); // idc
+        let level_idx = self.read(5)?; // level
+        seq.level_idx.push(level_idx);
+        seq.tier.push(if level_idx > 7 { self.read(1)? } else { 0 }); // tier
+
+        let decoder_model_present =
+          seq.decoder_model_info_present_flag && self.read_bit()?;
+        if decoder_model_present {
+          self.skip(buffer_delay_length)?; // decoder_buffer_delay
+          self.skip(buffer_delay_length)?; // encoder_buffer_delay
+          self.skip(1)?; // low_delay_mode_flag
+        }
+        seq.decoder_model_present_for_this_op.push(decoder_model_present);
+
+        if initial_display_delay_present && self.read_bit()? {
+          self.skip(4)?; // initial_display_delay_minus_1
+        }
+      }
+    }
+
+    self.read_sequence_header(&mut seq)?;
+
+    self.read_color_config(&mut seq)?;
+
+    seq.film_grain_params_present = self.read_bit()?;
+
+    Ok(seq)
+  }
+
+  fn read_sequence_header(
+    &mut self, seq: &mut SequenceHeader,
+  ) -> io::Result<()> {
+    seq.frame_width_bits = self.read::<u32>(4)? + 1;
+    seq.frame_height_bits = self.read::<u32>(4)? + 1;
+    seq.max_frame_width = self.read::<u32>(seq.frame_width_bits)? + 1;
+    seq.max_frame_height = self.read::<u32>(seq.frame_height_bits)? + 1;
+
+    seq.frame_id_numbers_present_flag =
+      !seq.reduced_still_picture_hdr && self.read_bit()?;
+    if seq.frame_id_numbers_present_flag {
+      let delta_frame_id_length_minus_2: u32 = self.read(4)?;
+      let additional_frame_id_length_minus_1: u32 = self.read(3)?;
+      seq.delta_frame_id_length = delta_frame_id_length_minus_2 + 2;
+      seq.frame_id_length =
+        additional_frame_id_length_minus_1 + delta_frame_id_length_minus_2 + 3;
+    }
+
+    seq.use_128x128_superblock = self.read_bit()?;
+    self.skip(1)?; // enable_filter_intra
+    self.skip(1)?; // enable_intra_edge_filter
+
+    if seq.reduced_still_picture_hdr {
+      seq.force_screen_content_tools = SELECT;
+      seq.force_integer_mv = SELECT;
+    } else {
+      self.skip(1)?; // enable_interintra_compound
+      self.skip(1)?; // enable_masked_compound
+      self.skip(1)?; // enable_warped_motion
+      self.skip(1)?; // enable_dual_filter
+      seq.enable_order_hint = self.read_bit()?;
+
+      if seq.enable_order_hint {
+        self.skip(1)?; // enable_jnt_comp
+        seq.enable_ref_frame_mvs = self.read_bit()?;
+      }
+
+      seq.force_screen_content_tools =
+        if self.read_bit()? { SELECT } else { self.read(1)? };
+      seq.force_integer_mv = if seq.force_screen_content_tools > 0 {
+        if self.read_bit()? {
+          SELECT
+        } else {
+          self.read(1)?
+        }
+      } else {
+        SELECT
+      };
+      if seq.enable_order_hint {
+        seq.order_hint_bits = self.read::<u32>(3)? + 1;
+      }
+    }
+
+    seq.enable_superres = self.read_bit()?;
+    self.skip(1)?; // enable_cdef
+    self.skip(1)?; // enable_restoration
+
+    Ok(())
+  }
+
+  // <https://aomediacodec.github.io/av1-spec/#color-config-syntax>
+  fn read_color_config(&mut self, seq: &mut SequenceHeader) -> io::Result<()> {
+    let high_bitdepth = self.read_bit()?;
+    seq.bit_depth = if seq.profile == 2 && high_bitdepth {
+      if self.read_bit()? {
+        12
+      } else {
+        10
+      }
+    } else if high_bitdepth {
+      10
+    } else {
+      8
+    };
+
+    let monochrome = seq.profile != 1 && self.read_bit()?; // mono_chrome
+
+    // color_description_present_flag
+    if self.read_bit()? {
+      seq.color_primaries = self.read(8)?;
+      seq.transfer_characteristics = self.read(8)?;
+      seq.matrix_coefficients = self.read(8)?;
+    } else {
+      // Unspecified
+      seq.color_primaries = 2;
+      seq.transfer_characteristics = 2;
+      seq.matrix_coefficients = 2;
+    }
+    let srgb_triple = seq.color_primaries == 1
+      && seq.transfer_characteristics == 13
+      && seq.matrix_coefficients == 0;
+
+    if monochrome {
+      seq.full_range = self.read_bit()?; // color_range
+      seq.chroma_sampling = ChromaSampling::Cs400;
+      return Ok(());
+    } else if srgb_triple {
+      seq.full_range = true;
+      seq.chroma_sampling = ChromaSampling::Cs444;
+    } else {
+      seq.full_range = self.read_bit()?; // color_range
+      seq.chroma_sampling = if seq.profile == 0 {
+        ChromaSampling::Cs420
+      } else if seq.profile == 1 {
+        ChromaSampling::Cs444
+      } else if seq.bit_depth == 12 {
+        let subsampling_x = self.read_bit()?;
+        let subsampling_y = subsampling_x && self.read_bit()?;
+        match (subsampling_x, subsampling_y) {
+          (true, true) => ChromaSampling::Cs420,
+          (true, false) => ChromaSampling::Cs422,
+          _ => ChromaSampling::Cs444,
+        }
+      } else {
+        ChromaSampling::Cs422
+      };
+      if seq.chroma_sampling == ChromaSampling::Cs420 {
+        self.skip(2)?; // chroma_sample_position
+      }
+    }
+    self.skip(1)?; // separate_uv_delta_q
+
+    Ok(())
+  }
+
+  fn read_metadata_obu(&mut self) -> io::Result<Metadata> {
+    // uleb128() - metadata_type
+    let metadata = match self.read_uleb128()? {
+      1 => Metadata::ContentLight(ContentLight {
+        max_content_light_level: self.read(16)?,
+        max_frame_average_light_level: self.read(16)?,
+      }),
+      2 => {
+        let mut primaries = [ChromaticityPoint { x: 0, y: 0 }; 3];
+        for primary in primaries.iter_mut() {
+          primary.x = self.read(16)?;
+          primary.y = self.read(16)?;
+        }
+        let white_point =
+          ChromaticityPoint { x: self.read(16)?, y: self.read(16)? };
+
+        Metadata::MasteringDisplay(MasteringDisplay {
+          primaries,
+          white_point,
+          max_luminance: self.read(32)?,
+          min_luminance: self.read(32)?,
+        })
+      }
+      4 => {
+        let country_code: u8 = self.read(8)?;
+        let country_code_extension_byte =
+          if country_code == 0xFF { self.read(8)? } else { 0 };
+
+        let mut data = Vec::new();
+        self.aligned_reader().read_to_end(&mut data)?;
+        // trailing bits
+        while data.last() == Some(&0) {
+          data.pop();
+        }
+        if data.pop() != Some(0x80) {
+          return Err(invalid_data("invalid trailing bits in T.35 metadata"));
+        }
+
+        Metadata::T35(T35 {
+          country_code,
+          country_code_extension_byte,
+          data: data.into_boxed_slice(),
+        })
+      }
+      metadata_type => Metadata::Other(metadata_type),
+    };
+
+    Ok(metadata)
+  }
+
+  fn read_frame_header_obu(
+    &mut self, seq: &SequenceHeader, refs: &RefFrames, obu: &ObuHeader,
+  ) -> io::Result<FrameHeader> {
+    let mut fh = FrameHeader {
+      show_existing_frame: false,
+      frame_type: FrameType::KEY,
+      show_frame: true,
+      showable_frame: false,
+      error_resilient_mode: true,
+      frame_size_override_flag: false,
+      order_hint: None,
+      refresh_frame_flags: ALL_REF_FRAMES_MASK,
+      buffer_removal_time: None,
+      upscaled_width: 0,
+      frame_width: 0,
+      frame_height: 0,
+      render_width: 0,
+      render_height: 0,
+      tile_info: None,
+      base_q_idx: None,
+    };
+
+    if !seq.reduced_still_picture_hdr {
+      fh.show_existing_frame = self.read_bit()?;
+
+      if fh.show_existing_frame {
+        let frame_to_show_map_idx: u8 = self.read(REF_FRAMES_LOG2)?;
+        if seq.decoder_model_info_present_flag && !seq.equal_picture_interval {
+          self.read_temporal_point_info(seq)?;
+        }
+        if seq.frame_id_numbers_present_flag {
+          self.skip(seq.frame_id_length)?; // display_frame_id
+        }
+
+        let frame = refs[frame_to_show_map_idx as usize]
+          .ok_or_else(|| invalid_data("shown reference slot is empty"))?;
+        fh.frame_type = frame.frame_type;
+        fh.upscaled_width = frame.upscaled_width;
+        fh.frame_width = frame.upscaled_width;
+        fh.frame_height = frame.frame_height;
+        fh.render_width = frame.render_width;
+        fh.render_height = frame.render_height;
+        // Showing a key frame again resets the other slots to it.
+        fh.refresh_frame_flags = if fh.frame_type == FrameType::KEY {
+          ALL_REF_FRAMES_MASK
+        } else {
+          0
+        };
+        return Ok(fh);
+      }
+
+      fh.frame_type = match self.read::<u32>(2)? {
+        0 => FrameType::KEY,
+        1 => FrameType::INTER,
+        2 => FrameType::INTRA_ONLY,
+        _ => FrameType::SWITCH,
+      };
+      fh.show_frame = self.read_bit()?; // show frame
+
+      if fh.show_frame
+        && seq.decoder_model_info_present_flag
+        && !seq.equal_picture_interval
+      {
+        self.read_temporal_point_info(seq)?;
+      }
+      fh.showable_frame = if fh.show_frame {
+        fh.frame_type != FrameType::KEY
+      } else {
+        self.read_bit()?
+      };
+
+      fh.error_resilient_mode = fh.frame_type == FrameType::SWITCH
+        || (fh.frame_type == FrameType::KEY && fh.show_frame)
+        || self.read_bit()?; // error resilient
+    }
+
+    let frame_is_intra = fh.frame_type.all_intra();
+    let disable_cdf_update = self.read_bit()?;
+
+    let allow_screen_content_tools =
+      if seq.force_screen_content_tools == SELECT {
+        self.read_bit()?
+      } else {
+        seq.force_screen_content_tools != 0
+      };
+
+    let force_integer_mv = if allow_screen_content_tools {
+      if seq.force_integer_mv == SELECT {
+        self.read_bit()?
+      } else {
+        seq.force_integer_mv != 0
+      }
+    } else {
+      false
+    };
+
+    if seq.frame_id_numbers_present_flag {
+      self.skip(seq.frame_id_length)?; // current_frame_id
+    }
+
+    fh.frame_size_override_flag = fh.frame_type == FrameType::SWITCH
+      || (!seq.reduced_still_picture_hdr && self.read_bit()?); // frame size overhead flag
+
+    if seq.enable_order_hint {
+      fh.order_hint = Some(self.read(seq.order_hint_bits)?);
+    }
+
+    if !fh.error_resilient_mode && !frame_is_intra {
+      self.skip(3)?; // primary_ref_frame
+    }
+
+    if seq.decoder_model_info_present_flag && self.read_bit()? {
+      // buffer_removal_time_present_flag
+      for (op, &idc) in seq.operating_point_idc.iter().enumerate() {
+        if seq.decoder_model_present_for_this_op[op] {
+          let in_temporal_layer = (idc >> obu.temporal_id) & 1 != 0;
+          let in_spatial_layer = (idc >> (obu.spatial_id + 8)) & 1 != 0;
+          if idc == 0 || (in_temporal_layer && in_spatial_layer) {
+            let buffer_removal_time =
+              self.read::<u32>(seq.buffer_removal_time_length)?;
+            fh.buffer_removal_time =
+              fh.buffer_removal_time.or(Some(buffer_removal_time));
+          }
+        }
+      }
+    }
+
+    if fh.frame_type == FrameType::SWITCH
+      || (fh.frame_type == FrameType::KEY && fh.show_frame)
+    {
+      fh.refresh_frame_flags = ALL_REF_FRAMES_MASK;
+    } else {
+      fh.refresh_frame_flags = self.read(REF_FRAMES as u32)?;
+    }
+
+    if (!frame_is_intra || fh.refresh_frame_flags != ALL_REF_FRAMES_MASK)
+      && fh.error_resilient_mode
+      && seq.enable_order_hint
+    {
+      for _ in 0..REF_FRAMES {
+        self.skip(seq.order_hint_bits)?; // ref_order_hint
+      }
+    }
+
+    // if KEY or INTRA_ONLY frame
+    if frame_is_intra {
+      self.read_frame_size(seq, &mut fh)?;
+      self.read_render_size(&mut fh)?;
+      if allow_screen_content_tools && fh.upscaled_width == fh.frame_width {
+        self.skip(1)?; // allow_intrabc
+      }
+    } else {
+      if seq.enable_order_hint && self.read_bit()? {
+        // The slots would have to be derived from the order hints.
+        return Err(unsupported("frame_refs_short_signaling"));
+      }
+
+      let mut ref_frame_idx = [0; INTER_REFS_PER_FRAME];
+      for idx in ref_frame_idx.iter_mut() {
+        *idx = self.read::<u8>(REF_FRAMES_LOG2)? as usize;
+        if seq.frame_id_numbers_present_flag {
+          self.skip(seq.delta_frame_id_length)?; // delta_frame_id_minus_1
+        }
+      }
+
+      if !fh.error_resilient_mode && fh.frame_size_override_flag {
+        self.read_frame_size_with_refs(seq, refs, &ref_frame_idx, &mut fh)?;
+      } else {
+        self.read_frame_size(seq, &mut fh)?;
+        self.read_render_size(&mut fh)?;
+      }
+
+      if !force_integer_mv {
+        self.skip(1)?; // allow_high_precision_mv
+      }
+
+      if !self.read_bit()? {
+        // is_filter_switchable
+        self.skip(2)?; // interpolation_filter
+      }
+      self.skip(1)?; // is_motion_mode_switchable
+
+      if !fh.error_resilient_mode && seq.enable_ref_frame_mvs {
+        self.skip(1)?; // use_ref_frame_mvs
+      }
+    }
+
+    if !seq.reduced_still_picture_hdr && !disable_cdf_update {
+      self.skip(1)?; // disable_frame_end_update_cdf
+    }
+
+    fh.tile_info = Some(self.read_tile_info(seq, &fh)?);
+
+    // quantization
+    fh.base_q_idx = Some(self.read(8)?); // base_q_idx
+
+    Ok(fh)
+  }
+
+  // <https://aomediacodec.github.io/av1-spec/#general-tile-group-obu-syntax>
+  fn read_tile_start_and_end(
+    &mut self, tile_info: &TileInfo,
+  ) -> io::Result<(u32, u32)> {
+    let num_tiles = tile_info.cols * tile_info.rows;
+    // tile_start_and_end_present_flag
+    if num_tiles > 1 && self.read_bit()? {
+      let tile_bits = tile_info.cols_log2 + tile_info.rows_log2;
+      Ok((self.read(tile_bits)?, self.read(tile_bits)?))
+    } else {
+      Ok((0, num_tiles - 1))
+    }
+  }
+
+  fn read_temporal_point_info(
+    &mut self, seq: &SequenceHeader,
+  ) -> io::Result<()> {
+    self.skip(seq.frame_presentation_time_length) // frame_presentation_time
+  }
+
+  fn read_frame_size(
+    &mut self, seq: &SequenceHeader, fh: &mut FrameHeader,
+  ) -> io::Result<()> {
+    if fh.frame_size_override_flag {
+      fh.frame_width = self.read::<u32>(seq.frame_width_bits)? + 1;
+      fh.frame_height = self.read::<u32>(seq.frame_height_bits)? + 1;
+    } else {
+      fh.frame_width = seq.max_frame_width;
+      fh.frame_height = seq.max_frame_height;
+    }
+
+    self.read_superres_params(seq, fh)
+  }
+
+  fn read_superres_params(
+    &mut self, seq: &SequenceHeader, fh: &mut FrameHeader,
+  ) -> io::Result<()> {
+    // use_superres
+    let denom = if seq.enable_superres && self.read_bit()? {
+      self.read::<u32>(SUPERRES_DENOM_BITS)? + SUPERRES_DENOM_MIN
+    } else {
+      SUPERRES_NUM
+    };
+
+    fh.upscaled_width = fh.frame_width;
+    fh.frame_width = (fh.upscaled_width * SUPERRES_NUM + denom / 2) / denom;
+
+    Ok(())
+  }
+
+  fn read_render_size(&mut self, fh: &mut FrameHeader) -> io::Result<()> {
+    // render_and_frame_size_different
+    if self.read_bit()? {
+      fh.render_width = self.read::<u32>(16)? + 1;
+      fh.render_height = self.read::<u32>(16)? + 1;
+    } else {
+      fh.render_width = fh.upscaled_width;
+      fh.render_height = fh.frame_height;
+    }
+
+    Ok(())
+  }
+
+  fn read_frame_size_with_refs(
+    &mut self, seq: &SequenceHeader, refs: &RefFrames,
+    ref_frame_idx: &[usize; INTER_REFS_PER_FRAME], fh: &mut FrameHeader,
+  ) -> io::Result<()> {
+    for &idx in ref_frame_idx {
+      // found_ref
+      if self.read_bit()? {
+        let frame =
+          refs[idx].ok_or_else(|| invalid_data("referenced slot is empty"))?;
+        fh.frame_width = frame.upscaled_width;
+        fh.frame_height = frame.frame_height;
+        fh.render_width = frame.render_width;
+        fh.render_height = frame.render_height;
+        return self.read_superres_params(seq, fh);
+      }
+    }
+
+    self.read_frame_size(seq, fh)?;
+    self.read_render_size(fh)
+  }
+
+  // <https://aomediacodec.github.io/av1-spec/#tile-info-syntax>
+  fn read_tile_info(
+    &mut self, seq: &SequenceHeader, fh: &FrameHeader,
+  ) -> io::Result<TileInfo> {
+    let mi_cols = 2 * ((fh.frame_width + 7) >> 3);
+    let mi_rows = 2 * ((fh.frame_height + 7) >> 3);
+    let (sb_cols, sb_rows, sb_size_log2) = if seq.use_128x128_superblock {
+      ((mi_cols + 31) >> 5, (mi_rows + 31) >> 5, 7)
+    } else {
+      ((mi_cols + 15) >> 4, (mi_rows + 15) >> 4, 6)
+    };
+
+    let max_tile_width_sb = MAX_TILE_WIDTH >> sb_size_log2;
+    let max_tile_area_sb = MAX_TILE_AREA >> (2 * sb_size_log2);
+    let min_log2_tile_cols = tile_log2(max_tile_width_sb, sb_cols);
+    let max_log2_tile_cols = tile_log2(1, sb_cols.min(MAX_TILE_COLS));
+    let max_log2_tile_rows = tile_log2(1, sb_rows.min(MAX_TILE_ROWS));
+    let min_log2_tiles =
+      min_log2_tile_cols.max(tile_log2(max_tile_area_sb, sb_rows * sb_cols));
+
+    let mut ti = TileInfo { cols: 0, rows: 0, cols_log2: 0, rows_log2: 0 };
+
+    if self.read_bit()? {
+      // uniform_tile_spacing_flag
+      ti.cols_log2 = min_log2_tile_cols;
+      while ti.cols_log2 < max_log2_tile_cols && self.read_bit()? {
+        ti.cols_log2 += 1; // increment_tile_cols_log2
+      }
+      let tile_width_sb = (sb_cols + (1 << ti.cols_log2) - 1) >> ti.cols_log2;
+      ti.cols = sb_cols.div_ceil(tile_width_sb);
+
+      ti.rows_log2 = min_log2_tiles.saturating_sub(ti.cols_log2);
+      while ti.rows_log2 < max_log2_tile_rows && self.read_bit()? {
+        ti.rows_log2 += 1; // increment_tile_rows_log2
+      }
+      let tile_height_sb = (sb_rows + (1 << ti.rows_log2) - 1) >> ti.rows_log2;
+      ti.rows = sb_rows.div_ceil(tile_height_sb);
+    } else {
+      let mut sofar = 0;
+      let mut widest_tile_sb = 0;
+      while sofar < sb_cols {
+        let max = (sb_cols - sofar).min(max_tile_width_sb);
+        let this_sb_width = self.read_quniform(max)? + 1; // width_in_sbs_minus_1
+        sofar += this_sb_width;
+        widest_tile_sb = widest_tile_sb.max(this_sb_width);
+        ti.cols += 1;
+      }
+      ti.cols_log2 = tile_log2(1, ti.cols);
+
+      let max_tile_area_sb = if min_log2_tiles > 0 {
+        (sb_rows * sb_cols) >> (min_log2_tiles + 1)
+      } else {
+        sb_rows * sb_cols
+      };
+      let max_tile_height_sb = (max_tile_area_sb / widest_tile_sb).max(1);
+
+      sofar = 0;
+      while sofar < sb_rows {
+        let max = (sb_rows - sofar).min(max_tile_height_sb);
+        sofar += self.read_quniform(max)? + 1; // height_in_sbs_minus_1
+        ti.rows += 1;
+      }
+      ti.rows_log2 = tile_log2(1, ti.rows);
+    }
+
+    let tiles_log2 = ti.cols_log2 + ti.rows_log2;
+    if tiles_log2 > 0 {
+      self.skip(tiles_log2)?; // context_update_tile_id
+      self.skip(2)?; // tile_size_bytes_minus_1
+    }
+
+    Ok(ti)
+  }
+
+  // ns(n) in the specification
+  fn read_quniform(&mut self, n: u32) -> io::Result<u32> {
+    let w = u32::BITS - n.leading_zeros();
+    let m = (1 << w) - n;
+    let v: u32 = if w > 1 { self.read(w - 1)? } else { 0 };
+    if v < m {
+      Ok(v)
+    } else {
+      Ok((v << 1) - m + self.read::<u32>(1)?)
+    }
+  }
+
+  fn read_uvlc(&mut self) -> io::Result<u32> {
+    let mut leading_zeros = 0;
+    while !self.read_bit()? {
+      leading_zeros += 1;
+    }
+    if leading_zeros >= 32 {
+      return Ok(u32::MAX);
+    }
+    let value: u32 =
+      if leading_zeros > 0 { self.read(leading_zeros)? } else { 0 };
+    Ok(value + ((1 << leading_zeros) - 1))
+  }
+}
@@ -0,0 +1,376 @@
+// Copyright (c) 2017-2023, The rav1e contributors. All rights reserved
+//
+// This source code is subject to the terms of the BSD 2 Clause License and
+// the Alliance for Open Media Patent License 1.0. If the BSD 2 Clause License
+// was not distributed with this source code in the LICENSE file, you can
+// obtain it at www.aomedia.org/license/software. If the Alliance for Open
+// Media Patent License 1.0 was not distributed with this source code in the
+// PATENTS file, you can obtain it at www.aomedia.org/license/patent.
+
+mod header;
+
+use self::header::*;
+use crate::error::*;
+use bitstream_io::{BigEndian, BitReader};
+use rav1e::prelude::*;
+
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+/// A frame header, along with the OBUs of the frame.
+struct FrameInfo {
+  packet: usize,
+  pts: u64,
+  temporal_id: u32,
+  spatial_id: u32,
+  header: FrameHeader,
+  /// Size of the frame header and tile group OBUs.
+  size: usize,
+  tile_groups: usize,
+  metadata: Vec<Metadata>,
+}
+
+#[derive(Default)]
+struct Stream {
+  sequence_header: Option<SequenceHeader>,
+  refs: RefFrames,
+  frames: Vec<FrameInfo>,
+  /// Metadata to attach to the next frame.
+  metadata: Vec<Metadata>,
+  /// Whether the tiles of the last frame header are yet to come, any other
+  /// frame header being a copy of it.
+  awaiting_tile_groups: bool,
+  /// Payload of the last frame OBU of a frame with several tiles, whose tile
+  /// group may leave some of them to the tile group OBUs that follow.
+  frame_obu: Option<Vec<u8>>,
+}
+
+impl Stream {
+  fn read_packet(
+    &mut self, index: usize, packet: &ivf::Packet,
+  ) -> io::Result<()> {
+    let first_frame = self.frames.len();
+    let mut data = &packet.data[..];
+    self.frame_obu = None;
+
+    while !data.is_empty() {
+      let len = data.len();
+      let (obu, obu_size) = {
+        let mut br = BitReader::endian(&mut data, BigEndian);
+        let obu = br.read_obu_header()?;
+        let obu_size =
+          if obu.has_size_field { Some(br.read_uleb128()?) } else { None };
+        (obu, obu_size)
+      };
+      let obu_size = obu_size.map_or(data.len(), |size| size as usize);
+      if obu_size > data.len() {
+        return Err(invalid_data("OBU larger than its packet"));
+      }
+      let (payload, rest) = data.split_at(obu_size);
+      data = rest;
+      let size = len - data.len();
+
+      let mut br = BitReader::endian(payload, BigEndian);
+      match obu.obu_type {
+        Some(ObuType::OBU_SEQUENCE_HEADER) => {
+          self.sequence_header = Some(br.read_sequence_header_obu()?);
+        }
+        Some(ObuType::OBU_METADATA) => {
+          self.metadata.push(br.read_metadata_obu()?);
+        }
+        Some(ObuType::OBU_FRAME_HEADER)
+          if self.awaiting_tile_groups
+            || self.is_frame_header_copy(payload) =>
+        {
+          if let Some(frame) = self.frames.last_mut() {
+            frame.size += size;
+          }
+        }
+        Some(ObuType::OBU_REDUNDANT_FRAME_HEADER) => {
+          if let Some(frame) = self.frames[first_frame..].last_mut() {
+            frame.size += size;
+          }
+        }
+        Some(ObuType::OBU_FRAME_HEADER | ObuType::OBU_FRAME) => {
+          let seq = self.sequence_header.as_ref().ok_or_else(|| {
+            invalid_data("frame header before any sequence header")
+          })?;
+          let header = br.read_frame_header_obu(seq, &self.refs, &obu)?;
+          for (i, slot) in self.refs.iter_mut().enumerate() {
+            if (header.refresh_frame_flags >> i) & 1 != 0 {
+              *slot = Some(header.ref_frame());
+            }
+          }
+
+          // The tiles missing from the tile group of a frame OBU come in the
+          // tile group OBUs that follow, which are counted as they come.
+          let is_frame_obu = obu.obu_type == Some(ObuType::OBU_FRAME);
+          self.awaiting_tile_groups =
+            !header.show_existing_frame && !is_frame_obu;
+          self.frame_obu = header
+            .tile_info
+            .filter(|ti| is_frame_obu && ti.cols * ti.rows > 1)
+            .map(|_| payload.to_vec());
+          self.frames.push(FrameInfo {
+            packet: index,
+            pts: packet.pts,
+            temporal_id: obu.temporal_id,
+            spatial_id: obu.spatial_id,
+            header,
+            size,
+            tile_groups: usize::from(is_frame_obu),
+            metadata: std::mem::take(&mut self.metadata),
+          });
+        }
+        Some(ObuType::OBU_TILE_GROUP) => {
+          let frame = self.frames[first_frame..]
+            .last_mut()
+            .filter(|frame| !frame.header.show_existing_frame)
+            .ok_or_else(|| {
+              invalid_data("tile group without a frame header")
+            })?;
+          if let Some(tile_info) = frame.header.tile_info {
+            let (_, tg_end) = br.read_tile_start_and_end(&tile_info)?;
+            self.awaiting_tile_groups =
+              tg_end + 1 < tile_info.cols * tile_info.rows;
+            if !self.awaiting_tile_groups {
+              self.frame_obu = None;
+            }
+          }
+          frame.size += size;
+          frame.tile_groups += 1;
+        }
+        _ => {}
+      }
+    }
+
+    // Metadata following the last frame of a temporal unit still belongs to
+    // it.
+    if let Some(frame) = self.frames[first_frame..].last_mut() {
+      frame.metadata.append(&mut self.metadata);
+    }
+
+    Ok(())
+  }
+
+  /// Whether a frame header OBU repeats the frame header of the last frame
+  /// OBU, its trailing bits aside, the end of that frame header being unknown.
+  fn is_frame_header_copy(&self, payload: &[u8]) -> bool {
+    match (&self.frame_obu, payload.split_last()) {
+      (Some(frame_obu), Some((_, header))) => {
+        !header.is_empty() && frame_obu.starts_with(header)
+      }
+      _ => false,
+    }
+  }
+
+  fn write_json(
+    &self, header: &ivf::Header, out: &mut dyn Write,
+  ) -> io::Result<()> {
+    writeln!(out, "{{")?;
+    writeln!(out, "  \"width\": {},", header.w)?;
+    writeln!(out, "  \"height\": {},", header.h)?;
+    writeln!(
+      out,
+      "  \"time_base\": {{\"num\": {}, \"den\": {}}},",
+      header.timebase_num, header.timebase_den
+    )?;
+    write!(out, "  \"sequence_header\": ")?;
+    match &self.sequence_header {
+      Some(seq) => write_sequence_header(out, seq)?,
+      None => write!(out, "null")?,
+    }
+    writeln!(out, ",")?;
+    writeln!(out, "  \"frames\": [")?;
+    for (i, frame) in self.frames.iter().enumerate() {
+      write!(out, "    ")?;
+      write_frame(out, frame)?;
+      writeln!(out, "{}", if i + 1 < self.frames.len() { "," } else { "" })?;
+    }
+    writeln!(out, "  ]")?;
+    writeln!(out, "}}")
+  }
+}
+
+fn write_option<T: std::fmt::Display>(
+  out: &mut dyn Write, value: Option<T>,
+) -> io::Result<()> {
+  match value {
+    Some(value) => write!(out, "{value}"),
+    None => write!(out, "null"),
+  }
+}
+
+fn write_sequence_header(
+  out: &mut dyn Write, seq: &SequenceHeader,
+) -> io::Result<()> {
+  let chroma_sampling = match seq.chroma_sampling {
+    ChromaSampling::Cs420 => "420",
+    ChromaSampling::Cs422 => "422",
+    ChromaSampling::Cs444 => "444",
+    ChromaSampling::Cs400 => "400",
+  };
+  write!(
+    out,
+    "{{\"seq_profile\": {}, \"still_picture\": {}, \
+     \"reduced_still_picture_header\": {}, \"seq_level_idx\": {}, \
+     \"seq_tier\": {}, \"operating_points\": {}, \
+     \"max_frame_width\": {}, \"max_frame_height\": {}, \
+     \"bit_depth\": {}, \"chroma_sampling\": \"{}\", \
+     \"color_primaries\": {}, \"transfer_characteristics\": {}, \
+     \"matrix_coefficients\": {}, \"full_range\": {}, \
+     \"film_grain_params_present\": {}}}",
+    seq.profile,
+    seq.still_picture,
+    seq.reduced_still_picture_hdr,
+    seq.level_idx[0],
+    seq.tier[0],
+    seq.operating_point_idc.len(),
+    seq.max_frame_width,
+    seq.max_frame_height,
+    seq.bit_depth,
+    chroma_sampling,
+    seq.color_primaries,
+    seq.transfer_characteristics,
+    seq.matrix_coefficients,
+    seq.full_range,
+    seq.film_grain_params_present,
+  )
+}
+
+fn write_frame(out: &mut dyn Write, frame: &FrameInfo) -> io::Result<()> {
+  let fh = &frame.header;
+  write!(
+    out,
+    "{{\"packet\": {}, \"pts\": {}, \"temporal_id\": {}, \
+     \"spatial_id\": {}, \"frame_type\": \"{:?}\", \"show_frame\": {}, \
+     \"showable_frame\": {}, \"show_existing_frame\": {}, \"size\": {}, \
+     \"width\": {}, \"height\": {}, \"render_width\": {}, \
+     \"render_height\": {}, \"order_hint\": ",
+    frame.packet,
+    frame.pts,
+    frame.temporal_id,
+    frame.spatial_id,
+    fh.frame_type,
+    fh.show_frame,
+    fh.showable_frame,
+    fh.show_existing_frame,
+    frame.size,
+    fh.upscaled_width,
+    fh.frame_height,
+    fh.render_width,
+    fh.render_height,
+  )?;
+  write_option(out, fh.order_hint)?;
+  write!(out, ", \"qindex\": ")?;
+  write_option(out, fh.base_q_idx)?;
+  write!(out, ", \"refresh_frame_flags\": {}", fh.refresh_frame_flags)?;
+  write!(out, ", \"buffer_removal_time\": ")?;
+  write_option(out, fh.buffer_removal_time)?;
+  write!(out, ", \"tile_cols\": ")?;
+  write_option(out, fh.tile_info.map(|ti| ti.cols))?;
+  write!(out, ", \"tile_rows\": ")?;
+  write_option(out, fh.tile_info.map(|ti| ti.rows))?;
+  write!(out, ", \"tile_groups\": {}, \"metadata\": [", frame.tile_groups)?;
+  for (i, metadata) in frame.metadata.iter().enumerate() {
+    if i > 0 {
+      write!(out, ", ")?;
+    }
+    write_metadata(out, metadata)?;
+  }
+  write!(out, "]}}")
+}
+
+fn write_metadata(out: &mut dyn Write, metadata: &Metadata) -> io::Result<()> {
+  match metadata {
+    Metadata::ContentLight(cll) => write!(
+      out,
+      "{{\"type\": \"HDR_CLL\", \"max_content_light_level\": {}, \
+       \"max_frame_average_light_level\": {}}}",
+      cll.max_content_light_level, cll.max_frame_average_light_level
+    ),
+    Metadata::MasteringDisplay(mdcv) => {
+      let [r, g, b] = mdcv.primaries;
+      let w = mdcv.white_point;
+      write!(
+        out,
+        "{{\"type\": \"HDR_MDCV\", \
+         \"primaries\": [[{}, {}], [{}, {}], [{}, {}]], \
+         \"white_point\": [{}, {}], \"max_luminance\": {}, \
+         \"min_luminance\": {}}}",
+        r.x,
+        r.y,
+        g.x,
+        g.y,
+        b.x,
+        b.y,
+        w.x,
+        w.y,
+        mdcv.max_luminance,
+        mdcv.min_luminance
+      )
+    }
+    Metadata::T35(t35) => {
+      write!(
+        out,
+        "{{\"type\": \"ITUT_T35\", \"country_code\": {}, \
+         \"country_code_extension_byte\": {}, \"data\": \"",
+        t35.country_code, t35.country_code_extension_byte
+      )?;
+      for byte in t35.data.iter() {
+        write!(out, "{byte:02x}")?;
+      }
+      write!(out, "\"}}")
+    }
+    Metadata::Other(metadata_type) => {
+      let name = match metadata_type {
+        3 => "SCALABILITY",
+        5 => "TIMECODE",
+        _ => "UNKNOWN",
+      };
+      write!(
+        out,
+        "{{\"type\": \"{name}\", \"metadata_type\": {metadata_type}}}"
+      )
+    }
+  }
+}
+
+/// Reads the OBUs of an IVF file, `-` being the standard input, and prints
+/// their headers to the standard output as JSON.
+pub fn analyze_ivf(path: &Path) -> Result<(), CliError> {
+  let input = match path.to_str() {
+    Some("-") => Box::new(io::stdin()) as Box<dyn Read>,
+    _ => Box::new(
+      File::open(path).map_err(|e| e.context("Cannot open input file"))?,
+    ) as Box<dyn Read>,
+  };
+  let mut input = BufReader::new(input);
+
+  let header = ivf::read_header(&mut input)
+    .map_err(|e| e.context("Cannot read the IVF header"))?;
+
+  let mut stream = Stream::default();
+  for index in 0.. {
+    let at_end = input
+      .fill_buf()
+      .map_err(|e| e.context("Cannot read the input"))?
+      .is_empty();
+    if at_end {
+      break;
+    }
+    let packet = ivf::read_packet(&mut input)
+      .map_err(|e| e.context(&format!("Cannot read packet {index}")))?;
+    stream
+      .read_packet(index, &packet)
+      .map_err(|e| e.context(&format!("Cannot parse packet {index}")))?;
+  }
+
+  let mut out = BufWriter::new(io::stdout().lock());
+  stream
+    .write_json(&header, &mut out)
+    .and_then(|_| out.flush())
+    .map_err(|e| e.context("Cannot write the analysis"))
+}
@@ -7,11 +7,14 @@
 // Media Patent License 1.0 was not distributed with this source code in the
 // PATENTS file, you can obtain it at www.aomedia.org/license/patent.
 
+use crate::analyzer::analyze_ivf;
+use crate::decoder::{InputFormat, RawFormat, VideoDetails};
 use crate::error::*;
-use crate::muxer::{create_muxer, Muxer};
+use crate::muxer::{create_muxer, is_annex_b, Muxer};
 use crate::stats::MetricsEnabled;
 use clap::builder::styling::AnsiColor;
 use clap::builder::Styles;
+use clap::error::ErrorKind;
 use clap::{CommandFactory, Parser as Clap, Subcommand};
 use clap_complete::{generate, Shell};
 use rav1e::prelude::*;
@@ -36,15 +39,41 @@ pub mod built_info {
   version = get_version(),
   long_version = get_long_version(),
   about = "AV1 video encoder",
-  long_about = None
+  long_about = None,
+  subcommand_negates_reqs = true
 )]
 pub struct CliOptions {
-  /// Uncompressed YUV4MPEG2 video input
-  #[clap(value_parser, help_heading = "INPUT/OUTPUT")]
-  pub input: PathBuf,
-  /// Compressed AV1 in IVF video output
-  #[clap(long, short, value_parser, help_heading = "INPUT/OUTPUT")]
-  pub output: PathBuf,
+  /// Uncompressed YUV4MPEG2 video input, or raw frames (.yuv, .nv12, .p010)
+  #[clap(value_parser, required = true, help_heading = "INPUT/OUTPUT")]
+  pub input: Option<PathBuf>,
+  /// Input format: y4m, or headerless i420, i422, i444, nv12 or p010 frames.
+  /// [default: i420 for .yuv, nv12 for .nv12, p010 for .p010, y4m otherwise]
+  #[clap(long, value_parser, help_heading = "INPUT/OUTPUT")]
+  pub input_format: Option<InputFormat>,
+  /// Width of raw input frames
+  #[clap(long, value_parser, help_heading = "INPUT/OUTPUT")]
+  pub input_width: Option<usize>,
+  /// Height of raw input frames
+  #[clap(long, value_parser, help_heading = "INPUT/OUTPUT")]
+  pub input_height: Option<usize>,
+  /// Bit depth of raw input frames, samples of more than 8 bits being
+  /// 16-bit little endian [default: 10 for p010, 8 otherwise]
+  #[clap(long, value_parser, help_heading = "INPUT/OUTPUT")]
+  pub input_bit_depth: Option<usize>,
+  /// Frame rate of raw input frames, as <num>[/<den>] [default: 30]
+  #[clap(long, value_parser = parse_frame_rate, help_heading = "INPUT/OUTPUT")]
+  pub input_frame_rate: Option<Rational>,
+  /// Compressed AV1 video output, in IVF, WebM (.webm), Matroska (.mkv),
+  /// MP4 (.mp4), fragmented MP4 for CMAF (.cmfv), or as an OBU stream (.obu)
+  /// or an Annex B length-delimited stream (.annexb)
+  #[clap(
+    long,
+    short,
+    value_parser,
+    required = true,
+    help_heading = "INPUT/OUTPUT"
+  )]
+  pub output: Option<PathBuf>,
   /// Overwrite output file.
   #[clap(short = 'y', help_heading = "INPUT/OUTPUT")]
   pub overwrite: bool,
@@ -119,6 +148,43 @@ pub struct CliOptions {
   /// Bitrate (kbps)
   #[clap(long, short, value_parser, help_heading = "ENCODE SETTINGS")]
   pub bitrate: Option<i32>,
+  /// Constant quality target (0-255), smaller values are higher quality.
+  /// The quantizer adapts to the complexity of each frame
+  #[clap(
+    long,
+    value_parser,
+    conflicts_with = "bitrate",
+    help_heading = "ENCODE SETTINGS"
+  )]
+  pub crf: Option<u8>,
+  /// Quality target, either "psnr:<dB>" or "ssim:<value>" on the luma plane.
+  /// The quantizer of each group of pictures is searched for by coding a
+  /// few of its frames at several quantizers
+  #[clap(
+    long,
+    value_parser,
+    conflicts_with_all = ["bitrate", "crf"],
+    help_heading = "ENCODE SETTINGS"
+  )]
+  pub quality_target: Option<QualityTarget>,
+  /// Drop frames that would exceed the target bitrate.
+  /// Requires a target bitrate and low latency mode
+  #[clap(
+    long,
+    requires_all = ["bitrate", "low_latency"],
+    help_heading = "ENCODE SETTINGS"
+  )]
+  pub drop_frames: bool,
+  /// Peak bitrate of the decoder buffer model (kbps).
+  /// Requires a target bitrate or a constant quality target
+  #[clap(long, value_parser = clap::value_parser!(i32).range(1..), help_heading = "ENCODE SETTINGS")]
+  pub max_bitrate: Option<i32>,
+  /// Size of the decoder buffer model (kbit) [default: 1 second of max bitrate]
+  #[clap(long, value_parser = clap::value_parser!(i32).range(1..), requires = "max_bitrate", help_heading = "ENCODE SETTINGS")]
+  pub buffer_size: Option<i32>,
+  /// Signal the decoder buffer model and timing info in the sequence header
+  #[clap(long, requires = "max_bitrate", help_heading = "ENCODE SETTINGS")]
+  pub decoder_model_info: bool,
   /// Speed level (0 is best quality, 10 is fastest).
   /// Speeds 10 and 0 are extremes and are generally not recommended.
   #[clap(long, short, value_parser = clap::value_parser!(u8).range(0..=10), default_value_t = 6, help_heading = "ENCODE SETTINGS", long_help = build_speed_long_help())]
@@ -163,6 +229,15 @@ pub struct CliOptions {
   /// Has a significant speed-to-quality trade-off
   #[clap(long, help_heading = "ENCODE SETTINGS")]
   pub low_latency: bool,
+  /// Number of temporal layers (L1T1 to L1T3).
+  /// Requires low latency mode when larger than 1
+  #[clap(
+    long,
+    value_parser = clap::value_parser!(u8).range(1..=3),
+    default_value_t = 1,
+    help_heading = "ENCODE SETTINGS"
+  )]
+  pub temporal_layers: u8,
   /// Disables scene detection entirely.
   /// Has a significant speed-to-quality trade-off in full encodes.
   /// Useful for chunked encoding.
@@ -178,6 +253,25 @@ pub struct CliOptions {
   /// Still picture mode
   #[clap(long, help_heading = "ENCODE SETTINGS")]
   pub still_picture: bool,
+  /// Mathematically lossless coding, also selected by a quantizer of 0
+  #[clap(long, help_heading = "ENCODE SETTINGS")]
+  pub lossless: bool,
+  /// Code key frames at a reduced width and upscale them afterwards.
+  /// Either "none", "auto" or a denominator from 9 to 16, frames being
+  /// coded at 8/denominator of their width. Requires --keyint 1.
+  #[clap(long, value_parser, default_value_t = SuperresMode::None, help_heading = "ENCODE SETTINGS")]
+  pub superres: SuperresMode,
+  /// Screen content coding tools, such as palettes and intra block copy.
+  /// Suited to slides, desktop captures and terminal recordings.
+  /// Either "auto", to detect screen content frame by frame, "on" or "off".
+  #[clap(long, value_parser, default_value_t = ScreenContentMode::Auto, default_missing_value = "on", num_args = 0..=1, help_heading = "ENCODE SETTINGS")]
+  pub screen_content: ScreenContentMode,
+  /// Settings for a range of input frames, given as
+  /// "start-end:q=..,speed=..,bitrate_factor=..", any of the settings being
+  /// optional. Can be repeated for several non-overlapping ranges, in order.
+  /// A key frame is inserted at the start of each zone.
+  #[clap(long, value_parser, help_heading = "ENCODE SETTINGS")]
+  pub zones: Vec<Zone>,
   /// Uses grain synthesis to add photon noise to the resulting encode.
   /// Takes a strength value 0-64.
   #[clap(
@@ -221,8 +315,10 @@ pub struct CliOptions {
   #[clap(long, help_heading = "VIDEO METADATA")]
   pub content_light: Option<String>,
   /// AV1 level to target in the form <major>.<minor>, e.g. 3.1.
-  /// Specify "unconstrained" for no level constraints or "auto" to let
-  /// the encoder choose (default)
+  /// Specify "unconstrained" for no level constraints (default) or "auto" to
+  /// signal the lowest level the stream conforms to, without constraining the
+  /// rate control. The encoder only chooses a level other than unconstrained
+  /// when a bitrate or maximum bitrate is given
   #[clap(long, help_heading = "LEVEL")]
   pub level: Option<String>,
   /// Constant frame rate to set at the output (inferred from input when omitted)
@@ -322,9 +418,15 @@ pub enum Commands {
     #[clap(long, short, value_parser)]
     save_config: Option<PathBuf>,
     /// Load the encoder configuration from a toml file
-    #[clap(long, short, value_parser, conflicts_with = "save-config")]
+    #[clap(long, short, value_parser, conflicts_with = "save_config")]
     load_config: Option<PathBuf>,
   },
+  /// Analyze the OBUs of an IVF file and print them as JSON
+  Ivf {
+    /// AV1 video in IVF, or - for the standard input
+    #[clap(value_parser)]
+    input: PathBuf,
+  },
 }
 
 pub struct EncoderIO {
@@ -342,6 +444,9 @@ pub enum Verboseness {
 
 pub struct ParsedCliOptions {
   pub io: EncoderIO,
+  pub input_format: InputFormat,
+  /// Details of the frames of raw input, given on the command line.
+  pub raw_details: Option<VideoDetails>,
   pub enc: EncoderConfig,
   pub limit: usize,
   pub color_range_specified: bool,
@@ -440,9 +545,23 @@ pub fn parse_cli() -> Result<ParsedCliOptions, CliError> {
           }
         }
       }
+      Commands::Ivf { input } => {
+        analyze_ivf(input)?;
+        std::process::exit(0);
+      }
     }
   }
 
+  // Only the subcommands may go without them.
+  let (Some(input), Some(output)) = (&matches.input, &matches.output) else {
+    CliOptions::command()
+      .error(
+        ErrorKind::MissingRequiredArgument,
+        "the input and the --output are required",
+      )
+      .exit();
+  };
+
   let rec = match matches.reconstruction.as_ref() {
     Some(f) => Some(Box::new(
       File::create(f)
@@ -451,20 +570,20 @@ pub fn parse_cli() -> Result<ParsedCliOptions, CliError> {
     None => None,
   };
 
-  let os_input = &matches.input;
   let io = EncoderIO {
-    input: match os_input.to_str() {
+    input: match input.to_str() {
       Some("-") => Box::new(io::stdin()) as Box<dyn Read + Send>,
       _ => Box::new(
-        File::open(os_input)
-          .map_err(|e| e.context("Cannot open input file"))?,
+        File::open(input).map_err(|e| e.context("Cannot open input file"))?,
       ) as Box<dyn Read + Send>,
     },
-    output: create_muxer(&matches.output, matches.overwrite)?,
+    output: create_muxer(output, matches.overwrite)?,
     rec,
   };
 
-  let enc = enc.map_or_else(|| parse_config(&matches), Ok)?;
+  let mut enc = enc.map_or_else(|| parse_config(&matches), Ok)?;
+  // The OBUs carry their sizes in any other output.
+  enc.annex_b = is_annex_b(output);
 
   let verbose = if matches.quiet {
     Verboseness::Quiet
@@ -490,8 +609,17 @@ pub fn parse_cli() -> Result<ParsedCliOptions, CliError> {
   #[cfg(feature = "unstable")]
   let slots = matches.slots;
 
+  let input_format =
+    matches.input_format.unwrap_or_else(|| InputFormat::from_path(input));
+  let raw_details = match input_format {
+    InputFormat::Y4m => None,
+    InputFormat::Raw(format) => Some(parse_raw_details(&matches, format)?),
+  };
+
   Ok(ParsedCliOptions {
     io,
+    input_format,
+    raw_details,
     enc,
     limit,
     color_range_specified: matches.range.is_some(),
@@ -512,12 +640,54 @@ pub fn parse_cli() -> Result<ParsedCliOptions, CliError> {
   })
 }
 
+fn parse_frame_rate(s: &str) -> Result<Rational, String> {
+  let (num, den) = s.split_once('/').unwrap_or((s, "1"));
+  match (num.parse(), den.parse()) {
+    (Ok(num), Ok(den)) if num > 0 && den > 0 => Ok(Rational::new(num, den)),
+    _ => Err(format!("expected <num>[/<den>], got {s}")),
+  }
+}
+
+fn parse_raw_details(
+  matches: &CliOptions, format: RawFormat,
+) -> Result<VideoDetails, CliError> {
+  let (Some(width), Some(height)) =
+    (matches.input_width, matches.input_height)
+  else {
+    return Err(CliError::new(
+      "Raw input requires --input-width and --input-height",
+    ));
+  };
+  let bit_depth = matches.input_bit_depth.unwrap_or(match format {
+    RawFormat::P010 => 10,
+    _ => 8,
+  });
+  if !format.supports_bit_depth(bit_depth) {
+    return Err(CliError::new(&format!(
+      "Bit depth {bit_depth} is not supported by {format} input"
+    )));
+  }
+  let frame_rate = matches.input_frame_rate.unwrap_or(Rational::new(30, 1));
+
+  Ok(VideoDetails {
+    width,
+    height,
+    bit_depth,
+    chroma_sampling: format.chroma_sampling(),
+    time_base: Rational::new(frame_rate.den, frame_rate.num),
+    ..Default::default()
+  })
+}
+
 fn parse_config(matches: &CliOptions) -> Result<EncoderConfig, CliError> {
   let maybe_quantizer = matches.quantizer;
   let maybe_bitrate = matches.bitrate;
   let quantizer = maybe_quantizer.unwrap_or_else(|| {
-    if maybe_bitrate.is_some() {
-      // If a bitrate is specified, the quantizer is the maximum allowed (e.g.,
+    if maybe_bitrate.is_some()
+      || matches.crf.is_some()
+      || matches.quality_target.is_some()
+    {
+      // If a bitrate or quality target is specified, the quantizer is the maximum allowed (e.g.,
       //  the minimum quality allowed), which by default should be
       //  unconstrained.
       255
@@ -532,9 +702,7 @@ fn parse_config(matches: &CliOptions) -> Result<EncoderConfig, CliError> {
     panic!("A target bitrate must be specified when using passes");
   }
 
-  if quantizer == 0 {
-    unimplemented!("Lossless encoding not yet implemented");
-  } else if quantizer > 255 {
+  if quantizer > 255 {
     panic!("Quantizer must be between 0-255");
   }
 
@@ -556,6 +724,7 @@ fn parse_config(matches: &CliOptions) -> Result<EncoderConfig, CliError> {
   let mut cfg = EncoderConfig::with_speed_preset(speed);
 
   if let Some(level_str) = &matches.level {
+    cfg.auto_level = level_str == "auto";
     cfg.level_idx = match level_str.as_str() {
       "auto" => None,
       "unconstrained" => Some(31),
@@ -672,10 +841,27 @@ fn parse_config(matches: &CliOptions) -> Result<EncoderConfig, CliError> {
     });
 
   cfg.still_picture = matches.still_picture;
+  cfg.superres_mode = matches.superres;
+  cfg.screen_content = matches.screen_content;
+  cfg.zones = matches.zones.clone();
 
   cfg.quantizer = quantizer;
+  cfg.lossless = matches.lossless || quantizer == 0;
   cfg.min_quantizer = matches.min_quantizer.unwrap_or(0);
   cfg.bitrate = bitrate.checked_mul(1000).expect("Bitrate too high");
+  cfg.crf = matches.crf;
+  cfg.drop_frames = matches.drop_frames;
+  cfg.quality_target = matches.quality_target;
+  cfg.max_bitrate = matches
+    .max_bitrate
+    .map_or(0, |rate| rate.checked_mul(1000).expect("Max bitrate too high"));
+  cfg.buffer_size = matches
+    .buffer_size
+    .map_or(0, |size| size.checked_mul(1000).expect("Buffer size too high"));
+  if matches.decoder_model_info {
+    cfg.enable_timing_info = true;
+    cfg.enable_decoder_model_info = true;
+  }
   cfg.reservoir_frame_delay = matches.reservoir_frame_delay;
 
   if let Some(rdo_frames) = matches.rdo_lookahead_frames {
@@ -712,6 +898,7 @@ fn parse_config(matches: &CliOptions) -> Result<EncoderConfig, CliError> {
   }
 
   cfg.low_latency = matches.low_latency;
+  cfg.temporal_layers = matches.temporal_layers;
   // Disables scene_detection
   if matches.no_scene_detection {
     cfg.speed_settings.scene_detection_mode = SceneDetectionSpeed::None;
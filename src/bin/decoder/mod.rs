@@ -10,8 +10,19 @@
 
 use rav1e::prelude::*;
 
+use std::ffi::OsStr;
+use std::fmt;
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::error::CliError;
+
+pub mod raw;
 pub mod y4m;
 
+pub use self::raw::{RawDecoder, RawFormat};
+
 pub trait FrameBuilder<T: Pixel> {
   fn new_frame(&self) -> Frame<T>;
 }
@@ -57,3 +68,110 @@ impl Default for VideoDetails {
     }
   }
 }
+
+/// Formats of the input, selected with `--input-format` or from the
+/// extension of the input file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputFormat {
+  Y4m,
+  Raw(RawFormat),
+}
+
+impl InputFormat {
+  pub fn from_path<P: AsRef<Path>>(path: P) -> Self {
+    let ext =
+      path.as_ref().extension().and_then(OsStr::to_str).map(str::to_lowercase);
+
+    match ext.as_deref() {
+      Some("yuv") => InputFormat::Raw(RawFormat::I420),
+      Some("nv12") => InputFormat::Raw(RawFormat::Nv12),
+      Some("p010") => InputFormat::Raw(RawFormat::P010),
+      _ => InputFormat::Y4m,
+    }
+  }
+}
+
+impl fmt::Display for InputFormat {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      InputFormat::Y4m => write!(f, "y4m"),
+      InputFormat::Raw(format) => format.fmt(f),
+    }
+  }
+}
+
+impl FromStr for InputFormat {
+  type Err = &'static str;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "y4m" => Ok(InputFormat::Y4m),
+      "i420" => Ok(InputFormat::Raw(RawFormat::I420)),
+      "i422" => Ok(InputFormat::Raw(RawFormat::I422)),
+      "i444" => Ok(InputFormat::Raw(RawFormat::I444)),
+      "nv12" => Ok(InputFormat::Raw(RawFormat::Nv12)),
+      "p010" => Ok(InputFormat::Raw(RawFormat::P010)),
+      _ => Err("expected y4m, i420, i422, i444, nv12 or p010"),
+    }
+  }
+}
+
+/// The decoder of the input, in any of the supported formats.
+pub enum InputDecoder {
+  Y4m(::y4m::Decoder<Box<dyn Read + Send>>),
+  Raw(RawDecoder),
+}
+
+impl InputDecoder {
+  /// Opens the input in `format`. The details of raw input, which has no
+  /// header, are required.
+  pub fn open(
+    input: Box<dyn Read + Send>, format: InputFormat,
+    raw_details: Option<VideoDetails>,
+  ) -> Result<Self, CliError> {
+    match format {
+      InputFormat::Y4m => y4m::open(input).map(InputDecoder::Y4m),
+      InputFormat::Raw(format) => Ok(InputDecoder::Raw(RawDecoder::new(
+        input,
+        format,
+        raw_details.expect("The details of raw input are required"),
+      ))),
+    }
+  }
+
+  /// Returns the y4m colorspace of the frames, to write the reconstruction.
+  pub fn get_colorspace(&self) -> ::y4m::Colorspace {
+    match self {
+      InputDecoder::Y4m(dec) => dec.get_colorspace(),
+      InputDecoder::Raw(dec) => y4m::map_color_space(dec.get_video_details()),
+    }
+  }
+
+  /// Reads the next frame without converting it.
+  pub fn skip_frame(&mut self) -> Result<(), DecodeError> {
+    match self {
+      InputDecoder::Y4m(dec) => {
+        dec.read_frame().map(|_| ()).map_err(Into::into)
+      }
+      InputDecoder::Raw(dec) => dec.read_raw_frame(),
+    }
+  }
+}
+
+impl Decoder for InputDecoder {
+  fn get_video_details(&self) -> VideoDetails {
+    match self {
+      InputDecoder::Y4m(dec) => dec.get_video_details(),
+      InputDecoder::Raw(dec) => dec.get_video_details(),
+    }
+  }
+
+  fn read_frame<T: Pixel, F: FrameBuilder<T>>(
+    &mut self, ctx: &F, cfg: &VideoDetails,
+  ) -> Result<Frame<T>, DecodeError> {
+    match self {
+      InputDecoder::Y4m(dec) => Decoder::read_frame(dec, ctx, cfg),
+      InputDecoder::Raw(dec) => dec.read_frame(ctx, cfg),
+    }
+  }
+}
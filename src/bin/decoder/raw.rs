@@ -0,0 +1,159 @@
+// Copyright (c) 2017-2023, The rav1e contributors. All rights reserved
+//
+// This source code is subject to the terms of the BSD 2 Clause License and
+// the Alliance for Open Media Patent License 1.0. If the BSD 2 Clause License
+// was not distributed with this source code in the LICENSE file, you can
+// obtain it at www.aomedia.org/license/software. If the Alliance for Open
+// Media Patent License 1.0 was not distributed with this source code in the
+// PATENTS file, you can obtain it at www.aomedia.org/license/patent.
+
+use std::fmt;
+use std::io;
+use std::io::Read;
+
+use crate::decoder::{DecodeError, Decoder, FrameBuilder, VideoDetails};
+use rav1e::prelude::*;
+
+/// Layouts of headerless YUV frames.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RawFormat {
+  /// Planar 4:2:0.
+  I420,
+  /// Planar 4:2:2.
+  I422,
+  /// Planar 4:4:4.
+  I444,
+  /// 8-bit 4:2:0, with the chroma samples interleaved in a single plane.
+  Nv12,
+  /// 10-bit 4:2:0 laid out as NV12, the samples being stored in the upper
+  /// bits of 16-bit words.
+  P010,
+}
+
+impl RawFormat {
+  pub const fn chroma_sampling(self) -> ChromaSampling {
+    match self {
+      RawFormat::I420 | RawFormat::Nv12 | RawFormat::P010 => {
+        ChromaSampling::Cs420
+      }
+      RawFormat::I422 => ChromaSampling::Cs422,
+      RawFormat::I444 => ChromaSampling::Cs444,
+    }
+  }
+
+  /// Returns whether `bit_depth` may be stored in this layout.
+  pub const fn supports_bit_depth(self, bit_depth: usize) -> bool {
+    match self {
+      RawFormat::I420 | RawFormat::I422 | RawFormat::I444 => {
+        matches!(bit_depth, 8 | 10 | 12)
+      }
+      RawFormat::Nv12 => bit_depth == 8,
+      RawFormat::P010 => bit_depth == 10,
+    }
+  }
+
+  const fn is_semi_planar(self) -> bool {
+    matches!(self, RawFormat::Nv12 | RawFormat::P010)
+  }
+}
+
+impl fmt::Display for RawFormat {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      RawFormat::I420 => write!(f, "i420"),
+      RawFormat::I422 => write!(f, "i422"),
+      RawFormat::I444 => write!(f, "i444"),
+      RawFormat::Nv12 => write!(f, "nv12"),
+      RawFormat::P010 => write!(f, "p010"),
+    }
+  }
+}
+
+/// Reads headerless frames of a known size and layout. Samples of more than
+/// 8 bits are stored as 16-bit little endian words.
+pub struct RawDecoder {
+  input: Box<dyn Read + Send>,
+  format: RawFormat,
+  details: VideoDetails,
+  buf: Vec<u8>,
+}
+
+impl RawDecoder {
+  pub fn new(
+    input: Box<dyn Read + Send>, format: RawFormat, details: VideoDetails,
+  ) -> Self {
+    let bytes = if details.bit_depth > 8 { 2 } else { 1 };
+    let (chroma_width, chroma_height) = details
+      .chroma_sampling
+      .get_chroma_dimensions(details.width, details.height);
+    let frame_size = (details.width * details.height
+      + 2 * chroma_width * chroma_height)
+      * bytes;
+    RawDecoder { input, format, details, buf: vec![0; frame_size] }
+  }
+
+  /// Reads the next frame into the buffer.
+  pub fn read_raw_frame(&mut self) -> Result<(), DecodeError> {
+    self.input.read_exact(&mut self.buf).map_err(|e| match e.kind() {
+      io::ErrorKind::UnexpectedEof => DecodeError::EOF,
+      _ => DecodeError::IoError,
+    })
+  }
+
+  /// Returns the Y, U and V planes of the buffered frame, each one stored
+  /// row after row.
+  fn planes(&self) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+    let details = &self.details;
+    let bytes = if details.bit_depth > 8 { 2 } else { 1 };
+    let luma_size = details.width * details.height * bytes;
+    let (luma, chroma) = self.buf.split_at(luma_size);
+    let mut luma = luma.to_vec();
+
+    let (mut u, mut v) = if self.format.is_semi_planar() {
+      let mut u = Vec::with_capacity(chroma.len() / 2);
+      let mut v = Vec::with_capacity(chroma.len() / 2);
+      for pair in chroma.chunks_exact(2 * bytes) {
+        u.extend_from_slice(&pair[..bytes]);
+        v.extend_from_slice(&pair[bytes..]);
+      }
+      (u, v)
+    } else {
+      let (u, v) = chroma.split_at(chroma.len() / 2);
+      (u.to_vec(), v.to_vec())
+    };
+
+    if self.format == RawFormat::P010 {
+      for plane in [&mut luma, &mut u, &mut v] {
+        for sample in plane.chunks_exact_mut(2) {
+          let value = u16::from_le_bytes([sample[0], sample[1]]) >> 6;
+          sample.copy_from_slice(&value.to_le_bytes());
+        }
+      }
+    }
+
+    (luma, u, v)
+  }
+}
+
+impl Decoder for RawDecoder {
+  fn get_video_details(&self) -> VideoDetails {
+    self.details
+  }
+
+  fn read_frame<T: Pixel, F: FrameBuilder<T>>(
+    &mut self, ctx: &F, cfg: &VideoDetails,
+  ) -> Result<Frame<T>, DecodeError> {
+    self.read_raw_frame()?;
+
+    let bytes = if cfg.bit_depth > 8 { 2 } else { 1 };
+    let (chroma_width, _) =
+      cfg.chroma_sampling.get_chroma_dimensions(cfg.width, cfg.height);
+    let (y, u, v) = self.planes();
+
+    let mut f = ctx.new_frame();
+    f.planes[0].copy_from_raw_u8(&y, cfg.width * bytes, bytes);
+    f.planes[1].copy_from_raw_u8(&u, chroma_width * bytes, bytes);
+    f.planes[2].copy_from_raw_u8(&v, chroma_width * bytes, bytes);
+    Ok(f)
+  }
+}
@@ -12,8 +12,40 @@ use std::io::Read;
 
 use crate::color::ChromaSampling::Cs400;
 use crate::decoder::{DecodeError, Decoder, FrameBuilder, VideoDetails};
+use crate::error::CliError;
 use rav1e::prelude::*;
 
+/// Opens a y4m input, reading its header.
+pub fn open(
+  input: Box<dyn Read + Send>,
+) -> Result<y4m::Decoder<Box<dyn Read + Send>>, CliError> {
+  // Maximum frame size by specification + maximum y4m header
+  let limit = y4m::Limits {
+    // Use saturating operations to gracefully handle 32-bit architectures
+    bytes: 64usize
+      .saturating_mul(64)
+      .saturating_mul(4096)
+      .saturating_mul(2304)
+      .saturating_add(1024),
+  };
+  y4m::Decoder::new_with_limits(input, limit).map_err(|e| {
+    CliError::new(match e {
+      y4m::Error::ParseError(_) => {
+        "Could not parse input video. Is it a y4m file?"
+      }
+      y4m::Error::IoError(_) => {
+        "Could not read input file. Check that the path is correct and you have read permissions."
+      }
+      y4m::Error::UnknownColorspace => {
+        "Unknown colorspace or unsupported bit depth."
+      }
+      y4m::Error::OutOfMemory => "The video's frame size exceeds the limit.",
+      y4m::Error::EOF => "Unexpected end of input.",
+      y4m::Error::BadInput => "Bad y4m input parameters provided.",
+    })
+  })
+}
+
 impl Decoder for y4m::Decoder<Box<dyn Read + Send>> {
   fn get_video_details(&self) -> VideoDetails {
     let width = self.get_width();
@@ -107,3 +139,22 @@ pub const fn map_y4m_color_space(
     _ => unimplemented!(),
   }
 }
+
+/// Returns the y4m colorspace of frames of the given details.
+pub const fn map_color_space(details: VideoDetails) -> y4m::Colorspace {
+  use crate::ChromaSampling::*;
+  use y4m::Colorspace::*;
+  match (details.chroma_sampling, details.bit_depth) {
+    (Cs400, 8) => Cmono,
+    (Cs400, _) => Cmono12,
+    (Cs420, 8) => C420,
+    (Cs420, 10) => C420p10,
+    (Cs420, _) => C420p12,
+    (Cs422, 8) => C422,
+    (Cs422, 10) => C422p10,
+    (Cs422, _) => C422p12,
+    (Cs444, 8) => C444,
+    (Cs444, 10) => C444p10,
+    (Cs444, _) => C444p12,
+  }
+}
@@ -23,27 +23,30 @@ pub struct IvfMuxer {
 }
 
 impl Muxer for IvfMuxer {
-  fn write_header(
-    &mut self, width: usize, height: usize, framerate_num: usize,
-    framerate_den: usize,
-  ) {
+  fn write_header(&mut self, config: &EncoderConfig, _sequence_header: &[u8]) {
     write_ivf_header(
       &mut self.output,
-      width,
-      height,
-      framerate_num,
-      framerate_den,
+      config.width,
+      config.height,
+      config.time_base.den as usize,
+      config.time_base.num as usize,
     );
   }
 
   #[profiling::function]
-  fn write_frame(&mut self, pts: u64, data: &[u8], _frame_type: FrameType) {
+  fn write_frame(
+    &mut self, pts: u64, _duration: u64, data: &[u8], _frame_type: FrameType,
+  ) {
     write_ivf_frame(&mut self.output, pts, data);
   }
 
   fn flush(&mut self) -> io::Result<()> {
     self.output.flush()
   }
+
+  fn finish(&mut self) -> io::Result<()> {
+    self.output.flush()
+  }
 }
 
 cfg_if::cfg_if! {
@@ -10,6 +10,15 @@
 mod ivf;
 use self::ivf::IvfMuxer;
 
+mod mp4;
+use self::mp4::Mp4FileMuxer;
+
+mod obu;
+use self::obu::ObuMuxer;
+
+mod webm;
+use self::webm::WebmMuxer;
+
 mod y4m;
 pub use self::y4m::write_y4m_frame;
 
@@ -22,14 +31,32 @@ use std::path::Path;
 use crate::error::*;
 
 pub trait Muxer: Send {
-  fn write_header(
-    &mut self, width: usize, height: usize, framerate_num: usize,
-    framerate_den: usize,
-  );
+  fn write_header(&mut self, config: &EncoderConfig, sequence_header: &[u8]);
 
-  fn write_frame(&mut self, pts: u64, data: &[u8], frame_type: FrameType);
+  /// Writes a frame shown at `pts` for `duration`, both in units of the time
+  /// base of the config.
+  fn write_frame(
+    &mut self, pts: u64, duration: u64, data: &[u8], frame_type: FrameType,
+  );
 
   fn flush(&mut self) -> io::Result<()>;
+
+  /// Completes the file once all the frames are written.
+  fn finish(&mut self) -> io::Result<()>;
+}
+
+fn output_extension(path: &Path) -> String {
+  path
+    .extension()
+    .and_then(OsStr::to_str)
+    .map(str::to_lowercase)
+    .unwrap_or_else(|| "ivf".into())
+}
+
+/// Whether the output is an AV1 stream in the length-delimited format of
+/// Annex B, whose OBUs do not carry their sizes.
+pub fn is_annex_b<P: AsRef<Path>>(path: P) -> bool {
+  output_extension(path.as_ref()) == "annexb"
 }
 
 pub fn create_muxer<P: AsRef<Path>>(
@@ -45,17 +72,19 @@ pub fn create_muxer<P: AsRef<Path>>(
     }
   }
 
-  let ext = path
-    .as_ref()
-    .extension()
-    .and_then(OsStr::to_str)
-    .map(str::to_lowercase)
-    .unwrap_or_else(|| "ivf".into());
+  let ext = output_extension(path.as_ref());
 
   match &ext[..] {
     "ivf" => IvfMuxer::open(path),
+    "webm" => WebmMuxer::open(path, "webm"),
+    "mkv" => WebmMuxer::open(path, "matroska"),
+    "mp4" => Mp4FileMuxer::open(path, false),
+    "cmfv" => Mp4FileMuxer::open(path, true),
+    "obu" | "annexb" => ObuMuxer::open(path),
     _e => {
-      panic!("{ext} is not a supported extension, please change to .ivf");
+      panic!(
+        "{ext} is not a supported extension, please change to .ivf, .webm, .mkv, .mp4, .cmfv, .obu or .annexb"
+      );
     }
   }
 }
@@ -0,0 +1,87 @@
+// Copyright (c) 2017-2022, The rav1e contributors. All rights reserved
+//
+// This source code is subject to the terms of the BSD 2 Clause License and
+// the Alliance for Open Media Patent License 1.0. If the BSD 2 Clause License
+// was not distributed with this source code in the LICENSE file, you can
+// obtain it at www.aomedia.org/license/software. If the Alliance for Open
+// Media Patent License 1.0 was not distributed with this source code in the
+// PATENTS file, you can obtain it at www.aomedia.org/license/patent.
+
+use super::Muxer;
+use crate::error::*;
+use rav1e::prelude::*;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+enum State {
+  // The header is not written yet.
+  Opened(BufWriter<File>),
+  Progressive(Box<Mp4Muxer<BufWriter<File>>>),
+  Fragmented(Box<FragmentedMp4Muxer<BufWriter<File>>>),
+  Finished,
+}
+
+pub struct Mp4FileMuxer {
+  state: State,
+  fragmented: bool,
+}
+
+impl Mp4FileMuxer {
+  pub fn open<P: AsRef<Path>>(
+    path: P, fragmented: bool,
+  ) -> Result<Box<dyn Muxer + Send>, CliError> {
+    let output = BufWriter::new(
+      File::create(path).map_err(|e| e.context("Cannot open output file"))?,
+    );
+    Ok(Box::new(Mp4FileMuxer { state: State::Opened(output), fragmented }))
+  }
+}
+
+impl Muxer for Mp4FileMuxer {
+  fn write_header(&mut self, config: &EncoderConfig, sequence_header: &[u8]) {
+    let State::Opened(output) =
+      std::mem::replace(&mut self.state, State::Finished)
+    else {
+      panic!("The header was already written");
+    };
+    self.state = if self.fragmented {
+      State::Fragmented(Box::new(
+        FragmentedMp4Muxer::new(output, config, sequence_header).unwrap(),
+      ))
+    } else {
+      State::Progressive(Box::new(
+        Mp4Muxer::new(output, config, sequence_header).unwrap(),
+      ))
+    };
+  }
+
+  #[profiling::function]
+  fn write_frame(
+    &mut self, pts: u64, duration: u64, data: &[u8], frame_type: FrameType,
+  ) {
+    let keyframe = frame_type == FrameType::KEY;
+    match &mut self.state {
+      State::Progressive(mp4) => {
+        mp4.write_frame(pts, duration, data, keyframe)
+      }
+      State::Fragmented(mp4) => mp4.write_frame(pts, duration, data, keyframe),
+      _ => panic!("The header is not written"),
+    }
+    .unwrap();
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+
+  fn finish(&mut self) -> io::Result<()> {
+    match std::mem::replace(&mut self.state, State::Finished) {
+      State::Progressive(mp4) => mp4.finish()?.flush(),
+      State::Fragmented(mp4) => mp4.finish()?.flush(),
+      State::Opened(mut output) => output.flush(),
+      State::Finished => Ok(()),
+    }
+  }
+}
@@ -0,0 +1,56 @@
+// Copyright (c) 2017-2022, The rav1e contributors. All rights reserved
+//
+// This source code is subject to the terms of the BSD 2 Clause License and
+// the Alliance for Open Media Patent License 1.0. If the BSD 2 Clause License
+// was not distributed with this source code in the LICENSE file, you can
+// obtain it at www.aomedia.org/license/software. If the Alliance for Open
+// Media Patent License 1.0 was not distributed with this source code in the
+// PATENTS file, you can obtain it at www.aomedia.org/license/patent.
+
+use super::Muxer;
+use crate::error::*;
+use rav1e::prelude::*;
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::path::Path;
+
+pub struct ObuMuxer {
+  // The writer is created along with the header, from the config.
+  output: Option<BufWriter<File>>,
+  writer: Option<ObuWriter<BufWriter<File>>>,
+}
+
+impl ObuMuxer {
+  pub fn open<P: AsRef<Path>>(
+    path: P,
+  ) -> Result<Box<dyn Muxer + Send>, CliError> {
+    let output = BufWriter::new(
+      File::create(path).map_err(|e| e.context("Cannot open output file"))?,
+    );
+    Ok(Box::new(ObuMuxer { output: Some(output), writer: None }))
+  }
+}
+
+impl Muxer for ObuMuxer {
+  fn write_header(&mut self, config: &EncoderConfig, _sequence_header: &[u8]) {
+    let output = self.output.take().expect("The header was already written");
+    self.writer = Some(ObuWriter::new(output, config));
+  }
+
+  #[profiling::function]
+  fn write_frame(
+    &mut self, _pts: u64, _duration: u64, data: &[u8], _frame_type: FrameType,
+  ) {
+    let writer = self.writer.as_mut().expect("The header is not written");
+    writer.write_frame(data).unwrap();
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.writer.as_mut().map_or(Ok(()), ObuWriter::flush)
+  }
+
+  fn finish(&mut self) -> io::Result<()> {
+    self.flush()
+  }
+}
@@ -0,0 +1,434 @@
+// Copyright (c) 2017-2022, The rav1e contributors. All rights reserved
+//
+// This source code is subject to the terms of the BSD 2 Clause License and
+// the Alliance for Open Media Patent License 1.0. If the BSD 2 Clause License
+// was not distributed with this source code in the LICENSE file, you can
+// obtain it at www.aomedia.org/license/software. If the Alliance for Open
+// Media Patent License 1.0 was not distributed with this source code in the
+// PATENTS file, you can obtain it at www.aomedia.org/license/patent.
+
+use super::Muxer;
+use crate::error::*;
+use rav1e::prelude::*;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+// Element IDs, see <https://www.matroska.org/technical/elements.html>
+const EBML: u32 = 0x1A45_DFA3;
+const EBML_VERSION: u32 = 0x4286;
+const EBML_READ_VERSION: u32 = 0x42F7;
+const EBML_MAX_ID_LENGTH: u32 = 0x42F2;
+const EBML_MAX_SIZE_LENGTH: u32 = 0x42F3;
+const DOC_TYPE: u32 = 0x4282;
+const DOC_TYPE_VERSION: u32 = 0x4287;
+const DOC_TYPE_READ_VERSION: u32 = 0x4285;
+const VOID: u32 = 0xEC;
+const SEGMENT: u32 = 0x1853_8067;
+const SEEK_HEAD: u32 = 0x114D_9B74;
+const SEEK: u32 = 0x4DBB;
+const SEEK_ID: u32 = 0x53AB;
+const SEEK_POSITION: u32 = 0x53AC;
+const INFO: u32 = 0x1549_A966;
+const TIMESTAMP_SCALE: u32 = 0x2A_D7B1;
+const DURATION: u32 = 0x4489;
+const MUXING_APP: u32 = 0x4D80;
+const WRITING_APP: u32 = 0x5741;
+const TRACKS: u32 = 0x1654_AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_NUMBER: u32 = 0xD7;
+const TRACK_UID: u32 = 0x73C5;
+const TRACK_TYPE: u32 = 0x83;
+const FLAG_LACING: u32 = 0x9C;
+const DEFAULT_DURATION: u32 = 0x23_E383;
+const CODEC_ID: u32 = 0x86;
+const CODEC_PRIVATE: u32 = 0x63A2;
+const VIDEO: u32 = 0xE0;
+const PIXEL_WIDTH: u32 = 0xB0;
+const PIXEL_HEIGHT: u32 = 0xBA;
+const DISPLAY_WIDTH: u32 = 0x54B0;
+const DISPLAY_HEIGHT: u32 = 0x54BA;
+const COLOUR: u32 = 0x55B0;
+const MATRIX_COEFFICIENTS: u32 = 0x55B1;
+const BITS_PER_CHANNEL: u32 = 0x55B2;
+const CHROMA_SUBSAMPLING_HORZ: u32 = 0x55B3;
+const CHROMA_SUBSAMPLING_VERT: u32 = 0x55B4;
+const CHROMA_SITING_HORZ: u32 = 0x55B7;
+const CHROMA_SITING_VERT: u32 = 0x55B8;
+const RANGE: u32 = 0x55B9;
+const TRANSFER_CHARACTERISTICS: u32 = 0x55BA;
+const PRIMARIES: u32 = 0x55BB;
+const MAX_CLL: u32 = 0x55BC;
+const MAX_FALL: u32 = 0x55BD;
+const MASTERING_METADATA: u32 = 0x55D0;
+const PRIMARY_R_CHROMATICITY_X: u32 = 0x55D1;
+const LUMINANCE_MAX: u32 = 0x55D9;
+const LUMINANCE_MIN: u32 = 0x55DA;
+const CLUSTER: u32 = 0x1F43_B675;
+const TIMESTAMP: u32 = 0xE7;
+const SIMPLE_BLOCK: u32 = 0xA3;
+const CUES: u32 = 0x1C53_BB6B;
+const CUE_POINT: u32 = 0xBB;
+const CUE_TIME: u32 = 0xB3;
+const CUE_TRACK_POSITIONS: u32 = 0xB7;
+const CUE_TRACK: u32 = 0xF7;
+const CUE_CLUSTER_POSITION: u32 = 0xF1;
+
+// An 8 byte size which is left unknown until it is rewritten.
+const UNKNOWN_SIZE: [u8; 8] = [0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+// Timestamps are in milliseconds.
+const TIMESTAMP_SCALE_NS: u64 = 1_000_000;
+// Clusters are kept in memory until complete, so start a new one at least
+// this often even without a key frame.
+const MAX_CLUSTER_DURATION_MS: u64 = 5_000;
+// Room kept at the start of the segment for the SeekHead, which can only be
+// written once the position of the Cues is known.
+const SEEK_HEAD_RESERVED_SZ: usize = 96;
+
+fn write_id(buf: &mut Vec<u8>, id: u32) {
+  let bytes = id.to_be_bytes();
+  let skip = (id.leading_zeros() / 8) as usize;
+  buf.extend_from_slice(&bytes[skip..]);
+}
+
+fn write_size(buf: &mut Vec<u8>, size: u64) {
+  // The all ones value of each length is reserved for unknown sizes.
+  let len = (1..8).find(|&len| size < (1 << (7 * len)) - 1).unwrap_or(8);
+  let marked = size | (1 << (7 * len));
+  buf.extend_from_slice(&marked.to_be_bytes()[8 - len..]);
+}
+
+fn write_uint(buf: &mut Vec<u8>, id: u32, value: u64) {
+  let bytes = value.to_be_bytes();
+  let skip = ((value.leading_zeros() / 8) as usize).min(7);
+  write_id(buf, id);
+  write_size(buf, (8 - skip) as u64);
+  buf.extend_from_slice(&bytes[skip..]);
+}
+
+fn write_float(buf: &mut Vec<u8>, id: u32, value: f64) {
+  write_id(buf, id);
+  write_size(buf, 8);
+  buf.extend_from_slice(&value.to_be_bytes());
+}
+
+fn write_binary(buf: &mut Vec<u8>, id: u32, data: &[u8]) {
+  write_id(buf, id);
+  write_size(buf, data.len() as u64);
+  buf.extend_from_slice(data);
+}
+
+fn write_string(buf: &mut Vec<u8>, id: u32, value: &str) {
+  write_binary(buf, id, value.as_bytes());
+}
+
+fn write_void(buf: &mut Vec<u8>, len: usize) {
+  // A Void element of up to 127 bytes has a one byte ID and size.
+  debug_assert!((2..=128).contains(&len));
+  write_id(buf, VOID);
+  write_size(buf, (len - 2) as u64);
+  buf.resize(buf.len() + len - 2, 0);
+}
+
+fn write_colour(buf: &mut Vec<u8>, config: &EncoderConfig) {
+  let mut colour = Vec::new();
+  write_uint(&mut colour, BITS_PER_CHANNEL, config.bit_depth as u64);
+  if config.chroma_sampling != ChromaSampling::Cs400 {
+    let (ss_x, ss_y) = config.chroma_sampling.get_decimation().unwrap();
+    write_uint(&mut colour, CHROMA_SUBSAMPLING_HORZ, ss_x as u64);
+    write_uint(&mut colour, CHROMA_SUBSAMPLING_VERT, ss_y as u64);
+    // 1 is left or top collocated, 2 is half way.
+    let siting = match config.chroma_sample_position {
+      ChromaSamplePosition::Unknown => None,
+      ChromaSamplePosition::Vertical => Some((1, 2)),
+      ChromaSamplePosition::Colocated => Some((1, 1)),
+    };
+    if let Some((horz, vert)) = siting {
+      write_uint(&mut colour, CHROMA_SITING_HORZ, horz);
+      write_uint(&mut colour, CHROMA_SITING_VERT, vert);
+    }
+  }
+  let range = match config.pixel_range {
+    PixelRange::Limited => 1,
+    PixelRange::Full => 2,
+  };
+  write_uint(&mut colour, RANGE, range);
+  if let Some(desc) = config.color_description {
+    write_uint(
+      &mut colour,
+      MATRIX_COEFFICIENTS,
+      desc.matrix_coefficients as u64,
+    );
+    write_uint(
+      &mut colour,
+      TRANSFER_CHARACTERISTICS,
+      desc.transfer_characteristics as u64,
+    );
+    write_uint(&mut colour, PRIMARIES, desc.color_primaries as u64);
+  }
+  if let Some(light) = config.content_light {
+    write_uint(&mut colour, MAX_CLL, light.max_content_light_level as u64);
+    write_uint(
+      &mut colour,
+      MAX_FALL,
+      light.max_frame_average_light_level as u64,
+    );
+  }
+  if let Some(mastering) = config.mastering_display {
+    let mut metadata = Vec::new();
+    // The red, green and blue primaries, then the white point, each as an
+    // x and y pair of consecutive IDs.
+    let points = mastering.primaries.iter().chain([&mastering.white_point]);
+    for (i, point) in points.enumerate() {
+      let id = PRIMARY_R_CHROMATICITY_X + 2 * i as u32;
+      write_float(&mut metadata, id, point.x as f64 / (1 << 16) as f64);
+      write_float(&mut metadata, id + 1, point.y as f64 / (1 << 16) as f64);
+    }
+    write_float(
+      &mut metadata,
+      LUMINANCE_MAX,
+      mastering.max_luminance as f64 / (1 << 8) as f64,
+    );
+    write_float(
+      &mut metadata,
+      LUMINANCE_MIN,
+      mastering.min_luminance as f64 / (1 << 14) as f64,
+    );
+    write_binary(&mut colour, MASTERING_METADATA, &metadata);
+  }
+  write_binary(buf, COLOUR, &colour);
+}
+
+pub struct WebmMuxer {
+  output: BufWriter<File>,
+  doc_type: &'static str,
+  // Duration of one pts unit, as a fraction of a second.
+  time_base: Rational,
+  // File offsets of the segment size, the segment data and the duration.
+  segment_size_pos: u64,
+  segment_data_pos: u64,
+  duration_pos: u64,
+  // Segment positions of the Info and Tracks elements.
+  info_pos: u64,
+  tracks_pos: u64,
+  // Segment position and timestamp of the cluster being filled.
+  cluster_pos: u64,
+  cluster_timestamp: u64,
+  cluster: Vec<u8>,
+  // (timestamp, cluster segment position) of the clusters starting with a
+  // key frame.
+  cues: Vec<(u64, u64)>,
+  end_timestamp: u64,
+}
+
+impl WebmMuxer {
+  pub fn open<P: AsRef<Path>>(
+    path: P, doc_type: &'static str,
+  ) -> Result<Box<dyn Muxer + Send>, CliError> {
+    let webm = WebmMuxer {
+      output: BufWriter::new(
+        File::create(path)
+          .map_err(|e| e.context("Cannot open output file"))?,
+      ),
+      doc_type,
+      time_base: Rational::new(1, 1),
+      segment_size_pos: 0,
+      segment_data_pos: 0,
+      duration_pos: 0,
+      info_pos: 0,
+      tracks_pos: 0,
+      cluster_pos: 0,
+      cluster_timestamp: 0,
+      cluster: Vec::new(),
+      cues: Vec::new(),
+      end_timestamp: 0,
+    };
+    Ok(Box::new(webm))
+  }
+
+  fn timestamp(&self, pts: u64) -> u64 {
+    let ns = pts as u128 * self.time_base.num as u128 * 1_000_000_000
+      / self.time_base.den as u128;
+    (ns / TIMESTAMP_SCALE_NS as u128) as u64
+  }
+
+  fn write_cluster(&mut self) -> io::Result<()> {
+    if self.cluster.is_empty() {
+      return Ok(());
+    }
+    let mut buf = Vec::new();
+    write_id(&mut buf, CLUSTER);
+    write_size(&mut buf, self.cluster.len() as u64);
+    self.output.write_all(&buf)?;
+    self.output.write_all(&self.cluster)?;
+    self.cluster.clear();
+    Ok(())
+  }
+
+  fn segment_pos(&mut self) -> io::Result<u64> {
+    Ok(self.output.stream_position()? - self.segment_data_pos)
+  }
+
+  fn write_trailer(&mut self) -> io::Result<()> {
+    self.write_cluster()?;
+
+    let cues_pos = self.segment_pos()?;
+    let mut cues = Vec::new();
+    for &(time, pos) in &self.cues {
+      let mut positions = Vec::new();
+      write_uint(&mut positions, CUE_TRACK, 1);
+      write_uint(&mut positions, CUE_CLUSTER_POSITION, pos);
+      let mut point = Vec::new();
+      write_uint(&mut point, CUE_TIME, time);
+      write_binary(&mut point, CUE_TRACK_POSITIONS, &positions);
+      write_binary(&mut cues, CUE_POINT, &point);
+    }
+    let mut buf = Vec::new();
+    write_binary(&mut buf, CUES, &cues);
+    self.output.write_all(&buf)?;
+    let segment_end = self.output.stream_position()?;
+
+    let mut seek_head = Vec::new();
+    for (id, pos) in
+      [(INFO, self.info_pos), (TRACKS, self.tracks_pos), (CUES, cues_pos)]
+    {
+      let mut seek = Vec::new();
+      write_binary(&mut seek, SEEK_ID, &id.to_be_bytes());
+      write_uint(&mut seek, SEEK_POSITION, pos);
+      write_binary(&mut seek_head, SEEK, &seek);
+    }
+    let mut buf = Vec::new();
+    write_binary(&mut buf, SEEK_HEAD, &seek_head);
+    let void_len = SEEK_HEAD_RESERVED_SZ - buf.len();
+    write_void(&mut buf, void_len);
+    self.output.seek(SeekFrom::Start(self.segment_data_pos))?;
+    self.output.write_all(&buf)?;
+
+    let mut buf = Vec::new();
+    write_float(&mut buf, DURATION, self.end_timestamp as f64);
+    self.output.seek(SeekFrom::Start(self.duration_pos))?;
+    self.output.write_all(&buf)?;
+
+    // The segment size is coded on 8 bytes.
+    let size = segment_end - self.segment_data_pos;
+    self.output.seek(SeekFrom::Start(self.segment_size_pos))?;
+    self.output.write_all(&(size | (1 << 56)).to_be_bytes())?;
+
+    self.output.seek(SeekFrom::Start(segment_end))?;
+    Ok(())
+  }
+}
+
+impl Muxer for WebmMuxer {
+  fn write_header(&mut self, config: &EncoderConfig, sequence_header: &[u8]) {
+    self.time_base = config.time_base;
+
+    let mut header = Vec::new();
+    write_uint(&mut header, EBML_VERSION, 1);
+    write_uint(&mut header, EBML_READ_VERSION, 1);
+    write_uint(&mut header, EBML_MAX_ID_LENGTH, 4);
+    write_uint(&mut header, EBML_MAX_SIZE_LENGTH, 8);
+    write_string(&mut header, DOC_TYPE, self.doc_type);
+    write_uint(&mut header, DOC_TYPE_VERSION, 4);
+    write_uint(&mut header, DOC_TYPE_READ_VERSION, 2);
+    let mut buf = Vec::new();
+    write_binary(&mut buf, EBML, &header);
+
+    // The segment size is only known once all the frames are written.
+    write_id(&mut buf, SEGMENT);
+    self.segment_size_pos = buf.len() as u64;
+    buf.extend_from_slice(&UNKNOWN_SIZE);
+    self.segment_data_pos = buf.len() as u64;
+    write_void(&mut buf, SEEK_HEAD_RESERVED_SZ);
+
+    let mut info = Vec::new();
+    write_uint(&mut info, TIMESTAMP_SCALE, TIMESTAMP_SCALE_NS);
+    write_string(&mut info, MUXING_APP, "rav1e");
+    write_string(
+      &mut info,
+      WRITING_APP,
+      &format!("rav1e {}", rav1e::version::full()),
+    );
+    // The duration is a placeholder until the end.
+    let duration_offset = info.len();
+    write_float(&mut info, DURATION, 0.);
+    self.info_pos = buf.len() as u64 - self.segment_data_pos;
+    write_id(&mut buf, INFO);
+    write_size(&mut buf, info.len() as u64);
+    self.duration_pos = (buf.len() + duration_offset) as u64;
+    buf.extend_from_slice(&info);
+
+    let mut video = Vec::new();
+    write_uint(&mut video, PIXEL_WIDTH, config.width as u64);
+    write_uint(&mut video, PIXEL_HEIGHT, config.height as u64);
+    let (render_width, render_height) = config.render_size();
+    if (render_width, render_height) != (config.width, config.height) {
+      write_uint(&mut video, DISPLAY_WIDTH, render_width as u64);
+      write_uint(&mut video, DISPLAY_HEIGHT, render_height as u64);
+    }
+    write_colour(&mut video, config);
+
+    let mut track = Vec::new();
+    write_uint(&mut track, TRACK_NUMBER, 1);
+    write_uint(&mut track, TRACK_UID, 1);
+    write_uint(&mut track, TRACK_TYPE, 1);
+    write_uint(&mut track, FLAG_LACING, 0);
+    write_uint(
+      &mut track,
+      DEFAULT_DURATION,
+      config.time_base.num * 1_000_000_000 / config.time_base.den,
+    );
+    write_string(&mut track, CODEC_ID, "V_AV1");
+    write_binary(&mut track, CODEC_PRIVATE, sequence_header);
+    write_binary(&mut track, VIDEO, &video);
+    let mut tracks = Vec::new();
+    write_binary(&mut tracks, TRACK_ENTRY, &track);
+    self.tracks_pos = buf.len() as u64 - self.segment_data_pos;
+    write_binary(&mut buf, TRACKS, &tracks);
+
+    self.output.write_all(&buf).unwrap();
+  }
+
+  #[profiling::function]
+  fn write_frame(
+    &mut self, pts: u64, duration: u64, data: &[u8], frame_type: FrameType,
+  ) {
+    let data = strip_temporal_delimiter(data);
+    let timestamp = self.timestamp(pts);
+    let keyframe = frame_type == FrameType::KEY;
+    if self.cluster.is_empty()
+      || keyframe
+      || timestamp >= self.cluster_timestamp + MAX_CLUSTER_DURATION_MS
+    {
+      self.write_cluster().unwrap();
+      self.cluster_pos = self.segment_pos().unwrap();
+      self.cluster_timestamp = timestamp;
+      write_uint(&mut self.cluster, TIMESTAMP, timestamp);
+      if keyframe {
+        self.cues.push((timestamp, self.cluster_pos));
+      }
+    }
+
+    let mut block = vec![0x81]; // track number
+    let relative = (timestamp - self.cluster_timestamp) as i16;
+    block.extend_from_slice(&relative.to_be_bytes());
+    block.push(if keyframe { 0x80 } else { 0 });
+    write_id(&mut self.cluster, SIMPLE_BLOCK);
+    write_size(&mut self.cluster, (block.len() + data.len()) as u64);
+    self.cluster.extend_from_slice(&block);
+    self.cluster.extend_from_slice(data);
+
+    self.end_timestamp =
+      self.end_timestamp.max(self.timestamp(pts + duration));
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.output.flush()
+  }
+
+  fn finish(&mut self) -> io::Result<()> {
+    self.write_trailer()?;
+    self.output.flush()
+  }
+}
@@ -10,6 +10,7 @@
 #[macro_use]
 extern crate log;
 
+mod analyzer;
 mod common;
 mod decoder;
 mod error;
@@ -24,7 +25,7 @@ use crate::stats::*;
 use rav1e::config::CpuFeatureLevel;
 use rav1e::prelude::*;
 
-use crate::decoder::{Decoder, FrameBuilder, VideoDetails};
+use crate::decoder::{Decoder, FrameBuilder, InputDecoder, VideoDetails};
 use crate::muxer::*;
 use std::fs::File;
 use std::io::{Read, Seek, Write};
@@ -95,9 +96,9 @@ impl<D: Decoder> Source<D> {
 }
 
 fn do_encode<T: Pixel, D: Decoder>(
-  cfg: Config, verbose: Verboseness, mut progress: ProgressInfo,
-  output: &mut dyn Muxer, mut source: Source<D>, pass1file: Option<File>,
-  pass2file: Option<File>,
+  cfg: Config, enc: &EncoderConfig, verbose: Verboseness,
+  mut progress: ProgressInfo, output: &mut dyn Muxer, mut source: Source<D>,
+  pass1file: Option<File>, pass2file: Option<File>,
   mut y4m_enc: Option<y4m::Encoder<Box<dyn Write + Send>>>,
   metrics_enabled: MetricsEnabled,
 ) -> Result<(), CliError> {
@@ -128,6 +129,8 @@ fn do_encode<T: Pixel, D: Decoder>(
       }
     };
 
+  output.write_header(enc, &receive_packet.container_sequence_header());
+
   let y4m_details = source.input.get_video_details();
 
   crossbeam::thread::scope(move |s| -> Result<(), CliError> {
@@ -229,12 +232,16 @@ fn do_encode<T: Pixel, D: Decoder>(
     // Receive Packets
     let receive_packets = s.spawn(move |_| -> Result<(), CliError> {
       for pkt in receive_packet.iter() {
-        output.write_frame(
-          pkt.input_frameno as u64,
-          pkt.data.as_ref(),
-          pkt.frame_type,
-        );
-        output.flush().unwrap();
+        // A dropped frame has no data, leaving a gap in the timestamps.
+        if !pkt.dropped {
+          output.write_frame(
+            pkt.pts,
+            pkt.duration,
+            pkt.data.as_ref(),
+            pkt.frame_type,
+          );
+          output.flush().unwrap();
+        }
         if let (Some(ref mut y4m_enc_uw), Some(ref rec)) =
           (y4m_enc.as_mut(), &pkt.rec)
         {
@@ -257,6 +264,9 @@ fn do_encode<T: Pixel, D: Decoder>(
           };
         }
       }
+      output
+        .finish()
+        .map_err(|e| e.context("Cannot finish the output file"))?;
 
       if verbose != Verboseness::Quiet {
         if verbose == Verboseness::Verbose {
@@ -374,35 +384,9 @@ cfg_if::cfg_if! {
 
 fn run() -> Result<(), error::CliError> {
   let mut cli = parse_cli()?;
-  // Maximum frame size by specification + maximum y4m header
-  let limit = y4m::Limits {
-    // Use saturating operations to gracefully handle 32-bit architectures
-    bytes: 64usize
-      .saturating_mul(64)
-      .saturating_mul(4096)
-      .saturating_mul(2304)
-      .saturating_add(1024),
-  };
-  let mut y4m_dec = match y4m::Decoder::new_with_limits(cli.io.input, limit) {
-    Err(e) => {
-      return Err(CliError::new(match e {
-        y4m::Error::ParseError(_) => {
-          "Could not parse input video. Is it a y4m file?"
-        }
-        y4m::Error::IoError(_) => {
-          "Could not read input file. Check that the path is correct and you have read permissions."
-        }
-        y4m::Error::UnknownColorspace => {
-          "Unknown colorspace or unsupported bit depth."
-        }
-        y4m::Error::OutOfMemory => "The video's frame size exceeds the limit.",
-        y4m::Error::EOF => "Unexpected end of input.",
-        y4m::Error::BadInput => "Bad y4m input parameters provided.",
-      }))
-    }
-    Ok(d) => d,
-  };
-  let video_info = y4m_dec.get_video_details();
+  let mut decoder =
+    InputDecoder::open(cli.io.input, cli.input_format, cli.raw_details)?;
+  let video_info = decoder.get_video_details();
   let y4m_enc = cli.io.rec.map(|rec| {
     y4m::encode(
       video_info.width,
@@ -412,7 +396,7 @@ fn run() -> Result<(), error::CliError> {
         video_info.time_base.num as usize,
       ),
     )
-    .with_colorspace(y4m_dec.get_colorspace())
+    .with_colorspace(decoder.get_colorspace())
     .with_pixel_aspect(y4m::Ratio {
       num: video_info.sample_aspect_ratio.num as usize,
       den: video_info.sample_aspect_ratio.den as usize,
@@ -515,20 +499,14 @@ fn run() -> Result<(), error::CliError> {
     }
   }
 
-  cli.io.output.write_header(
-    video_info.width,
-    video_info.height,
-    cli.enc.time_base.den as usize,
-    cli.enc.time_base.num as usize,
-  );
-
   let tiling =
     cfg.tiling_info().map_err(|e| e.context("Invalid configuration"))?;
   if cli.verbose != Verboseness::Quiet {
     info!("CPU Feature Level: {}", CpuFeatureLevel::default());
 
     info!(
-      "Using y4m decoder: {}x{}p @ {}/{} fps, {}, {}-bit",
+      "Using {} decoder: {}x{}p @ {}/{} fps, {}, {}-bit",
+      cli.input_format,
       video_info.width,
       video_info.height,
       video_info.time_base.den,
@@ -557,7 +535,7 @@ fn run() -> Result<(), error::CliError> {
   );
 
   for _ in 0..cli.skip {
-    match y4m_dec.read_frame() {
+    match decoder.skip_frame() {
       Ok(f) => f,
       Err(_) => {
         return Err(CliError::new("Skipped more frames than in the input"))
@@ -565,11 +543,12 @@ fn run() -> Result<(), error::CliError> {
     };
   }
 
-  let source = Source::new(cli.limit, y4m_dec);
+  let source = Source::new(cli.limit, decoder);
 
   if video_info.bit_depth == 8 && !cli.force_highbitdepth {
-    do_encode::<u8, y4m::Decoder<Box<dyn Read + Send>>>(
+    do_encode::<u8, InputDecoder>(
       cfg,
+      &cli.enc,
       cli.verbose,
       progress,
       &mut *cli.io.output,
@@ -580,8 +559,9 @@ fn run() -> Result<(), error::CliError> {
       cli.metrics_enabled,
     )?
   } else {
-    do_encode::<u16, y4m::Decoder<Box<dyn Read + Send>>>(
+    do_encode::<u16, InputDecoder>(
       cfg,
+      &cli.enc,
       cli.verbose,
       progress,
       &mut *cli.io.output,
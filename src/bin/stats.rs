@@ -25,6 +25,10 @@ pub struct FrameSummary {
   pub qp: u8,
   /// Block-level encoding stats for the frame
   pub enc_stats: EncoderStats,
+  /// Quantizer search of the group of pictures of the frame
+  pub quality_search: Option<QualitySearchStats>,
+  /// Whether the frame was dropped by the rate control
+  pub dropped: bool,
 }
 
 #[profiling::function]
@@ -32,15 +36,20 @@ pub fn build_frame_summary<T: Pixel>(
   packets: Packet<T>, bit_depth: usize, chroma_sampling: ChromaSampling,
   metrics_cli: MetricsEnabled,
 ) -> FrameSummary {
-  let metrics_input_frame: &Frame<T> = packets.source.as_ref().unwrap();
-  let metrics_output_frame: &Frame<T> = packets.rec.as_ref().unwrap();
-  let encode_metrics: QualityMetrics = calculate_frame_metrics(
-    metrics_input_frame,
-    metrics_output_frame,
-    bit_depth,
-    chroma_sampling,
-    metrics_cli,
-  );
+  // A dropped frame has no reconstruction to measure.
+  let encode_metrics: QualityMetrics = if packets.dropped {
+    QualityMetrics::default()
+  } else {
+    let metrics_input_frame: &Frame<T> = packets.source.as_ref().unwrap();
+    let metrics_output_frame: &Frame<T> = packets.rec.as_ref().unwrap();
+    calculate_frame_metrics(
+      metrics_input_frame,
+      metrics_output_frame,
+      bit_depth,
+      chroma_sampling,
+      metrics_cli,
+    )
+  };
   FrameSummary {
     size: packets.data.len(),
     input_frameno: packets.input_frameno,
@@ -48,6 +57,8 @@ pub fn build_frame_summary<T: Pixel>(
     metrics: encode_metrics,
     qp: packets.qp,
     enc_stats: packets.enc_stats,
+    quality_search: packets.quality_search,
+    dropped: packets.dropped,
   }
 }
 
@@ -55,10 +66,11 @@ impl fmt::Display for FrameSummary {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     write!(
       f,
-      "Input Frame {} - {} - {} bytes{}",
+      "Input Frame {} - {} - {} bytes{}{}{}",
       self.input_frameno,
       self.frame_type,
       self.size,
+      if self.dropped { " (dropped)" } else { "" },
       if let Some(psnr) = self.metrics.psnr {
         format!(
           " - PSNR: Y: {:.4}  Cb: {:.4}  Cr: {:.4}",
@@ -66,6 +78,15 @@ impl fmt::Display for FrameSummary {
         )
       } else {
         String::new()
+      },
+      match self.quality_search {
+        Some(search) if search.input_frameno == self.input_frameno => format!(
+          " - Quality search: quantizer {} ({:.4}{})",
+          search.quantizer,
+          search.quality,
+          if search.target_met { "" } else { ", target missed" }
+        ),
+        _ => String::new(),
       }
     )
   }
@@ -86,6 +107,9 @@ pub struct ProgressInfo {
   // This value will be updated in the CLI very frequently, so we cache the previous value
   // to reduce the overall complexity.
   encoded_size: usize,
+  // Number of frames dropped by the rate control, which are not kept in
+  // `frame_info`
+  frames_dropped: usize,
   // Which Metrics to display during and at end of encode
   metrics_enabled: MetricsEnabled,
 }
@@ -101,22 +125,27 @@ impl ProgressInfo {
       time_started: Instant::now(),
       frame_info: Vec::with_capacity(total_frames.unwrap_or_default()),
       encoded_size: 0,
+      frames_dropped: 0,
       metrics_enabled,
     }
   }
 
   pub fn add_frame(&mut self, frame: FrameSummary) {
+    if frame.dropped {
+      self.frames_dropped += 1;
+      return;
+    }
     self.encoded_size += frame.size;
     self.frame_info.push(frame);
   }
 
   pub fn frames_encoded(&self) -> usize {
-    self.frame_info.len()
+    self.frame_info.len() + self.frames_dropped
   }
 
   pub fn encoding_fps(&self) -> f64 {
     let duration = Instant::now().duration_since(self.time_started);
-    self.frame_info.len() as f64
+    self.frames_encoded() as f64
       / (duration.as_secs() as f64 + duration.subsec_millis() as f64 / 1000f64)
   }
 
@@ -127,7 +156,7 @@ impl ProgressInfo {
   // Returns the bitrate of the frames so far, in bits/second
   pub fn bitrate(&self) -> usize {
     let bits = self.encoded_size * 8;
-    let seconds = self.frame_info.len() as f64 / self.video_fps();
+    let seconds = self.frames_encoded() as f64 / self.video_fps();
     (bits as f64 / seconds) as usize
   }
 
@@ -322,6 +351,9 @@ impl ProgressInfo {
     self.print_frame_type_summary(FrameType::INTER);
     self.print_frame_type_summary(FrameType::INTRA_ONLY);
     self.print_frame_type_summary(FrameType::SWITCH);
+    if self.frames_dropped > 0 {
+      info!("{:17} {:>6}", "Dropped:", self.frames_dropped);
+    }
     if verbose {
       self.print_block_type_summary();
       self.print_transform_type_summary();
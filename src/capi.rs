@@ -115,6 +115,25 @@ pub struct Frame {
   frame_type: FrameTypeOverride,
   opaque: Option<FrameOpaque>,
   t35_metadata: Vec<T35>,
+  quantizer: Option<u8>,
+  quantizer_offset: i16,
+  no_reference: bool,
+  importance_map: Option<Box<[f32]>>,
+}
+
+impl Frame {
+  fn new(fi: FrameInternal) -> Frame {
+    Frame {
+      fi,
+      frame_type: FrameTypeOverride::No,
+      opaque: None,
+      t35_metadata: Vec::new(),
+      quantizer: None,
+      quantizer_offset: 0,
+      no_reference: false,
+      importance_map: None,
+    }
+  }
 }
 
 /// Status that can be returned by encoder functions.
@@ -203,14 +222,8 @@ impl EncContext {
     }
   }
   fn send_frame(
-    &mut self, frame: Option<FrameInternal>, frame_type: FrameTypeOverride,
-    opaque: Option<rav1e::Opaque>, t35_metadata: Box<[T35]>,
+    &mut self, frame: Option<FrameInternal>, info: rav1e::FrameParameters,
   ) -> Result<(), rav1e::EncoderStatus> {
-    let info = rav1e::FrameParameters {
-      frame_type_override: frame_type,
-      opaque,
-      t35_metadata,
-    };
     if let Some(frame) = frame {
       match (self, frame) {
         (EncContext::U8(ctx), FrameInternal::U8(ref f)) => {
@@ -245,33 +258,38 @@ impl EncContext {
         });
         let p = std::mem::ManuallyDrop::into_inner(p);
         let rav1e::Packet {
-          data, rec, source, input_frameno, frame_type, ..
+          data,
+          rec,
+          source,
+          input_frameno,
+          frame_type,
+          dropped,
+          ..
         } = p;
         let len = data.len();
         let data = Box::into_raw(data.into_boxed_slice()) as *const u8;
         let rec = if let Some(rec) = rec {
           let rec = FrameInternal::from(rec);
-          Box::into_raw(Box::new(Frame {
-            fi: rec,
-            frame_type: FrameTypeOverride::No,
-            opaque: None,
-            t35_metadata: Vec::new(),
-          }))
+          Box::into_raw(Box::new(Frame::new(rec)))
         } else {
           std::ptr::null_mut()
         };
         let source = if let Some(source) = source {
           let source = FrameInternal::from(source);
-          Box::into_raw(Box::new(Frame {
-            fi: source,
-            frame_type: FrameTypeOverride::No,
-            opaque: None,
-            t35_metadata: Vec::new(),
-          }))
+          Box::into_raw(Box::new(Frame::new(source)))
         } else {
           std::ptr::null_mut()
         };
-        Packet { data, rec, source, len, input_frameno, frame_type, opaque }
+        Packet {
+          data,
+          rec,
+          source,
+          len,
+          input_frameno,
+          frame_type,
+          opaque,
+          dropped,
+        }
       })
     }
     match self {
@@ -384,6 +402,9 @@ pub struct Packet {
   /// The Reference Frame
   /// This is freed automatically by `rav1e_packet_unref()`.
   pub source: *mut Frame,
+  /// Whether the frame was dropped by the rate control.
+  /// A dropped frame carries no data.
+  pub dropped: bool,
 }
 
 /// Version information as presented in `[package]` `version`.
@@ -691,6 +712,14 @@ unsafe fn option_match(
     "quantizer" => enc.quantizer = value.parse().map_err(|_| ())?,
     "min_quantizer" => enc.min_quantizer = value.parse().map_err(|_| ())?,
     "bitrate" => enc.bitrate = value.parse().map_err(|_| ())?,
+    "drop_frames" => enc.drop_frames = value.parse().map_err(|_| ())?,
+    "crf" => enc.crf = Some(value.parse().map_err(|_| ())?),
+    "quality_target" => {
+      enc.quality_target = Some(value.parse().map_err(|_| ())?)
+    }
+    "max_bitrate" => enc.max_bitrate = value.parse().map_err(|_| ())?,
+    "buffer_size" => enc.buffer_size = value.parse().map_err(|_| ())?,
+    "lossless" => enc.lossless = value.parse().map_err(|_| ())?,
 
     "key_frame_interval" => {
       enc.set_key_frame_interval(
@@ -715,10 +744,18 @@ unsafe fn option_match(
         value.parse().map_err(|_| ())?
     }
     "low_latency" => enc.low_latency = value.parse().map_err(|_| ())?,
+    "temporal_layers" => {
+      enc.temporal_layers = value.parse().map_err(|_| ())?
+    }
     "enable_timing_info" => {
       enc.enable_timing_info = value.parse().map_err(|_| ())?
     }
+    "enable_decoder_model_info" => {
+      enc.enable_decoder_model_info = value.parse().map_err(|_| ())?
+    }
     "still_picture" => enc.still_picture = value.parse().map_err(|_| ())?,
+    "superres" => enc.superres_mode = value.parse().map_err(|_| ())?,
+    "screen_content" => enc.screen_content = value.parse().map_err(|_| ())?,
     "no_scene_detection" => {
       if value.parse::<bool>().map_err(|_| ())? {
         enc.speed_settings.scene_detection_mode = SceneDetectionSpeed::None;
@@ -726,6 +763,7 @@ unsafe fn option_match(
     }
 
     "level" => {
+      enc.auto_level = value == "auto";
       enc.level_idx = match value {
         "auto" => None,
         "unconstrained" => Some(31),
@@ -760,14 +798,24 @@ unsafe fn option_match(
 /// - `"tile_cols"`: number of tiles vertically (must be a power of two, overridden by tiles if present), default `0`
 /// - `"min_quantizer"`: minimum allowed base quantizer to use in bitrate mode, default `0`
 /// - `"bitrate"`: target bitrate for the bitrate mode (required for two pass mode), default `0`
+/// - `"drop_frames"`: flag to drop frames that would underflow the rate control reservoir (requires bitrate and low latency mode), default `false`
+/// - `"crf"`: 0-255, constant quality target (cannot be used with bitrate), default unset
+/// - `"quality_target"`: `psnr:<dB>` or `ssim:<value>`, luma quality to reach by searching the quantizer of each group of pictures, default unset
+/// - `"max_bitrate"`: peak bitrate of the decoder buffer model (0 disables the model), default `0`
+/// - `"buffer_size"`: size of the decoder buffer model in bits (0 for one second of max bitrate), default `0`
+/// - `"lossless"`: flag to enable mathematically lossless coding, default `false`
 /// - `"key_frame_interval"`: maximum interval between two keyframes, default `240`
 /// - `"min_key_frame_interval"`: minimum interval between two keyframes, default `12`
 /// - `"switch_frame_interval"`: interval between switch frames, default `0`
 /// - `"reservoir_frame_delay"`: number of temporal units over which to distribute the reservoir usage, default `None`
 /// - `"rdo_lookahead_frames"`: number of frames to read ahead for the RDO lookahead computation, default `40`
 /// - `"low_latency"`: flag to enable low latency mode, default `false`
+/// - `"temporal_layers"`: number of temporal layers, 1 to 3 (more than 1 requires low latency mode), default `1`
 /// - `"enable_timing_info"`: flag to enable signaling timing info in the bitstream, default `false`
+/// - `"enable_decoder_model_info"`: flag to enable signaling the decoder buffer model in the bitstream (requires max bitrate and timing info), default `false`
 /// - `"still_picture"`: flag for still picture mode, default `false`
+/// - `"superres"`: super-resolution for key frames, `"none"`, `"auto"` or a denominator from 9 to 16, default `"none"`; requires a `"key_frame_interval"` of 1
+/// - `"screen_content"`: screen content coding tools, `"auto"`, `"on"` or `"off"`, default `"auto"`
 /// - `"no_scene_detection"`: flag to disable scene detection, default `false`
 ///
 /// Return a negative value on error or 0.
@@ -851,9 +899,7 @@ pub unsafe extern fn rav1e_context_unref(ctx: *mut Context) {
 #[no_mangle]
 pub unsafe extern fn rav1e_frame_new(ctx: *const Context) -> *mut Frame {
   let fi = (*ctx).ctx.new_frame();
-  let frame_type = rav1e::FrameTypeOverride::No;
-  let f = Frame { fi, frame_type, opaque: None, t35_metadata: Vec::new() };
-  let frame = Box::new(f);
+  let frame = Box::new(Frame::new(fi));
 
   Box::into_raw(frame)
 }
@@ -883,6 +929,99 @@ pub unsafe extern fn rav1e_frame_set_type(
   0
 }
 
+/// Overrides the base quantizer index (1-255) selected by the encoder for a
+/// frame
+///
+/// A negative value restores the encoder decision.
+///
+/// Must be called before `rav1e_send_frame()` if used.
+///
+/// Returns `-1` if the quantizer index is out of range, lossless coding
+/// being only available for the whole stream.
+///
+/// # Safety
+///
+/// `frame` must be a valid pointer returned by `rav1e_frame_new()` that has
+/// not been released yet.
+#[no_mangle]
+pub unsafe extern fn rav1e_frame_set_quantizer(
+  frame: *mut Frame, quantizer: c_int,
+) -> c_int {
+  if quantizer == 0 || quantizer > 255 {
+    return -1;
+  }
+  (*frame).quantizer = u8::try_from(quantizer).ok();
+
+  0
+}
+
+/// Adds an offset to the base quantizer index of a frame
+///
+/// Must be called before `rav1e_send_frame()` if used.
+///
+/// Returns `-1` if the offset is outside of -255 to 255.
+///
+/// # Safety
+///
+/// `frame` must be a valid pointer returned by `rav1e_frame_new()` that has
+/// not been released yet.
+#[no_mangle]
+pub unsafe extern fn rav1e_frame_set_quantizer_offset(
+  frame: *mut Frame, offset: c_int,
+) -> c_int {
+  if !(-255..=255).contains(&offset) {
+    return -1;
+  }
+  (*frame).quantizer_offset = offset as i16;
+
+  0
+}
+
+/// Prevents the encoder from using a frame as a reference for the following
+/// frames
+///
+/// It has no effect on key frames, switch frames and frames coded ahead of
+/// their display order.
+///
+/// Must be called before `rav1e_send_frame()` if used.
+///
+/// # Safety
+///
+/// `frame` must be a valid pointer returned by `rav1e_frame_new()` that has
+/// not been released yet.
+#[no_mangle]
+pub unsafe extern fn rav1e_frame_set_no_reference(
+  frame: *mut Frame, no_reference: c_int,
+) {
+  (*frame).no_reference = no_reference != 0;
+}
+
+/// Set the relative importance of each 8x8 block of a frame
+///
+/// The map has one positive entry per 8x8 block in raster order, with the
+/// frame dimensions rounded up to a multiple of 8. The buffer will be copied
+/// into the frame and can be freed immediately after this call.
+///
+/// Passing `NULL` removes the map.
+///
+/// Must be called before `rav1e_send_frame()` if used.
+///
+/// # Safety
+///
+/// `frame` must be a valid pointer returned by `rav1e_frame_new()` that has
+/// not been released yet. Unless it is `NULL`, `map` must point to
+/// `map_len` readable and initialized `float` values.
+#[no_mangle]
+pub unsafe extern fn rav1e_frame_set_importance_map(
+  frame: *mut Frame, map: *const f32, map_len: size_t,
+) {
+  (*frame).importance_map = if map.is_null() {
+    None
+  } else {
+    Some(slice::from_raw_parts(map, map_len).into())
+  };
+}
+
 /// Register an opaque data and a destructor to the frame
 ///
 /// It takes the ownership of its memory:
@@ -1152,27 +1291,24 @@ pub unsafe extern fn rav1e_send_frame(
 
   let frame_internal =
     if frame.is_null() { None } else { Some((*frame).fi.clone()) };
-  let frame_type = if frame.is_null() {
-    rav1e::FrameTypeOverride::No
+  let info = if frame.is_null() {
+    rav1e::FrameParameters::default()
   } else {
-    (*frame).frame_type
-  };
-
-  let maybe_opaque = if frame.is_null() {
-    None
-  } else {
-    (*frame).opaque.take().map(rav1e::Opaque::new)
-  };
-
-  let t35_metadata = if frame.is_null() {
-    Box::new([])
-  } else {
-    mem::take(&mut (*frame).t35_metadata).into_boxed_slice()
+    rav1e::FrameParameters {
+      frame_type_override: (*frame).frame_type,
+      opaque: (*frame).opaque.take().map(rav1e::Opaque::new),
+      t35_metadata: mem::take(&mut (*frame).t35_metadata).into_boxed_slice(),
+      quantizer: (*frame).quantizer,
+      quantizer_offset: (*frame).quantizer_offset,
+      no_reference: (*frame).no_reference,
+      importance_map: (*frame).importance_map.take(),
+      ..Default::default()
+    }
   };
 
   let ret = (*ctx)
     .ctx
-    .send_frame(frame_internal, frame_type, maybe_opaque, t35_metadata)
+    .send_frame(frame_internal, info)
     .map(|_v| None)
     .unwrap_or_else(Some);
 
@@ -1242,6 +1378,9 @@ pub unsafe extern fn rav1e_packet_unref(pkt: *mut Packet) {
 /// Produce a sequence header matching the current encoding context
 ///
 /// Its format is compatible with the AV1 Matroska and ISOBMFF specification.
+/// The `AV1CodecConfigurationRecord` reports the level and tier of the
+/// sequence and is followed by its sequence header OBU, where earlier
+/// versions returned the 4-byte record alone with the level set to 31.
 ///
 /// Use `rav1e_data_unref()` to free it.
 #[no_mangle]
@@ -11,7 +11,10 @@ use std::mem::MaybeUninit;
 
 use super::*;
 
-use crate::predict::PredictionMode;
+use crate::mc::{has_wedge, FilterMode};
+use crate::palette::*;
+use crate::predict::{CompoundParams, MotionMode, PredictionMode};
+use crate::warp::*;
 
 pub const MAX_PLANES: usize = 3;
 
@@ -33,6 +36,9 @@ const REF_TYPES: usize = 2;
 pub const COMP_INDEX_CONTEXTS: usize = 6;
 pub const COMP_GROUP_IDX_CONTEXTS: usize = 6;
 
+pub const SWITCHABLE_FILTERS: usize = 3;
+pub const SWITCHABLE_FILTER_CONTEXTS: usize = (SWITCHABLE_FILTERS + 1) * 4;
+
 pub const COEFF_CONTEXT_MAX_WIDTH: usize = MAX_TILE_WIDTH / MI_SIZE;
 
 /// Absolute offset in blocks, where a block is defined
@@ -180,6 +186,15 @@ pub struct Block {
   // deltas
   pub deblock_deltas: [i8; FRAME_LF_COUNT],
   pub segmentation_idx: u8,
+  // Number of luma and chroma palette colours, 0 without a palette
+  pub palette_size: [u8; 2],
+  // Luma and U palette colours, used to build the neighbours' colour caches
+  pub palette_colors: [[u16; PALETTE_MAX_SIZE]; 2],
+  // Combination of the predictions of compound and inter-intra blocks
+  pub compound: CompoundParams,
+  // Interpolation filters of the vertical and horizontal directions, in the
+  // order they are coded
+  pub interp_filter: [FilterMode; 2],
 }
 
 impl Block {
@@ -207,6 +222,10 @@ impl Default for Block {
       txsize: TX_64X64,
       deblock_deltas: [0, 0, 0, 0],
       segmentation_idx: 0,
+      palette_size: [0; 2],
+      palette_colors: [[0; PALETTE_MAX_SIZE]; 2],
+      compound: CompoundParams::Average,
+      interp_filter: [FilterMode::REGULAR; 2],
     }
   }
 }
@@ -682,12 +701,13 @@ impl ContextWriter<'_> {
   ) -> &[u16; INTRA_MODES] {
     static intra_mode_context: [usize; INTRA_MODES] =
       [0, 1, 2, 3, 4, 4, 4, 4, 3, 0, 1, 2, 0];
-    let above_mode = if bo.0.y > 0 {
+    // IntraBC neighbours count as DC_PRED.
+    let above_mode = if bo.0.y > 0 && !self.bc.blocks.above_of(bo).is_inter() {
       self.bc.blocks.above_of(bo).mode
     } else {
       PredictionMode::DC_PRED
     };
-    let left_mode = if bo.0.x > 0 {
+    let left_mode = if bo.0.x > 0 && !self.bc.blocks.left_of(bo).is_inter() {
       self.bc.blocks.left_of(bo).mode
     } else {
       PredictionMode::DC_PRED
@@ -702,12 +722,13 @@ impl ContextWriter<'_> {
   ) {
     static intra_mode_context: [usize; INTRA_MODES] =
       [0, 1, 2, 3, 4, 4, 4, 4, 3, 0, 1, 2, 0];
-    let above_mode = if bo.0.y > 0 {
+    // IntraBC neighbours count as DC_PRED.
+    let above_mode = if bo.0.y > 0 && !self.bc.blocks.above_of(bo).is_inter() {
       self.bc.blocks.above_of(bo).mode
     } else {
       PredictionMode::DC_PRED
     };
-    let left_mode = if bo.0.x > 0 {
+    let left_mode = if bo.0.x > 0 && !self.bc.blocks.left_of(bo).is_inter() {
       self.bc.blocks.left_of(bo).mode
     } else {
       PredictionMode::DC_PRED
@@ -733,9 +754,9 @@ impl ContextWriter<'_> {
   #[inline]
   pub fn write_intra_uv_mode<W: Writer>(
     &mut self, w: &mut W, uv_mode: PredictionMode, y_mode: PredictionMode,
-    bs: BlockSize,
+    cfl_allowed: bool,
   ) {
-    if bs.cfl_allowed() {
+    if cfl_allowed {
       let cdf = &self.fc.uv_mode_cfl_cdf[y_mode as usize];
       symbol_with_update!(self, w, uv_mode as u32, cdf);
     } else {
@@ -764,31 +785,248 @@ impl ContextWriter<'_> {
     symbol_with_update!(self, w, enable as u32, cdf);
   }
 
-  /// # Panics
-  ///
-  /// - If called with `enable: true` (not yet implemented
-  pub fn write_use_palette_mode<W: Writer>(
-    &mut self, w: &mut W, enable: bool, bsize: BlockSize, bo: TileBlockOffset,
-    luma_mode: PredictionMode, chroma_mode: PredictionMode, xdec: usize,
-    ydec: usize, cs: ChromaSampling,
+  /// Colours of the above and left palettes of `plane_type`, merged in
+  /// ascending order without duplicates. The above palette is not used
+  /// across a 64-pixel row boundary.
+  fn get_palette_cache(
+    &self, bo: TileBlockOffset, plane_type: usize,
+  ) -> ArrayVec<u16, { 2 * PALETTE_MAX_SIZE }> {
+    let colors = |b: &Block| -> ArrayVec<u16, PALETTE_MAX_SIZE> {
+      b.palette_colors[plane_type][..b.palette_size[plane_type] as usize]
+        .iter()
+        .copied()
+        .collect()
+    };
+    let above = if bo.0.y > 0 && (bo.0.y << MI_SIZE_LOG2) % 64 != 0 {
+      colors(self.bc.blocks.above_of(bo))
+    } else {
+      ArrayVec::new()
+    };
+    let left = if bo.0.x > 0 {
+      colors(self.bc.blocks.left_of(bo))
+    } else {
+      ArrayVec::new()
+    };
+
+    let mut cache = ArrayVec::new();
+    let mut push = |c: u16| {
+      if cache.last() != Some(&c) {
+        cache.push(c);
+      }
+    };
+    let (mut above_idx, mut left_idx) = (0, 0);
+    while above_idx < above.len() && left_idx < left.len() {
+      let (above_c, left_c) = (above[above_idx], left[left_idx]);
+      if left_c < above_c {
+        push(left_c);
+        left_idx += 1;
+      } else {
+        push(above_c);
+        above_idx += 1;
+        if left_c == above_c {
+          left_idx += 1;
+        }
+      }
+    }
+    for &c in above[above_idx..].iter().chain(&left[left_idx..]) {
+      push(c);
+    }
+    cache
+  }
+
+  /// Writes the ascending luma or U palette `colors`: first the flags
+  /// selecting entries of `cache`, then the remaining colours as a literal
+  /// followed by deltas. Luma colours are distinct, so their deltas are
+  /// coded minus one.
+  fn write_palette_colors<W: Writer>(
+    w: &mut W, colors: &[u16], cache: &[u16], bit_depth: usize,
+    delta_offset: u16,
   ) {
-    if enable {
-      unimplemented!(); // TODO
+    let mut remaining: ArrayVec<u16, PALETTE_MAX_SIZE> =
+      colors.iter().copied().collect();
+    for &c in cache {
+      if remaining.is_empty() {
+        break;
+      }
+      let pos = remaining.iter().position(|&r| r == c);
+      w.bit(pos.is_some() as u16);
+      if let Some(pos) = pos {
+        remaining.remove(pos);
+      }
     }
 
-    let (ctx_luma, ctx_chroma) = (0, 0); // TODO: increase based on surrounding block info
+    let Some((&first, rest)) = remaining.split_first() else {
+      return;
+    };
+    w.literal(bit_depth as u8, u32::from(first));
+    if rest.is_empty() {
+      return;
+    }
+    let min_bits = bit_depth as u32 - 3;
+    let max_delta = remaining
+      .windows(2)
+      .map(|p| u32::from(p[1] - p[0] - delta_offset))
+      .max()
+      .unwrap_or(0);
+    let extra_bits = (32 - max_delta.leading_zeros()).saturating_sub(min_bits);
+    w.literal(2, extra_bits);
+    let mut bits = min_bits + extra_bits;
+    let mut prev = first;
+    for &c in rest {
+      w.literal(bits as u8, u32::from(c - prev - delta_offset));
+      let range = (1u32 << bit_depth) - u32::from(c) - u32::from(delta_offset);
+      // CeilLog2(range)
+      bits = bits.min(32 - range.saturating_sub(1).leading_zeros());
+      prev = c;
+    }
+  }
+
+  /// Writes the V palette, either as wrapping deltas between consecutive
+  /// colours or as raw values, whichever is cheaper.
+  fn write_palette_colors_v<W: Writer>(
+    w: &mut W, colors: &[u16], bit_depth: usize,
+  ) {
+    let max_val = 1i32 << bit_depth;
+    let deltas: ArrayVec<i32, PALETTE_MAX_SIZE> = colors
+      .windows(2)
+      .map(|p| {
+        let d = i32::from(p[1]) - i32::from(p[0]);
+        if d > max_val / 2 {
+          d - max_val
+        } else if d < -max_val / 2 {
+          d + max_val
+        } else {
+          d
+        }
+      })
+      .collect();
+    let min_bits = bit_depth as u32 - 4;
+    let max_delta = deltas.iter().map(|d| d.unsigned_abs()).max().unwrap_or(0);
+    let bits = (32 - max_delta.leading_zeros()).max(min_bits);
+    let nonzero = deltas.iter().filter(|&&d| d != 0).count() as u32;
+    let delta_cost =
+      2 + bit_depth as u32 + deltas.len() as u32 * bits + nonzero;
+    let raw_cost = colors.len() as u32 * bit_depth as u32;
+
+    if bits <= min_bits + 3 && delta_cost < raw_cost {
+      w.bit(1);
+      w.literal(2, bits - min_bits);
+      w.literal(bit_depth as u8, u32::from(colors[0]));
+      for d in deltas {
+        w.literal(bits as u8, d.unsigned_abs());
+        if d != 0 {
+          w.bit((d < 0) as u16);
+        }
+      }
+    } else {
+      w.bit(0);
+      for &c in colors {
+        w.literal(bit_depth as u8, u32::from(c));
+      }
+    }
+  }
+
+  pub fn write_palette_mode_info<W: Writer>(
+    &mut self, w: &mut W, palette: &PaletteParams, bsize: BlockSize,
+    bo: TileBlockOffset, luma_mode: PredictionMode,
+    chroma_mode: PredictionMode, xdec: usize, ydec: usize, cs: ChromaSampling,
+    bit_depth: usize,
+  ) {
+    let bsize_ctx = bsize.width_mi_log2() + bsize.height_mi_log2() - 2;
 
     if luma_mode == PredictionMode::DC_PRED {
-      let bsize_ctx = bsize.width_mi_log2() + bsize.height_mi_log2() - 2;
-      let cdf = &self.fc.palette_y_mode_cdfs[bsize_ctx][ctx_luma];
-      symbol_with_update!(self, w, enable as u32, cdf);
+      let n = palette.size[0] as usize;
+      let ctx = (bo.0.y > 0 && self.bc.blocks.above_of(bo).palette_size[0] > 0)
+        as usize
+        + (bo.0.x > 0 && self.bc.blocks.left_of(bo).palette_size[0] > 0)
+          as usize;
+      let cdf = &self.fc.palette_y_mode_cdfs[bsize_ctx][ctx];
+      symbol_with_update!(self, w, (n > 0) as u32, cdf);
+      if n > 0 {
+        let cdf = &self.fc.palette_y_size_cdfs[bsize_ctx];
+        symbol_with_update!(self, w, n as u32 - 2, cdf);
+        let cache = self.get_palette_cache(bo, 0);
+        Self::write_palette_colors(
+          w,
+          palette.plane_colors(0),
+          &cache,
+          bit_depth,
+          1,
+        );
+      }
+    } else {
+      debug_assert_eq!(palette.size[0], 0);
     }
 
     if has_chroma(bo, bsize, xdec, ydec, cs)
       && chroma_mode == PredictionMode::DC_PRED
     {
-      let cdf = &self.fc.palette_uv_mode_cdfs[ctx_chroma];
-      symbol_with_update!(self, w, enable as u32, cdf);
+      let n = palette.size[1] as usize;
+      let cdf = &self.fc.palette_uv_mode_cdfs[(palette.size[0] > 0) as usize];
+      symbol_with_update!(self, w, (n > 0) as u32, cdf);
+      if n > 0 {
+        let cdf = &self.fc.palette_uv_size_cdfs[bsize_ctx];
+        symbol_with_update!(self, w, n as u32 - 2, cdf);
+        let cache = self.get_palette_cache(bo, 1);
+        Self::write_palette_colors(
+          w,
+          palette.plane_colors(1),
+          &cache,
+          bit_depth,
+          0,
+        );
+        Self::write_palette_colors_v(w, palette.plane_colors(2), bit_depth);
+      }
+    } else {
+      debug_assert_eq!(palette.size[1], 0);
+    }
+  }
+
+  /// Writes a colour index map of `n` colours in wavefront order, each index
+  /// coded by its rank in the order derived from its causal neighbours.
+  pub fn write_palette_color_map<W: Writer>(
+    &mut self, w: &mut W, map: &[u8], stride: usize, n: usize,
+    plane_type: usize, visible_w: usize, visible_h: usize,
+  ) {
+    w.write_quniform(n as u32, u32::from(map[0]));
+    for i in 1..visible_h + visible_w - 1 {
+      for j in (i.saturating_sub(visible_h - 1)..=i.min(visible_w - 1)).rev() {
+        let (r, c) = (i - j, j);
+        let (ctx, order) = color_context(map, stride, r, c, n);
+        let s =
+          order.iter().position(|&o| o == map[r * stride + c]).unwrap() as u32;
+        match n {
+          2 => {
+            let cdf = &self.fc.palette_color_idx_cdf2[plane_type][ctx];
+            symbol_with_update!(self, w, s, cdf);
+          }
+          3 => {
+            let cdf = &self.fc.palette_color_idx_cdf3[plane_type][ctx];
+            symbol_with_update!(self, w, s, cdf);
+          }
+          4 => {
+            let cdf = &self.fc.palette_color_idx_cdf4[plane_type][ctx];
+            symbol_with_update!(self, w, s, cdf);
+          }
+          5 => {
+            let cdf = &self.fc.palette_color_idx_cdf5[plane_type][ctx];
+            symbol_with_update!(self, w, s, cdf);
+          }
+          6 => {
+            let cdf = &self.fc.palette_color_idx_cdf6[plane_type][ctx];
+            symbol_with_update!(self, w, s, cdf);
+          }
+          7 => {
+            let cdf = &self.fc.palette_color_idx_cdf7[plane_type][ctx];
+            symbol_with_update!(self, w, s, cdf);
+          }
+          8 => {
+            let cdf = &self.fc.palette_color_idx_cdf8[plane_type][ctx];
+            symbol_with_update!(self, w, s, cdf);
+          }
+          _ => unreachable!(),
+        }
+      }
     }
   }
 
@@ -854,7 +1092,19 @@ impl ContextWriter<'_> {
     ref_frames: [RefType; 2], blk: &Block,
     mv_stack: &mut ArrayVec<CandidateMV, 9>, weight: u32,
     newmv_count: &mut usize, is_compound: bool,
+    gm_cand: [Option<MotionVector>; 2],
   ) -> bool {
+    // Large global motion blocks contribute the global mv of the current
+    // block rather than their own
+    let uses_gm = matches!(
+      blk.mode,
+      PredictionMode::GLOBALMV | PredictionMode::GLOBAL_GLOBALMV
+    ) && blk.n4_w.min(blk.n4_h) >= 2;
+    let cand_mv = |i: usize, list: usize| match gm_cand[list] {
+      Some(mv) if uses_gm => mv,
+      _ => blk.mv[i],
+    };
+
     if !blk.is_inter() {
       /* For intrabc */
       false
@@ -862,13 +1112,13 @@ impl ContextWriter<'_> {
       if blk.ref_frames[0] == ref_frames[0]
         && blk.ref_frames[1] == ref_frames[1]
       {
-        let found_match = Self::find_matching_comp_mv_and_update_weight(
-          blk.mv, mv_stack, weight,
-        );
+        let mvs = [cand_mv(0, 0), cand_mv(1, 1)];
+        let found_match =
+          Self::find_matching_comp_mv_and_update_weight(mvs, mv_stack, weight);
 
         if !found_match && mv_stack.len() < MAX_REF_MV_STACK_SIZE {
           let mv_cand =
-            CandidateMV { this_mv: blk.mv[0], comp_mv: blk.mv[1], weight };
+            CandidateMV { this_mv: mvs[0], comp_mv: mvs[1], weight };
 
           mv_stack.push(mv_cand);
         }
@@ -885,13 +1135,13 @@ impl ContextWriter<'_> {
       let mut found = false;
       for i in 0..2 {
         if blk.ref_frames[i] == ref_frames[0] {
-          let found_match = Self::find_matching_mv_and_update_weight(
-            blk.mv[i], mv_stack, weight,
-          );
+          let mv = cand_mv(i, 0);
+          let found_match =
+            Self::find_matching_mv_and_update_weight(mv, mv_stack, weight);
 
           if !found_match && mv_stack.len() < MAX_REF_MV_STACK_SIZE {
             let mv_cand = CandidateMV {
-              this_mv: blk.mv[i],
+              this_mv: mv,
               comp_mv: MotionVector::default(),
               weight,
             };
@@ -968,7 +1218,7 @@ impl ContextWriter<'_> {
     &self, bo: TileBlockOffset, row_offset: isize, max_row_offs: isize,
     processed_rows: &mut isize, ref_frames: [RefType; 2],
     mv_stack: &mut ArrayVec<CandidateMV, 9>, newmv_count: &mut usize,
-    bsize: BlockSize, is_compound: bool,
+    bsize: BlockSize, is_compound: bool, gm_cand: [Option<MotionVector>; 2],
   ) -> bool {
     let bc = &self.bc;
     let target_n4_w = bsize.width_mi();
@@ -1020,6 +1270,7 @@ impl ContextWriter<'_> {
         len as u32 * weight,
         newmv_count,
         is_compound,
+        gm_cand,
       ) {
         found_match = true;
       }
@@ -1034,7 +1285,7 @@ impl ContextWriter<'_> {
     &self, bo: TileBlockOffset, col_offset: isize, max_col_offs: isize,
     processed_cols: &mut isize, ref_frames: [RefType; 2],
     mv_stack: &mut ArrayVec<CandidateMV, 9>, newmv_count: &mut usize,
-    bsize: BlockSize, is_compound: bool,
+    bsize: BlockSize, is_compound: bool, gm_cand: [Option<MotionVector>; 2],
   ) -> bool {
     let bc = &self.bc;
 
@@ -1086,6 +1337,7 @@ impl ContextWriter<'_> {
         len as u32 * weight,
         newmv_count,
         is_compound,
+        gm_cand,
       ) {
         found_match = true;
       }
@@ -1099,7 +1351,7 @@ impl ContextWriter<'_> {
   fn scan_blk_mbmi(
     &self, bo: TileBlockOffset, ref_frames: [RefType; 2],
     mv_stack: &mut ArrayVec<CandidateMV, 9>, newmv_count: &mut usize,
-    is_compound: bool,
+    is_compound: bool, gm_cand: [Option<MotionVector>; 2],
   ) -> bool {
     if bo.0.x >= self.bc.blocks.cols() || bo.0.y >= self.bc.blocks.rows() {
       return false;
@@ -1114,6 +1366,7 @@ impl ContextWriter<'_> {
       weight,
       newmv_count,
       is_compound,
+      gm_cand,
     )
   }
 
@@ -1127,10 +1380,18 @@ impl ContextWriter<'_> {
   fn setup_mvref_list<T: Pixel>(
     &self, bo: TileBlockOffset, ref_frames: [RefType; 2],
     mv_stack: &mut ArrayVec<CandidateMV, 9>, bsize: BlockSize,
-    fi: &FrameInvariants<T>, is_compound: bool,
+    fi: &FrameInvariants<T>, is_compound: bool, global_mvs: [MotionVector; 2],
   ) -> usize {
     let (_rf, _rf_num) = (INTRA_FRAME, 1);
 
+    let gm_cand = [0, 1].map(|i| {
+      (ref_frames[i] != INTRA_FRAME
+        && ref_frames[i] != NONE_FRAME
+        && fi.globalmv_transformation_type[ref_frames[i].to_index()]
+          > GlobalMVMode::TRANSLATION)
+        .then_some(global_mvs[i])
+    });
+
     let target_n4_h = bsize.height_mi();
     let target_n4_w = bsize.width_mi();
 
@@ -1187,6 +1448,7 @@ impl ContextWriter<'_> {
         &mut newmv_count,
         bsize,
         is_compound,
+        gm_cand,
       );
       row_match |= found_match;
     }
@@ -1201,6 +1463,7 @@ impl ContextWriter<'_> {
         &mut newmv_count,
         bsize,
         is_compound,
+        gm_cand,
       );
       col_match |= found_match;
     }
@@ -1211,6 +1474,7 @@ impl ContextWriter<'_> {
         mv_stack,
         &mut newmv_count,
         is_compound,
+        gm_cand,
       );
       row_match |= found_match;
     }
@@ -1230,6 +1494,7 @@ impl ContextWriter<'_> {
         mv_stack,
         &mut far_newmv_count,
         is_compound,
+        gm_cand,
       );
     row_match |= found_match;
 
@@ -1250,6 +1515,7 @@ impl ContextWriter<'_> {
           &mut far_newmv_count,
           bsize,
           is_compound,
+          gm_cand,
         );
         row_match |= found_match;
       }
@@ -1267,6 +1533,7 @@ impl ContextWriter<'_> {
           &mut far_newmv_count,
           bsize,
           is_compound,
+          gm_cand,
         );
         col_match |= found_match;
       }
@@ -1330,7 +1597,7 @@ impl ContextWriter<'_> {
       }
 
       if is_compound {
-        let mut combined_mvs = [[MotionVector::default(); 2]; 2];
+        let mut combined_mvs = [global_mvs; 2];
 
         for list in 0..2 {
           let mut comp_count = 0;
@@ -1426,19 +1693,204 @@ impl ContextWriter<'_> {
     fi: &FrameInvariants<T>, is_compound: bool,
   ) -> usize {
     assert!(ref_frames[0] != NONE_FRAME);
-    if ref_frames[0] != NONE_FRAME {
-      // TODO: If ref_frames[0] != INTRA_FRAME, convert global mv to an mv;
-      // otherwise, set the global mv ref to invalid.
+    if ref_frames[0] == INTRA_FRAME && !fi.allow_intrabc {
+      return 0;
     }
 
-    if ref_frames[0] != INTRA_FRAME {
-      /* TODO: Set zeromv ref to the converted global motion vector */
-    } else {
-      /* TODO: Set the zeromv ref to 0 */
-      return 0;
+    let frame_bo = PlaneBlockOffset(BlockOffset {
+      x: self.bc.blocks.x() + bo.0.x,
+      y: self.bc.blocks.y() + bo.0.y,
+    });
+    let global_mvs = ref_frames.map(|rf| fi.global_mv(rf, frame_bo, bsize));
+
+    self.setup_mvref_list(
+      bo,
+      ref_frames,
+      mv_stack,
+      bsize,
+      fi,
+      is_compound,
+      global_mvs,
+    )
+  }
+
+  /// Whether an inter block directly above or to the left of the block can
+  /// be used for overlapped prediction.
+  pub fn has_overlappable_candidates(
+    &self, bo: TileBlockOffset, bsize: BlockSize,
+  ) -> bool {
+    let blocks = &self.bc.blocks;
+    if bo.0.y > 0 {
+      let x_end = blocks.cols().min(bo.0.x + bsize.width_mi());
+      for x in (bo.0.x..x_end).step_by(2) {
+        if blocks[bo.0.y - 1][(x | 1).min(blocks.cols() - 1)].is_inter() {
+          return true;
+        }
+      }
+    }
+    if bo.0.x > 0 {
+      let y_end = blocks.rows().min(bo.0.y + bsize.height_mi());
+      for y in (bo.0.y..y_end).step_by(2) {
+        if blocks[(y | 1).min(blocks.rows() - 1)][bo.0.x - 1].is_inter() {
+          return true;
+        }
+      }
+    }
+    false
+  }
+
+  /// Whether the motion mode of an inter block is coded, i.e. whether it can
+  /// use a motion mode other than `SIMPLE_TRANSLATION`.
+  pub fn is_motion_mode_switchable<T: Pixel>(
+    &self, fi: &FrameInvariants<T>, bo: TileBlockOffset, bsize: BlockSize,
+    mode: PredictionMode, ref_frames: [RefType; 2], compound: CompoundParams,
+  ) -> bool {
+    let global_warp = fi.force_integer_mv == 0
+      && matches!(
+        mode,
+        PredictionMode::GLOBALMV | PredictionMode::GLOBAL_GLOBALMV
+      )
+      && fi.globalmv_transformation_type[ref_frames[0].to_index()]
+        > GlobalMVMode::TRANSLATION;
+
+    fi.is_motion_mode_switchable
+      && bsize.width().min(bsize.height()) >= 8
+      && !global_warp
+      && ref_frames[1] == NONE_FRAME
+      && !compound.is_interintra()
+      && self.has_overlappable_candidates(bo, bsize)
+  }
+
+  /// Collects the neighbouring blocks predicted from `ref_frame` alone with
+  /// motion similar to `mv`, which are the samples a local warp is estimated
+  /// from.
+  pub fn find_warp_samples(
+    &self, bo: TileBlockOffset, bsize: BlockSize, ref_frame: RefType,
+    mv: MotionVector,
+  ) -> ArrayVec<WarpSample, LEAST_SQUARES_SAMPLES_MAX> {
+    let blocks = &self.bc.blocks;
+    let w4 = bsize.width_mi();
+    let h4 = bsize.height_mi();
+    let threshold = (bsize.width().max(bsize.height()) as i32).clamp(16, 112);
+
+    let mut samples = ArrayVec::new();
+    let mut first_sample = None;
+    let mut num_scanned = 0;
+    let mut add_sample = |delta_row: isize, delta_col: isize| {
+      if num_scanned >= LEAST_SQUARES_SAMPLES_MAX {
+        return;
+      }
+      let row = bo.0.y as isize + delta_row;
+      let col = bo.0.x as isize + delta_col;
+      if row < 0
+        || col < 0
+        || row as usize >= blocks.rows()
+        || col as usize >= blocks.cols()
+      {
+        return;
+      }
+      let cand = &blocks[row as usize][col as usize];
+      if cand.ref_frames[0] != ref_frame
+        || cand.ref_frames[1] != NONE_FRAME
+        || cand.compound.is_interintra()
+      {
+        return;
+      }
+
+      let cand_w4 = cand.n4_w as isize;
+      let cand_h4 = cand.n4_h as isize;
+      let mid_y =
+        ((row & !(cand_h4 - 1)) - bo.0.y as isize) * 4 + cand_h4 * 2 - 1;
+      let mid_x =
+        ((col & !(cand_w4 - 1)) - bo.0.x as isize) * 4 + cand_w4 * 2 - 1;
+      let cand_mv = cand.mv[0];
+      let sample = WarpSample {
+        pos: [mid_x as i32 * 8, mid_y as i32 * 8],
+        ref_pos: [
+          mid_x as i32 * 8 + cand_mv.col as i32,
+          mid_y as i32 * 8 + cand_mv.row as i32,
+        ],
+      };
+      num_scanned += 1;
+      if (cand_mv.row as i32 - mv.row as i32).abs()
+        + (cand_mv.col as i32 - mv.col as i32).abs()
+        <= threshold
+      {
+        samples.push(sample);
+      } else if num_scanned == 1 {
+        first_sample = Some(sample);
+      }
+    };
+
+    let mut do_top_left = true;
+    let mut do_top_right = true;
+    if bo.0.y > 0 {
+      let src_w4 = blocks[bo.0.y - 1][bo.0.x].n4_w as usize;
+      if w4 <= src_w4 {
+        let col_offset = bo.0.x & (src_w4 - 1);
+        if col_offset > 0 {
+          do_top_left = false;
+        }
+        if src_w4 - col_offset > w4 {
+          do_top_right = false;
+        }
+        add_sample(-1, 0);
+      } else {
+        let mut i = 0;
+        while i < w4.min(blocks.cols() - bo.0.x) {
+          let step = blocks[bo.0.y - 1][bo.0.x + i].n4_w as usize;
+          add_sample(-1, i as isize);
+          i += step.max(1);
+        }
+      }
+    }
+    if bo.0.x > 0 {
+      let src_h4 = blocks[bo.0.y][bo.0.x - 1].n4_h as usize;
+      if h4 <= src_h4 {
+        if bo.0.y & (src_h4 - 1) > 0 {
+          do_top_left = false;
+        }
+        add_sample(0, -1);
+      } else {
+        let mut i = 0;
+        while i < h4.min(blocks.rows() - bo.0.y) {
+          let step = blocks[bo.0.y + i][bo.0.x - 1].n4_h as usize;
+          add_sample(i as isize, -1);
+          i += step.max(1);
+        }
+      }
+    }
+    if do_top_left {
+      add_sample(-1, -1);
+    }
+    if do_top_right && w4.max(h4) <= 16 && has_tr(bo, bsize) {
+      add_sample(-1, w4 as isize);
     }
 
-    self.setup_mvref_list(bo, ref_frames, mv_stack, bsize, fi, is_compound)
+    if samples.is_empty() {
+      samples.extend(first_sample);
+    }
+    samples
+  }
+
+  /// Estimates the local warp used by a `WARPED_CAUSAL` block, if the frame
+  /// allows warped motion and the warp is valid.
+  pub fn local_warp<T: Pixel>(
+    &self, fi: &FrameInvariants<T>, bo: TileBlockOffset, bsize: BlockSize,
+    ref_frame: RefType, mv: MotionVector,
+  ) -> Option<WarpedMotion> {
+    if !fi.allow_warped_motion || fi.force_integer_mv != 0 {
+      return None;
+    }
+    let samples = self.find_warp_samples(bo, bsize, ref_frame, mv);
+    if samples.is_empty() {
+      return None;
+    }
+    let frame_bo = PlaneBlockOffset(BlockOffset {
+      x: self.bc.blocks.x() + bo.0.x,
+      y: self.bc.blocks.y() + bo.0.y,
+    });
+    WarpedMotion::from_samples(&samples, bsize, frame_bo, mv)
   }
 
   pub fn fill_neighbours_ref_counts(&mut self, bo: TileBlockOffset) {
@@ -1654,6 +2106,39 @@ impl ContextWriter<'_> {
     }
   }
 
+  pub fn get_comp_group_idx_ctx(&self, bo: TileBlockOffset) -> usize {
+    let ctx = |block: &Block| {
+      if block.has_second_ref() {
+        block.compound.is_masked() as usize
+      } else if block.ref_frames[0] == ALTREF_FRAME {
+        3
+      } else {
+        0
+      }
+    };
+    let above = if bo.0.y > 0 { ctx(self.bc.blocks.above_of(bo)) } else { 0 };
+    let left = if bo.0.x > 0 { ctx(self.bc.blocks.left_of(bo)) } else { 0 };
+    (above + left).min(5)
+  }
+
+  pub fn get_compound_idx_ctx<T: Pixel>(
+    &self, fi: &FrameInvariants<T>, bo: TileBlockOffset,
+    ref_frames: [RefType; 2],
+  ) -> usize {
+    let ctx = |block: &Block| {
+      if block.has_second_ref() {
+        (block.compound != CompoundParams::Distance) as usize
+      } else {
+        (block.ref_frames[0] == ALTREF_FRAME) as usize
+      }
+    };
+    let equal_dist =
+      fi.ref_frame_dist(ref_frames[0]) == fi.ref_frame_dist(ref_frames[1]);
+    let above = if bo.0.y > 0 { ctx(self.bc.blocks.above_of(bo)) } else { 0 };
+    let left = if bo.0.x > 0 { ctx(self.bc.blocks.left_of(bo)) } else { 0 };
+    3 * equal_dist as usize + above + left
+  }
+
   /// # Panics
   ///
   /// - If `mode` is not an inter mode
@@ -1719,12 +2204,170 @@ impl ContextWriter<'_> {
     symbol_with_update!(self, w, drl_mode as u32, cdf);
   }
 
+  /// Writes the motion mode of a block whose motion mode is coded. Only
+  /// `OBMC_CAUSAL` can be signalled besides `SIMPLE_TRANSLATION` unless
+  /// `allow_warped` is set.
+  pub fn write_motion_mode<W: Writer>(
+    &mut self, w: &mut W, bsize: BlockSize, motion_mode: MotionMode,
+    allow_warped: bool,
+  ) {
+    if allow_warped {
+      let cdf = &self.fc.motion_mode_cdf[bsize as usize];
+      symbol_with_update!(self, w, motion_mode as u32, cdf);
+    } else {
+      debug_assert!(motion_mode != MotionMode::WARPED_CAUSAL);
+      let cdf = &self.fc.obmc_cdf[bsize as usize];
+      let use_obmc = motion_mode == MotionMode::OBMC_CAUSAL;
+      symbol_with_update!(self, w, use_obmc as u32, cdf);
+    }
+  }
+
+  /// Writes whether a single reference block blends in an intra
+  /// prediction, and how. Only called when
+  /// [`interintra_allowed`](crate::mc::interintra_allowed).
+  pub fn write_interintra_mode<W: Writer>(
+    &mut self, w: &mut W, bsize: BlockSize, compound: CompoundParams,
+  ) {
+    let size_group = size_group_lookup[bsize as usize] as usize;
+    let cdf = &self.fc.interintra_cdf[size_group];
+    symbol_with_update!(self, w, compound.is_interintra() as u32, cdf);
+    if let CompoundParams::InterIntra { mode, wedge } = compound {
+      let cdf = &self.fc.interintra_mode_cdf[size_group];
+      symbol_with_update!(self, w, mode as u32, cdf);
+      let cdf = &self.fc.wedge_interintra_cdf[bsize as usize];
+      symbol_with_update!(self, w, wedge.is_some() as u32, cdf);
+      if let Some(index) = wedge {
+        let cdf = &self.fc.wedge_idx_cdf[bsize as usize];
+        symbol_with_update!(self, w, index as u32, cdf);
+      }
+    }
+  }
+
+  /// Writes how the two predictions of a compound block are combined.
+  pub fn write_compound_type<W: Writer, T: Pixel>(
+    &mut self, w: &mut W, fi: &FrameInvariants<T>, bo: TileBlockOffset,
+    bsize: BlockSize, ref_frames: [RefType; 2], compound: CompoundParams,
+  ) {
+    if fi.sequence.enable_masked_compound {
+      let ctx = self.get_comp_group_idx_ctx(bo);
+      let cdf = &self.fc.comp_group_idx_cdfs[ctx];
+      symbol_with_update!(self, w, compound.is_masked() as u32, cdf);
+    } else {
+      debug_assert!(!compound.is_masked());
+    }
+
+    match compound {
+      CompoundParams::Average | CompoundParams::Distance => {
+        if fi.sequence.enable_jnt_comp {
+          let ctx = self.get_compound_idx_ctx(fi, bo, ref_frames);
+          let cdf = &self.fc.compound_idx_cdfs[ctx];
+          let average = compound == CompoundParams::Average;
+          symbol_with_update!(self, w, average as u32, cdf);
+        } else {
+          debug_assert_eq!(compound, CompoundParams::Average);
+        }
+      }
+      CompoundParams::Wedge { index, sign } => {
+        debug_assert!(has_wedge(bsize));
+        let cdf = &self.fc.compound_type_cdf[bsize as usize];
+        symbol_with_update!(self, w, 0, cdf);
+        let cdf = &self.fc.wedge_idx_cdf[bsize as usize];
+        symbol_with_update!(self, w, index as u32, cdf);
+        w.bit(sign as u16);
+      }
+      CompoundParams::DiffWeighted { inverse } => {
+        if has_wedge(bsize) {
+          let cdf = &self.fc.compound_type_cdf[bsize as usize];
+          symbol_with_update!(self, w, 1, cdf);
+        }
+        w.bit(inverse as u16);
+      }
+      CompoundParams::InterIntra { .. } => unreachable!(),
+    }
+  }
+
+  fn get_interp_filter_ctx(
+    &self, bo: TileBlockOffset, ref_frames: [RefType; 2], dir: usize,
+  ) -> usize {
+    // Filter of a neighbour predicting from the same reference, or 3
+    let filter = |block: &Block| {
+      if block.ref_frames.contains(&ref_frames[0]) {
+        block.interp_filter[dir] as usize
+      } else {
+        SWITCHABLE_FILTERS
+      }
+    };
+    let above = if bo.0.y > 0 {
+      filter(self.bc.blocks.above_of(bo))
+    } else {
+      SWITCHABLE_FILTERS
+    };
+    let left = if bo.0.x > 0 {
+      filter(self.bc.blocks.left_of(bo))
+    } else {
+      SWITCHABLE_FILTERS
+    };
+    let ctx = ((dir & 1) * 2 + (ref_frames[1] != NONE_FRAME) as usize)
+      * (SWITCHABLE_FILTERS + 1);
+    ctx
+      + if above == left || left == SWITCHABLE_FILTERS {
+        above
+      } else if above == SWITCHABLE_FILTERS {
+        left
+      } else {
+        SWITCHABLE_FILTERS
+      }
+  }
+
+  /// Writes the vertical and, with dual filters, horizontal interpolation
+  /// filters of an inter block. Only called when the frame has switchable
+  /// filters and [`PredictionMode::needs_interp_filter`].
+  pub fn write_interp_filter<W: Writer, T: Pixel>(
+    &mut self, w: &mut W, fi: &FrameInvariants<T>, bo: TileBlockOffset,
+    ref_frames: [RefType; 2], interp_filter: [FilterMode; 2],
+  ) {
+    let dirs = if fi.sequence.enable_dual_filter { 2 } else { 1 };
+    debug_assert!(dirs == 2 || interp_filter[0] == interp_filter[1]);
+    for (dir, &filter) in interp_filter.iter().enumerate().take(dirs) {
+      debug_assert!((filter as usize) < SWITCHABLE_FILTERS);
+      let ctx = self.get_interp_filter_ctx(bo, ref_frames, dir);
+      let cdf = &self.fc.switchable_interp_cdf[ctx];
+      symbol_with_update!(self, w, filter as u32, cdf);
+    }
+  }
+
   /// # Panics
   ///
   /// - If the MV is invalid
   pub fn write_mv<W: Writer>(
     &mut self, w: &mut W, mv: MotionVector, ref_mv: MotionVector,
     mv_precision: MvSubpelPrecision,
+  ) {
+    self.write_mv_with_context(w, mv, ref_mv, mv_precision, 0);
+  }
+
+  /// Writes an `IntraBC` displacement vector, which is always coded at
+  /// integer precision in its own set of MV CDFs.
+  ///
+  /// # Panics
+  ///
+  /// - If the vector is invalid or not integer
+  pub fn write_intrabc_mv<W: Writer>(
+    &mut self, w: &mut W, dv: MotionVector, ref_dv: MotionVector,
+  ) {
+    assert!(dv.row & 7 == 0 && dv.col & 7 == 0);
+    self.write_mv_with_context(
+      w,
+      dv,
+      ref_dv,
+      MvSubpelPrecision::MV_SUBPEL_NONE,
+      MV_INTRABC_CONTEXT,
+    );
+  }
+
+  fn write_mv_with_context<W: Writer>(
+    &mut self, w: &mut W, mv: MotionVector, ref_mv: MotionVector,
+    mv_precision: MvSubpelPrecision, mv_ctx: usize,
   ) {
     // <https://aomediacodec.github.io/av1-spec/#assign-mv-semantics>
     assert!(mv.is_valid());
@@ -1733,17 +2376,24 @@ impl ContextWriter<'_> {
       MotionVector { row: mv.row - ref_mv.row, col: mv.col - ref_mv.col };
     let j: MvJointType = av1_get_mv_joint(diff);
 
-    let cdf = &self.fc.nmv_context.joints_cdf;
+    let cdf = &self.fc.nmv_context[mv_ctx].joints_cdf;
     symbol_with_update!(self, w, j as u32, cdf);
 
     if mv_joint_vertical(j) {
-      self.encode_mv_component(w, diff.row as i32, 0, mv_precision);
+      self.encode_mv_component(w, diff.row as i32, 0, mv_precision, mv_ctx);
     }
     if mv_joint_horizontal(j) {
-      self.encode_mv_component(w, diff.col as i32, 1, mv_precision);
+      self.encode_mv_component(w, diff.col as i32, 1, mv_precision, mv_ctx);
     }
   }
 
+  pub fn write_use_intrabc<W: Writer>(
+    &mut self, w: &mut W, use_intrabc: bool,
+  ) {
+    let cdf = &self.fc.intrabc_cdf;
+    symbol_with_update!(self, w, use_intrabc as u32, cdf);
+  }
+
   pub fn write_block_deblock_deltas<W: Writer>(
     &mut self, w: &mut W, bo: TileBlockOffset, multi: bool, planes: usize,
   ) {
@@ -1784,7 +2434,7 @@ impl ContextWriter<'_> {
     &mut self, w: &mut W, plane: usize, bo: TileBlockOffset, coeffs_in: &[T],
     eob: u16, pred_mode: PredictionMode, tx_size: TxSize, tx_type: TxType,
     plane_bsize: BlockSize, xdec: usize, ydec: usize,
-    use_reduced_tx_set: bool, frame_clipped_txw: usize,
+    use_reduced_tx_set: bool, lossless: bool, frame_clipped_txw: usize,
     frame_clipped_txh: usize,
   ) -> bool {
     debug_assert!(frame_clipped_txw != 0);
@@ -1836,8 +2486,9 @@ impl ContextWriter<'_> {
     let tx_class = tx_type_to_class[tx_type as usize];
     let plane_type = usize::from(plane != 0);
 
-    // Signal tx_type for luma plane only
-    if plane == 0 {
+    // Signal tx_type for luma plane only, lossless blocks always use the
+    // Walsh-Hadamard transform
+    if plane == 0 && !lossless {
       self.write_tx_type(
         w,
         tx_size,
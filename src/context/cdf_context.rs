@@ -8,6 +8,8 @@
 // PATENTS file, you can obtain it at www.aomedia.org/license/patent.
 
 use super::*;
+use crate::mc::WEDGE_TYPES;
+use crate::predict::{InterIntraMode, MotionMode, PaletteSize};
 use std::marker::PhantomData;
 
 pub const CDF_LEN_MAX: usize = 16;
@@ -25,23 +27,32 @@ pub struct CDFContext {
   pub comp_mode_cdf: [[u16; 2]; COMP_INTER_CONTEXTS],
   pub comp_ref_cdf: [[[u16; 2]; FWD_REFS - 1]; REF_CONTEXTS],
   pub comp_ref_type_cdf: [[u16; 2]; COMP_REF_TYPE_CONTEXTS],
+  pub comp_group_idx_cdfs: [[u16; 2]; COMP_GROUP_IDX_CONTEXTS],
+  pub compound_idx_cdfs: [[u16; 2]; COMP_INDEX_CONTEXTS],
+  pub compound_type_cdf: [[u16; 2]; BlockSize::BLOCK_SIZES_ALL],
   pub dc_sign_cdf: [[[u16; 2]; DC_SIGN_CONTEXTS]; PLANE_TYPES],
   pub drl_cdfs: [[u16; 2]; DRL_MODE_CONTEXTS],
   pub eob_extra_cdf:
     [[[[u16; 2]; EOB_COEF_CONTEXTS]; PLANE_TYPES]; TxSize::TX_SIZES],
   pub filter_intra_cdfs: [[u16; 2]; BlockSize::BLOCK_SIZES_ALL],
   pub intra_inter_cdfs: [[u16; 2]; INTRA_INTER_CONTEXTS],
+  pub intrabc_cdf: [u16; 2],
+  pub interintra_cdf: [[u16; 2]; BLOCK_SIZE_GROUPS],
   pub lrf_sgrproj_cdf: [u16; 2],
   pub lrf_wiener_cdf: [u16; 2],
   pub newmv_cdf: [[u16; 2]; NEWMV_MODE_CONTEXTS],
+  pub obmc_cdf: [[u16; 2]; BlockSize::BLOCK_SIZES_ALL],
   pub palette_uv_mode_cdfs: [[u16; 2]; PALETTE_UV_MODE_CONTEXTS],
   pub palette_y_mode_cdfs:
     [[[u16; 2]; PALETTE_Y_MODE_CONTEXTS]; PALETTE_BSIZE_CTXS],
+  pub palette_color_idx_cdf2:
+    [[[u16; 2]; PALETTE_COLOR_INDEX_CONTEXTS]; PLANE_TYPES],
   pub refmv_cdf: [[u16; 2]; REFMV_MODE_CONTEXTS],
   pub single_ref_cdfs: [[[u16; 2]; SINGLE_REFS - 1]; REF_CONTEXTS],
   pub skip_cdfs: [[u16; 2]; SKIP_CONTEXTS],
   pub txb_skip_cdf: [[[u16; 2]; TXB_SKIP_CONTEXTS]; TxSize::TX_SIZES],
   pub txfm_partition_cdf: [[u16; 2]; TXFM_PARTITION_CONTEXTS],
+  pub wedge_interintra_cdf: [[u16; 2]; BlockSize::BLOCK_SIZES_ALL],
   pub zeromv_cdf: [[u16; 2]; GLOBALMV_MODE_CONTEXTS],
   pub tx_size_8x8_cdf: [[u16; MAX_TX_DEPTH]; TX_SIZE_CONTEXTS],
   pub inter_tx_3_cdf: [[u16; 2]; TX_SIZE_SQR_CONTEXTS],
@@ -49,6 +60,12 @@ pub struct CDFContext {
   pub coeff_base_eob_cdf:
     [[[[u16; 3]; SIG_COEF_CONTEXTS_EOB]; PLANE_TYPES]; TxSize::TX_SIZES],
   pub lrf_switchable_cdf: [u16; 3],
+  pub palette_color_idx_cdf3:
+    [[[u16; 3]; PALETTE_COLOR_INDEX_CONTEXTS]; PLANE_TYPES],
+  pub motion_mode_cdf:
+    [[u16; MotionMode::MOTION_MODES as usize]; BlockSize::BLOCK_SIZES_ALL],
+  pub switchable_interp_cdf:
+    [[u16; SWITCHABLE_FILTERS]; SWITCHABLE_FILTER_CONTEXTS],
   pub tx_size_cdf: [[[u16; MAX_TX_DEPTH + 1]; TX_SIZE_CONTEXTS]; BIG_TX_CATS],
 
   pub coeff_base_cdf:
@@ -57,20 +74,36 @@ pub struct CDFContext {
     [[[[u16; BR_CDF_SIZE]; LEVEL_CONTEXTS]; PLANE_TYPES]; TxSize::TX_SIZES],
   pub deblock_delta_cdf: [u16; DELTA_LF_PROBS + 1],
   pub deblock_delta_multi_cdf: [[u16; DELTA_LF_PROBS + 1]; FRAME_LF_COUNT],
+  pub interintra_mode_cdf:
+    [[u16; InterIntraMode::INTERINTRA_MODES as usize]; BLOCK_SIZE_GROUPS],
+  pub palette_color_idx_cdf4:
+    [[[u16; 4]; PALETTE_COLOR_INDEX_CONTEXTS]; PLANE_TYPES],
   pub partition_w8_cdf: [[u16; 4]; PARTITION_TYPES],
 
   pub eob_flag_cdf16: [[[u16; 5]; 2]; PLANE_TYPES],
   pub intra_tx_2_cdf: [[[u16; 5]; INTRA_MODES]; TX_SIZE_SQR_CONTEXTS],
+  pub palette_color_idx_cdf5:
+    [[[u16; 5]; PALETTE_COLOR_INDEX_CONTEXTS]; PLANE_TYPES],
 
   pub eob_flag_cdf32: [[[u16; 6]; 2]; PLANE_TYPES],
+  pub palette_color_idx_cdf6:
+    [[[u16; 6]; PALETTE_COLOR_INDEX_CONTEXTS]; PLANE_TYPES],
 
   pub angle_delta_cdf: [[u16; 2 * MAX_ANGLE_DELTA + 1]; DIRECTIONAL_MODES],
   pub eob_flag_cdf64: [[[u16; 7]; 2]; PLANE_TYPES],
   pub intra_tx_1_cdf: [[[u16; 7]; INTRA_MODES]; TX_SIZE_SQR_CONTEXTS],
+  pub palette_color_idx_cdf7:
+    [[[u16; 7]; PALETTE_COLOR_INDEX_CONTEXTS]; PLANE_TYPES],
+  pub palette_uv_size_cdfs:
+    [[u16; PaletteSize::PALETTE_SIZES as usize]; PALETTE_BSIZE_CTXS],
+  pub palette_y_size_cdfs:
+    [[u16; PaletteSize::PALETTE_SIZES as usize]; PALETTE_BSIZE_CTXS],
 
   pub cfl_sign_cdf: [u16; CFL_JOINT_SIGNS],
   pub compound_mode_cdf: [[u16; INTER_COMPOUND_MODES]; INTER_MODE_CONTEXTS],
   pub eob_flag_cdf128: [[[u16; 8]; 2]; PLANE_TYPES],
+  pub palette_color_idx_cdf8:
+    [[[u16; 8]; PALETTE_COLOR_INDEX_CONTEXTS]; PLANE_TYPES],
   pub spatial_segmentation_cdfs: [[u16; 8]; 3],
   pub partition_w128_cdf: [[u16; 8]; PARTITION_TYPES],
 
@@ -91,8 +124,9 @@ pub struct CDFContext {
 
   pub cfl_alpha_cdf: [[u16; CFL_ALPHABET_SIZE]; CFL_ALPHA_CONTEXTS],
   pub inter_tx_1_cdf: [[u16; TX_TYPES]; TX_SIZE_SQR_CONTEXTS],
+  pub wedge_idx_cdf: [[u16; WEDGE_TYPES]; BlockSize::BLOCK_SIZES_ALL],
 
-  pub nmv_context: NMVContext,
+  pub nmv_context: [NMVContext; MV_CONTEXTS],
 }
 
 pub struct CDFOffset<const CDF_LEN: usize> {
@@ -121,6 +155,9 @@ impl CDFContext {
       newmv_cdf: default_newmv_cdf,
       zeromv_cdf: default_zeromv_cdf,
       refmv_cdf: default_refmv_cdf,
+      motion_mode_cdf: default_motion_mode_cdf,
+      switchable_interp_cdf: default_switchable_interp_cdf,
+      obmc_cdf: default_obmc_cdf,
       intra_tx_2_cdf: default_intra_tx_2_cdf,
       intra_tx_1_cdf: default_intra_tx_1_cdf,
       inter_tx_3_cdf: default_inter_tx_3_cdf,
@@ -131,10 +168,20 @@ impl CDFContext {
       txfm_partition_cdf: default_txfm_partition_cdf,
       skip_cdfs: default_skip_cdfs,
       intra_inter_cdfs: default_intra_inter_cdf,
+      intrabc_cdf: default_intrabc_cdf,
       angle_delta_cdf: default_angle_delta_cdf,
       filter_intra_cdfs: default_filter_intra_cdfs,
       palette_y_mode_cdfs: default_palette_y_mode_cdfs,
       palette_uv_mode_cdfs: default_palette_uv_mode_cdfs,
+      palette_y_size_cdfs: default_palette_y_size_cdf,
+      palette_uv_size_cdfs: default_palette_uv_size_cdf,
+      palette_color_idx_cdf2: default_palette_color_idx_cdf2,
+      palette_color_idx_cdf3: default_palette_color_idx_cdf3,
+      palette_color_idx_cdf4: default_palette_color_idx_cdf4,
+      palette_color_idx_cdf5: default_palette_color_idx_cdf5,
+      palette_color_idx_cdf6: default_palette_color_idx_cdf6,
+      palette_color_idx_cdf7: default_palette_color_idx_cdf7,
+      palette_color_idx_cdf8: default_palette_color_idx_cdf8,
       comp_mode_cdf: default_comp_mode_cdf,
       comp_ref_type_cdf: default_comp_ref_type_cdf,
       comp_ref_cdf: default_comp_ref_cdf,
@@ -142,7 +189,14 @@ impl CDFContext {
       single_ref_cdfs: default_single_ref_cdf,
       drl_cdfs: default_drl_cdf,
       compound_mode_cdf: default_compound_mode_cdf,
-      nmv_context: default_nmv_context,
+      comp_group_idx_cdfs: default_comp_group_idx_cdfs,
+      compound_idx_cdfs: default_compound_idx_cdfs,
+      compound_type_cdf: default_compound_type_cdf,
+      wedge_idx_cdf: default_wedge_idx_cdf,
+      interintra_cdf: default_interintra_cdf,
+      interintra_mode_cdf: default_interintra_mode_cdf,
+      wedge_interintra_cdf: default_wedge_interintra_cdf,
+      nmv_context: [default_nmv_context; MV_CONTEXTS],
       deblock_delta_multi_cdf: default_delta_lf_multi_cdf,
       deblock_delta_cdf: default_delta_lf_cdf,
       spatial_segmentation_cdfs: default_spatial_pred_seg_tree_cdf,
@@ -212,6 +266,9 @@ impl CDFContext {
     reset_2d!(self.newmv_cdf);
     reset_2d!(self.zeromv_cdf);
     reset_2d!(self.refmv_cdf);
+    reset_2d!(self.motion_mode_cdf);
+    reset_2d!(self.switchable_interp_cdf);
+    reset_2d!(self.obmc_cdf);
 
     reset_3d!(self.intra_tx_2_cdf);
     reset_3d!(self.intra_tx_1_cdf);
@@ -227,10 +284,20 @@ impl CDFContext {
 
     reset_2d!(self.skip_cdfs);
     reset_2d!(self.intra_inter_cdfs);
+    reset_1d!(self.intrabc_cdf);
     reset_2d!(self.angle_delta_cdf);
     reset_2d!(self.filter_intra_cdfs);
     reset_3d!(self.palette_y_mode_cdfs);
     reset_2d!(self.palette_uv_mode_cdfs);
+    reset_2d!(self.palette_y_size_cdfs);
+    reset_2d!(self.palette_uv_size_cdfs);
+    reset_3d!(self.palette_color_idx_cdf2);
+    reset_3d!(self.palette_color_idx_cdf3);
+    reset_3d!(self.palette_color_idx_cdf4);
+    reset_3d!(self.palette_color_idx_cdf5);
+    reset_3d!(self.palette_color_idx_cdf6);
+    reset_3d!(self.palette_color_idx_cdf7);
+    reset_3d!(self.palette_color_idx_cdf8);
     reset_2d!(self.comp_mode_cdf);
     reset_2d!(self.comp_ref_type_cdf);
     reset_3d!(self.comp_ref_cdf);
@@ -238,6 +305,13 @@ impl CDFContext {
     reset_3d!(self.single_ref_cdfs);
     reset_2d!(self.drl_cdfs);
     reset_2d!(self.compound_mode_cdf);
+    reset_2d!(self.comp_group_idx_cdfs);
+    reset_2d!(self.compound_idx_cdfs);
+    reset_2d!(self.compound_type_cdf);
+    reset_2d!(self.wedge_idx_cdf);
+    reset_2d!(self.interintra_cdf);
+    reset_2d!(self.interintra_mode_cdf);
+    reset_2d!(self.wedge_interintra_cdf);
     reset_2d!(self.deblock_delta_multi_cdf);
     reset_1d!(self.deblock_delta_cdf);
     reset_2d!(self.spatial_segmentation_cdfs);
@@ -245,16 +319,18 @@ impl CDFContext {
     reset_1d!(self.lrf_sgrproj_cdf);
     reset_1d!(self.lrf_wiener_cdf);
 
-    reset_1d!(self.nmv_context.joints_cdf);
-    for i in 0..2 {
-      reset_1d!(self.nmv_context.comps[i].classes_cdf);
-      reset_2d!(self.nmv_context.comps[i].class0_fp_cdf);
-      reset_1d!(self.nmv_context.comps[i].fp_cdf);
-      reset_1d!(self.nmv_context.comps[i].sign_cdf);
-      reset_1d!(self.nmv_context.comps[i].class0_hp_cdf);
-      reset_1d!(self.nmv_context.comps[i].hp_cdf);
-      reset_1d!(self.nmv_context.comps[i].class0_cdf);
-      reset_2d!(self.nmv_context.comps[i].bits_cdf);
+    for nmv_context in self.nmv_context.iter_mut() {
+      reset_1d!(nmv_context.joints_cdf);
+      for i in 0..2 {
+        reset_1d!(nmv_context.comps[i].classes_cdf);
+        reset_2d!(nmv_context.comps[i].class0_fp_cdf);
+        reset_1d!(nmv_context.comps[i].fp_cdf);
+        reset_1d!(nmv_context.comps[i].sign_cdf);
+        reset_1d!(nmv_context.comps[i].class0_hp_cdf);
+        reset_1d!(nmv_context.comps[i].hp_cdf);
+        reset_1d!(nmv_context.comps[i].class0_cdf);
+        reset_2d!(nmv_context.comps[i].bits_cdf);
+      }
     }
 
     // lv_map
@@ -318,6 +394,16 @@ impl CDFContext {
     let zeromv_cdf_end = zeromv_cdf_start + size_of_val(&self.zeromv_cdf);
     let refmv_cdf_start = self.refmv_cdf.first().unwrap().as_ptr() as usize;
     let refmv_cdf_end = refmv_cdf_start + size_of_val(&self.refmv_cdf);
+    let motion_mode_cdf_start =
+      self.motion_mode_cdf.first().unwrap().as_ptr() as usize;
+    let motion_mode_cdf_end =
+      motion_mode_cdf_start + size_of_val(&self.motion_mode_cdf);
+    let switchable_interp_cdf_start =
+      self.switchable_interp_cdf.first().unwrap().as_ptr() as usize;
+    let switchable_interp_cdf_end =
+      switchable_interp_cdf_start + size_of_val(&self.switchable_interp_cdf);
+    let obmc_cdf_start = self.obmc_cdf.first().unwrap().as_ptr() as usize;
+    let obmc_cdf_end = obmc_cdf_start + size_of_val(&self.obmc_cdf);
     let intra_tx_2_cdf_start =
       self.intra_tx_2_cdf.first().unwrap().as_ptr() as usize;
     let intra_tx_2_cdf_end =
@@ -355,6 +441,8 @@ impl CDFContext {
       self.intra_inter_cdfs.first().unwrap().as_ptr() as usize;
     let intra_inter_cdfs_end =
       intra_inter_cdfs_start + size_of_val(&self.intra_inter_cdfs);
+    let intrabc_cdf_start = self.intrabc_cdf.as_ptr() as usize;
+    let intrabc_cdf_end = intrabc_cdf_start + size_of_val(&self.intrabc_cdf);
     let angle_delta_cdf_start =
       self.angle_delta_cdf.first().unwrap().as_ptr() as usize;
     let angle_delta_cdf_end =
@@ -371,6 +459,42 @@ impl CDFContext {
       self.palette_uv_mode_cdfs.first().unwrap().as_ptr() as usize;
     let palette_uv_mode_cdfs_end =
       palette_uv_mode_cdfs_start + size_of_val(&self.palette_uv_mode_cdfs);
+    let palette_y_size_cdfs_start =
+      self.palette_y_size_cdfs.first().unwrap().as_ptr() as usize;
+    let palette_y_size_cdfs_end =
+      palette_y_size_cdfs_start + size_of_val(&self.palette_y_size_cdfs);
+    let palette_uv_size_cdfs_start =
+      self.palette_uv_size_cdfs.first().unwrap().as_ptr() as usize;
+    let palette_uv_size_cdfs_end =
+      palette_uv_size_cdfs_start + size_of_val(&self.palette_uv_size_cdfs);
+    let palette_color_idx_cdf2_start =
+      self.palette_color_idx_cdf2.first().unwrap().as_ptr() as usize;
+    let palette_color_idx_cdf2_end =
+      palette_color_idx_cdf2_start + size_of_val(&self.palette_color_idx_cdf2);
+    let palette_color_idx_cdf3_start =
+      self.palette_color_idx_cdf3.first().unwrap().as_ptr() as usize;
+    let palette_color_idx_cdf3_end =
+      palette_color_idx_cdf3_start + size_of_val(&self.palette_color_idx_cdf3);
+    let palette_color_idx_cdf4_start =
+      self.palette_color_idx_cdf4.first().unwrap().as_ptr() as usize;
+    let palette_color_idx_cdf4_end =
+      palette_color_idx_cdf4_start + size_of_val(&self.palette_color_idx_cdf4);
+    let palette_color_idx_cdf5_start =
+      self.palette_color_idx_cdf5.first().unwrap().as_ptr() as usize;
+    let palette_color_idx_cdf5_end =
+      palette_color_idx_cdf5_start + size_of_val(&self.palette_color_idx_cdf5);
+    let palette_color_idx_cdf6_start =
+      self.palette_color_idx_cdf6.first().unwrap().as_ptr() as usize;
+    let palette_color_idx_cdf6_end =
+      palette_color_idx_cdf6_start + size_of_val(&self.palette_color_idx_cdf6);
+    let palette_color_idx_cdf7_start =
+      self.palette_color_idx_cdf7.first().unwrap().as_ptr() as usize;
+    let palette_color_idx_cdf7_end =
+      palette_color_idx_cdf7_start + size_of_val(&self.palette_color_idx_cdf7);
+    let palette_color_idx_cdf8_start =
+      self.palette_color_idx_cdf8.first().unwrap().as_ptr() as usize;
+    let palette_color_idx_cdf8_end =
+      palette_color_idx_cdf8_start + size_of_val(&self.palette_color_idx_cdf8);
     let comp_mode_cdf_start =
       self.comp_mode_cdf.first().unwrap().as_ptr() as usize;
     let comp_mode_cdf_end =
@@ -397,7 +521,35 @@ impl CDFContext {
       self.compound_mode_cdf.first().unwrap().as_ptr() as usize;
     let compound_mode_cdf_end =
       compound_mode_cdf_start + size_of_val(&self.compound_mode_cdf);
-    let nmv_context_start = &self.nmv_context as *const NMVContext as usize;
+    let comp_group_idx_cdfs_start =
+      self.comp_group_idx_cdfs.first().unwrap().as_ptr() as usize;
+    let comp_group_idx_cdfs_end =
+      comp_group_idx_cdfs_start + size_of_val(&self.comp_group_idx_cdfs);
+    let compound_idx_cdfs_start =
+      self.compound_idx_cdfs.first().unwrap().as_ptr() as usize;
+    let compound_idx_cdfs_end =
+      compound_idx_cdfs_start + size_of_val(&self.compound_idx_cdfs);
+    let compound_type_cdf_start =
+      self.compound_type_cdf.first().unwrap().as_ptr() as usize;
+    let compound_type_cdf_end =
+      compound_type_cdf_start + size_of_val(&self.compound_type_cdf);
+    let wedge_idx_cdf_start =
+      self.wedge_idx_cdf.first().unwrap().as_ptr() as usize;
+    let wedge_idx_cdf_end =
+      wedge_idx_cdf_start + size_of_val(&self.wedge_idx_cdf);
+    let interintra_cdf_start =
+      self.interintra_cdf.first().unwrap().as_ptr() as usize;
+    let interintra_cdf_end =
+      interintra_cdf_start + size_of_val(&self.interintra_cdf);
+    let interintra_mode_cdf_start =
+      self.interintra_mode_cdf.first().unwrap().as_ptr() as usize;
+    let interintra_mode_cdf_end =
+      interintra_mode_cdf_start + size_of_val(&self.interintra_mode_cdf);
+    let wedge_interintra_cdf_start =
+      self.wedge_interintra_cdf.first().unwrap().as_ptr() as usize;
+    let wedge_interintra_cdf_end =
+      wedge_interintra_cdf_start + size_of_val(&self.wedge_interintra_cdf);
+    let nmv_context_start = self.nmv_context.as_ptr() as usize;
     let nmv_context_end = nmv_context_start + size_of_val(&self.nmv_context);
     let deblock_delta_multi_cdf_start =
       self.deblock_delta_multi_cdf.first().unwrap().as_ptr() as usize;
@@ -485,6 +637,13 @@ impl CDFContext {
       ("newmv_cdf", newmv_cdf_start, newmv_cdf_end),
       ("zeromv_cdf", zeromv_cdf_start, zeromv_cdf_end),
       ("refmv_cdf", refmv_cdf_start, refmv_cdf_end),
+      ("motion_mode_cdf", motion_mode_cdf_start, motion_mode_cdf_end),
+      (
+        "switchable_interp_cdf",
+        switchable_interp_cdf_start,
+        switchable_interp_cdf_end,
+      ),
+      ("obmc_cdf", obmc_cdf_start, obmc_cdf_end),
       ("intra_tx_2_cdf", intra_tx_2_cdf_start, intra_tx_2_cdf_end),
       ("intra_tx_1_cdf", intra_tx_1_cdf_start, intra_tx_1_cdf_end),
       ("inter_tx_3_cdf", inter_tx_3_cdf_start, inter_tx_3_cdf_end),
@@ -495,6 +654,7 @@ impl CDFContext {
       ("txfm_partition_cdf", txfm_partition_cdf_start, txfm_partition_cdf_end),
       ("skip_cdfs", skip_cdfs_start, skip_cdfs_end),
       ("intra_inter_cdfs", intra_inter_cdfs_start, intra_inter_cdfs_end),
+      ("intrabc_cdf", intrabc_cdf_start, intrabc_cdf_end),
       ("angle_delta_cdf", angle_delta_cdf_start, angle_delta_cdf_end),
       ("filter_intra_cdfs", filter_intra_cdfs_start, filter_intra_cdfs_end),
       (
@@ -507,6 +667,51 @@ impl CDFContext {
         palette_uv_mode_cdfs_start,
         palette_uv_mode_cdfs_end,
       ),
+      (
+        "palette_y_size_cdfs",
+        palette_y_size_cdfs_start,
+        palette_y_size_cdfs_end,
+      ),
+      (
+        "palette_uv_size_cdfs",
+        palette_uv_size_cdfs_start,
+        palette_uv_size_cdfs_end,
+      ),
+      (
+        "palette_color_idx_cdf2",
+        palette_color_idx_cdf2_start,
+        palette_color_idx_cdf2_end,
+      ),
+      (
+        "palette_color_idx_cdf3",
+        palette_color_idx_cdf3_start,
+        palette_color_idx_cdf3_end,
+      ),
+      (
+        "palette_color_idx_cdf4",
+        palette_color_idx_cdf4_start,
+        palette_color_idx_cdf4_end,
+      ),
+      (
+        "palette_color_idx_cdf5",
+        palette_color_idx_cdf5_start,
+        palette_color_idx_cdf5_end,
+      ),
+      (
+        "palette_color_idx_cdf6",
+        palette_color_idx_cdf6_start,
+        palette_color_idx_cdf6_end,
+      ),
+      (
+        "palette_color_idx_cdf7",
+        palette_color_idx_cdf7_start,
+        palette_color_idx_cdf7_end,
+      ),
+      (
+        "palette_color_idx_cdf8",
+        palette_color_idx_cdf8_start,
+        palette_color_idx_cdf8_end,
+      ),
       ("comp_mode_cdf", comp_mode_cdf_start, comp_mode_cdf_end),
       ("comp_ref_type_cdf", comp_ref_type_cdf_start, comp_ref_type_cdf_end),
       ("comp_ref_cdf", comp_ref_cdf_start, comp_ref_cdf_end),
@@ -514,6 +719,25 @@ impl CDFContext {
       ("single_ref_cdfs", single_ref_cdfs_start, single_ref_cdfs_end),
       ("drl_cdfs", drl_cdfs_start, drl_cdfs_end),
       ("compound_mode_cdf", compound_mode_cdf_start, compound_mode_cdf_end),
+      (
+        "comp_group_idx_cdfs",
+        comp_group_idx_cdfs_start,
+        comp_group_idx_cdfs_end,
+      ),
+      ("compound_idx_cdfs", compound_idx_cdfs_start, compound_idx_cdfs_end),
+      ("compound_type_cdf", compound_type_cdf_start, compound_type_cdf_end),
+      ("wedge_idx_cdf", wedge_idx_cdf_start, wedge_idx_cdf_end),
+      ("interintra_cdf", interintra_cdf_start, interintra_cdf_end),
+      (
+        "interintra_mode_cdf",
+        interintra_mode_cdf_start,
+        interintra_mode_cdf_end,
+      ),
+      (
+        "wedge_interintra_cdf",
+        wedge_interintra_cdf_start,
+        wedge_interintra_cdf_end,
+      ),
       ("nmv_context", nmv_context_start, nmv_context_end),
       (
         "deblock_delta_multi_cdf",
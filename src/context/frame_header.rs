@@ -174,93 +174,116 @@ impl ContextWriter<'_> {
   ) {
     let rp = &mut rs.planes[pli];
     if let Some(filter) = rp.restoration_unit(sbo, true).map(|ru| ru.filter) {
-      match filter {
-        RestorationFilter::None => match rp.rp_cfg.lrf_type {
-          RESTORE_WIENER => {
-            let cdf = &self.fc.lrf_wiener_cdf;
-            symbol_with_update!(self, w, 0, cdf);
-          }
+      self.write_lrf_unit(w, rp, filter, pli);
+    }
+  }
+
+  /// Writes the restoration units coded in a superblock of a frame using
+  /// super-resolution. `tile_sbo` is the offset of the tile in the frame.
+  pub fn write_lrf_superres<W: Writer>(
+    &mut self, w: &mut W, rs: &mut TileRestorationStateMut,
+    tile_sbo: PlaneSuperBlockOffset, sbo: TileSuperBlockOffset, pli: usize,
+  ) {
+    let rp = &mut rs.planes[pli];
+    let (cols, rows) = rp.superres_units(tile_sbo, sbo);
+    for y in rows {
+      for x in cols.clone() {
+        let filter = rp.units[y][x].filter;
+        self.write_lrf_unit(w, rp, filter, pli);
+      }
+    }
+  }
+
+  fn write_lrf_unit<W: Writer>(
+    &mut self, w: &mut W, rp: &mut TileRestorationPlaneMut,
+    filter: RestorationFilter, pli: usize,
+  ) {
+    match filter {
+      RestorationFilter::None => match rp.rp_cfg.lrf_type {
+        RESTORE_WIENER => {
+          let cdf = &self.fc.lrf_wiener_cdf;
+          symbol_with_update!(self, w, 0, cdf);
+        }
+        RESTORE_SGRPROJ => {
+          let cdf = &self.fc.lrf_sgrproj_cdf;
+          symbol_with_update!(self, w, 0, cdf);
+        }
+        RESTORE_SWITCHABLE => {
+          let cdf = &self.fc.lrf_switchable_cdf;
+          symbol_with_update!(self, w, 0, cdf);
+        }
+        RESTORE_NONE => {}
+        _ => unreachable!(),
+      },
+      RestorationFilter::Sgrproj { set, xqd } => {
+        match rp.rp_cfg.lrf_type {
           RESTORE_SGRPROJ => {
             let cdf = &self.fc.lrf_sgrproj_cdf;
-            symbol_with_update!(self, w, 0, cdf);
+            symbol_with_update!(self, w, 1, cdf);
           }
           RESTORE_SWITCHABLE => {
+            // Does *not* write 'RESTORE_SGRPROJ'
             let cdf = &self.fc.lrf_switchable_cdf;
-            symbol_with_update!(self, w, 0, cdf);
+            symbol_with_update!(self, w, 2, cdf);
           }
-          RESTORE_NONE => {}
           _ => unreachable!(),
-        },
-        RestorationFilter::Sgrproj { set, xqd } => {
-          match rp.rp_cfg.lrf_type {
-            RESTORE_SGRPROJ => {
-              let cdf = &self.fc.lrf_sgrproj_cdf;
-              symbol_with_update!(self, w, 1, cdf);
-            }
-            RESTORE_SWITCHABLE => {
-              // Does *not* write 'RESTORE_SGRPROJ'
-              let cdf = &self.fc.lrf_switchable_cdf;
-              symbol_with_update!(self, w, 2, cdf);
-            }
-            _ => unreachable!(),
-          }
-          w.literal(SGRPROJ_PARAMS_BITS, set as u32);
-          for i in 0..2 {
-            let s = SGRPROJ_PARAMS_S[set as usize][i];
-            let min = SGRPROJ_XQD_MIN[i] as i32;
-            let max = SGRPROJ_XQD_MAX[i] as i32;
-            if s > 0 {
-              w.write_signed_subexp_with_ref(
-                xqd[i] as i32,
-                min,
-                max + 1,
-                SGRPROJ_PRJ_SUBEXP_K,
-                rp.sgrproj_ref[i] as i32,
-              );
-              rp.sgrproj_ref[i] = xqd[i];
+        }
+        w.literal(SGRPROJ_PARAMS_BITS, set as u32);
+        for i in 0..2 {
+          let s = SGRPROJ_PARAMS_S[set as usize][i];
+          let min = SGRPROJ_XQD_MIN[i] as i32;
+          let max = SGRPROJ_XQD_MAX[i] as i32;
+          if s > 0 {
+            w.write_signed_subexp_with_ref(
+              xqd[i] as i32,
+              min,
+              max + 1,
+              SGRPROJ_PRJ_SUBEXP_K,
+              rp.sgrproj_ref[i] as i32,
+            );
+            rp.sgrproj_ref[i] = xqd[i];
+          } else {
+            // Nothing written, just update the reference
+            if i == 0 {
+              assert!(xqd[i] == 0);
+              rp.sgrproj_ref[0] = 0;
             } else {
-              // Nothing written, just update the reference
-              if i == 0 {
-                assert!(xqd[i] == 0);
-                rp.sgrproj_ref[0] = 0;
-              } else {
-                rp.sgrproj_ref[1] = 95; // LOL at spec.  The result is always 95.
-              }
+              rp.sgrproj_ref[1] = 95; // LOL at spec.  The result is always 95.
             }
           }
         }
-        RestorationFilter::Wiener { coeffs } => {
-          match rp.rp_cfg.lrf_type {
-            RESTORE_WIENER => {
-              let cdf = &self.fc.lrf_wiener_cdf;
-              symbol_with_update!(self, w, 1, cdf);
-            }
-            RESTORE_SWITCHABLE => {
-              // Does *not* write 'RESTORE_WIENER'
-              let cdf = &self.fc.lrf_switchable_cdf;
-              symbol_with_update!(self, w, 1, cdf);
-            }
-            _ => unreachable!(),
+      }
+      RestorationFilter::Wiener { coeffs } => {
+        match rp.rp_cfg.lrf_type {
+          RESTORE_WIENER => {
+            let cdf = &self.fc.lrf_wiener_cdf;
+            symbol_with_update!(self, w, 1, cdf);
           }
-          for pass in 0..2 {
-            let first_coeff = if pli == 0 {
-              0
-            } else {
-              assert!(coeffs[pass][0] == 0);
-              1
-            };
-            for i in first_coeff..3 {
-              let min = WIENER_TAPS_MIN[i] as i32;
-              let max = WIENER_TAPS_MAX[i] as i32;
-              w.write_signed_subexp_with_ref(
-                coeffs[pass][i] as i32,
-                min,
-                max + 1,
-                (i + 1) as u8,
-                rp.wiener_ref[pass][i] as i32,
-              );
-              rp.wiener_ref[pass][i] = coeffs[pass][i];
-            }
+          RESTORE_SWITCHABLE => {
+            // Does *not* write 'RESTORE_WIENER'
+            let cdf = &self.fc.lrf_switchable_cdf;
+            symbol_with_update!(self, w, 1, cdf);
+          }
+          _ => unreachable!(),
+        }
+        for pass in 0..2 {
+          let first_coeff = if pli == 0 {
+            0
+          } else {
+            assert!(coeffs[pass][0] == 0);
+            1
+          };
+          for i in first_coeff..3 {
+            let min = WIENER_TAPS_MIN[i] as i32;
+            let max = WIENER_TAPS_MAX[i] as i32;
+            w.write_signed_subexp_with_ref(
+              coeffs[pass][i] as i32,
+              min,
+              max + 1,
+              (i + 1) as u8,
+              rp.wiener_ref[pass][i] as i32,
+            );
+            rp.wiener_ref[pass][i] = coeffs[pass][i];
           }
         }
       }
@@ -7,6 +7,10 @@
 // Media Patent License 1.0 was not distributed with this source code in the
 // PATENTS file, you can obtain it at www.aomedia.org/license/patent.
 
+use crate::frame::{AsRegion, Plane};
+use crate::tiling::Area;
+use crate::util::{CastFromPrimitive, Pixel};
+
 cfg_if::cfg_if! {
   if #[cfg(nasm_x86_64)] {
     pub use crate::asm::x86::dist::*;
@@ -372,6 +376,77 @@ pub(crate) mod rust {
   }
 }
 
+/// Computes the PSNR of the luma plane `rec` against `src` over the top left
+/// `w`x`h` pixels, in dB. Identical planes are capped at 100 dB.
+pub(crate) fn frame_psnr<T: Pixel>(
+  src: &Plane<T>, rec: &Plane<T>, w: usize, h: usize, bit_depth: usize,
+) -> f64 {
+  let sse: u64 = src
+    .rows_iter()
+    .zip(rec.rows_iter())
+    .take(h)
+    .map(|(src_row, rec_row)| {
+      src_row[..w]
+        .iter()
+        .zip(&rec_row[..w])
+        .map(|(&a, &b)| {
+          let c = i32::cast_from(a) - i32::cast_from(b);
+          (c * c) as u64
+        })
+        .sum::<u64>()
+    })
+    .sum();
+  if sse == 0 {
+    return 100.;
+  }
+  let max = ((1 << bit_depth) - 1) as f64;
+  (10. * (max * max * (w * h) as f64 / sse as f64).log10()).min(100.)
+}
+
+/// Computes the mean SSIM of the luma plane `rec` against `src` over the top
+/// left `w`x`h` pixels, using 8x8 windows overlapping by half.
+pub(crate) fn frame_ssim<T: Pixel>(
+  src: &Plane<T>, rec: &Plane<T>, w: usize, h: usize, bit_depth: usize,
+) -> f64 {
+  let max = ((1 << bit_depth) - 1) as f64;
+  let c1 = (0.01 * max) * (0.01 * max);
+  let c2 = (0.03 * max) * (0.03 * max);
+  let size = 8.min(w).min(h);
+  let n = (size * size) as f64;
+  let src = src.region(Area::Rect { x: 0, y: 0, width: w, height: h });
+  let rec = rec.region(Area::Rect { x: 0, y: 0, width: w, height: h });
+
+  let mut ssim_sum = 0.;
+  let mut count = 0;
+  for y in (0..=h - size).step_by((size >> 1).max(1)) {
+    for x in (0..=w - size).step_by((size >> 1).max(1)) {
+      // Same summations as cdef_dist_kernel.
+      let (mut sum_s, mut sum_d) = (0u64, 0u64);
+      let (mut sum_s2, mut sum_d2, mut sum_sd) = (0u64, 0u64, 0u64);
+      for (row1, row2) in (y..y + size).map(|i| (&src[i], &rec[i])) {
+        for (&s, &d) in row1[x..x + size].iter().zip(&row2[x..x + size]) {
+          let s = u32::cast_from(s) as u64;
+          let d = u32::cast_from(d) as u64;
+          sum_s += s;
+          sum_d += d;
+          sum_s2 += s * s;
+          sum_d2 += d * d;
+          sum_sd += s * d;
+        }
+      }
+      let mean_s = sum_s as f64 / n;
+      let mean_d = sum_d as f64 / n;
+      let var_s = sum_s2 as f64 / n - mean_s * mean_s;
+      let var_d = sum_d2 as f64 / n - mean_d * mean_d;
+      let cov = sum_sd as f64 / n - mean_s * mean_d;
+      ssim_sum += ((2. * mean_s * mean_d + c1) * (2. * cov + c2))
+        / ((mean_s * mean_s + mean_d * mean_d + c1) * (var_s + var_d + c2));
+      count += 1;
+    }
+  }
+  ssim_sum / count as f64
+}
+
 #[cfg(test)]
 pub mod test {
   use super::*;
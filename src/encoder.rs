@@ -8,7 +8,6 @@
 // PATENTS file, you can obtain it at www.aomedia.org/license/patent.
 
 use std::collections::VecDeque;
-use std::io::Write;
 use std::mem::MaybeUninit;
 use std::sync::Arc;
 use std::{fmt, io, mem};
@@ -26,26 +25,37 @@ use crate::deblock::*;
 use crate::ec::*;
 use crate::frame::*;
 use crate::header::*;
+use crate::intrabc::*;
+use crate::levels::auto_level_idx;
 use crate::lrf::*;
-use crate::mc::{FilterMode, MotionVector};
+use crate::mc::{
+  blend_obmc_above, blend_obmc_left, interintra_allowed, FilterMode,
+  MotionVector,
+};
 use crate::me::*;
+use crate::palette::*;
 use crate::partition::PartitionType::*;
 use crate::partition::RefType::*;
 use crate::partition::*;
 use crate::predict::{
-  luma_ac, AngleDelta, IntraEdgeFilterParameters, IntraParam, PredictionMode,
+  luma_ac, predict_interintra, AngleDelta, CompoundParams,
+  IntraEdgeFilterParameters, IntraParam, MotionMode, PredictionMode,
 };
 use crate::quantize::*;
 use crate::rate::{
   QuantizerParameters, FRAME_SUBTYPE_I, FRAME_SUBTYPE_P, QSCALE,
+  VBV_INITIAL_FULLNESS_Q8,
 };
 use crate::rdo::*;
+use crate::scenechange::ScreenContentStats;
 use crate::segmentation::*;
 use crate::serialize::{Deserialize, Serialize};
 use crate::stats::EncoderStats;
+use crate::superres::*;
 use crate::tiling::*;
 use crate::transform::*;
 use crate::util::*;
+use crate::warp::*;
 use crate::wasm_bindgen::*;
 
 #[allow(dead_code)]
@@ -86,6 +96,7 @@ pub struct ReferenceFrame<T: Pixel> {
   pub frame_me_stats: RefMEStats,
   pub output_frameno: u64,
   pub segmentation: SegmentationState,
+  pub gm_params: [[i32; 6]; INTER_REFS_PER_FRAME],
 }
 
 #[derive(Debug, Clone, Default)]
@@ -189,6 +200,12 @@ pub struct Sequence {
   pub operating_point_idc: [u16; MAX_NUM_OPERATING_POINTS],
   pub display_model_info_present_flag: bool,
   pub decoder_model_info_present_flag: bool,
+  /// Initial decoder buffer delay of the first operating point, in units of
+  /// 1/90000 seconds.
+  pub decoder_buffer_delay: u32,
+  /// Initial encoder buffer delay of the first operating point, in units of
+  /// 1/90000 seconds.
+  pub encoder_buffer_delay: u32,
   pub level_idx: [u8; MAX_NUM_OPERATING_POINTS],
   /// `seq_tier` in the spec. One bit: 0 or 1.
   pub tier: [usize; MAX_NUM_OPERATING_POINTS],
@@ -216,17 +233,40 @@ impl Sequence {
       u8::from(config.chroma_sampling == ChromaSampling::Cs444)
     };
 
-    let operating_point_idc: [u16; MAX_NUM_OPERATING_POINTS] =
+    // Operating point i decodes the temporal layers 0..(temporal_layers - i)
+    //  of the only spatial layer; a single layer needs no operating points.
+    let temporal_layers = config.temporal_layers as usize;
+    let mut operating_point_idc: [u16; MAX_NUM_OPERATING_POINTS] =
       [0; MAX_NUM_OPERATING_POINTS];
-    let level_idx: [u8; MAX_NUM_OPERATING_POINTS] =
-      if let Some(level_idx) = config.level_idx {
-        [level_idx; MAX_NUM_OPERATING_POINTS]
-      } else {
-        [31; MAX_NUM_OPERATING_POINTS]
-      };
+    if temporal_layers > 1 {
+      for (i, idc) in
+        operating_point_idc.iter_mut().take(temporal_layers).enumerate()
+      {
+        *idc = (1 << 8) | ((1 << (temporal_layers - i)) - 1);
+      }
+    }
     let tier: [usize; MAX_NUM_OPERATING_POINTS] =
       [0; MAX_NUM_OPERATING_POINTS];
 
+    // The decoder model starts removing data once the buffer reaches its
+    //  initial fullness, which takes the decoder buffer delay at peak rate;
+    //  the encoder buffer delay covers the rest of the buffer.
+    let (decoder_buffer_delay, encoder_buffer_delay) =
+      if config.max_bitrate > 0 {
+        let buffer_size = config.decoder_buffer_size();
+        let delay = |bits: i64| {
+          (bits * 90_000 / config.max_bitrate as i64).clamp(1, u32::MAX as i64)
+            as u32
+        };
+        let decoder_buffer_delay =
+          delay((buffer_size * VBV_INITIAL_FULLNESS_Q8) >> 8);
+        let encoder_buffer_delay =
+          delay(buffer_size).saturating_sub(decoder_buffer_delay).max(1);
+        (decoder_buffer_delay, encoder_buffer_delay)
+      } else {
+        (0, 0)
+      };
+
     // Restoration filters are not useful for very small frame sizes,
     // so disable them in that case.
     let enable_restoration_filters = config.width >= 32 && config.height >= 32;
@@ -276,6 +316,15 @@ impl Sequence {
       }
     }
 
+    let level_idx: [u8; MAX_NUM_OPERATING_POINTS] =
+      [config.level_idx.unwrap_or_else(|| {
+        if config.auto_level {
+          auto_level_idx(config, &tiling, profile)
+        } else {
+          31
+        }
+      }); MAX_NUM_OPERATING_POINTS];
+
     Sequence {
       tiling,
       profile,
@@ -295,29 +344,43 @@ impl Sequence {
       delta_frame_id_length: DELTA_FRAME_ID_LENGTH,
       use_128x128_superblock,
       order_hint_bits_minus_1: 5,
-      force_screen_content_tools: if config.still_picture { 2 } else { 0 },
+      force_screen_content_tools: if config.still_picture
+        || config.screen_content != ScreenContentMode::Off
+      {
+        2
+      } else {
+        0
+      },
       force_integer_mv: 2,
       still_picture: config.still_picture,
       reduced_still_picture_hdr: config.still_picture,
       enable_filter_intra: false,
       enable_intra_edge_filter: true,
-      enable_interintra_compound: false,
-      enable_masked_compound: false,
-      enable_dual_filter: false,
+      enable_interintra_compound: config.speed_settings.motion.interintra
+        && !config.still_picture,
+      enable_masked_compound: config.speed_settings.motion.masked_compound
+        && !config.still_picture,
+      enable_dual_filter: config.speed_settings.motion.interp_filter_search
+        >= InterpFilterSearch::Separable
+        && !config.still_picture,
       enable_order_hint: !config.still_picture,
-      enable_jnt_comp: false,
+      enable_jnt_comp: config.speed_settings.motion.distance_weighted_compound
+        && !config.still_picture,
       enable_ref_frame_mvs: false,
-      enable_warped_motion: false,
-      enable_superres: false,
+      enable_warped_motion: config.speed_settings.motion.motion_modes
+        && !config.still_picture,
+      enable_superres: config.superres_mode != SuperresMode::None,
       enable_cdef: config.speed_settings.cdef && enable_restoration_filters,
       enable_restoration: config.speed_settings.lrf
         && enable_restoration_filters,
       enable_large_lru: true,
       enable_delayed_loopfilter_rdo: true,
-      operating_points_cnt_minus_1: 0,
+      operating_points_cnt_minus_1: temporal_layers - 1,
       operating_point_idc,
       display_model_info_present_flag: false,
-      decoder_model_info_present_flag: false,
+      decoder_model_info_present_flag: config.enable_decoder_model_info,
+      decoder_buffer_delay,
+      encoder_buffer_delay,
       level_idx,
       tier,
       film_grain_params_present: config
@@ -605,6 +668,12 @@ pub struct FrameInvariants<T: Pixel> {
   pub config: Arc<EncoderConfig>,
   pub width: usize,
   pub height: usize,
+  /// Frame width after super-resolution upscaling, equal to `width` when the
+  /// frame is coded at full width.
+  pub upscaled_width: usize,
+  /// Super-resolution denominator, `SUPERRES_NUM` when the frame is coded
+  /// at full width.
+  pub superres_denom: usize,
   pub render_width: u32,
   pub render_height: u32,
   pub frame_size_override_flag: bool,
@@ -614,6 +683,10 @@ pub struct FrameInvariants<T: Pixel> {
   pub w_in_b: usize,
   pub h_in_b: usize,
   pub input_frameno: u64,
+  /// Presentation timestamp of the frame, in `time_base` units.
+  pub pts: u64,
+  /// Duration of the frame, in `time_base` units.
+  pub duration: u64,
   pub order_hint: u32,
   pub show_frame: bool,
   pub showable_frame: bool,
@@ -627,11 +700,16 @@ pub struct FrameInvariants<T: Pixel> {
   pub use_prev_frame_mvs: bool,
   pub partition_range: PartitionRange,
   pub globalmv_transformation_type: [GlobalMVMode; INTER_REFS_PER_FRAME],
+  /// Global motion model of each reference, see [`WarpedMotion::params`].
+  pub gm_params: [[i32; 6]; INTER_REFS_PER_FRAME],
   pub num_tg: usize,
   pub large_scale_tile: bool,
   pub disable_cdf_update: bool,
   pub allow_screen_content_tools: u32,
   pub force_integer_mv: u32,
+  /// Screen content analysis of the frame, which decides the screen content
+  /// tools used on it.
+  pub screen_content: ScreenContentStats,
   pub primary_ref_frame: u32,
   pub refresh_frame_flags: u32, // a bitmask that specifies which
   // reference frame slots will be updated with the current frame
@@ -662,12 +740,25 @@ pub struct FrameInvariants<T: Pixel> {
   pub use_tx_domain_rate: bool,
   pub idx_in_group_output: u64,
   pub pyramid_level: u64,
+  /// Temporal layer of the frame, 0 unless temporal scalability is enabled.
+  pub temporal_id: u8,
   pub enable_early_exit: bool,
   pub tx_mode_select: bool,
   pub enable_inter_txfm_split: bool,
   pub default_filter: FilterMode,
   pub enable_segmentation: bool,
   pub t35_metadata: Box<[T35]>,
+  /// Base quantizer index requested for the frame instead of the one
+  /// selected by the rate control.
+  pub quantizer_override: Option<u8>,
+  /// Offset added to the base quantizer index of the frame.
+  pub quantizer_offset: i16,
+  /// Binary logarithm of the multiplier of the bits spent on the frame, in
+  /// Q57 format.
+  pub log_bitrate_factor: i64,
+  /// Removal time of the frame from the decoder buffer, in decoding ticks
+  /// since the removal of the last key frame.
+  pub buffer_removal_time: u32,
   /// Target CPU feature level.
   pub cpu_feature_level: crate::cpu_features::CpuFeatureLevel,
 
@@ -707,6 +798,8 @@ pub struct CodedFrameData<T: Pixel> {
   pub activity_mask: ActivityMask,
   /// Combined metric of activity and distortion
   pub spatiotemporal_scores: Box<[DistortionScale]>,
+  /// Whether the distortion scales include a user-provided importance map.
+  pub has_importance_map: bool,
 }
 
 impl<T: Pixel> CodedFrameData<T> {
@@ -735,6 +828,7 @@ impl<T: Pixel> CodedFrameData<T> {
       .into_boxed_slice(),
       activity_mask: Default::default(),
       spatiotemporal_scores: Default::default(),
+      has_importance_map: false,
     }
   }
 
@@ -855,6 +949,8 @@ impl<T: Pixel> FrameInvariants<T> {
     Self {
       width,
       height,
+      upscaled_width: width,
+      superres_denom: SUPERRES_NUM,
       render_width: render_width as u32,
       render_height: render_height as u32,
       frame_size_override_flag,
@@ -864,6 +960,8 @@ impl<T: Pixel> FrameInvariants<T> {
       w_in_b,
       h_in_b,
       input_frameno: 0,
+      pts: 0,
+      duration: 1,
       order_hint: 0,
       show_frame: true,
       showable_frame: !sequence.reduced_still_picture_hdr,
@@ -878,11 +976,13 @@ impl<T: Pixel> FrameInvariants<T> {
       partition_range: config.speed_settings.partition.partition_range,
       globalmv_transformation_type: [GlobalMVMode::IDENTITY;
         INTER_REFS_PER_FRAME],
+      gm_params: [WARP_IDENTITY; INTER_REFS_PER_FRAME],
       num_tg: 1,
       large_scale_tile: false,
       disable_cdf_update: false,
-      allow_screen_content_tools: sequence.force_screen_content_tools,
+      allow_screen_content_tools: 0,
       force_integer_mv: 1,
+      screen_content: ScreenContentStats::default(),
       primary_ref_frame: PRIMARY_REF_NONE,
       refresh_frame_flags: ALL_REF_FRAMES_MASK,
       allow_intrabc: false,
@@ -929,17 +1029,25 @@ impl<T: Pixel> FrameInvariants<T> {
       use_tx_domain_rate,
       idx_in_group_output: 0,
       pyramid_level: 0,
+      temporal_id: 0,
       enable_early_exit: true,
       tx_mode_select: false,
       default_filter: FilterMode::REGULAR,
       cpu_feature_level: Default::default(),
       enable_segmentation: config.speed_settings.segmentation
-        != SegmentationLevel::Disabled,
+        != SegmentationLevel::Disabled
+        && !config.lossless,
+      // Lossless frames only use 4x4 transforms.
       enable_inter_txfm_split: config
         .speed_settings
         .transform
-        .enable_inter_tx_split,
+        .enable_inter_tx_split
+        && !config.lossless,
       t35_metadata: Box::new([]),
+      quantizer_override: None,
+      quantizer_offset: 0,
+      log_bitrate_factor: 0,
+      buffer_removal_time: 0,
       sequence,
       config,
       coded_frame_data: None,
@@ -948,24 +1056,46 @@ impl<T: Pixel> FrameInvariants<T> {
 
   pub fn new_key_frame(
     config: Arc<EncoderConfig>, sequence: Arc<Sequence>,
-    gop_input_frameno_start: u64, t35_metadata: Box<[T35]>,
+    gop_input_frameno_start: u64, pts: u64, duration: u64,
+    t35_metadata: Box<[T35]>,
   ) -> Self {
-    let tx_mode_select = config.speed_settings.transform.rdo_tx_decision;
+    let tx_mode_select =
+      config.speed_settings.transform.rdo_tx_decision && !config.lossless;
     let mut fi = Self::new(config, sequence);
     fi.input_frameno = gop_input_frameno_start;
+    fi.pts = pts;
+    fi.duration = duration;
     fi.tx_mode_select = tx_mode_select;
     fi.coded_frame_data = Some(CodedFrameData::new(&fi));
     fi.t35_metadata = t35_metadata;
     fi
   }
 
+  /// Switches the frame to another configuration, which may only differ
+  /// from the current one in the speed settings that can change from frame
+  /// to frame.
+  pub(crate) fn set_config(&mut self, config: Arc<EncoderConfig>) {
+    let speed_settings = &config.speed_settings;
+    self.use_reduced_tx_set = speed_settings.transform.reduced_tx_set;
+    self.use_tx_domain_rate = speed_settings.transform.tx_domain_rate;
+    self.partition_range = speed_settings.partition.partition_range;
+    self.enable_inter_txfm_split =
+      speed_settings.transform.enable_inter_tx_split && !config.lossless;
+    self.tx_mode_select = if self.frame_type == FrameType::KEY {
+      speed_settings.transform.rdo_tx_decision && !config.lossless
+    } else {
+      self.enable_inter_txfm_split
+    };
+    self.config = config;
+  }
+
   /// Returns the created `FrameInvariants`, or `None` if this should be
   /// a placeholder frame.
   pub(crate) fn new_inter_frame(
     previous_coded_fi: &Self, inter_cfg: &InterConfig,
     gop_input_frameno_start: u64, output_frameno_in_gop: u64,
-    next_keyframe_input_frameno: u64, error_resilient: bool,
-    t35_metadata: Box<[T35]>,
+    next_keyframe_input_frameno: u64, error_resilient: bool, pts: u64,
+    duration: u64, t35_metadata: Box<[T35]>,
   ) -> Option<Self> {
     let input_frameno = inter_cfg
       .get_input_frameno(output_frameno_in_gop, gop_input_frameno_start);
@@ -979,6 +1109,9 @@ impl<T: Pixel> FrameInvariants<T> {
     let mut fi = previous_coded_fi.clone_without_coded_data();
     fi.intra_only = false;
     fi.force_integer_mv = 0; // note: should be 1 if fi.intra_only is true
+    fi.globalmv_transformation_type =
+      [GlobalMVMode::IDENTITY; INTER_REFS_PER_FRAME];
+    fi.gm_params = [WARP_IDENTITY; INTER_REFS_PER_FRAME];
     fi.idx_in_group_output =
       inter_cfg.get_idx_in_group_output(output_frameno_in_gop);
     fi.tx_mode_select = fi.enable_inter_txfm_split;
@@ -993,10 +1126,12 @@ impl<T: Pixel> FrameInvariants<T> {
       inter_cfg.get_order_hint(output_frameno_in_gop, fi.idx_in_group_output);
 
     fi.pyramid_level = inter_cfg.get_level(fi.idx_in_group_output);
+    fi.temporal_id = inter_cfg.get_temporal_id(fi.order_hint);
 
     fi.frame_type = if (inter_cfg.switch_frame_interval > 0)
       && (output_frameno_in_gop % inter_cfg.switch_frame_interval == 0)
       && (fi.pyramid_level == 0)
+      && (fi.temporal_id == 0)
     {
       FrameType::SWITCH
     } else {
@@ -1004,6 +1139,13 @@ impl<T: Pixel> FrameInvariants<T> {
     };
     fi.error_resilient =
       if fi.frame_type == FrameType::SWITCH { true } else { error_resilient };
+    fi.is_motion_mode_switchable =
+      fi.config.speed_settings.motion.motion_modes;
+    fi.is_filter_switchable =
+      fi.config.speed_settings.motion.interp_filter_search
+        != InterpFilterSearch::Off;
+    fi.allow_warped_motion =
+      fi.sequence.enable_warped_motion && !fi.error_resilient;
 
     fi.frame_size_override_flag = if fi.frame_type == FrameType::SWITCH {
       true
@@ -1021,13 +1163,21 @@ impl<T: Pixel> FrameInvariants<T> {
     };
 
     // this is the slot that the current frame is going to be saved into
-    let slot_idx = inter_cfg.get_slot_idx(fi.pyramid_level, fi.order_hint);
+    let slot_idx = if inter_cfg.temporal_layers > 1 {
+      inter_cfg.get_temporal_slot_idx(fi.temporal_id, fi.order_hint)
+    } else {
+      inter_cfg.get_slot_idx(fi.pyramid_level, fi.order_hint)
+    };
     fi.show_frame = inter_cfg.get_show_frame(fi.idx_in_group_output);
     fi.t35_metadata = if fi.show_frame { t35_metadata } else { Box::new([]) };
     fi.frame_to_show_map_idx = slot_idx;
     fi.refresh_frame_flags = if fi.frame_type == FrameType::SWITCH {
       ALL_REF_FRAMES_MASK
-    } else if fi.is_show_existing_frame() {
+    } else if fi.is_show_existing_frame()
+      || (fi.temporal_id > 0
+        && fi.temporal_id as u64 == inter_cfg.temporal_layers - 1)
+    {
+      // the top temporal layer can be dropped, so it is never referenced
       0
     } else {
       1 << slot_idx
@@ -1044,7 +1194,21 @@ impl<T: Pixel> FrameInvariants<T> {
       (ref_in_previous_group.to_index()) as u32
     };
 
-    if fi.pyramid_level == 0 {
+    if fi.temporal_id > 0 {
+      // only reference frames in lower temporal layers, so that every
+      //  operating point stays decodable on its own
+      let base_slot = inter_cfg.get_temporal_slot_idx(0, fi.order_hint);
+      let (last, last2) = if fi.temporal_id == 2 && (fi.order_hint & 3) == 3 {
+        // the middle layer frame of this group precedes this one
+        (4, base_slot)
+      } else {
+        (base_slot, (base_slot + 4 - 1) % 4)
+      };
+      fi.ref_frames = [last as u8; INTER_REFS_PER_FRAME];
+      if inter_cfg.multiref {
+        fi.ref_frames[second_ref_frame.to_index()] = last2 as u8;
+      }
+    } else if fi.pyramid_level == 0 {
       // level 0 has no forward references
       // default to last P frame
       fi.ref_frames = [
@@ -1099,6 +1263,8 @@ impl<T: Pixel> FrameInvariants<T> {
       ReferenceMode::SINGLE
     };
     fi.input_frameno = input_frameno;
+    fi.pts = pts;
+    fi.duration = duration;
     fi.me_range_scale = (inter_cfg.group_input_len >> fi.pyramid_level) as u8;
 
     if fi.show_frame || fi.showable_frame {
@@ -1129,6 +1295,8 @@ impl<T: Pixel> FrameInvariants<T> {
       config: self.config.clone(),
       width: self.width,
       height: self.height,
+      upscaled_width: self.upscaled_width,
+      superres_denom: self.superres_denom,
       render_width: self.render_width,
       render_height: self.render_height,
       frame_size_override_flag: self.frame_size_override_flag,
@@ -1138,6 +1306,8 @@ impl<T: Pixel> FrameInvariants<T> {
       w_in_b: self.w_in_b,
       h_in_b: self.h_in_b,
       input_frameno: self.input_frameno,
+      pts: self.pts,
+      duration: self.duration,
       order_hint: self.order_hint,
       show_frame: self.show_frame,
       showable_frame: self.showable_frame,
@@ -1151,11 +1321,13 @@ impl<T: Pixel> FrameInvariants<T> {
       use_prev_frame_mvs: self.use_prev_frame_mvs,
       partition_range: self.partition_range,
       globalmv_transformation_type: self.globalmv_transformation_type,
+      gm_params: self.gm_params,
       num_tg: self.num_tg,
       large_scale_tile: self.large_scale_tile,
       disable_cdf_update: self.disable_cdf_update,
       allow_screen_content_tools: self.allow_screen_content_tools,
       force_integer_mv: self.force_integer_mv,
+      screen_content: self.screen_content,
       primary_ref_frame: self.primary_ref_frame,
       refresh_frame_flags: self.refresh_frame_flags,
       allow_intrabc: self.allow_intrabc,
@@ -1184,12 +1356,17 @@ impl<T: Pixel> FrameInvariants<T> {
       use_tx_domain_rate: self.use_tx_domain_rate,
       idx_in_group_output: self.idx_in_group_output,
       pyramid_level: self.pyramid_level,
+      temporal_id: self.temporal_id,
       enable_early_exit: self.enable_early_exit,
       tx_mode_select: self.tx_mode_select,
       enable_inter_txfm_split: self.enable_inter_txfm_split,
       default_filter: self.default_filter,
       enable_segmentation: self.enable_segmentation,
       t35_metadata: self.t35_metadata.clone(),
+      quantizer_override: self.quantizer_override,
+      quantizer_offset: self.quantizer_offset,
+      log_bitrate_factor: self.log_bitrate_factor,
+      buffer_removal_time: self.buffer_removal_time,
       cpu_feature_level: self.cpu_feature_level,
     }
   }
@@ -1209,14 +1386,41 @@ impl<T: Pixel> FrameInvariants<T> {
     }
   }
 
+  /// Distance in order hints between this frame and the reference `rf`, or
+  /// 0 if order hints are disabled.
+  pub fn ref_frame_dist(&self, rf: RefType) -> u32 {
+    if !self.sequence.enable_order_hint {
+      return 0;
+    }
+    self.rec_buffer.frames[self.ref_frames[rf.to_index()] as usize]
+      .as_ref()
+      .map_or(0, |rec| {
+        self
+          .sequence
+          .get_relative_dist(rec.order_hint, self.order_hint)
+          .unsigned_abs()
+      })
+  }
+
   pub fn get_frame_subtype(&self) -> usize {
     if self.frame_type == FrameType::KEY {
       FRAME_SUBTYPE_I
     } else {
-      FRAME_SUBTYPE_P + (self.pyramid_level as usize)
+      // Temporal layers are only used without re-ordering, so at most one of
+      //  these is non-zero.
+      FRAME_SUBTYPE_P
+        + (self.pyramid_level as usize)
+        + (self.temporal_id as usize)
     }
   }
 
+  /// The OBU extension header for the layer-specific OBUs of this frame, if
+  ///  the sequence has more than one operating point.
+  pub fn obu_extension(&self) -> Option<ObuExtension> {
+    (self.sequence.operating_points_cnt_minus_1 > 0)
+      .then_some(ObuExtension { temporal_id: self.temporal_id, spatial_id: 0 })
+  }
+
   fn pick_strength_from_q(&mut self, qps: &QuantizerParameters) {
     self.cdef_damping = 3 + (self.base_q_idx >> 6);
     let q = bexp64(qps.log_target_q + q57(QSCALE)) as f32;
@@ -1242,11 +1446,19 @@ impl<T: Pixel> FrameInvariants<T> {
   }
 
   pub fn set_quantizers(&mut self, qps: &QuantizerParameters) {
-    self.base_q_idx = qps.ac_qi[0];
-    let base_q_idx = self.base_q_idx as i32;
-    for pi in 0..3 {
-      self.dc_delta_q[pi] = (qps.dc_qi[pi] as i32 - base_q_idx) as i8;
-      self.ac_delta_q[pi] = (qps.ac_qi[pi] as i32 - base_q_idx) as i8;
+    if self.config.lossless {
+      // Lossless coding is signaled by a zero quantizer index for all of the
+      // planes.
+      self.base_q_idx = 0;
+      self.dc_delta_q = [0; 3];
+      self.ac_delta_q = [0; 3];
+    } else {
+      self.base_q_idx = qps.ac_qi[0];
+      let base_q_idx = self.base_q_idx as i32;
+      for pi in 0..3 {
+        self.dc_delta_q[pi] = (qps.dc_qi[pi] as i32 - base_q_idx) as i8;
+        self.ac_delta_q[pi] = (qps.ac_qi[pi] as i32 - base_q_idx) as i8;
+      }
     }
     self.lambda =
       qps.lambda * ((1 << (2 * (self.sequence.bit_depth - 8))) as f64);
@@ -1260,6 +1472,138 @@ impl<T: Pixel> FrameInvariants<T> {
       // TODO: implement FastSearch and FullSearch
       _ => unreachable!(),
     }
+
+    self.superres_denom = select_superres_denom(self);
+    // IntraBC requires the frame to be coded at its full width.
+    self.allow_intrabc = self.allow_screen_content_tools != 0
+      && self.screen_content.intrabc
+      && self.intra_only
+      && !self.use_superres();
+  }
+
+  /// Enables the screen content tools picked by the lookahead for this frame.
+  pub(crate) fn set_screen_content(&mut self, stats: ScreenContentStats) {
+    self.screen_content = stats;
+    if self.sequence.force_screen_content_tools == 2 {
+      self.allow_screen_content_tools = u32::from(stats.tools);
+    }
+    if !self.intra_only {
+      self.force_integer_mv =
+        u32::from(self.allow_screen_content_tools != 0 && stats.integer_mv);
+    }
+  }
+
+  #[inline(always)]
+  pub const fn use_superres(&self) -> bool {
+    self.superres_denom != SUPERRES_NUM
+  }
+
+  /// Whether the whole frame is coded losslessly, see `CodedLossless` in the
+  /// spec. Segmentation is disabled in lossless mode, so only the frame
+  /// quantizers need to be checked.
+  #[inline(always)]
+  pub fn coded_lossless(&self) -> bool {
+    self.base_q_idx == 0
+      && self.dc_delta_q == [0; 3]
+      && self.ac_delta_q == [0; 3]
+  }
+
+  /// Transform size of the chroma planes of a block of `bsize`.
+  ///
+  /// Lossless frames only use 4x4 transforms.
+  #[inline]
+  pub fn uv_tx_size(
+    &self, bsize: BlockSize, xdec: usize, ydec: usize,
+  ) -> TxSize {
+    if self.coded_lossless() {
+      TxSize::TX_4X4
+    } else {
+      bsize.largest_chroma_tx_size(xdec, ydec)
+    }
+  }
+
+  /// Whether chroma from luma prediction is allowed for a block of `bsize`,
+  /// see `is_cfl_allowed` in the spec.
+  #[inline]
+  pub fn cfl_allowed(&self, bsize: BlockSize) -> bool {
+    if self.coded_lossless() {
+      let (xdec, ydec) =
+        self.sequence.chroma_sampling.get_decimation().unwrap_or((0, 0));
+      matches!(bsize.subsampled_size(xdec, ydec), Ok(BlockSize::BLOCK_4X4))
+    } else {
+      bsize.cfl_allowed()
+    }
+  }
+
+  /// Motion vector of a `GLOBALMV` block referencing `ref_frame`, see
+  /// `setup_global_mv` in the spec.
+  pub fn global_mv(
+    &self, ref_frame: RefType, bo: PlaneBlockOffset, bsize: BlockSize,
+  ) -> MotionVector {
+    if ref_frame == INTRA_FRAME || ref_frame == NONE_FRAME {
+      return MotionVector::default();
+    }
+    let params = self.gm_params[ref_frame.to_index()].map(i64::from);
+    let prec = WARPEDMODEL_PREC_BITS;
+    let (row, col) =
+      match self.globalmv_transformation_type[ref_frame.to_index()] {
+        GlobalMVMode::IDENTITY => (0, 0),
+        // The spec takes the row from the horizontal translation and
+        // vice versa, see `gm_translation_params`.
+        GlobalMVMode::TRANSLATION => {
+          (params[0] >> (prec - 3), params[1] >> (prec - 3))
+        }
+        GlobalMVMode::ROTZOOM | GlobalMVMode::AFFINE => {
+          let x = (bo.0.x * MI_SIZE + bsize.width() / 2) as i64 - 1;
+          let y = (bo.0.y * MI_SIZE + bsize.height() / 2) as i64 - 1;
+          let xc = (params[2] - (1 << prec)) * x + params[3] * y + params[0];
+          let yc = params[4] * x + (params[5] - (1 << prec)) * y + params[1];
+          if self.allow_high_precision_mv {
+            (round2signed(yc, prec - 3), round2signed(xc, prec - 3))
+          } else {
+            (round2signed(yc, prec - 2) * 2, round2signed(xc, prec - 2) * 2)
+          }
+        }
+      };
+    let lower_precision = |v: i64| {
+      let v = v.clamp(i16::MIN as i64, i16::MAX as i64) as i16;
+      if self.force_integer_mv != 0 {
+        let v_int = (v.abs() + 3) >> 3;
+        if v > 0 {
+          v_int << 3
+        } else {
+          -(v_int << 3)
+        }
+      } else if !self.allow_high_precision_mv && v & 1 != 0 {
+        v - v.signum()
+      } else {
+        v
+      }
+    };
+    MotionVector { row: lower_precision(row), col: lower_precision(col) }
+  }
+
+  /// Warp applied by `GLOBALMV` blocks referencing `ref_frame`, if its
+  /// global motion is not a plain translation.
+  pub fn global_warp(&self, ref_frame: RefType) -> Option<WarpedMotion> {
+    let idx = ref_frame.to_index();
+    if self.globalmv_transformation_type[idx] > GlobalMVMode::TRANSLATION {
+      WarpedMotion::new(self.gm_params[idx])
+    } else {
+      None
+    }
+  }
+
+  /// Global motion parameters of the primary reference frame, which the
+  /// parameters of this frame are coded against.
+  pub fn prev_gm_params(&self) -> [[i32; 6]; INTER_REFS_PER_FRAME] {
+    if self.primary_ref_frame == PRIMARY_REF_NONE {
+      return [WARP_IDENTITY; INTER_REFS_PER_FRAME];
+    }
+    let idx = self.ref_frames[self.primary_ref_frame as usize] as usize;
+    self.rec_buffer.frames[idx]
+      .as_ref()
+      .map_or([WARP_IDENTITY; INTER_REFS_PER_FRAME], |rf| rf.gm_params)
   }
 
   #[inline(always)]
@@ -1279,7 +1623,7 @@ impl<T: Pixel> FrameInvariants<T> {
     // I don't know why this is the base unit for a timestamp but it is. 1/10000000 of a second.
     const TIMESTAMP_BASE_UNIT: u64 = 10_000_000;
 
-    self.input_frameno * TIMESTAMP_BASE_UNIT * self.sequence.time_base.num
+    self.pts * TIMESTAMP_BASE_UNIT * self.sequence.time_base.num
       / self.sequence.time_base.den
   }
 }
@@ -1293,40 +1637,100 @@ impl<T: Pixel> fmt::Display for FrameInvariants<T> {
 /// # Errors
 ///
 /// - If the frame packet cannot be written to
-pub fn write_temporal_delimiter(packet: &mut dyn io::Write) -> io::Result<()> {
-  packet.write_all(&TEMPORAL_DELIMITER)?;
-  Ok(())
+pub fn write_temporal_delimiter(
+  packet: &mut dyn io::Write, annex_b: bool,
+) -> io::Result<()> {
+  if annex_b {
+    write_obu(packet, ObuType::OBU_TEMPORAL_DELIMITER, None, &[], true)
+  } else {
+    packet.write_all(&TEMPORAL_DELIMITER)
+  }
 }
 
-fn write_key_frame_obus<T: Pixel>(
-  packet: &mut dyn io::Write, fi: &FrameInvariants<T>, obu_extension: u32,
+/// Writes an OBU carrying `payload`, with its size, or without it and
+/// preceded by its `obu_length` in the length-delimited format of Annex B.
+fn write_obu(
+  packet: &mut dyn io::Write, obu_type: ObuType,
+  obu_extension: Option<ObuExtension>, payload: &[u8], annex_b: bool,
 ) -> io::Result<()> {
-  let mut buf1 = Vec::new();
-  let mut buf2 = Vec::new();
+  let mut header = Vec::new();
   {
-    let mut bw2 = BitWriter::endian(&mut buf2, BigEndian);
-    bw2.write_sequence_header_obu(fi)?;
-    bw2.write_bit(true)?; // trailing bit
-    bw2.byte_align()?;
+    let mut bw = BitWriter::endian(&mut header, BigEndian);
+    bw.write_obu_header(obu_type, obu_extension, !annex_b)?;
+    if !annex_b {
+      bw.write_uleb128(payload.len() as u64)?;
+    }
+  }
+
+  if annex_b {
+    let mut length = Vec::new();
+    let mut bw = BitWriter::endian(&mut length, BigEndian);
+    bw.write_uleb128((header.len() + payload.len()) as u64)?;
+    packet.write_all(&length)?;
   }
+  packet.write_all(&header)?;
+  packet.write_all(payload)
+}
 
+/// Writes the sequence header OBU of `fi`, in the format of Annex B if
+/// `annex_b`.
+pub(crate) fn write_sequence_obu<T: Pixel>(
+  packet: &mut dyn io::Write, fi: &FrameInvariants<T>, annex_b: bool,
+) -> io::Result<()> {
+  let mut buf = Vec::new();
   {
-    let mut bw1 = BitWriter::endian(&mut buf1, BigEndian);
-    bw1.write_obu_header(ObuType::OBU_SEQUENCE_HEADER, obu_extension)?;
+    let mut bw = BitWriter::endian(&mut buf, BigEndian);
+    bw.write_sequence_header_obu(fi)?;
+    bw.write_bit(true)?; // trailing bit
+    bw.byte_align()?;
   }
-  packet.write_all(&buf1).unwrap();
-  buf1.clear();
+
+  write_obu(packet, ObuType::OBU_SEQUENCE_HEADER, None, &buf, annex_b)
+}
+
+/// Writes the `AV1CodecConfigurationRecord` of the sequence coded with
+/// `config`, followed by its sequence header OBU.
+pub(crate) fn container_sequence_header<T: Pixel>(
+  config: &EncoderConfig,
+) -> io::Result<Vec<u8>> {
+  let fi = FrameInvariants::<T>::new(
+    Arc::new(config.clone()),
+    Arc::new(Sequence::new(config)),
+  );
+  let seq = &fi.sequence;
+  let mut buf = Vec::new();
 
   {
-    let mut bw1 = BitWriter::endian(&mut buf1, BigEndian);
-    bw1.write_uleb128(buf2.len() as u64)?;
+    let mut bw = BitWriter::endian(&mut buf, BigEndian);
+    bw.write_bit(true)?; // marker
+    bw.write(7, 1u8)?; // version
+    bw.write(3, seq.profile)?;
+    bw.write(5, seq.level_idx[0])?; // level
+    bw.write_bit(seq.tier[0] != 0)?; // tier
+    bw.write_bit(seq.bit_depth > 8)?; // high_bitdepth
+    bw.write_bit(seq.bit_depth == 12)?; // twelve_bit
+    bw.write_bit(seq.chroma_sampling == ChromaSampling::Cs400)?; // monochrome
+    bw.write_bit(seq.chroma_sampling != ChromaSampling::Cs444)?; // chroma_subsampling_x
+    bw.write_bit(seq.chroma_sampling == ChromaSampling::Cs420)?; // chroma_subsampling_y
+    bw.write(2, seq.chroma_sample_position as u8)?; // chroma_sample_position
+    bw.write(3, 0u8)?; // reserved
+    bw.write_bit(false)?; // initial_presentation_delay_present
+
+    bw.write(4, 0u8)?; // reserved
   }
 
-  packet.write_all(&buf1).unwrap();
-  buf1.clear();
+  // The configuration record holds OBUs with their sizes in any case.
+  write_sequence_obu(&mut buf, &fi, false)?;
+
+  Ok(buf)
+}
 
-  packet.write_all(&buf2).unwrap();
-  buf2.clear();
+fn write_key_frame_obus<T: Pixel>(
+  packet: &mut dyn io::Write, fi: &FrameInvariants<T>,
+) -> io::Result<()> {
+  let annex_b = fi.config.annex_b;
+  let mut buf1 = Vec::new();
+  write_sequence_obu(packet, fi, annex_b)?;
 
   if fi.sequence.content_light.is_some() {
     let mut bw1 = BitWriter::endian(&mut buf1, BigEndian);
@@ -1334,7 +1738,7 @@ fn write_key_frame_obus<T: Pixel>(
       ObuMetaType::OBU_META_HDR_CLL,
       &fi.sequence,
     )?;
-    packet.write_all(&buf1).unwrap();
+    write_obu(packet, ObuType::OBU_METADATA, None, &buf1, annex_b)?;
     buf1.clear();
   }
 
@@ -1344,13 +1748,26 @@ fn write_key_frame_obus<T: Pixel>(
       ObuMetaType::OBU_META_HDR_MDCV,
       &fi.sequence,
     )?;
-    packet.write_all(&buf1).unwrap();
+    write_obu(packet, ObuType::OBU_METADATA, None, &buf1, annex_b)?;
     buf1.clear();
   }
 
   Ok(())
 }
 
+fn write_t35_metadata_obus<T: Pixel>(
+  packet: &mut dyn io::Write, fi: &FrameInvariants<T>,
+) -> io::Result<()> {
+  let mut buf = Vec::new();
+  for t35 in fi.t35_metadata.iter() {
+    let mut bw = BitWriter::endian(&mut buf, BigEndian);
+    bw.write_t35_metadata_obu(t35)?;
+    write_obu(packet, ObuType::OBU_METADATA, None, &buf, fi.config.annex_b)?;
+    buf.clear();
+  }
+  Ok(())
+}
+
 /// Write into `dst` the difference between the blocks at `src1` and `src2`
 fn diff<T: Pixel>(
   dst: &mut [MaybeUninit<i16>], src1: &PlaneRegion<'_, T>,
@@ -1427,6 +1844,7 @@ pub fn encode_tx_block<T: Pixel, W: Writer>(
   qidx: u8,
   ac: &[i16],
   pred_intra_param: IntraParam,
+  palette: &PaletteParams,
   rdo_type: RDOType,
   need_recon_pixel: bool,
 ) -> (bool, ScaledDistortion) {
@@ -1468,10 +1886,25 @@ pub fn encode_tx_block<T: Pixel, W: Writer>(
     None
   };
 
+  if palette.plane_size(p) > 0 {
+    predict_palette(
+      fi,
+      ts,
+      tile_partition_bo,
+      bsize,
+      p,
+      palette,
+      tx_bo,
+      po,
+      tx_size.width(),
+      tx_size.height(),
+    );
+  }
+
   let frame_bo = ts.to_frame_block_offset(tx_bo);
   let rec = &mut ts.rec.planes[p];
 
-  if mode.is_intra() {
+  if mode.is_intra() && palette.plane_size(p) == 0 {
     let bit_depth = fi.sequence.bit_depth;
     let mut edge_buf = Aligned::uninit_array();
     let edge_buf = get_intra_edges(
@@ -1541,12 +1974,22 @@ pub fn encode_tx_block<T: Pixel, W: Writer>(
   // SAFETY: `diff()` inits `tx_size.area()` elements when it matches size of `subregion(area)`
   let residual = unsafe { slice_assume_init_mut(residual) };
 
+  // Lossless blocks are coded as DCT_DCT, which selects the scan order and
+  // the coefficient contexts, but use the Walsh-Hadamard transform.
+  let lossless = fi.coded_lossless();
+  let txfm_type = if lossless {
+    debug_assert!(tx_size == TxSize::TX_4X4 && tx_type == TxType::DCT_DCT);
+    TxType::WHT_WHT
+  } else {
+    tx_type
+  };
+
   forward_transform(
     residual,
     coeffs,
     tx_size.width(),
     tx_size,
-    tx_type,
+    txfm_type,
     fi.sequence.bit_depth,
     fi.cpu_feature_level,
   );
@@ -1578,6 +2021,7 @@ pub fn encode_tx_block<T: Pixel, W: Writer>(
       xdec,
       ydec,
       fi.use_reduced_tx_set,
+      lossless,
       frame_clipped_txw,
       frame_clipped_txh,
     )
@@ -1608,7 +2052,7 @@ pub fn encode_tx_block<T: Pixel, W: Writer>(
       &mut rec.subregion_mut(area),
       eob,
       tx_size,
-      tx_type,
+      txfm_type,
       fi.sequence.bit_depth,
       fi.cpu_feature_level,
     );
@@ -1667,7 +2111,8 @@ pub fn encode_tx_block<T: Pixel, W: Writer>(
 pub fn motion_compensate<T: Pixel>(
   fi: &FrameInvariants<T>, ts: &mut TileStateMut<'_, T>,
   cw: &mut ContextWriter, luma_mode: PredictionMode, ref_frames: [RefType; 2],
-  mvs: [MotionVector; 2], bsize: BlockSize, tile_bo: TileBlockOffset,
+  mvs: [MotionVector; 2], motion_mode: MotionMode, compound: CompoundParams,
+  interp_filter: [FilterMode; 2], bsize: BlockSize, tile_bo: TileBlockOffset,
   luma_only: bool,
 ) {
   debug_assert!(!luma_mode.is_intra());
@@ -1690,6 +2135,17 @@ pub fn motion_compensate<T: Pixel>(
       0
     };
 
+  if ref_frames[0] == INTRA_FRAME {
+    predict_intrabc(ts, tile_bo, bsize, mvs[0], num_planes);
+    return;
+  }
+
+  let warps = if motion_mode == MotionMode::WARPED_CAUSAL {
+    [cw.local_warp(fi, tile_bo, bsize, ref_frames[0], mvs[0]), None]
+  } else {
+    luma_mode.global_warps(fi, ref_frames, bsize)
+  };
+
   let luma_tile_rect = ts.tile_rect();
   let compound_buffer = &mut ts.inter_compound_buffers;
   for p in 0..num_planes {
@@ -1733,6 +2189,9 @@ pub fn motion_compensate<T: Pixel>(
           plane_bsize.height(),
           ref_frames,
           mvs,
+          warps,
+          compound,
+          interp_filter,
           compound_buffer,
         );
       } else {
@@ -1741,12 +2200,15 @@ pub fn motion_compensate<T: Pixel>(
         if bsize == BlockSize::BLOCK_4X4 {
           let mv0 = cw.bc.blocks[tile_bo.with_offset(-1, -1)].mv;
           let rf0 = cw.bc.blocks[tile_bo.with_offset(-1, -1)].ref_frames;
+          let if0 = cw.bc.blocks[tile_bo.with_offset(-1, -1)].interp_filter;
           let mv1 = cw.bc.blocks[tile_bo.with_offset(0, -1)].mv;
           let rf1 = cw.bc.blocks[tile_bo.with_offset(0, -1)].ref_frames;
+          let if1 = cw.bc.blocks[tile_bo.with_offset(0, -1)].interp_filter;
           let po1 = PlaneOffset { x: po.x + 2, y: po.y };
           let area1 = Area::StartingAt { x: po1.x, y: po1.y };
           let mv2 = cw.bc.blocks[tile_bo.with_offset(-1, 0)].mv;
           let rf2 = cw.bc.blocks[tile_bo.with_offset(-1, 0)].ref_frames;
+          let if2 = cw.bc.blocks[tile_bo.with_offset(-1, 0)].interp_filter;
           let po2 = PlaneOffset { x: po.x, y: po.y + 2 };
           let area2 = Area::StartingAt { x: po2.x, y: po2.y };
           let po3 = PlaneOffset { x: po.x + 2, y: po.y + 2 };
@@ -1761,6 +2223,9 @@ pub fn motion_compensate<T: Pixel>(
             2,
             rf0,
             mv0,
+            [None; 2],
+            CompoundParams::Average,
+            if0,
             compound_buffer,
           );
           luma_mode.predict_inter(
@@ -1773,6 +2238,9 @@ pub fn motion_compensate<T: Pixel>(
             2,
             rf1,
             mv1,
+            [None; 2],
+            CompoundParams::Average,
+            if1,
             compound_buffer,
           );
           luma_mode.predict_inter(
@@ -1785,6 +2253,9 @@ pub fn motion_compensate<T: Pixel>(
             2,
             rf2,
             mv2,
+            [None; 2],
+            CompoundParams::Average,
+            if2,
             compound_buffer,
           );
           luma_mode.predict_inter(
@@ -1797,12 +2268,16 @@ pub fn motion_compensate<T: Pixel>(
             2,
             ref_frames,
             mvs,
+            [None; 2],
+            CompoundParams::Average,
+            interp_filter,
             compound_buffer,
           );
         }
         if bsize == BlockSize::BLOCK_8X4 {
           let mv1 = cw.bc.blocks[tile_bo.with_offset(0, -1)].mv;
           let rf1 = cw.bc.blocks[tile_bo.with_offset(0, -1)].ref_frames;
+          let if1 = cw.bc.blocks[tile_bo.with_offset(0, -1)].interp_filter;
           luma_mode.predict_inter(
             fi,
             tile_rect,
@@ -1813,6 +2288,9 @@ pub fn motion_compensate<T: Pixel>(
             2,
             rf1,
             mv1,
+            [None; 2],
+            CompoundParams::Average,
+            if1,
             compound_buffer,
           );
           let po3 = PlaneOffset { x: po.x, y: po.y + 2 };
@@ -1827,12 +2305,16 @@ pub fn motion_compensate<T: Pixel>(
             2,
             ref_frames,
             mvs,
+            [None; 2],
+            CompoundParams::Average,
+            interp_filter,
             compound_buffer,
           );
         }
         if bsize == BlockSize::BLOCK_4X8 {
           let mv2 = cw.bc.blocks[tile_bo.with_offset(-1, 0)].mv;
           let rf2 = cw.bc.blocks[tile_bo.with_offset(-1, 0)].ref_frames;
+          let if2 = cw.bc.blocks[tile_bo.with_offset(-1, 0)].interp_filter;
           luma_mode.predict_inter(
             fi,
             tile_rect,
@@ -1843,6 +2325,9 @@ pub fn motion_compensate<T: Pixel>(
             4,
             rf2,
             mv2,
+            [None; 2],
+            CompoundParams::Average,
+            if2,
             compound_buffer,
           );
           let po3 = PlaneOffset { x: po.x + 2, y: po.y };
@@ -1857,6 +2342,9 @@ pub fn motion_compensate<T: Pixel>(
             4,
             ref_frames,
             mvs,
+            [None; 2],
+            CompoundParams::Average,
+            interp_filter,
             compound_buffer,
           );
         }
@@ -1872,8 +2360,135 @@ pub fn motion_compensate<T: Pixel>(
         plane_bsize.height(),
         ref_frames,
         mvs,
+        warps,
+        compound,
+        interp_filter,
         compound_buffer,
       );
+      if let CompoundParams::InterIntra { mode, wedge } = compound {
+        predict_interintra(fi, tile_rect, rec, tile_bo, bsize, mode, wedge);
+      }
+      if motion_mode == MotionMode::OBMC_CAUSAL {
+        overlapped_motion_compensate(
+          fi,
+          tile_rect,
+          &cw.bc.blocks.as_const(),
+          luma_mode,
+          p,
+          &mut rec.subregion_mut(area),
+          bsize,
+          tile_bo,
+        );
+      }
+    }
+  }
+}
+
+/// Blends the predictions from the motion of the inter blocks above and to
+/// the left of an `OBMC_CAUSAL` block into the block's own prediction in
+/// `dst`.
+fn overlapped_motion_compensate<T: Pixel>(
+  fi: &FrameInvariants<T>, tile_rect: TileRect, blocks: &TileBlocks<'_>,
+  mode: PredictionMode, p: usize, dst: &mut PlaneRegionMut<'_, T>,
+  bsize: BlockSize, tile_bo: TileBlockOffset,
+) {
+  let &PlaneConfig { xdec, ydec, .. } = dst.plane_cfg;
+  let po = tile_bo.plane_offset(dst.plane_cfg);
+  let w4 = bsize.width_mi();
+  let h4 = bsize.height_mi();
+
+  // Scratch pad for the predictions of the neighbours' motion, which cover
+  // at most 64x32 luma samples.
+  let cfg = PlaneConfig::new(64, 64, 0, 0, 0, 0, mem::size_of::<T>());
+  // SAFETY: We write to the array below before reading from it.
+  let mut buf: Aligned<[T; 64 * 64]> = unsafe { Aligned::uninitialized() };
+  let mut pred = PlaneRegionMut::from_slice(
+    &mut buf.data,
+    &cfg,
+    Rect { x: 0, y: 0, width: cfg.width, height: cfg.height },
+  );
+
+  if tile_bo.0.y > 0
+    && (p == 0 || ((w4 * 4) >> xdec) + ((h4 * 4) >> ydec) >= 16)
+  {
+    let x_end = w4.min(blocks.cols() - tile_bo.0.x);
+    let max_count = bsize.width_mi_log2().min(4);
+    let (mut x, mut count) = (0, 0);
+    while x < x_end && count < max_count {
+      // Only the odd columns are considered
+      let cand = &blocks[tile_bo.0.y - 1][tile_bo.0.x + x + 1];
+      let step = (cand.n4_w as usize).clamp(2, 16);
+      if cand.is_inter() {
+        count += 1;
+        let width = (step.min(w4) * 4) >> xdec;
+        let height = ((h4.min(16) >> 1) * 4) >> ydec;
+        let cand_po =
+          PlaneOffset { x: po.x + ((x * 4) >> xdec) as isize, ..po };
+        mode.predict_inter_single(
+          fi,
+          tile_rect,
+          p,
+          cand_po,
+          &mut pred,
+          width,
+          height,
+          cand.ref_frames[0],
+          cand.mv[0],
+          None,
+          cand.interp_filter,
+        );
+        blend_obmc_above(
+          &mut dst.subregion_mut(Area::StartingAt {
+            x: ((x * 4) >> xdec) as isize,
+            y: 0,
+          }),
+          &pred.as_const(),
+          width,
+          height,
+        );
+      }
+      x += step;
+    }
+  }
+
+  if tile_bo.0.x > 0 {
+    let y_end = h4.min(blocks.rows() - tile_bo.0.y);
+    let max_count = bsize.height_mi_log2().min(4);
+    let (mut y, mut count) = (0, 0);
+    while y < y_end && count < max_count {
+      // Only the odd rows are considered
+      let cand = &blocks[tile_bo.0.y + y + 1][tile_bo.0.x - 1];
+      let step = (cand.n4_h as usize).clamp(2, 16);
+      if cand.is_inter() {
+        count += 1;
+        let width = ((w4.min(16) >> 1) * 4) >> xdec;
+        let height = (step.min(h4) * 4) >> ydec;
+        let cand_po =
+          PlaneOffset { y: po.y + ((y * 4) >> ydec) as isize, ..po };
+        mode.predict_inter_single(
+          fi,
+          tile_rect,
+          p,
+          cand_po,
+          &mut pred,
+          width,
+          height,
+          cand.ref_frames[0],
+          cand.mv[0],
+          None,
+          cand.interp_filter,
+        );
+        blend_obmc_left(
+          &mut dst.subregion_mut(Area::StartingAt {
+            x: 0,
+            y: ((y * 4) >> ydec) as isize,
+          }),
+          &pred.as_const(),
+          width,
+          height,
+        );
+      }
+      y += step;
     }
   }
 }
@@ -1894,8 +2509,8 @@ pub fn save_block_motion<T: Pixel>(
 
 #[profiling::function]
 pub fn encode_block_pre_cdef<T: Pixel, W: Writer>(
-  seq: &Sequence, ts: &TileStateMut<'_, T>, cw: &mut ContextWriter, w: &mut W,
-  bsize: BlockSize, tile_bo: TileBlockOffset, skip: bool,
+  fi: &FrameInvariants<T>, ts: &TileStateMut<'_, T>, cw: &mut ContextWriter,
+  w: &mut W, bsize: BlockSize, tile_bo: TileBlockOffset, skip: bool,
 ) -> bool {
   cw.bc.blocks.set_skip(tile_bo, bsize, skip);
   if ts.segmentation.enabled
@@ -1923,7 +2538,11 @@ pub fn encode_block_pre_cdef<T: Pixel, W: Writer>(
       ts.segmentation.last_active_segid,
     );
   }
-  if !skip && seq.enable_cdef {
+  if !skip
+    && fi.sequence.enable_cdef
+    && !fi.allow_intrabc
+    && !fi.coded_lossless()
+  {
     cw.bc.cdef_coded = true;
   }
   cw.bc.cdef_coded
@@ -1938,11 +2557,12 @@ pub fn encode_block_post_cdef<T: Pixel, W: Writer>(
   fi: &FrameInvariants<T>, ts: &mut TileStateMut<'_, T>,
   cw: &mut ContextWriter, w: &mut W, luma_mode: PredictionMode,
   chroma_mode: PredictionMode, angle_delta: AngleDelta,
-  ref_frames: [RefType; 2], mvs: [MotionVector; 2], bsize: BlockSize,
-  tile_bo: TileBlockOffset, skip: bool, cfl: CFLParams, tx_size: TxSize,
-  tx_type: TxType, mode_context: usize, mv_stack: &[CandidateMV],
-  rdo_type: RDOType, need_recon_pixel: bool,
-  enc_stats: Option<&mut EncoderStats>,
+  ref_frames: [RefType; 2], mvs: [MotionVector; 2], motion_mode: MotionMode,
+  compound: CompoundParams, interp_filter: [FilterMode; 2], bsize: BlockSize,
+  tile_bo: TileBlockOffset, skip: bool, cfl: CFLParams,
+  palette: &PaletteParams, tx_size: TxSize, tx_type: TxType,
+  mode_context: usize, mv_stack: &[CandidateMV], rdo_type: RDOType,
+  need_recon_pixel: bool, enc_stats: Option<&mut EncoderStats>,
 ) -> (bool, ScaledDistortion) {
   let planes =
     if fi.sequence.chroma_sampling == ChromaSampling::Cs400 { 1 } else { 3 };
@@ -1970,6 +2590,9 @@ pub fn encode_block_post_cdef<T: Pixel, W: Writer>(
   cw.bc.blocks.set_tx_size(tile_bo, bsize, tx_size);
   cw.bc.blocks.set_ref_frames(tile_bo, bsize, ref_frames);
   cw.bc.blocks.set_motion_vectors(tile_bo, bsize, mvs);
+  cw.bc.blocks.set_palette(tile_bo, bsize, palette);
+  cw.bc.blocks.set_compound(tile_bo, bsize, compound);
+  cw.bc.blocks.set_interp_filter(tile_bo, bsize, interp_filter);
 
   //write_q_deltas();
   if cw.bc.code_deltas
@@ -2019,10 +2642,13 @@ pub fn encode_block_post_cdef<T: Pixel, W: Writer>(
         }
       }
 
+      let frame_bo = ts.to_frame_block_offset(tile_bo);
+      let global_mvs = ref_frames.map(|rf| fi.global_mv(rf, frame_bo, bsize));
+
       let ref_mvs = if num_mv_found > 0 {
         [mv_stack[ref_mv_idx].this_mv, mv_stack[ref_mv_idx].comp_mv]
       } else {
-        [MotionVector::default(); 2]
+        global_mvs
       };
 
       let mv_precision = if fi.force_integer_mv != 0 {
@@ -2067,23 +2693,69 @@ pub fn encode_block_post_cdef<T: Pixel, W: Writer>(
           assert!(mv_stack[ref_mv_idx].this_mv.row == mvs[0].row);
           assert!(mv_stack[ref_mv_idx].this_mv.col == mvs[0].col);
         } else {
-          assert!(0 == mvs[0].row);
-          assert!(0 == mvs[0].col);
+          assert_eq!(mvs[0], global_mvs[0]);
         }
       } else if luma_mode == PredictionMode::NEARESTMV {
         if mv_stack.is_empty() {
-          assert_eq!(mvs[0].row, 0);
-          assert_eq!(mvs[0].col, 0);
+          assert_eq!(mvs[0], global_mvs[0]);
         } else {
           assert_eq!(mvs[0].row, mv_stack[0].this_mv.row);
           assert_eq!(mvs[0].col, mv_stack[0].this_mv.col);
         }
       }
+
+      if fi.sequence.enable_interintra_compound
+        && !luma_mode.is_compound()
+        && interintra_allowed(bsize)
+      {
+        cw.write_interintra_mode(w, bsize, compound);
+      } else {
+        debug_assert!(!compound.is_interintra());
+      }
+
+      if cw.is_motion_mode_switchable(
+        fi, tile_bo, bsize, luma_mode, ref_frames, compound,
+      ) {
+        let allow_warped = fi.allow_warped_motion
+          && fi.force_integer_mv == 0
+          && !cw
+            .find_warp_samples(tile_bo, bsize, ref_frames[0], mvs[0])
+            .is_empty();
+        cw.write_motion_mode(w, bsize, motion_mode, allow_warped);
+      } else {
+        assert_eq!(motion_mode, MotionMode::SIMPLE_TRANSLATION);
+      }
+
+      if luma_mode.is_compound() {
+        cw.write_compound_type(w, fi, tile_bo, bsize, ref_frames, compound);
+      }
+
+      if fi.is_filter_switchable
+        && luma_mode.needs_interp_filter(fi, ref_frames, bsize, motion_mode)
+      {
+        cw.write_interp_filter(w, fi, tile_bo, ref_frames, interp_filter);
+      } else {
+        let implied = if fi.is_filter_switchable {
+          FilterMode::REGULAR
+        } else {
+          fi.default_filter
+        };
+        debug_assert_eq!(interp_filter, [implied; 2]);
+      }
     } else {
       cw.write_intra_mode(w, bsize, luma_mode);
     }
   } else {
-    cw.write_intra_mode_kf(w, tile_bo, luma_mode);
+    if fi.allow_intrabc {
+      cw.write_use_intrabc(w, is_inter);
+    }
+    if is_inter {
+      debug_assert!(fi.allow_intrabc && luma_mode == PredictionMode::NEWMV);
+      let ref_dv = intrabc_ref_dv(mv_stack, tile_bo, sb_size);
+      cw.write_intrabc_mv(w, mvs[0], ref_dv);
+    } else {
+      cw.write_intra_mode_kf(w, tile_bo, luma_mode);
+    }
   }
 
   if !is_inter {
@@ -2091,9 +2763,10 @@ pub fn encode_block_post_cdef<T: Pixel, W: Writer>(
       cw.write_angle_delta(w, angle_delta.y, luma_mode);
     }
     if has_chroma(tile_bo, bsize, xdec, ydec, fi.sequence.chroma_sampling) {
-      cw.write_intra_uv_mode(w, chroma_mode, luma_mode, bsize);
+      let cfl_allowed = fi.cfl_allowed(bsize);
+      cw.write_intra_uv_mode(w, chroma_mode, luma_mode, cfl_allowed);
       if chroma_mode.is_cfl() {
-        assert!(bsize.cfl_allowed());
+        assert!(cfl_allowed);
         cw.write_cfl_alphas(w, cfl);
       }
       if chroma_mode.is_directional() && bsize >= BlockSize::BLOCK_8X8 {
@@ -2101,14 +2774,10 @@ pub fn encode_block_post_cdef<T: Pixel, W: Writer>(
       }
     }
 
-    if fi.allow_screen_content_tools > 0
-      && bsize >= BlockSize::BLOCK_8X8
-      && bsize.width() <= 64
-      && bsize.height() <= 64
-    {
-      cw.write_use_palette_mode(
+    if palette_allowed(fi, bsize) {
+      cw.write_palette_mode_info(
         w,
-        false,
+        palette,
         bsize,
         tile_bo,
         luma_mode,
@@ -2116,16 +2785,39 @@ pub fn encode_block_post_cdef<T: Pixel, W: Writer>(
         xdec,
         ydec,
         fi.sequence.chroma_sampling,
+        fi.sequence.bit_depth,
       );
+    } else {
+      debug_assert_eq!(*palette, PaletteParams::default());
     }
 
     if fi.sequence.enable_filter_intra
       && luma_mode == PredictionMode::DC_PRED
+      && palette.size[0] == 0
       && bsize.width() <= 32
       && bsize.height() <= 32
     {
       cw.write_use_filter_intra(w, false, bsize); // turn off FILTER_INTRA
     }
+
+    let frame_bo = ts.to_frame_block_offset(tile_bo);
+    for plane_type in 0..2 {
+      let n = palette.size[plane_type] as usize;
+      if n == 0 {
+        continue;
+      }
+      let (xdec, ydec) = if plane_type == 0 { (0, 0) } else { (xdec, ydec) };
+      let (width, height, visible_w, visible_h) =
+        color_map_dimensions(fi, frame_bo, bsize, xdec, ydec);
+      let mut map = [0u8; 64 * 64];
+      let map = &mut map[..width * height];
+      fill_color_map(
+        fi, ts, tile_bo, bsize, plane_type, palette, 0, 0, width, height, map,
+      );
+      cw.write_palette_color_map(
+        w, map, width, n, plane_type, visible_w, visible_h,
+      );
+    }
   }
 
   // write tx_size here
@@ -2197,7 +2889,18 @@ pub fn encode_block_post_cdef<T: Pixel, W: Writer>(
 
   if is_inter {
     motion_compensate(
-      fi, ts, cw, luma_mode, ref_frames, mvs, bsize, tile_bo, false,
+      fi,
+      ts,
+      cw,
+      luma_mode,
+      ref_frames,
+      mvs,
+      motion_mode,
+      compound,
+      interp_filter,
+      bsize,
+      tile_bo,
+      false,
     );
     write_tx_tree(
       fi,
@@ -2230,6 +2933,7 @@ pub fn encode_block_post_cdef<T: Pixel, W: Writer>(
       tx_type,
       skip,
       cfl,
+      palette,
       false,
       rdo_type,
       need_recon_pixel,
@@ -2237,26 +2941,18 @@ pub fn encode_block_post_cdef<T: Pixel, W: Writer>(
   }
 }
 
-/// # Panics
-///
-/// - If attempting to encode a lossless block (not yet supported)
 pub fn write_tx_blocks<T: Pixel, W: Writer>(
   fi: &FrameInvariants<T>, ts: &mut TileStateMut<'_, T>,
   cw: &mut ContextWriter, w: &mut W, luma_mode: PredictionMode,
   chroma_mode: PredictionMode, angle_delta: AngleDelta,
   tile_bo: TileBlockOffset, bsize: BlockSize, tx_size: TxSize,
-  tx_type: TxType, skip: bool, cfl: CFLParams, luma_only: bool,
-  rdo_type: RDOType, need_recon_pixel: bool,
+  tx_type: TxType, skip: bool, cfl: CFLParams, palette: &PaletteParams,
+  luma_only: bool, rdo_type: RDOType, need_recon_pixel: bool,
 ) -> (bool, ScaledDistortion) {
   let bw = bsize.width_mi() / tx_size.width_mi();
   let bh = bsize.height_mi() / tx_size.height_mi();
   let qidx = get_qidx(fi, ts, cw, tile_bo);
 
-  // TODO: Lossless is not yet supported.
-  if !skip {
-    assert_ne!(qidx, 0);
-  }
-
   let PlaneConfig { xdec, ydec, .. } = ts.input.planes[1].cfg;
   let mut ac = Aligned::<[MaybeUninit<i16>; 32 * 32]>::uninit_array();
   let mut partition_has_coeff: bool = false;
@@ -2302,6 +2998,7 @@ pub fn write_tx_blocks<T: Pixel, W: Writer>(
         qidx,
         &[],
         IntraParam::AngleDelta(angle_delta.y),
+        palette,
         rdo_type,
         need_recon_pixel,
       );
@@ -2324,7 +3021,7 @@ pub fn write_tx_blocks<T: Pixel, W: Writer>(
     fi.sequence.chroma_sampling
   ));
 
-  let uv_tx_size = bsize.largest_chroma_tx_size(xdec, ydec);
+  let uv_tx_size = fi.uv_tx_size(bsize, xdec, ydec);
 
   let mut bw_uv = (bw * tx_size.width_mi()) >> xdec;
   let mut bh_uv = (bh * tx_size.height_mi()) >> ydec;
@@ -2343,7 +3040,10 @@ pub fn write_tx_blocks<T: Pixel, W: Writer>(
     [].as_slice()
   };
 
-  let uv_tx_type = if uv_tx_size.width() >= 32 || uv_tx_size.height() >= 32 {
+  let uv_tx_type = if uv_tx_size.width() >= 32
+    || uv_tx_size.height() >= 32
+    || fi.coded_lossless()
+  {
     TxType::DCT_DCT
   } else {
     uv_intra_mode_to_tx_type_context(chroma_mode)
@@ -2394,6 +3094,7 @@ pub fn write_tx_blocks<T: Pixel, W: Writer>(
           } else {
             IntraParam::AngleDelta(angle_delta.uv)
           },
+          palette,
           rdo_type,
           need_recon_pixel,
         );
@@ -2467,6 +3168,7 @@ pub fn write_tx_tree<T: Pixel, W: Writer>(
         qidx,
         ac,
         IntraParam::AngleDelta(angle_delta_y),
+        &PaletteParams::default(),
         rdo_type,
         need_recon_pixel,
       );
@@ -2491,7 +3193,7 @@ pub fn write_tx_tree<T: Pixel, W: Writer>(
 
   let max_tx_size = max_txsize_rect_lookup[bsize as usize];
   debug_assert!(max_tx_size.block_size() <= BlockSize::BLOCK_64X64);
-  let uv_tx_size = bsize.largest_chroma_tx_size(xdec, ydec);
+  let uv_tx_size = fi.uv_tx_size(bsize, xdec, ydec);
 
   let mut bw_uv = max_tx_size.width_mi() >> xdec;
   let mut bh_uv = max_tx_size.height_mi() >> ydec;
@@ -2551,6 +3253,7 @@ pub fn write_tx_tree<T: Pixel, W: Writer>(
           qidx,
           ac,
           IntraParam::AngleDelta(angle_delta_y),
+          &PaletteParams::default(),
           rdo_type,
           need_recon_pixel,
         );
@@ -2576,6 +3279,9 @@ pub fn encode_block_with_modes<T: Pixel, W: Writer>(
   let cfl = mode_decision.pred_cfl_params;
   let ref_frames = mode_decision.ref_frames;
   let mvs = mode_decision.mvs;
+  let motion_mode = mode_decision.motion_mode;
+  let compound = mode_decision.compound;
+  let interp_filter = mode_decision.interp_filter;
   let mut skip = mode_decision.skip;
   let mut cdef_coded = cw.bc.cdef_coded;
 
@@ -2591,14 +3297,25 @@ pub fn encode_block_with_modes<T: Pixel, W: Writer>(
   let (tx_size, tx_type) = if !mode_decision.skip && !mode_decision.has_coeff {
     skip = true;
     rdo_tx_size_type(
-      fi, ts, cw, bsize, tile_bo, mode_luma, ref_frames, mvs, skip,
+      fi,
+      ts,
+      cw,
+      bsize,
+      tile_bo,
+      mode_luma,
+      ref_frames,
+      mvs,
+      motion_mode,
+      compound,
+      interp_filter,
+      skip,
     )
   } else {
     (mode_decision.tx_size, mode_decision.tx_type)
   };
 
   cdef_coded = encode_block_pre_cdef(
-    &fi.sequence,
+    fi,
     ts,
     cw,
     if cdef_coded { w_post_cdef } else { w_pre_cdef },
@@ -2616,10 +3333,14 @@ pub fn encode_block_with_modes<T: Pixel, W: Writer>(
     mode_decision.angle_delta,
     ref_frames,
     mvs,
+    motion_mode,
+    compound,
+    interp_filter,
     bsize,
     tile_bo,
     skip,
     cfl,
+    &mode_decision.palette,
     tx_size,
     tx_type,
     mode_context,
@@ -2694,7 +3415,7 @@ fn encode_partition_bottomup<T: Pixel, W: Writer>(
     let mode_decision =
       rdo_mode_decision(fi, ts, cw, bsize, tile_bo, inter_cfg);
 
-    if !mode_decision.pred_mode_luma.is_intra() {
+    if mode_decision.ref_frames[0] != INTRA_FRAME {
       // Fill the saved motion structure
       save_block_motion(
         ts,
@@ -2864,7 +3585,7 @@ fn encode_partition_bottomup<T: Pixel, W: Writer>(
       for mode in rdo_output.part_modes.clone() {
         assert!(subsize == mode.bsize);
 
-        if !mode.pred_mode_luma.is_intra() {
+        if mode.ref_frames[0] != INTRA_FRAME {
           save_block_motion(
             ts,
             mode.bsize,
@@ -3007,6 +3728,9 @@ fn encode_partition_topdown<T: Pixel, W: Writer>(
       let skip = part_decision.skip;
       let ref_frames = part_decision.ref_frames;
       let mvs = part_decision.mvs;
+      let mut motion_mode = part_decision.motion_mode;
+      let compound = part_decision.compound;
+      let mut interp_filter = part_decision.interp_filter;
       let mut cdef_coded = cw.bc.cdef_coded;
 
       // Set correct segmentation ID before encoding and before
@@ -3017,7 +3741,18 @@ fn encode_partition_topdown<T: Pixel, W: Writer>(
       // because, with top-down partition RDO, the neighboring contexts
       // of current partition can change, i.e. neighboring partitions can split down more.
       let (tx_size, tx_type) = rdo_tx_size_type(
-        fi, ts, cw, bsize, tile_bo, mode_luma, ref_frames, mvs, skip,
+        fi,
+        ts,
+        cw,
+        bsize,
+        tile_bo,
+        mode_luma,
+        ref_frames,
+        mvs,
+        motion_mode,
+        compound,
+        interp_filter,
+        skip,
       );
 
       let mut mv_stack = ArrayVec::<CandidateMV, 9>::new();
@@ -3032,7 +3767,17 @@ fn encode_partition_topdown<T: Pixel, W: Writer>(
       );
 
       // TODO: proper remap when is_compound is true
-      if !mode_luma.is_intra() {
+      if ref_frames[0] != INTRA_FRAME {
+        let frame_bo = ts.to_frame_block_offset(tile_bo);
+        let global_mvs =
+          ref_frames.map(|rf| fi.global_mv(rf, frame_bo, bsize));
+        // Global motion blocks are only equivalent to blocks using the global
+        // mvs if they are not warped
+        let can_use_global = PredictionMode::GLOBALMV
+          .global_warps(fi, ref_frames, bsize)
+          .iter()
+          .all(Option::is_none);
+
         if is_compound && mode_luma != PredictionMode::GLOBAL_GLOBALMV {
           let match0 = mv_stack[0].this_mv.row == mvs[0].row
             && mv_stack[0].this_mv.col == mvs[0].col;
@@ -3067,10 +3812,8 @@ fn encode_partition_topdown<T: Pixel, W: Writer>(
           };
 
           if mode_luma != PredictionMode::NEAREST_NEARESTMV
-            && mvs[0].row == 0
-            && mvs[0].col == 0
-            && mvs[1].row == 0
-            && mvs[1].col == 0
+            && mvs == global_mvs
+            && can_use_global
           {
             mode_luma = PredictionMode::GLOBAL_GLOBALMV;
           }
@@ -3090,21 +3833,40 @@ fn encode_partition_topdown<T: Pixel, W: Writer>(
               mode_luma = *m;
             }
           }
-          if mode_luma == PredictionMode::NEWMV
-            && mvs[0].row == 0
-            && mvs[0].col == 0
-          {
+          if mode_luma == PredictionMode::NEWMV && mvs[0] == global_mvs[0] {
             mode_luma = if mv_stack.is_empty() {
               PredictionMode::NEARESTMV
             } else if mv_stack.len() == 1 {
               PredictionMode::NEAR0MV
-            } else {
+            } else if can_use_global {
               PredictionMode::GLOBALMV
+            } else {
+              PredictionMode::NEWMV
             };
           }
           mode_chroma = mode_luma;
         }
 
+        // The neighbours may also have changed which motion modes are allowed
+        let switchable = cw.is_motion_mode_switchable(
+          fi, tile_bo, bsize, mode_luma, ref_frames, compound,
+        );
+        if !switchable
+          || (motion_mode == MotionMode::WARPED_CAUSAL
+            && cw
+              .local_warp(fi, tile_bo, bsize, ref_frames[0], mvs[0])
+              .is_none())
+        {
+          motion_mode = MotionMode::SIMPLE_TRANSLATION;
+        }
+
+        // Blocks that became global motion blocks no longer code filters
+        if fi.is_filter_switchable
+          && !mode_luma.needs_interp_filter(fi, ref_frames, bsize, motion_mode)
+        {
+          interp_filter = [FilterMode::REGULAR; 2];
+        }
+
         save_block_motion(
           ts,
           part_decision.bsize,
@@ -3116,7 +3878,7 @@ fn encode_partition_topdown<T: Pixel, W: Writer>(
 
       // FIXME: every final block that has gone through the RDO decision process is encoded twice
       cdef_coded = encode_block_pre_cdef(
-        &fi.sequence,
+        fi,
         ts,
         cw,
         if cdef_coded { w_post_cdef } else { w_pre_cdef },
@@ -3134,10 +3896,14 @@ fn encode_partition_topdown<T: Pixel, W: Writer>(
         part_decision.angle_delta,
         ref_frames,
         mvs,
+        motion_mode,
+        compound,
+        interp_filter,
         bsize,
         tile_bo,
         skip,
         cfl,
+        &part_decision.palette,
         tx_size,
         tx_type,
         mode_context,
@@ -3242,17 +4008,30 @@ fn encode_tile_group<T: Pixel>(
   let mut blocks = FrameBlocks::new(fi.w_in_b, fi.h_in_b);
   let ti = &fi.sequence.tiling;
 
+  // Frames using super-resolution are coded from the input downscaled to
+  // the coded width, their loop restoration is decided against the input.
+  let input = fs.input.clone();
+  if fi.use_superres() {
+    fs.input = Arc::new(downscale_frame(fi, &input));
+    fs.rec =
+      Arc::new(Frame::new(fi.width, fi.height, fi.sequence.chroma_sampling));
+    fs.restoration = RestorationState::new(fi, &fs.input);
+  }
+
   let initial_cdf = get_initial_cdfcontext(fi);
   // dynamic allocation: once per frame
   let mut cdfs = vec![initial_cdf; ti.tile_count()];
+  let mut deferred_sbs: Vec<_> =
+    (0..ti.tile_count()).map(|_| VecDeque::new()).collect();
 
-  let (raw_tiles, stats): (Vec<_>, Vec<_>) = ti
+  let (mut raw_tiles, stats): (Vec<_>, Vec<_>) = ti
     .tile_iter_mut(fs, &mut blocks)
     .zip(cdfs.iter_mut())
+    .zip(deferred_sbs.iter_mut())
     .collect::<Vec<_>>()
     .into_par_iter()
-    .map(|(mut ctx, cdf)| {
-      encode_tile(fi, &mut ctx.ts, cdf, &mut ctx.tb, inter_cfg)
+    .map(|((mut ctx, cdf), sbs_q)| {
+      encode_tile(fi, &mut ctx.ts, cdf, &mut ctx.tb, inter_cfg, sbs_q)
     })
     .unzip();
 
@@ -3263,18 +4042,22 @@ fn encode_tile_group<T: Pixel>(
   /* Frame deblocking operates over a single large tile wrapping the
    * frame rather than the frame itself so that deblocking is
    * available inside RDO when needed */
-  /* TODO: Don't apply if lossless */
-  let levels = fs.apply_tile_state_mut(|ts| {
-    let rec = &mut ts.rec;
-    deblock_filter_optimize(
-      fi,
-      &rec.as_const(),
-      &ts.input.as_tile(),
-      &blocks.as_tile_blocks(),
-      fi.width,
-      fi.height,
-    )
-  });
+  // IntraBC and lossless frames have all of the loop filters disabled.
+  let levels = if fi.allow_intrabc || fi.coded_lossless() {
+    [0; 4]
+  } else {
+    fs.apply_tile_state_mut(|ts| {
+      let rec = &mut ts.rec;
+      deblock_filter_optimize(
+        fi,
+        &rec.as_const(),
+        &ts.input.as_tile(),
+        &blocks.as_tile_blocks(),
+        fi.width,
+        fi.height,
+      )
+    })
+  };
   fs.deblock.levels = levels;
 
   if fs.deblock.levels[0] != 0 || fs.deblock.levels[1] != 0 {
@@ -3292,33 +4075,53 @@ fn encode_tile_group<T: Pixel>(
     });
   }
 
-  if fi.sequence.enable_restoration {
+  if fi.sequence.enable_restoration
+    && !fi.allow_intrabc
+    && !fi.coded_lossless()
+  {
     // Until the loop filters are better pipelined, we'll need to keep
     // around a copy of both the deblocked and cdeffed frame.
-    let deblocked_frame = (*fs.rec).clone();
+    let mut deblocked_frame = (*fs.rec).clone();
 
-    /* TODO: Don't apply if lossless */
     if fi.sequence.enable_cdef {
       fs.apply_tile_state_mut(|ts| {
         let rec = &mut ts.rec;
         cdef_filter_tile(fi, &deblocked_frame, &blocks.as_tile_blocks(), rec);
       });
     }
-    /* TODO: Don't apply if lossless */
+    // Loop restoration runs on the upscaled frame
+    if fi.use_superres() {
+      deblocked_frame = upscale_frame(fi, &deblocked_frame);
+      let cdeffed = upscale_frame(fi, &fs.rec);
+      rdo_lrf_superres(fi, &mut fs.restoration, &cdeffed, &input);
+      raw_tiles = ti
+        .tile_iter_mut(fs, &mut blocks)
+        .zip(cdfs.iter_mut())
+        .zip(deferred_sbs)
+        .collect::<Vec<_>>()
+        .into_par_iter()
+        .map(|((mut ctx, cdf), sbs_q)| {
+          write_superres_tile(fi, &mut ctx.ts, cdf, &mut ctx.tb, sbs_q)
+        })
+        .collect();
+      fs.rec = Arc::new(cdeffed);
+    }
     fs.restoration.lrf_filter_frame(
       Arc::get_mut(&mut fs.rec).unwrap(),
       &deblocked_frame,
       fi,
     );
   } else {
-    /* TODO: Don't apply if lossless */
-    if fi.sequence.enable_cdef {
+    if fi.sequence.enable_cdef && !fi.allow_intrabc && !fi.coded_lossless() {
       let deblocked_frame = (*fs.rec).clone();
       fs.apply_tile_state_mut(|ts| {
         let rec = &mut ts.rec;
         cdef_filter_tile(fi, &deblocked_frame, &blocks.as_tile_blocks(), rec);
       });
     }
+    if fi.use_superres() {
+      fs.rec = Arc::new(upscale_frame(fi, &fs.rec));
+    }
   }
 
   let (idx_max, max_len) = raw_tiles
@@ -3338,6 +4141,7 @@ fn encode_tile_group<T: Pixel>(
   let max_tile_size_bytes = ILog::ilog(max_len).div_ceil(8) as u32;
   debug_assert!(max_tile_size_bytes > 0 && max_tile_size_bytes <= 4);
   fs.max_tile_size_bytes = max_tile_size_bytes;
+  fs.input = input;
 
   build_raw_tile_group(ti, &raw_tiles, max_tile_size_bytes)
 }
@@ -3372,11 +4176,30 @@ pub struct SBSQueueEntry {
   pub w_post_cdef: WriterBase<WriterRecorder>,
 }
 
+impl SBSQueueEntry {
+  /// Replays the recorded symbols of the superblock around its CDEF index,
+  /// once its loop restoration units are coded.
+  fn replay<T: Pixel>(
+    &mut self, fi: &FrameInvariants<T>, cw: &mut ContextWriter,
+    w: &mut WriterBase<WriterEncoder>,
+  ) {
+    self.w_pre_cdef.replay(w);
+    // Now code CDEF into the middle of the block
+    if self.cdef_coded {
+      let cdef_index = cw.bc.blocks.get_cdef(self.sbo);
+      cw.write_cdef(w, cdef_index, fi.cdef_bits);
+      // Code queued symbols that come after the CDEF index
+      self.w_post_cdef.replay(w);
+    }
+  }
+}
+
 #[profiling::function]
 fn check_lf_queue<T: Pixel>(
   fi: &FrameInvariants<T>, ts: &mut TileStateMut<'_, T>,
   cw: &mut ContextWriter, w: &mut WriterBase<WriterEncoder>,
-  sbs_q: &mut VecDeque<SBSQueueEntry>, last_lru_ready: &mut [i32; 3],
+  sbs_q: &mut VecDeque<SBSQueueEntry>,
+  deferred_sbs: &mut VecDeque<SBSQueueEntry>, last_lru_ready: &mut [i32; 3],
   last_lru_rdoed: &mut [i32; 3], last_lru_coded: &mut [i32; 3],
   deblock_p: bool,
 ) {
@@ -3398,7 +4221,11 @@ fn check_lf_queue<T: Pixel>(
       }
       if check_queue {
         // yes, this entry is ready
-        if qe.cdef_coded || fi.sequence.enable_restoration {
+        if qe.cdef_coded
+          || (fi.sequence.enable_restoration
+            && !fi.allow_intrabc
+            && !fi.coded_lossless())
+        {
           // only RDO once for a given LRU.
 
           // One quirk worth noting: LRUs in different planes
@@ -3436,8 +4263,17 @@ fn check_lf_queue<T: Pixel>(
           }
         }
         // write LRF information
-        if !fi.allow_intrabc && fi.sequence.enable_restoration {
-          // TODO: also disallow if lossless
+        if !fi.allow_intrabc
+          && !fi.coded_lossless()
+          && fi.sequence.enable_restoration
+        {
+          if fi.use_superres() {
+            // The restoration units of a frame using super-resolution are
+            // decided once the whole frame is upscaled, the superblock is
+            // coded by `write_superres_tile` then.
+            deferred_sbs.extend(sbs_q.pop_front());
+            continue;
+          }
           for pli in 0..planes {
             if qe.lru_index[pli] != -1
               && last_lru_coded[pli] < qe.lru_index[pli]
@@ -3448,14 +4284,7 @@ fn check_lf_queue<T: Pixel>(
           }
         }
         // Now that loop restoration is coded, we can replay the initial block bits
-        qe.w_pre_cdef.replay(w);
-        // Now code CDEF into the middle of the block
-        if qe.cdef_coded {
-          let cdef_index = cw.bc.blocks.get_cdef(qe.sbo);
-          cw.write_cdef(w, cdef_index, fi.cdef_bits);
-          // Code queued symbols that come after the CDEF index
-          qe.w_post_cdef.replay(w);
-        }
+        qe.replay(fi, cw, w);
         sbs_q.pop_front();
       }
     } else {
@@ -3468,7 +4297,7 @@ fn check_lf_queue<T: Pixel>(
 fn encode_tile<'a, T: Pixel>(
   fi: &FrameInvariants<T>, ts: &'a mut TileStateMut<'_, T>,
   fc: &'a mut CDFContext, blocks: &'a mut TileBlocksMut<'a>,
-  inter_cfg: &InterConfig,
+  inter_cfg: &InterConfig, deferred_sbs: &mut VecDeque<SBSQueueEntry>,
 ) -> (Vec<u8>, EncoderStats) {
   let mut enc_stats = EncoderStats::default();
   let mut w = WriterEncoder::new();
@@ -3482,6 +4311,17 @@ fn encode_tile<'a, T: Pixel>(
   let mut last_lru_rdoed = [-1; 3];
   let mut last_lru_coded = [-1; 3];
 
+  if fi.allow_intrabc {
+    let PlaneConfig { xdec, ydec, .. } = ts.input.planes[1].cfg;
+    ts.intrabc_hash = Some(IntraBcHashTable::new(
+      &ts.input_tile.planes[0],
+      ts.width,
+      ts.height,
+      1 << xdec,
+      1 << ydec,
+    ));
+  }
+
   // main loop
   for sby in 0..ts.sb_height {
     cw.bc.reset_left_contexts(planes);
@@ -3544,7 +4384,12 @@ fn encode_tile<'a, T: Pixel>(
         // queue our superblock for when the LRU is complete
         sbs_qe.cdef_coded = cw.bc.cdef_coded;
         for pli in 0..planes {
-          if let Some((lru_x, lru_y)) =
+          if fi.use_superres() {
+            // restoration units do not follow the coded superblocks,
+            // they are decided once the whole frame is upscaled
+            sbs_qe.lru_index[pli] = -1;
+            check_queue = true;
+          } else if let Some((lru_x, lru_y)) =
             ts.restoration.planes[pli].restoration_unit_index(tile_sbo, false)
           {
             let lru_index = ts.restoration.planes[pli]
@@ -3573,6 +4418,7 @@ fn encode_tile<'a, T: Pixel>(
             &mut cw,
             &mut w,
             &mut sbs_q,
+            deferred_sbs,
             &mut last_lru_ready,
             &mut last_lru_rdoed,
             &mut last_lru_coded,
@@ -3585,15 +4431,18 @@ fn encode_tile<'a, T: Pixel>(
 
   if fi.sequence.enable_delayed_loopfilter_rdo {
     // Solve deblocking for just this tile
-    /* TODO: Don't apply if lossless */
-    let deblock_levels = deblock_filter_optimize(
-      fi,
-      &ts.rec.as_const(),
-      &ts.input_tile,
-      &cw.bc.blocks.as_const(),
-      fi.width,
-      fi.height,
-    );
+    let deblock_levels = if fi.allow_intrabc || fi.coded_lossless() {
+      [0; 4]
+    } else {
+      deblock_filter_optimize(
+        fi,
+        &ts.rec.as_const(),
+        &ts.input_tile,
+        &cw.bc.blocks.as_const(),
+        fi.width,
+        fi.height,
+      )
+    };
 
     if deblock_levels[0] != 0 || deblock_levels[1] != 0 {
       // copy reconstruction to a temp frame to restore it later
@@ -3629,6 +4478,7 @@ fn encode_tile<'a, T: Pixel>(
         &mut cw,
         &mut w,
         &mut sbs_q,
+        deferred_sbs,
         &mut last_lru_ready,
         &mut last_lru_rdoed,
         &mut last_lru_coded,
@@ -3653,6 +4503,7 @@ fn encode_tile<'a, T: Pixel>(
         &mut cw,
         &mut w,
         &mut sbs_q,
+        deferred_sbs,
         &mut last_lru_ready,
         &mut last_lru_rdoed,
         &mut last_lru_coded,
@@ -3670,6 +4521,27 @@ fn encode_tile<'a, T: Pixel>(
   (w.done(), enc_stats)
 }
 
+/// Codes a tile of a frame using super-resolution once its restoration
+/// units are decided, from the superblocks that `encode_tile` deferred.
+fn write_superres_tile<'a, T: Pixel>(
+  fi: &FrameInvariants<T>, ts: &'a mut TileStateMut<'_, T>,
+  fc: &'a mut CDFContext, blocks: &'a mut TileBlocksMut<'a>,
+  sbs_q: VecDeque<SBSQueueEntry>,
+) -> Vec<u8> {
+  let mut w = WriterEncoder::new();
+  let planes =
+    if fi.sequence.chroma_sampling == ChromaSampling::Cs400 { 1 } else { 3 };
+
+  let mut cw = ContextWriter::new(fc, BlockContext::new(blocks));
+  for mut qe in sbs_q {
+    for pli in 0..planes {
+      cw.write_lrf_superres(&mut w, &mut ts.restoration, ts.sbo, qe.sbo, pli);
+    }
+    qe.replay(fi, &mut cw, &mut w);
+  }
+  w.done()
+}
+
 #[allow(unused)]
 fn write_tile_group_header(tile_start_and_end_present_flag: bool) -> Vec<u8> {
   let mut buf = Vec::new();
@@ -3694,45 +4566,29 @@ pub fn encode_show_existing_frame<T: Pixel>(
   fi: &FrameInvariants<T>, fs: &mut FrameState<T>, inter_cfg: &InterConfig,
 ) -> Vec<u8> {
   debug_assert!(fi.is_show_existing_frame());
-  let obu_extension = 0;
+  let obu_extension = fi.obu_extension();
 
   let mut packet = Vec::new();
 
   if fi.frame_type == FrameType::KEY {
-    write_key_frame_obus(&mut packet, fi, obu_extension).unwrap();
+    write_key_frame_obus(&mut packet, fi).unwrap();
   }
 
-  for t35 in fi.t35_metadata.iter() {
-    let mut t35_buf = Vec::new();
-    let mut t35_bw = BitWriter::endian(&mut t35_buf, BigEndian);
-    t35_bw.write_t35_metadata_obu(t35).unwrap();
-    packet.write_all(&t35_buf).unwrap();
-    t35_buf.clear();
-  }
-
-  let mut buf1 = Vec::new();
-  let mut buf2 = Vec::new();
-  {
-    let mut bw2 = BitWriter::endian(&mut buf2, BigEndian);
-    bw2.write_frame_header_obu(fi, fs, inter_cfg).unwrap();
-  }
-
-  {
-    let mut bw1 = BitWriter::endian(&mut buf1, BigEndian);
-    bw1.write_obu_header(ObuType::OBU_FRAME_HEADER, obu_extension).unwrap();
-  }
-  packet.write_all(&buf1).unwrap();
-  buf1.clear();
+  write_t35_metadata_obus(&mut packet, fi).unwrap();
 
+  let mut buf = Vec::new();
   {
-    let mut bw1 = BitWriter::endian(&mut buf1, BigEndian);
-    bw1.write_uleb128(buf2.len() as u64).unwrap();
+    let mut bw = BitWriter::endian(&mut buf, BigEndian);
+    bw.write_frame_header_obu(fi, fs, inter_cfg).unwrap();
   }
-  packet.write_all(&buf1).unwrap();
-  buf1.clear();
-
-  packet.write_all(&buf2).unwrap();
-  buf2.clear();
+  write_obu(
+    &mut packet,
+    ObuType::OBU_FRAME_HEADER,
+    obu_extension,
+    &buf,
+    fi.config.annex_b,
+  )
+  .unwrap();
 
   let map_idx = fi.frame_to_show_map_idx as usize;
   if let Some(ref rec) = fi.rec_buffer.frames[map_idx] {
@@ -3769,10 +4625,18 @@ pub fn encode_frame<T: Pixel>(
   fi: &FrameInvariants<T>, fs: &mut FrameState<T>, inter_cfg: &InterConfig,
 ) -> Vec<u8> {
   debug_assert!(!fi.is_show_existing_frame());
-  let obu_extension = 0;
+  let obu_extension = fi.obu_extension();
 
   let mut packet = Vec::new();
 
+  let coded_fi;
+  let fi = if fi.use_superres() {
+    coded_fi = superres_coded_frame_invariants(fi);
+    &coded_fi
+  } else {
+    fi
+  };
+
   if fi.enable_segmentation {
     fs.segmentation = get_initial_segmentation(fi);
     segmentation_optimize(fi, fs);
@@ -3780,42 +4644,25 @@ pub fn encode_frame<T: Pixel>(
   let tile_group = encode_tile_group(fi, fs, inter_cfg);
 
   if fi.frame_type == FrameType::KEY {
-    write_key_frame_obus(&mut packet, fi, obu_extension).unwrap();
-  }
-
-  for t35 in fi.t35_metadata.iter() {
-    let mut t35_buf = Vec::new();
-    let mut t35_bw = BitWriter::endian(&mut t35_buf, BigEndian);
-    t35_bw.write_t35_metadata_obu(t35).unwrap();
-    packet.write_all(&t35_buf).unwrap();
-    t35_buf.clear();
-  }
-
-  let mut buf1 = Vec::new();
-  let mut buf2 = Vec::new();
-  {
-    let mut bw2 = BitWriter::endian(&mut buf2, BigEndian);
-    bw2.write_frame_header_obu(fi, fs, inter_cfg).unwrap();
+    write_key_frame_obus(&mut packet, fi).unwrap();
   }
 
-  {
-    let mut bw1 = BitWriter::endian(&mut buf1, BigEndian);
-    bw1.write_obu_header(ObuType::OBU_FRAME, obu_extension).unwrap();
-  }
-  packet.write_all(&buf1).unwrap();
-  buf1.clear();
+  write_t35_metadata_obus(&mut packet, fi).unwrap();
 
+  let mut buf = Vec::new();
   {
-    let mut bw1 = BitWriter::endian(&mut buf1, BigEndian);
-    bw1.write_uleb128((buf2.len() + tile_group.len()) as u64).unwrap();
+    let mut bw = BitWriter::endian(&mut buf, BigEndian);
+    bw.write_frame_header_obu(fi, fs, inter_cfg).unwrap();
   }
-  packet.write_all(&buf1).unwrap();
-  buf1.clear();
-
-  packet.write_all(&buf2).unwrap();
-  buf2.clear();
-
-  packet.write_all(&tile_group).unwrap();
+  buf.extend_from_slice(&tile_group);
+  write_obu(
+    &mut packet,
+    ObuType::OBU_FRAME,
+    obu_extension,
+    &buf,
+    fi.config.annex_b,
+  )
+  .unwrap();
   packet
 }
 
@@ -3835,6 +4682,7 @@ pub fn update_rec_buffer<T: Pixel>(
     frame_me_stats: fs.frame_me_stats.clone(),
     output_frameno,
     segmentation: fs.segmentation,
+    gm_params: fi.gm_params,
   });
   for i in 0..REF_FRAMES {
     if (fi.refresh_frame_flags & (1 << i)) != 0 {
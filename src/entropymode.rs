@@ -18,7 +18,7 @@ use crate::util::*;
 pub const PALETTE_BSIZE_CTXS: usize = 7;
 pub const PALETTE_Y_MODE_CONTEXTS: usize = 3;
 pub const PALETTE_UV_MODE_CONTEXTS: usize = 2;
-const PALETTE_COLOR_INDEX_CONTEXTS: usize = 5;
+pub const PALETTE_COLOR_INDEX_CONTEXTS: usize = 5;
 const RESTORE_SWITCHABLE_TYPES: usize = 3;
 pub const TX_SIZE_CONTEXTS: usize = 3;
 
@@ -513,11 +513,6 @@ pub static default_cfl_alpha_cdf: [[u16; CFL_ALPHABET_SIZE];
   ],
 ]);
 
-// This does not appear to be used in the rust project currently
-const SWITCHABLE_FILTERS: usize = 3;
-const SWITCHABLE_FILTER_CONTEXTS: usize = (SWITCHABLE_FILTERS + 1) * 4;
-
-#[allow(unused)]
 pub static default_switchable_interp_cdf: [[u16; SWITCHABLE_FILTERS];
   SWITCHABLE_FILTER_CONTEXTS] = cdf_2d([
   [31935, 32720],
@@ -569,11 +564,9 @@ pub static default_compound_mode_cdf: [[u16; INTER_COMPOUND_MODES];
   [13046, 23214, 24505, 25942, 27435, 28442, 29330],
 ]);
 
-#[allow(unused)]
 pub static default_interintra_cdf: [[u16; 2]; BLOCK_SIZE_GROUPS] =
   cdf_2d([[16384], [26887], [27597], [30237]]);
 
-#[allow(unused)]
 pub static default_interintra_mode_cdf: [[u16;
   InterIntraMode::INTERINTRA_MODES as usize];
   BLOCK_SIZE_GROUPS] = cdf_2d([
@@ -583,7 +576,6 @@ pub static default_interintra_mode_cdf: [[u16;
   [4238, 11537, 25926],
 ]);
 
-#[allow(unused)]
 pub static default_wedge_interintra_cdf: [[u16; 2];
   BlockSize::BLOCK_SIZES_ALL] = cdf_2d([
   [16384],
@@ -610,7 +602,6 @@ pub static default_wedge_interintra_cdf: [[u16; 2];
   [16384],
 ]);
 
-#[allow(unused)]
 pub static default_compound_type_cdf: [[u16;
   CompoundType::COMPOUND_TYPES as usize - 1];
   BlockSize::BLOCK_SIZES_ALL] = cdf_2d([
@@ -638,7 +629,6 @@ pub static default_compound_type_cdf: [[u16;
   [16384],
 ]);
 
-#[allow(unused)]
 pub static default_wedge_idx_cdf: [[u16; 16]; BlockSize::BLOCK_SIZES_ALL] =
   cdf_2d([
     [
@@ -731,7 +721,6 @@ pub static default_wedge_idx_cdf: [[u16; 16]; BlockSize::BLOCK_SIZES_ALL] =
     ],
   ]);
 
-#[allow(unused)]
 pub static default_motion_mode_cdf: [[u16;
   MotionMode::MOTION_MODES as usize];
   BlockSize::BLOCK_SIZES_ALL] = cdf_2d([
@@ -759,7 +748,6 @@ pub static default_motion_mode_cdf: [[u16;
   [29742, 31203],
 ]);
 
-#[allow(unused)]
 pub static default_obmc_cdf: [[u16; 2]; BlockSize::BLOCK_SIZES_ALL] =
   cdf_2d([
     [16384],
@@ -820,7 +808,6 @@ pub static default_comp_ref_cdf: [[[u16; 2]; FWD_REFS - 1]; REF_CONTEXTS] =
 pub static default_comp_bwdref_cdf: [[[u16; 2]; BWD_REFS - 1]; REF_CONTEXTS] =
   cdf_3d([[[2235], [1423]], [[17182], [15175]], [[30606], [30489]]]);
 
-#[allow(unused)]
 pub static default_palette_y_size_cdf: [[u16;
   PaletteSize::PALETTE_SIZES as usize];
   PALETTE_BSIZE_CTXS] = cdf_2d([
@@ -833,7 +820,6 @@ pub static default_palette_y_size_cdf: [[u16;
   [14940, 20797, 21678, 24186, 27033, 28999],
 ]);
 
-#[allow(unused)]
 pub static default_palette_uv_size_cdf: [[u16;
   PaletteSize::PALETTE_SIZES as usize];
   PALETTE_BSIZE_CTXS] = cdf_2d([
@@ -860,105 +846,126 @@ pub static default_palette_y_mode_cdfs: [[[u16; 2]; PALETTE_Y_MODE_CONTEXTS];
 pub static default_palette_uv_mode_cdfs: [[u16; 2]; PALETTE_UV_MODE_CONTEXTS] =
   cdf_2d([[32461], [21488]]);
 
-#[allow(unused)]
-pub static default_palette_y_color_index_cdf: [[[u16;
-  PaletteColor::PALETTE_COLORS as usize];
+pub static default_palette_color_idx_cdf2: [[[u16; 2];
   PALETTE_COLOR_INDEX_CONTEXTS];
-  PaletteSize::PALETTE_SIZES as usize] = [
-  cdf_2d([[28710], [16384], [10553], [27036], [31603]]),
-  cdf_2d([
+  PLANE_TYPES] = cdf_3d([
+  [[28710], [16384], [10553], [27036], [31603]],
+  [[29089], [16384], [8713], [29257], [31610]],
+]);
+
+pub static default_palette_color_idx_cdf3: [[[u16; 3];
+  PALETTE_COLOR_INDEX_CONTEXTS];
+  PLANE_TYPES] = cdf_3d([
+  [
     [27877, 30490],
     [11532, 25697],
     [6544, 30234],
     [23018, 28072],
     [31915, 32385],
-  ]),
-  cdf_2d([
-    [25572, 28046, 30045],
-    [9478, 21590, 27256],
-    [7248, 26837, 29824],
-    [19167, 24486, 28349],
-    [31400, 31825, 32250],
-  ]),
-  cdf_2d([
-    [24779, 26955, 28576, 30282],
-    [8669, 20364, 24073, 28093],
-    [4255, 27565, 29377, 31067],
-    [19864, 23674, 26716, 29530],
-    [31646, 31893, 32147, 32426],
-  ]),
-  cdf_2d([
-    [23132, 25407, 26970, 28435, 30073],
-    [7443, 17242, 20717, 24762, 27982],
-    [6300, 24862, 26944, 28784, 30671],
-    [18916, 22895, 25267, 27435, 29652],
-    [31270, 31550, 31808, 32059, 32353],
-  ]),
-  cdf_2d([
-    [23105, 25199, 26464, 27684, 28931, 30318],
-    [6950, 15447, 18952, 22681, 25567, 28563],
-    [7560, 23474, 25490, 27203, 28921, 30708],
-    [18544, 22373, 24457, 26195, 28119, 30045],
-    [31198, 31451, 31670, 31882, 32123, 32391],
-  ]),
-  cdf_2d([
-    [21689, 23883, 25163, 26352, 27506, 28827, 30195],
-    [6892, 15385, 17840, 21606, 24287, 26753, 29204],
-    [5651, 23182, 25042, 26518, 27982, 29392, 30900],
-    [19349, 22578, 24418, 25994, 27524, 29031, 30448],
-    [31028, 31270, 31504, 31705, 31927, 32153, 32392],
-  ]),
-];
-
-#[allow(unused)]
-pub static default_palette_uv_color_index_cdf: [[[u16;
-  PaletteColor::PALETTE_COLORS as usize];
-  PALETTE_COLOR_INDEX_CONTEXTS];
-  PaletteSize::PALETTE_SIZES as usize] = [
-  cdf_2d([[29089], [16384], [8713], [29257], [31610]]),
-  cdf_2d([
+  ],
+  [
     [25257, 29145],
     [12287, 27293],
     [7033, 27960],
     [20145, 25405],
     [30608, 31639],
-  ]),
-  cdf_2d([
+  ],
+]);
+
+pub static default_palette_color_idx_cdf4: [[[u16; 4];
+  PALETTE_COLOR_INDEX_CONTEXTS];
+  PLANE_TYPES] = cdf_3d([
+  [
+    [25572, 28046, 30045],
+    [9478, 21590, 27256],
+    [7248, 26837, 29824],
+    [19167, 24486, 28349],
+    [31400, 31825, 32250],
+  ],
+  [
     [24210, 27175, 29903],
     [9888, 22386, 27214],
     [5901, 26053, 29293],
     [18318, 22152, 28333],
     [30459, 31136, 31926],
-  ]),
-  cdf_2d([
+  ],
+]);
+
+pub static default_palette_color_idx_cdf5: [[[u16; 5];
+  PALETTE_COLOR_INDEX_CONTEXTS];
+  PLANE_TYPES] = cdf_3d([
+  [
+    [24779, 26955, 28576, 30282],
+    [8669, 20364, 24073, 28093],
+    [4255, 27565, 29377, 31067],
+    [19864, 23674, 26716, 29530],
+    [31646, 31893, 32147, 32426],
+  ],
+  [
     [22980, 25479, 27781, 29986],
     [8413, 21408, 24859, 28874],
     [2257, 29449, 30594, 31598],
     [19189, 21202, 25915, 28620],
     [31844, 32044, 32281, 32518],
-  ]),
-  cdf_2d([
+  ],
+]);
+
+pub static default_palette_color_idx_cdf6: [[[u16; 6];
+  PALETTE_COLOR_INDEX_CONTEXTS];
+  PLANE_TYPES] = cdf_3d([
+  [
+    [23132, 25407, 26970, 28435, 30073],
+    [7443, 17242, 20717, 24762, 27982],
+    [6300, 24862, 26944, 28784, 30671],
+    [18916, 22895, 25267, 27435, 29652],
+    [31270, 31550, 31808, 32059, 32353],
+  ],
+  [
     [22217, 24567, 26637, 28683, 30548],
     [7307, 16406, 19636, 24632, 28424],
     [4441, 25064, 26879, 28942, 30919],
     [17210, 20528, 23319, 26750, 29582],
     [30674, 30953, 31396, 31735, 32207],
-  ]),
-  cdf_2d([
+  ],
+]);
+
+pub static default_palette_color_idx_cdf7: [[[u16; 7];
+  PALETTE_COLOR_INDEX_CONTEXTS];
+  PLANE_TYPES] = cdf_3d([
+  [
+    [23105, 25199, 26464, 27684, 28931, 30318],
+    [6950, 15447, 18952, 22681, 25567, 28563],
+    [7560, 23474, 25490, 27203, 28921, 30708],
+    [18544, 22373, 24457, 26195, 28119, 30045],
+    [31198, 31451, 31670, 31882, 32123, 32391],
+  ],
+  [
     [21239, 23168, 25044, 26962, 28705, 30506],
     [6545, 15012, 18004, 21817, 25503, 28701],
     [3448, 26295, 27437, 28704, 30126, 31442],
     [15889, 18323, 21704, 24698, 26976, 29690],
     [30988, 31204, 31479, 31734, 31983, 32325],
-  ]),
-  cdf_2d([
+  ],
+]);
+
+pub static default_palette_color_idx_cdf8: [[[u16; 8];
+  PALETTE_COLOR_INDEX_CONTEXTS];
+  PLANE_TYPES] = cdf_3d([
+  [
+    [21689, 23883, 25163, 26352, 27506, 28827, 30195],
+    [6892, 15385, 17840, 21606, 24287, 26753, 29204],
+    [5651, 23182, 25042, 26518, 27982, 29392, 30900],
+    [19349, 22578, 24418, 25994, 27524, 29031, 30448],
+    [31028, 31270, 31504, 31705, 31927, 32153, 32392],
+  ],
+  [
     [21442, 23288, 24758, 26246, 27649, 28980, 30563],
     [5863, 14933, 17552, 20668, 23683, 26411, 29273],
     [3415, 25810, 26877, 27990, 29223, 30394, 31618],
     [17965, 20084, 22232, 23974, 26274, 28402, 30390],
     [31190, 31329, 31516, 31679, 31825, 32026, 32322],
-  ]),
-];
+  ],
+]);
 
 pub static default_txfm_partition_cdf: [[u16; 2]; TXFM_PARTITION_CONTEXTS] =
   cdf_2d([
@@ -992,11 +999,9 @@ pub static default_skip_cdfs: [[u16; 2]; SKIP_CONTEXTS] =
 pub static default_skip_mode_cdfs: [[u16; 2]; SKIP_MODE_CONTEXTS] =
   cdf_2d([[32621], [20708], [8127]]);
 
-#[allow(unused)]
 pub static default_compound_idx_cdfs: [[u16; 2]; COMP_INDEX_CONTEXTS] =
   cdf_2d([[18244], [12865], [7053], [13259], [9334], [4644]]);
 
-#[allow(unused)]
 pub static default_comp_group_idx_cdfs: [[u16; 2]; COMP_GROUP_IDX_CONTEXTS] =
   cdf_2d([[26607], [22891], [18840], [24594], [19934], [22674]]);
 
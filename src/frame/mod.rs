@@ -44,6 +44,42 @@ pub struct FrameParameters {
   pub opaque: Option<Opaque>,
   /// List of t35 metadata associated with this frame
   pub t35_metadata: Box<[T35]>,
+  /// Presentation timestamp of the frame, in units of the `time_base`.
+  ///
+  /// Defaults to the end of the previous frame (0 for the first frame).
+  pub pts: Option<u64>,
+  /// Duration of the frame, in units of the `time_base`.
+  ///
+  /// Defaults to the distance to the timestamp of the next frame when it is
+  /// known, and to the duration of the previous frame (1 for the first
+  /// frame) otherwise.
+  pub duration: Option<u64>,
+  /// Base quantizer index (1-255) to encode the frame with, on the same
+  /// scale as `EncoderConfig::quantizer`, instead of the one selected by the
+  /// rate control. The frame header carries it as is, whatever the type of
+  /// the frame. Lossless coding is only available for the whole stream.
+  pub quantizer: Option<u8>,
+  /// Offset added to the base quantizer index of the frame, whether it was
+  /// selected by the rate control or set with `quantizer`.
+  pub quantizer_offset: i16,
+  /// Do not use the frame as a reference for the following frames.
+  ///
+  /// Ignored for key frames, switch frames, and frames coded ahead of their
+  /// display order when reordering is enabled.
+  pub no_reference: bool,
+  /// Relative importance of each 8x8 block of the frame, in raster order.
+  ///
+  /// The map has one entry per 8x8 block, with the frame dimensions rounded
+  /// up to a multiple of 8, and every entry must be positive. The distortion
+  /// of each block is weighted by its importance, on top of the weights the
+  /// encoder derives from the activity and the temporal dependencies of the
+  /// blocks: a block twice as important as the average of the frame gets a
+  /// quantizer about `sqrt(2)` times smaller.
+  ///
+  /// These quantizers are signalled with the segmentation of the frame, which
+  /// is enabled for the frames with a map even if the speed settings disable
+  /// it, lossless mode aside.
+  pub importance_map: Option<Box<[f32]>>,
 }
 
 pub use v_frame::frame::Frame;
@@ -161,9 +161,15 @@ impl Arbitrary<'_> for ArbitraryConfig {
     enc.max_key_frame_interval = Arbitrary::arbitrary(u)?;
     enc.reservoir_frame_delay = Arbitrary::arbitrary(u)?;
     enc.low_latency = Arbitrary::arbitrary(u)?;
+    enc.annex_b = Arbitrary::arbitrary(u)?;
     enc.quantizer = Arbitrary::arbitrary(u)?;
     enc.min_quantizer = Arbitrary::arbitrary(u)?;
     enc.bitrate = Arbitrary::arbitrary(u)?;
+    enc.crf = Arbitrary::arbitrary(u)?;
+    enc.drop_frames = Arbitrary::arbitrary(u)?;
+    enc.max_bitrate = Arbitrary::arbitrary(u)?;
+    enc.buffer_size = Arbitrary::arbitrary(u)?;
+    enc.lossless = Arbitrary::arbitrary(u)?;
     enc.tile_cols = Arbitrary::arbitrary(u)?;
     enc.tile_rows = Arbitrary::arbitrary(u)?;
     enc.tiles = Arbitrary::arbitrary(u)?;
@@ -226,12 +232,31 @@ impl Arbitrary<'_> for ArbitraryEncoder {
       min_key_frame_interval: u.int_in_range(0..=3)?,
       max_key_frame_interval: u.int_in_range(1..=4)?,
       low_latency: Arbitrary::arbitrary(u)?,
+      temporal_layers: 1,
       quantizer: Arbitrary::arbitrary(u)?,
       min_quantizer: Arbitrary::arbitrary(u)?,
       bitrate: Arbitrary::arbitrary(u)?,
+      crf: Arbitrary::arbitrary(u)?,
+      quality_target: None,
+      drop_frames: Arbitrary::arbitrary(u)?,
+      max_bitrate: Arbitrary::arbitrary(u)?,
+      buffer_size: Arbitrary::arbitrary(u)?,
+      enable_decoder_model_info: Arbitrary::arbitrary(u)?,
+      lossless: Arbitrary::arbitrary(u)?,
       tile_cols: u.int_in_range(0..=2)?,
       tile_rows: u.int_in_range(0..=2)?,
       tiles: u.int_in_range(0..=16)?,
+      superres_mode: *u.choose(&[
+        SuperresMode::None,
+        SuperresMode::Fixed(16),
+        SuperresMode::Auto,
+      ])?,
+      screen_content: *u.choose(&[
+        ScreenContentMode::Auto,
+        ScreenContentMode::On,
+        ScreenContentMode::Off,
+      ])?,
+      zones: Vec::new(),
 
       chroma_sampling: *u.choose(&[
         ChromaSampling::Cs420,
@@ -254,8 +279,10 @@ impl Arbitrary<'_> for ArbitraryEncoder {
       mastering_display: arbitrary_mastering_display(u)?,
       content_light: arbitrary_content_light(u)?,
       level_idx: Some(31),
+      auto_level: false,
       enable_timing_info: Arbitrary::arbitrary(u)?,
       switch_frame_interval: u.int_in_range(0..=3)?,
+      annex_b: Arbitrary::arbitrary(u)?,
       tune: *u.choose(&[Tune::Psnr, Tune::Psychovisual])?,
       film_grain_params: None,
     };
@@ -314,6 +341,7 @@ pub struct DecodeTestParameters<T: Pixel> {
   tile_cols_log2: usize,
   tile_rows_log2: usize,
   still_picture: bool,
+  lossless: bool,
   pixel: PhantomData<T>,
 }
 
@@ -341,6 +369,7 @@ impl<T: Pixel> Arbitrary<'_> for DecodeTestParameters<T> {
       tile_cols_log2: u.int_in_range(0..=2)?,
       tile_rows_log2: u.int_in_range(0..=2)?,
       still_picture: bool::arbitrary(u)?,
+      lossless: bool::arbitrary(u)?,
       pixel: PhantomData,
     };
     if matches!(T::type_enum(), PixelType::U16) {
@@ -352,6 +381,9 @@ impl<T: Pixel> Arbitrary<'_> for DecodeTestParameters<T> {
     if p.still_picture {
       p.limit = 1
     }
+    if p.lossless {
+      p.bitrate = 0;
+    }
     Ok(p)
   }
 }
@@ -379,6 +411,7 @@ pub fn fuzz_encode_decode<T: Pixel>(p: DecodeTestParameters<T>) {
     p.tile_cols_log2,
     p.tile_rows_log2,
     p.still_picture,
+    p.lossless,
     None,
   );
 }
@@ -0,0 +1,370 @@
+// Copyright (c) 2024, The rav1e contributors. All rights reserved
+//
+// This source code is subject to the terms of the BSD 2 Clause License and
+// the Alliance for Open Media Patent License 1.0. If the BSD 2 Clause License
+// was not distributed with this source code in the LICENSE file, you can
+// obtain it at www.aomedia.org/license/software. If the Alliance for Open
+// Media Patent License 1.0 was not distributed with this source code in the
+// PATENTS file, you can obtain it at www.aomedia.org/license/patent.
+
+//! Global motion estimation: fitting a frame-wide motion model to each
+//! reference from the motion vectors found by the lookahead.
+
+use crate::api::InterConfig;
+use crate::context::MI_SIZE;
+use crate::encoder::{FrameInvariants, FrameState};
+use crate::me::FrameMEStats;
+use crate::partition::*;
+use crate::util::Pixel;
+use crate::warp::*;
+
+/// Size in MI units of the blocks whose motion vectors are sampled.
+const SAMPLE_SIZE_MI: usize = 4;
+const RANSAC_ITERATIONS: usize = 64;
+/// Maximum distance in pixels between a motion vector and the model for the
+/// vector to count as an inlier.
+const INLIER_THRESHOLD: f64 = 1.0;
+/// Minimum fraction of the samples a model must explain to be used.
+const MIN_INLIER_FRACTION: f64 = 0.4;
+/// Fraction of the inliers of the best model a simpler model must reach to be
+/// preferred over it.
+const SIMPLER_MODEL_RATIO: f64 = 0.95;
+
+/// Motion of the block centered at `(x, y)`, in pixels.
+#[derive(Clone, Copy, Debug)]
+struct Sample {
+  x: f64,
+  y: f64,
+  dx: f64,
+  dy: f64,
+}
+
+/// Displacement `(m[0] + m[2] * x + m[3] * y, m[1] + m[4] * x + m[5] * y)`
+/// of the pixel at `(x, y)`, in pixels.
+type Model = [f64; 6];
+
+impl Sample {
+  fn error(&self, m: &Model) -> f64 {
+    let ex = m[0] + m[2] * self.x + m[3] * self.y - self.dx;
+    let ey = m[1] + m[4] * self.x + m[5] * self.y - self.dy;
+    ex.hypot(ey)
+  }
+
+  /// Rows of the least squares system of `mode` contributed by the sample.
+  fn equations(&self, mode: GlobalMVMode) -> [([f64; 6], f64); 2] {
+    let (x, y) = (self.x, self.y);
+    match mode {
+      GlobalMVMode::TRANSLATION => [
+        ([1., 0., 0., 0., 0., 0.], self.dx),
+        ([0., 1., 0., 0., 0., 0.], self.dy),
+      ],
+      GlobalMVMode::ROTZOOM => {
+        [([x, y, 1., 0., 0., 0.], self.dx), ([y, -x, 0., 1., 0., 0.], self.dy)]
+      }
+      _ => {
+        [([x, y, 1., 0., 0., 0.], self.dx), ([0., 0., 0., x, y, 1.], self.dy)]
+      }
+    }
+  }
+}
+
+const fn num_unknowns(mode: GlobalMVMode) -> usize {
+  match mode {
+    GlobalMVMode::IDENTITY => 0,
+    GlobalMVMode::TRANSLATION => 2,
+    GlobalMVMode::ROTZOOM => 4,
+    GlobalMVMode::AFFINE => 6,
+  }
+}
+
+/// Least squares fit of a `mode` model to `samples`.
+fn fit<'a>(
+  mode: GlobalMVMode, samples: impl Iterator<Item = &'a Sample>,
+) -> Option<Model> {
+  let n = num_unknowns(mode);
+  let mut a = [[0f64; 7]; 6];
+  for s in samples {
+    for (row, target) in s.equations(mode) {
+      for i in 0..n {
+        for j in 0..n {
+          a[i][j] += row[i] * row[j];
+        }
+        a[i][6] += row[i] * target;
+      }
+    }
+  }
+
+  // Gaussian elimination with partial pivoting
+  for col in 0..n {
+    let pivot = (col..n)
+      .max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))
+      .unwrap();
+    if a[pivot][col].abs() < 1e-9 {
+      return None;
+    }
+    a.swap(col, pivot);
+    for row in 0..n {
+      if row != col {
+        let f = a[row][col] / a[col][col];
+        for k in col..7 {
+          a[row][k] -= f * a[col][k];
+        }
+      }
+    }
+  }
+  let mut v = [0f64; 6];
+  for i in 0..n {
+    v[i] = a[i][6] / a[i][i];
+  }
+
+  Some(match mode {
+    GlobalMVMode::TRANSLATION => [v[0], v[1], 0., 0., 0., 0.],
+    GlobalMVMode::ROTZOOM => [v[2], v[3], v[0], v[1], -v[1], v[0]],
+    _ => [v[2], v[5], v[0], v[1], v[3], v[4]],
+  })
+}
+
+/// Fits a `mode` model to the samples with RANSAC, returning the model and
+/// its number of inliers.
+fn ransac(mode: GlobalMVMode, samples: &[Sample]) -> Option<(Model, usize)> {
+  let min_samples = num_unknowns(mode) / 2;
+  if samples.len() < min_samples * 2 {
+    return None;
+  }
+  let inliers = |m: &Model| {
+    samples.iter().filter(|s| s.error(m) <= INLIER_THRESHOLD).count()
+  };
+
+  // xorshift, seeded deterministically so that encodes are reproducible
+  let mut state = 0x2545_f491_u32;
+  let mut next = |n: usize| {
+    state ^= state << 13;
+    state ^= state >> 17;
+    state ^= state << 5;
+    state as usize % n
+  };
+
+  let mut best: Option<(Model, usize)> = None;
+  for _ in 0..RANSAC_ITERATIONS {
+    // Repeated picks make the system singular and are skipped by `fit`
+    let mut picked = [0; 3];
+    for p in picked.iter_mut().take(min_samples) {
+      *p = next(samples.len());
+    }
+    let Some(model) =
+      fit(mode, picked[..min_samples].iter().map(|&i| &samples[i]))
+    else {
+      continue;
+    };
+    let count = inliers(&model);
+    if best.is_none_or(|(_, c)| count > c) {
+      best = Some((model, count));
+    }
+  }
+
+  // Refine the best model on its inliers
+  let (mut model, mut count) = best?;
+  for _ in 0..2 {
+    let refined = fit(
+      mode,
+      samples.iter().filter(|s| s.error(&model) <= INLIER_THRESHOLD),
+    );
+    match refined {
+      Some(m) if inliers(&m) >= count => {
+        count = inliers(&m);
+        model = m;
+      }
+      _ => break,
+    }
+  }
+  Some((model, count))
+}
+
+/// Converts a model to codable global motion parameters, or `None` if they
+/// cannot be used for warping.
+fn quantize(
+  mode: GlobalMVMode, m: &Model, allow_high_precision_mv: bool,
+) -> Option<[i32; 6]> {
+  let quantize_param = |v: f64, abs_bits: u32, prec_bits: u32| {
+    let max = 1 << abs_bits;
+    ((v * (1 << prec_bits) as f64).round() as i32).clamp(-max, max)
+      << (WARPEDMODEL_PREC_BITS - prec_bits)
+  };
+
+  let mut params = WARP_IDENTITY;
+  if mode == GlobalMVMode::TRANSLATION {
+    let hp = allow_high_precision_mv as u32;
+    let abs_bits = GM_ABS_TRANS_ONLY_BITS + hp - 1;
+    let prec_bits = GM_TRANS_ONLY_PREC_BITS + hp - 1;
+    // The vertical translation comes first, see `FrameInvariants::global_mv`
+    params[0] = quantize_param(m[1], abs_bits, prec_bits);
+    params[1] = quantize_param(m[0], abs_bits, prec_bits);
+    return Some(params);
+  }
+
+  for idx in 2..6 {
+    params[idx] +=
+      quantize_param(m[idx], GM_ABS_ALPHA_BITS, GM_ALPHA_PREC_BITS);
+  }
+  if mode == GlobalMVMode::ROTZOOM {
+    params[4] = -params[3];
+    params[5] = params[2];
+  }
+  for idx in 0..2 {
+    params[idx] =
+      quantize_param(m[idx], GM_ABS_TRANS_BITS, GM_TRANS_PREC_BITS);
+  }
+  WarpedMotion::new(params).map(|_| params)
+}
+
+/// Collects the motion of each sampled block, with coordinates relative to
+/// `center` to keep the least squares systems well conditioned.
+fn collect_samples(stats: &FrameMEStats, center: (f64, f64)) -> Vec<Sample> {
+  let half = (SAMPLE_SIZE_MI * MI_SIZE) as f64 / 2. - 0.5;
+  (0..stats.rows / SAMPLE_SIZE_MI)
+    .flat_map(|by| (0..stats.cols / SAMPLE_SIZE_MI).map(move |bx| (bx, by)))
+    .map(|(bx, by)| {
+      let mv = stats[by * SAMPLE_SIZE_MI][bx * SAMPLE_SIZE_MI].mv;
+      Sample {
+        x: (bx * SAMPLE_SIZE_MI * MI_SIZE) as f64 + half - center.0,
+        y: (by * SAMPLE_SIZE_MI * MI_SIZE) as f64 + half - center.1,
+        dx: mv.col as f64 / 8.,
+        dy: mv.row as f64 / 8.,
+      }
+    })
+    .collect()
+}
+
+/// Estimates the global motion of a frame towards one reference, returning
+/// the model type and its parameters.
+fn estimate_ref(
+  stats: &FrameMEStats, center: (f64, f64), allow_high_precision_mv: bool,
+) -> (GlobalMVMode, [i32; 6]) {
+  let identity = (GlobalMVMode::IDENTITY, WARP_IDENTITY);
+  let samples = collect_samples(stats, center);
+  if samples.is_empty() {
+    return identity;
+  }
+
+  let identity_inliers =
+    samples.iter().filter(|s| s.error(&[0.; 6]) <= INLIER_THRESHOLD).count();
+
+  let fits: Vec<_> =
+    [GlobalMVMode::TRANSLATION, GlobalMVMode::ROTZOOM, GlobalMVMode::AFFINE]
+      .into_iter()
+      .filter_map(|mode| ransac(mode, &samples).map(|(m, c)| (mode, m, c)))
+      .collect();
+  let Some(best_inliers) = fits.iter().map(|&(_, _, c)| c).max() else {
+    return identity;
+  };
+
+  // Prefer the simplest model explaining nearly as much of the motion
+  let Some(&(mode, mut model, inliers)) = fits
+    .iter()
+    .find(|&&(_, _, c)| c as f64 >= best_inliers as f64 * SIMPLER_MODEL_RATIO)
+  else {
+    return identity;
+  };
+  if (inliers as f64) < samples.len() as f64 * MIN_INLIER_FRACTION
+    || identity_inliers as f64 >= inliers as f64 * SIMPLER_MODEL_RATIO
+  {
+    return identity;
+  }
+
+  // Move the origin back to the top-left corner of the frame
+  model[0] -= model[2] * center.0 + model[3] * center.1;
+  model[1] -= model[4] * center.0 + model[5] * center.1;
+
+  match quantize(mode, &model, allow_high_precision_mv) {
+    Some(params) if params != WARP_IDENTITY => (mode, params),
+    _ => identity,
+  }
+}
+
+/// Sets the global motion of each reference of `fi` from the lookahead motion
+/// vectors in `fs`.
+pub(crate) fn estimate_global_motion<T: Pixel>(
+  fi: &mut FrameInvariants<T>, fs: &FrameState<T>, inter_cfg: &InterConfig,
+) {
+  fi.globalmv_transformation_type =
+    [GlobalMVMode::IDENTITY; INTER_REFS_PER_FRAME];
+  fi.gm_params = [WARP_IDENTITY; INTER_REFS_PER_FRAME];
+  if !fi.frame_type.has_inter() || fi.force_integer_mv != 0 {
+    return;
+  }
+
+  let center = (fi.width as f64 / 2., fi.height as f64 / 2.);
+  let frame_me_stats = fs.frame_me_stats.read().expect("poisoned lock");
+  let mut estimated_slots = 0u32;
+  // The lookahead only searches the first reference of each slot
+  for &ref_frame in inter_cfg.allowed_ref_frames() {
+    let slot_flag = 1 << fi.ref_frames[ref_frame.to_index()];
+    if estimated_slots & slot_flag != 0 {
+      continue;
+    }
+    estimated_slots |= slot_flag;
+
+    let (mode, params) = estimate_ref(
+      &frame_me_stats[ref_frame.to_index()],
+      center,
+      fi.allow_high_precision_mv,
+    );
+    fi.globalmv_transformation_type[ref_frame.to_index()] = mode;
+    fi.gm_params[ref_frame.to_index()] = params;
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn samples_from(model: &Model, outliers: usize) -> Vec<Sample> {
+    let mut samples = Vec::new();
+    for y in (-200..200).step_by(16) {
+      for x in (-300..300).step_by(16) {
+        let (x, y) = (x as f64, y as f64);
+        let (mut dx, dy) = (
+          model[0] + model[2] * x + model[3] * y,
+          model[1] + model[4] * x + model[5] * y,
+        );
+        if samples.len() % 10 < outliers {
+          dx += 20.;
+        }
+        samples.push(Sample { x, y, dx, dy });
+      }
+    }
+    samples
+  }
+
+  #[test]
+  fn ransac_rejects_outliers() {
+    let model = [3.5, -1.25, 0.01, 0.02, -0.02, 0.01];
+    let samples = samples_from(&model, 3);
+    let (fitted, inliers) = ransac(GlobalMVMode::ROTZOOM, &samples).unwrap();
+    assert!(inliers >= samples.len() * 7 / 10);
+    for (a, b) in fitted.iter().zip(model.iter()) {
+      assert!((a - b).abs() < 1e-6);
+    }
+  }
+
+  #[test]
+  fn translation_is_stored_vertical_first() {
+    let params =
+      quantize(GlobalMVMode::TRANSLATION, &[2., -1., 0., 0., 0., 0.], false)
+        .unwrap();
+    assert_eq!(params[0], -1 << WARPEDMODEL_PREC_BITS);
+    assert_eq!(params[1], 2 << WARPEDMODEL_PREC_BITS);
+  }
+
+  #[test]
+  fn quantized_zoom_is_codable() {
+    let model = [4., -2., 0.02, 0., 0., 0.02];
+    let params = quantize(GlobalMVMode::ROTZOOM, &model, false).unwrap();
+    for idx in 2..6 {
+      let prec_diff = WARPEDMODEL_PREC_BITS - GM_ALPHA_PREC_BITS;
+      assert_eq!(params[idx] & ((1 << prec_diff) - 1), 0);
+    }
+    assert_eq!(params[2], params[5]);
+    assert_eq!(params[3], -params[4]);
+  }
+}
@@ -12,9 +12,11 @@ use crate::context::*;
 use crate::ec::*;
 use crate::lrf::*;
 use crate::partition::*;
+use crate::superres::{SUPERRES_DENOM_BITS, SUPERRES_DENOM_MIN};
 use crate::tiling::MAX_TILE_WIDTH;
 use crate::util::Fixed;
 use crate::util::Pixel;
+use crate::warp::*;
 
 use crate::DeblockState;
 use crate::FrameInvariants;
@@ -65,6 +67,13 @@ pub enum ObuType {
   OBU_PADDING = 15,
 }
 
+/// Layer ids carried by the optional OBU extension header.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ObuExtension {
+  pub temporal_id: u8,
+  pub spatial_id: u8,
+}
+
 #[derive(Clone, Copy)]
 #[allow(non_camel_case_types)]
 #[allow(unused)]
@@ -76,17 +85,6 @@ pub enum ObuMetaType {
   OBU_META_TIMECODE = 5,
 }
 
-impl ObuMetaType {
-  const fn size(self) -> u64 {
-    use self::ObuMetaType::*;
-    match self {
-      OBU_META_HDR_CLL => 4,
-      OBU_META_HDR_MDCV => 24,
-      _ => 0,
-    }
-  }
-}
-
 pub trait ULEB128Writer {
   fn write_uleb128(&mut self, payload: u64) -> io::Result<()>;
 }
@@ -140,7 +138,8 @@ impl<W: io::Write> LEWriter for BitWriter<W, BigEndian> {
 pub trait UncompressedHeader {
   // Start of OBU Headers
   fn write_obu_header(
-    &mut self, obu_type: ObuType, obu_extension: u32,
+    &mut self, obu_type: ObuType, obu_extension: Option<ObuExtension>,
+    has_size_field: bool,
   ) -> io::Result<()>;
   fn write_sequence_metadata_obu(
     &mut self, obu_meta_type: ObuMetaType, seq: &Sequence,
@@ -165,6 +164,9 @@ pub trait UncompressedHeader {
   fn write_frame_size<T: Pixel>(
     &mut self, fi: &FrameInvariants<T>,
   ) -> io::Result<()>;
+  fn write_superres_params<T: Pixel>(
+    &mut self, fi: &FrameInvariants<T>,
+  ) -> io::Result<()>;
   fn write_render_size<T: Pixel>(
     &mut self, fi: &FrameInvariants<T>,
   ) -> io::Result<()>;
@@ -193,17 +195,19 @@ impl<W: io::Write> UncompressedHeader for BitWriter<W, BigEndian> {
   // Start of OBU Headers
   // Write OBU Header syntax
   fn write_obu_header(
-    &mut self, obu_type: ObuType, obu_extension: u32,
+    &mut self, obu_type: ObuType, obu_extension: Option<ObuExtension>,
+    has_size_field: bool,
   ) -> io::Result<()> {
     self.write_bit(false)?; // forbidden bit.
     self.write(4, obu_type as u32)?;
-    self.write_bit(obu_extension != 0)?;
-    self.write_bit(true)?; // obu_has_payload_length_field
+    self.write_bit(obu_extension.is_some())?;
+    self.write_bit(has_size_field)?; // obu_has_payload_length_field
     self.write_bit(false)?; // reserved
 
-    if obu_extension != 0 {
-      unimplemented!();
-      //self.write(8, obu_extension & 0xFF)?; size += 8;
+    if let Some(ext) = obu_extension {
+      self.write(3, ext.temporal_id)?;
+      self.write(2, ext.spatial_id)?;
+      self.write(3, 0)?; // extension_header_reserved_3bits
     }
 
     Ok(())
@@ -212,15 +216,6 @@ impl<W: io::Write> UncompressedHeader for BitWriter<W, BigEndian> {
   fn write_sequence_metadata_obu(
     &mut self, obu_meta_type: ObuMetaType, seq: &Sequence,
   ) -> io::Result<()> {
-    // header
-    self.write_obu_header(ObuType::OBU_METADATA, 0)?;
-
-    // uleb128() - length
-    // we use a constant value to avoid computing the OBU size every time
-    // since it is fixed (depending on the metadata)
-    // +2 is for the metadata_type field and the trailing bits byte
-    self.write_uleb128(obu_meta_type.size() + 2)?;
-
     // uleb128() - metadata_type (1 byte)
     self.write_uleb128(obu_meta_type as u64)?;
 
@@ -254,13 +249,6 @@ impl<W: io::Write> UncompressedHeader for BitWriter<W, BigEndian> {
   }
 
   fn write_t35_metadata_obu(&mut self, t35: &T35) -> io::Result<()> {
-    self.write_obu_header(ObuType::OBU_METADATA, 0)?;
-
-    // metadata type + country code + optional extension + trailing bits
-    self.write_uleb128(
-      t35.data.len() as u64 + if t35.country_code == 0xFF { 4 } else { 3 },
-    )?;
-
     self.write_uleb128(ObuMetaType::OBU_META_ITUT_T35 as u64)?;
 
     self.write(8, t35.country_code)?;
@@ -304,15 +292,35 @@ impl<W: io::Write> UncompressedHeader for BitWriter<W, BigEndian> {
 
         self.write_bit(true)?; // equal picture interval
         self.write_bit(true)?; // zero interval
-        self.write_bit(false)?; // decoder model info present flag
+        self.write_bit(fi.sequence.decoder_model_info_present_flag)?;
+        if fi.sequence.decoder_model_info_present_flag {
+          self.write(5, 31u32)?; // buffer_delay_length_minus_1
+          self.write(32, fi.sequence.time_base.num)?; // num_units_in_decoding_tick
+          self.write(5, 31u32)?; // buffer_removal_time_length_minus_1
+          self.write(5, 31u32)?; // frame_presentation_time_length_minus_1
+        }
+      } else {
+        assert!(!fi.sequence.decoder_model_info_present_flag);
       }
 
       self.write_bit(false)?; // initial display delay present flag
-      self.write(5, 0)?; // one operating point
-      self.write(12, 0)?; // idc
-      self.write(5, fi.sequence.level_idx[0])?; // level
-      if fi.sequence.level_idx[0] > 7 {
-        self.write(1, 0)?; // tier
+      self.write(5, fi.sequence.operating_points_cnt_minus_1 as u32)?;
+      for i in 0..=fi.sequence.operating_points_cnt_minus_1 {
+        self.write(12, fi.sequence.operating_point_idc[i])?; // idc
+        self.write(5, fi.sequence.level_idx[i])?; // level
+        if fi.sequence.level_idx[i] > 7 {
+          self.write(1, fi.sequence.tier[i] as u32)?; // tier
+        }
+        if fi.sequence.decoder_model_info_present_flag {
+          // The buffer model only covers the full stream, which is what the
+          //  first operating point decodes.
+          self.write_bit(i == 0)?; // decoder_model_present_for_this_op
+          if i == 0 {
+            self.write(32, fi.sequence.decoder_buffer_delay)?;
+            self.write(32, fi.sequence.encoder_buffer_delay)?;
+            self.write_bit(false)?; // low_delay_mode_flag
+          }
+        }
       }
     }
 
@@ -531,10 +539,13 @@ impl<W: io::Write> UncompressedHeader for BitWriter<W, BigEndian> {
       }
     }
 
-    assert!(
-      fi.force_integer_mv
-        == u32::from(fi.frame_type == FrameType::KEY || fi.intra_only)
-    );
+    // Inter frames may only restrict motion vectors to whole pixels when
+    // the screen content tools are enabled.
+    if fi.frame_type == FrameType::KEY || fi.intra_only {
+      assert!(fi.force_integer_mv == 1);
+    } else {
+      assert!(fi.force_integer_mv == 0 || fi.allow_screen_content_tools > 0);
+    }
 
     if fi.sequence.frame_id_numbers_present_flag {
       unimplemented!();
@@ -561,7 +572,11 @@ impl<W: io::Write> UncompressedHeader for BitWriter<W, BigEndian> {
     }
 
     if fi.sequence.decoder_model_info_present_flag {
-      unimplemented!();
+      self.write_bit(true)?; // buffer_removal_time_present_flag
+
+      // Only the first operating point has a decoder model, and it decodes
+      // every layer.
+      self.write(32, fi.buffer_removal_time)?; // buffer_removal_time
     }
 
     if fi.frame_type == FrameType::KEY {
@@ -604,8 +619,7 @@ impl<W: io::Write> UncompressedHeader for BitWriter<W, BigEndian> {
     if fi.intra_only {
       self.write_frame_size(fi)?;
       self.write_render_size(fi)?;
-      if fi.allow_screen_content_tools != 0 {
-        // TODO: && UpscaledWidth == FrameWidth.
+      if fi.allow_screen_content_tools != 0 && !fi.use_superres() {
         self.write_bit(fi.allow_intrabc)?;
       }
     }
@@ -735,7 +749,6 @@ impl<W: io::Write> UncompressedHeader for BitWriter<W, BigEndian> {
     }
 
     // quantization
-    assert!(fi.base_q_idx > 0);
     self.write(8, fi.base_q_idx)?; // base_q_idx
     self.write_delta_q(fi.dc_delta_q[0])?;
     if fi.sequence.chroma_sampling != ChromaSampling::Cs400 {
@@ -756,7 +769,9 @@ impl<W: io::Write> UncompressedHeader for BitWriter<W, BigEndian> {
     self.write_segment_data(fi, &fs.segmentation)?;
 
     // delta_q
-    self.write_bit(false)?; // delta_q_present_flag: no delta q
+    if fi.base_q_idx > 0 {
+      self.write_bit(false)?; // delta_q_present_flag: no delta q
+    }
 
     // delta_lf_params in the spec
     self.write_deblock_filter_a(fi, &fs.deblock)?;
@@ -772,7 +787,9 @@ impl<W: io::Write> UncompressedHeader for BitWriter<W, BigEndian> {
     // loop restoration
     self.write_frame_lrf(fi, &fs.restoration)?;
 
-    self.write_bit(fi.tx_mode_select)?; // tx mode
+    if !fi.coded_lossless() {
+      self.write_bit(fi.tx_mode_select)?; // tx mode
+    }
 
     let mut reference_select = false;
     if !fi.intra_only {
@@ -796,6 +813,7 @@ impl<W: io::Write> UncompressedHeader for BitWriter<W, BigEndian> {
 
     // global motion
     if !fi.intra_only {
+      let prev_gm_params = fi.prev_gm_params();
       for i in 0..7 {
         let mode = fi.globalmv_transformation_type[i];
         self.write_bit(mode != GlobalMVMode::IDENTITY)?;
@@ -805,33 +823,39 @@ impl<W: io::Write> UncompressedHeader for BitWriter<W, BigEndian> {
             self.write_bit(mode == GlobalMVMode::TRANSLATION)?;
           }
         }
-        match mode {
-          GlobalMVMode::IDENTITY => { /* Nothing to do */ }
-          GlobalMVMode::TRANSLATION => {
-            let mv_x = 0;
-            let mv_x_ref = 0;
-            let mv_y = 0;
-            let mv_y_ref = 0;
-            let bits = 12 - 6 + 3 - !fi.allow_high_precision_mv as u8;
-            let bits_diff = 12 - 3 + fi.allow_high_precision_mv as u8;
-            BCodeWriter::write_s_refsubexpfin(
-              self,
-              (1 << bits) + 1,
-              3,
-              mv_x_ref >> bits_diff,
-              mv_x >> bits_diff,
-            )?;
-            BCodeWriter::write_s_refsubexpfin(
-              self,
-              (1 << bits) + 1,
-              3,
-              mv_y_ref >> bits_diff,
-              mv_y >> bits_diff,
-            )?;
-          }
-          GlobalMVMode::ROTZOOM => unimplemented!(),
-          GlobalMVMode::AFFINE => unimplemented!(),
+        let params = &fi.gm_params[i];
+        let prev_params = &prev_gm_params[i];
+        let mut write_param = |idx: usize| -> io::Result<()> {
+          let (abs_bits, prec_bits) = if idx >= 2 {
+            (GM_ABS_ALPHA_BITS, GM_ALPHA_PREC_BITS)
+          } else if mode == GlobalMVMode::TRANSLATION {
+            let hp = fi.allow_high_precision_mv as u32;
+            (GM_ABS_TRANS_ONLY_BITS + hp - 1, GM_TRANS_ONLY_PREC_BITS + hp - 1)
+          } else {
+            (GM_ABS_TRANS_BITS, GM_TRANS_PREC_BITS)
+          };
+          let prec_diff = WARPEDMODEL_PREC_BITS - prec_bits;
+          let sub = if idx % 3 == 2 { 1 << prec_bits } else { 0 };
+          BCodeWriter::write_s_refsubexpfin(
+            self,
+            (1 << abs_bits) + 1,
+            3,
+            ((prev_params[idx] >> prec_diff) - sub) as i16,
+            ((params[idx] >> prec_diff) - sub) as i16,
+          )
         };
+        if mode >= GlobalMVMode::ROTZOOM {
+          write_param(2)?;
+          write_param(3)?;
+          if mode == GlobalMVMode::AFFINE {
+            write_param(4)?;
+            write_param(5)?;
+          }
+        }
+        if mode >= GlobalMVMode::TRANSLATION {
+          write_param(0)?;
+          write_param(1)?;
+        }
       }
     }
 
@@ -944,7 +968,7 @@ impl<W: io::Write> UncompressedHeader for BitWriter<W, BigEndian> {
   ) -> io::Result<()> {
     // width_bits and height_bits will have to be moved to the sequence header OBU
     // when we add support for it.
-    let width = fi.width - 1;
+    let width = fi.upscaled_width - 1;
     let height = fi.height - 1;
     let width_bits = log_in_base_2(width as u32) as u32 + 1;
     let height_bits = log_in_base_2(height as u32) as u32 + 1;
@@ -963,7 +987,7 @@ impl<W: io::Write> UncompressedHeader for BitWriter<W, BigEndian> {
     // width_bits and height_bits will have to be moved to the sequence header OBU
     // when we add support for it.
     if fi.frame_size_override_flag {
-      let width = fi.width - 1;
+      let width = fi.upscaled_width - 1;
       let height = fi.height - 1;
       let width_bits = log_in_base_2(width as u32) as u32 + 1;
       let height_bits = log_in_base_2(height as u32) as u32 + 1;
@@ -972,8 +996,18 @@ impl<W: io::Write> UncompressedHeader for BitWriter<W, BigEndian> {
       self.write(width_bits, width as u16)?;
       self.write(height_bits, height as u16)?;
     }
+    self.write_superres_params(fi)
+  }
+
+  fn write_superres_params<T: Pixel>(
+    &mut self, fi: &FrameInvariants<T>,
+  ) -> io::Result<()> {
     if fi.sequence.enable_superres {
-      unimplemented!();
+      self.write_bit(fi.use_superres())?;
+      if fi.use_superres() {
+        let coded_denom = fi.superres_denom - SUPERRES_DENOM_MIN;
+        self.write(SUPERRES_DENOM_BITS, coded_denom as u8)?;
+      }
     }
     Ok(())
   }
@@ -995,7 +1029,7 @@ impl<W: io::Write> UncompressedHeader for BitWriter<W, BigEndian> {
     let mut found_ref = false;
     for i in 0..INTER_REFS_PER_FRAME {
       if let Some(ref rec) = fi.rec_buffer.frames[fi.ref_frames[i] as usize] {
-        if rec.width == fi.width as u32
+        if rec.width == fi.upscaled_width as u32
           && rec.height == fi.height as u32
           && rec.render_width == fi.render_width
           && rec.render_height == fi.render_height
@@ -1013,8 +1047,8 @@ impl<W: io::Write> UncompressedHeader for BitWriter<W, BigEndian> {
     if !found_ref {
       self.write_frame_size(fi)?;
       self.write_render_size(fi)?;
-    } else if fi.sequence.enable_superres {
-      unimplemented!();
+    } else {
+      self.write_superres_params(fi)?;
     }
     Ok(())
   }
@@ -1037,6 +1071,11 @@ impl<W: io::Write> UncompressedHeader for BitWriter<W, BigEndian> {
   fn write_deblock_filter_b<T: Pixel>(
     &mut self, fi: &FrameInvariants<T>, deblock: &DeblockState,
   ) -> io::Result<()> {
+    if fi.allow_intrabc || fi.coded_lossless() {
+      // The loop filter is implicitly disabled.
+      assert!(deblock.levels == [0; 4]);
+      return Ok(());
+    }
     let planes = if fi.sequence.chroma_sampling == ChromaSampling::Cs400 {
       1
     } else {
@@ -1095,7 +1134,7 @@ impl<W: io::Write> UncompressedHeader for BitWriter<W, BigEndian> {
   fn write_frame_cdef<T: Pixel>(
     &mut self, fi: &FrameInvariants<T>,
   ) -> io::Result<()> {
-    if fi.sequence.enable_cdef && !fi.allow_intrabc {
+    if fi.sequence.enable_cdef && !fi.allow_intrabc && !fi.coded_lossless() {
       assert!(fi.cdef_damping >= 3);
       assert!(fi.cdef_damping <= 6);
       self.write(2, fi.cdef_damping - 3)?;
@@ -1116,8 +1155,10 @@ impl<W: io::Write> UncompressedHeader for BitWriter<W, BigEndian> {
   fn write_frame_lrf<T: Pixel>(
     &mut self, fi: &FrameInvariants<T>, rs: &RestorationState,
   ) -> io::Result<()> {
-    if fi.sequence.enable_restoration && !fi.allow_intrabc {
-      // && !self.lossless
+    if fi.sequence.enable_restoration
+      && !fi.allow_intrabc
+      && !fi.coded_lossless()
+    {
       let planes = if fi.sequence.chroma_sampling == ChromaSampling::Cs400 {
         1
       } else {
@@ -0,0 +1,341 @@
+// Copyright (c) 2024, The rav1e contributors. All rights reserved
+//
+// This source code is subject to the terms of the BSD 2 Clause License and
+// the Alliance for Open Media Patent License 1.0. If the BSD 2 Clause License
+// was not distributed with this source code in the LICENSE file, you can
+// obtain it at www.aomedia.org/license/software. If the Alliance for Open
+// Media Patent License 1.0 was not distributed with this source code in the
+// PATENTS file, you can obtain it at www.aomedia.org/license/patent.
+
+//! Intra block copy: predicting blocks of an intra frame from the already
+//! reconstructed area of the same tile, which pays off on screen content
+//! with repeated glyphs and patterns.
+
+use crate::context::*;
+use crate::dist::get_sad;
+use crate::encoder::FrameInvariants;
+use crate::frame::PlaneConfig;
+use crate::mc::MotionVector;
+use crate::partition::BlockSize;
+use crate::tiling::*;
+use crate::util::*;
+
+use std::collections::HashMap;
+
+/// Horizontal distance, in pixels, the source of a block copy must keep
+/// from the current superblock so hardware decoders can pipeline the loop.
+pub const INTRABC_DELAY_PIXELS: usize = 256;
+const INTRABC_DELAY_SB64: isize = (INTRABC_DELAY_PIXELS / 64) as isize;
+
+/// Size of the luma blocks the hash table is built from.
+const HASH_BLOCK_SIZE: usize = 8;
+/// Maximum number of hash matches examined per block.
+const MAX_HASH_CANDIDATES: usize = 64;
+
+/// Positions of the 8x8 luma blocks of a tile, keyed by their hash.
+///
+/// Blocks made of constant rows or constant columns are left out: they are
+/// cheap to code with the directional predictors and would otherwise flood
+/// single buckets.
+#[derive(Debug, Default)]
+pub struct IntraBcHashTable {
+  map: HashMap<u32, Vec<(u16, u16)>>,
+}
+
+impl IntraBcHashTable {
+  /// Hashes every block of `plane` whose top-left corner is a multiple of
+  /// `(step_x, step_y)`, so that the matches stay aligned to the chroma grid.
+  pub fn new<T: Pixel>(
+    plane: &PlaneRegion<'_, T>, width: usize, height: usize, step_x: usize,
+    step_y: usize,
+  ) -> Self {
+    let mut map: HashMap<u32, Vec<(u16, u16)>> = HashMap::new();
+    if width < HASH_BLOCK_SIZE || height < HASH_BLOCK_SIZE {
+      return Self { map };
+    }
+    for y in (0..=height - HASH_BLOCK_SIZE).step_by(step_y) {
+      for x in (0..=width - HASH_BLOCK_SIZE).step_by(step_x) {
+        if let Some(hash) = block_hash(plane, x, y) {
+          map.entry(hash).or_default().push((x as u16, y as u16));
+        }
+      }
+    }
+    Self { map }
+  }
+
+  /// Positions of the blocks hashing to `hash`, in raster order.
+  pub fn candidates(&self, hash: u32) -> &[(u16, u16)] {
+    self.map.get(&hash).map_or(&[], Vec::as_slice)
+  }
+}
+
+/// FNV-1a hash of the 8x8 block at `(x, y)`, or `None` if the block is flat
+/// along its rows or along its columns.
+fn block_hash<T: Pixel>(
+  plane: &PlaneRegion<'_, T>, x: usize, y: usize,
+) -> Option<u32> {
+  let mut hash: u32 = 0x811c_9dc5;
+  let mut rows_flat = true;
+  let mut cols_flat = true;
+  let first_row = &plane[y][x..x + HASH_BLOCK_SIZE];
+  for row in 0..HASH_BLOCK_SIZE {
+    let pixels = &plane[y + row][x..x + HASH_BLOCK_SIZE];
+    rows_flat &= pixels.iter().all(|&p| p == pixels[0]);
+    cols_flat &= pixels == first_row;
+    for &p in pixels {
+      hash = (hash ^ u32::cast_from(p)).wrapping_mul(0x0100_0193);
+    }
+  }
+  (!rows_flat && !cols_flat).then_some(hash)
+}
+
+/// Whether `dv` may be used by a block of size `bsize` at `tile_bo`, see
+/// `is_mv_valid` in the spec.
+///
+/// The source must lie in the current tile, in a superblock that is already
+/// coded and that trails the current one by `INTRABC_DELAY_PIXELS`.
+pub fn is_dv_valid(
+  dv: MotionVector, tile_bo: TileBlockOffset, bsize: BlockSize,
+  tile_mi_width: usize, tile_mi_height: usize, sb_size_log2: usize,
+  xdec: usize, ydec: usize, has_chroma: bool,
+) -> bool {
+  if dv.row & 7 != 0 || dv.col & 7 != 0 {
+    return false;
+  }
+  let (dv_row, dv_col) = (dv.row as isize >> 3, dv.col as isize >> 3);
+  let (bw, bh) = (bsize.width() as isize, bsize.height() as isize);
+  let src_top = (tile_bo.0.y * MI_SIZE) as isize + dv_row;
+  let src_left = (tile_bo.0.x * MI_SIZE) as isize + dv_col;
+  let src_bottom = src_top + bh;
+  let src_right = src_left + bw;
+  if src_top < 0
+    || src_left < 0
+    || src_bottom > (tile_mi_height * MI_SIZE) as isize
+    || src_right > (tile_mi_width * MI_SIZE) as isize
+  {
+    return false;
+  }
+
+  // Chroma of sub-8x8 blocks is predicted from the aligned 8x8 area.
+  if has_chroma {
+    if bw < 8 && xdec != 0 && src_left < 4 {
+      return false;
+    }
+    if bh < 8 && ydec != 0 && src_top < 4 {
+      return false;
+    }
+  }
+
+  let sb_size = 1 << sb_size_log2;
+  let active_sb_row = ((tile_bo.0.y * MI_SIZE) >> sb_size_log2) as isize;
+  let active_sb64_col = ((tile_bo.0.x * MI_SIZE) >> 6) as isize;
+  let src_sb_row = (src_bottom - 1) / sb_size;
+  let src_sb64_col = (src_right - 1) >> 6;
+  let total_sb64_per_row = (((tile_mi_width - 1) >> 4) + 1) as isize;
+  let active_sb64 = active_sb_row * total_sb64_per_row + active_sb64_col;
+  let src_sb64 = src_sb_row * total_sb64_per_row + src_sb64_col;
+  if src_sb64 >= active_sb64 - INTRABC_DELAY_SB64 {
+    return false;
+  }
+
+  // Wavefront constraint: only the top-left area of the tile is usable.
+  let gradient = 1 + INTRABC_DELAY_SB64 + isize::from(sb_size > 64);
+  let wf_offset = gradient * (active_sb_row - src_sb_row);
+  src_sb_row <= active_sb_row
+    && src_sb64_col < active_sb64_col - INTRABC_DELAY_SB64 + wf_offset
+}
+
+/// The vector the displacement of an `IntraBC` block is coded against, see
+/// `assign_mv` in the spec.
+pub fn intrabc_ref_dv(
+  mv_stack: &[CandidateMV], tile_bo: TileBlockOffset, sb_size: BlockSize,
+) -> MotionVector {
+  let stack_mv = |i: usize| mv_stack.get(i).map(|c| c.this_mv);
+  let ref_dv = [stack_mv(0), stack_mv(1)]
+    .into_iter()
+    .flatten()
+    .find(|&mv| mv != MotionVector::default());
+  ref_dv.unwrap_or_else(|| {
+    let sb_h4 = sb_size.height_mi();
+    if tile_bo.0.y < sb_h4 {
+      MotionVector {
+        row: 0,
+        col: -(((sb_h4 * MI_SIZE + INTRABC_DELAY_PIXELS) * 8) as i16),
+      }
+    } else {
+      MotionVector { row: -((sb_h4 * MI_SIZE * 8) as i16), col: 0 }
+    }
+  })
+}
+
+/// Finds the displacement whose source best matches the block at `tile_bo`,
+/// trying the reference vector, the neighbouring displacements and the hash
+/// matches of the block's top-left 8x8 corner.
+///
+/// Sources are kept inside the visible frame and on even positions along
+/// subsampled directions, so that chroma is copied at integer positions too.
+pub fn intrabc_search<T: Pixel>(
+  fi: &FrameInvariants<T>, ts: &TileStateMut<'_, T>, tile_bo: TileBlockOffset,
+  bsize: BlockSize, ref_dv: MotionVector, mv_stack: &[CandidateMV],
+) -> Option<MotionVector> {
+  let hash_table = ts.intrabc_hash.as_ref()?;
+  if bsize.width() < HASH_BLOCK_SIZE || bsize.height() < HASH_BLOCK_SIZE {
+    return None;
+  }
+
+  let PlaneConfig { xdec, ydec, .. } = ts.input.planes[1].cfg;
+  let (bw, bh) = (bsize.width(), bsize.height());
+  let x0 = tile_bo.0.x * MI_SIZE;
+  let y0 = tile_bo.0.y * MI_SIZE;
+  let tile_rect = ts.tile_rect();
+  let visible_w = fi.width.saturating_sub(tile_rect.x);
+  let visible_h = fi.height.saturating_sub(tile_rect.y);
+  let sb_bottom = ((y0 >> ts.sb_size_log2) + 1) << ts.sb_size_log2;
+
+  let usable = |dv: MotionVector| {
+    let (dv_row, dv_col) = (dv.row as isize >> 3, dv.col as isize >> 3);
+    dv != MotionVector::default()
+      && dv.row & ((8 << ydec) - 1) == 0
+      && dv.col & ((8 << xdec) - 1) == 0
+      && (MV_LOW + 1..MV_UPP).contains(&(dv.row as i32 - ref_dv.row as i32))
+      && (MV_LOW + 1..MV_UPP).contains(&(dv.col as i32 - ref_dv.col as i32))
+      && x0 as isize + dv_col + bw as isize <= visible_w as isize
+      && y0 as isize + dv_row + bh as isize <= visible_h as isize
+      && is_dv_valid(
+        dv,
+        tile_bo,
+        bsize,
+        ts.mi_width,
+        ts.mi_height,
+        ts.sb_size_log2,
+        xdec,
+        ydec,
+        true,
+      )
+  };
+
+  let mut candidates: Vec<MotionVector> = Vec::new();
+  candidates.push(ref_dv);
+  candidates.extend(mv_stack.iter().map(|c| c.this_mv));
+  // Match on the first 8x8 corner that is not flat, as glyphs tend to leave
+  // the top-left corner of a block empty.
+  let anchor = (0..bh).step_by(HASH_BLOCK_SIZE).find_map(|sy| {
+    (0..bw).step_by(HASH_BLOCK_SIZE).find_map(|sx| {
+      block_hash(&ts.input_tile.planes[0], x0 + sx, y0 + sy)
+        .map(|hash| (hash, x0 + sx, y0 + sy))
+    })
+  });
+  if let Some((hash, ax, ay)) = anchor {
+    candidates.extend(
+      hash_table
+        .candidates(hash)
+        .iter()
+        .take_while(|&&(_, y)| (y as usize) < sb_bottom)
+        .map(|&(x, y)| MotionVector {
+          row: ((y as isize - ay as isize) * 8) as i16,
+          col: ((x as isize - ax as isize) * 8) as i16,
+        })
+        .filter(|&dv| usable(dv))
+        .take(MAX_HASH_CANDIDATES),
+    );
+  }
+
+  let org =
+    ts.input_tile.planes[0].subregion(Area::BlockStartingAt { bo: tile_bo.0 });
+  let mut best: Option<(u32, MotionVector)> = None;
+  for dv in candidates {
+    if !usable(dv) || best.is_some_and(|(_, best_dv)| best_dv == dv) {
+      continue;
+    }
+    let src = ts.rec.planes[0].subregion(Area::Rect {
+      x: x0 as isize + (dv.col as isize >> 3),
+      y: y0 as isize + (dv.row as isize >> 3),
+      width: bw,
+      height: bh,
+    });
+    let sad =
+      get_sad(&org, &src, bw, bh, fi.sequence.bit_depth, fi.cpu_feature_level);
+    if best.is_none_or(|(best_sad, _)| sad < best_sad) {
+      best = Some((sad, dv));
+      if sad == 0 {
+        break;
+      }
+    }
+  }
+  best.map(|(_, dv)| dv)
+}
+
+/// Predicts the block at `tile_bo` by copying the reconstructed pixels `dv`
+/// away in the current tile.
+pub fn predict_intrabc<T: Pixel>(
+  ts: &mut TileStateMut<'_, T>, tile_bo: TileBlockOffset, bsize: BlockSize,
+  dv: MotionVector, num_planes: usize,
+) {
+  for p in 0..num_planes {
+    let rec = &mut ts.rec.planes[p];
+    let &PlaneConfig { xdec, ydec, .. } = rec.plane_cfg;
+    let plane_bsize = bsize.subsampled_size(xdec, ydec).unwrap();
+    let (w, h) = (plane_bsize.width(), plane_bsize.height());
+    let po = tile_bo.plane_offset(rec.plane_cfg);
+    debug_assert!(dv.row & ((8 << ydec) - 1) == 0);
+    debug_assert!(dv.col & ((8 << xdec) - 1) == 0);
+    let src_x = (po.x + (dv.col as isize >> (3 + xdec))) as usize;
+    let src_y = (po.y + (dv.row as isize >> (3 + ydec))) as usize;
+    let (dst_x, dst_y) = (po.x as usize, po.y as usize);
+    let mut row = [T::cast_from(0); 128];
+    for y in 0..h {
+      row[..w].copy_from_slice(&rec[src_y + y][src_x..src_x + w]);
+      rec[dst_y + y][dst_x..dst_x + w].copy_from_slice(&row[..w]);
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn dv_validity() {
+    let bsize = BlockSize::BLOCK_16X16;
+    let valid = |dv: MotionVector, bo: TileBlockOffset| {
+      is_dv_valid(dv, bo, bsize, 256, 64, 6, 1, 1, true)
+    };
+    // Second superblock row, first column: the row above is usable.
+    let bo = TileBlockOffset(BlockOffset { x: 0, y: 16 });
+    assert!(valid(MotionVector { row: -64 * 8, col: 0 }, bo));
+    // Overlapping the current superblock row is not.
+    assert!(!valid(MotionVector { row: -8 * 8, col: 0 }, bo));
+    // Sub-pixel displacements are not allowed.
+    assert!(!valid(MotionVector { row: -64 * 8 + 4, col: 0 }, bo));
+    // Neither are sources outside of the tile.
+    assert!(!valid(MotionVector { row: -72 * 8, col: 0 }, bo));
+
+    // First superblock row: the source has to trail by 256 pixels.
+    let bo = TileBlockOffset(BlockOffset { x: 80, y: 0 });
+    assert!(valid(MotionVector { row: 0, col: -320 * 8 }, bo));
+    assert!(!valid(MotionVector { row: 0, col: -256 * 8 }, bo));
+  }
+
+  #[test]
+  fn ref_dv_defaults() {
+    let bo = TileBlockOffset(BlockOffset { x: 8, y: 4 });
+    let dv = intrabc_ref_dv(&[], bo, BlockSize::BLOCK_64X64);
+    assert_eq!(dv, MotionVector { row: 0, col: -2560 });
+    let bo = TileBlockOffset(BlockOffset { x: 8, y: 20 });
+    let dv = intrabc_ref_dv(&[], bo, BlockSize::BLOCK_64X64);
+    assert_eq!(dv, MotionVector { row: -512, col: 0 });
+
+    let zero = CandidateMV {
+      this_mv: MotionVector::default(),
+      comp_mv: MotionVector::default(),
+      weight: 2,
+    };
+    let stacked = CandidateMV {
+      this_mv: MotionVector { row: -64, col: -128 },
+      comp_mv: MotionVector::default(),
+      weight: 2,
+    };
+    let dv = intrabc_ref_dv(&[zero, stacked], bo, BlockSize::BLOCK_64X64);
+    assert_eq!(dv, MotionVector { row: -64, col: -128 });
+  }
+}
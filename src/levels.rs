@@ -7,6 +7,9 @@
 // Media Patent License 1.0 was not distributed with this source code in the
 // PATENTS file, you can obtain it at www.aomedia.org/license/patent.
 
+use crate::api::EncoderConfig;
+use crate::tiling::{TilingInfo, MAX_TILE_AREA, MAX_TILE_WIDTH};
+
 pub static AV1_LEVEL_DEFINED: [bool; 32] = [
   true, // 2.0
   true, // 2.1
@@ -31,8 +34,8 @@ pub static AV1_LEVEL_MAX_PIC_SIZE: [usize; 32] = [
   278784, // 2.1
   0, 0, 665856,  // 3.0
   1065024, // 3.1
-  0, 0, 2359296,  // 4.0
-  23592960, // 4.1
+  0, 0, 2359296, // 4.0
+  2359296, // 4.1
   0, 0, 8912896,  // 5.0
   8912896,  // 5.1
   8912896,  // 5.2
@@ -114,3 +117,234 @@ pub static AV1_LEVEL_MAX_DISPLAY_RATE: [usize; 32] = [
   0,
   0,
 ];
+
+pub static AV1_LEVEL_MAX_DECODE_RATE: [u64; 32] = [
+  5_529_600,  // 2.0
+  10_454_400, // 2.1
+  0,
+  0,
+  24_969_600, // 3.0
+  39_938_400, // 3.1
+  0,
+  0,
+  77_856_768,  // 4.0
+  155_713_536, // 4.1
+  0,
+  0,
+  273_715_200,   // 5.0
+  547_430_400,   // 5.1
+  1_094_860_800, // 5.2
+  1_176_502_272, // 5.3
+  1_176_502_272, // 6.0
+  2_189_721_600, // 6.1
+  4_379_443_200, // 6.2
+  4_706_009_088, // 6.3
+  0,
+  0,
+  0,
+  0,
+  0,
+  0,
+  0,
+  0,
+  0,
+  0,
+  0,
+  0,
+];
+
+pub static AV1_LEVEL_MAX_HEADER_RATE: [usize; 32] = [
+  150, // 2.0
+  150, // 2.1
+  0, 0, 150, // 3.0
+  150, // 3.1
+  0, 0, 300, // 4.0
+  300, // 4.1
+  0, 0, 300, // 5.0
+  300, // 5.1
+  300, // 5.2
+  300, // 5.3
+  300, // 6.0
+  300, // 6.1
+  300, // 6.2
+  300, // 6.3
+  0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+];
+
+/// Maximum bitrate of the main tier in kbps, for profile 0.
+pub static AV1_LEVEL_MAX_BITRATE: [usize; 32] = [
+  1_500, // 2.0
+  3_000, // 2.1
+  0, 0, 6_000,  // 3.0
+  10_000, // 3.1
+  0, 0, 12_000, // 4.0
+  20_000, // 4.1
+  0, 0, 30_000,  // 5.0
+  40_000,  // 5.1
+  60_000,  // 5.2
+  60_000,  // 5.3
+  60_000,  // 6.0
+  100_000, // 6.1
+  160_000, // 6.2
+  160_000, // 6.3
+  0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+];
+
+/// Minimum compression ratio basis of the main tier.
+pub static AV1_LEVEL_MIN_COMP_BASIS: [usize; 32] = [
+  2, // 2.0
+  2, // 2.1
+  0, 0, 2, // 3.0
+  2, // 3.1
+  0, 0, 4, // 4.0
+  4, // 4.1
+  0, 0, 6, // 5.0
+  8, // 5.1
+  8, // 5.2
+  8, // 5.3
+  8, // 6.0
+  8, // 6.1
+  8, // 6.2
+  8, // 6.3
+  0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+];
+
+pub static AV1_LEVEL_MAX_TILES: [usize; 32] = [
+  8, // 2.0
+  8, // 2.1
+  0, 0, 16, // 3.0
+  16, // 3.1
+  0, 0, 32, // 4.0
+  32, // 4.1
+  0, 0, 64,  // 5.0
+  64,  // 5.1
+  64,  // 5.2
+  64,  // 5.3
+  128, // 6.0
+  128, // 6.1
+  128, // 6.2
+  128, // 6.3
+  0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+];
+
+pub static AV1_LEVEL_MAX_TILE_COLS: [usize; 32] = [
+  4, // 2.0
+  4, // 2.1
+  0, 0, 6, // 3.0
+  6, // 3.1
+  0, 0, 8, // 4.0
+  8, // 4.1
+  0, 0, 8,  // 5.0
+  8,  // 5.1
+  8,  // 5.2
+  8,  // 5.3
+  16, // 6.0
+  16, // 6.1
+  16, // 6.2
+  16, // 6.3
+  0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+];
+
+/// Returns the maximum bitrate of a level in the main tier, in bits per
+/// second.
+pub(crate) fn level_max_bitrate(level_idx: u8, profile: u8) -> i64 {
+  // BitrateProfileFactor in the spec.
+  let profile_factor = match profile {
+    0 => 1,
+    1 => 2,
+    _ => 3,
+  };
+  AV1_LEVEL_MAX_BITRATE[level_idx as usize] as i64 * 1000 * profile_factor
+}
+
+/// Returns the maximum size of a compressed frame of a level in the main
+/// tier, in bits.
+///
+/// The speed adjustment of the minimum compression ratio is computed at the
+/// maximum decode rate of the level, which is never more permissive than the
+/// actual decode rate of the stream.
+pub(crate) fn level_max_frame_bits(
+  level_idx: u8, profile: u8, width: usize, height: usize, still_picture: bool,
+) -> i64 {
+  let l = level_idx as usize;
+  // PicSizeProfileFactor in the spec.
+  let profile_factor = match profile {
+    0 => 15,
+    1 => 30,
+    _ => 36,
+  };
+  let uncompressed_size = (width * height * profile_factor) >> 3;
+  let min_comp_ratio = if still_picture {
+    0.8
+  } else {
+    let speed_adj = AV1_LEVEL_MAX_DECODE_RATE[l] as f64
+      / AV1_LEVEL_MAX_DISPLAY_RATE[l] as f64;
+    (AV1_LEVEL_MIN_COMP_BASIS[l] as f64 * speed_adj).max(0.8)
+  };
+  (uncompressed_size as f64 / min_comp_ratio) as i64 * 8
+}
+
+/// Returns whether an encoder configured with `config` and using `tiling`
+/// produces a bitstream that conforms to the level in the main tier.
+///
+/// The bitrate and compressed frame size limits are enforced by the rate
+/// control, which requires lossy coding and a decoder buffer no larger than
+/// one second of the maximum bitrate of the level.
+pub(crate) fn level_constraints_met(
+  config: &EncoderConfig, level_idx: u8, tiling: &TilingInfo, profile: u8,
+) -> bool {
+  let l = level_idx as usize;
+  if l == 31 {
+    return true;
+  }
+  if l > 31 || !AV1_LEVEL_DEFINED[l] {
+    return false;
+  }
+  let pic_size = (config.width * config.height) as u64;
+  let (frame_rate_num, frame_rate_den) =
+    (config.time_base.den, config.time_base.num);
+  // Every frame is decoded once, so the decode rate and the display rate
+  //  only differ by the level limits.
+  let sample_rate = (pic_size * frame_rate_num).div_ceil(frame_rate_den);
+  // Frames coded out of order are shown again with a header of their own.
+  let headers_per_frame =
+    if config.low_latency || config.still_picture { 1 } else { 2 };
+  let header_rate =
+    (headers_per_frame * frame_rate_num).div_ceil(frame_rate_den);
+  let max_bitrate = level_max_bitrate(level_idx, profile);
+  // The first tile is the largest one.
+  let tile_width =
+    (tiling.tile_width_sb << tiling.sb_size_log2).min(tiling.frame_width);
+  let tile_height =
+    (tiling.tile_height_sb << tiling.sb_size_log2).min(tiling.frame_height);
+  config.width * config.height <= AV1_LEVEL_MAX_PIC_SIZE[l]
+    && config.width <= AV1_LEVEL_MAX_H_SIZE[l]
+    && config.height <= AV1_LEVEL_MAX_V_SIZE[l]
+    && sample_rate <= AV1_LEVEL_MAX_DISPLAY_RATE[l] as u64
+    && sample_rate <= AV1_LEVEL_MAX_DECODE_RATE[l]
+    && header_rate <= AV1_LEVEL_MAX_HEADER_RATE[l] as u64
+    && tiling.rows * tiling.cols <= AV1_LEVEL_MAX_TILES[l]
+    && tiling.cols <= AV1_LEVEL_MAX_TILE_COLS[l]
+    && tile_width <= MAX_TILE_WIDTH
+    && tile_width * tile_height <= MAX_TILE_AREA
+    && config.bitrate as i64 <= max_bitrate
+    && config.max_bitrate as i64 <= max_bitrate
+    && config.decoder_buffer_size() <= max_bitrate
+    && !config.lossless
+}
+
+/// Selects the lowest level the encoder can conform to, or 31 if there is
+/// none or no bound on the bitrate was configured.
+pub(crate) fn auto_level_idx(
+  config: &EncoderConfig, tiling: &TilingInfo, profile: u8,
+) -> u8 {
+  if config.bitrate <= 0 && config.max_bitrate <= 0 {
+    return 31;
+  }
+  (0..31)
+    .find(|&level_idx| {
+      AV1_LEVEL_DEFINED[level_idx as usize]
+        && level_constraints_met(config, level_idx, tiling, profile)
+    })
+    .unwrap_or(31)
+}
@@ -100,10 +100,13 @@ pub mod context;
 mod deblock;
 mod encoder;
 mod entropymode;
+mod global_motion;
+mod intrabc;
 mod levels;
 mod lrf;
 mod mc;
 mod me;
+mod palette;
 mod rate;
 mod recon_intra;
 mod sad_plane;
@@ -114,9 +117,11 @@ pub mod scenechange;
 mod scenechange;
 mod segmentation;
 mod stats;
+mod superres;
 #[doc(hidden)]
 pub mod tiling;
 mod token_cdfs;
+mod warp;
 
 mod api;
 mod frame;
@@ -138,9 +143,11 @@ pub mod prelude {
 /// Basic data structures
 pub mod data {
   pub use crate::api::{
-    ChromaticityPoint, EncoderStatus, FrameType, Packet, Rational,
+    ChromaticityPoint, EncoderStatus, FrameType, Packet, QualitySearchStats,
+    Rational,
   };
   pub use crate::frame::{Frame, FrameParameters};
+  pub use crate::scenechange::ScreenContentStats;
   pub use crate::stats::EncoderStats;
   pub use crate::util::{CastFromPrimitive, Pixel, PixelType};
 }
@@ -153,7 +160,8 @@ pub mod config {
   };
   pub use crate::api::{
     Config, EncoderConfig, InvalidConfig, PredictionModesSetting,
-    RateControlConfig, RateControlError, RateControlSummary, SpeedSettings,
+    RateControlConfig, RateControlError, RateControlFrameStats,
+    RateControlSummary, ScreenContentMode, SpeedSettings, SuperresMode,
   };
   pub use crate::cpu_features::CpuFeatureLevel;
 }
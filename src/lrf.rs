@@ -1258,6 +1258,9 @@ pub struct RestorationPlaneConfig {
   pub stripe_height: usize,
   pub cols: usize,
   pub rows: usize,
+  // super-resolution denominator of the frame, SUPERRES_NUM if the frame
+  // is coded at full width; units are then laid out over the upscaled frame
+  pub superres_denom: usize,
 }
 
 #[derive(Clone, Debug)]
@@ -1270,7 +1273,7 @@ impl RestorationPlane {
   pub fn new(
     lrf_type: u8, unit_size: usize, sb_h_shift: usize, sb_v_shift: usize,
     sb_cols: usize, sb_rows: usize, stripe_decimate: usize, cols: usize,
-    rows: usize,
+    rows: usize, superres_denom: usize,
   ) -> RestorationPlane {
     let stripe_height = if stripe_decimate != 0 { 32 } else { 64 };
     RestorationPlane {
@@ -1284,6 +1287,7 @@ impl RestorationPlane {
         stripe_height,
         cols,
         rows,
+        superres_denom,
       },
       units: FrameRestorationUnits::new(cols, rows),
     }
@@ -1330,7 +1334,7 @@ impl RestorationState {
       && fi.sequence.enable_restoration
     {
       assert!(
-        fi.width > 1 && fi.height > 1,
+        fi.upscaled_width > 1 && fi.height > 1,
         "Width and height must be higher than 1 for LRF setup"
       );
 
@@ -1352,10 +1356,11 @@ impl RestorationState {
           // If so, that's generally a win.
           let lrf_unit_size =
             1 << (RESTORATION_TILESIZE_MAX_LOG2 - lrf_base_shift);
-          let unshifted_stretch = ((fi.width >> xdec) - 1) % lrf_unit_size
+          let unshifted_stretch = ((fi.upscaled_width >> xdec) - 1)
+            % lrf_unit_size
             <= lrf_unit_size / 2
             || ((fi.height >> ydec) - 1) % lrf_unit_size <= lrf_unit_size / 2;
-          let shifted_stretch = ((fi.width >> xdec) - 1)
+          let shifted_stretch = ((fi.upscaled_width >> xdec) - 1)
             % (lrf_unit_size >> 1)
             <= lrf_unit_size / 4
             || ((fi.height >> ydec) - 1) % (lrf_unit_size >> 1)
@@ -1428,9 +1433,10 @@ impl RestorationState {
     // derive the rest
     let y_unit_log2 = y_unit_size.ilog() - 1;
     let uv_unit_log2 = uv_unit_size.ilog() - 1;
-    let y_cols = ((fi.width + (y_unit_size >> 1)) / y_unit_size).max(1);
+    let y_cols =
+      ((fi.upscaled_width + (y_unit_size >> 1)) / y_unit_size).max(1);
     let y_rows = ((fi.height + (y_unit_size >> 1)) / y_unit_size).max(1);
-    let uv_cols = ((((fi.width + (1 << xdec >> 1)) >> xdec)
+    let uv_cols = ((((fi.upscaled_width + (1 << xdec >> 1)) >> xdec)
       + (uv_unit_size >> 1))
       / uv_unit_size)
       .max(1);
@@ -1451,6 +1457,7 @@ impl RestorationState {
           0,
           y_cols,
           y_rows,
+          fi.superres_denom,
         ),
         RestorationPlane::new(
           RESTORE_SWITCHABLE,
@@ -1462,6 +1469,7 @@ impl RestorationState {
           stripe_uv_decimate,
           uv_cols,
           uv_rows,
+          fi.superres_denom,
         ),
         RestorationPlane::new(
           RESTORE_SWITCHABLE,
@@ -1473,6 +1481,7 @@ impl RestorationState {
           stripe_uv_decimate,
           uv_cols,
           uv_rows,
+          fi.superres_denom,
         ),
       ],
     }
@@ -1490,7 +1499,8 @@ impl RestorationState {
     // unlike the other loop filters that operate over the padded
     // frame dimensions, restoration filtering and source pixel
     // accesses are clipped to the original frame dimensions
-    // that's why we use fi.width and fi.height instead of PlaneConfig fields
+    // that's why we use fi.upscaled_width and fi.height instead of
+    // PlaneConfig fields
 
     // number of stripes (counted according to colocated Y luma position)
     let stripe_n = (fi.height + 7) / 64 + 1;
@@ -1503,7 +1513,7 @@ impl RestorationState {
       let rp = &self.planes[pli];
       let xdec = out.planes[pli].cfg.xdec;
       let ydec = out.planes[pli].cfg.ydec;
-      let crop_w = (fi.width + (1 << xdec >> 1)) >> xdec;
+      let crop_w = (fi.upscaled_width + (1 << xdec >> 1)) >> xdec;
       let crop_h = (fi.height + (1 << ydec >> 1)) >> ydec;
 
       for si in 0..stripe_n {
@@ -1542,10 +1552,6 @@ impl RestorationState {
               );
             }
             RestorationFilter::Sgrproj { set, xqd } => {
-              if !fi.sequence.enable_cdef {
-                continue;
-              }
-
               setup_integral_image(
                 &mut stripe_filter_buffer,
                 STRIPE_IMAGE_STRIDE,
@@ -19,6 +19,8 @@ cfg_if::cfg_if! {
 
 use crate::cpu_features::CpuFeatureLevel;
 use crate::frame::*;
+use crate::partition::BlockSize;
+use crate::predict::InterIntraMode;
 use crate::tiling::*;
 use crate::util::*;
 
@@ -218,6 +220,375 @@ const SUBPEL_FILTERS: [[[i32; SUBPEL_FILTER_SIZE]; 16]; 6] = [
   ],
 ];
 
+/// Weights of a block's own prediction when blending in the prediction from
+/// the motion of a neighbouring block, by distance from the shared edge.
+const OBMC_MASK_2: [u8; 2] = [45, 64];
+const OBMC_MASK_4: [u8; 4] = [39, 50, 59, 64];
+const OBMC_MASK_8: [u8; 8] = [36, 42, 48, 53, 57, 61, 64, 64];
+const OBMC_MASK_16: [u8; 16] =
+  [34, 37, 40, 43, 46, 49, 52, 54, 56, 58, 60, 61, 64, 64, 64, 64];
+#[rustfmt::skip]
+const OBMC_MASK_32: [u8; 32] = [
+  33, 35, 36, 38, 40, 41, 43, 44, 45, 47, 48, 50, 51, 52, 53, 55,
+  56, 57, 58, 59, 60, 60, 61, 62, 64, 64, 64, 64, 64, 64, 64, 64,
+];
+
+const fn obmc_mask(len: usize) -> &'static [u8] {
+  match len {
+    2 => &OBMC_MASK_2,
+    4 => &OBMC_MASK_4,
+    8 => &OBMC_MASK_8,
+    16 => &OBMC_MASK_16,
+    32 => &OBMC_MASK_32,
+    _ => unreachable!(),
+  }
+}
+
+#[inline(always)]
+fn blend_obmc<T: Pixel>(dst: &mut T, pred: T, m: u8) {
+  let m = m as i32;
+  let v = m * i32::cast_from(*dst) + (64 - m) * i32::cast_from(pred);
+  *dst = T::cast_from(round_shift(v, 6));
+}
+
+/// Blends `pred`, the prediction of the top `height` rows of a block from
+/// the motion of the block above it, into the prediction in `dst`.
+pub fn blend_obmc_above<T: Pixel>(
+  dst: &mut PlaneRegionMut<'_, T>, pred: &PlaneRegion<'_, T>, width: usize,
+  height: usize,
+) {
+  for (r, &m) in obmc_mask(height).iter().enumerate() {
+    for (d, &p) in dst[r][..width].iter_mut().zip(&pred[r][..width]) {
+      blend_obmc(d, p, m);
+    }
+  }
+}
+
+/// Blends `pred`, the prediction of the left `width` columns of a block from
+/// the motion of the block to its left, into the prediction in `dst`.
+pub fn blend_obmc_left<T: Pixel>(
+  dst: &mut PlaneRegionMut<'_, T>, pred: &PlaneRegion<'_, T>, width: usize,
+  height: usize,
+) {
+  let mask = obmc_mask(width);
+  for r in 0..height {
+    for ((d, &p), &m) in
+      dst[r][..width].iter_mut().zip(&pred[r][..width]).zip(mask)
+    {
+      blend_obmc(d, p, m);
+    }
+  }
+}
+
+// HBD output interval is [-20588, 36956] (10-bit), [-20602, 36983] (12-bit)
+// Subtract PREP_BIAS to ensure result fits in i16 and matches dav1d assembly
+const PREP_BIAS: i32 = 8192;
+
+/// Largest distance between frames told apart by distance-weighted compound
+/// prediction.
+const MAX_FRAME_DISTANCE: u32 = 31;
+
+/// Weight of the first prediction, out of 16, when the predictions from two
+/// references `dist` frames away from the current frame are averaged by
+/// distance.
+pub fn distance_weight(dist: [u32; 2]) -> i32 {
+  const QUANT_DIST_WEIGHT: [[u32; 2]; 3] = [[2, 3], [2, 5], [2, 7]];
+  const QUANT_DIST_LOOKUP: [[i32; 2]; 4] = [[9, 7], [11, 5], [12, 4], [13, 3]];
+
+  let d0 = dist[1].min(MAX_FRAME_DISTANCE);
+  let d1 = dist[0].min(MAX_FRAME_DISTANCE);
+  let order = (d0 <= d1) as usize;
+  let k = QUANT_DIST_WEIGHT
+    .iter()
+    .position(|c| {
+      let (d0_c0, d1_c1) = (d0 * c[order], d1 * c[1 - order]);
+      if d0 > d1 {
+        d0_c0 < d1_c1
+      } else {
+        d0_c0 > d1_c1
+      }
+    })
+    .unwrap_or(QUANT_DIST_WEIGHT.len());
+  QUANT_DIST_LOOKUP[k][order]
+}
+
+/// Averages two predictions made with `prep_8tap`, weighting the first with
+/// `weight` out of 16.
+pub fn mc_w_avg<T: Pixel>(
+  dst: &mut PlaneRegionMut<'_, T>, tmp1: &[i16], tmp2: &[i16], width: usize,
+  height: usize, weight: i32, bit_depth: usize,
+) {
+  let max_sample_val = (1 << bit_depth) - 1;
+  let intermediate_bits = 4 - if bit_depth == 12 { 2 } else { 0 };
+  let prep_bias = if bit_depth == 8 { 0 } else { PREP_BIAS * 16 };
+  for r in 0..height {
+    let tmp1 = &tmp1[r * width..][..width];
+    let tmp2 = &tmp2[r * width..][..width];
+    for ((d, &a), &b) in dst[r][..width].iter_mut().zip(tmp1).zip(tmp2) {
+      let v = a as i32 * weight + b as i32 * (16 - weight) + prep_bias;
+      *d = T::cast_from(
+        round_shift(v, intermediate_bits + 4).clamp(0, max_sample_val),
+      );
+    }
+  }
+}
+
+/// Weight at row `r` and column `c` of a plane with decimation `xdec` and
+/// `ydec`, averaged from a luma `mask` holding `stride` weights per row.
+#[inline(always)]
+fn mask_weight(
+  mask: &[u8], stride: usize, r: usize, c: usize, xdec: usize, ydec: usize,
+) -> i32 {
+  let mut sum = 0;
+  for y in 0..=ydec {
+    let row = &mask[((r << ydec) + y) * stride..];
+    for x in 0..=xdec {
+      sum += row[(c << xdec) + x] as i32;
+    }
+  }
+  round_shift(sum, xdec + ydec)
+}
+
+/// Blends two predictions made with `prep_8tap`, weighting the first with
+/// `mask`. The mask covers the luma block and is subsampled for planes with
+/// decimation `xdec` and `ydec`.
+pub fn mc_mask<T: Pixel>(
+  dst: &mut PlaneRegionMut<'_, T>, tmp1: &[i16], tmp2: &[i16], width: usize,
+  height: usize, mask: &[u8], xdec: usize, ydec: usize, bit_depth: usize,
+) {
+  let max_sample_val = (1 << bit_depth) - 1;
+  let intermediate_bits = 4 - if bit_depth == 12 { 2 } else { 0 };
+  let prep_bias = if bit_depth == 8 { 0 } else { PREP_BIAS * 64 };
+  let stride = width << xdec;
+  for r in 0..height {
+    let tmp1 = &tmp1[r * width..][..width];
+    let tmp2 = &tmp2[r * width..][..width];
+    for (c, (d, (&a, &b))) in
+      dst[r][..width].iter_mut().zip(tmp1.iter().zip(tmp2)).enumerate()
+    {
+      let m = mask_weight(mask, stride, r, c, xdec, ydec);
+      let v = a as i32 * m + b as i32 * (64 - m) + prep_bias;
+      *d = T::cast_from(
+        round_shift(v, intermediate_bits + 6).clamp(0, max_sample_val),
+      );
+    }
+  }
+}
+
+/// Blends `inter`, the inter prediction of an inter-intra block, into its
+/// intra prediction in `dst`, weighting the intra prediction with `mask`.
+/// The mask is subsampled as in [`mc_mask`].
+pub fn blend_interintra<T: Pixel>(
+  dst: &mut PlaneRegionMut<'_, T>, inter: &[T], width: usize, height: usize,
+  mask: &[u8], xdec: usize, ydec: usize,
+) {
+  let stride = width << xdec;
+  for r in 0..height {
+    let inter = &inter[r * width..][..width];
+    for (c, (d, &p)) in dst[r][..width].iter_mut().zip(inter).enumerate() {
+      let m = mask_weight(mask, stride, r, c, xdec, ydec);
+      let v = m * i32::cast_from(*d) + (64 - m) * i32::cast_from(p);
+      *d = T::cast_from(round_shift(v, 6));
+    }
+  }
+}
+
+/// Fills `mask` with weights of the first of two predictions made with
+/// `prep_8tap` which grow with the difference between them, or with their
+/// complement if `inverse` is set.
+pub fn diff_weighted_mask(
+  mask: &mut [u8], tmp1: &[i16], tmp2: &[i16], width: usize, height: usize,
+  inverse: bool, bit_depth: usize,
+) {
+  let intermediate_bits = 4 - if bit_depth == 12 { 2 } else { 0 };
+  let shift = bit_depth - 8 + intermediate_bits;
+  let len = width * height;
+  for ((m, &a), &b) in
+    mask[..len].iter_mut().zip(&tmp1[..len]).zip(&tmp2[..len])
+  {
+    let diff = round_shift((a as i32 - b as i32).abs(), shift);
+    let weight = (38 + diff / 16).min(64) as u8;
+    *m = if inverse { 64 - weight } else { weight };
+  }
+}
+
+/// Weights of the intra prediction in inter-intra blocks without a wedge, by
+/// distance from the top or left edge of a block scaled to 128 samples.
+#[rustfmt::skip]
+const II_WEIGHTS_1D: [u8; 128] = [
+  60, 58, 56, 54, 52, 50, 48, 47, 45, 44, 42, 41, 39, 38, 37, 35,
+  34, 33, 32, 31, 30, 29, 28, 27, 26, 25, 24, 23, 22, 22, 21, 20,
+  19, 19, 18, 18, 17, 16, 16, 15, 15, 14, 14, 13, 13, 12, 12, 12,
+  11, 11, 10, 10, 10,  9,  9,  9,  8,  8,  8,  8,  7,  7,  7,  7,
+   6,  6,  6,  6,  6,  5,  5,  5,  5,  5,  4,  4,  4,  4,  4,  4,
+   4,  4,  3,  3,  3,  3,  3,  3,  3,  3,  3,  2,  2,  2,  2,  2,
+   2,  2,  2,  2,  2,  2,  2,  2,  2,  2,  1,  1,  1,  1,  1,  1,
+   1,  1,  1,  1,  1,  1,  1,  1,  1,  1,  1,  1,  1,  1,  1,  1,
+];
+
+/// Fills `mask` with the weights of the intra prediction of a `width` by
+/// `height` block predicted with inter-intra `mode` and no wedge.
+pub fn interintra_mask(
+  mask: &mut [u8], mode: InterIntraMode, width: usize, height: usize,
+) {
+  let scale = II_WEIGHTS_1D.len() / width.max(height);
+  for (y, row) in mask.chunks_exact_mut(width).take(height).enumerate() {
+    for (x, m) in row.iter_mut().enumerate() {
+      *m = match mode {
+        InterIntraMode::II_V_PRED => II_WEIGHTS_1D[y * scale],
+        InterIntraMode::II_H_PRED => II_WEIGHTS_1D[x * scale],
+        InterIntraMode::II_SMOOTH_PRED => II_WEIGHTS_1D[x.min(y) * scale],
+        _ => 32,
+      };
+    }
+  }
+}
+
+/// Number of wedges available to each block size that has them.
+pub const WEDGE_TYPES: usize = 16;
+
+/// Whether blocks of this size can use wedge masks.
+pub const fn has_wedge(bsize: BlockSize) -> bool {
+  use crate::partition::BlockSize::*;
+  matches!(
+    bsize,
+    BLOCK_8X8
+      | BLOCK_8X16
+      | BLOCK_16X8
+      | BLOCK_16X16
+      | BLOCK_16X32
+      | BLOCK_32X16
+      | BLOCK_32X32
+      | BLOCK_8X32
+      | BLOCK_32X8
+  )
+}
+
+/// Whether single reference blocks of this size can blend in an intra
+/// prediction.
+pub const fn interintra_allowed(bsize: BlockSize) -> bool {
+  has_wedge(bsize)
+    && !matches!(bsize, BlockSize::BLOCK_8X32 | BlockSize::BLOCK_32X8)
+}
+
+#[derive(Clone, Copy)]
+enum WedgeDirection {
+  Horizontal,
+  Vertical,
+  Oblique27,
+  Oblique63,
+  Oblique117,
+  Oblique153,
+}
+
+use WedgeDirection::*;
+
+const MASK_MASTER_SIZE: usize = 64;
+
+/// Transitions of the master wedge masks across the wedge boundary, on the
+/// even and odd rows of oblique masks and on vertical masks.
+const WEDGE_MASTER_LINE_EVEN: [u8; 8] = [1, 4, 11, 27, 46, 58, 62, 63];
+const WEDGE_MASTER_LINE_ODD: [u8; 8] = [1, 2, 6, 18, 37, 53, 60, 63];
+const WEDGE_MASTER_LINE_VERTICAL: [u8; 8] = [0, 2, 7, 21, 43, 57, 62, 64];
+
+/// Direction and x and y offsets, in eighths of the block, of each wedge for
+/// blocks taller than wide, wider than tall and square blocks.
+#[rustfmt::skip]
+const WEDGE_CODEBOOK: [[(WedgeDirection, usize, usize); WEDGE_TYPES]; 3] = [
+  [
+    (Oblique27, 4, 4), (Oblique63, 4, 4), (Oblique117, 4, 4),
+    (Oblique153, 4, 4), (Horizontal, 4, 2), (Horizontal, 4, 4),
+    (Horizontal, 4, 6), (Vertical, 4, 4), (Oblique27, 4, 2),
+    (Oblique27, 4, 6), (Oblique153, 4, 2), (Oblique153, 4, 6),
+    (Oblique63, 2, 4), (Oblique63, 6, 4), (Oblique117, 2, 4),
+    (Oblique117, 6, 4),
+  ],
+  [
+    (Oblique27, 4, 4), (Oblique63, 4, 4), (Oblique117, 4, 4),
+    (Oblique153, 4, 4), (Vertical, 2, 4), (Vertical, 4, 4),
+    (Vertical, 6, 4), (Horizontal, 4, 4), (Oblique27, 4, 2),
+    (Oblique27, 4, 6), (Oblique153, 4, 2), (Oblique153, 4, 6),
+    (Oblique63, 2, 4), (Oblique63, 6, 4), (Oblique117, 2, 4),
+    (Oblique117, 6, 4),
+  ],
+  [
+    (Oblique27, 4, 4), (Oblique63, 4, 4), (Oblique117, 4, 4),
+    (Oblique153, 4, 4), (Horizontal, 4, 2), (Horizontal, 4, 6),
+    (Vertical, 2, 4), (Vertical, 6, 4), (Oblique27, 4, 2),
+    (Oblique27, 4, 6), (Oblique153, 4, 2), (Oblique153, 4, 6),
+    (Oblique63, 2, 4), (Oblique63, 6, 4), (Oblique117, 2, 4),
+    (Oblique117, 6, 4),
+  ],
+];
+
+/// Weight at row `y` and column `x` of the 64x64 master mask that the wedge
+/// masks with direction `dir` are cut from.
+fn wedge_master(dir: WedgeDirection, y: usize, x: usize) -> u8 {
+  // Position `i` of a row whose transition is centred on `ctr`
+  fn line(line: &[u8; 8], i: usize, ctr: usize) -> u8 {
+    if i + 4 < ctr {
+      0
+    } else if i + 4 >= ctr + line.len() {
+      64
+    } else {
+      line[i + 4 - ctr]
+    }
+  }
+  fn oblique63(y: usize, x: usize) -> u8 {
+    let row = if y % 2 == 0 {
+      &WEDGE_MASTER_LINE_EVEN
+    } else {
+      &WEDGE_MASTER_LINE_ODD
+    };
+    line(row, x, MASK_MASTER_SIZE * 3 / 4 - y.div_ceil(2))
+  }
+
+  let last = MASK_MASTER_SIZE - 1;
+  match dir {
+    Horizontal => line(&WEDGE_MASTER_LINE_VERTICAL, y, MASK_MASTER_SIZE / 2),
+    Vertical => line(&WEDGE_MASTER_LINE_VERTICAL, x, MASK_MASTER_SIZE / 2),
+    Oblique27 => oblique63(x, y),
+    Oblique63 => oblique63(y, x),
+    Oblique117 => 64 - oblique63(y, last - x),
+    Oblique153 => 64 - oblique63(x, last - y),
+  }
+}
+
+/// Fills `mask` with the weights of the first prediction of a `bsize` block
+/// split by wedge `index`, with the sides of the wedge swapped by `sign`.
+///
+/// # Panics
+///
+/// - If blocks of size `bsize` have no wedges
+pub fn wedge_mask(
+  mask: &mut [u8], bsize: BlockSize, index: usize, sign: bool,
+) {
+  assert!(has_wedge(bsize));
+  let (w, h) = (bsize.width(), bsize.height());
+  let shape = match h.cmp(&w) {
+    std::cmp::Ordering::Greater => 0,
+    std::cmp::Ordering::Less => 1,
+    std::cmp::Ordering::Equal => 2,
+  };
+  let (dir, xoff, yoff) = WEDGE_CODEBOOK[shape][index];
+  let x0 = MASK_MASTER_SIZE / 2 - ((xoff * w) >> 3);
+  let y0 = MASK_MASTER_SIZE / 2 - ((yoff * h) >> 3);
+
+  // Without a sign, the first prediction dominates the top and left edges
+  let edge_sum =
+    (0..w).map(|x| wedge_master(dir, y0, x0 + x) as usize).sum::<usize>()
+      + (1..h).map(|y| wedge_master(dir, y0 + y, x0) as usize).sum::<usize>();
+  let edge_avg = (edge_sum + (w + h - 1) / 2) / (w + h - 1);
+  let invert = (edge_avg < 32) != sign;
+
+  for (y, row) in mask.chunks_exact_mut(w).take(h).enumerate() {
+    for (x, m) in row.iter_mut().enumerate() {
+      let v = wedge_master(dir, y0 + y, x0 + x);
+      *m = if invert { 64 - v } else { v };
+    }
+  }
+}
+
 pub(crate) mod rust {
   use super::*;
   use num_traits::*;
@@ -238,10 +609,14 @@ pub(crate) mod rust {
   fn get_filter(
     mode: FilterMode, frac: i32, length: usize,
   ) -> [i32; SUBPEL_FILTER_SIZE] {
+    // Blocks of at most 4 samples use 4-tap versions of the regular and
+    // smooth filters, the former standing in for the sharp filter.
     let filter_idx = if mode == FilterMode::BILINEAR || length > 4 {
       mode as usize
+    } else if mode == FilterMode::SMOOTH {
+      5
     } else {
-      (mode as usize).min(1) + 4
+      4
     };
     SUBPEL_FILTERS[filter_idx][frac as usize]
   }
@@ -352,10 +727,6 @@ pub(crate) mod rust {
     }
   }
 
-  // HBD output interval is [-20588, 36956] (10-bit), [-20602, 36983] (12-bit)
-  // Subtract PREP_BIAS to ensure result fits in i16 and matches dav1d assembly
-  const PREP_BIAS: i32 = 8192;
-
   #[cold_for_target_arch("x86_64")]
   pub fn prep_8tap<T: Pixel>(
     tmp: &mut [i16], src: PlaneSlice<'_, T>, width: usize, height: usize,
@@ -593,7 +593,9 @@ pub fn estimate_motion<T: Pixel>(
       ssdec,
     );
 
-    if let Some(pmv) = pmv {
+    // Motion vectors are coded in whole pixels when `force_integer_mv` is
+    // set, so the subpixel refinement is skipped.
+    if let Some(pmv) = pmv.filter(|_| fi.force_integer_mv == 0) {
       let use_satd: bool = fi.config.speed_settings.motion.use_satd_subpel;
       if use_satd {
         best.rd = get_fullpel_mv_rd(
@@ -1429,9 +1431,18 @@ fn get_subpel_mv_rd<T: Pixel>(
     TileRect { x: 0, y: 0, width: tmp_width, height: tmp_height };
 
   PredictionMode::NEWMV.predict_inter_single(
-    fi, tile_rect, 0, po, tmp_region,
+    fi,
+    tile_rect,
+    0,
+    po,
+    tmp_region,
     // motion comp's w & h on edges can be different than distortion's
-    tmp_width, tmp_height, ref_frame, cand_mv,
+    tmp_width,
+    tmp_height,
+    ref_frame,
+    cand_mv,
+    None,
+    [fi.default_filter; 2],
   );
   let plane_ref = tmp_region.as_const();
   compute_mv_rd(
@@ -0,0 +1,426 @@
+// Copyright (c) 2024, The rav1e contributors. All rights reserved
+//
+// This source code is subject to the terms of the BSD 2 Clause License and
+// the Alliance for Open Media Patent License 1.0. If the BSD 2 Clause License
+// was not distributed with this source code in the LICENSE file, you can
+// obtain it at www.aomedia.org/license/software. If the Alliance for Open
+// Media Patent License 1.0 was not distributed with this source code in the
+// PATENTS file, you can obtain it at www.aomedia.org/license/patent.
+
+//! Palette prediction: a block is coded as a handful of colours plus a map
+//! assigning one of them to every pixel, which suits text and user interface
+//! content far better than the smooth intra predictors.
+
+use crate::context::*;
+use crate::encoder::FrameInvariants;
+use crate::frame::PlaneOffset;
+use crate::partition::BlockSize;
+use crate::predict::PaletteColor;
+use crate::tiling::*;
+use crate::util::*;
+
+use arrayvec::ArrayVec;
+
+/// Maximum number of colours in a palette.
+pub const PALETTE_MAX_SIZE: usize = PaletteColor::PALETTE_COLORS as usize;
+/// Blocks with more distinct colours than this are not searched.
+const PALETTE_MAX_DISTINCT: usize = 64;
+/// Number of neighbouring indices reordered to the front of the colour order.
+const PALETTE_NUM_NEIGHBORS: usize = 3;
+/// Colour context for each hash of the neighbouring index scores.
+const PALETTE_COLOR_CONTEXT: [usize; 9] = [0, 0, 0, 0, 0, 4, 3, 2, 1];
+/// Palette sizes tried when a block has too many colours to keep all of them.
+const PALETTE_CLUSTER_SIZES: [usize; 2] = [8, 4];
+
+/// Palette of a block for the luma plane and for both chroma planes.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PaletteParams {
+  /// Number of luma and chroma colours, 0 when no palette is used
+  pub size: [u8; 2],
+  /// Colours of the Y, U and V planes; Y and U are in ascending order
+  pub colors: [[u16; PALETTE_MAX_SIZE]; 3],
+}
+
+impl PaletteParams {
+  /// Number of colours used for plane `p`.
+  #[inline]
+  pub const fn plane_size(&self, p: usize) -> usize {
+    self.size[(p > 0) as usize] as usize
+  }
+
+  /// Colours used for plane `p`.
+  #[inline]
+  pub fn plane_colors(&self, p: usize) -> &[u16] {
+    &self.colors[p][..self.plane_size(p)]
+  }
+
+  /// The same palette with the chroma colours dropped.
+  pub const fn luma_only(&self) -> Self {
+    Self { size: [self.size[0], 0], colors: self.colors }
+  }
+}
+
+/// Whether palette mode info is coded for a block of size `bsize`.
+pub fn palette_allowed<T: Pixel>(
+  fi: &FrameInvariants<T>, bsize: BlockSize,
+) -> bool {
+  fi.allow_screen_content_tools > 0
+    && bsize >= BlockSize::BLOCK_8X8
+    && bsize.width() <= 64
+    && bsize.height() <= 64
+}
+
+/// Block and visible dimensions of the colour index map of a plane with the
+/// given decimation, as derived by the decoder.
+pub fn color_map_dimensions<T: Pixel>(
+  fi: &FrameInvariants<T>, frame_bo: PlaneBlockOffset, bsize: BlockSize,
+  xdec: usize, ydec: usize,
+) -> (usize, usize, usize, usize) {
+  let w = bsize.width();
+  let h = bsize.height();
+  let visible_w = w.min((fi.w_in_b - frame_bo.0.x) << MI_SIZE_LOG2);
+  let visible_h = h.min((fi.h_in_b - frame_bo.0.y) << MI_SIZE_LOG2);
+  (w >> xdec, h >> ydec, visible_w >> xdec, visible_h >> ydec)
+}
+
+/// Index of the palette entry nearest to a luma value.
+fn nearest_luma(colors: &[u16], v: i32) -> u8 {
+  let mut best = 0;
+  let mut best_dist = i32::MAX;
+  for (i, &c) in colors.iter().enumerate() {
+    let dist = (v - i32::from(c)).abs();
+    if dist < best_dist {
+      best_dist = dist;
+      best = i;
+    }
+  }
+  best as u8
+}
+
+/// Index of the palette entry nearest to a pair of chroma values.
+fn nearest_chroma(u_colors: &[u16], v_colors: &[u16], u: i32, v: i32) -> u8 {
+  let mut best = 0;
+  let mut best_dist = i32::MAX;
+  for (i, (&cu, &cv)) in u_colors.iter().zip(v_colors).enumerate() {
+    let du = u - i32::from(cu);
+    let dv = v - i32::from(cv);
+    let dist = du * du + dv * dv;
+    if dist < best_dist {
+      best_dist = dist;
+      best = i;
+    }
+  }
+  best as u8
+}
+
+/// Fills `map` with the colour indices of the `width`x`height` window at
+/// `(x0, y0)` of the block at `tile_bo`, for the luma (`plane_type` 0) or
+/// the chroma palette.
+///
+/// Every source pixel is mapped to its nearest palette entry. Outside the
+/// visible area the last visible row and column are repeated, as the decoder
+/// does, so that the map is a pure function of the source and the palette.
+pub fn fill_color_map<T: Pixel>(
+  fi: &FrameInvariants<T>, ts: &TileStateMut<'_, T>, tile_bo: TileBlockOffset,
+  bsize: BlockSize, plane_type: usize, palette: &PaletteParams, x0: usize,
+  y0: usize, width: usize, height: usize, map: &mut [u8],
+) {
+  let (xdec, ydec) = if plane_type == 0 {
+    (0, 0)
+  } else {
+    let cfg = ts.input_tile.planes[1].plane_cfg;
+    (cfg.xdec, cfg.ydec)
+  };
+  let frame_bo = ts.to_frame_block_offset(tile_bo);
+  let (_, _, visible_w, visible_h) =
+    color_map_dimensions(fi, frame_bo, bsize, xdec, ydec);
+  let area = Area::BlockStartingAt { bo: tile_bo.0 };
+
+  if plane_type == 0 {
+    let src = ts.input_tile.planes[0].subregion(area);
+    let colors = palette.plane_colors(0);
+    for (y, row) in map.chunks_exact_mut(width).take(height).enumerate() {
+      let src_row = &src[(y0 + y).min(visible_h - 1)];
+      for (x, idx) in row.iter_mut().enumerate() {
+        let v = i32::cast_from(src_row[(x0 + x).min(visible_w - 1)]);
+        *idx = nearest_luma(colors, v);
+      }
+    }
+  } else {
+    let src_u = ts.input_tile.planes[1].subregion(area);
+    let src_v = ts.input_tile.planes[2].subregion(area);
+    let (u_colors, v_colors) =
+      (palette.plane_colors(1), palette.plane_colors(2));
+    for (y, row) in map.chunks_exact_mut(width).take(height).enumerate() {
+      let sy = (y0 + y).min(visible_h - 1);
+      for (x, idx) in row.iter_mut().enumerate() {
+        let sx = (x0 + x).min(visible_w - 1);
+        let u = i32::cast_from(src_u[sy][sx]);
+        let v = i32::cast_from(src_v[sy][sx]);
+        *idx = nearest_chroma(u_colors, v_colors, u, v);
+      }
+    }
+  }
+}
+
+/// Writes the palette prediction of the transform block of plane `p` at
+/// `po` into the reconstruction.
+pub fn predict_palette<T: Pixel>(
+  fi: &FrameInvariants<T>, ts: &mut TileStateMut<'_, T>,
+  tile_bo: TileBlockOffset, bsize: BlockSize, p: usize,
+  palette: &PaletteParams, tx_bo: TileBlockOffset, po: PlaneOffset,
+  width: usize, height: usize,
+) {
+  let block_po = tile_bo.plane_offset(ts.rec.planes[p].plane_cfg);
+  let x0 = (po.x - block_po.x) as usize;
+  let y0 = (po.y - block_po.y) as usize;
+  let mut map = [0u8; 64 * 64];
+  let map = &mut map[..width * height];
+  fill_color_map(
+    fi,
+    ts,
+    tile_bo,
+    bsize,
+    p.min(1),
+    palette,
+    x0,
+    y0,
+    width,
+    height,
+    map,
+  );
+
+  let colors = palette.plane_colors(p);
+  let mut dst = ts.rec.planes[p].subregion_mut(Area::BlockRect {
+    bo: tx_bo.0,
+    width,
+    height,
+  });
+  for (y, indices) in map.chunks_exact(width).enumerate() {
+    for (px, &idx) in dst[y].iter_mut().zip(indices) {
+      *px = T::cast_from(colors[idx as usize]);
+    }
+  }
+}
+
+/// Context and colour order used to code the index at `(r, c)` of a colour
+/// map with `n` colours. The order lists the colours most used by the left,
+/// above-left and above neighbours first.
+pub fn color_context(
+  map: &[u8], stride: usize, r: usize, c: usize, n: usize,
+) -> (usize, [u8; PALETTE_MAX_SIZE]) {
+  let mut scores = [0u8; PALETTE_MAX_SIZE];
+  let mut order: [u8; PALETTE_MAX_SIZE] = [0, 1, 2, 3, 4, 5, 6, 7];
+  if c > 0 {
+    scores[map[r * stride + c - 1] as usize] += 2;
+  }
+  if r > 0 && c > 0 {
+    scores[map[(r - 1) * stride + c - 1] as usize] += 1;
+  }
+  if r > 0 {
+    scores[map[(r - 1) * stride + c] as usize] += 2;
+  }
+  for i in 0..PALETTE_NUM_NEIGHBORS {
+    let mut max_score = scores[i];
+    let mut max_idx = i;
+    for (j, &score) in scores.iter().enumerate().take(n).skip(i + 1) {
+      if score > max_score {
+        max_score = score;
+        max_idx = j;
+      }
+    }
+    if max_idx != i {
+      let max_order = order[max_idx];
+      scores.copy_within(i..max_idx, i + 1);
+      order.copy_within(i..max_idx, i + 1);
+      scores[i] = max_score;
+      order[i] = max_order;
+    }
+  }
+  let hash =
+    scores[0] as usize + 2 * (scores[1] as usize + scores[2] as usize);
+  (PALETTE_COLOR_CONTEXT[hash], order)
+}
+
+/// Clusters sorted `data` into `k` colours with k-means, returning the
+/// distinct colours found in ascending order.
+fn cluster(data: &[u16], k: usize) -> ArrayVec<u16, PALETTE_MAX_SIZE> {
+  let mut colors: ArrayVec<u16, PALETTE_MAX_SIZE> = match k {
+    8 => kmeans::<u16, 8>(data).into_iter().collect(),
+    4 => kmeans::<u16, 4>(data).into_iter().collect(),
+    _ => unreachable!(),
+  };
+  colors.sort_unstable();
+  let mut distinct = ArrayVec::new();
+  for c in colors {
+    if distinct.last() != Some(&c) {
+      distinct.push(c);
+    }
+  }
+  distinct
+}
+
+/// Candidate luma palettes for the block at `tile_bo`.
+///
+/// A block with at most 8 distinct colours gets an exact palette; blocks
+/// with a few more are clustered with k-means. Flat blocks and blocks with
+/// many colours yield no candidate.
+pub fn luma_palette_candidates<T: Pixel>(
+  fi: &FrameInvariants<T>, ts: &TileStateMut<'_, T>, tile_bo: TileBlockOffset,
+  bsize: BlockSize,
+) -> ArrayVec<PaletteParams, 2> {
+  let mut candidates = ArrayVec::new();
+  let frame_bo = ts.to_frame_block_offset(tile_bo);
+  let (_, _, visible_w, visible_h) =
+    color_map_dimensions(fi, frame_bo, bsize, 0, 0);
+  let src =
+    ts.input_tile.planes[0].subregion(Area::BlockStartingAt { bo: tile_bo.0 });
+
+  let mut data: Vec<u16> = (0..visible_h)
+    .flat_map(|y| src[y][..visible_w].iter().map(|&px| u16::cast_from(px)))
+    .collect();
+  data.sort_unstable();
+  let mut distinct = data.clone();
+  distinct.dedup();
+  if distinct.len() < 2 || distinct.len() > PALETTE_MAX_DISTINCT {
+    return candidates;
+  }
+
+  let mut push = |colors: &[u16]| {
+    if colors.len() >= 2 {
+      let mut palette = PaletteParams::default();
+      palette.size[0] = colors.len() as u8;
+      palette.colors[0][..colors.len()].copy_from_slice(colors);
+      candidates.push(palette);
+    }
+  };
+  if distinct.len() <= PALETTE_MAX_SIZE {
+    push(&distinct);
+  } else {
+    for k in PALETTE_CLUSTER_SIZES {
+      push(&cluster(&data, k));
+    }
+  }
+  candidates
+}
+
+/// Chroma palette for the block at `tile_bo`, added to the luma palette in
+/// `palette`.
+///
+/// Exact when the block has at most 8 distinct colour pairs. Otherwise the
+/// chroma plane with more distinct values is clustered with k-means and the
+/// other one takes the mean value of each cluster.
+pub fn chroma_palette_candidate<T: Pixel>(
+  fi: &FrameInvariants<T>, ts: &TileStateMut<'_, T>, tile_bo: TileBlockOffset,
+  bsize: BlockSize, palette: &PaletteParams,
+) -> Option<PaletteParams> {
+  let cfg = ts.input_tile.planes[1].plane_cfg;
+  let (xdec, ydec) = (cfg.xdec, cfg.ydec);
+  // The decoder widens chroma maps narrower than 4 pixels with the
+  // neighbouring block, which the search does not model.
+  if bsize.width() >> xdec < 4 || bsize.height() >> ydec < 4 {
+    return None;
+  }
+  let frame_bo = ts.to_frame_block_offset(tile_bo);
+  let (_, _, visible_w, visible_h) =
+    color_map_dimensions(fi, frame_bo, bsize, xdec, ydec);
+  let area = Area::BlockStartingAt { bo: tile_bo.0 };
+  let src_u = ts.input_tile.planes[1].subregion(area);
+  let src_v = ts.input_tile.planes[2].subregion(area);
+
+  let mut pairs: Vec<(u16, u16)> = (0..visible_h)
+    .flat_map(|y| {
+      src_u[y][..visible_w]
+        .iter()
+        .zip(&src_v[y][..visible_w])
+        .map(|(&u, &v)| (u16::cast_from(u), u16::cast_from(v)))
+    })
+    .collect();
+  pairs.sort_unstable();
+  let mut distinct = pairs.clone();
+  distinct.dedup();
+  if distinct.len() < 2 || distinct.len() > PALETTE_MAX_DISTINCT {
+    return None;
+  }
+
+  let colors: ArrayVec<(u16, u16), PALETTE_MAX_SIZE> =
+    if distinct.len() <= PALETTE_MAX_SIZE {
+      distinct.into_iter().collect()
+    } else {
+      let mut u: Vec<u16> = pairs.iter().map(|&(u, _)| u).collect();
+      let mut v: Vec<u16> = pairs.iter().map(|&(_, v)| v).collect();
+      u.sort_unstable();
+      v.sort_unstable();
+      let count_distinct =
+        |data: &[u16]| 1 + data.windows(2).filter(|w| w[0] != w[1]).count();
+      let swap = count_distinct(&v) > count_distinct(&u);
+      let primary = if swap { &v } else { &u };
+      let centers = cluster(primary, PALETTE_MAX_SIZE);
+      if centers.len() < 2 {
+        return None;
+      }
+      let mut sums = [(0u32, 0u32); PALETTE_MAX_SIZE];
+      for &(u, v) in &pairs {
+        let (p, s) = if swap { (v, u) } else { (u, v) };
+        let i = nearest_luma(&centers, i32::from(p)) as usize;
+        sums[i].0 += u32::from(s);
+        sums[i].1 += 1;
+      }
+      let mut colors: ArrayVec<(u16, u16), PALETTE_MAX_SIZE> = centers
+        .iter()
+        .zip(&sums)
+        .filter(|(_, &(_, count))| count > 0)
+        .map(|(&c, &(sum, count))| {
+          let mean = ((sum + count / 2) / count) as u16;
+          if swap {
+            (mean, c)
+          } else {
+            (c, mean)
+          }
+        })
+        .collect();
+      colors.sort_unstable();
+      colors
+    };
+  if colors.len() < 2 {
+    return None;
+  }
+
+  let mut palette = *palette;
+  palette.size[1] = colors.len() as u8;
+  for (i, &(u, v)) in colors.iter().enumerate() {
+    palette.colors[1][i] = u;
+    palette.colors[2][i] = v;
+  }
+  Some(palette)
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn color_context_orders_neighbours() {
+    // 2 1
+    // 1 ?
+    let map = [2, 1, 1, 0];
+    let (ctx, order) = color_context(&map, 2, 1, 1, 3);
+    // left and above both score 2 for colour 1, above-left 1 for colour 2
+    assert_eq!(order[..3], [1, 2, 0]);
+    assert_eq!(ctx, PALETTE_COLOR_CONTEXT[4 + 2]);
+
+    let (ctx, order) = color_context(&map, 2, 0, 0, 3);
+    assert_eq!(order[..3], [0, 1, 2]);
+    assert_eq!(ctx, 0);
+  }
+
+  #[test]
+  fn cluster_returns_distinct_sorted_colors() {
+    let mut data: Vec<u16> =
+      (0..64).map(|i| [10, 12, 200, 202, 90][i % 5]).collect();
+    data.sort_unstable();
+    let colors = cluster(&data, 4);
+    assert!(colors.windows(2).all(|w| w[0] < w[1]));
+    assert!(colors.len() >= 2 && colors.len() <= 4);
+  }
+}
@@ -27,13 +27,14 @@ use aligned_vec::{avec, ABox};
 
 use crate::context::{TileBlockOffset, MAX_SB_SIZE_LOG2, MAX_TX_SIZE};
 use crate::cpu_features::CpuFeatureLevel;
-use crate::encoder::FrameInvariants;
+use crate::encoder::{FrameInvariants, ReferenceFrame};
 use crate::frame::*;
 use crate::mc::*;
 use crate::partition::*;
 use crate::tiling::*;
 use crate::transform::*;
 use crate::util::*;
+use crate::warp::WarpedMotion;
 
 pub const ANGLE_STEP: i8 = 3;
 
@@ -296,6 +297,64 @@ impl PredictionMode {
     (row_frac, col_frac, rec_plane.slice(qo).clamp().subslice(3, 3))
   }
 
+  // Position of the bottom-right sample of a reference plane, beyond which
+  // the warp filter replicates edge samples.
+  #[inline]
+  fn get_warp_last<T: Pixel>(
+    rec: &ReferenceFrame<T>, rec_plane: &Plane<T>,
+  ) -> (isize, isize) {
+    let &PlaneConfig { xdec, ydec, .. } = &rec_plane.cfg;
+    (
+      ((rec.width as isize + xdec as isize) >> xdec) - 1,
+      ((rec.height as isize + ydec as isize) >> ydec) - 1,
+    )
+  }
+
+  /// Warps used by a block in this mode, which are the global motion warps of
+  /// its references for `GLOBALMV` and `GLOBAL_GLOBALMV` blocks of at least
+  /// 8x8 luma samples.
+  pub fn global_warps<T: Pixel>(
+    self, fi: &FrameInvariants<T>, ref_frames: [RefType; 2], bsize: BlockSize,
+  ) -> [Option<WarpedMotion>; 2] {
+    if !matches!(
+      self,
+      PredictionMode::GLOBALMV | PredictionMode::GLOBAL_GLOBALMV
+    ) || bsize.width().min(bsize.height()) < 8
+    {
+      return [None; 2];
+    }
+    ref_frames.map(|r| {
+      if r == RefType::INTRA_FRAME || r == RefType::NONE_FRAME {
+        None
+      } else {
+        fi.global_warp(r)
+      }
+    })
+  }
+
+  /// Whether blocks in this mode code their interpolation filters, which
+  /// warped blocks and `GLOBALMV` and `GLOBAL_GLOBALMV` blocks of at least
+  /// 8x8 luma samples without a translation do not.
+  pub fn needs_interp_filter<T: Pixel>(
+    self, fi: &FrameInvariants<T>, ref_frames: [RefType; 2], bsize: BlockSize,
+    motion_mode: MotionMode,
+  ) -> bool {
+    let large = bsize.width().min(bsize.height()) >= 8;
+    let translation = |r: RefType| {
+      fi.globalmv_transformation_type[r.to_index()]
+        == GlobalMVMode::TRANSLATION
+    };
+    if motion_mode == MotionMode::WARPED_CAUSAL {
+      false
+    } else if large && self == PredictionMode::GLOBALMV {
+      translation(ref_frames[0])
+    } else if large && self == PredictionMode::GLOBAL_GLOBALMV {
+      translation(ref_frames[0]) || translation(ref_frames[1])
+    } else {
+      true
+    }
+  }
+
   /// Inter prediction with a single reference (i.e. not compound mode)
   ///
   /// # Panics
@@ -305,17 +364,29 @@ impl PredictionMode {
     self, fi: &FrameInvariants<T>, tile_rect: TileRect, p: usize,
     po: PlaneOffset, dst: &mut PlaneRegionMut<'_, T>, width: usize,
     height: usize, ref_frame: RefType, mv: MotionVector,
+    warp: Option<WarpedMotion>, interp_filter: [FilterMode; 2],
   ) {
     assert!(!self.is_intra());
     let frame_po = tile_rect.to_frame_plane_offset(po);
 
-    let mode = fi.default_filter;
-
     if let Some(ref rec) =
       fi.rec_buffer.frames[fi.ref_frames[ref_frame.to_index()] as usize]
     {
+      let rec_plane = &rec.frame.planes[p];
+      if let Some(warp) = warp.filter(|_| width >= 8 && height >= 8) {
+        warp.put_warp(
+          dst,
+          rec_plane,
+          frame_po,
+          PredictionMode::get_warp_last(rec, rec_plane),
+          width,
+          height,
+          fi.sequence.bit_depth,
+        );
+        return;
+      }
       let (row_frac, col_frac, src) =
-        PredictionMode::get_mv_params(&rec.frame.planes[p], frame_po, mv);
+        PredictionMode::get_mv_params(rec_plane, frame_po, mv);
       put_8tap(
         dst,
         src,
@@ -323,8 +394,8 @@ impl PredictionMode {
         height,
         col_frac,
         row_frac,
-        mode,
-        mode,
+        interp_filter[1],
+        interp_filter[0],
         fi.sequence.bit_depth,
         fi.cpu_feature_level,
       );
@@ -340,22 +411,31 @@ impl PredictionMode {
     self, fi: &FrameInvariants<T>, tile_rect: TileRect, p: usize,
     po: PlaneOffset, dst: &mut PlaneRegionMut<'_, T>, width: usize,
     height: usize, ref_frames: [RefType; 2], mvs: [MotionVector; 2],
-    buffer: &mut InterCompoundBuffers,
+    warps: [Option<WarpedMotion>; 2], compound: CompoundParams,
+    interp_filter: [FilterMode; 2], buffer: &mut InterCompoundBuffers,
   ) {
     assert!(!self.is_intra());
     let frame_po = tile_rect.to_frame_plane_offset(po);
 
-    let mode = fi.default_filter;
-
     for i in 0..2 {
       if let Some(ref rec) =
         fi.rec_buffer.frames[fi.ref_frames[ref_frames[i].to_index()] as usize]
       {
-        let (row_frac, col_frac, src) = PredictionMode::get_mv_params(
-          &rec.frame.planes[p],
-          frame_po,
-          mvs[i],
-        );
+        let rec_plane = &rec.frame.planes[p];
+        if let Some(warp) = warps[i].filter(|_| width >= 8 && height >= 8) {
+          warp.prep_warp(
+            buffer.get_buffer_mut(i),
+            rec_plane,
+            frame_po,
+            PredictionMode::get_warp_last(rec, rec_plane),
+            width,
+            height,
+            fi.sequence.bit_depth,
+          );
+          continue;
+        }
+        let (row_frac, col_frac, src) =
+          PredictionMode::get_mv_params(rec_plane, frame_po, mvs[i]);
         prep_8tap(
           buffer.get_buffer_mut(i),
           src,
@@ -363,30 +443,70 @@ impl PredictionMode {
           height,
           col_frac,
           row_frac,
-          mode,
-          mode,
+          interp_filter[1],
+          interp_filter[0],
           fi.sequence.bit_depth,
           fi.cpu_feature_level,
         );
       }
     }
-    mc_avg(
-      dst,
-      buffer.get_buffer(0),
-      buffer.get_buffer(1),
-      width,
-      height,
-      fi.sequence.bit_depth,
-      fi.cpu_feature_level,
-    );
+
+    let bit_depth = fi.sequence.bit_depth;
+    let &PlaneConfig { xdec, ydec, .. } = dst.plane_cfg;
+    let (tmp1, tmp2, mask) = buffer.get_buffers_and_mask();
+    match compound {
+      CompoundParams::Distance => {
+        let weight = distance_weight(ref_frames.map(|r| fi.ref_frame_dist(r)));
+        mc_w_avg(dst, tmp1, tmp2, width, height, weight, bit_depth);
+      }
+      CompoundParams::Wedge { index, sign } => {
+        let bsize =
+          BlockSize::from_width_and_height(width << xdec, height << ydec);
+        wedge_mask(mask, bsize, index as usize, sign);
+        mc_mask(dst, tmp1, tmp2, width, height, mask, xdec, ydec, bit_depth);
+      }
+      CompoundParams::DiffWeighted { inverse } => {
+        // Chroma reuses the mask derived from the luma prediction
+        if p == 0 {
+          diff_weighted_mask(
+            mask, tmp1, tmp2, width, height, inverse, bit_depth,
+          );
+        }
+        mc_mask(dst, tmp1, tmp2, width, height, mask, xdec, ydec, bit_depth);
+      }
+      _ => {
+        mc_avg(
+          dst,
+          tmp1,
+          tmp2,
+          width,
+          height,
+          bit_depth,
+          fi.cpu_feature_level,
+        );
+      }
+    }
   }
 
   /// Inter prediction that determines whether compound mode is being used based
   /// on the second [`RefType`] in [`ref_frames`].
+  ///
+  /// References with a warp in `warps` are predicted with the warp instead of
+  /// their motion vector when the block is at least 8x8, see
+  /// [`PredictionMode::global_warps`].
+  ///
+  /// Compound predictions are combined as set by `compound`. A
+  /// difference-weighted mask is derived when predicting luma, so chroma
+  /// must be predicted after it.
+  ///
+  /// Motion vectors are interpolated with the vertical and horizontal
+  /// filters in `interp_filter`.
   pub fn predict_inter<T: Pixel>(
     self, fi: &FrameInvariants<T>, tile_rect: TileRect, p: usize,
     po: PlaneOffset, dst: &mut PlaneRegionMut<'_, T>, width: usize,
     height: usize, ref_frames: [RefType; 2], mvs: [MotionVector; 2],
+    warps: [Option<WarpedMotion>; 2], compound: CompoundParams,
+    interp_filter: [FilterMode; 2],
     compound_buffer: &mut InterCompoundBuffers,
   ) {
     let is_compound = ref_frames[1] != RefType::INTRA_FRAME
@@ -403,6 +523,8 @@ impl PredictionMode {
         height,
         ref_frames[0],
         mvs[0],
+        warps[0],
+        interp_filter,
       )
     } else {
       self.predict_inter_compound(
@@ -415,17 +537,21 @@ impl PredictionMode {
         height,
         ref_frames,
         mvs,
+        warps,
+        compound,
+        interp_filter,
         compound_buffer,
       );
     }
   }
 }
 
-/// A pair of buffers holding the interpolation of two references. Use for
-/// compound inter prediction.
+/// A pair of buffers holding the interpolation of two references, and the
+/// mask blending them. Use for compound inter prediction.
 #[derive(Debug)]
 pub struct InterCompoundBuffers {
   data: ABox<[i16]>,
+  mask: Box<[u8]>,
 }
 
 impl InterCompoundBuffers {
@@ -443,18 +569,91 @@ impl InterCompoundBuffers {
   }
 
   #[inline]
-  fn get_buffer(&self, i: usize) -> &[i16] {
-    match i {
-      0 => &self.data[0..Self::BUFFER_SIZE],
-      1 => &self.data[Self::BUFFER_SIZE..2 * Self::BUFFER_SIZE],
-      _ => panic!(),
-    }
+  fn get_buffers_and_mask(&mut self) -> (&[i16], &[i16], &mut [u8]) {
+    let (tmp1, tmp2) = self.data.split_at(Self::BUFFER_SIZE);
+    (tmp1, tmp2, &mut self.mask)
   }
 }
 
 impl Default for InterCompoundBuffers {
   fn default() -> Self {
-    Self { data: avec![0; 2 * Self::BUFFER_SIZE].into_boxed_slice() }
+    Self {
+      data: avec![0; 2 * Self::BUFFER_SIZE].into_boxed_slice(),
+      mask: vec![0; Self::BUFFER_SIZE].into_boxed_slice(),
+    }
+  }
+}
+
+/// Blends an intra prediction made with `mode` into the inter prediction of
+/// the `bsize` block at `tile_bo` in `dst`, a plane of the reconstruction.
+/// The intra prediction is weighted with the wedge mask `wedge` or, without
+/// a wedge, with a mask smoothed along `mode`.
+///
+/// # Panics
+///
+/// - If the block is larger than 32x32
+pub fn predict_interintra<T: Pixel>(
+  fi: &FrameInvariants<T>, tile_rect: TileRect,
+  dst: &mut PlaneRegionMut<'_, T>, tile_bo: TileBlockOffset, bsize: BlockSize,
+  mode: InterIntraMode, wedge: Option<u8>,
+) {
+  const MAX_INTERINTRA_AREA: usize = 32 * 32;
+
+  let &PlaneConfig { xdec, ydec, .. } = dst.plane_cfg;
+  let plane_bsize = bsize.subsampled_size(xdec, ydec).unwrap();
+  let (width, height) = (plane_bsize.width(), plane_bsize.height());
+  let po = tile_bo.plane_offset(dst.plane_cfg);
+  let area = Area::BlockStartingAt { bo: tile_bo.0 };
+
+  let mut inter = [T::cast_from(0); MAX_INTERINTRA_AREA];
+  let region = dst.subregion(area);
+  for (r, row) in inter.chunks_exact_mut(width).take(height).enumerate() {
+    row.copy_from_slice(&region[r][..width]);
+  }
+
+  let intra_mode = match mode {
+    InterIntraMode::II_V_PRED => PredictionMode::V_PRED,
+    InterIntraMode::II_H_PRED => PredictionMode::H_PRED,
+    InterIntraMode::II_SMOOTH_PRED => PredictionMode::SMOOTH_PRED,
+    _ => PredictionMode::DC_PRED,
+  };
+  let tx_size = plane_bsize.tx_size();
+  let bit_depth = fi.sequence.bit_depth;
+  let mut edge_buf = Aligned::uninit_array();
+  let edge_buf = get_intra_edges(
+    &mut edge_buf,
+    &dst.as_const(),
+    tile_bo,
+    0,
+    0,
+    bsize,
+    po,
+    tx_size,
+    bit_depth,
+    Some(intra_mode),
+    fi.sequence.enable_intra_edge_filter,
+    IntraParam::None,
+  );
+  let dst = &mut dst.subregion_mut(area);
+  intra_mode.predict_intra(
+    tile_rect,
+    dst,
+    tx_size,
+    bit_depth,
+    &[],
+    IntraParam::None,
+    None,
+    &edge_buf,
+    fi.cpu_feature_level,
+  );
+
+  let mut mask = [0; MAX_INTERINTRA_AREA];
+  if let Some(index) = wedge {
+    wedge_mask(&mut mask, bsize, index as usize, false);
+    blend_interintra(dst, &inter, width, height, &mask, xdec, ydec);
+  } else {
+    interintra_mask(&mut mask, mode, width, height);
+    blend_interintra(dst, &inter, width, height, &mask, 0, 0);
   }
 }
 
@@ -475,6 +674,41 @@ pub enum CompoundType {
   COMPOUND_TYPES,
 }
 
+/// How the predictions of an inter block are combined: the two inter
+/// predictions of a compound block, or the inter and intra predictions of an
+/// inter-intra block.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum CompoundParams {
+  /// Plain average of two predictions, or a lone inter prediction
+  #[default]
+  Average,
+  /// Average weighted by the distance of each reference
+  Distance,
+  /// Wedge mask `index`, with the sides of the wedge swapped by `sign`
+  Wedge { index: u8, sign: bool },
+  /// Mask derived from the difference between the predictions, inverted by
+  /// `inverse`
+  DiffWeighted { inverse: bool },
+  /// Intra prediction with `mode` blended into the inter prediction, with
+  /// the wedge mask `wedge` or a mask smoothed along the mode
+  InterIntra { mode: InterIntraMode, wedge: Option<u8> },
+}
+
+impl CompoundParams {
+  /// Whether two predictions are blended with a mask, signalled by a
+  /// `comp_group_idx` of 1.
+  pub const fn is_masked(self) -> bool {
+    matches!(
+      self,
+      CompoundParams::Wedge { .. } | CompoundParams::DiffWeighted { .. }
+    )
+  }
+
+  pub const fn is_interintra(self) -> bool {
+    matches!(self, CompoundParams::InterIntra { .. })
+  }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd)]
 pub enum MotionMode {
   SIMPLE_TRANSLATION,
@@ -48,7 +48,6 @@ pub fn ac_q(qindex: u8, delta_q: i8, bit_depth: usize) -> NonZeroU16 {
   ac_q[bd][((qindex as isize + delta_q as isize).max(0) as usize).min(255)]
 }
 
-// TODO: Handle lossless properly.
 fn select_qi(quantizer: i64, qlookup: &[NonZeroU16; QINDEX_RANGE]) -> u8 {
   if quantizer < qlookup[MINQ].get() as i64 {
     MINQ as u8
@@ -8,6 +8,8 @@
 // PATENTS file, you can obtain it at www.aomedia.org/license/patent.
 
 use std::cmp;
+use std::collections::{BTreeMap, VecDeque};
+use std::ops::Range;
 
 use crate::api::color::ChromaSampling;
 use crate::api::ContextInner;
@@ -38,12 +40,57 @@ const PASS_2_PLUS_1: i32 = 3;
 // Magic value at the start of the 2-pass stats file
 const TWOPASS_MAGIC: i32 = 0x50324156;
 // Version number for the 2-pass stats file
-const TWOPASS_VERSION: i32 = 1;
+const TWOPASS_VERSION: i32 = 2;
 // 4 byte magic + 4 byte version + 4 byte TU count + 4 byte SEF frame count
 //  + FRAME_NSUBTYPES*(4 byte frame count + 1 byte exp + 8 byte scale_sum)
-pub(crate) const TWOPASS_HEADER_SZ: usize = 16 + FRAME_NSUBTYPES * (4 + 1 + 8);
-// 4 byte frame type (show_frame and fti jointly coded) + 4 byte log_scale_q24
-const TWOPASS_PACKET_SZ: usize = 8;
+// Version 1 summaries stop here.
+const TWOPASS_V1_HEADER_SZ: usize = 16 + FRAME_NSUBTYPES * (4 + 1 + 8);
+// The maximum number of per-frame fields a summary can describe.
+const TWOPASS_MAX_FIELDS: usize = 16;
+// Version 1 summary + 4 byte pass count + 8 byte temporal complexity sum
+//  + 4 byte temporal complexity frame count + 1 byte field count
+//  + TWOPASS_MAX_FIELDS*(1 byte field id + 1 byte field size)
+pub(crate) const TWOPASS_HEADER_SZ: usize =
+  TWOPASS_V1_HEADER_SZ + 4 + 8 + 4 + 1 + TWOPASS_MAX_FIELDS * 2;
+
+// Identifiers of the per-frame fields.
+// The summary lists the fields of the frame packets in order, along with
+//  their size, so fields that are unknown to the reader can be skipped.
+// Frame type (show_frame and fti jointly coded)
+const TWOPASS_FIELD_FRAME_TYPE: u8 = 0;
+// log_scale_q24
+const TWOPASS_FIELD_LOG_SCALE: u8 = 1;
+// Input frame number
+const TWOPASS_FIELD_INPUT_FRAMENO: u8 = 2;
+// The remaining fields are f32 values, see RCFrameStats.
+const TWOPASS_FIELD_INTRA_COST: u8 = 3;
+const TWOPASS_FIELD_INTER_COST: u8 = 4;
+const TWOPASS_FIELD_MOTION: u8 = 5;
+const TWOPASS_FIELD_SCENE_SCORE: u8 = 6;
+const TWOPASS_FIELD_SPATIAL_COMPLEXITY: u8 = 7;
+// The per-frame fields written by this version, with their size in bytes.
+pub(crate) const TWOPASS_FIELDS: [(u8, u8); 8] = [
+  (TWOPASS_FIELD_FRAME_TYPE, 4),
+  (TWOPASS_FIELD_LOG_SCALE, 4),
+  (TWOPASS_FIELD_INPUT_FRAMENO, 4),
+  (TWOPASS_FIELD_INTRA_COST, 4),
+  (TWOPASS_FIELD_INTER_COST, 4),
+  (TWOPASS_FIELD_MOTION, 4),
+  (TWOPASS_FIELD_SCENE_SCORE, 4),
+  (TWOPASS_FIELD_SPATIAL_COMPLEXITY, 4),
+];
+// The per-frame fields of version 1, which did not describe them.
+const TWOPASS_V1_FIELDS: [(u8, u8); 2] =
+  [(TWOPASS_FIELD_FRAME_TYPE, 4), (TWOPASS_FIELD_LOG_SCALE, 4)];
+// The size of the frame packets written by this version.
+const TWOPASS_PACKET_SZ: usize = 4 * TWOPASS_FIELDS.len();
+
+// The weight of the temporal complexity of an inter frame relative to the
+//  average of the first pass in its log quantizer in the second pass, in Q8.
+const TWOPASS_TEMPORAL_WEIGHT_Q8: i64 = 64;
+// The maximum change of log quantizer due to the temporal complexity of a
+//  frame in Q57 format (about 19% of the quantizer).
+const TWOPASS_TEMPORAL_LOG_Q_MAX: i64 = q57(1) >> 2;
 
 const SEF_BITS: i64 = 24;
 
@@ -56,6 +103,24 @@ pub(crate) const QSCALE: i32 = 3;
 // 10 is not an exact choice, but rather a good working trade-off.
 const INTER_DELAY_TARGET_MIN: i32 = 10;
 
+// The initial fullness of the decoder buffer model, as a fraction of its size
+//  in Q8 (about 90%).
+pub(crate) const VBV_INITIAL_FULLNESS_Q8: i64 = 230;
+
+// The time constant, in frames, of the filters that track the average frame
+//  complexity in the constant quality mode.
+const CRF_CPLX_DELAY: i32 = 32;
+
+// The exponent relating the quantizer to the frame complexity in the constant
+//  quality mode, in Q8 (0.4).
+// A frame twice as complex as the average gets a quantizer 2**0.4 times
+//  larger: it gets more bits, but not twice as many.
+const CRF_CPLX_EXP_Q8: i64 = 102;
+
+// The maximum number of times a frame is re-encoded at a coarser quantizer to
+//  avoid underflowing the decoder buffer model.
+pub(crate) const VBV_MAX_REENCODES: usize = 4;
+
 // The base quantizer for a frame is adjusted based on the frame type using the
 //  formula (log_qp*mqp + dqp), where log_qp is the base-2 logarithm of the
 //  "linear" quantizer (the actual factor by which coefficients are divided).
@@ -215,14 +280,89 @@ struct RCFrameMetrics {
   fti: usize,
   // Whether or not the frame was hidden in pass 1
   show_frame: bool,
-  // TODO: The input frame number corresponding to this frame in the input.
-  // input_frameno: u32
+  // The analysis of the frame from pass 1.
+  stats: RCFrameStats,
   // TODO vfr: PTS
 }
 
 impl RCFrameMetrics {
   const fn new() -> RCFrameMetrics {
-    RCFrameMetrics { log_scale_q24: 0, fti: 0, show_frame: false }
+    RCFrameMetrics {
+      log_scale_q24: 0,
+      fti: 0,
+      show_frame: false,
+      stats: RCFrameStats::new(),
+    }
+  }
+}
+
+/// Rate control pass statistics of a frame
+///
+/// They are gathered by the encoding pass emitting the pass data, and can be
+/// read back from its frame packets.
+///
+/// The next pass uses them to allocate its quantizers and to place its
+/// keyframes instead of running the scene change detection: a frame coded as
+/// a keyframe with a scene score of at least 1 starts a new scene again,
+/// within the keyframe intervals of the next pass. Applications may place
+/// further keyframes from the scene scores with [`FrameTypeOverride::Key`].
+///
+/// [`FrameTypeOverride::Key`]: crate::prelude::FrameTypeOverride::Key
+///
+/// The costs are the mean SATD of the 8x8 luma blocks of the frame.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct RCFrameStats {
+  /// The number of the frame in the input.
+  pub input_frameno: u64,
+  /// Whether the frame was coded as a keyframe.
+  pub keyframe: bool,
+  /// Whether the frame was shown.
+  pub show_frame: bool,
+  /// Whether the packet only shows a previously coded frame again.
+  ///
+  /// The frame is not analyzed in that case.
+  pub show_existing_frame: bool,
+  /// The cost of intra predicting the frame.
+  pub intra_cost: f32,
+  /// The cost of predicting the frame from the previous input frame, if any.
+  pub inter_cost: Option<f32>,
+  /// The mean magnitude of the motion vectors against the first reference
+  /// frame, in 1/8 pixel units.
+  pub motion: f32,
+  /// The scene change score of the frame, relative to the detection
+  /// threshold.
+  ///
+  /// Frames scoring at least 1 are candidates for starting a new scene,
+  /// frames which were not analyzed score 0.
+  pub scene_score: f32,
+  /// The mean variance of the 8x8 luma blocks of the frame.
+  pub spatial_complexity: f32,
+}
+
+impl RCFrameStats {
+  const fn new() -> RCFrameStats {
+    RCFrameStats {
+      input_frameno: 0,
+      keyframe: false,
+      show_frame: false,
+      show_existing_frame: false,
+      intra_cost: 0.,
+      inter_cost: None,
+      motion: 0.,
+      scene_score: 0.,
+      spatial_complexity: 0.,
+    }
+  }
+
+  // The log base 2 of the ratio of the inter and intra costs in Q24 format,
+  //  i.e., how hard the frame is to predict from its past.
+  fn log_temporal_ratio_q24(&self) -> Option<i64> {
+    let inter_cost = self.inter_cost?;
+    if self.intra_cost <= 0. {
+      return None;
+    }
+    let ratio = inter_cost.max(1.) as f64 / self.intra_cost.max(1.) as f64;
+    Some((ratio.log2() * (1i64 << 24) as f64).round() as i64)
   }
 }
 
@@ -237,6 +377,35 @@ pub struct RCSummary {
   exp: [u8; FRAME_NSUBTYPES],
   scale_sum: [i64; FRAME_NSUBTYPES],
   pub(crate) total: i32,
+  // The number of passes the statistics went through.
+  passes: i32,
+  // The sum and count of the log temporal ratios of the inter frames in Q24
+  //  format.
+  temporal_log_sum: i64,
+  temporal_nframes: i32,
+  // The id and size of each field of the frame packets.
+  fields: [(u8, u8); TWOPASS_MAX_FIELDS],
+  nfields: usize,
+}
+
+impl RCSummary {
+  /// The number of encoding passes that produced the data
+  ///
+  /// It is 1 for the data of a first pass, 2 for the data emitted by a second
+  /// pass, and so on.
+  pub const fn passes(&self) -> i32 {
+    self.passes
+  }
+
+  // The id and size of each field of the frame packets.
+  pub(crate) fn fields(&self) -> &[(u8, u8)] {
+    &self.fields[..self.nfields]
+  }
+
+  // The size of the frame packets described by the summary.
+  pub(crate) fn packet_size(&self) -> usize {
+    self.fields().iter().map(|&(_, size)| size as usize).sum()
+  }
 }
 
 // Backing storage to deserialize Summary and Per-Frame pass data
@@ -295,31 +464,83 @@ impl RCDeserialize {
     ret
   }
 
-  // Read metrics for the next frame.
-  fn parse_metrics(&mut self) -> Result<RCFrameMetrics, String> {
-    debug_assert!(self.pass2_buffer_fill >= TWOPASS_PACKET_SZ);
-    let ft_val = self.unbuffer_val(4);
-    let show_frame = (ft_val >> 31) != 0;
-    let fti = (ft_val & 0x7FFFFFFF) as usize;
-    // Make sure the frame type is valid.
-    if fti > FRAME_NSUBTYPES {
-      return Err("Invalid frame type".to_string());
-    }
-    let log_scale_q24 = self.unbuffer_val(4) as i32;
-    Ok(RCFrameMetrics { log_scale_q24, fti, show_frame })
+  // Get the size of the summary from its magic value and version number.
+  pub(crate) fn summary_size(&mut self) -> Result<usize, String> {
+    if self.pass2_buffer_fill < 8 {
+      return Err("Summary too short".to_string());
+    }
+    self.pass2_buffer_pos = 0;
+    let magic = self.unbuffer_val(4);
+    let version = self.unbuffer_val(4);
+    self.pass2_buffer_pos = 0;
+    if magic != TWOPASS_MAGIC as i64 {
+      return Err("Magic value mismatch".to_string());
+    }
+    match version {
+      1 => Ok(TWOPASS_V1_HEADER_SZ),
+      2 => Ok(TWOPASS_HEADER_SZ),
+      _ => Err("Version number mismatch".to_string()),
+    }
+  }
+
+  // Read metrics for the next frame, laid out as described by fields.
+  fn parse_metrics(
+    &mut self, fields: &[(u8, u8)],
+  ) -> Result<RCFrameMetrics, String> {
+    let mut m = RCFrameMetrics::new();
+    for &(id, size) in fields {
+      let val = self.unbuffer_val(size as usize);
+      let f32_val = f32::from_bits(val as u32);
+      match id {
+        TWOPASS_FIELD_FRAME_TYPE => {
+          m.show_frame = (val >> 31) != 0;
+          m.fti = (val & 0x7FFFFFFF) as usize;
+          // Make sure the frame type is valid.
+          if m.fti > FRAME_NSUBTYPES {
+            return Err("Invalid frame type".to_string());
+          }
+        }
+        TWOPASS_FIELD_LOG_SCALE => m.log_scale_q24 = val as i32,
+        TWOPASS_FIELD_INPUT_FRAMENO => m.stats.input_frameno = val as u64,
+        TWOPASS_FIELD_INTRA_COST => m.stats.intra_cost = f32_val,
+        TWOPASS_FIELD_INTER_COST => {
+          m.stats.inter_cost = (f32_val >= 0.).then_some(f32_val)
+        }
+        TWOPASS_FIELD_MOTION => m.stats.motion = f32_val,
+        TWOPASS_FIELD_SCENE_SCORE => m.stats.scene_score = f32_val,
+        TWOPASS_FIELD_SPATIAL_COMPLEXITY => {
+          m.stats.spatial_complexity = f32_val
+        }
+        // Skip the fields written by newer versions.
+        _ => {}
+      }
+    }
+    m.stats.keyframe = m.fti == FRAME_SUBTYPE_I;
+    m.stats.show_frame = m.show_frame;
+    m.stats.show_existing_frame = m.fti == FRAME_SUBTYPE_SEF;
+    Ok(m)
+  }
+
+  // Read the statistics of the next frame, laid out as described by fields.
+  pub(crate) fn parse_frame_stats(
+    &mut self, fields: &[(u8, u8)],
+  ) -> Result<RCFrameStats, String> {
+    Ok(self.parse_metrics(fields)?.stats)
   }
 
   // Read the summary header data.
   pub(crate) fn parse_summary(&mut self) -> Result<RCSummary, String> {
     // check the magic value and version number.
-    if self.unbuffer_val(4) != TWOPASS_MAGIC as i64 {
-      return Err("Magic value mismatch".to_string());
-    }
-    if self.unbuffer_val(4) != TWOPASS_VERSION as i64 {
-      return Err("Version number mismatch".to_string());
-    }
-    let mut s =
-      RCSummary { ntus: self.unbuffer_val(4) as i32, ..Default::default() };
+    let size = self.summary_size()?;
+    debug_assert!(self.pass2_buffer_fill >= size);
+    self.pass2_buffer_pos = 8;
+    let mut s = RCSummary {
+      ntus: self.unbuffer_val(4) as i32,
+      passes: 1,
+      nfields: TWOPASS_V1_FIELDS.len(),
+      ..Default::default()
+    };
+    s.fields[..s.nfields].copy_from_slice(&TWOPASS_V1_FIELDS);
 
     // Make sure the file claims to have at least one TU.
     // Otherwise we probably got the placeholder data from an aborted
@@ -357,6 +578,46 @@ impl RCDeserialize {
         return Err("Got negative scale sum".to_string());
       }
     }
+
+    if size > TWOPASS_V1_HEADER_SZ {
+      s.passes = self.unbuffer_val(4) as i32;
+      if s.passes < 1 {
+        return Err("Invalid pass count".to_string());
+      }
+      s.temporal_log_sum = self.unbuffer_val(8);
+      s.temporal_nframes = self.unbuffer_val(4) as i32;
+      if s.temporal_nframes < 0 || s.temporal_nframes > total {
+        return Err("Invalid temporal complexity frame count".to_string());
+      }
+      s.nfields = self.unbuffer_val(1) as usize;
+      if s.nfields > TWOPASS_MAX_FIELDS {
+        return Err("Too many frame fields".to_string());
+      }
+      for field in s.fields.iter_mut() {
+        *field = (self.unbuffer_val(1) as u8, self.unbuffer_val(1) as u8);
+      }
+      for &(id, size) in s.fields() {
+        let known_size = TWOPASS_FIELDS
+          .iter()
+          .find(|&&(known_id, _)| known_id == id)
+          .map(|&(_, known_size)| known_size);
+        if known_size
+          .map_or(!(1..=8).contains(&size), |known_size| size != known_size)
+        {
+          return Err("Invalid frame field size".to_string());
+        }
+      }
+      // The frame packets are read through the same buffer.
+      if s.packet_size() > TWOPASS_HEADER_SZ {
+        return Err("Frame packets too large".to_string());
+      }
+      // The frame packets must carry the fields we can't do without.
+      for required in [TWOPASS_FIELD_FRAME_TYPE, TWOPASS_FIELD_LOG_SCALE] {
+        if !s.fields().iter().any(|&(id, _)| id == required) {
+          return Err("Missing frame field".to_string());
+        }
+      }
+    }
     Ok(s)
   }
 }
@@ -364,6 +625,9 @@ impl RCDeserialize {
 pub struct RCState {
   // The target bit-rate in bits per second.
   target_bitrate: i32,
+  // The nominal frame rate, i.e., the inverse of the time base.
+  framerate_num: i64,
+  framerate_den: i64,
   // The number of TUs over which to distribute the reservoir usage.
   // We use TUs because in our leaky bucket model, we only add bits to the
   //  reservoir on TU boundaries.
@@ -376,6 +640,17 @@ pub struct RCState {
   maybe_ac_qi_max: Option<u8>,
   // The minimum quantizer index to allow (for the luma AC coefficients).
   ac_qi_min: u8,
+  // The quality target of the constant quality mode, as a base quantizer
+  //  index.
+  maybe_crf: Option<u8>,
+  // Second-order lowpass filters tracking the log of the average complexity
+  //  of intra and inter frames in Q24 format, for the constant quality mode.
+  cplxfilter: [IIRBessel2; 2],
+  // The number of intra and inter frames whose complexity was tracked.
+  ncplx_frames: [i64; 2],
+  // The log quantizer offset of the next frame in Q57 format, derived from
+  //  its complexity in the constant quality mode.
+  crf_log_q_offset: i64,
   // Will we drop frames to meet bitrate requirements?
   drop_frames: bool,
   // Do we respect the maximum reservoir fullness?
@@ -393,7 +668,8 @@ pub struct RCState {
   twopass_state: i32,
   // The log of the number of pixels in a frame in Q57 format.
   log_npixels: i64,
-  // The target average bits per Temporal Unit (input frame).
+  // The target average bits per Temporal Unit (input frame), scaled by the
+  //  estimated average frame duration.
   bits_per_tu: i64,
   // The current bit reservoir fullness (bits available to be used).
   reservoir_fullness: i64,
@@ -403,6 +679,19 @@ pub struct RCState {
   reservoir_target: i64,
   // The maximum buffer fullness (total size of the buffer).
   reservoir_max: i64,
+  // The peak bit-rate of the decoder buffer model in bits per second, or 0 if
+  //  the model is disabled.
+  max_bitrate: i32,
+  // The size of the decoder buffer model in bits.
+  vbv_buffer_size: i64,
+  // The fullness of the decoder buffer model in bits, as seen by the decoder
+  //  right before it removes the next Temporal Unit.
+  // Unlike the reservoir, this must never go negative.
+  vbv_fullness: i64,
+  // The maximum size of a single frame in bits imposed by the level, or 0 if
+  //  there is no such limit.
+  // Only enforced along with the decoder buffer model.
+  max_frame_bits: i64,
   // The log of estimated scale factor for the rate model in Q57 format.
   //
   // TODO: Convert to Q23 or figure out a better way to avoid overflow
@@ -412,10 +701,14 @@ pub struct RCState {
   exp: [u8; FRAME_NSUBTYPES],
   // The log of an estimated scale factor used to obtain the real framerate,
   //  for VFR sources or, e.g., 12 fps content doubled to 24 fps, etc.
-  // TODO vfr: log_vfr_scale: i64,
+  // This is the log of the average frame duration in time base units in Q57
+  //  format.
+  log_vfr_scale: i64,
   // Second-order lowpass filters to track scale and VFR.
   scalefilter: [IIRBessel2; FRAME_NSUBTYPES],
-  // TODO vfr: vfrfilter: IIRBessel2,
+  vfrfilter: IIRBessel2,
+  // The number of shown frames, for VFR filter adaptation purposes.
+  nshown_frames: i64,
   // The number of frames of each type we have seen, for filter adaptation
   //  purposes.
   // These are only 32 bits to guarantee that we can sum the scales over the
@@ -446,9 +739,16 @@ pub struct RCState {
   //  frame, and must be set to true by calling twopass_in() before the next
   //  frame can be encoded.
   pass2_data_ready: bool,
-  // TODO: Add a way to force the next frame to be a keyframe in 2-pass mode.
-  // Right now we are relying on keyframe detection to detect the same
-  //  keyframes.
+  // The metrics read ahead of the frames being encoded in pass 2, to place
+  //  the keyframes of the lookahead.
+  pass2_read_ahead: VecDeque<RCFrameMetrics>,
+  // The statistics of the analyzed frames read in pass 2 and not yet used to
+  //  place the keyframes, by input frame number.
+  pass2_frame_stats: BTreeMap<u64, RCFrameStats>,
+  // The input frames whose statistics the keyframe placement waits for.
+  pass2_wanted_frames: Range<u64>,
+  // The number of frame packets read in pass 2.
+  pass2_nframes_read: i32,
   // The metrics for the previous frame.
   prev_metrics: RCFrameMetrics,
   // The metrics for the current frame.
@@ -461,6 +761,19 @@ pub struct RCState {
   frame_metrics_head: usize,
   // Data deserialization
   des: RCDeserialize,
+  // The id and size of each field of the frame packets in pass 2.
+  pass2_fields: Vec<(u8, u8)>,
+  // The size of the frame packets in pass 2.
+  pass2_packet_sz: usize,
+  // The number of passes the pass 2 data went through.
+  pass2_passes: i32,
+  // The average log temporal ratio of the inter frames in pass 1 in Q24
+  //  format, if known.
+  pass2_temporal_log_mean: Option<i64>,
+  // The sum and count of the log temporal ratios of the inter frames
+  //  emitted in pass 1 in Q24 format.
+  temporal_log_sum: i64,
+  temporal_nframes: i32,
   // The TU count encoded so far.
   ntus: i32,
   // The TU count for the whole file.
@@ -578,6 +891,21 @@ impl QuantizerParameters {
       dist_scale,
     }
   }
+
+  /// Sets the base quantizer index, which the model of the quantizers may
+  ///  have moved, keeping the others within reach of its deltas.
+  fn with_base_qi(mut self, base_q_idx: u8) -> QuantizerParameters {
+    let min_qi = base_q_idx.saturating_sub(63).max(1);
+    let max_qi = base_q_idx.saturating_add(63);
+    self.ac_qi[0] = base_q_idx;
+    for qi in self.dc_qi.iter_mut().chain(self.ac_qi[1..].iter_mut()) {
+      // Monochrome streams have no chroma quantizers.
+      if *qi != 0 {
+        *qi = (*qi).clamp(min_qi, max_qi);
+      }
+    }
+    self
+  }
 }
 
 impl RCState {
@@ -585,7 +913,9 @@ impl RCState {
     frame_width: i32, frame_height: i32, framerate_num: i64,
     framerate_den: i64, target_bitrate: i32, maybe_ac_qi_max: Option<u8>,
     ac_qi_min: u8, max_key_frame_interval: i32,
-    maybe_reservoir_frame_delay: Option<i32>,
+    maybe_reservoir_frame_delay: Option<i32>, max_bitrate: i32,
+    vbv_buffer_size: i64, max_frame_bits: i64, maybe_crf: Option<u8>,
+    drop_frames: bool,
   ) -> RCState {
     // The default buffer size is set equal to 1.5x the keyframe interval, or 240
     //  frames; whichever is smaller, with a minimum of 12.
@@ -600,17 +930,8 @@ impl RCState {
       .max(12);
     // TODO: What are the limits on these?
     let npixels = (frame_width as i64) * (frame_height as i64);
-    // Insane framerates or frame sizes mean insane bitrates.
-    // Let's not get carried away.
-    // We also subtract 16 bits from each temporal unit to account for the
-    //  temporal delimiter, whose bits are not included in the frame sizes
-    //  reported to update_state().
-    // TODO: Support constraints imposed by levels.
-    let bits_per_tu = clamp(
-      (target_bitrate as i64) * framerate_den / framerate_num,
-      40,
-      0x4000_0000_0000,
-    ) - (TEMPORAL_DELIMITER.len() * 8) as i64;
+    let bits_per_tu =
+      Self::calc_bits_per_tu(target_bitrate, framerate_num, framerate_den, 0);
     let reservoir_max = bits_per_tu * (reservoir_frame_delay as i64);
     // Start with a buffer fullness and fullness target of 50%.
     let reservoir_target = (reservoir_max + 1) >> 1;
@@ -650,11 +971,20 @@ impl RCState {
     // TODO: Add support for "golden" P frames.
     RCState {
       target_bitrate,
+      framerate_num,
+      framerate_den,
       reservoir_frame_delay,
       reservoir_frame_delay_is_set: maybe_reservoir_frame_delay.is_some(),
       maybe_ac_qi_max,
       ac_qi_min,
-      drop_frames: false,
+      maybe_crf,
+      cplxfilter: [
+        IIRBessel2::new(CRF_CPLX_DELAY, 0),
+        IIRBessel2::new(CRF_CPLX_DELAY, 0),
+      ],
+      ncplx_frames: [0; 2],
+      crf_log_q_offset: 0,
+      drop_frames,
       cap_overflow: true,
       cap_underflow: false,
       pass1_log_base_q: 0,
@@ -664,15 +994,21 @@ impl RCState {
       reservoir_fullness: reservoir_target,
       reservoir_target,
       reservoir_max,
+      max_bitrate,
+      vbv_buffer_size,
+      vbv_fullness: (vbv_buffer_size * VBV_INITIAL_FULLNESS_Q8) >> 8,
+      max_frame_bits,
       log_scale: [i_log_scale, p_log_scale, b0_log_scale, b1_log_scale],
       exp: [i_exp, p_exp, b0_exp, b1_exp],
+      log_vfr_scale: 0,
       scalefilter: [
         IIRBessel2::new(4, q57_to_q24(i_log_scale)),
         IIRBessel2::new(INTER_DELAY_TARGET_MIN, q57_to_q24(p_log_scale)),
         IIRBessel2::new(INTER_DELAY_TARGET_MIN, q57_to_q24(b0_log_scale)),
         IIRBessel2::new(INTER_DELAY_TARGET_MIN, q57_to_q24(b1_log_scale)),
       ],
-      // TODO VFR
+      vfrfilter: IIRBessel2::new(INTER_DELAY_TARGET_MIN, 0),
+      nshown_frames: 0,
       nframes: [0; FRAME_NSUBTYPES + 1],
       inter_delay: [INTER_DELAY_TARGET_MIN; FRAME_NSUBTYPES - 1],
       inter_delay_target: reservoir_frame_delay >> 1,
@@ -683,6 +1019,10 @@ impl RCState {
       pass1_data_retrieved: true,
       pass1_summary_retrieved: false,
       pass2_data_ready: false,
+      pass2_read_ahead: VecDeque::new(),
+      pass2_frame_stats: BTreeMap::new(),
+      pass2_wanted_frames: 0..0,
+      pass2_nframes_read: 0,
       prev_metrics: RCFrameMetrics::new(),
       cur_metrics: RCFrameMetrics::new(),
       frame_metrics: Vec::new(),
@@ -699,6 +1039,12 @@ impl RCState {
       scale_window_nframes: [0; FRAME_NSUBTYPES + 1],
       scale_window_sum: [0; FRAME_NSUBTYPES],
       des: RCDeserialize::default(),
+      pass2_fields: TWOPASS_FIELDS.to_vec(),
+      pass2_packet_sz: TWOPASS_PACKET_SZ,
+      pass2_passes: 0,
+      pass2_temporal_log_mean: None,
+      temporal_log_sum: 0,
+      temporal_nframes: 0,
     }
   }
 
@@ -719,20 +1065,104 @@ impl RCState {
     )
   }
 
-  // TODO: Separate quantizers for Cb and Cr.
+  // Selects the quantizers of a frame, honoring the base quantizer index
+  //  override and offset requested for it, if any.
   #[profiling::function]
   pub(crate) fn select_qi<T: Pixel>(
     &self, ctx: &ContextInner<T>, output_frameno: u64, fti: usize,
-    maybe_prev_log_base_q: Option<i64>, log_isqrt_mean_scale: i64,
+    show_frame: bool, maybe_prev_log_base_q: Option<i64>,
+    log_isqrt_mean_scale: i64, maybe_qi_override: Option<u8>, qi_offset: i16,
+    log_bitrate_factor: i64,
+  ) -> QuantizerParameters {
+    let bit_depth = ctx.config.bit_depth;
+    let chroma_sampling = ctx.config.chroma_sampling;
+    let qps = if let Some(qi) = maybe_qi_override {
+      // The quantizer was chosen by the caller, bypass the rate control and
+      //  the adjustments for the frame type.
+      let log_q = blog64(ac_q(qi, 0, bit_depth).get() as i64)
+        - q57(QSCALE + bit_depth as i32 - 8);
+      QuantizerParameters::new_from_log_q(
+        log_q,
+        log_q,
+        bit_depth,
+        chroma_sampling,
+        true,
+        log_isqrt_mean_scale,
+      )
+      .with_base_qi(qi)
+    } else {
+      self.select_rc_qi(
+        ctx,
+        output_frameno,
+        fti,
+        show_frame,
+        maybe_prev_log_base_q,
+        log_isqrt_mean_scale,
+        log_bitrate_factor,
+      )
+    };
+    if qi_offset == 0 {
+      return qps;
+    }
+    // Move all the quantizers by the ratio between the base quantizer and the
+    //  offset one.
+    let qi = qps.ac_qi[0];
+    let offset_qi = (qi as i16 + qi_offset).clamp(1, 255) as u8;
+    let log_offset = blog64(ac_q(offset_qi, 0, bit_depth).get() as i64)
+      - blog64(ac_q(qi, 0, bit_depth).get() as i64);
+    QuantizerParameters::new_from_log_q(
+      qps.log_base_q + log_offset,
+      qps.log_target_q + log_offset,
+      bit_depth,
+      chroma_sampling,
+      fti == 0,
+      log_isqrt_mean_scale,
+    )
+    .with_base_qi(offset_qi)
+  }
+
+  // TODO: Separate quantizers for Cb and Cr.
+  fn select_rc_qi<T: Pixel>(
+    &self, ctx: &ContextInner<T>, output_frameno: u64, fti: usize,
+    show_frame: bool, maybe_prev_log_base_q: Option<i64>,
+    log_isqrt_mean_scale: i64, log_bitrate_factor: i64,
   ) -> QuantizerParameters {
     // Is rate control active?
     if self.target_bitrate <= 0 {
       // Rate control is not active.
-      // Derive quantizer directly from frame type.
       let bit_depth = ctx.config.bit_depth;
       let chroma_sampling = ctx.config.chroma_sampling;
-      let (log_base_q, log_q) =
-        Self::calc_flat_quantizer(ctx.config.quantizer as u8, bit_depth, fti);
+      let (log_base_q, log_q) = if let Some(crf) = self.maybe_crf {
+        // Start from the quality target, and follow the complexity of the
+        //  frame.
+        let (log_base_q, log_q) =
+          Self::calc_flat_quantizer(crf, bit_depth, fti);
+        let log_factor_q = self.bitrate_factor_log_q(log_bitrate_factor, fti);
+        let (log_base_q, log_q) = self.clamp_log_q(
+          log_base_q + self.crf_log_q_offset - log_factor_q,
+          log_q + self.crf_log_q_offset - log_factor_q,
+          bit_depth,
+          fti,
+        );
+        let log_cur_scale = (self.scalefilter[fti].y[0] as i64) << 33;
+        (
+          log_base_q,
+          self.vbv_clamp_log_q(log_q, log_cur_scale, fti, show_frame),
+        )
+      } else {
+        // Derive quantizer directly from frame type.
+        let (log_base_q, log_q) = Self::calc_flat_quantizer(
+          ctx.config.quantizer as u8,
+          bit_depth,
+          fti,
+        );
+        // Only a level enables the decoder buffer model in this mode.
+        let log_cur_scale = (self.scalefilter[fti].y[0] as i64) << 33;
+        (
+          log_base_q,
+          self.vbv_clamp_log_q(log_q, log_cur_scale, fti, show_frame),
+        )
+      };
       QuantizerParameters::new_from_log_q(
         log_base_q,
         log_q,
@@ -885,7 +1315,6 @@ impl RCState {
             ctx.guess_frame_subtypes(&mut nframes, self.reservoir_frame_delay);
           reservoir_frames = guessed_reservoir_frames;
           reservoir_tus = guessed_reservoir_tus;
-          // TODO: Scale for VFR.
         }
       }
       // If we've been missing our target, add a penalty term.
@@ -962,6 +1391,8 @@ impl RCState {
       let mut log_q = ((log_base_q + (1i64 << 11)) >> 12)
         * (MQP_Q12[fti] as i64)
         + DQP_Q57[fti];
+      // Then by how hard the frame was to predict in the previous pass.
+      log_q += self.pass2_temporal_log_q_offset(fti);
       // The above allocation looks only at the total rate we'll accumulate
       //  in the next reservoir_frame_delay frames.
       // However, we could overflow the bit reservoir on the very next
@@ -1020,18 +1451,17 @@ impl RCState {
         }
       }
 
-      if let Some(qi_max) = self.maybe_ac_qi_max {
-        let (max_log_base_q, max_log_q) =
-          Self::calc_flat_quantizer(qi_max, ctx.config.bit_depth, fti);
-        log_base_q = cmp::min(log_base_q, max_log_base_q);
-        log_q = cmp::min(log_q, max_log_q);
-      }
-      if self.ac_qi_min > 0 {
-        let (min_log_base_q, min_log_q) =
-          Self::calc_flat_quantizer(self.ac_qi_min, ctx.config.bit_depth, fti);
-        log_base_q = cmp::max(log_base_q, min_log_base_q);
-        log_q = cmp::max(log_q, min_log_q);
-      }
+      // Give the frame its share of the zone's bitrate.
+      let log_factor_q = self.bitrate_factor_log_q(log_bitrate_factor, fti);
+      (log_base_q, log_q) = self.clamp_log_q(
+        log_base_q - log_factor_q,
+        log_q - log_factor_q,
+        ctx.config.bit_depth,
+        fti,
+      );
+      // Finally, the decoder buffer model is a hard constraint that overrides
+      //  the quantizer limits.
+      log_q = self.vbv_clamp_log_q(log_q, log_cur_scale, fti, show_frame);
       QuantizerParameters::new_from_log_q(
         log_base_q,
         log_q,
@@ -1043,6 +1473,109 @@ impl RCState {
     }
   }
 
+  // Computes the change of log quantizer (Q57) of an inter frame in the
+  //  second pass from its temporal complexity in the previous pass, relative
+  //  to the average inter frame.
+  // Frames that were cheap to predict from their past get finer quantizers,
+  //  as their quality carries over to the frames predicted from them.
+  // The reservoir absorbs the resulting rate error.
+  fn pass2_temporal_log_q_offset(&self, fti: usize) -> i64 {
+    if (self.twopass_state != PASS_2 && self.twopass_state != PASS_2_PLUS_1)
+      || fti == FRAME_SUBTYPE_I
+      || self.cur_metrics.fti != fti
+    {
+      return 0;
+    }
+    let (Some(log_mean), Some(log_ratio)) = (
+      self.pass2_temporal_log_mean,
+      self.cur_metrics.stats.log_temporal_ratio_q24(),
+    ) else {
+      return 0;
+    };
+    let log_ratio_diff = (log_ratio - log_mean).clamp(-16 << 24, 16 << 24);
+    ((log_ratio_diff * TWOPASS_TEMPORAL_WEIGHT_Q8) << 25)
+      .clamp(-TWOPASS_TEMPORAL_LOG_Q_MAX, TWOPASS_TEMPORAL_LOG_Q_MAX)
+  }
+
+  // Converts a bitrate scale factor (Q57 log) into the change of log
+  //  quantizer (also Q57) that scales the modeled rate of the frame by it.
+  fn bitrate_factor_log_q(&self, log_bitrate_factor: i64, fti: usize) -> i64 {
+    if log_bitrate_factor == 0 {
+      return 0;
+    }
+    let exp = self.exp[fti] as i64;
+    ((log_bitrate_factor + (exp >> 1)) / exp) << 6
+  }
+
+  // Clamps the log quantizers of a frame to the allowed quantizer range.
+  fn clamp_log_q(
+    &self, log_base_q: i64, log_q: i64, bit_depth: usize, fti: usize,
+  ) -> (i64, i64) {
+    let (mut log_base_q, mut log_q) = (log_base_q, log_q);
+    if let Some(qi_max) = self.maybe_ac_qi_max {
+      let (max_log_base_q, max_log_q) =
+        Self::calc_flat_quantizer(qi_max, bit_depth, fti);
+      log_base_q = cmp::min(log_base_q, max_log_base_q);
+      log_q = cmp::min(log_q, max_log_q);
+    }
+    if self.ac_qi_min > 0 {
+      let (min_log_base_q, min_log_q) =
+        Self::calc_flat_quantizer(self.ac_qi_min, bit_depth, fti);
+      log_base_q = cmp::max(log_base_q, min_log_base_q);
+      log_q = cmp::max(log_q, min_log_q);
+    }
+    (log_base_q, log_q)
+  }
+
+  // Raises the log quantizer of a frame if the rate model predicts it would
+  //  underflow the decoder buffer model.
+  fn vbv_clamp_log_q(
+    &self, log_q: i64, log_cur_scale: i64, fti: usize, show_frame: bool,
+  ) -> i64 {
+    let Some(vbv_limit) = self.vbv_frame_limit(show_frame) else {
+      return log_q;
+    };
+    // Leave 1/8 of the available bits for prediction error, since a frame
+    //  that still underflows has to be encoded again.
+    let log_vbv_limit = blog64((vbv_limit - (vbv_limit >> 3)).max(1));
+    let log_scale_pixels = log_cur_scale + self.log_npixels;
+    let exp = self.exp[fti] as i64;
+    let log_q_exp = ((log_q + 32) >> 6) * exp;
+    if log_scale_pixels - log_q_exp > log_vbv_limit {
+      ((log_scale_pixels - log_vbv_limit + (exp >> 1)) / exp) << 6
+    } else {
+      log_q
+    }
+  }
+
+  // Updates the complexity model of the constant quality mode with the
+  //  complexity of the next frame to encode, as estimated by the lookahead.
+  pub(crate) fn update_crf_complexity(&mut self, fti: usize, cplx: f64) {
+    if self.maybe_crf.is_none() {
+      return;
+    }
+    // Intra and inter frame complexities are not comparable, so track them
+    //  separately.
+    let i = usize::from(fti != FRAME_SUBTYPE_I);
+    let log_cplx_q24 = q57_to_q24(blog64(((cplx * 16.) as i64).max(1)));
+    if self.ncplx_frames[i] <= 0 {
+      let f = &mut self.cplxfilter[i];
+      let x = log_cplx_q24;
+      f.x[0] = x;
+      f.x[1] = x;
+      f.y[0] = x;
+      f.y[1] = x;
+    } else {
+      self.cplxfilter[i].update(log_cplx_q24);
+    }
+    self.ncplx_frames[i] += 1;
+    // Quantize frames more or less coarsely than the quality target depending
+    //  on how their complexity compares to the recent average.
+    self.crf_log_q_offset =
+      (q24_to_q57(log_cplx_q24 - self.cplxfilter[i].y[0]) >> 8)
+        * CRF_CPLX_EXP_Q8;
+  }
+
   // Computes a quantizer directly from the frame type and base quantizer index,
   // without consideration for rate control.
   fn calc_flat_quantizer(
@@ -1069,10 +1602,141 @@ impl RCState {
     (log_base_q, log_q)
   }
 
+  // Returns the maximum number of bits the next frame can use without
+  //  underflowing the decoder buffer model or exceeding the frame size limit
+  //  of the level, or None if the model is disabled.
+  // A frame that is not shown shares its Temporal Unit with the next shown
+  //  frame, so it only gets half of the bits that are left.
+  pub(crate) fn vbv_frame_limit(&self, show_frame: bool) -> Option<i64> {
+    if self.max_bitrate <= 0 {
+      return None;
+    }
+    let available = self.vbv_fullness - (TEMPORAL_DELIMITER.len() * 8) as i64;
+    let mut limit = if show_frame { available } else { available >> 1 };
+    if self.max_frame_bits > 0 {
+      limit = limit.min(self.max_frame_bits);
+    }
+    Some(limit.max(0))
+  }
+
+  // Selects a coarser quantizer for a frame that used `bits` bits when coded
+  //  with `qps` and underflowed the decoder buffer model.
+  // `prev` holds the bits and log target quantizer of the previous attempt
+  //  at coding the same frame, if any.
+  // Returns None if the frame fits, or if the quantizer cannot be raised any
+  //  further.
+  pub(crate) fn select_vbv_qi(
+    &self, bits: i64, qps: &QuantizerParameters, prev: Option<(i64, i64)>,
+    fti: usize, show_frame: bool, bit_depth: usize,
+    chroma_sampling: ChromaSampling, log_isqrt_mean_scale: i64,
+  ) -> Option<QuantizerParameters> {
+    let vbv_limit = self.vbv_frame_limit(show_frame)?;
+    if bits <= vbv_limit || fti == FRAME_SUBTYPE_SEF {
+      return None;
+    }
+    let log_bits = blog64(bits);
+    // The exponents of the rate model are tuned for typical content, and
+    //  overestimate how quickly the rate falls for the frames that bust the
+    //  buffer, so we use at most a linear relationship.
+    // Once we have coded the frame twice, we measure the exponent instead.
+    let exp = match prev {
+      Some((prev_bits, prev_log_q)) if qps.log_target_q > prev_log_q => {
+        ((blog64(prev_bits) - log_bits)
+          / ((qps.log_target_q - prev_log_q + 32) >> 6))
+          .clamp(16, 64)
+      }
+      _ => (self.exp[fti] as i64).min(64),
+    };
+    // Predict the quantizer change needed to scale the frame down to 7/8 of
+    //  the limit.
+    let log_vbv_limit = blog64((vbv_limit - (vbv_limit >> 3)).max(1));
+    let log_q_exp =
+      ((qps.log_target_q + 32) >> 6) * exp + log_bits - log_vbv_limit;
+    let log_q = ((log_q_exp + (exp >> 1)) / exp) << 6;
+    let vbv_qps = QuantizerParameters::new_from_log_q(
+      qps.log_base_q,
+      log_q,
+      bit_depth,
+      chroma_sampling,
+      fti == FRAME_SUBTYPE_I,
+      log_isqrt_mean_scale,
+    );
+    if vbv_qps.ac_qi[0] > qps.ac_qi[0] {
+      Some(vbv_qps)
+    } else {
+      None
+    }
+  }
+
+  // Computes the bits the decoder buffer model receives at the peak rate
+  //  during a Temporal Unit lasting `duration` time base units.
+  fn vbv_bits_for_duration(&self, duration: u64) -> i64 {
+    ((self.max_bitrate as i128)
+      * (duration as i128)
+      * (self.framerate_den as i128)
+      / (self.framerate_num as i128)) as i64
+  }
+
+  // Computes the target bits for a Temporal Unit lasting exp2(log_duration)
+  //  time base units, with log_duration in Q57 format.
+  fn calc_bits_per_tu(
+    target_bitrate: i32, framerate_num: i64, framerate_den: i64,
+    log_duration: i64,
+  ) -> i64 {
+    let bits = if log_duration == 0 {
+      (target_bitrate as i64) * framerate_den / framerate_num
+    } else {
+      bexp64(
+        blog64((target_bitrate as i64) * framerate_den)
+          - blog64(framerate_num)
+          + log_duration,
+      )
+    };
+    // Insane framerates or frame sizes mean insane bitrates.
+    // Let's not get carried away.
+    // We also subtract 16 bits from each temporal unit to account for the
+    //  temporal delimiter, whose bits are not included in the frame sizes
+    //  reported to update_state().
+    clamp(bits, 40, 0x4000_0000_0000) - (TEMPORAL_DELIMITER.len() * 8) as i64
+  }
+
+  // Updates the estimate of the average frame duration with the duration of a
+  //  shown frame, and rescales the reservoir accordingly.
+  fn update_vfr_scale(&mut self, duration: u64) {
+    let log_duration_q24 = q57_to_q24(blog64(duration.max(1) as i64));
+    if self.nshown_frames <= 0 {
+      let f = &mut self.vfrfilter;
+      let x = log_duration_q24;
+      f.x[0] = x;
+      f.x[1] = x;
+      f.y[0] = x;
+      f.y[1] = x;
+    } else {
+      self.vfrfilter.update(log_duration_q24);
+    }
+    self.nshown_frames += 1;
+    let log_vfr_scale = q24_to_q57(self.vfrfilter.y[0]);
+    if log_vfr_scale != self.log_vfr_scale {
+      self.log_vfr_scale = log_vfr_scale;
+      self.bits_per_tu = Self::calc_bits_per_tu(
+        self.target_bitrate,
+        self.framerate_num,
+        self.framerate_den,
+        log_vfr_scale,
+      );
+      self.reservoir_max =
+        self.bits_per_tu * (self.reservoir_frame_delay as i64);
+      // Keep the same distance to the target fullness.
+      let reservoir_target = (self.reservoir_max + 1) >> 1;
+      self.reservoir_fullness += reservoir_target - self.reservoir_target;
+      self.reservoir_target = reservoir_target;
+    }
+  }
+
   #[profiling::function]
   pub fn update_state(
-    &mut self, bits: i64, fti: usize, show_frame: bool, log_target_q: i64,
-    trial: bool, droppable: bool,
+    &mut self, bits: i64, fti: usize, show_frame: bool, duration: u64,
+    log_target_q: i64, trial: bool, droppable: bool,
   ) -> bool {
     if trial {
       assert!(self.needs_trial_encode(fti));
@@ -1080,7 +1744,9 @@ impl RCState {
     }
     let mut dropped = false;
     // Update rate control only if rate control is active.
-    if self.target_bitrate > 0 {
+    // The rate model is also needed to enforce the decoder buffer model in
+    //  the constant quality mode, but the reservoir is not.
+    if self.target_bitrate > 0 || self.max_bitrate > 0 {
       let mut estimated_bits = 0;
       let mut bits = bits;
       let mut droppable = droppable;
@@ -1161,6 +1827,7 @@ impl RCState {
         self.prev_metrics.log_scale_q24 = log_scale_q24;
         self.prev_metrics.fti = fti;
         self.prev_metrics.show_frame = show_frame;
+        self.prev_metrics.stats = RCFrameStats::new();
         self.pass1_data_retrieved = false;
       }
       // Common to all passes:
@@ -1197,21 +1864,23 @@ impl RCState {
           // TODO: Adjust VFR rate based on drop count.
           bits = 0;
           dropped = true;
-        } else {
-          // TODO: Update a low-pass filter to estimate the "real" frame rate
-          //  taking timestamps and drops into account.
-          // This is only done if the frame is coded, as it needs the final
-          //  count of dropped frames.
         }
       }
-      if !trial {
-        // Increment the frame count for filter adaptation purposes.
-        if !trial && self.nframes[fti] < i32::MAX {
-          self.nframes[fti] += 1;
-        }
+      // Increment the frame count for filter adaptation purposes.
+      if !trial && self.nframes[fti] < i32::MAX {
+        self.nframes[fti] += 1;
+      }
+      if !trial && self.target_bitrate > 0 {
         self.reservoir_fullness -= bits;
         if show_frame {
-          self.reservoir_fullness += self.bits_per_tu;
+          // Budget the bits by the time that actually elapsed.
+          self.update_vfr_scale(duration);
+          self.reservoir_fullness += Self::calc_bits_per_tu(
+            self.target_bitrate,
+            self.framerate_num,
+            self.framerate_den,
+            blog64(duration.max(1) as i64),
+          );
           // TODO: Properly account for temporal delimiter bits.
         }
         // If we're too quick filling the buffer and overflow is capped, that
@@ -1229,6 +1898,19 @@ impl RCState {
         self.rate_bias += estimated_bits - bits;
       }
     }
+    if !trial && self.max_bitrate > 0 {
+      // The decoder removes each Temporal Unit from its buffer all at once,
+      //  which then refills at the peak rate until the next one is due.
+      if !dropped {
+        self.vbv_fullness -= bits;
+      }
+      if show_frame {
+        self.vbv_fullness = (self.vbv_fullness
+          - (TEMPORAL_DELIMITER.len() * 8) as i64
+          + self.vbv_bits_for_duration(duration))
+        .min(self.vbv_buffer_size);
+      }
+    }
     dropped
   }
 
@@ -1262,7 +1944,9 @@ impl RCState {
     &self, ctx: &ContextInner<T>, output_frameno: u64,
   ) -> i64 {
     assert_eq!(self.twopass_state, PASS_SINGLE);
-    self.select_qi(ctx, output_frameno, FRAME_SUBTYPE_I, None, 0).log_base_q
+    self
+      .select_rc_qi(ctx, output_frameno, FRAME_SUBTYPE_I, true, None, 0, 0)
+      .log_base_q
   }
 
   // Initialize the first pass and emit a placeholder summary
@@ -1279,6 +1963,17 @@ impl RCState {
     self.twopass_state += PASS_1;
   }
 
+  // Whether the pass data of the frames is emitted.
+  pub(crate) const fn emits_pass_data(&self) -> bool {
+    self.twopass_state == PASS_1 || self.twopass_state == PASS_2_PLUS_1
+  }
+
+  // Record the analysis of the last frame passed to update_state(), to be
+  //  emitted along with its metrics.
+  pub(crate) fn set_frame_stats(&mut self, stats: RCFrameStats) {
+    self.prev_metrics.stats = stats;
+  }
+
   // Prepare a placeholder summary
   fn emit_placeholder_summary(&mut self) -> &[u8] {
     // Fill in dummy summary values.
@@ -1301,6 +1996,13 @@ impl RCState {
     if self.prev_metrics.show_frame {
       self.ntus += 1;
     }
+    if fti != FRAME_SUBTYPE_I && fti < FRAME_NSUBTYPES {
+      if let Some(log_ratio) = self.prev_metrics.stats.log_temporal_ratio_q24()
+      {
+        self.temporal_log_sum += log_ratio;
+        self.temporal_nframes += 1;
+      }
+    }
     // If we have encoded too many frames, prevent us from reaching the
     //  ready state required to encode more.
     if self.nencoded_frames + self.nsef_frames >= i32::MAX as i64 {
@@ -1314,6 +2016,18 @@ impl RCState {
     );
     cur_pos =
       self.buffer_val(self.prev_metrics.log_scale_q24 as i64, 4, cur_pos);
+    let stats = self.prev_metrics.stats;
+    cur_pos = self.buffer_val(stats.input_frameno as i64, 4, cur_pos);
+    for val in [
+      stats.intra_cost,
+      // A negative cost marks the lack of a previous frame.
+      stats.inter_cost.unwrap_or(-1.),
+      stats.motion,
+      stats.scene_score,
+      stats.spatial_complexity,
+    ] {
+      cur_pos = self.buffer_val(val.to_bits() as i64, 4, cur_pos);
+    }
     debug_assert!(cur_pos == TWOPASS_PACKET_SZ);
     self.pass1_data_retrieved = true;
     Some(&self.pass1_buffer[..cur_pos])
@@ -1334,6 +2048,15 @@ impl RCState {
     for fti in 0..FRAME_NSUBTYPES {
       cur_pos = self.buffer_val(self.scale_sum[fti], 8, cur_pos);
     }
+    cur_pos = self.buffer_val(self.pass2_passes as i64 + 1, 4, cur_pos);
+    cur_pos = self.buffer_val(self.temporal_log_sum, 8, cur_pos);
+    cur_pos = self.buffer_val(self.temporal_nframes as i64, 4, cur_pos);
+    cur_pos = self.buffer_val(TWOPASS_FIELDS.len() as i64, 1, cur_pos);
+    for i in 0..TWOPASS_MAX_FIELDS {
+      let (id, size) = TWOPASS_FIELDS.get(i).copied().unwrap_or((0, 0));
+      cur_pos = self.buffer_val(id as i64, 1, cur_pos);
+      cur_pos = self.buffer_val(size as i64, 1, cur_pos);
+    }
     debug_assert!(cur_pos == TWOPASS_HEADER_SZ);
     self.pass1_summary_retrieved = true;
     &self.pass1_buffer[..cur_pos]
@@ -1401,6 +2124,11 @@ impl RCState {
       self.reservoir_frame_delay = self.reservoir_frame_delay.min(s.ntus);
     }
     self.exp = s.exp;
+    self.pass2_fields = s.fields().to_vec();
+    self.pass2_packet_sz = s.packet_size();
+    self.pass2_passes = s.passes;
+    self.pass2_temporal_log_mean = (s.temporal_nframes > 0)
+      .then(|| s.temporal_log_sum / s.temporal_nframes as i64);
   }
 
   // Parse the rate control summary
@@ -1408,8 +2136,14 @@ impl RCState {
   // It returns the amount of data consumed in the process or
   // an empty error on parsing failure.
   fn twopass_parse_summary(&mut self, buf: &[u8]) -> Result<usize, String> {
-    let consumed = self.des.buffer_fill(buf, 0, TWOPASS_HEADER_SZ);
-    if self.des.pass2_buffer_fill >= TWOPASS_HEADER_SZ {
+    // The magic value and version number tell the size of the summary.
+    let mut consumed = self.des.buffer_fill(buf, 0, 8);
+    if self.des.pass2_buffer_fill < 8 {
+      return Ok(consumed);
+    }
+    let summary_size = self.des.summary_size()?;
+    consumed = self.des.buffer_fill(buf, consumed, summary_size);
+    if self.des.pass2_buffer_fill >= summary_size {
       self.des.pass2_buffer_pos = 0;
 
       let s = self.des.parse_summary()?;
@@ -1440,12 +2174,28 @@ impl RCState {
       1
     };
 
-    TWOPASS_HEADER_SZ + frames_needed * TWOPASS_PACKET_SZ
+    TWOPASS_HEADER_SZ + frames_needed * self.pass2_packet_sz
   }
 
   // Return the number of frame data packets to be parsed before
   // the encoding process can continue.
   pub(crate) fn twopass_in_frames_needed(&self) -> i32 {
+    if self.target_bitrate <= 0 {
+      return 0;
+    }
+    let read_ahead = self.pass2_read_ahead.len() as i32;
+    let frames_needed = (self.rc_frames_needed() - read_ahead).max(0);
+    // The keyframe placement may need the packets of frames further ahead.
+    let wanted = self.pass2_nframes_read < self.nframes_total_total
+      && self.pass2_wanted_frames.clone().any(|input_frameno| {
+        !self.pass2_frame_stats.contains_key(&input_frameno)
+      });
+    frames_needed.max(i32::from(wanted))
+  }
+
+  // Return the number of frame data packets the rate control itself needs
+  // before the encoding process can continue.
+  fn rc_frames_needed(&self) -> i32 {
     if self.target_bitrate <= 0 {
       return 0;
     }
@@ -1466,24 +2216,46 @@ impl RCState {
   pub(crate) fn parse_frame_data_packet(
     &mut self, buf: &[u8],
   ) -> Result<(), String> {
-    if buf.len() != TWOPASS_PACKET_SZ {
+    if buf.len() != self.pass2_packet_sz {
       return Err("Incorrect buffer size".to_string());
     }
 
-    self.des.buffer_fill(buf, 0, TWOPASS_PACKET_SZ);
+    // Safety check
+    if self.twopass_in_frames_needed() <= 0 {
+      return Err("No frames needed".to_string());
+    }
+
+    self.des.buffer_fill(buf, 0, self.pass2_packet_sz);
     self.des.pass2_buffer_pos = 0;
-    let m = self.des.parse_metrics()?;
+    let m = self.des.parse_metrics(&self.pass2_fields)?;
     self.des.pass2_buffer_fill = 0;
 
-    if self.frame_metrics.is_empty() {
-      // We're using a whole-file buffer.
-      self.cur_metrics = m;
-      self.pass2_data_ready = true;
-    } else {
-      // Safety check
-      let frames_needed = self.twopass_in_frames_needed();
+    self.read_frame_metrics(m)
+  }
 
-      if frames_needed > 0 {
+  // Queue the metrics of the next frame packet, and pass them on to the rate
+  //  control as it needs them.
+  fn read_frame_metrics(&mut self, m: RCFrameMetrics) -> Result<(), String> {
+    if !m.stats.show_existing_frame {
+      self.pass2_frame_stats.insert(m.stats.input_frameno, m.stats);
+    }
+    self.pass2_nframes_read += 1;
+    self.pass2_read_ahead.push_back(m);
+    self.feed_frame_metrics()
+  }
+
+  // Pass the metrics read ahead on to the rate control as it needs them.
+  fn feed_frame_metrics(&mut self) -> Result<(), String> {
+    while self.rc_frames_needed() > 0 {
+      let Some(m) = self.pass2_read_ahead.pop_front() else {
+        return Ok(());
+      };
+      if self.frame_metrics.is_empty() {
+        // We're using a whole-file buffer.
+        self.cur_metrics = m;
+        self.pass2_data_ready = true;
+      } else {
+        // We're using a finite buffer.
         if self.nframe_metrics >= self.frame_metrics.len() {
           return Err(
             "Read too many frames without finding enough TUs".to_string(),
@@ -1504,18 +2276,52 @@ impl RCState {
         if m.show_frame {
           self.scale_window_ntus += 1;
         }
-        if frames_needed == 1 {
-          self.pass2_data_ready = true;
-          self.cur_metrics = self.frame_metrics[self.frame_metrics_head];
-        }
-      } else {
-        return Err("No frames needed".to_string());
       }
     }
+    // If we've got all the frames we need, fill in the current metrics.
+    // We're ready to go.
+    if !self.frame_metrics.is_empty() && !self.pass2_data_ready {
+      self.cur_metrics = self.frame_metrics[self.frame_metrics_head];
+      self.pass2_data_ready = true;
+    }
 
     Ok(())
   }
 
+  // Whether the keyframes are placed from the statistics in the pass data,
+  //  which needs a format recording the input frame numbers.
+  pub(crate) fn places_keyframes(&self) -> bool {
+    (self.twopass_state == PASS_2 || self.twopass_state == PASS_2_PLUS_1)
+      && self.target_bitrate > 0
+      && self.nframes_total[FRAME_SUBTYPE_I] > 0
+      && [TWOPASS_FIELD_INPUT_FRAMENO, TWOPASS_FIELD_SCENE_SCORE]
+        .iter()
+        .all(|&id| self.pass2_fields.iter().any(|&(field, _)| field == id))
+  }
+
+  // Take the statistics of an analyzed input frame from the pass data, if
+  //  read already.
+  //
+  // Returns None if they are not read yet, or Some(None) if the pass data has
+  //  none for the frame.
+  pub(crate) fn take_frame_stats(
+    &mut self, input_frameno: u64,
+  ) -> Option<Option<RCFrameStats>> {
+    if let Some(stats) = self.pass2_frame_stats.remove(&input_frameno) {
+      Some(Some(stats))
+    } else if self.pass2_nframes_read < self.nframes_total_total {
+      None
+    } else {
+      Some(None)
+    }
+  }
+
+  // Request the packets of the pass data up to the statistics of the given
+  //  input frames, for the keyframe placement to proceed.
+  pub(crate) fn wait_for_frame_stats(&mut self, frames: Range<u64>) {
+    self.pass2_wanted_frames = frames;
+  }
+
   // Parse the rate control per-frame data
   //
   // If no buffer is passed return the amount of data it expects
@@ -1526,85 +2332,29 @@ impl RCState {
   fn twopass_parse_frame_data(
     &mut self, maybe_buf: Option<&[u8]>, mut consumed: usize,
   ) -> Result<usize, String> {
-    {
-      if self.frame_metrics.is_empty() {
-        // We're using a whole-file buffer.
-        if let Some(buf) = maybe_buf {
-          consumed = self.des.buffer_fill(buf, consumed, TWOPASS_PACKET_SZ);
-          if self.des.pass2_buffer_fill >= TWOPASS_PACKET_SZ {
-            self.des.pass2_buffer_pos = 0;
-            // Read metrics for the next frame.
-            self.cur_metrics = self.des.parse_metrics()?;
-            // Clear the buffer for the next frame.
-            self.des.pass2_buffer_fill = 0;
-            self.pass2_data_ready = true;
-          }
+    // Use the metrics read ahead first.
+    self.feed_frame_metrics()?;
+    let mut frames_needed = self.twopass_in_frames_needed();
+    while frames_needed > 0 {
+      if let Some(buf) = maybe_buf {
+        consumed = self.des.buffer_fill(buf, consumed, self.pass2_packet_sz);
+        if self.des.pass2_buffer_fill >= self.pass2_packet_sz {
+          self.des.pass2_buffer_pos = 0;
+          // Read the metrics for the next frame.
+          let m = self.des.parse_metrics(&self.pass2_fields)?;
+          // Clear the buffer for the next frame.
+          self.des.pass2_buffer_fill = 0;
+          self.read_frame_metrics(m)?;
+          frames_needed = self.twopass_in_frames_needed();
         } else {
-          return Ok(TWOPASS_PACKET_SZ - self.des.pass2_buffer_fill);
+          // Go back for more data.
+          break;
         }
       } else {
-        // We're using a finite buffer.
-        let mut cur_scale_window_nframes = 0;
-        let mut cur_nframes_left = 0;
-
-        for fti in 0..=FRAME_NSUBTYPES {
-          cur_scale_window_nframes += self.scale_window_nframes[fti];
-          cur_nframes_left += self.nframes_left[fti];
-        }
-
-        let mut frames_needed = self.twopass_in_frames_needed();
-        while frames_needed > 0 {
-          if let Some(buf) = maybe_buf {
-            consumed = self.des.buffer_fill(buf, consumed, TWOPASS_PACKET_SZ);
-            if self.des.pass2_buffer_fill >= TWOPASS_PACKET_SZ {
-              self.des.pass2_buffer_pos = 0;
-              // Read the metrics for the next frame.
-              let m = self.des.parse_metrics()?;
-              // Add them to the circular buffer.
-              if self.nframe_metrics >= self.frame_metrics.len() {
-                return Err(
-                  "Read too many frames without finding enough TUs"
-                    .to_string(),
-                );
-              }
-              let mut fmi = self.frame_metrics_head + self.nframe_metrics;
-              if fmi >= self.frame_metrics.len() {
-                fmi -= self.frame_metrics.len();
-              }
-              self.nframe_metrics += 1;
-              self.frame_metrics[fmi] = m;
-              // And accumulate the statistics over the window.
-              self.scale_window_nframes[m.fti] += 1;
-              cur_scale_window_nframes += 1;
-              if m.fti < FRAME_NSUBTYPES {
-                self.scale_window_sum[m.fti] += bexp_q24(m.log_scale_q24);
-              }
-              if m.show_frame {
-                self.scale_window_ntus += 1;
-              }
-              frames_needed = (self.reservoir_frame_delay
-                - self.scale_window_ntus)
-                .clamp(0, cur_nframes_left - cur_scale_window_nframes);
-              // Clear the buffer for the next frame.
-              self.des.pass2_buffer_fill = 0;
-            } else {
-              // Go back for more data.
-              break;
-            }
-          } else {
-            return Ok(
-              TWOPASS_PACKET_SZ * (frames_needed as usize)
-                - self.des.pass2_buffer_fill,
-            );
-          }
-        }
-        // If we've got all the frames we need, fill in the current metrics.
-        // We're ready to go.
-        if frames_needed <= 0 {
-          self.cur_metrics = self.frame_metrics[self.frame_metrics_head];
-          // Mark us ready for the next frame.
-          self.pass2_data_ready = true;
-        }
+        return Ok(
+          self.pass2_packet_sz * (frames_needed as usize)
+            - self.des.pass2_buffer_fill,
+        );
       }
     }
 
@@ -1638,7 +2388,7 @@ impl RCState {
         // We don't want any more data after the last frame, and we don't want
         //  to allow any more frames to be encoded.
         self.pass2_data_ready = false;
-      } else if !self.pass2_data_ready {
+      } else if !self.pass2_data_ready || self.twopass_in_frames_needed() > 0 {
         return self.twopass_parse_frame_data(maybe_buf, consumed);
       }
     }
@@ -27,23 +27,28 @@ use crate::encode_block_with_modes;
 use crate::encoder::{FrameInvariants, IMPORTANCE_BLOCK_SIZE};
 use crate::frame::*;
 use crate::header::ReferenceMode;
+use crate::intrabc::{intrabc_ref_dv, intrabc_search};
 use crate::lrf::*;
-use crate::mc::MotionVector;
+use crate::mc::{
+  has_wedge, interintra_allowed, FilterMode, MotionVector, WEDGE_TYPES,
+};
 use crate::me::estimate_motion;
 use crate::me::MVSamplingMode;
 use crate::me::MotionSearchResult;
 use crate::motion_compensate;
+use crate::palette::*;
 use crate::partition::PartitionType::*;
 use crate::partition::RefType::*;
 use crate::partition::*;
 use crate::predict::{
-  luma_ac, AngleDelta, IntraEdgeFilterParameters, IntraParam, PredictionMode,
+  luma_ac, AngleDelta, CompoundParams, InterIntraMode,
+  IntraEdgeFilterParameters, IntraParam, MotionMode, PredictionMode,
   RAV1E_INTER_COMPOUND_MODES, RAV1E_INTER_MODES_MINIMAL, RAV1E_INTRA_MODES,
 };
 use crate::rdo_tables::*;
 use crate::tiling::*;
 use crate::transform::{TxSet, TxSize, TxType, RAV1E_TX_TYPES};
-use crate::util::{init_slice_repeat_mut, Aligned, Pixel};
+use crate::util::{init_slice_repeat_mut, Aligned, CastFromPrimitive, Pixel};
 use crate::write_tx_blocks;
 use crate::write_tx_tree;
 use crate::Tune;
@@ -96,11 +101,15 @@ pub struct PartitionParameters {
   pub angle_delta: AngleDelta,
   pub ref_frames: [RefType; 2],
   pub mvs: [MotionVector; 2],
+  pub motion_mode: MotionMode,
+  pub compound: CompoundParams,
+  pub interp_filter: [FilterMode; 2],
   pub skip: bool,
   pub has_coeff: bool,
   pub tx_size: TxSize,
   pub tx_type: TxType,
   pub sidx: u8,
+  pub palette: PaletteParams,
 }
 
 impl Default for PartitionParameters {
@@ -115,11 +124,15 @@ impl Default for PartitionParameters {
       angle_delta: AngleDelta::default(),
       ref_frames: [RefType::INTRA_FRAME, RefType::NONE_FRAME],
       mvs: [MotionVector::default(); 2],
+      motion_mode: MotionMode::SIMPLE_TRANSLATION,
+      compound: CompoundParams::Average,
+      interp_filter: [FilterMode::REGULAR; 2],
       skip: false,
       has_coeff: true,
       tx_size: TxSize::TX_4X4,
       tx_type: TxType::DCT_DCT,
       sidx: 0,
+      palette: PaletteParams::default(),
     }
   }
 }
@@ -464,12 +477,15 @@ pub fn distortion_scale<T: Pixel>(
 pub fn spatiotemporal_scale<T: Pixel>(
   fi: &FrameInvariants<T>, frame_bo: PlaneBlockOffset, bsize: BlockSize,
 ) -> DistortionScale {
-  if !fi.config.temporal_rdo() && fi.config.tune != Tune::Psychovisual {
+  let coded_data = fi.coded_frame_data.as_ref().unwrap();
+
+  if !fi.config.temporal_rdo()
+    && fi.config.tune != Tune::Psychovisual
+    && !coded_data.has_importance_map
+  {
     return DistortionScale::default();
   }
 
-  let coded_data = fi.coded_frame_data.as_ref().unwrap();
-
   let x0 = frame_bo.0.x >> IMPORTANCE_BLOCK_TO_BLOCK_SHIFT;
   let y0 = frame_bo.0.y >> IMPORTANCE_BLOCK_TO_BLOCK_SHIFT;
   let x1 = (x0 + bsize.width_imp_b()).min(coded_data.w_in_imp_b);
@@ -726,12 +742,20 @@ pub fn rdo_tx_size_type<T: Pixel>(
   fi: &FrameInvariants<T>, ts: &mut TileStateMut<'_, T>,
   cw: &mut ContextWriter, bsize: BlockSize, tile_bo: TileBlockOffset,
   luma_mode: PredictionMode, ref_frames: [RefType; 2], mvs: [MotionVector; 2],
-  skip: bool,
+  motion_mode: MotionMode, compound: CompoundParams,
+  interp_filter: [FilterMode; 2], skip: bool,
 ) -> (TxSize, TxType) {
+  // Lossless frames only use 4x4 transforms, without signaling the type.
+  if fi.coded_lossless() {
+    return (TxSize::TX_4X4, TxType::DCT_DCT);
+  }
+
   let is_inter = !luma_mode.is_intra();
   let mut tx_size = max_txsize_rect_lookup[bsize as usize];
 
-  if fi.enable_inter_txfm_split && is_inter && !skip {
+  // Var-tx is only signaled with TX_MODE_SELECT, which IntraBC frames do
+  // not use.
+  if fi.enable_inter_txfm_split && fi.tx_mode_select && is_inter && !skip {
     tx_size = sub_tx_size_map[tx_size as usize]; // Always choose one level split size
   }
 
@@ -769,6 +793,9 @@ pub fn rdo_tx_size_type<T: Pixel>(
       luma_mode,
       ref_frames,
       mvs,
+      motion_mode,
+      compound,
+      interp_filter,
       bsize,
       tile_bo,
       tx_size,
@@ -818,10 +845,11 @@ fn luma_chroma_mode_rdo<T: Pixel>(
   tile_bo: TileBlockOffset, ts: &mut TileStateMut<'_, T>,
   cw: &mut ContextWriter, rdo_type: RDOType,
   cw_checkpoint: &ContextWriterCheckpoint, best: &mut PartitionParameters,
-  mvs: [MotionVector; 2], ref_frames: [RefType; 2],
+  mvs: [MotionVector; 2], ref_frames: [RefType; 2], motion_mode: MotionMode,
+  compound: CompoundParams, interp_filter: [FilterMode; 2],
   mode_set_chroma: &[PredictionMode], luma_mode_is_intra: bool,
   mode_context: usize, mv_stack: &ArrayVec<CandidateMV, 9>,
-  angle_delta: AngleDelta,
+  angle_delta: AngleDelta, palette: &PaletteParams,
 ) {
   let PlaneConfig { xdec, ydec, .. } = ts.input.planes[1].cfg;
 
@@ -829,8 +857,16 @@ fn luma_chroma_mode_rdo<T: Pixel>(
     has_chroma(tile_bo, bsize, xdec, ydec, fi.sequence.chroma_sampling);
 
   if !luma_mode_is_intra {
-    let ref_mvs = if mv_stack.is_empty() {
-      [MotionVector::default(); 2]
+    let ref_mvs = if ref_frames[0] == INTRA_FRAME {
+      let sb_size = if fi.sequence.use_128x128_superblock {
+        BlockSize::BLOCK_128X128
+      } else {
+        BlockSize::BLOCK_64X64
+      };
+      [intrabc_ref_dv(mv_stack, tile_bo, sb_size), MotionVector::default()]
+    } else if mv_stack.is_empty() {
+      let frame_bo = ts.to_frame_block_offset(tile_bo);
+      ref_frames.map(|rf| fi.global_mv(rf, frame_bo, bsize))
     } else {
       [mv_stack[0].this_mv, mv_stack[0].comp_mv]
     };
@@ -861,7 +897,18 @@ fn luma_chroma_mode_rdo<T: Pixel>(
       cw.bc.blocks.set_segmentation_idx(tile_bo, bsize, sidx);
 
       let (tx_size, tx_type) = rdo_tx_size_type(
-        fi, ts, cw, bsize, tile_bo, luma_mode, ref_frames, mvs, skip,
+        fi,
+        ts,
+        cw,
+        bsize,
+        tile_bo,
+        luma_mode,
+        ref_frames,
+        mvs,
+        motion_mode,
+        compound,
+        interp_filter,
+        skip,
       );
       for &chroma_mode in mode_set_chroma.iter() {
         let wr = &mut WriterCounter::new();
@@ -880,7 +927,7 @@ fn luma_chroma_mode_rdo<T: Pixel>(
         let need_recon_pixel =
           luma_mode_is_intra && tx_size.block_size() != bsize;
 
-        encode_block_pre_cdef(&fi.sequence, ts, cw, wr, bsize, tile_bo, skip);
+        encode_block_pre_cdef(fi, ts, cw, wr, bsize, tile_bo, skip);
         let (has_coeff, tx_dist) = encode_block_post_cdef(
           fi,
           ts,
@@ -891,10 +938,14 @@ fn luma_chroma_mode_rdo<T: Pixel>(
           angle_delta,
           ref_frames,
           mvs,
+          motion_mode,
+          compound,
+          interp_filter,
           bsize,
           tile_bo,
           skip,
           CFLParams::default(),
+          palette,
           tx_size,
           tx_type,
           mode_context,
@@ -929,11 +980,15 @@ fn luma_chroma_mode_rdo<T: Pixel>(
           best.angle_delta = angle_delta;
           best.ref_frames = ref_frames;
           best.mvs = mvs;
+          best.motion_mode = motion_mode;
+          best.compound = compound;
+          best.interp_filter = interp_filter;
           best.skip = skip;
           best.has_coeff = has_coeff;
           best.tx_size = tx_size;
           best.tx_type = tx_type;
           best.sidx = sidx;
+          best.palette = *palette;
           zero_distortion = is_zero_dist;
         }
 
@@ -944,9 +999,13 @@ fn luma_chroma_mode_rdo<T: Pixel>(
     zero_distortion
   };
 
-  // Don't skip when using intra modes
-  let zero_distortion =
-    if !luma_mode_is_intra { chroma_rdo(true) } else { false };
+  // Don't skip when using intra modes, nor in lossless frames where the
+  // residual must always be coded
+  let zero_distortion = if !luma_mode_is_intra && !fi.coded_lossless() {
+    chroma_rdo(true)
+  } else {
+    false
+  };
   // early skip
   if !zero_distortion {
     chroma_rdo(false);
@@ -1010,7 +1069,8 @@ pub fn rdo_mode_decision<T: Pixel>(
     );
   }
 
-  if best.pred_mode_luma.is_intra() && is_chroma_block && bsize.cfl_allowed() {
+  if best.pred_mode_luma.is_intra() && is_chroma_block && fi.cfl_allowed(bsize)
+  {
     cw.bc.blocks.set_segmentation_idx(tile_bo, bsize, best.sidx);
 
     let chroma_mode = PredictionMode::UV_CFL_PRED;
@@ -1032,6 +1092,7 @@ pub fn rdo_mode_decision<T: Pixel>(
       best.tx_type,
       false,
       CFLParams::default(),
+      &best.palette,
       true,
       rdo_type,
       true,
@@ -1042,15 +1103,7 @@ pub fn rdo_mode_decision<T: Pixel>(
         let mut wr = WriterCounter::new();
         let tell = wr.tell_frac();
 
-        encode_block_pre_cdef(
-          &fi.sequence,
-          ts,
-          cw,
-          &mut wr,
-          bsize,
-          tile_bo,
-          best.skip,
-        );
+        encode_block_pre_cdef(fi, ts, cw, &mut wr, bsize, tile_bo, best.skip);
         let (has_coeff, _) = encode_block_post_cdef(
           fi,
           ts,
@@ -1061,10 +1114,14 @@ pub fn rdo_mode_decision<T: Pixel>(
           angle_delta,
           best.ref_frames,
           best.mvs,
+          best.motion_mode,
+          best.compound,
+          best.interp_filter,
           bsize,
           tile_bo,
           best.skip,
           cfl,
+          &best.palette.luma_only(),
           best.tx_size,
           best.tx_type,
           0,
@@ -1086,6 +1143,7 @@ pub fn rdo_mode_decision<T: Pixel>(
           best.angle_delta = angle_delta;
           best.has_coeff = has_coeff;
           best.pred_cfl_params = cfl;
+          best.palette = best.palette.luma_only();
         }
 
         cw.rollback(&cw_checkpoint);
@@ -1108,12 +1166,16 @@ pub fn rdo_mode_decision<T: Pixel>(
     angle_delta: best.angle_delta,
     ref_frames: best.ref_frames,
     mvs: best.mvs,
+    motion_mode: best.motion_mode,
+    compound: best.compound,
+    interp_filter: best.interp_filter,
     rd_cost: best.rd_cost,
     skip: best.skip,
     has_coeff: best.has_coeff,
     tx_size: best.tx_size,
     tx_type: best.tx_type,
     sidx: best.sidx,
+    palette: best.palette,
   }
 }
 
@@ -1160,6 +1222,8 @@ fn inter_frame_rdo_mode_decision<T: Pixel>(
   let mut satds = ArrayVec::<u32, 20>::new();
   let mut mv_stacks = ArrayVec::<_, 20>::new();
   let mut mode_contexts = ArrayVec::<_, 7>::new();
+  let mut global_mvs = ArrayVec::<[MotionVector; 2], 7>::new();
+  let frame_bo = ts.to_frame_block_offset(tile_bo);
 
   for (i, &ref_frames) in ref_frames_set.iter().enumerate() {
     let mut mv_stack = ArrayVec::<CandidateMV, 9>::new();
@@ -1172,7 +1236,10 @@ fn inter_frame_rdo_mode_decision<T: Pixel>(
       false,
     ));
 
-    let mut pmv = [MotionVector::default(); 2];
+    let global_mv = fi.global_mv(ref_frames[0], frame_bo, bsize);
+    global_mvs.push([global_mv, MotionVector::default()]);
+
+    let mut pmv = [global_mv; 2];
     if !mv_stack.is_empty() {
       pmv[0] = mv_stack[0].this_mv;
     }
@@ -1204,7 +1271,10 @@ fn inter_frame_rdo_mode_decision<T: Pixel>(
     if !mv_stack.is_empty() {
       inter_mode_set.push((PredictionMode::NEAR0MV, i));
     }
-    if mv_stack.len() >= 2 {
+    let has_global_motion = fi.globalmv_transformation_type
+      [ref_frames[0].to_index()]
+      != GlobalMVMode::IDENTITY;
+    if mv_stack.len() >= 2 || has_global_motion {
       inter_mode_set.push((PredictionMode::GLOBALMV, i));
     }
     let include_near_mvs = fi.config.speed_settings.motion.include_near_mvs;
@@ -1224,7 +1294,8 @@ fn inter_frame_rdo_mode_decision<T: Pixel>(
       .iter()
       .take(if include_near_mvs { 4 } else { 2 })
       .any(same_row_col)
-      && (mvs_from_me[i][0].row != 0 || mvs_from_me[i][0].col != 0)
+      && (mvs_from_me[i][0] != global_mv
+        || fi.global_warp(ref_frames[0]).is_some())
     {
       inter_mode_set.push((PredictionMode::NEWMV, i));
     }
@@ -1244,6 +1315,7 @@ fn inter_frame_rdo_mode_decision<T: Pixel>(
         let mv0 = mvs_from_me[r0][0];
         let mv1 = mvs_from_me[r1][0];
         mvs_from_me.push([mv0, mv1]);
+        global_mvs.push([global_mvs[r0][0], global_mvs[r1][0]]);
         let mut mv_stack = ArrayVec::<CandidateMV, 9>::new();
         mode_contexts.push(cw.find_mvrefs(
           tile_bo,
@@ -1285,14 +1357,14 @@ fn inter_frame_rdo_mode_decision<T: Pixel>(
         if !mv_stacks[i].is_empty() {
           [mv_stacks[i][0].this_mv, mv_stacks[i][0].comp_mv]
         } else {
-          [MotionVector::default(); 2]
+          global_mvs[i]
         }
       }
       PredictionMode::NEAR0MV | PredictionMode::NEAR_NEAR0MV => {
         if mv_stacks[i].len() > 1 {
           [mv_stacks[i][1].this_mv, mv_stacks[i][1].comp_mv]
         } else {
-          [MotionVector::default(); 2]
+          global_mvs[i]
         }
       }
       PredictionMode::NEAR1MV
@@ -1309,7 +1381,7 @@ fn inter_frame_rdo_mode_decision<T: Pixel>(
         [mvs_from_me[i][0], mv_stacks[i][0].comp_mv]
       }
       PredictionMode::GLOBALMV | PredictionMode::GLOBAL_GLOBALMV => {
-        [MotionVector::default(); 2]
+        global_mvs[i]
       }
       _ => {
         unimplemented!();
@@ -1335,6 +1407,9 @@ fn inter_frame_rdo_mode_decision<T: Pixel>(
         bsize.height(),
         ref_frames_set[i],
         mvs,
+        luma_mode.global_warps(fi, ref_frames_set[i], bsize),
+        CompoundParams::Average,
+        [FilterMode::REGULAR; 2],
         &mut ts.inter_compound_buffers,
       );
 
@@ -1362,9 +1437,11 @@ fn inter_frame_rdo_mode_decision<T: Pixel>(
     sorted.sort_by_key(|((_mode, _i), _mvs, satd)| *satd);
   }
 
+  let mut best_candidate = None;
   sorted.iter().take(num_modes_rdo).for_each(
     |&((luma_mode, i), mvs, _satd)| {
       let mode_set_chroma = ArrayVec::from([luma_mode]);
+      let best_rd = best.rd_cost;
 
       luma_chroma_mode_rdo(
         luma_mode,
@@ -1378,18 +1455,271 @@ fn inter_frame_rdo_mode_decision<T: Pixel>(
         &mut best,
         mvs,
         ref_frames_set[i],
+        MotionMode::SIMPLE_TRANSLATION,
+        CompoundParams::Average,
+        [FilterMode::REGULAR; 2],
         &mode_set_chroma,
         false,
         mode_contexts[i],
         &mv_stacks[i],
         AngleDelta::default(),
+        &PaletteParams::default(),
       );
+
+      if best.rd_cost < best_rd {
+        best_candidate = Some((luma_mode, i, mvs));
+      }
     },
   );
 
+  // Try overlapped and locally warped prediction for the best candidate
+  if let Some((luma_mode, i, mvs)) = best_candidate {
+    let ref_frames = ref_frames_set[i];
+    if cw.is_motion_mode_switchable(
+      fi,
+      tile_bo,
+      bsize,
+      luma_mode,
+      ref_frames,
+      CompoundParams::Average,
+    ) {
+      let mut motion_modes = ArrayVec::<_, 2>::new();
+      motion_modes.push(MotionMode::OBMC_CAUSAL);
+      if cw.local_warp(fi, tile_bo, bsize, ref_frames[0], mvs[0]).is_some() {
+        motion_modes.push(MotionMode::WARPED_CAUSAL);
+      }
+      for motion_mode in motion_modes {
+        luma_chroma_mode_rdo(
+          luma_mode,
+          fi,
+          bsize,
+          tile_bo,
+          ts,
+          cw,
+          rdo_type,
+          cw_checkpoint,
+          &mut best,
+          mvs,
+          ref_frames,
+          motion_mode,
+          CompoundParams::Average,
+          [FilterMode::REGULAR; 2],
+          &[luma_mode],
+          false,
+          mode_contexts[i],
+          &mv_stacks[i],
+          AngleDelta::default(),
+          &PaletteParams::default(),
+        );
+      }
+    }
+
+    // Then other ways of combining its predictions
+    let compounds = compound_candidates(
+      fi, ts, cw, bsize, tile_bo, luma_mode, ref_frames, mvs,
+    );
+    for compound in compounds {
+      luma_chroma_mode_rdo(
+        luma_mode,
+        fi,
+        bsize,
+        tile_bo,
+        ts,
+        cw,
+        rdo_type,
+        cw_checkpoint,
+        &mut best,
+        mvs,
+        ref_frames,
+        MotionMode::SIMPLE_TRANSLATION,
+        compound,
+        [FilterMode::REGULAR; 2],
+        &[luma_mode],
+        false,
+        mode_contexts[i],
+        &mv_stacks[i],
+        AngleDelta::default(),
+        &PaletteParams::default(),
+      );
+    }
+
+    // Finally the interpolation filters of the best prediction
+    let (motion_mode, compound) = (best.motion_mode, best.compound);
+    if let Some(interp_filter) = interp_filter_candidate(
+      fi,
+      ts,
+      cw,
+      bsize,
+      tile_bo,
+      luma_mode,
+      ref_frames,
+      mvs,
+      motion_mode,
+      compound,
+    ) {
+      luma_chroma_mode_rdo(
+        luma_mode,
+        fi,
+        bsize,
+        tile_bo,
+        ts,
+        cw,
+        rdo_type,
+        cw_checkpoint,
+        &mut best,
+        mvs,
+        ref_frames,
+        motion_mode,
+        compound,
+        interp_filter,
+        &[luma_mode],
+        false,
+        mode_contexts[i],
+        &mv_stacks[i],
+        AngleDelta::default(),
+        &PaletteParams::default(),
+      );
+    }
+  }
+
   best
 }
 
+/// Screens the masked and distance-weighted combinations of the predictions
+/// of a compound block, or the inter-intra predictions of a single reference
+/// block, by the SATD of their luma. Returns the best of each kind allowed by
+/// the sequence header, to be evaluated by full RDO.
+fn compound_candidates<T: Pixel>(
+  fi: &FrameInvariants<T>, ts: &mut TileStateMut<'_, T>,
+  cw: &mut ContextWriter, bsize: BlockSize, tile_bo: TileBlockOffset,
+  luma_mode: PredictionMode, ref_frames: [RefType; 2], mvs: [MotionVector; 2],
+) -> ArrayVec<CompoundParams, 3> {
+  let mut satd = |compound: CompoundParams| {
+    motion_compensate(
+      fi,
+      ts,
+      cw,
+      luma_mode,
+      ref_frames,
+      mvs,
+      MotionMode::SIMPLE_TRANSLATION,
+      compound,
+      [FilterMode::REGULAR; 2],
+      bsize,
+      tile_bo,
+      true,
+    );
+    let area = Area::BlockStartingAt { bo: tile_bo.0 };
+    get_satd(
+      &ts.input_tile.planes[0].subregion(area),
+      &ts.rec.planes[0].subregion(area),
+      bsize.width(),
+      bsize.height(),
+      fi.sequence.bit_depth,
+      fi.cpu_feature_level,
+    )
+  };
+
+  let mut candidates = ArrayVec::new();
+  if ref_frames[1] != NONE_FRAME {
+    if fi.sequence.enable_jnt_comp {
+      candidates.push(CompoundParams::Distance);
+    }
+    if fi.sequence.enable_masked_compound {
+      if has_wedge(bsize) {
+        let wedges = (0..WEDGE_TYPES as u8).flat_map(|index| {
+          [false, true].map(|sign| CompoundParams::Wedge { index, sign })
+        });
+        candidates.extend(wedges.min_by_key(|&c| satd(c)));
+      }
+      let diff_weighted =
+        [false, true].map(|inverse| CompoundParams::DiffWeighted { inverse });
+      candidates.extend(diff_weighted.into_iter().min_by_key(|&c| satd(c)));
+    }
+  } else if fi.sequence.enable_interintra_compound && interintra_allowed(bsize)
+  {
+    use InterIntraMode::*;
+    let smooth = [II_DC_PRED, II_V_PRED, II_H_PRED, II_SMOOTH_PRED]
+      .map(|mode| CompoundParams::InterIntra { mode, wedge: None })
+      .into_iter()
+      .min_by_key(|&c| satd(c));
+    // Wedges are only tried with the best intra mode
+    if let Some(CompoundParams::InterIntra { mode, .. }) = smooth {
+      let wedges = (0..WEDGE_TYPES as u8)
+        .map(|index| CompoundParams::InterIntra { mode, wedge: Some(index) });
+      candidates.extend(smooth);
+      candidates.extend(wedges.min_by_key(|&c| satd(c)));
+    }
+  }
+  candidates
+}
+
+/// Screens the interpolation filters of an inter block by the SATD of its
+/// luma, searching the pairs of vertical and horizontal filters allowed by
+/// the speed settings. Returns the best pair if it is not the regular filter,
+/// to be evaluated by full RDO.
+fn interp_filter_candidate<T: Pixel>(
+  fi: &FrameInvariants<T>, ts: &mut TileStateMut<'_, T>,
+  cw: &mut ContextWriter, bsize: BlockSize, tile_bo: TileBlockOffset,
+  luma_mode: PredictionMode, ref_frames: [RefType; 2], mvs: [MotionVector; 2],
+  motion_mode: MotionMode, compound: CompoundParams,
+) -> Option<[FilterMode; 2]> {
+  use FilterMode::*;
+  const FILTERS: [FilterMode; 3] = [REGULAR, SMOOTH, SHARP];
+
+  let refs = if ref_frames[1] != NONE_FRAME { 2 } else { 1 };
+  // Filters make no difference to the luma of whole sample motion vectors
+  if !fi.is_filter_switchable
+    || !luma_mode.needs_interp_filter(fi, ref_frames, bsize, motion_mode)
+    || mvs[..refs].iter().all(|mv| mv.row & 7 == 0 && mv.col & 7 == 0)
+  {
+    return None;
+  }
+
+  let mut satd = |interp_filter: [FilterMode; 2]| {
+    motion_compensate(
+      fi,
+      ts,
+      cw,
+      luma_mode,
+      ref_frames,
+      mvs,
+      motion_mode,
+      compound,
+      interp_filter,
+      bsize,
+      tile_bo,
+      true,
+    );
+    let area = Area::BlockStartingAt { bo: tile_bo.0 };
+    get_satd(
+      &ts.input_tile.planes[0].subregion(area),
+      &ts.rec.planes[0].subregion(area),
+      bsize.width(),
+      bsize.height(),
+      fi.sequence.bit_depth,
+      fi.cpu_feature_level,
+    )
+  };
+
+  let best = match fi.config.speed_settings.motion.interp_filter_search {
+    InterpFilterSearch::Off => return None,
+    InterpFilterSearch::Joint => {
+      FILTERS.map(|f| [f; 2]).into_iter().min_by_key(|&f| satd(f))
+    }
+    InterpFilterSearch::Separable => {
+      // The horizontal filter first, then the vertical filter with it
+      let x = FILTERS.into_iter().min_by_key(|&x| satd([REGULAR, x]))?;
+      FILTERS.map(|y| [y, x]).into_iter().min_by_key(|&f| satd(f))
+    }
+    InterpFilterSearch::Full => FILTERS
+      .into_iter()
+      .flat_map(|y| FILTERS.map(|x| [y, x]))
+      .min_by_key(|&f| satd(f)),
+  };
+  best.filter(|&f| f != [REGULAR; 2])
+}
+
 #[profiling::function]
 fn intra_frame_rdo_mode_decision<T: Pixel>(
   fi: &FrameInvariants<T>, ts: &mut TileStateMut<'_, T>,
@@ -1527,11 +1857,15 @@ fn intra_frame_rdo_mode_decision<T: Pixel>(
       &mut best,
       mvs,
       ref_frames,
+      MotionMode::SIMPLE_TRANSLATION,
+      CompoundParams::Average,
+      [FilterMode::REGULAR; 2],
       &mode_set_chroma,
       true,
       0,
       &ArrayVec::<CandidateMV, 9>::new(),
       AngleDelta::default(),
+      &PaletteParams::default(),
     );
   });
 
@@ -1561,11 +1895,15 @@ fn intra_frame_rdo_mode_decision<T: Pixel>(
           &mut best,
           mvs,
           ref_frames,
+          MotionMode::SIMPLE_TRANSLATION,
+          CompoundParams::Average,
+          [FilterMode::REGULAR; 2],
           &mode_set_chroma,
           true,
           0,
           &mv_stack,
           AngleDelta { y, uv },
+          &PaletteParams::default(),
         );
       }
       best.angle_delta
@@ -1583,6 +1921,104 @@ fn intra_frame_rdo_mode_decision<T: Pixel>(
     }
   }
 
+  if palette_allowed(fi, bsize) {
+    let mvs = [MotionVector::default(); 2];
+    let ref_frames = [INTRA_FRAME, NONE_FRAME];
+    let mv_stack = ArrayVec::<_, 9>::new();
+    for palette in luma_palette_candidates(fi, ts, tile_bo, bsize) {
+      luma_chroma_mode_rdo(
+        PredictionMode::DC_PRED,
+        fi,
+        bsize,
+        tile_bo,
+        ts,
+        cw,
+        rdo_type,
+        cw_checkpoint,
+        &mut best,
+        mvs,
+        ref_frames,
+        MotionMode::SIMPLE_TRANSLATION,
+        CompoundParams::Average,
+        [FilterMode::REGULAR; 2],
+        &[PredictionMode::DC_PRED],
+        true,
+        0,
+        &mv_stack,
+        AngleDelta::default(),
+        &palette,
+      );
+    }
+
+    // The chroma palette goes with whichever luma mode won
+    if is_chroma_block && best.pred_mode_luma.is_intra() {
+      if let Some(palette) =
+        chroma_palette_candidate(fi, ts, tile_bo, bsize, &best.palette)
+      {
+        let angle_delta = AngleDelta { y: best.angle_delta.y, uv: 0 };
+        luma_chroma_mode_rdo(
+          best.pred_mode_luma,
+          fi,
+          bsize,
+          tile_bo,
+          ts,
+          cw,
+          rdo_type,
+          cw_checkpoint,
+          &mut best,
+          mvs,
+          ref_frames,
+          MotionMode::SIMPLE_TRANSLATION,
+          CompoundParams::Average,
+          [FilterMode::REGULAR; 2],
+          &[PredictionMode::DC_PRED],
+          true,
+          0,
+          &mv_stack,
+          angle_delta,
+          &palette,
+        );
+      }
+    }
+  }
+
+  if fi.allow_intrabc {
+    let ref_frames = [INTRA_FRAME, NONE_FRAME];
+    let sb_size = if fi.sequence.use_128x128_superblock {
+      BlockSize::BLOCK_128X128
+    } else {
+      BlockSize::BLOCK_64X64
+    };
+    let mut mv_stack = ArrayVec::<CandidateMV, 9>::new();
+    cw.find_mvrefs(tile_bo, ref_frames, &mut mv_stack, bsize, fi, false);
+    let ref_dv = intrabc_ref_dv(&mv_stack, tile_bo, sb_size);
+    if let Some(dv) = intrabc_search(fi, ts, tile_bo, bsize, ref_dv, &mv_stack)
+    {
+      luma_chroma_mode_rdo(
+        PredictionMode::NEWMV,
+        fi,
+        bsize,
+        tile_bo,
+        ts,
+        cw,
+        rdo_type,
+        cw_checkpoint,
+        &mut best,
+        [dv, MotionVector::default()],
+        ref_frames,
+        MotionMode::SIMPLE_TRANSLATION,
+        CompoundParams::Average,
+        [FilterMode::REGULAR; 2],
+        &[PredictionMode::NEWMV],
+        false,
+        0,
+        &mv_stack,
+        AngleDelta::default(),
+        &PaletteParams::default(),
+      );
+    }
+  }
+
   best
 }
 
@@ -1702,8 +2138,9 @@ pub fn rdo_tx_type_decision<T: Pixel>(
   fi: &FrameInvariants<T>, ts: &mut TileStateMut<'_, T>,
   cw: &mut ContextWriter, cw_checkpoint: &mut Option<ContextWriterCheckpoint>,
   mode: PredictionMode, ref_frames: [RefType; 2], mvs: [MotionVector; 2],
-  bsize: BlockSize, tile_bo: TileBlockOffset, tx_size: TxSize, tx_set: TxSet,
-  tx_types: &[TxType], cur_best_rd: f64,
+  motion_mode: MotionMode, compound: CompoundParams,
+  interp_filter: [FilterMode; 2], bsize: BlockSize, tile_bo: TileBlockOffset,
+  tx_size: TxSize, tx_set: TxSet, tx_types: &[TxType], cur_best_rd: f64,
 ) -> (TxType, f64) {
   let mut best_type = TxType::DCT_DCT;
   let mut best_rd = f64::MAX;
@@ -1737,7 +2174,18 @@ pub fn rdo_tx_type_decision<T: Pixel>(
 
     if is_inter {
       motion_compensate(
-        fi, ts, cw, mode, ref_frames, mvs, bsize, tile_bo, true,
+        fi,
+        ts,
+        cw,
+        mode,
+        ref_frames,
+        mvs,
+        motion_mode,
+        compound,
+        interp_filter,
+        bsize,
+        tile_bo,
+        true,
       );
     }
 
@@ -1775,6 +2223,7 @@ pub fn rdo_tx_type_decision<T: Pixel>(
         tx_type,
         false,
         CFLParams::default(), // Unused.
+        &PaletteParams::default(),
         true,
         rdo_type,
         need_recon_pixel,
@@ -2120,7 +2569,15 @@ pub fn rdo_loop_decision<T: Pixel, W: Writer>(
                     // is/how many SBs we're processing (same thing)
   let mut lru_w = [0; MAX_PLANES]; // how many LRUs we're processing
   let mut lru_h = [0; MAX_PLANES]; // how many LRUs we're processing
+
+  // With super-resolution, LRUs do not line up with superblocks: they are
+  // chosen by `rdo_lrf_superres` once the frame is upscaled and only CDEF
+  // is decided here, one superblock at a time.
+  let lrf_rdo = !fi.use_superres();
   for pli in 0..planes {
+    if !lrf_rdo {
+      break;
+    }
     let sb_h_shift = ts.restoration.planes[pli].rp_cfg.sb_h_shift;
     let sb_v_shift = ts.restoration.planes[pli].rp_cfg.sb_v_shift;
     if sb_w < (1 << sb_h_shift) {
@@ -2211,7 +2668,7 @@ pub fn rdo_loop_decision<T: Pixel, W: Writer>(
   // This should be the same as `cdef_skip_all`, except when cdef is disabled.
   let mut lru_skip_all = true;
   let mut lru_skip = [[true; MAX_PLANES]; MAX_LRU_SIZE * MAX_LRU_SIZE];
-  if fi.sequence.enable_restoration {
+  if fi.sequence.enable_restoration && lrf_rdo {
     if fi.config.speed_settings.lru_on_skip {
       lru_skip_all = false;
       lru_skip = [[false; MAX_PLANES]; MAX_LRU_SIZE * MAX_LRU_SIZE];
@@ -2673,34 +3130,20 @@ pub fn rdo_loop_decision<T: Pixel, W: Writer>(
 
               for &set in get_sgr_sets(fi.config.speed_settings.sgr_complexity)
               {
-                let (xqd0, xqd1) = sgrproj_solve(
+                let current_lrf = lrf_optimize_sgrproj(
                   set,
                   fi,
                   &ts.integral_buffer,
                   &src_plane
                     .subregion(Area::StartingAt { x: lrf_po.x, y: lrf_po.y }),
                   &lrf_in_plane.slice(lrf_po),
-                  vis_width,
-                  vis_height,
+                  &mut lrf_ref.planes[pli].region_mut(Area::Rect {
+                    x: lrf_po.x,
+                    y: lrf_po.y,
+                    width: vis_width,
+                    height: vis_height,
+                  }),
                 );
-                let current_lrf =
-                  RestorationFilter::Sgrproj { set, xqd: [xqd0, xqd1] };
-                if let RestorationFilter::Sgrproj { set, xqd } = current_lrf {
-                  sgrproj_stripe_filter(
-                    set,
-                    xqd,
-                    fi,
-                    &ts.integral_buffer,
-                    SOLVE_IMAGE_STRIDE,
-                    &lrf_in_plane.slice(lrf_po),
-                    &mut lrf_ref.planes[pli].region_mut(Area::Rect {
-                      x: lrf_po.x,
-                      y: lrf_po.y,
-                      width: vis_width,
-                      height: vis_height,
-                    }),
-                  );
-                }
                 let err = rdo_loop_plane_error(
                   base_sbo,
                   loop_sbo,
@@ -2746,6 +3189,146 @@ pub fn rdo_loop_decision<T: Pixel, W: Writer>(
   }
 }
 
+/// Solves the self guided filter parameters of `set` for a restoration unit
+/// of `lrf_in`, whose integral image is set up in `integral_buffer`, and
+/// filters the unit into `lrf_out`, which gives its visible size.
+pub fn lrf_optimize_sgrproj<T: Pixel>(
+  set: u8, fi: &FrameInvariants<T>, integral_buffer: &IntegralImageBuffer,
+  src: &PlaneRegion<'_, T>, lrf_in: &PlaneSlice<'_, T>,
+  lrf_out: &mut PlaneRegionMut<'_, T>,
+) -> RestorationFilter {
+  let &Rect { width, height, .. } = lrf_out.rect();
+  let (xqd0, xqd1) =
+    sgrproj_solve(set, fi, integral_buffer, src, lrf_in, width, height);
+  let xqd = [xqd0, xqd1];
+  sgrproj_stripe_filter(
+    set,
+    xqd,
+    fi,
+    integral_buffer,
+    SOLVE_IMAGE_STRIDE,
+    lrf_in,
+    lrf_out,
+  );
+  RestorationFilter::Sgrproj { set, xqd }
+}
+
+/// Chooses the loop restoration filters of a frame using super-resolution.
+///
+/// Restoration units are laid out over the upscaled frame, so unlike
+/// [`rdo_loop_decision`] this searches the whole frame at once, from the
+/// upscaled output `cdeffed` of the deblocking and CDEF filters and the full
+/// width `src`.
+pub fn rdo_lrf_superres<T: Pixel>(
+  fi: &FrameInvariants<T>, rs: &mut RestorationState, cdeffed: &Frame<T>,
+  src: &Frame<T>,
+) {
+  let planes =
+    if fi.sequence.chroma_sampling == ChromaSampling::Cs400 { 1 } else { 3 };
+  let fc = CDFContext::new(fi.base_q_idx);
+  let w = WriterCounter::new();
+  let mut integral_buffer = IntegralImageBuffer::zeroed(SOLVE_IMAGE_SIZE);
+  let mut trs = TileRestorationStateMut::new(
+    rs,
+    PlaneSuperBlockOffset(SuperBlockOffset { x: 0, y: 0 }),
+    fi.sb_width,
+    fi.sb_height,
+  );
+
+  for pli in 0..planes {
+    let unit_size = trs.planes[pli].rp_cfg.unit_size;
+    let PlaneConfig { xdec, ydec, .. } = cdeffed.planes[pli].cfg;
+    let crop_w = (fi.upscaled_width + (1 << xdec >> 1)) >> xdec;
+    let crop_h = (fi.height + (1 << ydec >> 1)) >> ydec;
+    let mut lrf_out = Plane::new(unit_size, unit_size, xdec, ydec, 0, 0);
+
+    for lru_y in 0..trs.planes[pli].units.rows() {
+      for lru_x in 0..trs.planes[pli].units.cols() {
+        let po = PlaneOffset {
+          x: (lru_x * unit_size) as isize,
+          y: (lru_y * unit_size) as isize,
+        };
+        // Only the nominal area of stretched units is considered
+        let vis_width = unit_size.min(crop_w - po.x as usize);
+        let vis_height = unit_size.min(crop_h - po.y as usize);
+        let src_region =
+          src.planes[pli].region(Area::StartingAt { x: po.x, y: po.y });
+        let cdeffed_slice = cdeffed.planes[pli].slice(po);
+
+        let err = lrf_unit_sse(
+          &src_region,
+          &cdeffed.planes[pli].region(Area::StartingAt { x: po.x, y: po.y }),
+          vis_width,
+          vis_height,
+        ) * fi.dist_scale[pli];
+        let rate = fc.count_lrf_switchable(
+          &w,
+          &trs.as_const(),
+          RestorationFilter::None,
+          pli,
+        );
+        let mut best_cost = compute_rd_cost(fi, rate, err);
+        let mut best_lrf = RestorationFilter::None;
+
+        setup_integral_image(
+          &mut integral_buffer,
+          SOLVE_IMAGE_STRIDE,
+          vis_width,
+          vis_height,
+          vis_width,
+          vis_height,
+          &cdeffed_slice,
+          &cdeffed_slice,
+        );
+
+        for &set in get_sgr_sets(fi.config.speed_settings.sgr_complexity) {
+          let current_lrf = lrf_optimize_sgrproj(
+            set,
+            fi,
+            &integral_buffer,
+            &src_region,
+            &cdeffed_slice,
+            &mut lrf_out.region_mut(Area::Rect {
+              x: 0,
+              y: 0,
+              width: vis_width,
+              height: vis_height,
+            }),
+          );
+          let err = lrf_unit_sse(
+            &src_region,
+            &lrf_out.region(Area::StartingAt { x: 0, y: 0 }),
+            vis_width,
+            vis_height,
+          ) * fi.dist_scale[pli];
+          let rate =
+            fc.count_lrf_switchable(&w, &trs.as_const(), current_lrf, pli);
+          let cost = compute_rd_cost(fi, rate, err);
+          if cost < best_cost {
+            best_cost = cost;
+            best_lrf = current_lrf;
+          }
+        }
+
+        trs.planes[pli].units[lru_y][lru_x].filter = best_lrf;
+      }
+    }
+  }
+}
+
+fn lrf_unit_sse<T: Pixel>(
+  src: &PlaneRegion<'_, T>, test: &PlaneRegion<'_, T>, w: usize, h: usize,
+) -> Distortion {
+  let mut sse = 0;
+  for y in 0..h {
+    for (&a, &b) in src[y][..w].iter().zip(&test[y][..w]) {
+      let diff = i32::cast_from(a) - i32::cast_from(b);
+      sse += (diff * diff) as u64;
+    }
+  }
+  Distortion(sse)
+}
+
 #[test]
 fn estimate_rate_test() {
   assert_eq!(estimate_rate(0, TxSize::TX_4X4, 0), RDO_RATE_TABLE[0][0][0]);
@@ -22,8 +22,12 @@ use crate::util::Pixel;
 use self::fast::{detect_scale_factor, FAST_THRESHOLD};
 
 mod fast;
+mod screen_content;
 mod standard;
 
+pub(crate) use self::screen_content::ScreenContentDetector;
+pub use self::screen_content::ScreenContentStats;
+
 /// Experiments have determined this to be an optimal threshold
 const IMP_BLOCK_DIFF_THRESHOLD: f64 = 7.0;
 
@@ -85,6 +89,13 @@ pub struct SceneChangeDetector<T: Pixel> {
   /// Calculated intra costs for each input frame.
   /// These are cached for reuse later in rav1e.
   pub(crate) intra_costs: BTreeMap<u64, Box<[u32]>>,
+  /// Calculated mean inter costs of each input frame against the previous
+  /// one, if the motion search ran.
+  /// These are cached for reuse later in rav1e.
+  pub(crate) inter_costs: BTreeMap<u64, f64>,
+  /// Scene change scores of the analyzed input frames, relative to the
+  /// detection threshold.
+  pub(crate) scores: BTreeMap<u64, f64>,
   /// Temporary buffer used by `estimate_intra_costs`.
   pub(crate) temp_plane: Option<Plane<T>>,
 }
@@ -138,6 +149,8 @@ impl<T: Pixel> SceneChangeDetector<T> {
       encoder_config,
       sequence,
       intra_costs: BTreeMap::new(),
+      inter_costs: BTreeMap::new(),
+      scores: BTreeMap::new(),
       temp_plane: None,
     }
   }
@@ -206,6 +219,11 @@ impl<T: Pixel> SceneChangeDetector<T> {
 
     // Adaptive scenecut check
     let (scenecut, score) = self.adaptive_scenecut();
+    if score.threshold > 0.0 {
+      self
+        .scores
+        .insert(input_frameno, score.forward_adjusted_cost / score.threshold);
+    }
     let scenecut = self.handle_min_max_intervals(distance).unwrap_or(scenecut);
     debug!(
       "[SC-Detect] Frame {}: Raw={:5.1}  ImpBl={:5.1}  Bwd={:5.1}  Fwd={:5.1}  Th={:.1}  {}",
@@ -227,7 +245,9 @@ impl<T: Pixel> SceneChangeDetector<T> {
     scenecut
   }
 
-  fn handle_min_max_intervals(&mut self, distance: u64) -> Option<bool> {
+  pub(crate) fn handle_min_max_intervals(
+    &mut self, distance: u64,
+  ) -> Option<bool> {
     // Handle minimum and maximum keyframe intervals.
     if distance < self.encoder_config.min_key_frame_interval {
       return Some(false);
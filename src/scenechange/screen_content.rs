@@ -0,0 +1,200 @@
+// Copyright (c) 2024, The rav1e contributors. All rights reserved
+//
+// This source code is subject to the terms of the BSD 2 Clause License and
+// the Alliance for Open Media Patent License 1.0. If the BSD 2 Clause License
+// was not distributed with this source code in the LICENSE file, you can
+// obtain it at www.aomedia.org/license/software. If the Alliance for Open
+// Media Patent License 1.0 was not distributed with this source code in the
+// PATENTS file, you can obtain it at www.aomedia.org/license/patent.
+
+use std::collections::BTreeMap;
+
+use arrayvec::ArrayVec;
+
+use crate::api::ScreenContentMode;
+use crate::frame::*;
+use crate::serialize::{Deserialize, Serialize};
+use crate::util::{CastFromPrimitive, Pixel};
+
+/// Size of the square luma blocks the frame is classified in.
+const BLOCK_SIZE: usize = 16;
+/// Blocks with at most this many colours are candidates for palette coding.
+const FEW_COLORS: usize = 4;
+/// Minimum difference between the darkest and the brightest colour of a
+/// few-colour block, for 8-bit content, for it to count as high contrast.
+const HIGH_CONTRAST_RANGE: i32 = 64;
+
+/// Screen content analysis of a frame, as measured by the lookahead.
+///
+/// The shares are fractions of the 16x16 luma blocks fully inside the frame.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ScreenContentStats {
+  /// Share of blocks made of a single colour.
+  pub flat_blocks: f32,
+  /// Share of blocks made of two to four colours.
+  pub few_color_blocks: f32,
+  /// Share of blocks made of two to four colours far apart from each other,
+  /// as found in text and user interface elements.
+  pub high_contrast_blocks: f32,
+  /// Whether the screen content coding tools, such as palette prediction,
+  /// are enabled for the frame.
+  pub tools: bool,
+  /// Whether intra block copy is enabled, if the frame is an intra frame.
+  pub intrabc: bool,
+  /// Whether motion vectors are restricted to whole pixels, if the frame is
+  /// an inter frame.
+  pub integer_mv: bool,
+}
+
+impl ScreenContentStats {
+  /// Measures the share of flat, few-colour and high contrast blocks in
+  /// `frame`.
+  pub(crate) fn measure<T: Pixel>(frame: &Frame<T>, bit_depth: usize) -> Self {
+    let plane = &frame.planes[0];
+    let stride = plane.cfg.stride;
+    let data = plane.data_origin();
+    let high_contrast_range = HIGH_CONTRAST_RANGE << (bit_depth - 8);
+
+    let cols = plane.cfg.width / BLOCK_SIZE;
+    let rows = plane.cfg.height / BLOCK_SIZE;
+    let mut flat = 0;
+    let mut few_colors = 0;
+    let mut high_contrast = 0;
+    for by in 0..rows {
+      for bx in 0..cols {
+        let mut colors = ArrayVec::<T, { FEW_COLORS + 1 }>::new();
+        'block: for y in 0..BLOCK_SIZE {
+          let row = &data[(by * BLOCK_SIZE + y) * stride + bx * BLOCK_SIZE..]
+            [..BLOCK_SIZE];
+          for &v in row {
+            if !colors.contains(&v) {
+              if colors.is_full() {
+                break 'block;
+              }
+              colors.push(v);
+            }
+          }
+        }
+        match colors.len() {
+          1 => flat += 1,
+          2..=FEW_COLORS => {
+            few_colors += 1;
+            let min = colors.iter().map(|&v| i32::cast_from(v)).min();
+            let max = colors.iter().map(|&v| i32::cast_from(v)).max();
+            if max.unwrap() - min.unwrap() >= high_contrast_range {
+              high_contrast += 1;
+            }
+          }
+          _ => {}
+        }
+      }
+    }
+
+    let blocks = (cols * rows).max(1) as f32;
+    ScreenContentStats {
+      flat_blocks: flat as f32 / blocks,
+      few_color_blocks: few_colors as f32 / blocks,
+      high_contrast_blocks: high_contrast as f32 / blocks,
+      ..Default::default()
+    }
+  }
+}
+
+/// Classifies frames from the lookahead queue as screen content or natural
+/// content, and picks the screen content tools to use for each of them.
+pub(crate) struct ScreenContentDetector {
+  mode: ScreenContentMode,
+  bit_depth: usize,
+  /// Analysis of each input frame, indexed by `input_frameno`.
+  stats: BTreeMap<u64, ScreenContentStats>,
+}
+
+impl ScreenContentDetector {
+  pub fn new(mode: ScreenContentMode, bit_depth: usize) -> Self {
+    Self { mode, bit_depth, stats: BTreeMap::new() }
+  }
+
+  /// Analyzes the frame `input_frameno` when it enters the lookahead queue.
+  pub fn analyze_frame<T: Pixel>(
+    &mut self, frame: &Frame<T>, input_frameno: u64,
+  ) {
+    let stats = match self.mode {
+      ScreenContentMode::Off => ScreenContentStats::default(),
+      ScreenContentMode::Auto | ScreenContentMode::On => {
+        let mut stats = ScreenContentStats::measure(frame, self.bit_depth);
+        // Palettes pay off as soon as a tenth of the frame is made of few
+        // colours. IntraBC disables the loop filters and integer motion
+        // vectors rule out subpixel motion, so both are kept to frames where
+        // a sizeable share of sharp text or graphics is found as well.
+        let is_text = stats.few_color_blocks >= 0.1
+          && stats.high_contrast_blocks >= 1.0 / 12.0;
+        stats.tools =
+          self.mode == ScreenContentMode::On || stats.few_color_blocks >= 0.1;
+        stats.intrabc = self.mode == ScreenContentMode::On || is_text;
+        stats.integer_mv = is_text;
+        stats
+      }
+    };
+    self.stats.insert(input_frameno, stats);
+  }
+
+  /// Returns the analysis of the frame `input_frameno`.
+  pub fn get(&self, input_frameno: u64) -> ScreenContentStats {
+    self.stats.get(&input_frameno).copied().unwrap_or_default()
+  }
+
+  /// Drops the analysis of the frames before `input_frameno`.
+  pub fn discard_before(&mut self, input_frameno: u64) {
+    self.stats = self.stats.split_off(&input_frameno);
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::api::ChromaSampling;
+
+  fn frame_from_fn(f: impl Fn(usize, usize) -> u8) -> Frame<u8> {
+    let mut frame = Frame::new(64, 64, ChromaSampling::Cs420);
+    let stride = frame.planes[0].cfg.stride;
+    for (y, row) in
+      frame.planes[0].data_origin_mut().chunks_mut(stride).take(64).enumerate()
+    {
+      for (x, v) in row[..64].iter_mut().enumerate() {
+        *v = f(x, y);
+      }
+    }
+    frame
+  }
+
+  #[test]
+  fn detects_text_on_flat_background() {
+    let frame = frame_from_fn(|x, y| {
+      if y >= 32 && (x * 7 + y * 3) % 11 < 4 {
+        20
+      } else {
+        235
+      }
+    });
+    let mut detector = ScreenContentDetector::new(ScreenContentMode::Auto, 8);
+    detector.analyze_frame(&frame, 0);
+    let stats = detector.get(0);
+    assert_eq!(stats.flat_blocks, 0.5);
+    assert_eq!(stats.few_color_blocks, 0.5);
+    assert_eq!(stats.high_contrast_blocks, 0.5);
+    assert!(stats.tools && stats.intrabc && stats.integer_mv);
+  }
+
+  #[test]
+  fn ignores_natural_content() {
+    let frame = frame_from_fn(|x, y| ((x * 37 + y * 91) % 251) as u8);
+    let mut detector = ScreenContentDetector::new(ScreenContentMode::Auto, 8);
+    detector.analyze_frame(&frame, 0);
+    assert_eq!(detector.get(0), ScreenContentStats::default());
+
+    let mut detector = ScreenContentDetector::new(ScreenContentMode::On, 8);
+    detector.analyze_frame(&frame, 0);
+    let stats = detector.get(0);
+    assert!(stats.tools && stats.intrabc && !stats.integer_mv);
+  }
+}
@@ -81,6 +81,7 @@ impl<T: Pixel> SceneChangeDetector<T> {
           estimate_importance_block_difference(frame2_imp_ref, frame1_imp_ref);
       });
     });
+    self.inter_costs.insert(input_frameno, mv_inter_cost);
 
     // `BIAS` determines how likely we are
     // to choose a keyframe, between 0.0-1.0.
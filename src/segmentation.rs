@@ -29,8 +29,12 @@ pub fn segmentation_optimize<T: Pixel>(
   if fs.segmentation.enabled {
     fs.segmentation.update_map = true;
 
-    // We don't change the values between frames.
-    fs.segmentation.update_data = fi.primary_ref_frame == PRIMARY_REF_NONE;
+    // We don't change the values between frames, except to follow the
+    // importance map of the frame.
+    let has_importance_map =
+      fi.coded_frame_data.as_ref().is_some_and(|data| data.has_importance_map);
+    fs.segmentation.update_data =
+      fi.primary_ref_frame == PRIMARY_REF_NONE || has_importance_map;
 
     // Avoid going into lossless mode by never bringing qidx below 1.
     // Because base_q_idx changes more frequently than the segmentation
@@ -0,0 +1,354 @@
+// Copyright (c) 2024, The rav1e contributors. All rights reserved
+//
+// This source code is subject to the terms of the BSD 2 Clause License and
+// the Alliance for Open Media Patent License 1.0. If the BSD 2 Clause License
+// was not distributed with this source code in the LICENSE file, you can
+// obtain it at www.aomedia.org/license/software. If the Alliance for Open
+// Media Patent License 1.0 was not distributed with this source code in the
+// PATENTS file, you can obtain it at www.aomedia.org/license/patent.
+
+//! Horizontal super-resolution: coding frames at a reduced width and
+//! upscaling the reconstruction back to the full width.
+
+use crate::api::SuperresMode;
+use crate::context::MI_SIZE;
+use crate::encoder::{CodedFrameData, FrameInvariants};
+use crate::frame::*;
+use crate::tiling::TilingInfo;
+use crate::util::*;
+
+use std::sync::Arc;
+
+pub const SUPERRES_NUM: usize = 8;
+pub const SUPERRES_DENOM_MIN: usize = 9;
+pub const SUPERRES_DENOM_BITS: u32 = 3;
+const SUPERRES_FILTER_BITS: usize = 6;
+const SUPERRES_FILTER_TAPS: usize = 8;
+const SUPERRES_FILTER_OFFSET: isize = 3;
+const SUPERRES_SCALE_BITS: usize = 14;
+const SUPERRES_SCALE_MASK: i64 = (1 << SUPERRES_SCALE_BITS) - 1;
+const SUPERRES_EXTRA_BITS: usize = SUPERRES_SCALE_BITS - SUPERRES_FILTER_BITS;
+const FILTER_BITS: usize = 7;
+
+/// Normative upscaling filter, see `Upscale_Filter` in the spec.
+#[rustfmt::skip]
+const UPSCALE_FILTER: [[i16; SUPERRES_FILTER_TAPS]; 1 << SUPERRES_FILTER_BITS] = [
+  [0, 0, 0, 128, 0, 0, 0, 0],          [0, 0, -1, 128, 2, -1, 0, 0],
+  [0, 1, -3, 127, 4, -2, 1, 0],        [0, 1, -4, 127, 6, -3, 1, 0],
+  [0, 2, -6, 126, 8, -3, 1, 0],        [0, 2, -7, 125, 11, -4, 1, 0],
+  [-1, 2, -8, 125, 13, -5, 2, 0],      [-1, 3, -9, 124, 15, -6, 2, 0],
+  [-1, 3, -10, 123, 18, -6, 2, -1],    [-1, 3, -11, 122, 20, -7, 3, -1],
+  [-1, 4, -12, 121, 22, -8, 3, -1],    [-1, 4, -13, 120, 25, -9, 3, -1],
+  [-1, 4, -14, 118, 28, -9, 3, -1],    [-1, 4, -15, 117, 30, -10, 4, -1],
+  [-1, 5, -16, 116, 32, -11, 4, -1],   [-1, 5, -16, 114, 35, -12, 4, -1],
+  [-1, 5, -17, 112, 38, -12, 4, -1],   [-1, 5, -18, 111, 40, -13, 5, -1],
+  [-1, 5, -18, 109, 43, -14, 5, -1],   [-1, 6, -19, 107, 45, -14, 5, -1],
+  [-1, 6, -19, 105, 48, -15, 5, -1],   [-1, 6, -19, 103, 51, -16, 5, -1],
+  [-1, 6, -20, 101, 53, -16, 6, -1],   [-1, 6, -20, 99, 56, -17, 6, -1],
+  [-1, 6, -20, 97, 58, -17, 6, -1],    [-1, 6, -20, 95, 61, -18, 6, -1],
+  [-2, 7, -20, 93, 64, -18, 6, -2],    [-2, 7, -20, 91, 66, -19, 6, -1],
+  [-2, 7, -20, 88, 69, -19, 6, -1],    [-2, 7, -20, 86, 71, -19, 6, -1],
+  [-2, 7, -20, 84, 74, -20, 7, -2],    [-2, 7, -20, 81, 76, -20, 7, -1],
+  [-2, 7, -20, 79, 79, -20, 7, -2],    [-1, 7, -20, 76, 81, -20, 7, -2],
+  [-2, 7, -20, 74, 84, -20, 7, -2],    [-1, 6, -19, 71, 86, -20, 7, -2],
+  [-1, 6, -19, 69, 88, -20, 7, -2],    [-1, 6, -19, 66, 91, -20, 7, -2],
+  [-2, 6, -18, 64, 93, -20, 7, -2],    [-1, 6, -18, 61, 95, -20, 6, -1],
+  [-1, 6, -17, 58, 97, -20, 6, -1],    [-1, 6, -17, 56, 99, -20, 6, -1],
+  [-1, 6, -16, 53, 101, -20, 6, -1],   [-1, 5, -16, 51, 103, -19, 6, -1],
+  [-1, 5, -15, 48, 105, -19, 6, -1],   [-1, 5, -14, 45, 107, -19, 6, -1],
+  [-1, 5, -14, 43, 109, -18, 5, -1],   [-1, 5, -13, 40, 111, -18, 5, -1],
+  [-1, 4, -12, 38, 112, -17, 5, -1],   [-1, 4, -12, 35, 114, -16, 5, -1],
+  [-1, 4, -11, 32, 116, -16, 5, -1],   [-1, 4, -10, 30, 117, -15, 4, -1],
+  [-1, 3, -9, 28, 118, -14, 4, -1],    [-1, 3, -9, 25, 120, -13, 4, -1],
+  [-1, 3, -8, 22, 121, -12, 4, -1],    [-1, 3, -7, 20, 122, -11, 3, -1],
+  [-1, 2, -6, 18, 123, -10, 3, -1],    [0, 2, -6, 15, 124, -9, 3, -1],
+  [0, 2, -5, 13, 125, -8, 2, -1],      [0, 1, -4, 11, 125, -7, 2, 0],
+  [0, 1, -3, 8, 126, -6, 2, 0],        [0, 1, -3, 6, 127, -4, 1, 0],
+  [0, 1, -2, 4, 127, -3, 1, 0],        [0, 0, -1, 2, 128, -1, 0, 0],
+];
+
+/// Coded frame width for a given upscaled width and denominator,
+/// see `compute_superres_params` in the spec.
+pub const fn superres_downscaled_width(
+  upscaled_width: usize, denom: usize,
+) -> usize {
+  let width = (upscaled_width * SUPERRES_NUM + denom / 2) / denom;
+  let min_width = if upscaled_width < 16 { upscaled_width } else { 16 };
+  if width < min_width {
+    min_width
+  } else {
+    width
+  }
+}
+
+/// Picks the super-resolution denominator for a frame, `SUPERRES_NUM` meaning
+/// that the frame is coded at full width.
+pub fn select_superres_denom<T: Pixel>(fi: &FrameInvariants<T>) -> usize {
+  if !fi.sequence.enable_superres || !fi.frame_type.all_intra() {
+    return SUPERRES_NUM;
+  }
+  match fi.config.superres_mode {
+    SuperresMode::None => SUPERRES_NUM,
+    SuperresMode::Fixed(denom) => denom as usize,
+    SuperresMode::Auto => {
+      // Only trade resolution for bits once the quantizer gets coarse
+      // enough that the fine detail would be lost anyway.
+      const QINDEX_THRESHOLD: usize = 128;
+      let qindex = fi.base_q_idx as usize;
+      if qindex < QINDEX_THRESHOLD {
+        SUPERRES_NUM
+      } else {
+        SUPERRES_DENOM_MIN
+          + (qindex - QINDEX_THRESHOLD)
+            * (2 * SUPERRES_NUM - SUPERRES_DENOM_MIN)
+            / (255 - QINDEX_THRESHOLD)
+      }
+    }
+  }
+}
+
+/// Derives the invariants of a frame using super-resolution as it is coded,
+/// that is at the downscaled width.
+///
+/// Tiling is recomputed for the coded width and the lookahead data is
+/// resampled to match the coded block grid.
+pub fn superres_coded_frame_invariants<T: Pixel>(
+  fi: &FrameInvariants<T>,
+) -> FrameInvariants<T> {
+  let width = superres_downscaled_width(fi.upscaled_width, fi.superres_denom);
+  let mut coded_fi = fi.clone_without_coded_data();
+  coded_fi.width = width;
+  coded_fi.w_in_b = 2 * width.align_power_of_two_and_shift(3);
+  coded_fi.sb_width = width.align_power_of_two_and_shift(6);
+
+  let mut sequence = *fi.sequence;
+  let tiling = &fi.sequence.tiling;
+  sequence.tiling = TilingInfo::from_target_tiles(
+    tiling.sb_size_log2,
+    width,
+    fi.height,
+    fi.config.frame_rate(),
+    tiling.tile_cols_log2,
+    tiling.tile_rows_log2,
+    fi.sequence.chroma_sampling == ChromaSampling::Cs422,
+  );
+  coded_fi.sequence = Arc::new(sequence);
+
+  coded_fi.coded_frame_data = fi.coded_frame_data.as_ref().map(|data| {
+    downscale_coded_frame_data(data, coded_fi.w_in_b / 2, fi.superres_denom)
+  });
+  coded_fi
+}
+
+/// Resamples the per importance block lookahead data to a narrower frame by
+/// picking the nearest block of the full width frame.
+fn downscale_coded_frame_data<T: Pixel>(
+  data: &CodedFrameData<T>, w_in_imp_b: usize, denom: usize,
+) -> CodedFrameData<T> {
+  let w = data.w_in_imp_b;
+  let h = data.h_in_imp_b;
+
+  CodedFrameData {
+    lookahead_rec_buffer: data.lookahead_rec_buffer.clone(),
+    w_in_imp_b,
+    h_in_imp_b: h,
+    lookahead_intra_costs: resample_blocks(
+      &data.lookahead_intra_costs,
+      w,
+      h,
+      w_in_imp_b,
+      denom,
+    ),
+    block_importances: resample_blocks(
+      &data.block_importances,
+      w,
+      h,
+      w_in_imp_b,
+      denom,
+    ),
+    distortion_scales: resample_blocks(
+      &data.distortion_scales,
+      w,
+      h,
+      w_in_imp_b,
+      denom,
+    ),
+    activity_scales: resample_blocks(
+      &data.activity_scales,
+      w,
+      h,
+      w_in_imp_b,
+      denom,
+    ),
+    activity_mask: data.activity_mask.clone(),
+    spatiotemporal_scores: resample_blocks(
+      &data.spatiotemporal_scores,
+      w,
+      h,
+      w_in_imp_b,
+      denom,
+    ),
+    has_importance_map: data.has_importance_map,
+  }
+}
+
+fn resample_blocks<V: Copy>(
+  values: &[V], w: usize, h: usize, new_w: usize, denom: usize,
+) -> Box<[V]> {
+  if values.len() != w * h {
+    // not computed for this frame
+    return values.into();
+  }
+  values
+    .chunks_exact(w)
+    .flat_map(|row| {
+      (0..new_w).map(move |x| row[(x * denom / SUPERRES_NUM).min(w - 1)])
+    })
+    .collect()
+}
+
+/// Upscales a plane of the reconstruction of a frame coded with
+/// super-resolution, following the upscaling process of the spec (7.16).
+///
+/// `mi_cols` is the width of the coded frame in mode info units: pixels up
+/// to that width are read from `src` even if they are past its visible width.
+fn upscale_plane<T: Pixel>(
+  src: &Plane<T>, dst: &mut Plane<T>, mi_cols: usize, bit_depth: usize,
+) {
+  let downscaled_w = src.cfg.width as i64;
+  let upscaled_w = dst.cfg.width as i64;
+  let max_x = ((mi_cols >> src.cfg.xdec) * MI_SIZE - 1) as isize;
+
+  let step =
+    ((downscaled_w << SUPERRES_SCALE_BITS) + upscaled_w / 2) / upscaled_w;
+  let err = upscaled_w * step - (downscaled_w << SUPERRES_SCALE_BITS);
+  let initial_subpel_x = ((-((upscaled_w - downscaled_w)
+    << (SUPERRES_SCALE_BITS - 1))
+    + upscaled_w / 2)
+    / upscaled_w
+    + (1 << (SUPERRES_EXTRA_BITS - 1))
+    - err / 2)
+    & SUPERRES_SCALE_MASK;
+  let max_value = (1 << bit_depth) - 1;
+
+  for (y, dst_row) in dst.rows_iter_mut().enumerate().take(src.cfg.height) {
+    let src_row = src.row(y as isize);
+    for (x, out) in dst_row.iter_mut().enumerate() {
+      let src_x =
+        -(1 << SUPERRES_SCALE_BITS) + initial_subpel_x + x as i64 * step;
+      let src_p = (src_x >> SUPERRES_SCALE_BITS) as isize;
+      let filter = &UPSCALE_FILTER
+        [((src_x & SUPERRES_SCALE_MASK) >> SUPERRES_EXTRA_BITS) as usize];
+      let sum: i32 = filter
+        .iter()
+        .enumerate()
+        .map(|(k, &coeff)| {
+          let px =
+            (src_p + k as isize - SUPERRES_FILTER_OFFSET).clamp(0, max_x);
+          i32::cast_from(src_row[px as usize]) * coeff as i32
+        })
+        .sum();
+      *out = T::cast_from(round_shift(sum, FILTER_BITS).clamp(0, max_value));
+    }
+  }
+}
+
+/// Upscales the reconstruction of a frame coded with super-resolution to the
+/// full frame width.
+pub fn upscale_frame<T: Pixel>(
+  fi: &FrameInvariants<T>, src: &Frame<T>,
+) -> Frame<T> {
+  let mut dst =
+    Frame::new(fi.upscaled_width, fi.height, fi.sequence.chroma_sampling);
+  let planes =
+    if fi.sequence.chroma_sampling == ChromaSampling::Cs400 { 1 } else { 3 };
+  for (src_plane, dst_plane) in
+    src.planes.iter().zip(dst.planes.iter_mut()).take(planes)
+  {
+    upscale_plane(src_plane, dst_plane, fi.w_in_b, fi.sequence.bit_depth);
+  }
+  dst
+}
+
+/// Horizontally resamples a plane to the width of `dst` by averaging the
+/// source pixels each destination pixel covers.
+///
+/// This is only used to prepare the encoder input, so it does not need to
+/// match any normative process.
+fn downscale_plane<T: Pixel>(src: &Plane<T>, dst: &mut Plane<T>) {
+  let src_w = src.cfg.width;
+  let dst_w = dst.cfg.width;
+
+  for (y, dst_row) in dst.rows_iter_mut().enumerate() {
+    let src_row = &src.row(y as isize)[..src_w];
+    for (x, out) in dst_row.iter_mut().enumerate() {
+      // Source interval covered by this pixel, in units of 1 / dst_w pixels.
+      let start = x * src_w;
+      let end = start + src_w;
+      let mut sum = 0;
+      for (i, &px) in src_row
+        .iter()
+        .enumerate()
+        .take(end.div_ceil(dst_w))
+        .skip(start / dst_w)
+      {
+        let overlap = end.min((i + 1) * dst_w) - start.max(i * dst_w);
+        sum += u32::cast_from(px) as usize * overlap;
+      }
+      *out = T::cast_from((sum + src_w / 2) / src_w);
+    }
+  }
+}
+
+/// Downscales an input frame to the coded width of a frame coded with
+/// super-resolution.
+pub fn downscale_frame<T: Pixel>(
+  fi: &FrameInvariants<T>, src: &Frame<T>,
+) -> Frame<T> {
+  let mut dst = Frame::new(fi.width, fi.height, fi.sequence.chroma_sampling);
+  let planes =
+    if fi.sequence.chroma_sampling == ChromaSampling::Cs400 { 1 } else { 3 };
+  for (src_plane, dst_plane) in
+    src.planes.iter().zip(dst.planes.iter_mut()).take(planes)
+  {
+    downscale_plane(src_plane, dst_plane);
+  }
+  dst.pad(fi.width, fi.height, planes);
+  dst
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn upscale_filter_is_normalized_and_symmetric() {
+    for (i, taps) in UPSCALE_FILTER.iter().enumerate() {
+      assert_eq!(taps.iter().map(|&t| t as i32).sum::<i32>(), 128);
+      if i > 0 {
+        let mirror = &UPSCALE_FILTER[UPSCALE_FILTER.len() - i];
+        assert!(taps.iter().eq(mirror.iter().rev()), "row {i}");
+      }
+    }
+  }
+
+  #[test]
+  fn downscaled_width() {
+    assert_eq!(superres_downscaled_width(1920, 8), 1920);
+    assert_eq!(superres_downscaled_width(1920, 16), 960);
+    assert_eq!(superres_downscaled_width(1920, 9), 1707);
+    assert_eq!(superres_downscaled_width(20, 16), 16);
+    assert_eq!(superres_downscaled_width(8, 16), 8);
+  }
+
+  #[test]
+  fn upscale_flat_plane() {
+    let mut src = Plane::<u16>::new(100, 4, 0, 0, 0, 0);
+    for row in src.rows_iter_mut() {
+      row.fill(700);
+    }
+    let mut dst = Plane::<u16>::new(200, 4, 0, 0, 0, 0);
+    upscale_plane(&src, &mut dst, 100 / MI_SIZE, 10);
+    assert!(dst.rows_iter().all(|row| row.iter().all(|&px| px == 700)));
+  }
+}
@@ -22,6 +22,10 @@ use dav1d_sys::*;
 
 pub(crate) struct Dav1dDecoder<T: Pixel> {
   dec: *mut Dav1dContext,
+  segment_qidx: Option<[u8; 8]>,
+  superres_denominator: Option<u8>,
+  global_motion: Option<bool>,
+  motion_modes: Option<bool>,
   pixel: PhantomData<T>,
 }
 
@@ -34,8 +38,14 @@ impl<T: Pixel> TestDecoder<T> for Dav1dDecoder<T> {
       // Was initialized by dav1d_default_settings().
       let settings = settings.assume_init();
 
-      let mut dec: Dav1dDecoder<T> =
-        Dav1dDecoder { dec: ptr::null_mut(), pixel: PhantomData };
+      let mut dec: Dav1dDecoder<T> = Dav1dDecoder {
+        dec: ptr::null_mut(),
+        segment_qidx: None,
+        superres_denominator: None,
+        global_motion: None,
+        motion_modes: None,
+        pixel: PhantomData,
+      };
       let ret = dav1d_open(&mut dec.dec, &settings);
 
       if ret != 0 {
@@ -71,6 +81,23 @@ impl<T: Pixel> TestDecoder<T> for Dav1dDecoder<T> {
           panic!("Decode fail");
         }
 
+        let frame_hdr = unsafe { &*pic.0.frame_hdr };
+        let segmentation = &frame_hdr.segmentation;
+        self.segment_qidx =
+          (segmentation.enabled != 0).then_some(segmentation.qidx);
+        let super_res = &frame_hdr.super_res;
+        self.superres_denominator = Some(if super_res.enabled != 0 {
+          super_res.width_scale_denominator
+        } else {
+          8
+        });
+        self.global_motion = Some(
+          frame_hdr.gmv.iter().any(|gmv| gmv.type_ != DAV1D_WM_TYPE_IDENTITY),
+        );
+        self.motion_modes = Some(
+          frame_hdr.switchable_motion_mode != 0 && frame_hdr.warp_motion != 0,
+        );
+
         if verify {
           let rec = rec_fifo.pop_front().unwrap();
           compare_pic(&pic.0, &rec, bit_depth, w, h, chroma_sampling);
@@ -83,6 +110,22 @@ impl<T: Pixel> TestDecoder<T> for Dav1dDecoder<T> {
       DecodeResult::NotDone
     }
   }
+
+  fn segment_qindices(&self) -> Option<[u8; 8]> {
+    self.segment_qidx
+  }
+
+  fn superres_denominator(&self) -> Option<u8> {
+    self.superres_denominator
+  }
+
+  fn global_motion(&self) -> Option<bool> {
+    self.global_motion
+  }
+
+  fn motion_modes(&self) -> Option<bool> {
+    self.motion_modes
+  }
 }
 
 impl<T: Pixel> Drop for Dav1dDecoder<T> {
@@ -12,7 +12,8 @@
 
 use crate::color::ChromaSampling;
 
-use crate::api::config::GrainTableSegment;
+use crate::api::config::{GrainTableSegment, SuperresMode};
+use crate::frame::FrameParameters;
 use crate::util::Pixel;
 use crate::*;
 
@@ -46,11 +47,37 @@ fn fill_frame<T: Pixel>(ra: &mut ChaChaRng, frame: &mut Frame<T>) {
 
 fn read_frame_batch<T: Pixel>(
   ctx: &mut Context<T>, ra: &mut ChaChaRng, limit: usize,
-) {
+) -> Vec<Frame<T>> {
+  let mut frames = Vec::with_capacity(limit);
   for _ in 0..limit {
     let mut input = ctx.new_frame();
     fill_frame(ra, &mut input);
 
+    // Keep a copy, so that the encoder owns and pads the frame it gets.
+    frames.push(input.clone());
+    let _ = ctx.send_frame(input);
+  }
+
+  ctx.flush();
+  frames
+}
+
+/// Sends `limit` frames to `ctx`, the pixel at `(x, y)` of the frame `i`
+/// being `pattern(i, x, y)`.
+fn send_pattern_frames(
+  ctx: &mut Context<u8>, limit: usize,
+  pattern: impl Fn(usize, usize, usize) -> u8,
+) {
+  for i in 0..limit {
+    let mut input = ctx.new_frame();
+    for plane in input.planes.iter_mut() {
+      let stride = plane.cfg.stride;
+      for (y, row) in plane.data.chunks_mut(stride).enumerate() {
+        for (x, pixel) in row.iter_mut().enumerate() {
+          *pixel = pattern(i, x, y);
+        }
+      }
+    }
     let _ = ctx.send_frame(input);
   }
 
@@ -73,7 +100,7 @@ pub(crate) trait TestDecoder<T: Pixel> {
     min_keyint: u64, max_keyint: u64, switch_frame_interval: u64,
     low_latency: bool, error_resilient: bool, bitrate: i32,
     tile_cols_log2: usize, tile_rows_log2: usize, still_picture: bool,
-    grain_table: Option<Vec<GrainTableSegment>>,
+    lossless: bool, grain_table: Option<Vec<GrainTableSegment>>,
   ) {
     let mut ra = ChaChaRng::from_seed([0; 32]);
 
@@ -93,6 +120,7 @@ pub(crate) trait TestDecoder<T: Pixel> {
       tile_cols_log2,
       tile_rows_log2,
       still_picture,
+      lossless,
       grain_table,
     );
 
@@ -110,7 +138,7 @@ pub(crate) trait TestDecoder<T: Pixel> {
     ivf::write_ivf_header(&mut out, w, h, 30, 1);
 
     let mut rec_fifo = VecDeque::new();
-    read_frame_batch(&mut ctx, &mut ra, limit);
+    let input_frames = read_frame_batch(&mut ctx, &mut ra, limit);
 
     for _ in 0..limit {
       let mut corrupted_count = 0;
@@ -123,6 +151,10 @@ pub(crate) trait TestDecoder<T: Pixel> {
           ivf::write_ivf_frame(&mut out, pkt.input_frameno, &pkt.data);
 
           if let Some(pkt_rec) = pkt.rec {
+            if lossless {
+              let input = &input_frames[pkt.input_frameno as usize];
+              compare_frame(&pkt_rec, input, w, h, chroma_sampling);
+            }
             rec_fifo.push_back((*pkt_rec).clone());
           }
           let packet = pkt.data;
@@ -155,6 +187,26 @@ pub(crate) trait TestDecoder<T: Pixel> {
     &mut self, packet: &[u8], rec_fifo: &mut VecDeque<Frame<T>>, w: usize,
     h: usize, chroma_sampling: ChromaSampling, bit_depth: usize, verify: bool,
   ) -> DecodeResult;
+  /// The qindex of each segment of the last decoded frame, if it is
+  /// segmented and the decoder exposes its frame header.
+  fn segment_qindices(&self) -> Option<[u8; 8]> {
+    None
+  }
+  /// The superres denominator of the last decoded frame, 8 if it is coded at
+  /// full width, if the decoder exposes its frame header.
+  fn superres_denominator(&self) -> Option<u8> {
+    None
+  }
+  /// Whether the last decoded frame has a global motion other than the
+  /// identity, if the decoder exposes its frame header.
+  fn global_motion(&self) -> Option<bool> {
+    None
+  }
+  /// Whether the last decoded frame allows the blocks to switch to the OBMC
+  /// and local warped motion modes, if the decoder exposes its frame header.
+  fn motion_modes(&self) -> Option<bool> {
+    None
+  }
 }
 
 pub fn compare_plane<T: Ord + std::fmt::Debug>(
@@ -174,14 +226,70 @@ pub fn compare_plane<T: Ord + std::fmt::Debug>(
   }
 }
 
+/// Checks that the reconstruction of a lossless frame matches its input.
+fn compare_frame<T: Pixel>(
+  rec: &Frame<T>, input: &Frame<T>, w: usize, h: usize,
+  chroma_sampling: ChromaSampling,
+) {
+  let planes = if chroma_sampling == ChromaSampling::Cs400 { 1 } else { 3 };
+  let (xdec, ydec) = chroma_sampling.get_decimation().unwrap_or((0, 0));
+  for pli in 0..planes {
+    let (w, h) =
+      if pli == 0 { (w, h) } else { ((w + xdec) >> xdec, (h + ydec) >> ydec) };
+    let rec = &rec.planes[pli];
+    let input = &input.planes[pli];
+    compare_plane::<T>(
+      rec.data_origin(),
+      rec.cfg.stride,
+      input.data_origin(),
+      input.cfg.stride,
+      w,
+      h,
+      pli,
+    );
+  }
+}
+
 fn setup_encoder<T: Pixel>(
   w: usize, h: usize, speed: u8, quantizer: usize, bit_depth: usize,
   chroma_sampling: ChromaSampling, min_keyint: u64, max_keyint: u64,
   switch_frame_interval: u64, low_latency: bool, error_resilient: bool,
   bitrate: i32, tile_cols_log2: usize, tile_rows_log2: usize,
-  still_picture: bool, grain_table: Option<Vec<GrainTableSegment>>,
+  still_picture: bool, lossless: bool,
+  grain_table: Option<Vec<GrainTableSegment>>,
 ) -> Context<T> {
   assert!(bit_depth == 8 || std::mem::size_of::<T>() > 1);
+  let cfg = setup_config(
+    w,
+    h,
+    speed,
+    quantizer,
+    bit_depth,
+    chroma_sampling,
+    min_keyint,
+    max_keyint,
+    switch_frame_interval,
+    low_latency,
+    error_resilient,
+    bitrate,
+    tile_cols_log2,
+    tile_rows_log2,
+    still_picture,
+    lossless,
+    grain_table,
+  );
+
+  cfg.new_context().unwrap()
+}
+
+fn setup_config(
+  w: usize, h: usize, speed: u8, quantizer: usize, bit_depth: usize,
+  chroma_sampling: ChromaSampling, min_keyint: u64, max_keyint: u64,
+  switch_frame_interval: u64, low_latency: bool, error_resilient: bool,
+  bitrate: i32, tile_cols_log2: usize, tile_rows_log2: usize,
+  still_picture: bool, lossless: bool,
+  grain_table: Option<Vec<GrainTableSegment>>,
+) -> Config {
   let mut enc = EncoderConfig::with_speed_preset(speed);
   enc.quantizer = quantizer;
   enc.min_key_frame_interval = min_keyint;
@@ -197,13 +305,45 @@ fn setup_encoder<T: Pixel>(
   enc.tile_cols = 1 << tile_cols_log2;
   enc.tile_rows = 1 << tile_rows_log2;
   enc.still_picture = still_picture;
+  enc.lossless = lossless;
   enc.film_grain_params = grain_table;
 
   let threads = if cfg!(fuzzing) { 1 } else { 2 };
 
-  let cfg = Config::new().with_encoder_config(enc).with_threads(threads);
+  Config::new().with_encoder_config(enc).with_threads(threads)
+}
 
-  cfg.new_context().unwrap()
+/// Checks that the decoding of the frames sent to `ctx` matches their
+/// reconstruction, calling `check` after each decoded packet.
+fn decode_sent_frames<T: Pixel>(
+  dec: &mut dyn TestDecoder<T>, ctx: &mut Context<T>, limit: usize, w: usize,
+  h: usize, bit_depth: usize, mut check: impl FnMut(&dyn TestDecoder<T>),
+) {
+  let mut rec_fifo = VecDeque::new();
+  let mut count = 0;
+  loop {
+    match ctx.receive_packet() {
+      Ok(pkt) => {
+        rec_fifo.push_back((*pkt.rec.unwrap()).clone());
+        let res = dec.decode_packet(
+          &pkt.data,
+          &mut rec_fifo,
+          w,
+          h,
+          Default::default(),
+          bit_depth,
+          true,
+        );
+        assert!(!matches!(res, DecodeResult::Corrupted(_)));
+        check(dec);
+        count += 1;
+      }
+      Err(EncoderStatus::Encoded) => {}
+      Err(EncoderStatus::LimitReached) => break,
+      Err(e) => panic!("Unexpected status {:?}", e),
+    }
+  }
+  assert_eq!(count, limit);
 }
 
 // TODO: support non-multiple-of-16 dimensions
@@ -238,6 +378,7 @@ fn speed(s: u8, decoder: &str) {
       0,
       0,
       false,
+      false,
       None,
     );
   }
@@ -332,6 +473,7 @@ fn dimension(w: usize, h: usize, decoder: &str) {
     0,
     0,
     still_picture,
+    false,
     None,
   );
 }
@@ -362,6 +504,7 @@ fn quantizer(decoder: &str, q: usize) {
       0,
       0,
       false,
+      false,
       None,
     );
   }
@@ -413,6 +556,7 @@ fn bitrate(decoder: &str) {
         0,
         0,
         false,
+        false,
         None,
       );
     }
@@ -447,6 +591,7 @@ fn keyframes(decoder: &str) {
     0,
     0,
     false,
+    false,
     None,
   );
 }
@@ -480,6 +625,7 @@ fn reordering(decoder: &str) {
       0,
       0,
       false,
+      false,
       None,
     );
   }
@@ -515,6 +661,7 @@ fn reordering_short_video(decoder: &str) {
     0,
     0,
     false,
+    false,
     None,
   );
 }
@@ -548,6 +695,7 @@ fn error_resilient(decoder: &str) {
     0,
     0,
     false,
+    false,
     None,
   );
 }
@@ -581,6 +729,7 @@ fn error_resilient_reordering(decoder: &str) {
       0,
       0,
       false,
+      false,
       None,
     );
   }
@@ -615,6 +764,7 @@ fn switch_frame(decoder: &str) {
     0,
     0,
     false,
+    false,
     None,
   );
 }
@@ -648,6 +798,7 @@ fn odd_size_frame_with_full_rdo(decoder: &str) {
     0,
     0,
     false,
+    false,
     None,
   );
 }
@@ -682,6 +833,7 @@ fn low_bit_depth(decoder: &str) {
     0,
     0,
     false,
+    false,
     None,
   );
 }
@@ -712,6 +864,7 @@ fn high_bit_depth(decoder: &str, depth: usize) {
     0,
     0,
     false,
+    false,
     None,
   );
 }
@@ -743,7 +896,7 @@ fn chroma_sampling(decoder: &str, cs: ChromaSampling) {
   let mut dec = get_decoder::<u8>(decoder, w as usize, h as usize);
   dec.encode_decode(
     true, w, h, speed, quantizer, limit, 8, cs, 15, 15, 0, true, false, 0, 0,
-    0, false, None,
+    0, false, false, None,
   );
 }
 
@@ -795,6 +948,7 @@ fn tile_encoding_with_stretched_restoration_units(decoder: &str) {
     2,
     2,
     false,
+    false,
     None,
   );
 }
@@ -827,10 +981,109 @@ fn still_picture_mode(decoder: &str) {
     0,
     0,
     true,
+    false,
     None,
   );
 }
 
+fn lossless<T: Pixel>(decoder: &str, bit_depth: usize, cs: ChromaSampling) {
+  let quantizer = 100;
+  let limit = 3; // Include inter frames
+  let speed = 6;
+  let w = 64;
+  let h = 80;
+
+  // The reconstruction is also checked against the input frames.
+  let mut dec = get_decoder::<T>(decoder, w as usize, h as usize);
+  dec.encode_decode(
+    true, w, h, speed, quantizer, limit, bit_depth, cs, 15, 15, 0, true,
+    false, 0, 0, 0, false, true, None,
+  );
+}
+
+#[cfg_attr(feature = "decode_test", interpolate_test(aom, "aom"))]
+#[cfg_attr(feature = "decode_test_dav1d", interpolate_test(dav1d, "dav1d"))]
+fn lossless_low_bit_depth(decoder: &str) {
+  lossless::<u8>(decoder, 8, ChromaSampling::Cs420);
+}
+
+#[cfg_attr(feature = "decode_test", interpolate_test(aom, "aom"))]
+#[cfg_attr(feature = "decode_test_dav1d", interpolate_test(dav1d, "dav1d"))]
+fn lossless_high_bit_depth(decoder: &str) {
+  lossless::<u16>(decoder, 10, ChromaSampling::Cs420);
+}
+
+#[cfg_attr(feature = "decode_test", interpolate_test(aom, "aom"))]
+#[cfg_attr(feature = "decode_test_dav1d", interpolate_test(dav1d, "dav1d"))]
+fn lossless_chroma_sampling_444(decoder: &str) {
+  lossless::<u8>(decoder, 8, ChromaSampling::Cs444);
+}
+
+#[cfg_attr(feature = "decode_test", interpolate_test(aom, "aom"))]
+#[cfg_attr(feature = "decode_test_dav1d", interpolate_test(dav1d, "dav1d"))]
+fn importance_map(decoder: &str) {
+  let w = 64;
+  let h = 64;
+  let speed = 10;
+  let quantizer = 100;
+
+  let mut ctx: Context<u8> = setup_encoder(
+    w,
+    h,
+    speed,
+    quantizer,
+    8,
+    Default::default(),
+    15,
+    15,
+    0,
+    true,
+    false,
+    0,
+    0,
+    0,
+    false,
+    false,
+    None,
+  );
+  let mut ra = ChaChaRng::from_seed([0; 32]);
+  let mut input = ctx.new_frame();
+  fill_frame(&mut ra, &mut input);
+  // The left half is more important than the right half.
+  let map = (0..64).map(|i| if i % 8 < 4 { 16. } else { 1. }).collect();
+  let fp = FrameParameters { importance_map: Some(map), ..Default::default() };
+  ctx.send_frame((input, fp)).unwrap();
+  ctx.flush();
+
+  let mut dec = get_decoder::<u8>(decoder, w, h);
+  let mut rec_fifo = VecDeque::new();
+  let pkt = loop {
+    match ctx.receive_packet() {
+      Ok(pkt) => break pkt,
+      Err(EncoderStatus::Encoded) => {}
+      Err(e) => panic!("Unexpected status {:?}", e),
+    }
+  };
+  rec_fifo.push_back((*pkt.rec.unwrap()).clone());
+  let res = dec.decode_packet(
+    &pkt.data,
+    &mut rec_fifo,
+    w,
+    h,
+    Default::default(),
+    8,
+    true,
+  );
+  assert!(!matches!(res, DecodeResult::Corrupted(_)));
+
+  // The segments carry different quantizers.
+  if let Some(qindices) = dec.segment_qindices() {
+    let min = qindices.iter().min().unwrap();
+    let max = qindices.iter().max().unwrap();
+    assert!(min < max, "{:?}", qindices);
+  }
+}
+
 pub(crate) fn get_decoder<T: Pixel>(
   decoder: &str, w: usize, h: usize,
 ) -> Box<dyn TestDecoder<T>> {
@@ -872,6 +1125,7 @@ fn rdo_loop_decision_lrf_sanity(decoder: &str) {
     0,
     0,
     false,
+    false,
     None,
   );
 }
@@ -905,6 +1159,7 @@ fn rdo_loop_decision_cdef_sanity(decoder: &str) {
     0,
     0,
     false,
+    false,
     None,
   );
 }
@@ -938,6 +1193,7 @@ fn film_grain_table_luma_only(decoder: &str) {
     0,
     0,
     false,
+    false,
     Some(vec![GrainTableSegment {
       start_time: 0,
       end_time: 9223372036854775807,
@@ -1008,6 +1264,7 @@ fn film_grain_table_chroma(decoder: &str) {
     0,
     0,
     false,
+    false,
     Some(vec![GrainTableSegment {
       start_time: 0,
       end_time: 9223372036854775807,
@@ -1070,3 +1327,145 @@ fn film_grain_table_chroma(decoder: &str) {
     }]),
   );
 }
+
+#[cfg_attr(feature = "decode_test", interpolate_test(aom, "aom"))]
+#[cfg_attr(feature = "decode_test_dav1d", interpolate_test(dav1d, "dav1d"))]
+fn superres(decoder: &str) {
+  let limit = 4;
+  let w = 128;
+  let h = 64;
+  let speed = 6; // Include loop restoration
+  let quantizer = 200;
+
+  let mut cfg = setup_config(
+    w,
+    h,
+    speed,
+    quantizer,
+    8,
+    Default::default(),
+    1,
+    1,
+    0,
+    true,
+    false,
+    0,
+    0,
+    0,
+    false,
+    false,
+    None,
+  );
+  // The frames are all key frames coded at half width, then upscaled before
+  // their loop restoration.
+  cfg.enc.superres_mode = SuperresMode::Fixed(16);
+  let mut ctx: Context<u8> = cfg.new_context().unwrap();
+  let mut ra = ChaChaRng::from_seed([0; 32]);
+  read_frame_batch(&mut ctx, &mut ra, limit);
+
+  let mut dec = get_decoder::<u8>(decoder, w, h);
+  let mut denominators = Vec::new();
+  decode_sent_frames(&mut *dec, &mut ctx, limit, w, h, 8, |dec| {
+    denominators.push(dec.superres_denominator())
+  });
+  if denominators.iter().all(Option::is_some) {
+    assert_eq!(denominators, [Some(16); 4]);
+  }
+}
+
+#[cfg_attr(feature = "decode_test", interpolate_test(aom, "aom"))]
+#[cfg_attr(feature = "decode_test_dav1d", interpolate_test(dav1d, "dav1d"))]
+fn global_motion(decoder: &str) {
+  let limit = 8;
+  let w = 128;
+  let h = 96;
+  let speed = 6;
+  let quantizer = 100;
+
+  let cfg = setup_config(
+    w,
+    h,
+    speed,
+    quantizer,
+    8,
+    Default::default(),
+    30,
+    30,
+    0,
+    true,
+    false,
+    0,
+    0,
+    0,
+    false,
+    false,
+    None,
+  );
+  assert!(cfg.enc.speed_settings.motion.global_motion);
+  let mut ctx: Context<u8> = cfg.new_context().unwrap();
+  // Zoom in on a smooth pattern while panning across it
+  send_pattern_frames(&mut ctx, limit, |i, x, y| {
+    let u = (x * 64 / (64 + i) + 2 * i) as f64;
+    let v = (y * 64 / (64 + i) + i) as f64;
+    (128. + 60. * (u * 0.15).sin() * (v * 0.1).cos()) as u8
+  });
+
+  let mut dec = get_decoder::<u8>(decoder, w, h);
+  let mut global_motion = Vec::new();
+  decode_sent_frames(&mut *dec, &mut ctx, limit, w, h, 8, |dec| {
+    global_motion.push(dec.global_motion())
+  });
+  if global_motion.iter().all(Option::is_some) {
+    assert!(global_motion.contains(&Some(true)));
+  }
+}
+
+#[cfg_attr(feature = "decode_test", interpolate_test(aom, "aom"))]
+#[cfg_attr(feature = "decode_test_dav1d", interpolate_test(dav1d, "dav1d"))]
+fn motion_modes(decoder: &str) {
+  let limit = 8;
+  let w = 128;
+  let h = 96;
+  let speed = 6;
+  let quantizer = 100;
+
+  let cfg = setup_config(
+    w,
+    h,
+    speed,
+    quantizer,
+    8,
+    Default::default(),
+    30,
+    30,
+    0,
+    true,
+    false,
+    0,
+    0,
+    0,
+    false,
+    false,
+    None,
+  );
+  assert!(cfg.enc.speed_settings.motion.motion_modes);
+  let mut ctx: Context<u8> = cfg.new_context().unwrap();
+  // Pan across a smooth pattern with some local distortion
+  send_pattern_frames(&mut ctx, limit, |i, x, y| {
+    let d = (i as f64) * ((x + y) as f64 * 0.05).sin();
+    let u = (x + 2 * i) as f64 + d;
+    let v = (y + i) as f64 - d;
+    (128. + 60. * (u * 0.15).sin() * (v * 0.1).cos()) as u8
+  });
+
+  let mut dec = get_decoder::<u8>(decoder, w, h);
+  let mut motion_modes = Vec::new();
+  decode_sent_frames(&mut *dec, &mut ctx, limit, w, h, 8, |dec| {
+    motion_modes.push(dec.motion_modes())
+  });
+  if motion_modes.iter().all(Option::is_some) {
+    // Only the key frame uses the simple translation alone
+    assert_eq!(motion_modes[0], Some(false));
+    assert!(motion_modes[1..].iter().all(|&m| m == Some(true)));
+  }
+}
@@ -8,9 +8,10 @@
 // PATENTS file, you can obtain it at www.aomedia.org/license/patent.
 
 use crate::context::*;
-use crate::mc::MotionVector;
+use crate::mc::{FilterMode, MotionVector};
+use crate::palette::PaletteParams;
 use crate::partition::*;
-use crate::predict::PredictionMode;
+use crate::predict::{CompoundParams, PredictionMode};
 use crate::transform::*;
 
 use std::cmp;
@@ -262,6 +263,30 @@ impl TileBlocksMut<'_> {
     self.for_each(bo, bsize, |block| block.segmentation_idx = idx);
   }
 
+  #[inline(always)]
+  pub fn set_palette(
+    &mut self, bo: TileBlockOffset, bsize: BlockSize, palette: &PaletteParams,
+  ) {
+    self.for_each(bo, bsize, |block| {
+      block.palette_size = palette.size;
+      block.palette_colors = [palette.colors[0], palette.colors[1]];
+    });
+  }
+
+  #[inline(always)]
+  pub fn set_compound(
+    &mut self, bo: TileBlockOffset, bsize: BlockSize, compound: CompoundParams,
+  ) {
+    self.for_each(bo, bsize, |block| block.compound = compound);
+  }
+
+  #[inline(always)]
+  pub fn set_interp_filter(
+    &mut self, bo: TileBlockOffset, bsize: BlockSize, filter: [FilterMode; 2],
+  ) {
+    self.for_each(bo, bsize, |block| block.interp_filter = filter);
+  }
+
   #[inline(always)]
   pub fn set_ref_frames(
     &mut self, bo: TileBlockOffset, bsize: BlockSize, r: [RefType; 2],
@@ -10,13 +10,25 @@
 use crate::context::*;
 use crate::encoder::FrameInvariants;
 use crate::lrf::*;
+use crate::superres::SUPERRES_NUM;
 use crate::util::Pixel;
 
 use std::marker::PhantomData;
-use std::ops::{Index, IndexMut};
+use std::ops::{Index, IndexMut, Range};
 use std::ptr;
 use std::slice;
 
+/// First restoration unit column coded in the superblock column `sbx` of a
+/// frame using super-resolution, see `read_lr` in the spec.
+const fn superres_unit_col_start(
+  cfg: &RestorationPlaneConfig, sbx: usize,
+) -> usize {
+  // superblock width in this plane, scaled up to the upscaled frame
+  let numerator = (cfg.unit_size >> cfg.sb_h_shift) * cfg.superres_denom;
+  let denominator = cfg.unit_size * SUPERRES_NUM;
+  (sbx * numerator).div_ceil(denominator)
+}
+
 /// Tiled view of `RestorationUnits`
 #[derive(Debug)]
 pub struct TileRestorationUnits<'a> {
@@ -261,6 +273,32 @@ macro_rules! tile_restoration_plane_common {
         last_x && last_y
       }
 
+      // With super-resolution, returns the ranges of restoration unit
+      // columns and rows coded in a superblock, relative to the tile.
+      // `tile_sbo` is the offset of the tile within the frame.
+      pub fn superres_units(&self, tile_sbo: PlaneSuperBlockOffset, sbo: TileSuperBlockOffset)
+        -> (Range<usize>, Range<usize>) {
+        let sbx = tile_sbo.0.x + sbo.0.x;
+        let sby = tile_sbo.0.y + sbo.0.y;
+        let clip = |start: usize, end: usize, base: usize, len: usize| {
+          start.clamp(base, base + len) - base..end.clamp(base, base + len) - base
+        };
+        let cols = clip(
+          superres_unit_col_start(self.rp_cfg, sbx),
+          superres_unit_col_start(self.rp_cfg, sbx + 1),
+          self.units.x(),
+          self.units.cols(),
+        );
+        let v_mask = (1 << self.rp_cfg.sb_v_shift) - 1;
+        let rows = if sby & v_mask == 0 {
+          let y = sby >> self.rp_cfg.sb_v_shift;
+          clip(y, y + 1, self.units.y(), self.units.rows())
+        } else {
+          0..0
+        };
+        (cols, rows)
+      }
+
       #[inline(always)]
       pub fn restoration_unit(&self, sbo: TileSuperBlockOffset, stretch: bool)
                               -> Option<&RestorationUnit> {
@@ -365,6 +403,18 @@ macro_rules! tile_restoration_state_common {
       ) -> (usize, usize, usize, usize) {
         let sb_h_shift = rs.planes[pli].cfg.sb_h_shift;
         let sb_v_shift = rs.planes[pli].cfg.sb_v_shift;
+        if rs.planes[pli].cfg.superres_denom != SUPERRES_NUM {
+          // with super-resolution, restoration units are spread over the
+          // superblock columns according to the upscaled frame; give each
+          // tile the units coded within its superblocks
+          let cfg = &rs.planes[pli].cfg;
+          let FrameRestorationUnits { cols: rs_cols, rows: rs_rows, .. } = rs.planes[pli].units;
+          let units_x = superres_unit_col_start(cfg, sbo.0.x).min(rs_cols);
+          let units_x_end = superres_unit_col_start(cfg, sbo.0.x + sb_width).min(rs_cols);
+          let units_y = (sbo.0.y >> sb_v_shift).min(rs_rows);
+          let units_rows = (sb_height + (1 << sb_v_shift) - 1 >> sb_v_shift).min(rs_rows - units_y);
+          return (units_x, units_y, units_x_end - units_x, units_rows);
+        }
         // there may be several super-blocks per restoration unit
         // the given super-block offset must match the start of a restoration unit
         debug_assert!(sbo.0.x % (1 << sb_h_shift) == 0);
@@ -12,6 +12,7 @@ use super::*;
 use crate::context::*;
 use crate::encoder::*;
 use crate::frame::*;
+use crate::intrabc::IntraBcHashTable;
 use crate::lrf::{IntegralImageBuffer, SOLVE_IMAGE_SIZE};
 use crate::mc::MotionVector;
 use crate::me::FrameMEStats;
@@ -68,6 +69,7 @@ pub struct TileStateMut<'a, T: Pixel> {
   pub coded_block_info: MiTileState,
   pub integral_buffer: IntegralImageBuffer,
   pub inter_compound_buffers: InterCompoundBuffers,
+  pub intrabc_hash: Option<IntraBcHashTable>,
 }
 
 /// Contains information for a coded block that is
@@ -191,6 +193,7 @@ impl<'a, T: Pixel> TileStateMut<'a, T> {
       ),
       integral_buffer: IntegralImageBuffer::zeroed(SOLVE_IMAGE_SIZE),
       inter_compound_buffers: InterCompoundBuffers::default(),
+      intrabc_hash: None,
     }
   }
 
@@ -0,0 +1,493 @@
+// Copyright (c) 2024, The rav1e contributors. All rights reserved
+//
+// This source code is subject to the terms of the BSD 2 Clause License and
+// the Alliance for Open Media Patent License 1.0. If the BSD 2 Clause License
+// was not distributed with this source code in the LICENSE file, you can
+// obtain it at www.aomedia.org/license/software. If the Alliance for Open
+// Media Patent License 1.0 was not distributed with this source code in the
+// PATENTS file, you can obtain it at www.aomedia.org/license/patent.
+
+//! Warped motion: affine motion models, their shear decomposition and the
+//! normative block warp used for warped inter prediction.
+
+use crate::context::PlaneBlockOffset;
+use crate::frame::*;
+use crate::mc::MotionVector;
+use crate::partition::BlockSize;
+use crate::tiling::*;
+use crate::util::*;
+
+pub const WARPEDMODEL_PREC_BITS: u32 = 16;
+const WARPEDPIXEL_PREC_SHIFTS: i32 = 1 << 6;
+const WARPEDDIFF_PREC_BITS: usize = 10;
+const WARP_PARAM_REDUCE_BITS: u32 = 6;
+const DIV_LUT_BITS: u32 = 8;
+const DIV_LUT_PREC_BITS: u32 = 14;
+
+// Precision of the coded global motion parameters
+pub const GM_ABS_ALPHA_BITS: u32 = 12;
+pub const GM_ALPHA_PREC_BITS: u32 = 15;
+pub const GM_ABS_TRANS_ONLY_BITS: u32 = 9;
+pub const GM_TRANS_ONLY_PREC_BITS: u32 = 3;
+pub const GM_ABS_TRANS_BITS: u32 = 12;
+pub const GM_TRANS_PREC_BITS: u32 = 6;
+
+/// Largest number of neighbouring samples used to estimate a local warp.
+pub const LEAST_SQUARES_SAMPLES_MAX: usize = 8;
+
+/// Parameters of the identity model.
+pub const WARP_IDENTITY: [i32; 6] =
+  [0, 0, 1 << WARPEDMODEL_PREC_BITS, 0, 0, 1 << WARPEDMODEL_PREC_BITS];
+
+/// Normative warp interpolation filters, see `Warped_Filters` in the spec.
+#[rustfmt::skip]
+const WARPED_FILTERS: [[i32; 8]; 3 * WARPEDPIXEL_PREC_SHIFTS as usize + 1] = [
+  [0, 0, 127, 1, 0, 0, 0, 0],         [0, -1, 127, 2, 0, 0, 0, 0],
+  [1, -3, 127, 4, -1, 0, 0, 0],       [1, -4, 126, 6, -2, 1, 0, 0],
+  [1, -5, 126, 8, -3, 1, 0, 0],       [1, -6, 125, 11, -4, 1, 0, 0],
+  [1, -7, 124, 13, -4, 1, 0, 0],      [2, -8, 123, 15, -5, 1, 0, 0],
+  [2, -9, 122, 18, -6, 1, 0, 0],      [2, -10, 121, 20, -6, 1, 0, 0],
+  [2, -11, 120, 22, -7, 2, 0, 0],     [2, -12, 119, 25, -8, 2, 0, 0],
+  [3, -13, 117, 27, -8, 2, 0, 0],     [3, -13, 116, 29, -9, 2, 0, 0],
+  [3, -14, 114, 32, -10, 3, 0, 0],    [3, -15, 113, 35, -10, 2, 0, 0],
+  [3, -15, 111, 37, -11, 3, 0, 0],    [3, -16, 109, 40, -11, 3, 0, 0],
+  [3, -16, 108, 42, -12, 3, 0, 0],    [4, -17, 106, 45, -13, 3, 0, 0],
+  [4, -17, 104, 47, -13, 3, 0, 0],    [4, -17, 102, 50, -14, 3, 0, 0],
+  [4, -17, 100, 52, -14, 3, 0, 0],    [4, -18, 98, 55, -15, 4, 0, 0],
+  [4, -18, 96, 58, -15, 3, 0, 0],     [4, -18, 94, 60, -16, 4, 0, 0],
+  [4, -18, 91, 63, -16, 4, 0, 0],     [4, -18, 89, 65, -16, 4, 0, 0],
+  [4, -18, 87, 68, -17, 4, 0, 0],     [4, -18, 85, 70, -17, 4, 0, 0],
+  [4, -18, 82, 73, -17, 4, 0, 0],     [4, -18, 80, 75, -17, 4, 0, 0],
+  [4, -18, 78, 78, -18, 4, 0, 0],     [4, -17, 75, 80, -18, 4, 0, 0],
+  [4, -17, 73, 82, -18, 4, 0, 0],     [4, -17, 70, 85, -18, 4, 0, 0],
+  [4, -17, 68, 87, -18, 4, 0, 0],     [4, -16, 65, 89, -18, 4, 0, 0],
+  [4, -16, 63, 91, -18, 4, 0, 0],     [4, -16, 60, 94, -18, 4, 0, 0],
+  [3, -15, 58, 96, -18, 4, 0, 0],     [4, -15, 55, 98, -18, 4, 0, 0],
+  [3, -14, 52, 100, -17, 4, 0, 0],    [3, -14, 50, 102, -17, 4, 0, 0],
+  [3, -13, 47, 104, -17, 4, 0, 0],    [3, -13, 45, 106, -17, 4, 0, 0],
+  [3, -12, 42, 108, -16, 3, 0, 0],    [3, -11, 40, 109, -16, 3, 0, 0],
+  [3, -11, 37, 111, -15, 3, 0, 0],    [2, -10, 35, 113, -15, 3, 0, 0],
+  [3, -10, 32, 114, -14, 3, 0, 0],    [2, -9, 29, 116, -13, 3, 0, 0],
+  [2, -8, 27, 117, -13, 3, 0, 0],     [2, -8, 25, 119, -12, 2, 0, 0],
+  [2, -7, 22, 120, -11, 2, 0, 0],     [1, -6, 20, 121, -10, 2, 0, 0],
+  [1, -6, 18, 122, -9, 2, 0, 0],      [1, -5, 15, 123, -8, 2, 0, 0],
+  [1, -4, 13, 124, -7, 1, 0, 0],      [1, -4, 11, 125, -6, 1, 0, 0],
+  [1, -3, 8, 126, -5, 1, 0, 0],       [1, -2, 6, 126, -4, 1, 0, 0],
+  [0, -1, 4, 127, -3, 1, 0, 0],       [0, 0, 2, 127, -1, 0, 0, 0],
+  [0, 0, 0, 127, 1, 0, 0, 0],         [0, 0, -1, 127, 2, 0, 0, 0],
+  [0, 1, -3, 127, 4, -2, 1, 0],       [0, 1, -5, 127, 6, -2, 1, 0],
+  [0, 2, -6, 126, 8, -3, 1, 0],       [-1, 2, -7, 126, 11, -4, 2, -1],
+  [-1, 3, -8, 125, 13, -5, 2, -1],    [-1, 3, -10, 124, 16, -6, 3, -1],
+  [-1, 4, -11, 123, 18, -7, 3, -1],   [-1, 4, -12, 122, 20, -7, 3, -1],
+  [-1, 4, -13, 121, 23, -8, 3, -1],   [-2, 5, -14, 120, 25, -9, 4, -1],
+  [-1, 5, -15, 119, 27, -10, 4, -1],  [-1, 5, -16, 118, 30, -11, 4, -1],
+  [-2, 6, -17, 116, 33, -12, 5, -1],  [-2, 6, -17, 114, 35, -12, 5, -1],
+  [-2, 6, -18, 113, 38, -13, 5, -1],  [-2, 7, -19, 111, 41, -14, 6, -2],
+  [-2, 7, -19, 110, 43, -15, 6, -2],  [-2, 7, -20, 108, 46, -15, 6, -2],
+  [-2, 7, -20, 106, 49, -16, 6, -2],  [-2, 7, -21, 104, 51, -16, 7, -2],
+  [-2, 7, -21, 102, 54, -17, 7, -2],  [-2, 8, -21, 100, 56, -18, 7, -2],
+  [-2, 8, -22, 98, 59, -18, 7, -2],   [-2, 8, -22, 96, 62, -19, 7, -2],
+  [-2, 8, -22, 94, 64, -19, 7, -2],   [-2, 8, -22, 91, 67, -20, 8, -2],
+  [-2, 8, -22, 89, 69, -20, 8, -2],   [-2, 8, -22, 87, 72, -21, 8, -2],
+  [-2, 8, -21, 84, 74, -21, 8, -2],   [-2, 8, -22, 82, 77, -21, 8, -2],
+  [-2, 8, -21, 79, 79, -21, 8, -2],   [-2, 8, -21, 77, 82, -22, 8, -2],
+  [-2, 8, -21, 74, 84, -21, 8, -2],   [-2, 8, -21, 72, 87, -22, 8, -2],
+  [-2, 8, -20, 69, 89, -22, 8, -2],   [-2, 8, -20, 67, 91, -22, 8, -2],
+  [-2, 7, -19, 64, 94, -22, 8, -2],   [-2, 7, -19, 62, 96, -22, 8, -2],
+  [-2, 7, -18, 59, 98, -22, 8, -2],   [-2, 7, -18, 56, 100, -21, 8, -2],
+  [-2, 7, -17, 54, 102, -21, 7, -2],  [-2, 7, -16, 51, 104, -21, 7, -2],
+  [-2, 6, -16, 49, 106, -20, 7, -2],  [-2, 6, -15, 46, 108, -20, 7, -2],
+  [-2, 6, -15, 43, 110, -19, 7, -2],  [-2, 6, -14, 41, 111, -19, 7, -2],
+  [-1, 5, -13, 38, 113, -18, 6, -2],  [-1, 5, -12, 35, 114, -17, 6, -2],
+  [-1, 5, -12, 33, 116, -17, 6, -2],  [-1, 4, -11, 30, 118, -16, 5, -1],
+  [-1, 4, -10, 27, 119, -15, 5, -1],  [-1, 4, -9, 25, 120, -14, 5, -2],
+  [-1, 3, -8, 23, 121, -13, 4, -1],   [-1, 3, -7, 20, 122, -12, 4, -1],
+  [-1, 3, -7, 18, 123, -11, 4, -1],   [-1, 3, -6, 16, 124, -10, 3, -1],
+  [-1, 2, -5, 13, 125, -8, 3, -1],    [-1, 2, -4, 11, 126, -7, 2, -1],
+  [0, 1, -3, 8, 126, -6, 2, 0],       [0, 1, -2, 6, 127, -5, 1, 0],
+  [0, 1, -2, 4, 127, -3, 1, 0],       [0, 0, 0, 2, 127, -1, 0, 0],
+  [0, 0, 0, 1, 127, 0, 0, 0],         [0, 0, 0, -1, 127, 2, 0, 0],
+  [0, 0, 1, -3, 127, 4, -1, 0],       [0, 0, 1, -4, 126, 6, -2, 1],
+  [0, 0, 1, -5, 126, 8, -3, 1],       [0, 0, 1, -6, 125, 11, -4, 1],
+  [0, 0, 1, -7, 124, 13, -4, 1],      [0, 0, 2, -8, 123, 15, -5, 1],
+  [0, 0, 2, -9, 122, 18, -6, 1],      [0, 0, 2, -10, 121, 20, -6, 1],
+  [0, 0, 2, -11, 120, 22, -7, 2],     [0, 0, 2, -12, 119, 25, -8, 2],
+  [0, 0, 3, -13, 117, 27, -8, 2],     [0, 0, 3, -13, 116, 29, -9, 2],
+  [0, 0, 3, -14, 114, 32, -10, 3],    [0, 0, 3, -15, 113, 35, -10, 2],
+  [0, 0, 3, -15, 111, 37, -11, 3],    [0, 0, 3, -16, 109, 40, -11, 3],
+  [0, 0, 3, -16, 108, 42, -12, 3],    [0, 0, 4, -17, 106, 45, -13, 3],
+  [0, 0, 4, -17, 104, 47, -13, 3],    [0, 0, 4, -17, 102, 50, -14, 3],
+  [0, 0, 4, -17, 100, 52, -14, 3],    [0, 0, 4, -18, 98, 55, -15, 4],
+  [0, 0, 4, -18, 96, 58, -15, 3],     [0, 0, 4, -18, 94, 60, -16, 4],
+  [0, 0, 4, -18, 91, 63, -16, 4],     [0, 0, 4, -18, 89, 65, -16, 4],
+  [0, 0, 4, -18, 87, 68, -17, 4],     [0, 0, 4, -18, 85, 70, -17, 4],
+  [0, 0, 4, -18, 82, 73, -17, 4],     [0, 0, 4, -18, 80, 75, -17, 4],
+  [0, 0, 4, -18, 78, 78, -18, 4],     [0, 0, 4, -17, 75, 80, -18, 4],
+  [0, 0, 4, -17, 73, 82, -18, 4],     [0, 0, 4, -17, 70, 85, -18, 4],
+  [0, 0, 4, -17, 68, 87, -18, 4],     [0, 0, 4, -16, 65, 89, -18, 4],
+  [0, 0, 4, -16, 63, 91, -18, 4],     [0, 0, 4, -16, 60, 94, -18, 4],
+  [0, 0, 3, -15, 58, 96, -18, 4],     [0, 0, 4, -15, 55, 98, -18, 4],
+  [0, 0, 3, -14, 52, 100, -17, 4],    [0, 0, 3, -14, 50, 102, -17, 4],
+  [0, 0, 3, -13, 47, 104, -17, 4],    [0, 0, 3, -13, 45, 106, -17, 4],
+  [0, 0, 3, -12, 42, 108, -16, 3],    [0, 0, 3, -11, 40, 109, -16, 3],
+  [0, 0, 3, -11, 37, 111, -15, 3],    [0, 0, 2, -10, 35, 113, -15, 3],
+  [0, 0, 3, -10, 32, 114, -14, 3],    [0, 0, 2, -9, 29, 116, -13, 3],
+  [0, 0, 2, -8, 27, 117, -13, 3],     [0, 0, 2, -8, 25, 119, -12, 2],
+  [0, 0, 2, -7, 22, 120, -11, 2],     [0, 0, 1, -6, 20, 121, -10, 2],
+  [0, 0, 1, -6, 18, 122, -9, 2],      [0, 0, 1, -5, 15, 123, -8, 2],
+  [0, 0, 1, -4, 13, 124, -7, 1],      [0, 0, 1, -4, 11, 125, -6, 1],
+  [0, 0, 1, -3, 8, 126, -5, 1],       [0, 0, 1, -2, 6, 126, -4, 1],
+  [0, 0, 0, -1, 4, 127, -3, 1],       [0, 0, 0, 0, 2, 127, -1, 0],
+  [0, 0, 0, 0, 2, 127, -1, 0],
+];
+
+/// Reciprocal table used to divide by the model scale, see `Div_Lut`.
+#[rustfmt::skip]
+const DIV_LUT: [i64; (1 << DIV_LUT_BITS) + 1] = [
+  16384, 16320, 16257, 16194, 16132, 16070, 16009, 15948, 15888, 15828,
+  15768, 15709, 15650, 15592, 15534, 15477, 15420, 15364, 15308, 15252,
+  15197, 15142, 15087, 15033, 14980, 14926, 14873, 14821, 14769, 14717,
+  14665, 14614, 14564, 14513, 14463, 14413, 14364, 14315, 14266, 14218,
+  14170, 14122, 14075, 14028, 13981, 13935, 13888, 13843, 13797, 13752,
+  13707, 13662, 13618, 13574, 13530, 13487, 13443, 13400, 13358, 13315,
+  13273, 13231, 13190, 13148, 13107, 13066, 13026, 12985, 12945, 12906,
+  12866, 12827, 12788, 12749, 12710, 12672, 12633, 12596, 12558, 12520,
+  12483, 12446, 12409, 12373, 12336, 12300, 12264, 12228, 12193, 12157,
+  12122, 12087, 12053, 12018, 11984, 11950, 11916, 11882, 11848, 11815,
+  11782, 11749, 11716, 11683, 11651, 11619, 11586, 11555, 11523, 11491,
+  11460, 11429, 11398, 11367, 11336, 11305, 11275, 11245, 11215, 11185,
+  11155, 11125, 11096, 11067, 11038, 11009, 10980, 10951, 10923, 10894,
+  10866, 10838, 10810, 10782, 10755, 10727, 10700, 10673, 10645, 10618,
+  10592, 10565, 10538, 10512, 10486, 10460, 10434, 10408, 10382, 10356,
+  10331, 10305, 10280, 10255, 10230, 10205, 10180, 10156, 10131, 10107,
+  10082, 10058, 10034, 10010, 9986, 9963, 9939, 9916, 9892, 9869,
+  9846, 9823, 9800, 9777, 9754, 9732, 9709, 9687, 9664, 9642,
+  9620, 9598, 9576, 9554, 9533, 9511, 9489, 9468, 9447, 9425,
+  9404, 9383, 9362, 9341, 9321, 9300, 9279, 9259, 9239, 9218,
+  9198, 9178, 9158, 9138, 9118, 9098, 9079, 9059, 9039, 9020,
+  9001, 8981, 8962, 8943, 8924, 8905, 8886, 8867, 8849, 8830,
+  8812, 8793, 8775, 8756, 8738, 8720, 8702, 8684, 8666, 8648,
+  8630, 8613, 8595, 8577, 8560, 8542, 8525, 8508, 8490, 8473,
+  8456, 8439, 8422, 8405, 8389, 8372, 8355, 8339, 8322, 8306,
+  8289, 8273, 8257, 8240, 8224, 8208, 8192,
+];
+
+/// Rounds `value / 2^bits` to the nearest integer, away from zero on ties.
+pub const fn round2signed(value: i64, bits: u32) -> i64 {
+  if value >= 0 {
+    (value + (1 << bits >> 1)) >> bits
+  } else {
+    -((-value + (1 << bits >> 1)) >> bits)
+  }
+}
+
+/// Returns the shift and factor approximating a division by `d`.
+fn resolve_divisor(d: i64) -> (u32, i64) {
+  let d_abs = d.unsigned_abs();
+  let n = d_abs.ilog2();
+  let e = d_abs - (1 << n);
+  let f = if n > DIV_LUT_BITS {
+    (e + (1 << (n - DIV_LUT_BITS) >> 1)) >> (n - DIV_LUT_BITS)
+  } else {
+    e << (DIV_LUT_BITS - n)
+  };
+  let factor = DIV_LUT[f as usize];
+  (n + DIV_LUT_PREC_BITS, if d < 0 { -factor } else { factor })
+}
+
+/// A position in the current frame and the position it moves to in a
+/// reference frame, in 1/8 luma sample units relative to the top-left of the
+/// block whose local warp is estimated.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct WarpSample {
+  pub pos: [i32; 2],
+  pub ref_pos: [i32; 2],
+}
+
+/// An affine motion model that can be used for warped prediction.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct WarpedMotion {
+  /// Model parameters with `WARPEDMODEL_PREC_BITS` of precision. The luma
+  /// sample at `(x, y)` is predicted from the reference position
+  /// `(p[2] * x + p[3] * y + p[0], p[4] * x + p[5] * y + p[1])`.
+  pub params: [i32; 6],
+  alpha: i32,
+  beta: i32,
+  gamma: i32,
+  delta: i32,
+}
+
+impl WarpedMotion {
+  /// Decomposes a model into the horizontal and vertical shears applied by
+  /// the block warp. Returns `None` when the decoder would consider the
+  /// model invalid for warping.
+  pub fn new(params: [i32; 6]) -> Option<Self> {
+    if params[2] <= 0 {
+      return None;
+    }
+    let clip = |v: i64| v.clamp(i16::MIN as i64, i16::MAX as i64);
+    let reduce = |v: i64| {
+      (round2signed(v, WARP_PARAM_REDUCE_BITS) << WARP_PARAM_REDUCE_BITS)
+        as i32
+    };
+
+    let one = 1i64 << WARPEDMODEL_PREC_BITS;
+    let alpha = clip(params[2] as i64 - one);
+    let beta = clip(params[3] as i64);
+    let (shift, factor) = resolve_divisor(params[2].into());
+    let v = (params[4] as i64) << WARPEDMODEL_PREC_BITS;
+    let gamma = clip(round2signed(v * factor, shift));
+    let w = params[3] as i64 * params[4] as i64;
+    let delta = clip(params[5] as i64 - round2signed(w * factor, shift) - one);
+
+    let (alpha, beta, gamma, delta) =
+      (reduce(alpha), reduce(beta), reduce(gamma), reduce(delta));
+
+    if 4 * alpha.abs() + 7 * beta.abs() >= (1 << WARPEDMODEL_PREC_BITS)
+      || 4 * gamma.abs() + 4 * delta.abs() >= (1 << WARPEDMODEL_PREC_BITS)
+    {
+      return None;
+    }
+
+    Some(WarpedMotion { params, alpha, beta, gamma, delta })
+  }
+
+  /// Estimates the local warp of a `bsize` block at `bo` with motion vector
+  /// `mv` from the motion of its neighbours, see the "warp estimation
+  /// process" in the spec. Returns `None` when the decoder would consider
+  /// the local warp invalid.
+  pub fn from_samples(
+    samples: &[WarpSample], bsize: BlockSize, bo: PlaneBlockOffset,
+    mv: MotionVector,
+  ) -> Option<Self> {
+    let rsuy = 2 * bsize.height_mi() as i32 - 1;
+    let rsux = 2 * bsize.width_mi() as i32 - 1;
+    let suy = rsuy * 8;
+    let sux = rsux * 8;
+    let duy = suy + mv.row as i32;
+    let dux = sux + mv.col as i32;
+
+    let mut a = [[0i64; 2]; 2];
+    let mut bx = [0i64; 2];
+    let mut by = [0i64; 2];
+    for sample in samples {
+      let sx = sample.pos[0] - sux;
+      let sy = sample.pos[1] - suy;
+      let dx = sample.ref_pos[0] - dux;
+      let dy = sample.ref_pos[1] - duy;
+      if (sx - dx).abs() < 256 && (sy - dy).abs() < 256 {
+        a[0][0] += (((sx * sx) >> 2) + sx * 2 + 8) as i64;
+        a[0][1] += (((sx * sy) >> 2) + sx + sy + 4) as i64;
+        a[1][1] += (((sy * sy) >> 2) + sy * 2 + 8) as i64;
+        bx[0] += (((sx * dx) >> 2) + sx + dx + 8) as i64;
+        bx[1] += (((sy * dx) >> 2) + sy + dx + 4) as i64;
+        by[0] += (((sx * dy) >> 2) + sx + dy + 4) as i64;
+        by[1] += (((sy * dy) >> 2) + sy + dy + 8) as i64;
+      }
+    }
+
+    let det = a[0][0] * a[1][1] - a[0][1] * a[0][1];
+    if det == 0 {
+      return None;
+    }
+    let (shift, factor) = resolve_divisor(det);
+    let (shift, factor) = match shift.checked_sub(WARPEDMODEL_PREC_BITS) {
+      Some(shift) => (shift, factor),
+      None => (0, factor << (WARPEDMODEL_PREC_BITS - shift)),
+    };
+    let solve = |v: i64, min: i64, max: i64| {
+      round2signed(v.wrapping_mul(factor), shift).clamp(min, max) as i32
+    };
+    let diag = |v: i64| solve(v, 0xe001, 0x11fff);
+    let nondiag = |v: i64| solve(v, -0x1fff, 0x1fff);
+
+    let mut params = [0; 6];
+    params[2] = diag(a[1][1] * bx[0] - a[0][1] * bx[1]);
+    params[3] = nondiag(a[0][0] * bx[1] - a[0][1] * bx[0]);
+    params[4] = nondiag(a[1][1] * by[0] - a[0][1] * by[1]);
+    params[5] = diag(a[0][0] * by[1] - a[0][1] * by[0]);
+
+    let isuy = (bo.0.y * 4) as i64 + rsuy as i64;
+    let isux = (bo.0.x * 4) as i64 + rsux as i64;
+    let p = params.map(i64::from);
+    let one = 1 << WARPEDMODEL_PREC_BITS;
+    let clip = |v: i64| v.clamp(-(1 << 23), (1 << 23) - 1) as i32;
+    params[0] = clip(
+      ((mv.col as i64) << (WARPEDMODEL_PREC_BITS - 3))
+        - (isux * (p[2] - one) + isuy * p[3]),
+    );
+    params[1] = clip(
+      ((mv.row as i64) << (WARPEDMODEL_PREC_BITS - 3))
+        - (isux * p[4] + isuy * (p[5] - one)),
+    );
+
+    WarpedMotion::new(params)
+  }
+
+  /// Runs the block warp over a `width`x`height` block whose top-left
+  /// sample is at `po` in the plane of `src`, passing each output sample
+  /// rounded by `round1` bits to `out(row, col, value)`.
+  ///
+  /// `last` is the position of the bottom-right sample of the reference
+  /// plane; samples outside of it are replicated from the edges.
+  fn warp<T: Pixel>(
+    &self, src: &Plane<T>, po: PlaneOffset, last: (isize, isize),
+    width: usize, height: usize, bit_depth: usize, round1: usize,
+    mut out: impl FnMut(usize, usize, i32),
+  ) {
+    let PlaneConfig { xdec, ydec, .. } = src.cfg;
+    let p = self.params.map(i64::from);
+    let round0 = 3 + if bit_depth == 12 { 2 } else { 0 };
+    let (last_x, last_y) = last;
+
+    for i8 in 0..=(height - 1) >> 3 {
+      for j8 in 0..=(width - 1) >> 3 {
+        let src_x = (po.x + j8 as isize * 8 + 4) << xdec;
+        let src_y = (po.y + i8 as isize * 8 + 4) << ydec;
+        let dst_x = p[2] * src_x as i64 + p[3] * src_y as i64 + p[0];
+        let dst_y = p[4] * src_x as i64 + p[5] * src_y as i64 + p[1];
+        let x4 = dst_x >> xdec;
+        let y4 = dst_y >> ydec;
+        let ix4 = (x4 >> WARPEDMODEL_PREC_BITS) as isize;
+        let sx4 = (x4 & ((1 << WARPEDMODEL_PREC_BITS) - 1)) as i32;
+        let iy4 = (y4 >> WARPEDMODEL_PREC_BITS) as isize;
+        let sy4 = (y4 & ((1 << WARPEDMODEL_PREC_BITS) - 1)) as i32;
+
+        // Horizontal filter into a 15x8 intermediate block
+        let mut intermediate = [[0i32; 8]; 15];
+        for (i1, row) in (-7..8).zip(intermediate.iter_mut()) {
+          let src_row = src.row((iy4 + i1).clamp(0, last_y));
+          for (i2, v) in (-4..4).zip(row.iter_mut()) {
+            let sx = sx4 + self.alpha * i2 + self.beta * i1 as i32;
+            let offs =
+              round_shift(sx, WARPEDDIFF_PREC_BITS) + WARPEDPIXEL_PREC_SHIFTS;
+            let filter = &WARPED_FILTERS[offs as usize];
+            let s: i32 = (0..8)
+              .map(|i3| {
+                let x = (ix4 + i2 as isize - 3 + i3 as isize).clamp(0, last_x);
+                filter[i3] * i32::cast_from(src_row[x as usize])
+              })
+              .sum();
+            *v = round_shift(s, round0);
+          }
+        }
+
+        // Vertical filter
+        let rows = 4.min(height as isize - i8 as isize * 8 - 4);
+        let cols = 4.min(width as isize - j8 as isize * 8 - 4);
+        for i1 in -4..rows {
+          for i2 in -4..cols {
+            let sy = sy4 + self.gamma * i2 as i32 + self.delta * i1 as i32;
+            let offs =
+              round_shift(sy, WARPEDDIFF_PREC_BITS) + WARPEDPIXEL_PREC_SHIFTS;
+            let filter = &WARPED_FILTERS[offs as usize];
+            let s: i32 = (0..8)
+              .map(|i3| {
+                filter[i3]
+                  * intermediate[(i1 + i3 as isize + 4) as usize]
+                    [(i2 + 4) as usize]
+              })
+              .sum();
+            out(
+              (i8 as isize * 8 + i1 + 4) as usize,
+              (j8 as isize * 8 + i2 + 4) as usize,
+              round_shift(s, round1),
+            );
+          }
+        }
+      }
+    }
+  }
+
+  /// Warped prediction of a single reference into `dst`.
+  pub fn put_warp<T: Pixel>(
+    &self, dst: &mut PlaneRegionMut<'_, T>, src: &Plane<T>, po: PlaneOffset,
+    last: (isize, isize), width: usize, height: usize, bit_depth: usize,
+  ) {
+    let max_sample_val = (1 << bit_depth) - 1;
+    let round1 = if bit_depth == 12 { 9 } else { 11 };
+    self.warp(src, po, last, width, height, bit_depth, round1, |r, c, v| {
+      dst[r][c] = T::cast_from(v.clamp(0, max_sample_val));
+    });
+  }
+
+  /// Warped prediction of one reference of a compound block, in the same
+  /// intermediate format as [`prep_8tap`](crate::mc::prep_8tap).
+  pub fn prep_warp<T: Pixel>(
+    &self, tmp: &mut [i16], src: &Plane<T>, po: PlaneOffset,
+    last: (isize, isize), width: usize, height: usize, bit_depth: usize,
+  ) {
+    let prep_bias = if bit_depth == 8 { 0 } else { 8192 };
+    self.warp(src, po, last, width, height, bit_depth, 7, |r, c, v| {
+      tmp[r * width + c] = (v - prep_bias) as i16;
+    });
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::context::BlockOffset;
+
+  #[test]
+  fn warped_filters_are_normalized() {
+    for filter in WARPED_FILTERS.iter() {
+      assert_eq!(filter.iter().sum::<i32>(), 128);
+    }
+  }
+
+  #[test]
+  fn identity_has_no_shear() {
+    let wm = WarpedMotion::new(WARP_IDENTITY).unwrap();
+    assert_eq!((wm.alpha, wm.beta, wm.gamma, wm.delta), (0, 0, 0, 0));
+  }
+
+  #[test]
+  fn large_shear_is_invalid() {
+    let mut params = WARP_IDENTITY;
+    params[3] = 1 << 14;
+    assert!(WarpedMotion::new(params).is_none());
+  }
+
+  #[test]
+  fn identity_warp_copies() {
+    let mut src = Plane::<u8>::new(32, 32, 0, 0, 8, 8);
+    for (y, row) in src.rows_iter_mut().enumerate() {
+      for (x, v) in row.iter_mut().enumerate() {
+        *v = (x * 7 + y * 3) as u8;
+      }
+    }
+    let mut dst = Plane::<u8>::new(16, 16, 0, 0, 0, 0);
+    let wm = WarpedMotion::new(WARP_IDENTITY).unwrap();
+    let po = PlaneOffset { x: 8, y: 4 };
+    wm.put_warp(&mut dst.as_region_mut(), &src, po, (31, 31), 16, 16, 8);
+    for y in 0..16 {
+      for x in 0..16 {
+        assert_eq!(dst.p(x, y), src.p(x + 8, y + 4));
+      }
+    }
+  }
+
+  #[test]
+  fn translated_samples_give_translation() {
+    let mv = MotionVector { row: -12, col: 20 };
+    let bo = PlaneBlockOffset(BlockOffset { x: 8, y: 4 });
+    let samples =
+      [(-9, 15), (15, -9), (39, -9), (-9, 47)].map(|(x, y)| WarpSample {
+        pos: [x * 8, y * 8],
+        ref_pos: [x * 8 + mv.col as i32, y * 8 + mv.row as i32],
+      });
+    let wm =
+      WarpedMotion::from_samples(&samples, BlockSize::BLOCK_32X32, bo, mv)
+        .unwrap();
+    let p = wm.params;
+    let one = 1 << WARPEDMODEL_PREC_BITS;
+    for (&p, e) in p[2..].iter().zip([one, 0, 0, one]) {
+      assert!((p - e).abs() < 1 << 5, "{:?}", wm.params);
+    }
+    // The block centre lands exactly on the centre displaced by mv
+    let (cx, cy) = (8 * 4 + 15, 4 * 4 + 15);
+    assert_eq!(p[2] * cx + p[3] * cy + p[0], (cx << 16) + (20 << 13));
+    assert_eq!(p[4] * cx + p[5] * cy + p[1], (cy << 16) - (12 << 13));
+  }
+}
@@ -84,6 +84,375 @@ mod binary {
       .success();
   }
 
+  #[interpolate_test(i420, "i420")]
+  #[interpolate_test(nv12, "nv12")]
+  #[interpolate_test(p010, "p010")]
+  fn raw_input(format: &str) {
+    let (width, height, frames) = (64, 48, 3);
+    let luma: Vec<u8> =
+      (0..width * height * frames).map(|i| (i * 7 % 251) as u8).collect();
+    let chroma: Vec<u8> =
+      (0..width * height / 2 * frames).map(|i| (i * 3 % 241) as u8).collect();
+
+    // The same frames in y4m or 10-bit planar input, and in `format`.
+    let mut reference = Vec::new();
+    let mut raw = Vec::new();
+    if format != "p010" {
+      reference
+        .extend_from_slice(b"YUV4MPEG2 W64 H48 F30:1 Ip A1:1 C420jpeg\n");
+    }
+    for (y, uv) in
+      luma.chunks(width * height).zip(chroma.chunks(width * height / 2))
+    {
+      let (u, v) = uv.split_at(uv.len() / 2);
+      let interleaved = u.iter().zip(v).flat_map(|(&u, &v)| [u, v]);
+      match format {
+        "i420" => raw.extend(y.iter().chain(uv)),
+        "nv12" => raw.extend(y.iter().copied().chain(interleaved)),
+        _ => raw.extend(
+          y.iter()
+            .copied()
+            .chain(interleaved)
+            .flat_map(|s| ((s as u16) << 8).to_le_bytes()),
+        ),
+      }
+      if format == "p010" {
+        reference.extend(
+          y.iter().chain(uv).flat_map(|&s| ((s as u16) << 2).to_le_bytes()),
+        );
+      } else {
+        reference.extend_from_slice(b"FRAME\n");
+        reference.extend(y.iter().chain(uv));
+      }
+    }
+
+    let encode = |input: Vec<u8>, args: &[&str]| {
+      let outfile = get_tempfile_path("ivf");
+      get_rav1e_command(false)
+        .args(["--quantizer", "100", "-o"])
+        .arg(&outfile)
+        .args(args)
+        .arg("-")
+        .write_stdin(input)
+        .assert()
+        .success();
+      let mut data = Vec::new();
+      File::open(&outfile).unwrap().read_to_end(&mut data).unwrap();
+      data
+    };
+
+    let size = ["--input-width", "64", "--input-height", "48"];
+    let reference = if format == "p010" {
+      let args = [&size[..], &["--input-format", "i420"]].concat();
+      encode(reference, &[&args[..], &["--input-bit-depth", "10"]].concat())
+    } else {
+      encode(reference, &[])
+    };
+    let args = [&size[..], &["--input-format", format]].concat();
+    assert_eq!(encode(raw, &args), reference);
+  }
+
+  #[interpolate_test(webm, "webm")]
+  #[interpolate_test(mkv, "mkv")]
+  fn matroska_output(extension: &str) {
+    let outfile = get_tempfile_path(extension);
+
+    get_common_cmd(&outfile, false)
+      .arg("-")
+      .write_stdin(get_y4m_input())
+      .assert()
+      .success();
+
+    let mut data = Vec::new();
+    File::open(&outfile).unwrap().read_to_end(&mut data).unwrap();
+    let contains =
+      |needle: &[u8]| data.windows(needle.len()).any(|w| w == needle);
+    // EBML header, then the doc type.
+    assert_eq!(data[..4], [0x1A, 0x45, 0xDF, 0xA3]);
+    let doc_type = if extension == "webm" { "webm" } else { "matroska" };
+    assert!(contains(doc_type.as_bytes()));
+    assert!(contains(b"V_AV1"));
+    // The segment size is set once the Cues are written at the end.
+    let segment = data.windows(4).position(|w| w == [0x18, 0x53, 0x80, 0x67]);
+    let segment = segment.unwrap() + 4;
+    let size =
+      u64::from_be_bytes(data[segment..segment + 8].try_into().unwrap());
+    assert_eq!(size & !(1 << 56), (data.len() - segment - 8) as u64);
+    assert!(contains(&[0x1C, 0x53, 0xBB, 0x6B]));
+  }
+
+  #[test]
+  fn obu_output() {
+    let outfile = get_tempfile_path("obu");
+
+    get_common_cmd(&outfile, false)
+      .arg("-")
+      .write_stdin(get_y4m_input())
+      .assert()
+      .success();
+
+    let mut data = Vec::new();
+    File::open(&outfile).unwrap().read_to_end(&mut data).unwrap();
+    // A temporal delimiter, then the sequence header OBU.
+    assert_eq!(data[..2], [0x12, 0x00]);
+    assert_eq!(data[2], 0x0A);
+  }
+
+  #[test]
+  fn annex_b_output() {
+    let outfile = get_tempfile_path("annexb");
+
+    get_common_cmd(&outfile, false)
+      .arg("-")
+      .write_stdin(get_y4m_input())
+      .assert()
+      .success();
+
+    let mut data = Vec::new();
+    File::open(&outfile).unwrap().read_to_end(&mut data).unwrap();
+    let read_leb128 = |data: &mut &[u8]| {
+      let mut value = 0;
+      let mut i = 0;
+      loop {
+        value |= ((data[i] & 0x7f) as usize) << (7 * i);
+        i += 1;
+        if data[i - 1] & 0x80 == 0 {
+          *data = &data[i..];
+          return value;
+        }
+      }
+    };
+    // The temporal units span the whole file, each one made of frame units
+    // and starting with a temporal delimiter without a size field.
+    let mut rest = &data[..];
+    while !rest.is_empty() {
+      let temporal_unit_size = read_leb128(&mut rest);
+      let (mut temporal_unit, next) = rest.split_at(temporal_unit_size);
+      rest = next;
+      let mut first = true;
+      while !temporal_unit.is_empty() {
+        let frame_unit_size = read_leb128(&mut temporal_unit);
+        let (frame_unit, next) = temporal_unit.split_at(frame_unit_size);
+        temporal_unit = next;
+        if first {
+          assert_eq!(frame_unit[..2], [1, 0x10]);
+          first = false;
+        }
+      }
+    }
+  }
+
+  #[interpolate_test(mp4, "mp4")]
+  #[interpolate_test(cmfv, "cmfv")]
+  fn mp4_output(extension: &str) {
+    let outfile = get_tempfile_path(extension);
+
+    get_common_cmd(&outfile, false)
+      .arg("-")
+      .write_stdin(get_y4m_input())
+      .assert()
+      .success();
+
+    let mut data = Vec::new();
+    File::open(&outfile).unwrap().read_to_end(&mut data).unwrap();
+    let mut kinds = Vec::new();
+    let mut rest = &data[..];
+    while !rest.is_empty() {
+      let mut size =
+        u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+      if size == 1 {
+        size = u64::from_be_bytes(rest[8..16].try_into().unwrap()) as usize;
+      }
+      kinds.push(rest[4..8].to_vec());
+      rest = &rest[size..];
+    }
+    if extension == "mp4" {
+      assert_eq!(kinds, [b"ftyp", b"mdat", b"moov"]);
+      // Every frame lasts one tick of the 1/25 time base.
+      let frames =
+        get_y4m_input().windows(6).filter(|w| w == b"FRAME\n").count();
+      let stts = data.windows(4).position(|w| w == b"stts").unwrap();
+      let entries: Vec<u32> = data[stts + 8..stts + 20]
+        .chunks(4)
+        .map(|b| u32::from_be_bytes(b.try_into().unwrap()))
+        .collect();
+      assert_eq!(entries, [1, frames as u32, 1]);
+    } else {
+      assert_eq!(kinds[..3], [b"ftyp", b"moov", b"moof"]);
+      assert_eq!(kinds.last().unwrap(), b"mdat");
+    }
+    assert!(data.windows(4).any(|w| w == b"av1C"));
+  }
+
+  #[test]
+  fn ivf_analysis() {
+    let outfile = get_tempfile_path("ivf");
+
+    get_rav1e_command(false)
+      .args(["--quantizer", "100"])
+      .arg("-o")
+      .arg(&outfile)
+      .arg("-")
+      .write_stdin(get_y4m_input())
+      .assert()
+      .success();
+
+    let output =
+      get_rav1e_command(false).arg("ivf").arg(&outfile).output().unwrap();
+    assert!(output.status.success());
+    let json = String::from_utf8(output.stdout).unwrap();
+
+    assert!(json.contains("\"width\": 64, \"height\": 64"));
+    assert!(json.contains("\"bit_depth\": 8, \"chroma_sampling\": \"420\""));
+    let frames: Vec<_> =
+      json.lines().filter(|line| line.contains("\"frame_type\"")).collect();
+    assert!(frames[0].contains("\"frame_type\": \"KEY\""));
+    assert!(frames[0].contains("\"refresh_frame_flags\": 255"));
+    // Every input frame is shown once, and every coded frame has a qindex.
+    let shown =
+      frames.iter().filter(|frame| frame.contains("\"show_frame\": true"));
+    assert_eq!(shown.count(), 5);
+    for frame in frames {
+      let existing = frame.contains("\"show_existing_frame\": true");
+      assert_eq!(frame.contains("\"qindex\": null"), existing);
+    }
+  }
+
+  #[test]
+  fn ivf_analysis_tile_groups() {
+    let outfile = get_tempfile_path("ivf");
+    let mut input = b"YUV4MPEG2 W128 H64 F30:1 Ip A1:1 C420jpeg\n".to_vec();
+    for i in 0..3 {
+      input.extend_from_slice(b"FRAME\n");
+      input.extend((0..128 * 64 * 3 / 2).map(|j| (j * 7 + i * 13) as u8));
+    }
+
+    get_rav1e_command(false)
+      .args(["--quantizer", "100", "--tile-cols", "2", "-o"])
+      .arg(&outfile)
+      .arg("-")
+      .write_stdin(input)
+      .assert()
+      .success();
+
+    let analyze = |path: &Path| {
+      let output =
+        get_rav1e_command(false).arg("ivf").arg(path).output().unwrap();
+      assert!(output.status.success());
+      let json = String::from_utf8(output.stdout).unwrap();
+      json
+        .lines()
+        .filter(|line| line.contains("\"frame_type\""))
+        .map(str::to_owned)
+        .collect::<Vec<_>>()
+    };
+    let frames = analyze(&outfile);
+    assert!(frames[0].contains("\"tile_cols\": 2, \"tile_rows\": 1"));
+    assert!(frames[0].contains("\"tile_groups\": 1"));
+
+    // Follow the frame OBU of the key frame with a copy of its frame header,
+    // taken as the start of the frame OBU with trailing bits, and with a
+    // tile group OBU of its second tile, tile_start_and_end_present_flag,
+    // tg_start and tg_end being set.
+    let mut data = Vec::new();
+    File::open(&outfile).unwrap().read_to_end(&mut data).unwrap();
+    let size = u32::from_le_bytes(data[32..36].try_into().unwrap()) as usize;
+    let mut pos = 44;
+    while data[pos] >> 3 != 6 {
+      pos += 2 + data[pos + 1] as usize;
+    }
+    pos += 1;
+    while data[pos] & 0x80 != 0 {
+      pos += 1;
+    }
+    let mut obus = vec![0x1A, 0x09];
+    obus.extend_from_slice(&data[pos + 1..pos + 9]);
+    obus.extend_from_slice(&[0x80, 0x22, 0x02, 0xE0, 0x00]);
+    data.splice(44 + size..44 + size, obus.iter().copied());
+    data[32..36].copy_from_slice(&((size + obus.len()) as u32).to_le_bytes());
+    let split_file = get_tempfile_path("ivf");
+    std::fs::write(&split_file, &data).unwrap();
+
+    let split_frames = analyze(&split_file);
+    assert_eq!(split_frames.len(), frames.len());
+    let frame_size = |frame: &str| {
+      let size = frame.split("\"size\": ").nth(1).unwrap();
+      size.split(',').next().unwrap().parse::<usize>().unwrap()
+    };
+    assert_eq!(
+      frame_size(&split_frames[0]),
+      frame_size(&frames[0]) + obus.len()
+    );
+    assert!(split_frames[0].contains("\"tile_groups\": 2"));
+    assert_eq!(split_frames[1..], frames[1..]);
+  }
+
+  #[test]
+  fn decoder_model_removal_times() {
+    let outfile = get_tempfile_path("ivf");
+
+    get_rav1e_command(false)
+      .args(["--bitrate", "500", "--max-bitrate", "1000"])
+      .arg("--decoder-model-info")
+      .arg("-o")
+      .arg(&outfile)
+      .arg("-")
+      .write_stdin(get_y4m_input())
+      .assert()
+      .success();
+
+    let output =
+      get_rav1e_command(false).arg("ivf").arg(&outfile).output().unwrap();
+    assert!(output.status.success());
+    let json = String::from_utf8(output.stdout).unwrap();
+
+    // Every coded frame is removed from the decoder buffer along with its
+    // temporal unit, one tick of the time base after the previous one.
+    let frames: Vec<_> = json
+      .lines()
+      .filter(|line| line.contains("\"frame_type\""))
+      .filter(|line| !line.contains("\"show_existing_frame\": true"))
+      .collect();
+    assert!(frames
+      .iter()
+      .any(|frame| frame.contains("\"show_frame\": false")));
+    for frame in frames {
+      let pts = frame.split("\"pts\": ").nth(1).unwrap().split(',').next();
+      let removal_time = format!("\"buffer_removal_time\": {},", pts.unwrap());
+      assert!(frame.contains(&removal_time), "{frame}");
+    }
+  }
+
+  #[test]
+  fn zone_quantizer() {
+    let outfile = get_tempfile_path("ivf");
+
+    get_rav1e_command(false)
+      .args(["--quantizer", "100", "--zones", "0-4:q=50"])
+      .arg("-o")
+      .arg(&outfile)
+      .arg("-")
+      .write_stdin(get_y4m_input())
+      .assert()
+      .success();
+
+    let output =
+      get_rav1e_command(false).arg("ivf").arg(&outfile).output().unwrap();
+    assert!(output.status.success());
+    let json = String::from_utf8(output.stdout).unwrap();
+
+    // Every coded frame of the zone, inter frames included, has its qindex.
+    let frames: Vec<_> = json
+      .lines()
+      .filter(|line| line.contains("\"frame_type\""))
+      .filter(|line| !line.contains("\"show_existing_frame\": true"))
+      .collect();
+    assert!(frames.iter().any(|frame| frame.contains("\"INTER\"")));
+    for frame in frames {
+      assert!(frame.contains("\"qindex\": 50,"), "{frame}");
+    }
+  }
+
   #[interpolate_test(low_bitdepth, false)]
   #[interpolate_test(high_bitdepth, true)]
   fn two_pass_bitrate_based(high_bitdepth: bool) {